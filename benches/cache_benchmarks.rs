@@ -58,7 +58,7 @@ fn cache_performance(c: &mut Criterion) {
                     Value::String("value".to_string()),
                     if i % 10 == 0 {
                         SetOptions {
-                            parent: Some("parent".to_string()),
+                            parents: vec!["parent".to_string()],
                             ..Default::default()
                         }
                     } else {
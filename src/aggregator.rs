@@ -0,0 +1,188 @@
+use crate::cache::Cache;
+use crate::keyspace::KeyspaceReport;
+use crate::metrics::Labels;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tracing::debug;
+
+#[derive(Debug, Clone)]
+pub struct AggregatorConfig {
+    pub interval: Duration,
+    pub samples: usize,   // Keys visited per pass
+    pub budget: Duration, // Time allowed per pass
+}
+
+impl Default for AggregatorConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            samples: 100_000,
+            budget: Duration::from_millis(200),
+        }
+    }
+}
+
+/// Result of the latest aggregation pass
+#[derive(Debug, Clone)]
+pub struct AggregatedStats {
+    pub keyspace: KeyspaceReport,
+    pub collected_at: SystemTime,
+}
+
+/// Periodically computes expensive derived stats (per-type counts, big keys) off the
+/// request path, so `/metrics` and friends serve the cached result instantly
+pub struct StatsAggregator {
+    cache: Arc<Cache>,
+    config: AggregatorConfig,
+    latest: RwLock<Option<Arc<AggregatedStats>>>,
+}
+
+impl StatsAggregator {
+    pub fn new(cache: Arc<Cache>, config: AggregatorConfig) -> Self {
+        Self {
+            cache,
+            config,
+            latest: RwLock::new(None),
+        }
+    }
+
+    /// Runs one aggregation pass and caches its result
+    pub fn refresh(&self) -> Arc<AggregatedStats> {
+        let keyspace =
+            KeyspaceReport::collect(&self.cache, self.config.samples, self.config.budget);
+        debug!(
+            "Aggregated {} of {} keys in {}ms",
+            keyspace.sampled, keyspace.total_keys, keyspace.elapsed_ms
+        );
+
+        let stats = Arc::new(AggregatedStats {
+            keyspace,
+            collected_at: SystemTime::now(),
+        });
+        *self.latest.write().unwrap() = Some(stats.clone());
+        stats
+    }
+
+    pub fn latest(&self) -> Option<Arc<AggregatedStats>> {
+        self.latest.read().unwrap().clone()
+    }
+
+    /// Refreshes on every interval tick; scans run on the blocking pool
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(self.config.interval);
+        loop {
+            interval.tick().await;
+            let aggregator = self.clone();
+            if tokio::task::spawn_blocking(move || aggregator.refresh())
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+
+    /// Prometheus gauges from the latest pass; nothing until the first one completes
+    pub fn render_metrics(&self, out: &mut String, labels: &Labels) {
+        let Some(stats) = self.latest() else {
+            return;
+        };
+        let keyspace = &stats.keyspace;
+
+        writeln!(out, "# HELP cache_type_keys Sampled keys per value type").unwrap();
+        writeln!(out, "# TYPE cache_type_keys gauge").unwrap();
+        for (type_name, breakdown) in &keyspace.by_type {
+            let labels = labels.series_with(&[("type", type_name)]);
+            writeln!(out, "cache_type_keys{} {}", labels, breakdown.keys).unwrap();
+        }
+
+        writeln!(
+            out,
+            "# HELP cache_type_memory_bytes Sampled memory per value type"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE cache_type_memory_bytes gauge").unwrap();
+        for (type_name, breakdown) in &keyspace.by_type {
+            let labels = labels.series_with(&[("type", type_name)]);
+            writeln!(
+                out,
+                "cache_type_memory_bytes{} {}",
+                labels, breakdown.memory
+            )
+            .unwrap();
+        }
+
+        let largest = keyspace.big_keys.first().map_or(0, |big| big.memory);
+        let labels = labels.series();
+        writeln!(
+            out,
+            "# HELP cache_largest_key_bytes Size of the largest sampled key"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE cache_largest_key_bytes gauge").unwrap();
+        writeln!(out, "cache_largest_key_bytes{} {}", labels, largest).unwrap();
+
+        writeln!(
+            out,
+            "# HELP cache_aggregation_sampled_keys Keys visited by the last aggregation pass"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE cache_aggregation_sampled_keys gauge").unwrap();
+        writeln!(
+            out,
+            "cache_aggregation_sampled_keys{} {}",
+            labels, keyspace.sampled
+        )
+        .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Config, SetOptions, Value};
+
+    #[test]
+    fn test_refresh() {
+        let cache = Arc::new(Cache::new(Config::default()));
+        cache
+            .set(
+                "a".to_string(),
+                Value::String("1".to_string()),
+                SetOptions::default(),
+            )
+            .unwrap();
+        cache
+            .set(
+                "b".to_string(),
+                Value::String("22".to_string()),
+                SetOptions::default(),
+            )
+            .unwrap();
+        let aggregator = StatsAggregator::new(cache.clone(), AggregatorConfig::default());
+
+        // Nothing is rendered until the first pass completes
+        let mut out = String::new();
+        aggregator.render_metrics(&mut out, &Labels::default());
+        assert!(out.is_empty());
+        assert!(aggregator.latest().is_none());
+
+        let stats = aggregator.refresh();
+        assert_eq!(stats.keyspace.sampled, 2);
+        assert_eq!(stats.keyspace.by_type["string"].keys, 2);
+
+        // Later writes only show up after the next pass
+        cache
+            .set(
+                "c".to_string(),
+                Value::String("3".to_string()),
+                SetOptions::default(),
+            )
+            .unwrap();
+        aggregator.render_metrics(&mut out, &Labels::default());
+        assert!(out.contains("cache_type_keys{type=\"string\"} 2\n"));
+        assert!(out.contains("cache_aggregation_sampled_keys 2\n"));
+        assert_eq!(aggregator.refresh().keyspace.sampled, 3);
+    }
+}
@@ -0,0 +1,557 @@
+use crate::cache::Cache;
+use crate::executor::{Command, CommandExecutor, CommandResponse};
+use crate::snapshot;
+use redis_protocol::resp2::decode::decode;
+use redis_protocol::resp2::types::OwnedFrame;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Snapshot the log is replayed on top of, written by each rewrite
+pub const BASE_FILE: &str = "appendonly.base";
+/// Writes since the last rewrite, one RESP command per record
+pub const LOG_FILE: &str = "appendonly.aof";
+
+/// When appended commands are forced to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FsyncPolicy {
+    /// After every write, before it's acknowledged
+    Always,
+    /// Once a second; a crash loses at most the last second of writes
+    #[default]
+    EverySec,
+    /// Whenever the OS flushes its page cache
+    No,
+}
+
+impl fmt::Display for FsyncPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FsyncPolicy::Always => "always",
+            FsyncPolicy::EverySec => "everysec",
+            FsyncPolicy::No => "no",
+        })
+    }
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "always" => Ok(FsyncPolicy::Always),
+            "everysec" => Ok(FsyncPolicy::EverySec),
+            "no" => Ok(FsyncPolicy::No),
+            _ => Err(format!("unknown fsync policy '{}'", s)),
+        }
+    }
+}
+
+/// How durable one write must be before it's acknowledged, overriding the
+/// configured policy for that write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteConcern {
+    /// Applied in memory and never logged; lost on restart
+    CacheOnly,
+    /// Logged, and synced to disk per the `FsyncPolicy`
+    Logged,
+    /// Logged and synced to disk before the reply, whatever the policy
+    Fsynced,
+}
+
+impl fmt::Display for WriteConcern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            WriteConcern::CacheOnly => "cache",
+            WriteConcern::Logged => "logged",
+            WriteConcern::Fsynced => "fsynced",
+        })
+    }
+}
+
+impl FromStr for WriteConcern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cache" => Ok(WriteConcern::CacheOnly),
+            "logged" => Ok(WriteConcern::Logged),
+            "fsynced" => Ok(WriteConcern::Fsynced),
+            _ => Err(format!("unknown write concern '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AofConfig {
+    pub dir: PathBuf,
+    pub fsync: FsyncPolicy,
+    pub rewrite_min_size: u64, // Log size below which no automatic rewrite happens
+    pub rewrite_percentage: u64, // Rewrite once the log outgrows the base by this much
+}
+
+impl AofConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            fsync: FsyncPolicy::default(),
+            rewrite_min_size: 64 * 1024 * 1024,
+            rewrite_percentage: 100,
+        }
+    }
+
+    fn base_path(&self) -> PathBuf {
+        self.dir.join(BASE_FILE)
+    }
+
+    fn log_path(&self) -> PathBuf {
+        self.dir.join(LOG_FILE)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub base_keys: usize,
+    pub commands: usize,
+    pub failed: usize, // Commands that parsed but errored, as they did originally
+    pub truncated_bytes: usize, // Incomplete tail left by a crash mid-append, cut off
+}
+
+/// Append-only log of write commands. The log is replayed on top of a base snapshot;
+/// rewriting takes a fresh base and empties the log, keeping the files bounded.
+pub struct Aof {
+    config: AofConfig,
+    log: Mutex<LogFile>,
+    // Writers hold this shared from applying a command until it's logged, so a rewrite
+    // (which takes it exclusively) never snapshots a write it will also replay
+    writes: RwLock<()>,
+}
+
+struct LogFile {
+    writer: BufWriter<File>,
+    size: u64,
+    base_size: u64,
+}
+
+impl Aof {
+    /// Opens the log for appending, creating the directory and files as needed.
+    /// Replay with `load` first, since appends go after what's already there.
+    pub fn open(config: AofConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(config.log_path())?;
+        let size = file.metadata()?.len();
+        let base_size = fs::metadata(config.base_path()).map_or(0, |m| m.len());
+        Ok(Self {
+            config,
+            log: Mutex::new(LogFile {
+                writer: BufWriter::new(file),
+                size,
+                base_size,
+            }),
+            writes: RwLock::new(()),
+        })
+    }
+
+    /// Restores `cache` from the base snapshot and log in `config.dir`, if present
+    pub fn load(cache: &Arc<Cache>, config: &AofConfig) -> io::Result<ReplayReport> {
+        let mut report = ReplayReport::default();
+        match snapshot::load(cache, &config.base_path()) {
+            Ok(loaded) => report.base_keys = loaded.loaded,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let log_path = config.log_path();
+        let log = match fs::read(&log_path) {
+            Ok(log) => log,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(report),
+            Err(e) => return Err(e),
+        };
+
+        let executor = CommandExecutor::new(cache.clone());
+        let now = unix_ms();
+        let mut pos = 0;
+        while pos < log.len() {
+            let Some(record) = next_record(&log[pos..])? else {
+                break;
+            };
+            pos += record.len;
+            let args: Vec<&[u8]> = record.args.iter().map(Vec::as_slice).collect();
+            let command = Command::parse(&args)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            let elapsed = Duration::from_millis(now.saturating_sub(record.logged_at));
+            if let CommandResponse::Error(_) = executor.execute(rebase_ttl(command, elapsed)) {
+                report.failed += 1;
+            }
+            report.commands += 1;
+        }
+
+        if pos < log.len() {
+            report.truncated_bytes = log.len() - pos;
+            warn!(
+                "Truncating {} bytes of incomplete commands from {}",
+                report.truncated_bytes,
+                log_path.display()
+            );
+            OpenOptions::new()
+                .write(true)
+                .open(&log_path)?
+                .set_len(pos as u64)?;
+        }
+        Ok(report)
+    }
+
+    /// Commands in the log under `config.dir`, oldest first, with the Unix milliseconds
+    /// each was logged at. The base snapshot isn't included and an incomplete tail is
+    /// left out.
+    pub fn logged_commands(config: &AofConfig) -> io::Result<Vec<(u64, Vec<Vec<u8>>)>> {
+        let log = fs::read(config.log_path())?;
+        let mut commands = Vec::new();
+        let mut pos = 0;
+        while let Some(record) = next_record(&log[pos..])? {
+            pos += record.len;
+            commands.push((record.logged_at, record.args));
+        }
+        Ok(commands)
+    }
+
+    /// Held while a write command is applied and logged
+    pub fn begin_write(&self) -> RwLockReadGuard<'_, ()> {
+        self.writes.read().unwrap()
+    }
+
+    /// Appends a successful write, syncing it to disk under `FsyncPolicy::Always` or
+    /// when `fsync` asks for it
+    pub fn append(&self, args: &[Vec<u8>], fsync: bool) -> io::Result<()> {
+        let mut record = format!("#TS:{}\r\n*{}\r\n", unix_ms(), args.len()).into_bytes();
+        for arg in args {
+            record.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            record.extend_from_slice(arg);
+            record.extend_from_slice(b"\r\n");
+        }
+
+        let mut log = self.log.lock().unwrap();
+        let result = log.writer.write_all(&record).and_then(|()| {
+            if fsync || self.config.fsync == FsyncPolicy::Always {
+                log.writer.flush()?;
+                log.writer.get_ref().sync_data()?;
+            }
+            Ok(())
+        });
+        match &result {
+            Ok(()) => log.size += record.len() as u64,
+            Err(e) => warn!("Failed to append to the AOF: {}", e),
+        }
+        result
+    }
+
+    /// Pushes buffered appends to the OS, and to disk unless the policy is `No`
+    pub fn sync(&self) -> io::Result<()> {
+        let mut log = self.log.lock().unwrap();
+        log.writer.flush()?;
+        if self.config.fsync != FsyncPolicy::No {
+            log.writer.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the base with a snapshot of `cache` and empties the log. Writes wait
+    /// for the snapshot; reads carry on.
+    pub fn rewrite(&self, cache: &Cache) -> io::Result<()> {
+        let _paused = self.writes.write().unwrap();
+        let mut log = self.log.lock().unwrap();
+        log.writer.flush()?;
+        let saved = snapshot::save(cache, &self.config.base_path())?;
+        // The base is durable before the commands it replaces are dropped
+        log.writer.get_ref().set_len(0)?;
+        log.writer.get_ref().sync_all()?;
+        info!(
+            "Rewrote AOF: {} keys in a {} byte base, dropped {} bytes of log",
+            saved.keys, saved.bytes, log.size
+        );
+        log.size = 0;
+        log.base_size = saved.bytes as u64;
+        Ok(())
+    }
+
+    /// Whether the log has grown enough since the last rewrite to warrant another
+    pub fn needs_rewrite(&self) -> bool {
+        let log = self.log.lock().unwrap();
+        let threshold = log.base_size * self.config.rewrite_percentage / 100;
+        log.size >= self.config.rewrite_min_size.max(threshold)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.log.lock().unwrap().size
+    }
+
+    /// Syncs once a second and rewrites when the log outgrows its base
+    pub async fn run(self: Arc<Self>, cache: Arc<Cache>) {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.sync() {
+                warn!("Failed to sync the AOF: {}", e);
+            }
+            if self.needs_rewrite() {
+                let (aof, cache) = (self.clone(), cache.clone());
+                let rewritten = tokio::task::spawn_blocking(move || aof.rewrite(&cache)).await;
+                if let Ok(Err(e)) = rewritten {
+                    warn!("AOF rewrite failed: {}", e);
+                }
+            }
+        }
+    }
+}
+
+/// One logged command
+struct Record {
+    args: Vec<Vec<u8>>,
+    logged_at: u64, // Unix milliseconds
+    len: usize,     // Bytes in the log, annotation included
+}
+
+/// Next record in `buf`, or None if the buffer ends mid-record
+fn next_record(buf: &[u8]) -> io::Result<Option<Record>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    let Some(line_end) = buf.iter().position(|&b| b == b'\n') else {
+        return Ok(None);
+    };
+    let logged_at = std::str::from_utf8(&buf[..line_end])
+        .ok()
+        .and_then(|line| line.trim_end().strip_prefix("#TS:"))
+        .and_then(|ms| ms.parse().ok())
+        .ok_or_else(|| invalid("expected a #TS annotation"))?;
+
+    let frame_start = line_end + 1;
+    let (frame, used) = match decode(&buf[frame_start..]) {
+        Ok(Some(decoded)) => decoded,
+        Ok(None) => return Ok(None),
+        Err(e) => return Err(invalid(e.details())),
+    };
+    let OwnedFrame::Array(frames) = frame else {
+        return Err(invalid("expected an array of bulk strings"));
+    };
+    let args = frames
+        .into_iter()
+        .map(|frame| match frame {
+            OwnedFrame::BulkString(arg) => Ok(arg),
+            _ => Err(invalid("expected an array of bulk strings")),
+        })
+        .collect::<io::Result<_>>()?;
+    Ok(Some(Record {
+        args,
+        logged_at,
+        len: frame_start + used,
+    }))
+}
+
+/// Shortens relative TTLs by the time since the command was logged, so keys expire
+/// when they originally would have
+fn rebase_ttl(command: Command, elapsed: Duration) -> Command {
+    // A TTL that ran out while the server was down still has to expire the key
+    const MIN_TTL: Duration = Duration::from_millis(1);
+
+    match command {
+        // Only PXAT parses to JITTER 0, which is logged as a deadline already
+        Command::Set { ref options, .. } if options.jitter == Some(0) => command,
+        Command::Set {
+            key,
+            value,
+            mut options,
+            concern,
+        } => {
+            options.ttl = options
+                .ttl
+                .map(|ttl| ttl.saturating_sub(elapsed).max(MIN_TTL));
+            Command::Set {
+                key,
+                value,
+                options,
+                concern,
+            }
+        }
+        Command::Expire {
+            key,
+            ttl,
+            millis: false,
+        } => Command::Expire {
+            key,
+            ttl: ttl.saturating_sub(elapsed.as_secs()).max(1),
+            millis: false,
+        },
+        Command::Expire {
+            key,
+            ttl,
+            millis: true,
+        } => Command::Expire {
+            key,
+            ttl: ttl.saturating_sub(elapsed.as_millis() as u64).max(1),
+            millis: true,
+        },
+        Command::HExpire {
+            key,
+            seconds,
+            fields,
+        } => Command::HExpire {
+            key,
+            seconds: seconds.saturating_sub(elapsed.as_secs()).max(1),
+            fields,
+        },
+        command => command,
+    }
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Config, DependencyEdge, SetOptions, TtlPolicy, Value};
+
+    #[test]
+    fn test_aof_replay_and_rewrite() {
+        let dir = std::env::temp_dir().join(format!("dashdot-aof-{}", std::process::id()));
+        let config = AofConfig {
+            fsync: FsyncPolicy::Always,
+            ..AofConfig::new(&dir)
+        };
+        let cache = Arc::new(Cache::new(Config::default()));
+        let aof = Arc::new(Aof::open(config.clone()).unwrap());
+        let executor = CommandExecutor::new(cache.clone()).with_aof(aof.clone());
+        let set = |key: &str, value: &str| Command::Set {
+            key: key.into(),
+            value: value.into(),
+            options: SetOptions::default(),
+            concern: None,
+        };
+
+        executor.execute(set("a", "1"));
+        executor.execute(set("b", "2"));
+        executor.execute(Command::Expire {
+            key: "b".into(),
+            ttl: 3600,
+            millis: false,
+        });
+        executor.execute(Command::Get { key: "a".into() }); // Reads aren't logged
+        aof.rewrite(&cache).unwrap();
+        assert_eq!(aof.size(), 0);
+        executor.execute(Command::IncrBy {
+            key: "a".into(),
+            delta: 4,
+            limit: None,
+        });
+        executor.execute(Command::Del {
+            keys: vec!["c".into()],
+            cascade: false,
+        });
+        executor.execute(Command::ImportGraph {
+            edges: vec![DependencyEdge {
+                key: "b".into(),
+                parent: "a".into(),
+            }],
+        });
+
+        // A crash mid-append leaves a partial record behind
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(config.log_path())
+            .unwrap();
+        log.write_all(b"#TS:1\r\n*3\r\n$3\r\nSET\r\n").unwrap();
+
+        let restored = Arc::new(Cache::new(Config::default()));
+        let report = Aof::load(&restored, &config).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(report.base_keys, 2);
+        assert_eq!(report.commands, 3);
+        assert!(report.truncated_bytes > 0);
+        assert_eq!(restored.get("a"), Some(Value::Integer(5)));
+        assert!(restored.ttl("b") > 3500);
+        assert_eq!(restored.parents("b"), ["a"]);
+    }
+
+    #[test]
+    fn test_write_concern() {
+        let dir = std::env::temp_dir().join(format!("dashdot-concern-{}", std::process::id()));
+        let config = AofConfig {
+            fsync: FsyncPolicy::No,
+            ..AofConfig::new(&dir)
+        };
+        let cache = Arc::new(Cache::new(Config::default()));
+        let aof = Arc::new(Aof::open(config.clone()).unwrap());
+        let executor = CommandExecutor::new(cache.clone()).with_aof(aof);
+        let set = |key: &str, concern| Command::Set {
+            key: key.into(),
+            value: "v".into(),
+            options: SetOptions::default(),
+            concern,
+        };
+
+        executor.execute(set("scratch", Some(WriteConcern::CacheOnly)));
+        let response = executor.execute(set("order", Some(WriteConcern::Fsynced)));
+        assert!(matches!(response, CommandResponse::Ok));
+        assert!(cache.get("scratch").is_some());
+
+        let restored = Arc::new(Cache::new(Config::default()));
+        Aof::load(&restored, &config).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(restored.get("scratch").is_none());
+        assert!(restored.get("order").is_some());
+
+        // Without an AOF there's nothing to honour a durable write concern with
+        let plain = CommandExecutor::new(cache);
+        let response = plain.execute(set("k", Some(WriteConcern::Fsynced)));
+        assert!(matches!(response, CommandResponse::Error(_)));
+        assert!(matches!(
+            plain.execute(set("k", Some(WriteConcern::CacheOnly))),
+            CommandResponse::Ok
+        ));
+    }
+
+    #[test]
+    fn test_jittered_set_logs_deadline() {
+        let dir = std::env::temp_dir().join(format!("dashdot-jitter-{}", std::process::id()));
+        let config = AofConfig::new(&dir);
+        let cache_config = || Config {
+            ttl_policy: TtlPolicy {
+                jitter: Some(50),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let cache = Arc::new(Cache::new(cache_config()));
+        let aof = Arc::new(Aof::open(config.clone()).unwrap());
+        let executor = CommandExecutor::new(cache.clone()).with_aof(aof);
+        executor.execute(Command::Set {
+            key: "k".into(),
+            value: "v".into(),
+            options: SetOptions {
+                ttl: Some(Duration::from_secs(1000)),
+                ..Default::default()
+            },
+            concern: Some(WriteConcern::Fsynced),
+        });
+
+        // Replay lands on the deadline the write got, not a fresh roll
+        let log = fs::read_to_string(config.log_path()).unwrap();
+        assert!(log.contains("PXAT") && !log.contains("JITTER"));
+        let restored = Arc::new(Cache::new(cache_config()));
+        Aof::load(&restored, &config).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!((cache.expire_time("k") - restored.expire_time("k")).abs() <= 5);
+    }
+}
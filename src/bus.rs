@@ -0,0 +1,282 @@
+use crate::cache::Cache;
+use crate::executor::{Command, CommandExecutor, CommandResponse};
+use crate::metrics::Labels;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tracing::{debug, info, warn};
+
+/// Commands sent to peers. They only drop or mark keys, so no values cross the bus.
+const PROPAGATED: [&str; 4] = ["DEL", "DELCASCADE", "INVALIDATE", "FLUSHPATTERN"];
+/// Invalidations queued per peer while its link is down; beyond this they're dropped
+const PEER_QUEUE: usize = 10_000;
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Default)]
+pub struct BusConfig {
+    pub listen: Option<String>, // Where peers connect to deliver their invalidations
+    pub peers: Vec<String>,     // Instances this one delivers its invalidations to
+}
+
+/// One invalidation, sent as a JSON line
+#[derive(Debug, Serialize, Deserialize)]
+struct Message {
+    origin: String,
+    command: Vec<String>,
+}
+
+struct Peer {
+    addr: String,
+    tx: mpsc::Sender<Arc<String>>,
+}
+
+/// Keeps independent instances coherent without replicating values: successful
+/// DEL, INVALIDATE and FLUSHPATTERN commands are sent to every configured peer, which
+/// applies them locally. Peers don't forward what they receive, so every instance
+/// that should hear an invalidation must list the others as peers.
+pub struct InvalidationBus {
+    node_id: String,
+    listen: Option<String>,
+    peers: Vec<Peer>,
+    queues: Mutex<Vec<mpsc::Receiver<Arc<String>>>>, // Taken by `run`
+    published: AtomicU64,
+    received: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl InvalidationBus {
+    pub fn new(config: BusConfig) -> Self {
+        let (peers, queues) = config
+            .peers
+            .into_iter()
+            .map(|addr| {
+                let (tx, rx) = mpsc::channel(PEER_QUEUE);
+                (Peer { addr, tx }, rx)
+            })
+            .unzip();
+        Self {
+            node_id: format!("{:016x}", rand::random::<u64>()),
+            listen: config.listen,
+            peers,
+            queues: Mutex::new(queues),
+            published: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Queues a successful write for every peer if it's an invalidation
+    pub fn publish(&self, args: &[Vec<u8>]) {
+        let is_invalidation = args
+            .first()
+            .is_some_and(|name| PROPAGATED.iter().any(|p| p.as_bytes() == name));
+        if !is_invalidation || self.peers.is_empty() {
+            return;
+        }
+        // Invalidations carry only keys and patterns, which are UTF-8
+        let message = Message {
+            origin: self.node_id.clone(),
+            command: args
+                .iter()
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect(),
+        };
+        // Serializing a struct of strings can't fail
+        let mut line = serde_json::to_string(&message).unwrap_or_default();
+        line.push('\n');
+        let line = Arc::new(line);
+
+        self.published.fetch_add(1, Ordering::Relaxed);
+        for peer in &self.peers {
+            if peer.tx.try_send(line.clone()).is_err() {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Delivers queued invalidations to each peer and, with a listen address, applies
+    /// those peers deliver here
+    pub async fn run(self: Arc<Self>, cache: Arc<Cache>) {
+        let mut tasks = JoinSet::new();
+        let queues = std::mem::take(&mut *self.queues.lock().unwrap());
+        for (peer, queue) in self.peers.iter().zip(queues) {
+            tasks.spawn(deliver(peer.addr.clone(), queue));
+        }
+        if let Some(addr) = &self.listen {
+            match TcpListener::bind(addr).await {
+                Ok(listener) => {
+                    info!("Invalidation bus listening on {}", addr);
+                    tasks.spawn(self.clone().serve(cache, listener));
+                }
+                Err(e) => warn!("Invalidation bus can't listen on {}: {}", addr, e),
+            }
+        }
+        while tasks.join_next().await.is_some() {}
+    }
+
+    /// Accepts peer connections and applies the invalidations they send
+    pub async fn serve(self: Arc<Self>, cache: Arc<Cache>, listener: TcpListener) {
+        // Received invalidations go through a plain executor, so they aren't re-sent
+        let executor = Arc::new(CommandExecutor::new(cache));
+        loop {
+            let Ok((stream, peer)) = listener.accept().await else {
+                continue;
+            };
+            debug!("Invalidation bus peer {} connected", peer);
+            let bus = self.clone();
+            let executor = executor.clone();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stream).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    bus.apply(&executor, &line);
+                }
+            });
+        }
+    }
+
+    fn apply(&self, executor: &CommandExecutor, line: &str) {
+        let Ok(message) = serde_json::from_str::<Message>(line) else {
+            warn!("Ignoring malformed invalidation: {}", line);
+            return;
+        };
+        let is_invalidation = message
+            .command
+            .first()
+            .is_some_and(|name| PROPAGATED.contains(&name.as_str()));
+        if message.origin == self.node_id || !is_invalidation {
+            return;
+        }
+        let args: Vec<&[u8]> = message.command.iter().map(String::as_bytes).collect();
+        match Command::parse(&args).map(|command| executor.execute(command)) {
+            Ok(CommandResponse::Error(e)) => warn!("Invalidation from a peer failed: {}", e),
+            Ok(_) => {
+                self.received.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => warn!("Invalidation from a peer failed: {}", e),
+        }
+    }
+
+    pub fn render_metrics(&self, out: &mut String, labels: &Labels) {
+        let series = labels.series();
+        for (name, help, value) in [
+            (
+                "cache_bus_published_total",
+                "Invalidations sent to peers",
+                &self.published,
+            ),
+            (
+                "cache_bus_received_total",
+                "Invalidations applied from peers",
+                &self.received,
+            ),
+            (
+                "cache_bus_dropped_total",
+                "Invalidations dropped because a peer's queue was full",
+                &self.dropped,
+            ),
+        ] {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} counter", name).unwrap();
+            writeln!(out, "{}{} {}", name, series, value.load(Ordering::Relaxed)).unwrap();
+        }
+    }
+}
+
+/// Writes one peer's queue to it, reconnecting with backoff. A line that failed to
+/// send is retried on the next connection.
+async fn deliver(addr: String, mut queue: mpsc::Receiver<Arc<String>>) {
+    let mut backoff = RECONNECT_MIN;
+    let mut pending: Option<Arc<String>> = None;
+    loop {
+        let mut stream = match TcpStream::connect(&addr).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                debug!("Invalidation bus peer {} unreachable: {}", addr, e);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(RECONNECT_MAX);
+                continue;
+            }
+        };
+        info!("Invalidation bus connected to {}", addr);
+        backoff = RECONNECT_MIN;
+        loop {
+            let line = match pending.take() {
+                Some(line) => line,
+                None => match queue.recv().await {
+                    Some(line) => line,
+                    None => return,
+                },
+            };
+            if let Err(e) = stream.write_all(line.as_bytes()).await {
+                warn!("Lost invalidation bus peer {}: {}", addr, e);
+                pending = Some(line);
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Config, SetOptions, Value};
+
+    #[tokio::test]
+    async fn test_invalidations_reach_peers() {
+        let remote = Arc::new(Cache::new(Config::default()));
+        for key in ["a", "b", "session:1"] {
+            remote
+                .set(key.into(), Value::String("v".into()), SetOptions::default())
+                .unwrap();
+        }
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let remote_bus = Arc::new(InvalidationBus::new(BusConfig::default()));
+        tokio::spawn(remote_bus.clone().serve(remote.clone(), listener));
+
+        let local = Arc::new(Cache::new(Config::default()));
+        let bus = Arc::new(InvalidationBus::new(BusConfig {
+            listen: None,
+            peers: vec![addr],
+        }));
+        tokio::spawn(bus.clone().run(local.clone()));
+        let executor = CommandExecutor::new(local.clone()).with_invalidation_bus(bus.clone());
+
+        executor.execute(Command::Set {
+            key: "a".into(),
+            value: "local".into(),
+            options: SetOptions::default(),
+            concern: None,
+        });
+        executor.execute(Command::Del {
+            keys: vec!["a".into()],
+            cascade: false,
+        });
+        executor.execute(Command::FlushPattern {
+            pattern: "session:*".into(),
+        });
+
+        for _ in 0..500 {
+            if remote_bus.received.load(Ordering::Relaxed) == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // Only the invalidations crossed; SET carries a value and stays local
+        assert_eq!(bus.published.load(Ordering::Relaxed), 2);
+        assert!(remote.get("a").is_none());
+        assert!(remote.get("session:1").is_none());
+        assert!(remote.get("b").is_some());
+
+        let mut metrics = String::new();
+        bus.render_metrics(&mut metrics, &Labels::new(&[]));
+        assert!(metrics.contains("cache_bus_published_total 2"));
+    }
+}
@@ -1,22 +1,60 @@
 use dashmap::DashMap;
+use dashmap::mapref::entry::Entry as IndexEntry;
 
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fmt::Write;
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
-use std::sync::{Arc, RwLock};
-use std::time::{Duration, Instant};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tracing::debug;
 
 use crate::cache_errors::CacheError;
+use crate::contention::{ContentionReport, ContentionTracker};
+use crate::events::{CacheEvent, EventBus};
+use crate::eviction::{EVICTION_SAMPLES, EvictionPolicy, KeyLimitPolicy, LfuConfig, Priority};
+use crate::expiry::{ExpiryIndex, SampleScheduler};
+use crate::hyperloglog::HyperLogLog;
+use crate::json_path::{self, JsonPath};
+use crate::metrics::Labels;
+use crate::notifications::KeyspaceEvents;
+use crate::pubsub::glob_match;
+use crate::shards::{MapEntry, ShardedMap};
+use crate::stream::{Stream, StreamEntry, StreamId};
+use crate::tombstone::{Tombstone, Tombstones};
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub max_memory: Option<usize>,
     pub max_keys: Option<usize>,
     pub enable_dependencies: bool,
+    pub shard_amount: Option<usize>, // Rounded up to a power of two; defaults to 4x cores
+    pub shard_growth_keys: Option<usize>, // Grow the shard count 4x once shards average this many keys
     pub ttl_cleanup_interval: Duration,
+    pub notify_misses: bool,   // Publish CacheEvent::Miss for absent keys
+    pub expire_children: bool, // Remove dependents eagerly when a parent expires
+    pub info_children_depth: usize, // Depth walked when counting children for key info
+    pub max_children_results: usize, // Cap on children returned or counted per request
+    pub namespace_delimiter: char,
+    pub ttl_policy: TtlPolicy,
+    pub namespace_ttl_policies: HashMap<String, TtlPolicy>, // Overrides ttl_policy per field
+    pub metric_labels: Vec<(String, String)>, // Static labels on every exported series
+    pub max_metric_namespaces: usize,         // Namespaces with their own series; 0 disables them
+    pub encode_numbers: bool, // Store canonical numeric strings as Integer/Float on SET
+    pub stale_grace: Option<Duration>, // Keep serving expired values, marked stale, this long
+    pub max_pinned_memory: Option<usize>, // Cap on bytes held by pinned keys
+    pub eviction_policy: EvictionPolicy, // Applied when max_memory is reached
+    pub key_limit_policy: KeyLimitPolicy, // Applied when max_keys is reached
+    pub lfu: LfuConfig,       // Access count growth and decay, as ranked by LFU
+    pub track_contention: bool, // Time lock waits per shard; see `contention_report`
+    pub keyspace_events: KeyspaceEvents, // Events republished by a `KeyspaceNotifier`
+    pub command_time_budget: Option<Duration>, // KEYS and GETCHILDREN stop here with a cursor
+    pub tombstone_retention: Option<Duration>, // Soft delete: deleted keys leave a tombstone this long
+    pub compaction_interval: Option<Duration>, // Run `purge_memory` in the background this often
+    pub cascade_deletes: bool, // DEL also removes every dependent of the deleted keys
+    pub max_dependency_depth: usize, // Levels of parents above any key, however the link is made
 }
 
 impl Default for Config {
@@ -25,11 +63,203 @@ impl Default for Config {
             max_memory: None,
             max_keys: None,
             enable_dependencies: true,
+            shard_amount: None,
+            shard_growth_keys: None,
             ttl_cleanup_interval: Duration::from_secs(60),
+            notify_misses: false,
+            expire_children: false,
+            info_children_depth: 16,
+            max_children_results: 10_000,
+            namespace_delimiter: ':',
+            ttl_policy: TtlPolicy::default(),
+            namespace_ttl_policies: HashMap::new(),
+            metric_labels: Vec::new(),
+            max_metric_namespaces: 64,
+            encode_numbers: false,
+            stale_grace: None,
+            max_pinned_memory: None,
+            eviction_policy: EvictionPolicy::default(),
+            key_limit_policy: KeyLimitPolicy::default(),
+            lfu: LfuConfig::default(),
+            track_contention: false,
+            keyspace_events: KeyspaceEvents::default(),
+            command_time_budget: None,
+            tombstone_retention: None,
+            compaction_interval: None,
+            cascade_deletes: false,
+            max_dependency_depth: 64,
         }
     }
 }
 
+impl Config {
+    /// Defaults overridden by DASHDOT_MAX_MEMORY, DASHDOT_MAX_KEYS, DASHDOT_EVICTION_POLICY,
+    /// DASHDOT_KEY_LIMIT_POLICY, DASHDOT_LFU_DECAY_SECS, DASHDOT_LFU_LOG_FACTOR,
+    /// DASHDOT_TRACK_CONTENTION, DASHDOT_NOTIFY_KEYSPACE_EVENTS, DASHDOT_COMMAND_BUDGET_MS,
+    /// DASHDOT_TOMBSTONE_RETENTION_SECS, DASHDOT_COMPACTION_INTERVAL_SECS,
+    /// DASHDOT_DEFAULT_TTL_SECS, DASHDOT_MAX_TTL_SECS, DASHDOT_TTL_JITTER_PERCENT,
+    /// DASHDOT_TTL_CLEANUP_MS, DASHDOT_CASCADE_DELETES, DASHDOT_MAX_DEPENDENCY_DEPTH,
+    /// DASHDOT_SHARDS, DASHDOT_SHARD_GROWTH_KEYS, DASHDOT_ENCODE_NUMBERS,
+    /// DASHDOT_STALE_GRACE_SECS and DASHDOT_MAX_PINNED_MEMORY
+    pub fn from_env() -> Self {
+        let parsed = |name| std::env::var(name).ok().and_then(|v| v.parse().ok());
+        let defaults = Self::default();
+        let lfu = LfuConfig::default();
+        Self {
+            max_memory: parsed("DASHDOT_MAX_MEMORY"),
+            max_keys: parsed("DASHDOT_MAX_KEYS"),
+            eviction_policy: std::env::var("DASHDOT_EVICTION_POLICY")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default(),
+            key_limit_policy: std::env::var("DASHDOT_KEY_LIMIT_POLICY")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default(),
+            lfu: LfuConfig {
+                decay_period: parsed("DASHDOT_LFU_DECAY_SECS")
+                    .map_or(lfu.decay_period, |secs: usize| {
+                        Duration::from_secs(secs as u64)
+                    }),
+                log_factor: parsed("DASHDOT_LFU_LOG_FACTOR")
+                    .map_or(lfu.log_factor, |factor: usize| factor as u32),
+            },
+            track_contention: std::env::var("DASHDOT_TRACK_CONTENTION")
+                .is_ok_and(|v| v == "1" || v == "true"),
+            keyspace_events: std::env::var("DASHDOT_NOTIFY_KEYSPACE_EVENTS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default(),
+            command_time_budget: parsed("DASHDOT_COMMAND_BUDGET_MS")
+                .filter(|&ms: &usize| ms > 0)
+                .map(|ms| Duration::from_millis(ms as u64)),
+            tombstone_retention: parsed("DASHDOT_TOMBSTONE_RETENTION_SECS")
+                .filter(|&secs: &usize| secs > 0)
+                .map(|secs| Duration::from_secs(secs as u64)),
+            compaction_interval: parsed("DASHDOT_COMPACTION_INTERVAL_SECS")
+                .filter(|&secs: &usize| secs > 0)
+                .map(|secs| Duration::from_secs(secs as u64)),
+            cascade_deletes: std::env::var("DASHDOT_CASCADE_DELETES")
+                .is_ok_and(|v| v == "1" || v == "true"),
+            max_dependency_depth: parsed("DASHDOT_MAX_DEPENDENCY_DEPTH")
+                .filter(|&depth: &usize| depth > 0)
+                .unwrap_or(defaults.max_dependency_depth),
+            shard_amount: parsed("DASHDOT_SHARDS"),
+            shard_growth_keys: parsed("DASHDOT_SHARD_GROWTH_KEYS").filter(|&keys: &usize| keys > 0),
+            encode_numbers: std::env::var("DASHDOT_ENCODE_NUMBERS")
+                .is_ok_and(|v| v == "1" || v == "true"),
+            stale_grace: parsed("DASHDOT_STALE_GRACE_SECS")
+                .filter(|&secs: &usize| secs > 0)
+                .map(|secs| Duration::from_secs(secs as u64)),
+            max_pinned_memory: parsed("DASHDOT_MAX_PINNED_MEMORY"),
+            ttl_cleanup_interval: parsed("DASHDOT_TTL_CLEANUP_MS")
+                .map_or(defaults.ttl_cleanup_interval, |ms: usize| {
+                    Duration::from_millis(ms as u64)
+                }),
+            ttl_policy: TtlPolicy {
+                default: parsed("DASHDOT_DEFAULT_TTL_SECS")
+                    .filter(|&secs: &usize| secs > 0)
+                    .map(|secs| Duration::from_secs(secs as u64)),
+                max: parsed("DASHDOT_MAX_TTL_SECS")
+                    .filter(|&secs: &usize| secs > 0)
+                    .map(|secs| Duration::from_secs(secs as u64)),
+                jitter: parsed("DASHDOT_TTL_JITTER_PERCENT")
+                    .filter(|&percent: &usize| percent > 0)
+                    .map(|percent| percent.min(100) as u32),
+                ..TtlPolicy::default()
+            },
+            ..Self::default()
+        }
+    }
+
+    /// Effective TTL policy for a key: its namespace's policy, falling back to the global one
+    pub fn ttl_policy_for(&self, key: &str) -> TtlPolicy {
+        let global = &self.ttl_policy;
+        let Some(policy) = namespace_of(key, self.namespace_delimiter)
+            .and_then(|ns| self.namespace_ttl_policies.get(ns))
+        else {
+            return global.clone();
+        };
+
+        TtlPolicy {
+            default: policy.default.or(global.default),
+            min: policy.min.or(global.min),
+            max: policy.max.or(global.max),
+            jitter: policy.jitter.or(global.jitter),
+        }
+    }
+}
+
+/// Retention policy applied to TTLs on write
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TtlPolicy {
+    pub default: Option<Duration>, // Used when the caller omits a TTL
+    pub min: Option<Duration>,
+    pub max: Option<Duration>, // Also bounds keys written without a TTL
+    pub jitter: Option<u32>,   // Spread TTLs up to this percent either way, within min and max
+}
+
+impl TtlPolicy {
+    pub fn apply(&self, ttl: Option<Duration>) -> Option<Duration> {
+        let ttl = ttl.or(self.default).or(self.max)?;
+        let ttl = self.jitter.map_or(ttl, |percent| jittered(ttl, percent));
+        let ttl = self.min.map_or(ttl, |min| ttl.max(min));
+        Some(self.max.map_or(ttl, |max| ttl.min(max)))
+    }
+}
+
+/// Moves a TTL by a random amount up to `percent` of it either way, so keys written
+/// together don't all expire together
+fn jittered(ttl: Duration, percent: u32) -> Duration {
+    let spread = ttl.as_secs_f64() * f64::from(percent.min(100)) / 100.0;
+    if spread == 0.0 {
+        return ttl;
+    }
+    let secs = ttl.as_secs_f64() + rand::random_range(-spread..=spread);
+    Duration::from_secs_f64(secs).max(Duration::from_millis(1))
+}
+
+/// Expired share of sampled keys above which cleanup samples again at once, and
+/// background expiration speeds up
+const BUSY_EXPIRED_RATIO: f64 = 0.25;
+/// Longest a cleanup keeps resampling a keyspace full of expired keys
+const EXPIRE_CYCLE_BUDGET: Duration = Duration::from_millis(25);
+
+/// Delay before the next background expiration pass: a quarter of `period` when
+/// many of the keys just sampled had expired
+fn cleanup_delay(period: Duration, sampled: u64, expired: u64) -> Duration {
+    if sampled > 0 && expired as f64 / sampled as f64 >= BUSY_EXPIRED_RATIO {
+        period / 4
+    } else {
+        period
+    }
+}
+
+/// Namespace label for keys without a delimiter
+pub const NO_NAMESPACE: &str = "(none)";
+/// Shared label for namespaces past `Config::max_metric_namespaces`
+pub const OTHER_NAMESPACE: &str = "(other)";
+
+/// Namespace of a key: everything before the first delimiter, e.g. `session` for `session:42`
+pub fn namespace_of(key: &str, delimiter: char) -> Option<&str> {
+    key.split_once(delimiter).map(|(namespace, _)| namespace)
+}
+
+/// Point past which a budgeted scan stops at its next checkpoint; the default never
+/// passes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Deadline(Option<Instant>);
+
+impl Deadline {
+    pub fn after(budget: Option<Duration>) -> Self {
+        Self(budget.map(|budget| Instant::now() + budget))
+    }
+
+    pub fn passed(&self) -> bool {
+        self.0.is_some_and(|at| Instant::now() >= at)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum Value {
     String(String),
@@ -39,6 +269,9 @@ pub enum Value {
     Hash(HashMap<String, Value>),
     List(Vec<Value>),
     Set(HashSet<String>),
+    Stream(Stream),
+    HyperLogLog(HyperLogLog),
+    Json(serde_json::Value),
 }
 
 impl fmt::Display for Value {
@@ -51,11 +284,103 @@ impl fmt::Display for Value {
             Value::Hash(h) => write!(f, "hash with {} fields", h.len()),
             Value::List(l) => write!(f, "list with {} items", l.len()),
             Value::Set(s) => write!(f, "set with {} members", s.len()),
+            Value::Stream(s) => write!(f, "stream with {} entries", s.len()),
+            Value::HyperLogLog(h) => write!(f, "hyperloglog of about {} members", h.count()),
+            Value::Json(j) => write!(f, "{}", j),
         }
     }
 }
 
+/// Strings up to this length use the compact "embstr" encoding in Redis
+const EMBSTR_MAX_LEN: usize = 44;
+
+/// Largest string or byte value range writes may grow a key to, as in Redis
+pub const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
 impl Value {
+    /// Stores strings that print back identically as integers or floats, so reads
+    /// are unaffected; anything else stays a string
+    pub fn encode_number(s: String) -> Value {
+        if let Ok(i) = s.parse::<i64>()
+            && i.to_string() == s
+        {
+            return Value::Integer(i);
+        }
+        if let Ok(f) = s.parse::<f64>()
+            && f.is_finite()
+            && f.to_string() == s
+        {
+            return Value::Float(f);
+        }
+        Value::String(s)
+    }
+
+    /// Type as reported by TYPE: numbers, bytes and HyperLogLogs are all strings to
+    /// clients, as in Redis
+    pub fn redis_type(&self) -> &'static str {
+        match self {
+            Value::String(_)
+            | Value::Integer(_)
+            | Value::Float(_)
+            | Value::Bytes(_)
+            | Value::HyperLogLog(_) => "string",
+            Value::Json(_) => "ReJSON-RL", // As RedisJSON names it
+            Value::Hash(_) => "hash",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+            Value::Stream(_) => "stream",
+        }
+    }
+
+    /// Internal representation as reported by OBJECT ENCODING
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Value::Integer(_) => "int",
+            Value::Float(_) => "float",
+            Value::String(s) if s.len() <= EMBSTR_MAX_LEN => "embstr",
+            Value::String(_) | Value::Bytes(_) | Value::HyperLogLog(_) | Value::Json(_) => "raw",
+            Value::Hash(_) | Value::Set(_) => "hashtable",
+            Value::List(_) => "list",
+            Value::Stream(_) => "stream",
+        }
+    }
+
+    /// Text values as strings, binary ones as bytes
+    pub fn from_bytes(bytes: Vec<u8>) -> Value {
+        match String::from_utf8(bytes) {
+            Ok(s) => Value::String(s),
+            Err(e) => Value::Bytes(e.into_bytes()),
+        }
+    }
+
+    /// Byte reading of string-like values, formatting numbers the way GET does
+    pub fn as_bytes(&self) -> Option<Cow<'_, [u8]>> {
+        match self {
+            Value::String(s) => Some(Cow::Borrowed(s.as_bytes())),
+            Value::Bytes(b) => Some(Cow::Borrowed(b)),
+            Value::Integer(_) | Value::Float(_) => Some(Cow::Owned(self.to_string().into_bytes())),
+            _ => None,
+        }
+    }
+
+    /// Integer reading of the value, parsing strings the way INCR does
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            Value::String(s) => s.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Float(f) => Some(*f),
+            Value::Integer(i) => Some(*i as f64),
+            Value::String(s) => s.parse().ok().filter(|f: &f64| f.is_finite()),
+            _ => None,
+        }
+    }
+
     pub fn type_name(&self) -> &'static str {
         match self {
             Value::String(_) => "string",
@@ -65,6 +390,9 @@ impl Value {
             Value::Hash(_) => "hash",
             Value::List(_) => "list",
             Value::Set(_) => "set",
+            Value::Stream(_) => "stream",
+            Value::HyperLogLog(_) => "hyperloglog",
+            Value::Json(_) => "json",
         }
     }
 
@@ -92,10 +420,41 @@ impl Value {
                 size += s.iter().map(|v| v.capacity()).sum::<usize>();
                 size
             }
+            Value::Stream(s) => s.memory_usage(),
+            Value::HyperLogLog(h) => h.memory_usage(),
+            Value::Json(j) => json_path::memory_usage(j),
+        }
+    }
+
+    /// Drops spare capacity left by the buffers the value was built from or grown in
+    pub fn shrink_to_fit(&mut self) {
+        match self {
+            Value::String(s) => s.shrink_to_fit(),
+            Value::Integer(_) | Value::Float(_) => {}
+            Value::Bytes(b) => b.shrink_to_fit(),
+            Value::Hash(h) => {
+                h.shrink_to_fit();
+                h.values_mut().for_each(Value::shrink_to_fit);
+            }
+            Value::List(l) => {
+                l.shrink_to_fit();
+                l.iter_mut().for_each(Value::shrink_to_fit);
+            }
+            Value::Set(s) => s.shrink_to_fit(),
+            Value::Stream(s) => s.shrink_to_fit(),
+            Value::HyperLogLog(h) => h.shrink_to_fit(),
+            Value::Json(_) => {} // Documents are rebuilt on every write
         }
     }
 }
 
+/// Result of a bounded increment, carrying the value after the decision
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncrOutcome {
+    Applied(i64),
+    Rejected(i64), // Would have exceeded the limit; value left unchanged
+}
+
 #[derive(Debug, Clone)]
 pub struct Ttl {
     pub expires_at: Instant,
@@ -112,6 +471,24 @@ impl Ttl {
         }
     }
 
+    /// Expires at a wall-clock time; past times are already expired
+    pub fn until(at: SystemTime) -> Self {
+        Self::new(
+            at.duration_since(SystemTime::now())
+                .unwrap_or(Duration::ZERO),
+        )
+    }
+
+    /// The expiry as a wall-clock time. Tracked on the monotonic clock, so this
+    /// moves if the system clock is changed.
+    pub fn deadline(&self) -> SystemTime {
+        let now = Instant::now();
+        match self.expires_at.checked_duration_since(now) {
+            Some(left) => SystemTime::now() + left,
+            None => SystemTime::now() - now.duration_since(self.expires_at),
+        }
+    }
+
     pub fn sliding(duration: Duration) -> Self {
         Self {
             expires_at: Instant::now() + duration,
@@ -124,6 +501,11 @@ impl Ttl {
         Instant::now() >= self.expires_at
     }
 
+    /// Expired, and for longer than `grace`
+    pub fn is_past_grace(&self, grace: Duration) -> bool {
+        Instant::now() >= self.expires_at + grace
+    }
+
     pub fn reset(&mut self) {
         if self.sliding {
             self.expires_at = Instant::now() + self.duration;
@@ -140,85 +522,171 @@ impl Ttl {
     }
 }
 
+/// Stable identity of an entry, kept when its value is overwritten or its key renamed
+pub type EntryId = u64;
+
+static NEXT_ENTRY_ID: AtomicU64 = AtomicU64::new(1);
+
+fn next_entry_id() -> EntryId {
+    NEXT_ENTRY_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// A link from an entry to one it depends on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParentLink {
+    pub id: EntryId,                 // Resolved to a key through `Cache::key_of`
+    pub expires_at: Option<Instant>, // The link lapses here; the key stays, without this parent
+}
+
+impl ParentLink {
+    pub fn new(id: EntryId, expires_at: Option<Instant>) -> Self {
+        Self { id, expires_at }
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.expires_at.is_none_or(|at| at > Instant::now())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Entry {
+    pub id: EntryId,
     pub value: Value,
     pub ttl: Option<Ttl>,
-    pub parent: Option<String>,
+    pub parents: Vec<ParentLink>, // Invalid once any live parent is gone or invalid
+    pub tags: Option<Box<Vec<String>>>, // Invalidated together by INVALIDATE_TAG
     pub access_count: u64,
     pub last_accessed: Instant,
     pub created_at: Instant,
+    pub field_expiry: Option<Box<HashMap<String, Instant>>>, // Per-field TTLs of a hash
+    pub stale: bool,  // Invalidated in place; cleared by the next write
+    pub pinned: bool, // Exempt from eviction
+    pub priority: Priority,
 }
 
 impl Entry {
     pub fn new(value: Value) -> Self {
         let now = Instant::now();
         Self {
+            id: next_entry_id(),
             value,
             ttl: None,
-            parent: None,
+            parents: Vec::new(),
+            tags: None,
             access_count: 0,
             last_accessed: now,
             created_at: now,
+            field_expiry: None,
+            stale: false,
+            pinned: false,
+            priority: Priority::Normal,
         }
     }
 
     pub fn with_ttl(value: Value, ttl: Ttl) -> Self {
         let now = Instant::now();
         Self {
+            id: next_entry_id(),
             value,
             ttl: Some(ttl),
-            parent: None,
+            parents: Vec::new(),
+            tags: None,
             access_count: 0,
             last_accessed: now,
             created_at: now,
+            field_expiry: None,
+            stale: false,
+            pinned: false,
+            priority: Priority::Normal,
         }
     }
 
-    pub fn with_parent(value: Value, parent: String) -> Self {
+    pub fn with_parent(value: Value, parent: EntryId) -> Self {
         let now = Instant::now();
         Self {
+            id: next_entry_id(),
             value,
             ttl: None,
-            parent: Some(parent),
+            parents: vec![ParentLink::new(parent, None)],
+            tags: None,
             access_count: 0,
             last_accessed: now,
             created_at: now,
+            field_expiry: None,
+            stale: false,
+            pinned: false,
+            priority: Priority::Normal,
         }
     }
 
-    pub fn is_valid(&self, cache: &DashMap<String, Entry>) -> bool {
-        if let Some(ttl) = &self.ttl {
-            if ttl.is_expired() {
-                return false;
-            }
-        }
+    pub fn tags(&self) -> &[String] {
+        self.tags.as_deref().map_or(&[], Vec::as_slice)
+    }
 
-        if let Some(parent_key) = &self.parent {
-            match cache.get(parent_key) {
-                Some(parent_entry) => parent_entry.is_valid(cache),
-                None => false,
-            }
-        } else {
-            true
+    /// Replaces the tags, keeping the first of any repeats
+    pub fn set_tags(&mut self, mut tags: Vec<String>) {
+        let mut seen = HashSet::new();
+        tags.retain(|tag| seen.insert(tag.clone()));
+        self.tags = (!tags.is_empty()).then(|| Box::new(tags));
+    }
+
+    /// Parents whose links haven't lapsed, in the order they were linked
+    pub fn live_parents(&self) -> impl Iterator<Item = EntryId> + '_ {
+        self.parents
+            .iter()
+            .filter(|link| link.is_live())
+            .map(|link| link.id)
+    }
+
+    pub fn has_live_parent(&self, parent: EntryId) -> bool {
+        self.live_parents().any(|id| id == parent)
+    }
+
+    pub fn is_valid(&self, cache: &Cache) -> bool {
+        if let Some(ttl) = &self.ttl
+            && ttl.is_expired()
+        {
+            return false;
         }
+
+        let generation = cache.chain_generation();
+        let parents: Vec<EntryId> = self.live_parents().collect();
+        parents.is_empty() || cache.ancestors_live(self.id, parents, Duration::ZERO, generation)
     }
 
-    pub fn mark_accessed(&mut self) {
-        self.access_count += 1;
-        self.last_accessed = Instant::now();
+    pub fn mark_accessed(&mut self, lfu: &LfuConfig) {
+        let now = Instant::now();
+        self.access_count = lfu.increment(self.frequency(now, lfu));
+        self.last_accessed = now;
         if let Some(ttl) = &mut self.ttl {
             ttl.reset();
         }
     }
 
+    /// Access count decayed for the time since the last access
+    pub fn frequency(&self, now: Instant, lfu: &LfuConfig) -> u64 {
+        lfu.decay(
+            self.access_count,
+            now.saturating_duration_since(self.last_accessed),
+        )
+    }
+
     pub fn memory_usage(&self) -> usize {
         let mut size = std::mem::size_of_val(self);
 
         size += self.value.memory_usage();
 
-        if let Some(parent) = &self.parent {
-            size += parent.capacity();
+        if let Some(tags) = &self.tags {
+            size += std::mem::size_of::<Vec<String>>();
+            size += tags.iter().map(String::capacity).sum::<usize>();
+        }
+
+        if let Some(field_expiry) = &self.field_expiry {
+            size += std::mem::size_of::<HashMap<String, Instant>>();
+            size += field_expiry
+                .keys()
+                .map(|field| field.capacity() + std::mem::size_of::<Instant>())
+                .sum::<usize>();
         }
 
         size
@@ -233,6 +701,27 @@ pub struct Stats {
     pub sets: AtomicU64,
     pub deletes: AtomicU64,
     pub memory_usage: AtomicUsize,
+    pub pinned_memory: AtomicUsize,
+    pub evicted_keys: AtomicU64,
+    pub expired_keys: AtomicU64,
+    pub orphaned_keys: AtomicU64, // Dependents removed after their parent chain broke
+    pub purged_memory: AtomicU64, // Spare capacity released by `Cache::purge_memory`
+    // RESP connections
+    pub connected_clients: AtomicUsize,
+    pub total_connections: AtomicU64,
+    pub commands_processed: AtomicU64,
+    pub net_input_bytes: AtomicU64,
+    pub net_output_bytes: AtomicU64,
+    pub labels: Labels,
+    pub namespaces: DashMap<String, NamespaceStats>,
+}
+
+/// Counters kept per key namespace
+#[derive(Debug, Default)]
+pub struct NamespaceStats {
+    pub hits: AtomicU64,
+    pub misses: AtomicU64,
+    pub memory_usage: AtomicUsize,
 }
 
 impl Stats {
@@ -240,11 +729,13 @@ impl Stats {
     pub fn render(&self) -> String {
         let mut s = String::with_capacity(256);
 
+        let labels = self.labels.series();
+
         macro_rules! write_metric {
             ($buffer:expr, $name:expr, $help:expr, $type:expr, $value:expr) => {
                 writeln!($buffer, "# HELP {} {}", $name, $help).unwrap();
                 writeln!($buffer, "# TYPE {} {}", $name, $type).unwrap();
-                writeln!($buffer, "{} {}", $name, $value).unwrap();
+                writeln!($buffer, "{}{} {}", $name, labels, $value).unwrap();
             };
         }
 
@@ -283,39 +774,235 @@ impl Stats {
             "gauge",
             self.memory_usage.load(Ordering::Relaxed)
         );
+        write_metric!(
+            &mut s,
+            "cache_evicted_keys_total",
+            "Keys evicted to stay under the memory limit",
+            "counter",
+            self.evicted_keys.load(Ordering::Relaxed)
+        );
+        write_metric!(
+            &mut s,
+            "cache_expired_keys_total",
+            "Keys removed for reaching their TTL",
+            "counter",
+            self.expired_keys.load(Ordering::Relaxed)
+        );
+        write_metric!(
+            &mut s,
+            "cache_orphaned_keys_total",
+            "Dependent keys removed because an ancestor was gone or expired",
+            "counter",
+            self.orphaned_keys.load(Ordering::Relaxed)
+        );
+        write_metric!(
+            &mut s,
+            "cache_purged_memory_bytes_total",
+            "Spare value capacity released by memory purges",
+            "counter",
+            self.purged_memory.load(Ordering::Relaxed)
+        );
+        write_metric!(
+            &mut s,
+            "cache_pinned_memory_bytes",
+            "Estimated memory held by keys pinned against eviction",
+            "gauge",
+            self.pinned_memory.load(Ordering::Relaxed)
+        );
+        write_metric!(
+            &mut s,
+            "cache_connected_clients",
+            "Number of open RESP connections",
+            "gauge",
+            self.connected_clients.load(Ordering::Relaxed)
+        );
+        write_metric!(
+            &mut s,
+            "cache_connections_total",
+            "Total number of accepted RESP connections",
+            "counter",
+            self.total_connections.load(Ordering::Relaxed)
+        );
+        write_metric!(
+            &mut s,
+            "cache_commands_processed_total",
+            "Total number of commands processed over RESP",
+            "counter",
+            self.commands_processed.load(Ordering::Relaxed)
+        );
+        write_metric!(
+            &mut s,
+            "cache_net_input_bytes_total",
+            "Total bytes read from RESP connections",
+            "counter",
+            self.net_input_bytes.load(Ordering::Relaxed)
+        );
+        write_metric!(
+            &mut s,
+            "cache_net_output_bytes_total",
+            "Total bytes written to RESP connections",
+            "counter",
+            self.net_output_bytes.load(Ordering::Relaxed)
+        );
+
+        let mut namespaces: Vec<_> = self.namespaces.iter().collect();
+        namespaces.sort_by(|a, b| a.key().cmp(b.key()));
+        let write_family = |s: &mut String, name, help, kind, value: fn(&NamespaceStats) -> u64| {
+            if namespaces.is_empty() {
+                return;
+            }
+            writeln!(s, "# HELP {} {}", name, help).unwrap();
+            writeln!(s, "# TYPE {} {}", name, kind).unwrap();
+            for namespace in &namespaces {
+                let labels = self.labels.series_with(&[("namespace", namespace.key())]);
+                writeln!(s, "{}{} {}", name, labels, value(namespace.value())).unwrap();
+            }
+        };
+        write_family(
+            &mut s,
+            "cache_namespace_hits_total",
+            "Cache hits per key namespace",
+            "counter",
+            |n| n.hits.load(Ordering::Relaxed),
+        );
+        write_family(
+            &mut s,
+            "cache_namespace_misses_total",
+            "Cache misses per key namespace",
+            "counter",
+            |n| n.misses.load(Ordering::Relaxed),
+        );
+        write_family(
+            &mut s,
+            "cache_namespace_memory_usage_bytes",
+            "Estimated memory usage per key namespace in bytes",
+            "gauge",
+            |n| n.memory_usage.load(Ordering::Relaxed) as u64,
+        );
 
         s
     }
 }
 
+/// Longest a cleanup spends moving keys into a grown shard generation
+const MIGRATE_CYCLE_BUDGET: Duration = Duration::from_millis(10);
+/// Keys moved between budget checks while migrating
+const MIGRATE_BATCH: usize = 256;
+/// Factor the shard count grows by when `Config::shard_growth_keys` is reached
+const SHARD_GROWTH_FACTOR: usize = 4;
+/// Background expiration period while a migration is underway
+const MIGRATE_PERIOD: Duration = Duration::from_millis(100);
+
+/// Lookups derived from entries' parent links and tags, boxed to keep `Cache` small
+#[derive(Default)]
+struct DependencyIndexes {
+    children_by_id: DashMap<EntryId, HashSet<EntryId>>, // Reverse of each entry's parent links
+    ids_by_tag: DashMap<String, HashSet<EntryId>>,
+    chain_checks: DashMap<EntryId, ChainCheck>, // Ancestor chains last checked
+    chain_generation: AtomicU64,                // Orders checks against the writes undoing them
+}
+
+/// An entry's ancestors, found live by a walk begun at `generation`, or marked stale
+/// by a change to them at `generation`. A live chain stays live until the earliest
+/// of its TTLs unless marked stale.
+#[derive(Debug, Clone, Copy)]
+struct ChainCheck {
+    generation: u64,
+    expires_at: Option<Instant>,
+    live: bool,
+}
+
 pub struct Cache {
-    data: DashMap<String, Entry>,
-    config: Config,
+    data: ShardedMap<Entry>,
+    keys_by_id: DashMap<EntryId, String>, // Parent links are IDs, resolved here
+    indexes: Box<DependencyIndexes>,
+    config: Arc<Config>,
     stats: Arc<Stats>,
-    cleanup_shard_index: AtomicUsize,
+    sampler: Box<SampleScheduler>,
     dependency_lock: RwLock<()>,
+    events: EventBus,
+    expiry_index: ExpiryIndex,
+    contention: Option<Box<ContentionTracker>>,
+    tombstones: Option<Tombstones>,
+    started_at: Instant,
+}
+
+/// What happens to keys reached by a cascade invalidation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InvalidationMode {
+    #[default]
+    Delete,
+    Stale, // Keep serving the value, flagged as stale
+}
+
+/// What a destructive command would remove or mark, as reported by a dry run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DryRunReport {
+    pub keys: Vec<String>, // Sorted
+    pub count: usize,
+    pub bytes: usize, // Memory held by the keys, freed if they're deleted
+}
+
+/// A parent-child link in an exported dependency graph
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct DependencyEdge {
+    pub key: String,
+    pub parent: String,
+}
+
+/// Outcome of re-applying an exported dependency graph
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct GraphImport {
+    pub applied: usize,
+    pub missing: usize,  // Key or parent not in the cache
+    pub cycles: usize,   // Rejected because the link would create a cycle
+    pub too_deep: usize, // Rejected for going past `Config::max_dependency_depth`
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct SetOptions {
     pub ttl: Option<Duration>,
-    pub parent: Option<String>,
-    pub nx: bool, // not exists: flag for 'set', to set only if key is new
-    pub xx: bool, // exists: flag for 'set', to update only if key pre-exists
+    pub parents: Vec<String>,
+    pub parent_ttl: Option<Duration>, // Links to `parents` lapse after this; the key stays
+    pub nx: bool,                     // not exists: flag for 'set', to set only if key is new
+    pub xx: bool,                     // exists: flag for 'set', to update only if key pre-exists
+    pub pinned: bool, // Exempt the key from eviction, within `Config::max_pinned_memory`
+    pub priority: Priority, // Lower classes are evicted, and cleaned up, first
+    pub jitter: Option<u32>, // Overrides the TTL policy's jitter percent for this write
+    pub tags: Vec<String>,
 }
 
 impl Cache {
     pub fn new(config: Config) -> Self {
+        let stats = Stats {
+            labels: Labels::new(&config.metric_labels),
+            ..Default::default()
+        };
+
+        let data = ShardedMap::new(config.shard_amount);
+        let shards = data.shard_count();
+
+        let contention = config
+            .track_contention
+            .then(|| Box::new(ContentionTracker::new(shards)));
+        let tombstones = config.tombstone_retention.map(Tombstones::new);
         let cache = Self {
-            data: DashMap::new(),
-            config,
-            stats: Arc::new(Stats::default()),
-            cleanup_shard_index: AtomicUsize::new(0),
+            data,
+            keys_by_id: DashMap::new(),
+            indexes: Box::default(),
+            config: Arc::new(config),
+            stats: Arc::new(stats),
+            sampler: Box::new(SampleScheduler::new(shards)),
             dependency_lock: RwLock::new(()),
+            events: EventBus::default(),
+            expiry_index: ExpiryIndex::new(),
+            contention,
+            tombstones,
+            started_at: Instant::now(),
         };
 
-        let base_memory =
-            std::mem::size_of::<Cache>() + std::mem::size_of::<DashMap<String, Entry>>();
+        let base_memory = std::mem::size_of::<Cache>() + std::mem::size_of::<ShardedMap<Entry>>();
         cache
             .stats
             .memory_usage
@@ -323,29 +1010,93 @@ impl Cache {
         cache
     }
 
-    /// Checks key liveness on access
+    /// Reads a value, counting a hit or miss. With `Config::stale_grace`, values
+    /// that expired within the grace period are still returned (see `is_stale`).
     pub fn get(&self, key: &str) -> Option<Value> {
-        match self.data.get_mut(key) {
-            Some(mut entry) => {
-                if !entry.is_valid(&self.data) {
-                    self.stats.misses.fetch_add(1, Ordering::Relaxed);
-                    drop(entry);
-                    self.data.remove(key);
-                    return None;
-                }
+        self.track_shard(key, true);
+        if self.is_live_within(key, self.stale_grace())
+            && let Some(mut entry) = self.data.get_mut(key)
+        {
+            // Touching an expired entry would revive a sliding TTL
+            if !entry.ttl.as_ref().is_some_and(Ttl::is_expired) {
+                entry.mark_accessed(&self.config.lfu);
+            }
+            let value = entry.value.clone();
+            drop(entry);
+            self.stats.hits.fetch_add(1, Ordering::Relaxed);
+            self.with_namespace_stats(key, |ns| ns.hits.fetch_add(1, Ordering::Relaxed));
+            return Some(value);
+        }
 
-                entry.mark_accessed();
-                self.stats.hits.fetch_add(1, Ordering::Relaxed);
-                Some(entry.value.clone())
+        self.stats.misses.fetch_add(1, Ordering::Relaxed);
+        self.with_namespace_stats(key, |ns| ns.misses.fetch_add(1, Ordering::Relaxed));
+        let grace = self.stale_grace();
+        let expired = self.data.get(key).map(|entry| {
+            entry
+                .ttl
+                .as_ref()
+                .is_some_and(|ttl| ttl.is_past_grace(grace))
+        });
+        match expired {
+            Some(true) => {
+                self.remove_expired(&[key.to_string()]);
             }
-            None => {
-                self.stats.misses.fetch_add(1, Ordering::Relaxed);
-                None
+            Some(false) => {
+                self.remove_orphans(&[key.to_string()]);
             }
+            None => {}
+        }
+        self.notify_miss(key);
+        None
+    }
+
+    /// Subscribes to keyspace events, e.g. misses for cache warming
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<CacheEvent> {
+        self.events.subscribe()
+    }
+
+    /// Publishes a `Written` event per key changed by a write command, if anything
+    /// is subscribed
+    pub fn notify_written(&self, keys: Vec<String>) {
+        for key in keys {
+            self.events.publish(CacheEvent::Written { key });
+        }
+    }
+
+    pub fn has_event_subscribers(&self) -> bool {
+        self.events.has_subscribers()
+    }
+
+    /// Reads a live entry's value and size without counting a hit or access
+    pub fn peek(&self, key: &str) -> Option<(Value, usize)> {
+        if !self.is_live(key) {
+            return None;
         }
+        let entry = self.data.get(key)?;
+        Some((entry.value.clone(), key.len() + entry.memory_usage()))
     }
 
+    /// Remaining TTL in seconds; -1 without one and -2 for a missing key
     pub fn ttl(&self, key: &str) -> i64 {
+        self.ttl_with(key, |ttl| ttl.remaining().map(|r| r.as_secs() as i64))
+    }
+
+    /// Like `ttl`, in milliseconds
+    pub fn pttl(&self, key: &str) -> i64 {
+        self.ttl_with(key, |ttl| ttl.remaining().map(|r| r.as_millis() as i64))
+    }
+
+    /// When the key expires, as unix milliseconds; -1 without a TTL and -2 for a
+    /// missing key
+    pub fn expire_time(&self, key: &str) -> i64 {
+        self.ttl_with(key, |ttl| {
+            ttl.remaining()?;
+            let since_epoch = ttl.deadline().duration_since(UNIX_EPOCH).ok()?;
+            Some(since_epoch.as_millis() as i64)
+        })
+    }
+
+    fn ttl_with(&self, key: &str, f: impl FnOnce(&Ttl) -> Option<i64>) -> i64 {
         let Some(entry) = self.data.get(key) else {
             return -2;
         };
@@ -354,14 +1105,36 @@ impl Cache {
             return -1;
         };
 
-        ttl.remaining().map(|r| r.as_secs() as i64).unwrap_or(-2)
+        f(ttl).unwrap_or(-2)
+    }
+
+    /// Time until the key expires, if it has a TTL and hasn't yet
+    pub fn ttl_remaining(&self, key: &str) -> Option<Duration> {
+        self.data.get(key)?.ttl.as_ref()?.remaining()
+    }
+
+    /// The TTL the key was last given, as opposed to what's left of it
+    pub fn ttl_duration(&self, key: &str) -> Option<Duration> {
+        Some(self.data.get(key)?.ttl.as_ref()?.duration)
     }
 
     /// Sets an entry, with synchronous writes for parent refs to avoid cycles
     pub fn set(&self, key: String, value: Value, options: SetOptions) -> Result<bool, CacheError> {
-        // Branch: parent refs require validation under a dependency_lock to avoid inserting cycles
-        let _dependency_guard = if options.parent.is_some() || options.nx || options.xx {
-            Some(self.dependency_lock.write().unwrap())
+        // Branch: parent refs require validation under a dependency_lock to avoid inserting cycles.
+        // Overwriting a linked or tagged entry changes the indexes too; other writes
+        // leave the graph alone and skip the lock.
+        let indexed = || {
+            self.data
+                .get(&key)
+                .is_some_and(|entry| !entry.parents.is_empty() || !entry.tags().is_empty())
+        };
+        let _dependency_guard = if !options.parents.is_empty()
+            || !options.tags.is_empty()
+            || options.nx
+            || options.xx
+            || indexed()
+        {
+            Some(self.dependency_write())
         } else {
             None
         };
@@ -374,616 +1147,5590 @@ impl Cache {
             return Ok(false);
         }
 
-        if let Some(ref parent_key) = options.parent {
-            if !self.config.enable_dependencies {
-                return Err(CacheError::DependenciesDisabled);
-            }
-
-            if !self.data.contains_key(parent_key) {
+        if !options.parents.is_empty() && !self.config.enable_dependencies {
+            return Err(CacheError::DependenciesDisabled);
+        }
+        let link_expires_at = options.parent_ttl.map(|ttl| Instant::now() + ttl);
+        let mut parents: Vec<ParentLink> = Vec::with_capacity(options.parents.len());
+        for parent_key in &options.parents {
+            let Some(parent_id) = self.data.get(parent_key).map(|entry| entry.id) else {
                 return Err(CacheError::ParentNotFound(parent_key.clone()));
-            }
+            };
 
             if self.would_create_cycle(&key, parent_key) {
                 return Err(CacheError::DependencyCycle(key, parent_key.clone()));
             }
+            if parents.iter().all(|link| link.id != parent_id) {
+                parents.push(ParentLink::new(parent_id, link_expires_at));
+            }
+        }
+        if !parents.is_empty() {
+            // Overwrites keep the entry's ID, and with it any dependents
+            let id = self.data.get(&key).map(|entry| entry.id);
+            let ids: Vec<EntryId> = parents.iter().map(|link| link.id).collect();
+            self.check_depth(&key, id, &ids)?;
         }
 
-        let entry = Entry {
+        let mut policy = self.config.ttl_policy_for(&key);
+        policy.jitter = options.jitter.or(policy.jitter);
+        let ttl = policy.apply(options.ttl);
+        let notify = self.config.keyspace_events.set.then(|| key.clone());
+        let mut entry = Entry {
+            id: next_entry_id(),
             value,
-            ttl: options.ttl.map(Ttl::new),
-            parent: options.parent,
+            ttl: ttl.map(Ttl::new),
+            parents,
+            tags: None,
             access_count: 0,
             last_accessed: Instant::now(),
             created_at: Instant::now(),
+            field_expiry: None,
+            stale: false,
+            pinned: options.pinned,
+            priority: options.priority,
         };
+        entry.set_tags(options.tags);
+
+        if let Some(ttl) = &entry.ttl {
+            self.expiry_index.insert(&key, ttl.expires_at);
+        }
 
         debug!("Inserted key {}", key);
         self.insert_entry(key, entry)?;
+        if let Some(key) = notify {
+            self.events.publish(CacheEvent::Set { key });
+        }
         Ok(true)
     }
 
-    pub fn expire(&self, key: &str, seconds: u64) -> i64 {
-        let _guard = self.dependency_lock.write().unwrap();
-
-        match self.data.get_mut(key) {
-            Some(mut entry) => {
-                entry.ttl = Some(Ttl::new(Duration::from_secs(seconds)));
-                1
+    /// Atomically adds `delta` to an integer value, treating a missing key as 0.
+    /// With a limit, the increment is only applied if the result stays within it.
+    pub fn incr_by(
+        &self,
+        key: &str,
+        delta: i64,
+        limit: Option<i64>,
+    ) -> Result<IncrOutcome, CacheError> {
+        self.update_number(key, |current| {
+            let current = match current {
+                Some(value) => value.as_integer().ok_or(CacheError::NotAnInteger)?,
+                None => 0,
+            };
+            let next = current.checked_add(delta).ok_or(CacheError::Overflow)?;
+            if limit.is_some_and(|max| next > max) {
+                return Ok((None, IncrOutcome::Rejected(current)));
             }
-            None => 0,
-        }
+            Ok((Some(Value::Integer(next)), IncrOutcome::Applied(next)))
+        })
     }
 
-    pub fn persist(&self, key: &str) -> i64 {
-        let _guard = self.dependency_lock.write().unwrap();
-
-        match self.data.get_mut(key) {
-            Some(mut entry) => {
-                entry.ttl = None;
-                1
+    /// Atomically adds `delta` to a numeric value, treating a missing key as 0. The
+    /// result is stored as a float.
+    pub fn incr_by_float(&self, key: &str, delta: f64) -> Result<f64, CacheError> {
+        self.update_number(key, |current| {
+            let current = match current {
+                Some(value) => value.as_float().ok_or(CacheError::NotAFloat)?,
+                None => 0.0,
+            };
+            let next = current + delta;
+            if !next.is_finite() {
+                return Err(CacheError::NotFinite);
             }
-            None => 0,
-        }
+            Ok((Some(Value::Float(next)), next))
+        })
     }
 
-    pub fn del(&self, keys: &[&str]) -> usize {
-        let mut deleted_count: usize = 0;
-        let mut total_memory_freed = 0;
+    /// Read-modify-write of one value under its shard lock. `update` gets the live
+    /// value (None if missing) and returns the replacement, or None to leave it be,
+    /// with the caller's result. A new key takes its namespace's default TTL, and a
+    /// value that isn't string-like is a `WrongType` error.
+    fn update_number<T>(
+        &self,
+        key: &str,
+        update: impl FnOnce(Option<&Value>) -> Result<(Option<Value>, T), CacheError>,
+    ) -> Result<T, CacheError> {
+        self.track_shard(key, true);
+        // Ancestors can't be checked under the shard lock, so the entry found there
+        // must be the same one judged, not a replacement, and not expired since
+        let (map_entry, live) = loop {
+            let seen = self.liveness(key);
+            let live = seen.is_some_and(|(_, _, live)| live);
+            if !live {
+                self.check_limits(key, key.len() + size_of::<Entry>())?;
+            }
+            let map_entry = self.data.entry(key.to_string());
+            match &map_entry {
+                MapEntry::Occupied(occupied) => {
+                    let entry = occupied.get();
+                    // Overwrites keep the ID, but not the creation time
+                    if seen.is_none_or(|(id, created_at, _)| {
+                        id != entry.id || created_at != entry.created_at
+                    }) {
+                        continue; // Replaced in between; judge the new entry
+                    }
+                    let expired = entry.ttl.as_ref().is_some_and(Ttl::is_expired);
+                    break (map_entry, live && !expired);
+                }
+                MapEntry::Vacant(_) => break (map_entry, false),
+            }
+        };
+
+        let (old_size, new_size, outcome) = match map_entry {
+            MapEntry::Occupied(mut occupied) if live => {
+                let entry = occupied.get_mut();
+                // Numbers are strings; hashes and the like aren't merely unparseable
+                if entry.value.as_bytes().is_none() {
+                    return Err(CacheError::WrongType);
+                }
+                let (value, outcome) = update(Some(&entry.value))?;
+                let Some(value) = value else {
+                    return Ok(outcome);
+                };
+
+                let old_size = entry.memory_usage();
+                entry.value = value;
+                self.adjust_pinned(entry.pinned, entry.memory_usage(), old_size);
+                (old_size, entry.memory_usage(), outcome)
+            }
+            map_entry => {
+                let (value, outcome) = update(None)?;
+                let Some(value) = value else {
+                    return Ok(outcome);
+                };
+
+                let ttl = self.config.ttl_policy_for(key).apply(None).map(Ttl::new);
+                if let Some(ttl) = &ttl {
+                    self.expiry_index.insert(key, ttl.expires_at);
+                }
+                let mut entry = Entry::new(value);
+                entry.ttl = ttl;
+                let new_size = key.len() + entry.memory_usage();
+
+                let old_size = self.place_entry(key, map_entry, entry);
+                (old_size, new_size, outcome)
+            }
+        };
+
+        self.stats.sets.fetch_add(1, Ordering::Relaxed);
+        self.adjust_memory(key, new_size, old_size);
+        Ok(outcome)
+    }
 
+    /// Bytes `start..=end` of a string-like value together with its full length. Like
+    /// GETRANGE, negative offsets count from the end and out-of-range ones are clamped.
+    /// Counts as a read like GET.
+    pub fn get_range(
+        &self,
+        key: &str,
+        start: i64,
+        end: i64,
+    ) -> Result<Option<(Vec<u8>, usize)>, CacheError> {
+        self.track_shard(key, true);
+        let range = if self.is_live(key)
+            && let Some(mut entry) = self.data.get_mut(key)
         {
-            let _guard = self.dependency_lock.write().unwrap();
+            let bytes = entry.value.as_bytes().ok_or(CacheError::WrongType)?;
+            let len = bytes.len() as i64;
+            let resolve = |index: i64| if index < 0 { len + index } else { index };
+            let (first, last) = (resolve(start).max(0), resolve(end).min(len - 1));
+            let slice = if first <= last {
+                bytes[first as usize..=last as usize].to_vec()
+            } else {
+                Vec::new()
+            };
+            let len = len as usize;
+            entry.mark_accessed(&self.config.lfu);
+            Some((slice, len))
+        } else {
+            None
+        };
 
-            for &key in keys {
-                if let Some((removed_key, entry)) = self.data.remove(key) {
-                    deleted_count += 1;
-                    total_memory_freed += removed_key.capacity() + entry.memory_usage();
-                }
+        match range {
+            Some(_) => {
+                self.stats.hits.fetch_add(1, Ordering::Relaxed);
+                self.with_namespace_stats(key, |ns| ns.hits.fetch_add(1, Ordering::Relaxed));
+            }
+            None => {
+                self.stats.misses.fetch_add(1, Ordering::Relaxed);
+                self.with_namespace_stats(key, |ns| ns.misses.fetch_add(1, Ordering::Relaxed));
             }
         }
+        Ok(range)
+    }
 
-        self.stats
-            .deletes
-            .fetch_add(deleted_count as u64, Ordering::Relaxed);
-        self.stats
-            .memory_usage
-            .fetch_sub(total_memory_freed, Ordering::Relaxed);
-        deleted_count
+    /// Overwrites a string-like value from `offset`, zero-padding any gap, and returns
+    /// the new length. A missing key is created as if it held an empty string.
+    pub fn set_range(&self, key: &str, offset: usize, data: &[u8]) -> Result<usize, CacheError> {
+        self.write_range(key, Some(offset), data)
     }
 
-    pub fn delete(&self, key: &str) -> bool {
-        self.del(&[key]) == 1
+    /// Appends to a string-like value and returns the new length, creating the key
+    /// if it's missing
+    pub fn append(&self, key: &str, data: &[u8]) -> Result<usize, CacheError> {
+        self.write_range(key, None, data)
     }
 
-    pub fn exists(&self, key: &str) -> bool {
+    /// Length in bytes of a string-like value, 0 for a missing key. Not counted as a
+    /// read.
+    pub fn strlen(&self, key: &str) -> Result<usize, CacheError> {
+        if !self.is_live(key) {
+            return Ok(0);
+        }
         match self.data.get(key) {
-            Some(entry) => entry.is_valid(&self.data),
-            None => false,
+            Some(entry) => Ok(entry.value.as_bytes().ok_or(CacheError::WrongType)?.len()),
+            None => Ok(0),
         }
     }
 
-    pub fn exists_multi(&self, keys: &[&str]) -> usize {
-        keys.iter()
-            .map(|key| if self.exists(key) { 1 } else { 0 })
-            .sum()
+    /// Writes `data` at `offset`, or at the end of the value when it's None
+    fn write_range(
+        &self,
+        key: &str,
+        offset: Option<usize>,
+        data: &[u8],
+    ) -> Result<usize, CacheError> {
+        self.track_shard(key, true);
+        let live = self.is_live(key);
+        let current_len = match self.data.get(key) {
+            Some(entry) if live => entry.value.as_bytes().map_or(0, |bytes| bytes.len()),
+            _ => 0,
+        };
+        let end = offset
+            .unwrap_or(current_len)
+            .checked_add(data.len())
+            .filter(|&end| end <= MAX_STRING_LEN)
+            .ok_or(CacheError::ValueTooLarge)?;
+        let growth = end.saturating_sub(current_len);
+        if live {
+            self.check_limits(key, growth)?;
+        } else {
+            self.check_limits(key, key.len() + size_of::<Entry>() + growth)?;
+        }
+
+        let (old_size, new_size, len) = match self.data.entry(key.to_string()) {
+            MapEntry::Occupied(mut occupied) if live => {
+                let entry = occupied.get_mut();
+                let mut bytes = entry
+                    .value
+                    .as_bytes()
+                    .ok_or(CacheError::WrongType)?
+                    .into_owned();
+                if data.is_empty() {
+                    return Ok(bytes.len());
+                }
+                // Another append may have landed since the length was read
+                let offset = offset.unwrap_or(bytes.len());
+                let end = offset + data.len();
+                if end > MAX_STRING_LEN {
+                    return Err(CacheError::ValueTooLarge);
+                }
+                if bytes.len() < end {
+                    bytes.resize(end, 0);
+                }
+                bytes[offset..end].copy_from_slice(data);
+
+                let old_size = entry.memory_usage();
+                let len = bytes.len();
+                entry.value = Value::from_bytes(bytes);
+                self.adjust_pinned(entry.pinned, entry.memory_usage(), old_size);
+                (old_size, entry.memory_usage(), len)
+            }
+            map_entry => {
+                if data.is_empty() {
+                    return Ok(0);
+                }
+                let mut bytes = vec![0; offset.unwrap_or(0)];
+                bytes.extend_from_slice(data);
+                let end = bytes.len();
+
+                let ttl = self.config.ttl_policy_for(key).apply(None).map(Ttl::new);
+                let mut entry = Entry::new(Value::from_bytes(bytes));
+                entry.ttl = ttl;
+                if let Some(ttl) = &entry.ttl {
+                    self.expiry_index.insert(key, ttl.expires_at);
+                }
+                let new_size = key.len() + entry.memory_usage();
+
+                let old_size = self.place_entry(key, map_entry, entry);
+                (old_size, new_size, end)
+            }
+        };
+
+        self.stats.sets.fetch_add(1, Ordering::Relaxed);
+        self.adjust_memory(key, new_size, old_size);
+        Ok(len)
     }
 
-    // Slow, avoid
-    pub fn keys(&self, pattern: &str, limit: usize) -> Vec<String> {
-        self.data
+    /// Sets a TTL on hash fields. Per field: 1 if set, 2 if a zero TTL deleted it,
+    /// -2 if the field (or key) doesn't exist.
+    pub fn hexpire(
+        &self,
+        key: &str,
+        ttl: Duration,
+        fields: &[String],
+    ) -> Result<Vec<i64>, CacheError> {
+        let expires_at = Instant::now() + ttl;
+        let replies = self.with_hash(key, fields.len(), |hash, expiry| {
+            fields
+                .iter()
+                .map(|field| {
+                    if !hash.contains_key(field) {
+                        -2
+                    } else if ttl.is_zero() {
+                        hash.remove(field);
+                        expiry.remove(field);
+                        2
+                    } else {
+                        expiry.insert(field.clone(), expires_at);
+                        1
+                    }
+                })
+                .collect()
+        })?;
+
+        if !ttl.is_zero() && replies.contains(&1) {
+            self.expiry_index.insert(key, expires_at);
+        }
+        Ok(replies)
+    }
+
+    /// Remaining TTL of hash fields in seconds: -1 without a TTL, -2 if missing
+    pub fn httl(&self, key: &str, fields: &[String]) -> Result<Vec<i64>, CacheError> {
+        let now = Instant::now();
+        self.with_hash(key, fields.len(), |hash, expiry| {
+            fields
+                .iter()
+                .map(|field| match expiry.get(field) {
+                    _ if !hash.contains_key(field) => -2,
+                    Some(at) => at.saturating_duration_since(now).as_secs() as i64,
+                    None => -1,
+                })
+                .collect()
+        })
+    }
+
+    /// Removes hash field TTLs: 1 if removed, -1 without a TTL, -2 if missing
+    pub fn hpersist(&self, key: &str, fields: &[String]) -> Result<Vec<i64>, CacheError> {
+        self.with_hash(key, fields.len(), |hash, expiry| {
+            fields
+                .iter()
+                .map(|field| match expiry.remove(field) {
+                    _ if !hash.contains_key(field) => -2,
+                    Some(_) => 1,
+                    None => -1,
+                })
+                .collect()
+        })
+    }
+
+    /// Sets hash fields, creating the key if needed, and returns how many fields are
+    /// new. Overwriting a field clears its TTL, as in Redis.
+    pub fn hset(&self, key: &str, fields: Vec<(String, Value)>) -> Result<usize, CacheError> {
+        let growth = fields
             .iter()
-            .filter(|item| matches_pattern(item.key(), pattern))
-            .take(limit)
-            .map(|item| item.key().clone())
-            .collect()
+            .map(|(field, value)| field.len() + value.memory_usage())
+            .sum();
+        self.update_hash(key, growth, |hash, expiry| {
+            let mut added = 0;
+            for (field, value) in fields {
+                expiry.remove(&field);
+                if hash.insert(field, value).is_none() {
+                    added += 1;
+                }
+            }
+            Ok(added)
+        })
     }
 
-    pub fn parent(&self, key: &str) -> Option<String> {
-        self.data.get(key).and_then(|entry| entry.parent.clone())
+    /// Value of one hash field, or None if the key or field is missing. Counts as a
+    /// read like GET.
+    pub fn hget(&self, key: &str, field: &str) -> Result<Option<Value>, CacheError> {
+        self.read_hash(key, |hash, expiry| {
+            let now = Instant::now();
+            let live = expiry.is_none_or(|e| e.get(field).is_none_or(|at| *at > now));
+            Ok(hash.get(field).filter(|_| live).cloned())
+        })
+        .map(Option::flatten)
     }
 
-    pub fn set_parent(&self, key: &str, parent: String) -> Result<i64, CacheError> {
-        let _guard = self.dependency_lock.write().unwrap();
+    /// Every live field of a hash, sorted by field; empty for a missing key
+    pub fn hgetall(&self, key: &str) -> Result<Vec<(String, Value)>, CacheError> {
+        let fields = self.read_hash(key, |hash, expiry| {
+            let now = Instant::now();
+            let mut fields: Vec<(String, Value)> = hash
+                .iter()
+                .filter(|(field, _)| {
+                    expiry.is_none_or(|e| e.get(*field).is_none_or(|at| *at > now))
+                })
+                .map(|(field, value)| (field.clone(), value.clone()))
+                .collect();
+            fields.sort_by(|a, b| a.0.cmp(&b.0));
+            Ok(fields)
+        })?;
+        Ok(fields.unwrap_or_default())
+    }
 
-        if !self.data.contains_key(&parent) {
-            return Err(CacheError::ParentNotFound(parent.clone()));
-        }
+    /// Removes hash fields and returns how many existed, deleting the key once empty
+    pub fn hdel(&self, key: &str, fields: &[String]) -> Result<usize, CacheError> {
+        let replies = self.with_hash(key, fields.len(), |hash, expiry| {
+            fields
+                .iter()
+                .map(|field| {
+                    expiry.remove(field);
+                    hash.remove(field).is_some() as i64
+                })
+                .collect()
+        })?;
+        Ok(replies.iter().filter(|&&reply| reply == 1).count())
+    }
 
-        if self.would_create_cycle(key, &parent) {
-            return Err(CacheError::DependencyCycle(key.to_string(), parent.clone()));
-        }
+    /// Atomically adds `delta` to an integer hash field, treating a missing key or
+    /// field as 0
+    pub fn hincr_by(&self, key: &str, field: &str, delta: i64) -> Result<i64, CacheError> {
+        self.update_hash(key, field.len() + size_of::<Value>(), |hash, _| {
+            let current = match hash.get(field) {
+                Some(value) => value.as_integer().ok_or(CacheError::NotAnInteger)?,
+                None => 0,
+            };
+            let next = current.checked_add(delta).ok_or(CacheError::Overflow)?;
+            hash.insert(field.to_string(), Value::Integer(next));
+            Ok(next)
+        })
+    }
 
-        match self.data.get_mut(key) {
-            Some(mut entry) => {
-                entry.parent = Some(parent);
-                Ok(1)
+    /// Runs `f` over a live hash (None for a missing key) without modifying it, and
+    /// records a hit or miss
+    fn read_hash<T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(
+            &HashMap<String, Value>,
+            Option<&HashMap<String, Instant>>,
+        ) -> Result<T, CacheError>,
+    ) -> Result<Option<T>, CacheError> {
+        self.track_shard(key, true);
+        let result = if self.is_live(key)
+            && let Some(mut entry) = self.data.get_mut(key)
+        {
+            let Value::Hash(hash) = &entry.value else {
+                return Err(CacheError::WrongType);
+            };
+            let result = f(hash, entry.field_expiry.as_deref())?;
+            entry.mark_accessed(&self.config.lfu);
+            Some(result)
+        } else {
+            None
+        };
+
+        match result {
+            Some(_) => {
+                self.stats.hits.fetch_add(1, Ordering::Relaxed);
+                self.with_namespace_stats(key, |ns| ns.hits.fetch_add(1, Ordering::Relaxed));
+            }
+            None => {
+                self.stats.misses.fetch_add(1, Ordering::Relaxed);
+                self.with_namespace_stats(key, |ns| ns.misses.fetch_add(1, Ordering::Relaxed));
             }
-            None => Ok(0),
         }
+        Ok(result)
     }
 
-    pub fn children_recursive(&self, parent_key: &str, max_depth: usize) -> Vec<(String, u64)> {
-        let mut result = Vec::new();
-        let mut current_parents: HashSet<String> = [parent_key.to_string()].into();
+    /// Read-modify-write of a hash under its shard lock, creating an empty hash for a
+    /// missing key. `growth` is the most the write can add, checked against the
+    /// memory limit up front. A new key takes its namespace's default TTL.
+    fn update_hash<T>(
+        &self,
+        key: &str,
+        growth: usize,
+        f: impl FnOnce(
+            &mut HashMap<String, Value>,
+            &mut HashMap<String, Instant>,
+        ) -> Result<T, CacheError>,
+    ) -> Result<T, CacheError> {
+        self.track_shard(key, true);
+        let live = self.is_live(key);
+        if live {
+            self.check_limits(key, growth)?;
+        } else {
+            self.check_limits(key, key.len() + size_of::<Entry>() + growth)?;
+        }
 
-        for depth in 1..=max_depth {
-            if current_parents.is_empty() {
-                break;
+        let (old_size, new_size, outcome) = match self.data.entry(key.to_string()) {
+            MapEntry::Occupied(mut occupied) if live => {
+                let entry = occupied.get_mut();
+                let old_size = entry.memory_usage();
+                let Value::Hash(hash) = &mut entry.value else {
+                    return Err(CacheError::WrongType);
+                };
+                let mut expiry = entry.field_expiry.take().map(|e| *e).unwrap_or_default();
+                drop_expired_fields(hash, &mut expiry, Instant::now());
+                let outcome = f(hash, &mut expiry);
+                entry.field_expiry = (!expiry.is_empty()).then(|| Box::new(expiry));
+
+                self.adjust_pinned(entry.pinned, entry.memory_usage(), old_size);
+                (old_size, entry.memory_usage(), outcome)
             }
+            map_entry => {
+                let mut hash = HashMap::new();
+                let outcome = f(&mut hash, &mut HashMap::new())?;
 
-            let mut next_parents = HashSet::new();
-
-            for entry in self.data.iter() {
-                if let Some(parent) = &entry.parent {
-                    if current_parents.contains(parent) {
-                        let child = entry.key().clone();
-                        result.push((child.clone(), depth as u64));
-                        next_parents.insert(child);
-                    }
+                let ttl = self.config.ttl_policy_for(key).apply(None).map(Ttl::new);
+                if let Some(ttl) = &ttl {
+                    self.expiry_index.insert(key, ttl.expires_at);
                 }
+                let mut entry = Entry::new(Value::Hash(hash));
+                entry.ttl = ttl;
+                let new_size = key.len() + entry.memory_usage();
+
+                let old_size = self.place_entry(key, map_entry, entry);
+                (old_size, new_size, Ok(outcome))
             }
+        };
 
-            current_parents = next_parents;
+        // Expired fields may have been dropped even if `f` failed
+        self.adjust_memory(key, new_size, old_size);
+        if outcome.is_ok() {
+            self.stats.sets.fetch_add(1, Ordering::Relaxed);
         }
-        result
+        outcome
     }
 
-    pub fn flush_all(&self) {
-        self.data.clear();
-        self.stats.memory_usage.store(0, Ordering::Relaxed);
+    /// Adds members to a set, creating the key if needed, and returns how many are new
+    pub fn sadd(&self, key: &str, members: Vec<String>) -> Result<usize, CacheError> {
+        let growth = members.iter().map(String::len).sum();
+        self.update_set(key, growth, |set| {
+            members
+                .into_iter()
+                .map(|member| set.insert(member))
+                .filter(|&added| added)
+                .count()
+        })
     }
 
-    pub fn stats(&self) -> &Stats {
-        &self.stats
+    /// Removes members from a set and returns how many existed, deleting the key once
+    /// empty
+    pub fn srem(&self, key: &str, members: &[String]) -> Result<usize, CacheError> {
+        if !self.is_live(key) {
+            return Ok(0);
+        }
+        self.update_set(key, 0, |set| {
+            members.iter().filter(|member| set.remove(*member)).count()
+        })
     }
 
-    pub fn len(&self) -> usize {
-        self.data.len()
+    /// Every member of a set, sorted; empty for a missing key
+    pub fn smembers(&self, key: &str) -> Result<Vec<String>, CacheError> {
+        let mut members = self
+            .read_set(key, |set| set.iter().cloned().collect::<Vec<_>>())?
+            .unwrap_or_default();
+        members.sort();
+        Ok(members)
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.data.is_empty()
+    pub fn sismember(&self, key: &str, member: &str) -> Result<bool, CacheError> {
+        Ok(self.read_set(key, |set| set.contains(member))? == Some(true))
     }
 
-    pub fn memory_usage(&self) -> usize {
-        self.stats.memory_usage.load(Ordering::Relaxed)
+    /// Members found in every one of `keys`, sorted. A missing key counts as an empty
+    /// set, so the result is empty too.
+    pub fn sinter(&self, keys: &[String]) -> Result<Vec<String>, CacheError> {
+        let mut common: Option<HashSet<String>> = None;
+        for key in keys {
+            let members = self.read_set(key, |set| match &common {
+                Some(common) => common
+                    .iter()
+                    .filter(|m| set.contains(*m))
+                    .cloned()
+                    .collect(),
+                None => set.clone(),
+            })?;
+            common = Some(members.unwrap_or_default());
+        }
+        let mut members: Vec<String> = common.unwrap_or_default().into_iter().collect();
+        members.sort();
+        Ok(members)
     }
 
-    /// Probabilistic cleanup: iterates over underlying shards in the DashMap,
-    /// taking random samples in a round robin.
-    pub fn cleanup_expired(&self) -> usize {
-        const NUM_SAMPLES: usize = 20;
-
-        let shards = self.data.shards();
-        if shards.is_empty() {
-            return 0;
+    /// Members found in any of `keys`, sorted
+    pub fn sunion(&self, keys: &[String]) -> Result<Vec<String>, CacheError> {
+        let mut union = HashSet::new();
+        for key in keys {
+            self.read_set(key, |set| union.extend(set.iter().cloned()))?;
         }
+        let mut members: Vec<String> = union.into_iter().collect();
+        members.sort();
+        Ok(members)
+    }
 
-        let shard_counter = self.cleanup_shard_index.fetch_add(1, Ordering::Relaxed);
-        let shard_index = shard_counter % shards.len();
-        let shard = &shards[shard_index];
-
-        let keys_to_delete: Vec<_> = unsafe {
-            let shard_guard = shard.read();
-            let shard_size = shard_guard.len();
-
-            if shard_size == 0 {
-                return 0;
-            }
-
-            let (skip, take) = if shard_size < NUM_SAMPLES {
-                (0, shard_size)
-            } else {
-                let offset = shard_counter * 7 % (shard_size - NUM_SAMPLES + 1);
-                (offset, NUM_SAMPLES)
+    /// Runs `f` over a live set (None for a missing key) and records a hit or miss
+    fn read_set<T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&HashSet<String>) -> T,
+    ) -> Result<Option<T>, CacheError> {
+        self.track_shard(key, true);
+        let result = if self.is_live(key)
+            && let Some(mut entry) = self.data.get_mut(key)
+        {
+            let Value::Set(set) = &entry.value else {
+                return Err(CacheError::WrongType);
             };
+            let result = f(set);
+            entry.mark_accessed(&self.config.lfu);
+            Some(result)
+        } else {
+            None
+        };
 
-            shard_guard
-                .iter()
-                .skip(skip)
-                .take(take)
-                .filter_map(|bucket| {
-                    let (key, value) = bucket.as_ref();
-                    if value.get().ttl.as_ref()?.is_expired() {
-                        Some(key.clone())
-                    } else {
-                        None
-                    }
-                })
-                .collect()
-        }; // lock released
-
-        self.del(
-            &keys_to_delete
-                .iter()
-                .map(String::as_str)
-                .collect::<Vec<_>>(),
-        )
+        match result {
+            Some(_) => {
+                self.stats.hits.fetch_add(1, Ordering::Relaxed);
+                self.with_namespace_stats(key, |ns| ns.hits.fetch_add(1, Ordering::Relaxed));
+            }
+            None => {
+                self.stats.misses.fetch_add(1, Ordering::Relaxed);
+                self.with_namespace_stats(key, |ns| ns.misses.fetch_add(1, Ordering::Relaxed));
+            }
+        }
+        Ok(result)
     }
 
-    /// Used to check for cycles before adding a parent dependency
-    /// Access under the dependency_lock, or you might allow cycles
-    fn would_create_cycle(&self, key: &str, parent: &str) -> bool {
-        if key == parent {
-            return true;
+    /// Writes `value` at `path` in the JSON document at `key`, as JSON.SET does. A
+    /// missing key can only be created at the root. Ok(false) when NX or XX rules the
+    /// write out.
+    pub fn json_set(
+        &self,
+        key: &str,
+        path: &str,
+        value: serde_json::Value,
+        nx: bool,
+        xx: bool,
+    ) -> Result<bool, CacheError> {
+        let path = JsonPath::parse(path).map_err(|e| CacheError::JsonPath(e.to_string()))?;
+        self.track_shard(key, true);
+        let live = self.is_live(key);
+        let growth = json_path::memory_usage(&value);
+        if live {
+            self.check_limits(key, growth)?;
+        } else {
+            self.check_limits(key, key.len() + size_of::<Entry>() + growth)?;
         }
 
-        let mut visited = HashSet::new();
-        let mut current_option = Some(parent.to_string());
+        let (old_size, new_size) = match self.data.entry(key.to_string()) {
+            MapEntry::Occupied(mut occupied) if live => {
+                let entry = occupied.get_mut();
+                let old_size = entry.memory_usage();
+                let Value::Json(doc) = &mut entry.value else {
+                    return Err(CacheError::WrongType);
+                };
+                let exists = path.get(doc).is_some();
+                if (nx && exists) || (xx && !exists) {
+                    return Ok(false);
+                }
+                path.set(doc, value)
+                    .map_err(|e| CacheError::JsonPath(e.to_string()))?;
 
-        while let Some(current_key) = current_option {
-            if current_key == key {
-                return true;
+                self.adjust_pinned(entry.pinned, entry.memory_usage(), old_size);
+                (old_size, entry.memory_usage())
             }
+            map_entry => {
+                if xx {
+                    return Ok(false);
+                }
+                if !path.is_root() {
+                    return Err(CacheError::JsonPath(
+                        "new documents must be created at the root".to_string(),
+                    ));
+                }
 
-            if !visited.insert(current_key.clone()) {
-                return true;
-            }
+                let ttl = self.config.ttl_policy_for(key).apply(None).map(Ttl::new);
+                if let Some(ttl) = &ttl {
+                    self.expiry_index.insert(key, ttl.expires_at);
+                }
+                let mut entry = Entry::new(Value::Json(value));
+                entry.ttl = ttl;
+                let new_size = key.len() + entry.memory_usage();
 
-            current_option = self.data.get(&current_key).and_then(|e| e.parent.clone());
-        }
+                let old_size = self.place_entry(key, map_entry, entry);
+                (old_size, new_size)
+            }
+        };
 
-        false
+        self.adjust_memory(key, new_size, old_size);
+        self.stats.sets.fetch_add(1, Ordering::Relaxed);
+        Ok(true)
     }
 
-    fn insert_entry(&self, key: String, entry: Entry) -> Result<(), CacheError> {
-        let memory_delta = key.capacity() + entry.memory_usage();
+    /// The value at `path` in the JSON document at `key`; None if either is missing
+    pub fn json_get(&self, key: &str, path: &str) -> Result<Option<serde_json::Value>, CacheError> {
+        let path = JsonPath::parse(path).map_err(|e| CacheError::JsonPath(e.to_string()))?;
+        self.track_shard(key, true);
+        let result = if self.is_live(key)
+            && let Some(mut entry) = self.data.get_mut(key)
+        {
+            let Value::Json(doc) = &entry.value else {
+                return Err(CacheError::WrongType);
+            };
+            let found = path.get(doc).cloned();
+            entry.mark_accessed(&self.config.lfu);
+            Some(found)
+        } else {
+            None
+        };
 
-        if let Some(max_memory) = self.config.max_memory {
-            let current_memory = self.memory_usage();
-            if current_memory + memory_delta > max_memory {
-                return Err(CacheError::MemoryLimitExceeded);
+        match result {
+            Some(_) => {
+                self.stats.hits.fetch_add(1, Ordering::Relaxed);
+                self.with_namespace_stats(key, |ns| ns.hits.fetch_add(1, Ordering::Relaxed));
+            }
+            None => {
+                self.stats.misses.fetch_add(1, Ordering::Relaxed);
+                self.with_namespace_stats(key, |ns| ns.misses.fetch_add(1, Ordering::Relaxed));
             }
         }
+        Ok(result.flatten())
+    }
 
-        if let Some(max_keys) = self.config.max_keys {
-            if self.data.len() >= max_keys && !self.data.contains_key(&key) {
-                return Err(CacheError::KeyLimitExceeded);
-            }
+    /// Removes the value at `path` in the JSON document at `key`, or the whole key
+    /// for the root, returning how many values went
+    pub fn json_del(&self, key: &str, path: &str) -> Result<usize, CacheError> {
+        let path = JsonPath::parse(path).map_err(|e| CacheError::JsonPath(e.to_string()))?;
+        self.track_shard(key, true);
+        if !self.is_live(key) {
+            return Ok(0);
+        }
+        if path.is_root() {
+            // Read first: the guard must be gone before `del` locks the shard
+            let is_json = self
+                .data
+                .get(key)
+                .map(|entry| matches!(entry.value, Value::Json(_)));
+            return match is_json {
+                Some(true) => Ok(self.del(&[key])),
+                Some(false) => Err(CacheError::WrongType),
+                None => Ok(0),
+            };
+        }
+
+        let Some(mut entry) = self.data.get_mut(key) else {
+            return Ok(0);
+        };
+        let old_size = entry.memory_usage();
+        let Value::Json(doc) = &mut entry.value else {
+            return Err(CacheError::WrongType);
+        };
+        if !path.delete(doc) {
+            return Ok(0);
         }
+        let new_size = entry.memory_usage();
+        self.adjust_pinned(entry.pinned, new_size, old_size);
+        drop(entry);
 
-        self.data.insert(key, entry);
+        self.adjust_memory(key, new_size, old_size);
         self.stats.sets.fetch_add(1, Ordering::Relaxed);
-        self.stats
-            .memory_usage
-            .fetch_add(memory_delta, Ordering::Relaxed);
+        Ok(1)
+    }
 
-        Ok(())
+    /// Adds members to a HyperLogLog, creating it if missing. True if the key was
+    /// created or its estimate may have changed, as PFADD reports.
+    pub fn pfadd(&self, key: &str, members: &[String]) -> Result<bool, CacheError> {
+        self.update_hll(key, |hll, created| {
+            members
+                .iter()
+                .fold(created, |changed, member| hll.add(member) || changed)
+        })
     }
-}
 
-fn matches_pattern(key: &str, pattern: &str) -> bool {
-    if pattern == "*" {
-        return true;
+    /// Estimated members in the union of the HyperLogLogs at `keys`; missing keys
+    /// count as empty
+    pub fn pfcount(&self, keys: &[String]) -> Result<u64, CacheError> {
+        let mut union: Option<HyperLogLog> = None;
+        for key in keys {
+            let Some(hll) = self.read_hll(key)? else {
+                continue;
+            };
+            match &mut union {
+                Some(union) => union.merge(&hll),
+                None => union = Some(hll),
+            }
+        }
+        Ok(union.map_or(0, |hll| hll.count()))
     }
 
-    if let Some(prefix) = pattern.strip_suffix('*') {
-        key.starts_with(prefix)
-    } else {
-        key == pattern
+    /// Merges the HyperLogLogs at `sources` into `dest`, creating it if missing
+    pub fn pfmerge(&self, dest: &str, sources: &[String]) -> Result<(), CacheError> {
+        // Read before taking the destination's shard lock, which a source may share
+        let mut hlls = Vec::new();
+        for source in sources {
+            hlls.extend(self.read_hll(source)?);
+        }
+        self.update_hll(dest, |hll, _| {
+            hlls.iter().for_each(|other| hll.merge(other))
+        })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::cache_errors::CacheError;
+    /// Copy of a live HyperLogLog (None for a missing key), recording a hit or miss
+    fn read_hll(&self, key: &str) -> Result<Option<HyperLogLog>, CacheError> {
+        self.track_shard(key, true);
+        let result = if self.is_live(key)
+            && let Some(mut entry) = self.data.get_mut(key)
+        {
+            let Value::HyperLogLog(hll) = &entry.value else {
+                return Err(CacheError::WrongType);
+            };
+            let hll = hll.clone();
+            entry.mark_accessed(&self.config.lfu);
+            Some(hll)
+        } else {
+            None
+        };
 
-    use super::*;
-    use std::time::Duration;
+        match result {
+            Some(_) => {
+                self.stats.hits.fetch_add(1, Ordering::Relaxed);
+                self.with_namespace_stats(key, |ns| ns.hits.fetch_add(1, Ordering::Relaxed));
+            }
+            None => {
+                self.stats.misses.fetch_add(1, Ordering::Relaxed);
+                self.with_namespace_stats(key, |ns| ns.misses.fetch_add(1, Ordering::Relaxed));
+            }
+        }
+        Ok(result)
+    }
 
-    #[test]
-    fn test_basic_operations() {
-        let cache = Cache::new(Config::default());
+    /// Read-modify-write of a HyperLogLog under its shard lock, like `update_set`. A
+    /// missing key is always created; `f` is told whether it was.
+    fn update_hll<T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut HyperLogLog, bool) -> T,
+    ) -> Result<T, CacheError> {
+        self.track_shard(key, true);
+        let live = self.is_live(key);
+        if !live {
+            let size = HyperLogLog::new().memory_usage();
+            self.check_limits(key, key.len() + size_of::<Entry>() + size)?;
+        }
 
-        // Test set and get
-        cache
-            .set(
-                "key1".to_string(),
-                Value::String("value1".to_string()),
-                SetOptions::default(),
-            )
+        let (old_size, new_size, outcome) = match self.data.entry(key.to_string()) {
+            MapEntry::Occupied(mut occupied) if live => {
+                let entry = occupied.get_mut();
+                // Registers are fixed, so updates don't change the footprint
+                let size = entry.memory_usage();
+                let Value::HyperLogLog(hll) = &mut entry.value else {
+                    return Err(CacheError::WrongType);
+                };
+                (size, size, f(hll, false))
+            }
+            map_entry => {
+                let mut hll = HyperLogLog::new();
+                let outcome = f(&mut hll, true);
+
+                let ttl = self.config.ttl_policy_for(key).apply(None).map(Ttl::new);
+                if let Some(ttl) = &ttl {
+                    self.expiry_index.insert(key, ttl.expires_at);
+                }
+                let mut entry = Entry::new(Value::HyperLogLog(hll));
+                entry.ttl = ttl;
+                let new_size = key.len() + entry.memory_usage();
+
+                let old_size = self.place_entry(key, map_entry, entry);
+                (old_size, new_size, outcome)
+            }
+        };
+
+        self.adjust_memory(key, new_size, old_size);
+        self.stats.sets.fetch_add(1, Ordering::Relaxed);
+        Ok(outcome)
+    }
+
+    /// Read-modify-write of a set under its shard lock, like `update_hash`. A missing
+    /// key is created only if `f` leaves members in it, and a set `f` empties is
+    /// deleted.
+    fn update_set<T>(
+        &self,
+        key: &str,
+        growth: usize,
+        f: impl FnOnce(&mut HashSet<String>) -> T,
+    ) -> Result<T, CacheError> {
+        self.track_shard(key, true);
+        let live = self.is_live(key);
+        if live {
+            self.check_limits(key, growth)?;
+        } else {
+            self.check_limits(key, key.len() + size_of::<Entry>() + growth)?;
+        }
+
+        let (old_size, new_size, outcome, emptied) = match self.data.entry(key.to_string()) {
+            MapEntry::Occupied(mut occupied) if live => {
+                let entry = occupied.get_mut();
+                let old_size = entry.memory_usage();
+                let Value::Set(set) = &mut entry.value else {
+                    return Err(CacheError::WrongType);
+                };
+                let outcome = f(set);
+                let emptied = set.is_empty();
+
+                self.adjust_pinned(entry.pinned, entry.memory_usage(), old_size);
+                (old_size, entry.memory_usage(), outcome, emptied)
+            }
+            map_entry => {
+                let mut set = HashSet::new();
+                let outcome = f(&mut set);
+                if set.is_empty() {
+                    return Ok(outcome);
+                }
+
+                let ttl = self.config.ttl_policy_for(key).apply(None).map(Ttl::new);
+                if let Some(ttl) = &ttl {
+                    self.expiry_index.insert(key, ttl.expires_at);
+                }
+                let mut entry = Entry::new(Value::Set(set));
+                entry.ttl = ttl;
+                let new_size = key.len() + entry.memory_usage();
+
+                let old_size = self.place_entry(key, map_entry, entry);
+                (old_size, new_size, outcome, false)
+            }
+        };
+
+        self.adjust_memory(key, new_size, old_size);
+        self.stats.sets.fetch_add(1, Ordering::Relaxed);
+        if emptied {
+            self.del(&[key]);
+        }
+        Ok(outcome)
+    }
+
+    /// Runs `f` over a live hash and its field TTLs after dropping expired fields,
+    /// keeping memory accounting in step and deleting the key if the hash empties.
+    /// A missing key answers -2 for every field.
+    fn with_hash<F>(&self, key: &str, field_count: usize, f: F) -> Result<Vec<i64>, CacheError>
+    where
+        F: FnOnce(&mut HashMap<String, Value>, &mut HashMap<String, Instant>) -> Vec<i64>,
+    {
+        if !self.is_live(key) {
+            return Ok(vec![-2; field_count]);
+        }
+
+        let (replies, emptied) = {
+            let Some(mut guard) = self.data.get_mut(key) else {
+                return Ok(vec![-2; field_count]);
+            };
+            let before = guard.memory_usage();
+            let entry = &mut *guard;
+            let Value::Hash(hash) = &mut entry.value else {
+                return Err(CacheError::WrongType);
+            };
+            let mut expiry = entry.field_expiry.take().map(|e| *e).unwrap_or_default();
+            drop_expired_fields(hash, &mut expiry, Instant::now());
+
+            let replies = f(hash, &mut expiry);
+            let emptied = hash.is_empty();
+            entry.field_expiry = (!expiry.is_empty()).then(|| Box::new(expiry));
+
+            let after = entry.memory_usage();
+            self.adjust_memory(key, after, before);
+            self.adjust_pinned(entry.pinned, after, before);
+            (replies, emptied)
+        };
+
+        if emptied {
+            self.del(&[key]);
+        }
+        Ok(replies)
+    }
+
+    /// Drops expired hash fields of a key, deleting it if nothing is left.
+    /// Returns whether the key was removed.
+    fn purge_expired_fields(&self, key: &str, now: Instant) -> bool {
+        let emptied = {
+            let Some(mut entry) = self.data.get_mut(key) else {
+                return false;
+            };
+            let before = entry.memory_usage();
+            let entry = &mut *entry;
+            let (Value::Hash(hash), Some(expiry)) = (&mut entry.value, &mut entry.field_expiry)
+            else {
+                return false;
+            };
+
+            drop_expired_fields(hash, expiry, now);
+            if let Some(next) = expiry.values().min() {
+                self.expiry_index.insert(key, *next);
+            }
+            if expiry.is_empty() {
+                entry.field_expiry = None;
+            }
+            let emptied = matches!(&entry.value, Value::Hash(hash) if hash.is_empty());
+
+            let after = entry.memory_usage();
+            self.adjust_memory(key, after, before);
+            self.adjust_pinned(entry.pinned, after, before);
+            emptied
+        };
+
+        emptied && self.del(&[key]) == 1
+    }
+
+    /// Appends an entry to a stream, creating it if needed and trimming it to `max_len`
+    pub fn stream_append(
+        &self,
+        key: &str,
+        fields: Vec<(String, String)>,
+        max_len: Option<usize>,
+    ) -> Result<StreamId, CacheError> {
+        self.track_shard(key, true);
+        let live = self.is_live(key);
+        let (old_size, new_size, id) = match self.data.entry(key.to_string()) {
+            MapEntry::Occupied(mut occupied) if live => {
+                let entry = occupied.get_mut();
+                let old_size = entry.memory_usage();
+                let Value::Stream(stream) = &mut entry.value else {
+                    return Err(CacheError::WrongType);
+                };
+                let id = stream.append(fields, max_len);
+                self.adjust_pinned(entry.pinned, entry.memory_usage(), old_size);
+                (old_size, entry.memory_usage(), id)
+            }
+            map_entry => {
+                let mut stream = Stream::default();
+                let id = stream.append(fields, max_len);
+                let entry = Entry::new(Value::Stream(stream));
+                let new_size = key.len() + entry.memory_usage();
+                let old_size = self.place_entry(key, map_entry, entry);
+                (old_size, new_size, id)
+            }
+        };
+
+        self.adjust_memory(key, new_size, old_size);
+        Ok(id)
+    }
+
+    /// Entries after `after`; a missing key reads as an empty stream
+    pub fn stream_read(
+        &self,
+        key: &str,
+        after: StreamId,
+        count: usize,
+    ) -> Result<Vec<StreamEntry>, CacheError> {
+        if !self.is_live(key) {
+            return Ok(Vec::new());
+        }
+        match self.data.get(key).as_deref().map(|entry| &entry.value) {
+            Some(Value::Stream(stream)) => Ok(stream.read_after(after, count)),
+            Some(_) => Err(CacheError::WrongType),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn expire(&self, key: &str, seconds: u64) -> i64 {
+        self.pexpire(key, seconds.saturating_mul(1000))
+    }
+
+    pub fn pexpire(&self, key: &str, millis: u64) -> i64 {
+        self.set_ttl(key, Ttl::new(Duration::from_millis(millis)))
+    }
+
+    /// Expires the key at an absolute wall-clock time; past times expire immediately
+    pub fn expire_at(&self, key: &str, at: SystemTime) -> i64 {
+        self.set_ttl(key, Ttl::until(at))
+    }
+
+    /// Sets a TTL within the key's TTL policy min and max. Jitter is left to writes,
+    /// so a replayed EXPIRE lands on the same deadline.
+    fn set_ttl(&self, key: &str, mut ttl: Ttl) -> i64 {
+        let policy = TtlPolicy {
+            jitter: None,
+            ..self.config.ttl_policy_for(key)
+        };
+        if let Some(left) = ttl.remaining()
+            && let Some(bounded) = policy.apply(Some(left))
+            && bounded != left
+        {
+            ttl = Ttl::new(bounded);
+        }
+        let _guard = self.dependency_write();
+
+        match self.data.get_mut(key) {
+            Some(mut entry) => {
+                self.expiry_index.insert(key, ttl.expires_at);
+                entry.ttl = Some(ttl);
+                self.ancestor_changed(entry.id);
+                1
+            }
+            None => 0,
+        }
+    }
+
+    /// Removes the key's TTL. Under a TTL policy max the key can't be persisted, and
+    /// keeps its TTL; 0 is returned as for a missing key.
+    pub fn persist(&self, key: &str) -> i64 {
+        if self.config.ttl_policy_for(key).max.is_some() {
+            return 0;
+        }
+        let _guard = self.dependency_write();
+
+        match self.data.get_mut(key) {
+            Some(mut entry) => {
+                entry.ttl = None;
+                self.ancestor_changed(entry.id);
+                1
+            }
+            None => 0,
+        }
+    }
+
+    /// Deletes keys, and their dependents with `Config::cascade_deletes`
+    pub fn del(&self, keys: &[&str]) -> usize {
+        if self.config.cascade_deletes {
+            return self.del_cascade(keys);
+        }
+        self.del_keys(keys, self.config.keyspace_events.del, true)
+    }
+
+    /// Deletes keys and every key depending on them, returning the total removed
+    pub fn del_cascade(&self, keys: &[&str]) -> usize {
+        let subtree: Vec<String> = keys
+            .iter()
+            .flat_map(|key| self.invalidation_keys(key))
+            .collect();
+        let subtree: Vec<&str> = subtree.iter().map(String::as_str).collect();
+        self.del_keys(&subtree, self.config.keyspace_events.del, true)
+    }
+
+    /// Deletes keys, publishing `Deleted` for each with `notify`. Expiry and
+    /// invalidation publish their own events instead. In soft-delete mode, keys
+    /// removed with `tombstone` leave one behind; expired keys don't.
+    fn del_keys(&self, keys: &[&str], notify: bool, tombstone: bool) -> usize {
+        let mut deleted_count: usize = 0;
+        let mut total_memory_freed = 0;
+
+        {
+            let _guard = self.dependency_write();
+
+            for &key in keys {
+                if let Some(freed) = self.remove_entry(key) {
+                    deleted_count += 1;
+                    total_memory_freed += freed;
+                    if tombstone && let Some(tombstones) = &self.tombstones {
+                        tombstones.record(key);
+                    }
+                    if notify {
+                        self.events.publish(CacheEvent::Deleted {
+                            key: key.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+
+        self.stats
+            .deletes
+            .fetch_add(deleted_count as u64, Ordering::Relaxed);
+        debug!(
+            "Deleted {} keys, freeing {} bytes",
+            deleted_count, total_memory_freed
+        );
+        deleted_count
+    }
+
+    /// Removes an entry and releases its memory, returning the bytes freed. Callers
+    /// deleting on behalf of clients hold the dependency lock; eviction doesn't.
+    fn remove_entry(&self, key: &str) -> Option<usize> {
+        self.remove_entry_if(key, |_| true)
+    }
+
+    /// `remove_entry`, if the entry still passes `predicate` once its shard is locked
+    fn remove_entry_if(&self, key: &str, predicate: impl FnOnce(&Entry) -> bool) -> Option<usize> {
+        self.track_shard(key, true);
+        let (removed_key, entry) = self.data.remove_if(key, |_, entry| predicate(entry))?;
+        self.keys_by_id.remove(&entry.id);
+        self.relink(entry.id, &entry.parents, &[]);
+        self.retag(entry.id, entry.tags(), &[]);
+        self.ancestor_changed(entry.id);
+        self.indexes.children_by_id.remove(&entry.id);
+        self.indexes.chain_checks.remove(&entry.id);
+        let freed = removed_key.len() + entry.memory_usage();
+        self.adjust_memory(key, 0, freed);
+        self.adjust_pinned(entry.pinned, 0, freed);
+        Some(freed)
+    }
+
+    pub fn delete(&self, key: &str) -> bool {
+        self.del(&[key]) == 1
+    }
+
+    /// Tombstone left by deleting `key`, if soft delete is on, the key hasn't been
+    /// written since and the retention period hasn't passed
+    pub fn tombstone(&self, key: &str) -> Option<Tombstone> {
+        let tombstones = self.tombstones.as_ref()?;
+        if self.is_live(key) {
+            return None;
+        }
+        tombstones.get(key)
+    }
+
+    pub fn tombstone_count(&self) -> usize {
+        self.tombstones.as_ref().map_or(0, Tombstones::len)
+    }
+
+    /// A write creating `key` clears any tombstone from an earlier delete
+    fn revive(&self, key: &str) {
+        if let Some(tombstones) = &self.tombstones {
+            tombstones.clear(key);
+        }
+    }
+
+    /// Moves an entry to `new_key`, replacing whatever was there. The entry keeps its
+    /// ID, so its dependents follow it without being touched; dependents of a replaced
+    /// key lose their parent.
+    pub fn rename(&self, key: &str, new_key: &str) -> Result<(), CacheError> {
+        let _guard = self.dependency_write();
+        if !self.is_live(key) {
+            return Err(CacheError::NoSuchKey);
+        }
+        if key == new_key {
+            return Ok(());
+        }
+
+        self.track_shard(key, true);
+        let Some((old_key, entry)) = self.data.remove(key) else {
+            return Err(CacheError::NoSuchKey);
+        };
+        let size = entry.memory_usage();
+        self.adjust_memory(key, 0, old_key.len() + size);
+        self.adjust_pinned(entry.pinned, 0, old_key.len() + size);
+        self.remove_entry(new_key);
+
+        self.track_shard(new_key, true);
+        self.revive(new_key);
+        if let Some(ttl) = &entry.ttl {
+            self.expiry_index.insert(new_key, ttl.expires_at);
+        }
+        self.keys_by_id.insert(entry.id, new_key.to_string());
+        self.adjust_memory(new_key, new_key.len() + size, 0);
+        self.adjust_pinned(entry.pinned, new_key.len() + size, 0);
+        self.data.insert(new_key.to_string(), entry);
+        Ok(())
+    }
+
+    /// Key currently holding the entry with `id`
+    pub fn key_of(&self, id: EntryId) -> Option<String> {
+        self.keys_by_id.get(&id).map(|key| key.clone())
+    }
+
+    /// Moves `child` from its `old` parents to its `new` ones in the reverse index.
+    /// Links that lapse stay indexed until the child is relinked or removed; readers
+    /// check them.
+    fn relink(&self, child: EntryId, old: &[ParentLink], new: &[ParentLink]) {
+        let same = |a: &[ParentLink], b: &[ParentLink]| {
+            a.iter().all(|link| b.iter().any(|kept| kept.id == link.id))
+        };
+        if !(same(old, new) && same(new, old)) {
+            self.invalidate_chain_checks(vec![child]);
+        }
+        for link in old {
+            if new.iter().any(|kept| kept.id == link.id) {
+                continue;
+            }
+            let emptied =
+                self.indexes
+                    .children_by_id
+                    .get_mut(&link.id)
+                    .is_some_and(|mut children| {
+                        children.remove(&child);
+                        children.is_empty()
+                    });
+            if emptied {
+                self.indexes
+                    .children_by_id
+                    .remove_if(&link.id, |_, children| children.is_empty());
+            }
+        }
+        for link in new {
+            if !old.iter().any(|kept| kept.id == link.id) {
+                self.indexes
+                    .children_by_id
+                    .entry(link.id)
+                    .or_default()
+                    .insert(child);
+            }
+        }
+    }
+
+    /// Marks the cached chain checks of `ids` and everything below them stale, so
+    /// their next reads walk their ancestors. Only the reverse index is read, so
+    /// writers can call this while holding the entry they change.
+    fn invalidate_chain_checks(&self, ids: Vec<EntryId>) {
+        let generation = self.indexes.chain_generation.fetch_add(1, Ordering::AcqRel) + 1;
+        let stale = ChainCheck {
+            generation,
+            expires_at: None,
+            live: false,
+        };
+        let mut visited = HashSet::new();
+        let mut pending = ids;
+        while let Some(id) = pending.pop() {
+            if !visited.insert(id) {
+                continue;
+            }
+            self.indexes.chain_checks.insert(id, stale);
+            if let Some(children) = self.indexes.children_by_id.get(&id) {
+                pending.extend(children.iter().copied());
+            }
+        }
+    }
+
+    /// Called when an entry's TTL or value changes in a way its dependents' cached
+    /// chain checks could have relied on
+    fn ancestor_changed(&self, id: EntryId) {
+        let children: Vec<EntryId> = match self.indexes.children_by_id.get(&id) {
+            Some(children) => children.iter().copied().collect(),
+            None => return,
+        };
+        self.invalidate_chain_checks(children);
+    }
+
+    /// Moves an entry from its `old` tags to its `new` ones in the tag index
+    fn retag(&self, id: EntryId, old: &[String], new: &[String]) {
+        for tag in old.iter().filter(|tag| !new.contains(tag)) {
+            let emptied = self.indexes.ids_by_tag.get_mut(tag).is_some_and(|mut ids| {
+                ids.remove(&id);
+                ids.is_empty()
+            });
+            if emptied {
+                self.indexes
+                    .ids_by_tag
+                    .remove_if(tag, |_, ids| ids.is_empty());
+            }
+        }
+        for tag in new.iter().filter(|tag| !old.contains(tag)) {
+            self.indexes
+                .ids_by_tag
+                .entry(tag.clone())
+                .or_default()
+                .insert(id);
+        }
+    }
+
+    /// Keys carrying `tag`, in no particular order
+    pub fn tagged(&self, tag: &str) -> Vec<String> {
+        let Some(ids) = self.indexes.ids_by_tag.get(tag).map(|ids| ids.clone()) else {
+            return Vec::new();
+        };
+        ids.into_iter()
+            .filter_map(|id| self.key_of(id))
+            .filter(|key| self.is_live(key))
+            .collect()
+    }
+
+    pub fn tags(&self, key: &str) -> Vec<String> {
+        if !self.is_live(key) {
+            return Vec::new();
+        }
+        self.data
+            .get(key)
+            .map(|entry| entry.tags().to_vec())
+            .unwrap_or_default()
+    }
+
+    /// Keys and IDs of the entries currently linked to `parent`, found through the
+    /// reverse index
+    fn children_of(&self, parent: EntryId) -> Vec<(String, EntryId)> {
+        let Some(ids) = self
+            .indexes
+            .children_by_id
+            .get(&parent)
+            .map(|ids| ids.clone())
+        else {
+            return Vec::new();
+        };
+        ids.into_iter()
+            .filter_map(|id| {
+                let key = self.key_of(id)?;
+                let entry = self.data.get(&key)?;
+                (entry.id == id && entry.has_live_parent(parent)).then_some((key, id))
+            })
+            .collect()
+    }
+
+    /// Stores a newly created entry at a missing or dead key, returning the dead
+    /// occupant's size. A dead occupant (expired, or its parent is gone) is replaced
+    /// outright, but its ID carries over so dependents stay attached to the key.
+    fn place_entry(&self, key: &str, map_entry: MapEntry<'_, Entry>, entry: Entry) -> usize {
+        self.revive(key);
+        match map_entry {
+            MapEntry::Occupied(mut occupied) => {
+                let id = occupied.get().id;
+                self.relink(id, &occupied.get().parents, &entry.parents);
+                self.retag(id, occupied.get().tags(), entry.tags());
+                self.ancestor_changed(id);
+                let old = std::mem::replace(occupied.get_mut(), entry);
+                occupied.get_mut().id = id;
+                let old_size = key.len() + old.memory_usage();
+                self.adjust_pinned(old.pinned, 0, old_size);
+                old_size
+            }
+            MapEntry::Vacant(vacant) => {
+                self.keys_by_id.insert(entry.id, key.to_string());
+                self.relink(entry.id, &[], &entry.parents);
+                self.retag(entry.id, &[], entry.tags());
+                vacant.insert(entry);
+                0
+            }
+        }
+    }
+
+    pub fn exists(&self, key: &str) -> bool {
+        self.track_shard(key, false);
+        self.is_live(key)
+    }
+
+    pub fn exists_multi(&self, keys: &[&str]) -> usize {
+        keys.iter()
+            .map(|key| if self.exists(key) { 1 } else { 0 })
+            .sum()
+    }
+
+    /// `keys`, a shard at a time from shard `cursor`, checking `deadline` between
+    /// shards. A walk cut short returns what it found with the shard to resume from.
+    pub fn keys_page(
+        &self,
+        pattern: &str,
+        limit: usize,
+        cursor: usize,
+        deadline: Deadline,
+    ) -> (Vec<String>, Option<usize>) {
+        let mut keys = Vec::new();
+        for shard in cursor..self.shard_count() {
+            if keys.len() >= limit {
+                break;
+            }
+            if shard > cursor && deadline.passed() {
+                return (keys, Some(shard));
+            }
+            keys.extend(self.shard_keys(shard, pattern));
+        }
+        keys.truncate(limit);
+        (keys, None)
+    }
+
+    /// One step of an incremental walk: live keys matching the glob `pattern` from
+    /// whole shards, starting at the shard `cursor` names, until `count` are found.
+    /// Returns the cursor to continue from, 0 once every shard was visited. Keys
+    /// only move to later shards when the cache grows, so each key present throughout
+    /// the walk comes back at least once.
+    pub fn scan(&self, cursor: u64, pattern: Option<&str>, count: usize) -> (u64, Vec<String>) {
+        let shards = self.shard_count() as u64;
+        let mut shard = cursor;
+        let mut keys = Vec::new();
+        while shard < shards && keys.len() < count.max(1) {
+            keys.extend(
+                self.shard_keys(shard as usize, "*")
+                    .into_iter()
+                    .filter(|key| pattern.is_none_or(|pattern| glob_match(pattern, key))),
+            );
+            shard += 1;
+        }
+        // Checked after the shard's lock is released; liveness looks up ancestors
+        keys.retain(|key| self.is_live(key));
+        let next = if shard < shards { shard } else { 0 };
+        (next, keys)
+    }
+
+    // Slow, avoid
+    pub fn keys(&self, pattern: &str, limit: usize) -> Vec<String> {
+        self.data
+            .iter()
+            .filter(|item| matches_pattern(item.key(), pattern))
+            .take(limit)
+            .map(|item| item.key().clone())
+            .collect()
+    }
+
+    /// The key's first live parent
+    pub fn parent(&self, key: &str) -> Option<String> {
+        self.parents(key).into_iter().next()
+    }
+
+    /// Every live parent of the key, in the order they were linked
+    pub fn parents(&self, key: &str) -> Vec<String> {
+        let Some(entry) = self.data.get(key) else {
+            return Vec::new();
+        };
+        let ids: Vec<EntryId> = entry.live_parents().collect();
+        drop(entry);
+        ids.into_iter().filter_map(|id| self.key_of(id)).collect()
+    }
+
+    /// Time left before the key's link to its first parent lapses; None without a
+    /// parent or when the link doesn't expire
+    pub fn parent_ttl(&self, key: &str) -> Option<Duration> {
+        let entry = self.data.get(key)?;
+        let link = entry.parents.iter().find(|link| link.is_live())?;
+        let expires_at = link.expires_at?;
+        Some(expires_at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Makes `parent` the key's only parent
+    pub fn set_parent(&self, key: &str, parent: String) -> Result<i64, CacheError> {
+        self.link_parent(key, parent, None)
+    }
+
+    /// Like `set_parent`, with the link lapsing at `expires_at`
+    pub fn link_parent(
+        &self,
+        key: &str,
+        parent: String,
+        expires_at: Option<Instant>,
+    ) -> Result<i64, CacheError> {
+        self.update_parents(
+            key,
+            parent,
+            |parents, link| *parents = vec![link],
+            expires_at,
+        )
+    }
+
+    /// Adds `parent` alongside the key's other parents, or moves the deadline of an
+    /// existing link to it
+    pub fn add_parent(
+        &self,
+        key: &str,
+        parent: String,
+        expires_at: Option<Instant>,
+    ) -> Result<i64, CacheError> {
+        self.update_parents(
+            key,
+            parent,
+            |parents, link| {
+                parents.retain(|existing| existing.id != link.id);
+                parents.push(link);
+            },
+            expires_at,
+        )
+    }
+
+    /// Applies `update` to the key's parent links once `parent` is known to exist and
+    /// not to depend on the key
+    fn update_parents(
+        &self,
+        key: &str,
+        parent: String,
+        update: impl FnOnce(&mut Vec<ParentLink>, ParentLink),
+        expires_at: Option<Instant>,
+    ) -> Result<i64, CacheError> {
+        let _guard = self.dependency_write();
+
+        let Some(parent_id) = self.data.get(&parent).map(|entry| entry.id) else {
+            return Err(CacheError::ParentNotFound(parent));
+        };
+
+        if self.would_create_cycle(key, &parent) {
+            return Err(CacheError::DependencyCycle(key.to_string(), parent));
+        }
+
+        let Some((id, mut parents)) = self
+            .data
+            .get(key)
+            .map(|entry| (entry.id, entry.parents.clone()))
+        else {
+            return Ok(0);
+        };
+        update(&mut parents, ParentLink::new(parent_id, expires_at));
+        let ids: Vec<EntryId> = parents.iter().map(|link| link.id).collect();
+        self.check_depth(key, Some(id), &ids)?;
+
+        match self.data.get_mut(key) {
+            Some(mut entry) => {
+                self.relink(entry.id, &entry.parents, &parents);
+                entry.parents = parents;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// Every parent-child link, sorted by key so exports diff cleanly
+    pub fn dependency_graph(&self) -> Vec<DependencyEdge> {
+        let _guard = self.dependency_read();
+
+        let mut edges: Vec<DependencyEdge> = self
+            .data
+            .iter()
+            .flat_map(|entry| {
+                entry
+                    .live_parents()
+                    .filter_map(|parent| {
+                        Some(DependencyEdge {
+                            key: entry.key().clone(),
+                            parent: self.key_of(parent)?,
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        edges.sort_unstable();
+        edges
+    }
+
+    /// Re-applies exported links to keys that are present, e.g. after warming a fresh
+    /// cache. Links whose key or parent is absent are skipped, as are ones that would
+    /// now form a cycle or a chain past `max_dependency_depth`.
+    pub fn import_dependency_graph(
+        &self,
+        edges: &[DependencyEdge],
+    ) -> Result<GraphImport, CacheError> {
+        if !self.config.enable_dependencies {
+            return Err(CacheError::DependenciesDisabled);
+        }
+
+        let mut result = GraphImport::default();
+        for edge in edges {
+            match self.add_parent(&edge.key, edge.parent.clone(), None) {
+                Ok(1) => result.applied += 1,
+                Ok(_) | Err(CacheError::ParentNotFound(_)) => result.missing += 1,
+                Err(CacheError::DependencyCycle(..)) => result.cycles += 1,
+                Err(CacheError::DependencyTooDeep(..)) => result.too_deep += 1,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Detaches a key from all its parents, making it independent
+    pub fn unset_parent(&self, key: &str) -> i64 {
+        let _guard = self.dependency_write();
+
+        match self.data.get_mut(key) {
+            Some(mut entry) => {
+                let linked = entry.live_parents().next().is_some();
+                self.relink(entry.id, &entry.parents, &[]);
+                entry.parents.clear();
+                linked as i64
+            }
+            None => 0,
+        }
+    }
+
+    /// Detaches a key from one of its parents, keeping the others
+    pub fn remove_parent(&self, key: &str, parent: &str) -> i64 {
+        let _guard = self.dependency_write();
+
+        let Some(parent_id) = self.data.get(parent).map(|entry| entry.id) else {
+            return 0;
+        };
+        match self.data.get_mut(key) {
+            Some(mut entry) => {
+                let linked = entry.has_live_parent(parent_id);
+                let mut parents = entry.parents.clone();
+                parents.retain(|link| link.id != parent_id);
+                self.relink(entry.id, &entry.parents, &parents);
+                entry.parents = parents;
+                linked as i64
+            }
+            None => 0,
+        }
+    }
+
+    /// Atomically moves every direct child of `from` under `to`, leaving their other
+    /// parents alone; either all children move or none do
+    pub fn reparent_children(&self, from: &str, to: &str) -> Result<usize, CacheError> {
+        let _guard = self.dependency_write();
+
+        let Some(to_id) = self.data.get(to).map(|entry| entry.id) else {
+            return Err(CacheError::ParentNotFound(to.to_string()));
+        };
+        if from == to {
+            return Ok(0);
+        }
+        let Some(from_id) = self.data.get(from).map(|entry| entry.id) else {
+            return Ok(0);
+        };
+
+        let children: Vec<String> = self
+            .children_recursive(from, 1)
+            .into_iter()
+            .map(|(child, _)| child)
+            .collect();
+
+        if let Some(child) = children
+            .iter()
+            .find(|child| self.would_create_cycle(child, to))
+        {
+            return Err(CacheError::DependencyCycle(child.clone(), to.to_string()));
+        }
+
+        // Every child is checked against the depth limit before any of them moves
+        let mut moves = Vec::with_capacity(children.len());
+        for child in &children {
+            let Some((id, mut parents)) = self
+                .data
+                .get(child)
+                .map(|entry| (entry.id, entry.parents.clone()))
+            else {
+                continue;
+            };
+            let moved = parents.iter().find(|link| link.id == from_id).copied();
+            parents.retain(|link| link.id != from_id && link.id != to_id);
+            parents.push(ParentLink::new(
+                to_id,
+                moved.and_then(|link| link.expires_at),
+            ));
+            let ids: Vec<EntryId> = parents.iter().map(|link| link.id).collect();
+            self.check_depth(child, Some(id), &ids)?;
+            moves.push((child, parents));
+        }
+
+        for (child, parents) in moves {
+            if let Some(mut entry) = self.data.get_mut(child) {
+                self.relink(entry.id, &entry.parents, &parents);
+                entry.parents = parents;
+            }
+        }
+
+        Ok(children.len())
+    }
+
+    /// Invalidates a key and all of its dependents, returning how many keys were
+    /// affected. Every affected key is published as `Invalidated` by the root.
+    pub fn invalidate(&self, root: &str, mode: InvalidationMode) -> usize {
+        let keys = self.invalidation_keys(root);
+        let keys = keys.into_iter().map(|key| (key, root.to_string()));
+        self.invalidate_keys(keys.collect(), mode)
+    }
+
+    /// Invalidates every key carrying `tag` along with its dependents. Each affected
+    /// key is published as `Invalidated` by the tagged key it was reached from.
+    pub fn invalidate_tag(&self, tag: &str, mode: InvalidationMode) -> usize {
+        self.invalidate_keys(self.tag_invalidation_keys(tag), mode)
+    }
+
+    /// Keys an INVALIDATE_TAG of `tag` would reach, each paired with its tagged root
+    pub fn tag_invalidation_keys(&self, tag: &str) -> Vec<(String, String)> {
+        let mut roots = self.tagged(tag);
+        roots.sort();
+        let mut seen = HashSet::new();
+        let mut keys = Vec::new();
+        for root in roots {
+            for key in self.invalidation_keys(&root) {
+                if seen.insert(key.clone()) {
+                    keys.push((key, root.clone()));
+                }
+            }
+        }
+        keys
+    }
+
+    /// Deletes or marks stale each key, publishing it as invalidated by its cause
+    fn invalidate_keys(&self, keys: Vec<(String, String)>, mode: InvalidationMode) -> usize {
+        let affected = match mode {
+            InvalidationMode::Delete => {
+                let keys: Vec<&str> = keys.iter().map(|(key, _)| key.as_str()).collect();
+                self.del_keys(&keys, false, true)
+            }
+            InvalidationMode::Stale => keys
+                .iter()
+                .filter(|(key, _)| match self.data.get_mut(key.as_str()) {
+                    Some(mut entry) => {
+                        entry.stale = true;
+                        true
+                    }
+                    None => false,
+                })
+                .count(),
+        };
+
+        for (key, cause) in keys {
+            self.events.publish(CacheEvent::Invalidated { key, cause });
+        }
+        affected
+    }
+
+    /// TYPE and OBJECT ENCODING of a live key, without cloning its value
+    pub fn describe(&self, key: &str) -> Option<(&'static str, &'static str)> {
+        if !self.is_live(key) {
+            return None;
+        }
+        let entry = self.data.get(key)?;
+        Some((entry.value.redis_type(), entry.value.encoding()))
+    }
+
+    /// Decayed access count of a live key, as reported by OBJECT FREQ; not an access itself
+    pub fn frequency(&self, key: &str) -> Option<u64> {
+        if !self.is_live(key) {
+            return None;
+        }
+        let entry = self.data.get(key)?;
+        Some(entry.frequency(Instant::now(), &self.config.lfu))
+    }
+
+    /// Whether reads of the key are served stale: flagged by a stale-mode invalidation,
+    /// or expired (itself or via its parent) but within `Config::stale_grace`
+    pub fn is_stale(&self, key: &str) -> bool {
+        let flagged = match self.data.get(key) {
+            Some(entry) => entry.stale,
+            None => return false,
+        };
+        flagged || !self.is_live(key)
+    }
+
+    pub fn children_recursive(&self, parent_key: &str, max_depth: usize) -> Vec<(String, u64)> {
+        self.children_page(parent_key, max_depth, 0, usize::MAX)
+    }
+
+    /// Breadth-first page of descendants, ordered by depth then key. Traversal
+    /// stops once `offset + limit` children have been found.
+    pub fn children_page(
+        &self,
+        parent_key: &str,
+        max_depth: usize,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<(String, u64)> {
+        self.children_page_until(parent_key, max_depth, offset, limit, Deadline::default())
+            .0
+    }
+
+    /// `children_page`, checking `deadline` between levels. A walk cut short returns
+    /// what it found with the offset to resume from; it always finds at least one
+    /// child past `offset` first, so resuming makes progress.
+    pub fn children_page_until(
+        &self,
+        parent_key: &str,
+        max_depth: usize,
+        offset: usize,
+        limit: usize,
+        deadline: Deadline,
+    ) -> (Vec<(String, u64)>, Option<usize>) {
+        let wanted = offset.saturating_add(limit);
+        let mut result = Vec::new();
+        let mut cut_short = false;
+
+        let mut levels = self.children_levels(parent_key, max_depth);
+        while let Some(level) = levels.next() {
+            result.extend(level);
+            if result.len() >= wanted {
+                break;
+            }
+            if result.len() > offset && !levels.is_done() && deadline.passed() {
+                cut_short = true;
+                break;
+            }
+        }
+
+        let page: Vec<_> = result.into_iter().skip(offset).take(limit).collect();
+        let cursor = cut_short.then(|| offset + page.len());
+        (page, cursor)
+    }
+
+    /// Lazily walks dependents breadth-first, yielding one level (sorted by key) at a
+    /// time. A key reached along several paths is yielded once, at its shallowest depth.
+    pub fn children_levels(&self, parent_key: &str, max_depth: usize) -> ChildrenLevels<'_> {
+        let parents: HashSet<EntryId> = self
+            .data
+            .get(parent_key)
+            .map(|entry| entry.id)
+            .into_iter()
+            .collect();
+        ChildrenLevels {
+            cache: self,
+            seen: parents.clone(),
+            parents,
+            depth: 0,
+            max_depth,
+        }
+    }
+
+    /// Descendants of each parent within `max_depth` levels, in the order given, each
+    /// counted once however many paths reach it. All parents are walked together, a
+    /// level at a time.
+    pub fn children_counts(&self, parents: &[String], max_depth: usize) -> Vec<usize> {
+        let mut counts = vec![0; parents.len()];
+        // Frontier entry -> indexes of the parents it descends from
+        let mut frontier: HashMap<EntryId, Vec<usize>> = HashMap::new();
+        let mut seen: HashSet<(EntryId, usize)> = HashSet::new();
+        for (i, parent) in parents.iter().enumerate() {
+            if let Some(entry) = self.data.get(parent) {
+                frontier.entry(entry.id).or_default().push(i);
+                seen.insert((entry.id, i));
+            }
+        }
+        for _ in 0..max_depth {
+            if frontier.is_empty() {
+                break;
+            }
+            let mut next: HashMap<EntryId, Vec<usize>> = HashMap::new();
+            for (parent, roots) in &frontier {
+                for (_, child) in self.children_of(*parent) {
+                    for &root in roots {
+                        if seen.insert((child, root)) {
+                            counts[root] += 1;
+                            next.entry(child).or_default().push(root);
+                        }
+                    }
+                }
+            }
+            frontier = next;
+        }
+        counts
+    }
+
+    /// Live keys due to expire within the window, soonest first
+    pub fn expiring_within(&self, within: Duration, limit: usize) -> Vec<String> {
+        let now = Instant::now();
+        let mut due: Vec<(Instant, String)> = self
+            .expiry_index
+            .due_within(now, within)
+            .into_iter()
+            .filter_map(|key| {
+                let expires_at = self.data.get(&key)?.ttl.as_ref()?.expires_at;
+                (expires_at > now && expires_at <= now + within).then_some((expires_at, key))
+            })
+            .collect();
+
+        due.sort();
+        due.into_iter().take(limit).map(|(_, key)| key).collect()
+    }
+
+    /// Keys `invalidate` reaches from `root`: the root, then every descendant. None
+    /// when the root is missing.
+    pub fn invalidation_keys(&self, root: &str) -> Vec<String> {
+        let _guard = self.dependency_read();
+        if !self.data.contains_key(root) {
+            return Vec::new();
+        }
+        let mut keys: Vec<String> = self
+            .children_recursive(root, usize::MAX)
+            .into_iter()
+            .map(|(child, _)| child)
+            .collect();
+        keys.insert(0, root.to_string());
+        keys
+    }
+
+    /// Reports which of `keys` are in the cache and the memory they hold, without
+    /// touching them. Counts what a delete would remove, expired entries included.
+    pub fn dry_run(&self, keys: impl IntoIterator<Item = String>) -> DryRunReport {
+        let mut keys: Vec<String> = keys.into_iter().collect();
+        keys.sort();
+        keys.dedup();
+        let mut bytes = 0;
+        keys.retain(|key| match self.data.get(key.as_str()) {
+            Some(entry) => {
+                bytes += entry.key().len() + entry.memory_usage();
+                true
+            }
+            None => false,
+        });
+        DryRunReport {
+            count: keys.len(),
+            keys,
+            bytes,
+        }
+    }
+
+    pub fn flush_all(&self) {
+        self.data.clear();
+        self.keys_by_id.clear();
+        self.indexes.children_by_id.clear();
+        self.indexes.ids_by_tag.clear();
+        self.indexes.chain_checks.clear();
+        self.indexes.chain_generation.fetch_add(1, Ordering::AcqRel);
+        self.expiry_index.clear();
+        self.stats.memory_usage.store(0, Ordering::Relaxed);
+        self.stats.pinned_memory.store(0, Ordering::Relaxed);
+        for namespace in self.stats.namespaces.iter() {
+            namespace.memory_usage.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Deletes the keys matching `pattern` one shard at a time, so the whole
+    /// keyspace is never materialized or locked at once. Returns how many went.
+    pub fn flush_pattern(&self, pattern: &str) -> usize {
+        (0..self.shard_count())
+            .map(|shard| {
+                let keys = self.shard_keys(shard, pattern);
+                self.del(&keys.iter().map(String::as_str).collect::<Vec<_>>())
+            })
+            .sum()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.stats.memory_usage.load(Ordering::Relaxed)
+    }
+
+    /// Shards across every generation, including ones emptied by growing; see `ShardedMap`
+    pub fn shard_count(&self) -> usize {
+        self.data.shard_count()
+    }
+
+    /// Shards no longer holding keys after the cache grew; they're never refilled
+    pub fn retired_shards(&self) -> usize {
+        self.data.retired_shards()
+    }
+
+    /// Whether keys are still moving into the shards added by the last `grow_shards`
+    pub fn is_migrating(&self) -> bool {
+        self.data.is_migrating()
+    }
+
+    /// Adds a generation of `shard_amount` shards that keys move into incrementally,
+    /// on write and during cleanup. False when the cache is already migrating, the
+    /// amount doesn't exceed the current generation, or it has grown too many times.
+    pub fn grow_shards(&self, shard_amount: usize) -> bool {
+        if !self.data.grow(shard_amount) {
+            return false;
+        }
+        let shards = self.data.shard_count();
+        self.sampler.resize(shards, self.data.retired_shards());
+        if let Some(tracker) = &self.contention {
+            tracker.resize(shards);
+        }
+        debug!("Growing to {} shards", shards - self.data.retired_shards());
+        true
+    }
+
+    /// Moves keys into the newest shard generation for up to `MIGRATE_CYCLE_BUDGET`,
+    /// growing first when shards average over `Config::shard_growth_keys` keys.
+    /// Returns how many keys moved.
+    pub fn migrate_shards(&self) -> usize {
+        if let Some(limit) = self.config.shard_growth_keys {
+            let live = self.data.shard_count() - self.data.retired_shards();
+            if !self.data.is_migrating() && self.data.len() / live > limit {
+                self.grow_shards(live * SHARD_GROWTH_FACTOR);
+            }
+        }
+        if !self.data.is_migrating() {
+            return 0;
+        }
+        let started = Instant::now();
+        let mut moved = 0;
+        while self.data.is_migrating() && started.elapsed() < MIGRATE_CYCLE_BUDGET {
+            let batch = self.data.migrate(MIGRATE_BATCH);
+            moved += batch;
+            if batch == 0 {
+                break;
+            }
+        }
+        if !self.data.is_migrating() {
+            self.sampler
+                .resize(self.data.shard_count(), self.data.retired_shards());
+            debug!("Shard migration finished");
+        }
+        moved
+    }
+
+    /// Keys matching the pattern in a single shard, so callers can walk the
+    /// keyspace without materializing all of it
+    pub fn shard_keys(&self, shard_index: usize, pattern: &str) -> Vec<String> {
+        self.data
+            .read_shard(shard_index, |entries| {
+                entries
+                    .map(|(key, _)| key)
+                    .filter(|key| matches_pattern(key, pattern))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Visits up to `limit` live-by-TTL entries, shard by shard from a random
+    /// starting shard, until the time budget runs out. Returns how many were visited.
+    pub fn sample_entries<F>(&self, limit: usize, budget: Duration, mut f: F) -> usize
+    where
+        F: FnMut(&str, &Entry),
+    {
+        let shards = self.data.shard_count();
+        let deadline = Instant::now() + budget;
+        let start = rand::random_range(0..shards);
+        let mut visited = 0;
+
+        for i in 0..shards {
+            let stopped = self.data.read_shard((start + i) % shards, |entries| {
+                for (key, entry) in entries {
+                    if visited >= limit || (visited % 64 == 0 && Instant::now() >= deadline) {
+                        return true;
+                    }
+                    if entry.ttl.as_ref().is_some_and(Ttl::is_expired) {
+                        continue;
+                    }
+                    f(key, entry);
+                    visited += 1;
+                }
+                false
+            });
+            if stopped == Some(true) {
+                break;
+            }
+        }
+        visited
+    }
+
+    /// Visits every entry that hasn't expired, shard by shard
+    pub fn for_each_entry<F>(&self, mut f: F)
+    where
+        F: FnMut(&str, &Entry),
+    {
+        for shard in 0..self.data.shard_count() {
+            self.data.read_shard(shard, |entries| {
+                for (key, entry) in entries {
+                    if !entry.ttl.as_ref().is_some_and(Ttl::is_expired) {
+                        f(key, entry);
+                    }
+                }
+            });
+        }
+    }
+
+    /// Inserts an entry as-is, as read back from a snapshot. Snapshots hold parents
+    /// by key, so restored entries come without one; links are made afterwards with
+    /// `add_parent`, since parents may be restored after their children, and are held
+    /// to `max_dependency_depth` there. AOF replay and replicas link through commands.
+    pub fn restore(&self, key: String, entry: Entry) -> Result<(), CacheError> {
+        if let Some(ttl) = &entry.ttl {
+            self.expiry_index.insert(&key, ttl.expires_at);
+        }
+        self.insert_entry(key, entry)
+    }
+
+    /// Removes expired keys: precisely via the expiry index, then with a sampling
+    /// pass to catch anything the index missed, along with orphaned dependents.
+    /// Moves keys along first while the shard count is growing.
+    pub fn cleanup_expired(&self) -> usize {
+        if let Some(tombstones) = &self.tombstones {
+            tombstones.purge();
+        }
+        self.migrate_shards();
+        self.expire_due() + self.sample_expired()
+    }
+
+    /// Indexed cleanup: removes keys whose expiry bucket has passed, re-indexing
+    /// entries whose TTL moved (sliding resets, EXPIRE) since they were indexed.
+    /// Low priority keys are removed first.
+    pub fn expire_due(&self) -> usize {
+        self.expire_due_at(Instant::now())
+    }
+
+    /// `expire_due` as of `now`, so tests can step past TTLs instead of sleeping
+    fn expire_due_at(&self, now: Instant) -> usize {
+        let mut expired = Vec::new();
+
+        let mut purged = 0;
+
+        for key in self.expiry_index.take_due(now) {
+            if self.purge_expired_fields(&key, now) {
+                purged += 1;
+                continue;
+            }
+            let Some(entry) = self.data.get(&key) else {
+                continue;
+            };
+
+            // Keys within the stale grace period are re-indexed for when it ends
+            let grace = self.stale_grace();
+            match &entry.ttl {
+                Some(ttl) if now >= ttl.expires_at + grace => {
+                    let priority = entry.priority;
+                    drop(entry);
+                    expired.push((priority, key));
+                }
+                Some(ttl) => self.expiry_index.insert(&key, ttl.expires_at + grace),
+                None => {}
+            }
+        }
+
+        expired.sort_by_key(|(priority, _)| *priority);
+        let expired: Vec<String> = expired.into_iter().map(|(_, key)| key).collect();
+        purged + self.remove_expired(&expired)
+    }
+
+    /// Probabilistic cleanup: samples shards until a pass finds under
+    /// `BUSY_EXPIRED_RATIO` of its keys expired or orphaned, or `EXPIRE_CYCLE_BUDGET`
+    /// runs out, so a burst of expiring keys goes in one cycle.
+    fn sample_expired(&self) -> usize {
+        let deadline = Deadline::after(Some(EXPIRE_CYCLE_BUDGET));
+        let mut removed = 0;
+        loop {
+            let (sampled, expired, dependents) = self.sample_shard();
+            let orphaned = self.remove_orphans(&dependents);
+            removed += self.remove_expired(&expired) + orphaned;
+            if sampled == 0
+                || ((expired.len() + orphaned) as f64 / sampled as f64) < BUSY_EXPIRED_RATIO
+                || deadline.passed()
+            {
+                return removed;
+            }
+        }
+    }
+
+    /// Removes dependents whose parent chain is broken, which would otherwise wait
+    /// for a read to find them. Each is re-checked under the dependency lock, so one
+    /// rewritten or relinked since it was seen stays. Like `remove_expired`, the key
+    /// and its own dependents are published as invalidated, and with `expire_children`
+    /// the subtree goes too.
+    fn remove_orphans(&self, keys: &[String]) -> usize {
+        let grace = self.stale_grace();
+        let cascade = self.config.expire_children || self.events.has_subscribers();
+        let mut orphaned = 0;
+        let mut removed = 0;
+
+        let _guard = self.dependency_write();
+        for key in keys {
+            if self.is_live_within(key, grace) {
+                continue;
+            }
+            let Some((id, parents)) = self.data.get(key).and_then(|entry| {
+                // An expired key is left to expiry, which reports it as such
+                let expired = entry
+                    .ttl
+                    .as_ref()
+                    .is_some_and(|ttl| ttl.is_past_grace(grace));
+                (!expired).then(|| (entry.id, entry.live_parents().collect::<Vec<_>>()))
+            }) else {
+                continue;
+            };
+            let cause = self
+                .broken_parent(&parents, grace)
+                .unwrap_or_else(|| key.clone());
+            let children: Vec<String> = match cascade {
+                true => self
+                    .children_recursive(key, usize::MAX)
+                    .into_iter()
+                    .map(|(child, _)| child)
+                    .collect(),
+                false => Vec::new(),
+            };
+            if self.remove_entry_if(key, |entry| entry.id == id).is_none() {
+                continue;
+            }
+            orphaned += 1;
+            removed += 1;
+
+            self.events.publish(CacheEvent::Invalidated {
+                key: key.clone(),
+                cause,
+            });
+            for child in &children {
+                self.events.publish(CacheEvent::Invalidated {
+                    key: child.clone(),
+                    cause: key.clone(),
+                });
+            }
+            if self.config.expire_children {
+                removed += children
+                    .iter()
+                    .filter(|child| self.remove_entry(child).is_some())
+                    .count();
+            }
+        }
+
+        self.stats
+            .orphaned_keys
+            .fetch_add(orphaned as u64, Ordering::Relaxed);
+        removed
+    }
+
+    /// The first of `parents` whose own chain is broken, if it still has a key
+    fn broken_parent(&self, parents: &[EntryId], grace: Duration) -> Option<String> {
+        parents
+            .iter()
+            .filter_map(|&parent| self.key_of(parent))
+            .find(|parent| !self.is_live_within(parent, grace))
+    }
+
+    /// Samples a random run of entries from one shard, picked by the `SampleScheduler`
+    /// in proportion to how many expired keys it has recently turned up. Returns how
+    /// many were sampled, the expired ones, and the unexpired ones with live parents,
+    /// whose chains are checked once the shard is unlocked.
+    fn sample_shard(&self) -> (usize, Vec<String>, Vec<String>) {
+        const NUM_SAMPLES: usize = 20;
+
+        let grace = self.stale_grace();
+        let shard_index = self.sampler.next_shard();
+
+        let sample = self.data.read_shard(shard_index, |entries| {
+            let shard_size = entries.len();
+            let take = shard_size.min(NUM_SAMPLES);
+            let skip = rand::random_range(0..=shard_size - take);
+
+            let mut keys = Vec::new();
+            let mut dependents = Vec::new();
+            for (key, entry) in entries.skip(skip).take(take) {
+                if entry
+                    .ttl
+                    .as_ref()
+                    .is_some_and(|ttl| ttl.is_past_grace(grace))
+                {
+                    keys.push(key.clone());
+                } else if entry.live_parents().next().is_some() {
+                    dependents.push(key.clone());
+                }
+            }
+            (take, keys, dependents)
+        }); // lock released
+        let Some((sampled, keys_to_delete, dependents)) = sample else {
+            return (0, Vec::new(), Vec::new());
+        };
+
+        self.sampler
+            .record(shard_index, sampled, keys_to_delete.len());
+        (sampled, keys_to_delete, dependents)
+    }
+
+    /// Prometheus counters for the sampling cleanup, including how evenly it covers shards,
+    /// and the tombstones it purges
+    pub fn render_cleanup_metrics(&self, out: &mut String, labels: &Labels) {
+        let labels = labels.series();
+        let shards = (self.data.shard_count() - self.data.retired_shards()) as u64;
+        let families: [(&str, &str, &str, String); 5] = [
+            (
+                "cache_cleanup_passes_total",
+                "Sampling cleanup passes run",
+                "counter",
+                self.sampler.passes().to_string(),
+            ),
+            (
+                "cache_cleanup_sampled_keys_total",
+                "Keys inspected by sampling cleanup",
+                "counter",
+                self.sampler.samples().to_string(),
+            ),
+            (
+                "cache_cleanup_expired_found_total",
+                "Expired keys found by sampling cleanup",
+                "counter",
+                self.sampler.expired().to_string(),
+            ),
+            (
+                "cache_cleanup_shard_coverage_ratio",
+                "Share of shards sampled within the last 4x shard-count passes",
+                "gauge",
+                self.sampler.coverage(shards * 4).to_string(),
+            ),
+            (
+                "cache_tombstones",
+                "Tombstones kept for soft-deleted keys, including any past retention",
+                "gauge",
+                self.tombstone_count().to_string(),
+            ),
+        ];
+        for (name, help, kind, value) in families {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+            writeln!(out, "{}{} {}", name, labels, value).unwrap();
+        }
+    }
+
+    /// Deletes expired keys and cascades to their dependents: subscribers are sent
+    /// invalidation events, and with `expire_children` the subtree is removed too.
+    fn remove_expired(&self, keys: &[String]) -> usize {
+        // Dependents are found through their parent's entry, so before it goes
+        let cascade = self.config.expire_children || self.events.has_subscribers();
+        let dependents: Vec<Vec<String>> = keys
+            .iter()
+            .map(|key| {
+                if !cascade {
+                    return Vec::new();
+                }
+                self.children_recursive(key, usize::MAX)
+                    .into_iter()
+                    .map(|(child, _)| child)
+                    .collect()
+            })
+            .collect();
+        let mut removed = self.del_keys(
+            &keys.iter().map(String::as_str).collect::<Vec<_>>(),
+            false,
+            false,
+        );
+        self.stats
+            .expired_keys
+            .fetch_add(removed as u64, Ordering::Relaxed);
+
+        for (key, children) in keys.iter().zip(dependents) {
+            self.events
+                .publish(CacheEvent::Expired { key: key.clone() });
+            if !cascade {
+                continue;
+            }
+
+            for child in &children {
+                self.events.publish(CacheEvent::Invalidated {
+                    key: child.clone(),
+                    cause: key.clone(),
+                });
+            }
+
+            if self.config.expire_children {
+                removed += self.del_keys(
+                    &children.iter().map(String::as_str).collect::<Vec<_>>(),
+                    false,
+                    false,
+                );
+            }
+        }
+
+        removed
+    }
+
+    /// Checks the TTL and parent chain of a key. Unlike `Entry::is_valid`, no shard
+    /// guard is held while looking up the parent, which may live in the same shard.
+    fn is_live(&self, key: &str) -> bool {
+        self.is_live_within(key, Duration::ZERO)
+    }
+
+    /// Like `is_live`, treating TTLs as running `grace` longer. Every ancestor must
+    /// be live, through each of a key's parents.
+    fn is_live_within(&self, key: &str, grace: Duration) -> bool {
+        let generation = self.chain_generation();
+        let (id, parents) = {
+            let Some(entry) = self.data.get(key) else {
+                return false;
+            };
+            if entry
+                .ttl
+                .as_ref()
+                .is_some_and(|ttl| ttl.is_past_grace(grace))
+            {
+                return false;
+            }
+            (entry.id, entry.live_parents().collect::<Vec<_>>())
+        };
+        parents.is_empty() || self.ancestors_live(id, parents, grace, generation)
+    }
+
+    /// The key's entry ID and creation time, and whether it's live, for writers that
+    /// re-check under the entry's lock that it's still the entry judged
+    fn liveness(&self, key: &str) -> Option<(EntryId, Instant, bool)> {
+        let generation = self.chain_generation();
+        let (id, created_at, parents) = {
+            let entry = self.data.get(key)?;
+            if entry.ttl.as_ref().is_some_and(Ttl::is_expired) {
+                return Some((entry.id, entry.created_at, false));
+            }
+            let parents = entry.live_parents().collect::<Vec<_>>();
+            (entry.id, entry.created_at, parents)
+        };
+        let live =
+            parents.is_empty() || self.ancestors_live(id, parents, Duration::ZERO, generation);
+        Some((id, created_at, live))
+    }
+
+    /// Read before the entries a chain check looks at. Writers bump it while still
+    /// holding the entry they change, then mark the checks they undo stale: a walk
+    /// begun before the bump can't be cached over the mark, and one begun after sees
+    /// the change.
+    fn chain_generation(&self) -> u64 {
+        self.indexes.chain_generation.load(Ordering::Acquire)
+    }
+
+    /// Whether every ancestor of entry `id`, reached through `parents`, is live
+    /// within `grace`. A live chain is remembered until its earliest TTL or a change
+    /// above the entry, so repeat reads skip the walk.
+    fn ancestors_live(
+        &self,
+        id: EntryId,
+        parents: Vec<EntryId>,
+        grace: Duration,
+        generation: u64,
+    ) -> bool {
+        if let Some(check) = self.indexes.chain_checks.get(&id)
+            && check.live
+            && check
+                .expires_at
+                .is_none_or(|at| Instant::now() < at + grace)
+        {
+            return true;
+        }
+
+        let mut expires_at: Option<Instant> = None;
+        let mut visited = HashSet::new();
+        let mut pending = parents;
+        while let Some(current) = pending.pop() {
+            if !visited.insert(current) {
+                continue;
+            }
+            let Some(key) = self.key_of(current) else {
+                return false;
+            };
+            let Some(entry) = self.data.get(&key) else {
+                return false;
+            };
+            if let Some(ttl) = &entry.ttl {
+                if ttl.is_past_grace(grace) {
+                    return false;
+                }
+                expires_at = Some(expires_at.map_or(ttl.expires_at, |at| at.min(ttl.expires_at)));
+            }
+            pending.extend(entry.live_parents());
+        }
+
+        let check = ChainCheck {
+            generation,
+            expires_at,
+            live: true,
+        };
+        match self.indexes.chain_checks.entry(id) {
+            // Marked stale since the walk began
+            IndexEntry::Occupied(current) if current.get().generation > generation => {}
+            IndexEntry::Occupied(mut current) => {
+                current.insert(check);
+            }
+            IndexEntry::Vacant(vacant) => {
+                vacant.insert(check);
+            }
+        }
+        true
+    }
+
+    fn stale_grace(&self) -> Duration {
+        self.config.stale_grace.unwrap_or_default()
+    }
+
+    fn notify_miss(&self, key: &str) {
+        if self.config.notify_misses || self.config.keyspace_events.miss {
+            self.events.publish(CacheEvent::Miss {
+                key: key.to_string(),
+            });
+        }
+    }
+
+    /// Used to check for cycles before adding a parent dependency: whether `key` is
+    /// `parent` or one of its ancestors, through any of their parents.
+    /// Access under the dependency_lock, or you might allow cycles
+    fn would_create_cycle(&self, key: &str, parent: &str) -> bool {
+        let mut visited = HashSet::new();
+        let mut pending = vec![parent.to_string()];
+
+        while let Some(current_key) = pending.pop() {
+            if current_key == key {
+                return true;
+            }
+            // Shared ancestors are reached once per path; walking them again adds nothing
+            if !visited.insert(current_key.clone()) {
+                continue;
+            }
+
+            if let Some(entry) = self.data.get(&current_key) {
+                let parents: Vec<EntryId> = entry.live_parents().collect();
+                drop(entry);
+                pending.extend(parents.into_iter().filter_map(|id| self.key_of(id)));
+            }
+        }
+
+        false
+    }
+
+    /// Errors if linking `key` (entry `id`, if it exists) below `parents` would put it,
+    /// or any of its dependents, more than `max_dependency_depth` levels down
+    fn check_depth(
+        &self,
+        key: &str,
+        id: Option<EntryId>,
+        parents: &[EntryId],
+    ) -> Result<(), CacheError> {
+        let max = self.config.max_dependency_depth;
+        let too_deep = || Err(CacheError::DependencyTooDeep(key.to_string(), max));
+
+        let mut memo = HashMap::new();
+        let up = |id| self.parents_of(id);
+        let above = parents
+            .iter()
+            .map(|&parent| 1 + self.longest_path(parent, max, &mut memo, &up))
+            .max()
+            .unwrap_or(0);
+        if above > max {
+            return too_deep();
+        }
+
+        let down = |id| self.children_of(id).into_iter().map(|(_, id)| id).collect();
+        let below = id.map_or(0, |id| {
+            self.longest_path(id, max + 1 - above, &mut HashMap::new(), &down)
+        });
+        if above + below > max {
+            return too_deep();
+        }
+        Ok(())
+    }
+
+    /// Live parents of the entry with `id`
+    fn parents_of(&self, id: EntryId) -> Vec<EntryId> {
+        self.key_of(id)
+            .and_then(|key| self.data.get(&key))
+            .map(|entry| entry.live_parents().collect())
+            .unwrap_or_default()
+    }
+
+    /// Length of the longest path from `id` following `next`, counted up to `budget`.
+    /// Only exact lengths are memoized, since a capped one depends on the budget.
+    fn longest_path(
+        &self,
+        id: EntryId,
+        budget: usize,
+        memo: &mut HashMap<EntryId, usize>,
+        next: &impl Fn(EntryId) -> Vec<EntryId>,
+    ) -> usize {
+        if budget == 0 {
+            return 0;
+        }
+        if let Some(&length) = memo.get(&id) {
+            return length.min(budget);
+        }
+        let mut length = 0;
+        for neighbour in next(id) {
+            length = length.max(1 + self.longest_path(neighbour, budget - 1, memo, next));
+            if length >= budget {
+                return budget;
+            }
+        }
+        memo.insert(id, length);
+        length
+    }
+
+    fn insert_entry(&self, key: String, mut entry: Entry) -> Result<(), CacheError> {
+        let memory_delta = key.len() + entry.memory_usage();
+        let parents: Vec<String> = entry
+            .live_parents()
+            .filter_map(|id| self.key_of(id))
+            .collect();
+        self.check_limits_for(&key, &parents, memory_delta)?;
+        self.track_shard(&key, true);
+        if entry.pinned
+            && let Some(max_pinned) = self.config.max_pinned_memory
+            && self.stats.pinned_memory.load(Ordering::Relaxed) + memory_delta > max_pinned
+        {
+            return Err(CacheError::PinnedLimitExceeded);
+        }
+
+        self.adjust_memory(&key, memory_delta, 0);
+        self.adjust_pinned(entry.pinned, memory_delta, 0);
+        self.revive(&key);
+        let key_len = key.len();
+        // Overwriting keeps the old entry's ID, so dependents stay attached
+        match self.data.entry(key) {
+            MapEntry::Occupied(mut occupied) => {
+                entry.id = occupied.get().id;
+                self.relink(entry.id, &occupied.get().parents, &entry.parents);
+                self.retag(entry.id, occupied.get().tags(), entry.tags());
+                self.ancestor_changed(entry.id);
+                let old = occupied.insert(entry);
+                let old_size = key_len + old.memory_usage();
+                self.adjust_memory(occupied.key(), 0, old_size);
+                self.adjust_pinned(old.pinned, 0, old_size);
+            }
+            MapEntry::Vacant(vacant) => {
+                self.keys_by_id.insert(entry.id, vacant.key().clone());
+                self.relink(entry.id, &[], &entry.parents);
+                self.retag(entry.id, &[], entry.tags());
+                vacant.insert(entry);
+            }
+        }
+        self.stats.sets.fetch_add(1, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Evicts keys chosen by the eviction policy until `needed` bytes are freed.
+    /// Returns whether enough was freed.
+    fn evict(&self, needed: usize, protected: &[&str]) -> bool {
+        let policy = self.config.eviction_policy;
+        policy.evicts()
+            && self.evict_by(
+                protected,
+                |entry, now| policy.score(entry, now, &self.config.lfu),
+                |freed, _| freed >= needed,
+            )
+    }
+
+    /// Evicts `bytes` worth of keys chosen by the eviction policy, e.g. when the
+    /// process outgrows a bound the logical estimate doesn't see. Returns whether
+    /// the policy could free that much.
+    pub fn shed_memory(&self, bytes: usize) -> bool {
+        self.evict(bytes, &[])
+    }
+
+    /// Evicts `count` keys chosen by the key limit policy. Returns whether it could.
+    fn evict_keys(&self, count: usize, protected: &[&str]) -> bool {
+        let policy = self.config.key_limit_policy;
+        policy.evicts()
+            && self.evict_by(
+                protected,
+                |entry, now| policy.score(entry, now),
+                |_, evicted| evicted >= count,
+            )
+    }
+
+    /// Evicts until `done(bytes freed, keys evicted)` holds. Each round samples
+    /// `EVICTION_SAMPLES` keys, walking shards from a random start, and evicts the
+    /// lowest scoring of the lowest priority, skipping pinned and `protected` keys and
+    /// those `score` passes on.
+    fn evict_by(
+        &self,
+        protected: &[&str],
+        score: impl Fn(&Entry, Instant) -> Option<u64>,
+        done: impl Fn(usize, usize) -> bool,
+    ) -> bool {
+        const MAX_EMPTY_ROUNDS: usize = 32;
+
+        let (mut freed, mut evicted) = (0, 0);
+        let mut empty_rounds = 0;
+        while !done(freed, evicted) && empty_rounds < MAX_EMPTY_ROUNDS {
+            let shards = self.data.shard_count();
+            let start = rand::random_range(0..shards);
+            let now = Instant::now();
+            let mut sampled = 0;
+            let mut victim: Option<((Priority, u64), String)> = None;
+            for offset in 0..shards {
+                if sampled >= EVICTION_SAMPLES {
+                    break;
+                }
+                self.data.read_shard((start + offset) % shards, |entries| {
+                    let take = entries.len().min(EVICTION_SAMPLES - sampled);
+                    let skip = rand::random_range(0..=entries.len() - take);
+                    for (key, entry) in entries.skip(skip).take(take) {
+                        sampled += 1;
+                        if entry.pinned || protected.contains(&key.as_str()) {
+                            continue;
+                        }
+                        let Some(score) = score(entry, now).map(|score| (entry.priority, score))
+                        else {
+                            continue;
+                        };
+                        if victim.as_ref().is_none_or(|(best, _)| score < *best) {
+                            victim = Some((score, key.clone()));
+                        }
+                    }
+                });
+            } // shard locks released as each is visited
+
+            let Some((_, key)) = victim else {
+                empty_rounds += 1;
+                continue;
+            };
+            if let Some(bytes) = self.remove_entry(&key) {
+                freed += bytes;
+                evicted += 1;
+                self.stats.evicted_keys.fetch_add(1, Ordering::Relaxed);
+                debug!("Evicted key {} ({} bytes)", key, bytes);
+                self.events.publish(CacheEvent::Evicted { key });
+            }
+        }
+        done(freed, evicted)
+    }
+
+    /// Whether a live key is exempt from eviction
+    pub fn is_pinned(&self, key: &str) -> bool {
+        self.is_live(key) && self.data.get(key).is_some_and(|entry| entry.pinned)
+    }
+
+    /// Shrinks stored values to their length, since memory is counted by capacity and
+    /// values built in or grown from oversized buffers hold more than they use.
+    /// Returns the bytes released.
+    pub fn purge_memory(&self) -> usize {
+        let mut purged = 0;
+        for mut item in self.data.iter_mut() {
+            let entry = item.value_mut();
+            let before = entry.memory_usage();
+            entry.value.shrink_to_fit();
+            if let Some(field_expiry) = &mut entry.field_expiry {
+                field_expiry.shrink_to_fit();
+            }
+            let after = entry.memory_usage();
+            let pinned = entry.pinned;
+            if after < before {
+                self.adjust_memory(item.key(), after, before);
+                self.adjust_pinned(pinned, after, before);
+                purged += before - after;
+            }
+        }
+        self.stats
+            .purged_memory
+            .fetch_add(purged as u64, Ordering::Relaxed);
+        purged
+    }
+
+    /// Purges memory every `period`; see `Config::compaction_interval`
+    pub async fn run_compaction(self: Arc<Self>, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let purged = self.purge_memory();
+            if purged > 0 {
+                debug!("Compaction released {} bytes", purged);
+            }
+        }
+    }
+
+    /// Cleans up expired keys every `period`; see `Config::ttl_cleanup_interval`. Runs
+    /// more often while the sampling pass keeps finding expired keys.
+    pub async fn run_expiration(self: Arc<Self>, period: Duration) {
+        let mut delay = period;
+        loop {
+            tokio::time::sleep(delay).await;
+            let (samples, expired) = (self.sampler.samples(), self.sampler.expired());
+            let removed = self.cleanup_expired();
+            if removed > 0 {
+                debug!("Expiration removed {} keys", removed);
+            }
+            delay = cleanup_delay(
+                period,
+                self.sampler.samples() - samples,
+                self.sampler.expired() - expired,
+            );
+            if self.data.is_migrating() {
+                delay = delay.min(MIGRATE_PERIOD);
+            }
+        }
+    }
+
+    /// Priority class of a live key; Normal for a missing one
+    pub fn priority(&self, key: &str) -> Priority {
+        match self.is_live(key) {
+            true => self
+                .data
+                .get(key)
+                .map_or(Priority::Normal, |entry| entry.priority),
+            false => Priority::Normal,
+        }
+    }
+
+    /// Applies a change in a pinned key's footprint to the pinned total
+    fn adjust_pinned(&self, pinned: bool, added: usize, removed: usize) {
+        if pinned {
+            self.stats.pinned_memory.fetch_add(added, Ordering::Relaxed);
+            self.stats
+                .pinned_memory
+                .fetch_sub(removed, Ordering::Relaxed);
+        }
+    }
+
+    /// Applies a change in a key's memory footprint to the global and namespace gauges
+    fn adjust_memory(&self, key: &str, added: usize, removed: usize) {
+        let start = self.contention.as_ref().map(|_| Instant::now());
+        self.stats.memory_usage.fetch_add(added, Ordering::Relaxed);
+        self.stats
+            .memory_usage
+            .fetch_sub(removed, Ordering::Relaxed);
+        self.with_namespace_stats(key, |ns| {
+            ns.memory_usage.fetch_add(added, Ordering::Relaxed);
+            ns.memory_usage.fetch_sub(removed, Ordering::Relaxed)
+        });
+        if let (Some(tracker), Some(start)) = (&self.contention, start) {
+            tracker.record_accounting(start.elapsed());
+        }
+    }
+
+    /// With contention tracking on, counts an operation on the key's shard and times
+    /// any wait for its lock. The lock is only probed, then released for the operation.
+    fn track_shard(&self, key: &str, write: bool) {
+        let Some(tracker) = &self.contention else {
+            return;
+        };
+        let index = self.data.shard_of(key);
+        tracker.acquire_shard(
+            index,
+            || self.data.try_lock_shard(index, write),
+            || self.data.lock_shard(index, write),
+        );
+    }
+
+    /// Takes the dependency lock for writing, timing the wait when tracking contention
+    fn dependency_write(&self) -> RwLockWriteGuard<'_, ()> {
+        let lock = || self.dependency_lock.write().unwrap();
+        match &self.contention {
+            Some(tracker) => tracker
+                .dependency_lock
+                .acquire(|| self.dependency_lock.try_write().ok(), lock),
+            None => lock(),
+        }
+    }
+
+    fn dependency_read(&self) -> RwLockReadGuard<'_, ()> {
+        let lock = || self.dependency_lock.read().unwrap();
+        match &self.contention {
+            Some(tracker) => tracker
+                .dependency_lock
+                .acquire(|| self.dependency_lock.try_read().ok(), lock),
+            None => lock(),
+        }
+    }
+
+    /// Where operations have waited, if `Config::track_contention` is on
+    pub fn contention_report(&self) -> Option<ContentionReport> {
+        self.contention.as_ref().map(|tracker| tracker.report())
+    }
+
+    /// Runs `f` on the key's namespace counters. Namespaces past the configured cap
+    /// share one series to bound label cardinality.
+    fn with_namespace_stats<R>(&self, key: &str, f: impl FnOnce(&NamespaceStats) -> R) {
+        let max = self.config.max_metric_namespaces;
+        if max == 0 {
+            return;
+        }
+
+        let namespace = namespace_of(key, self.config.namespace_delimiter).unwrap_or(NO_NAMESPACE);
+        if let Some(stats) = self.stats.namespaces.get(namespace) {
+            f(&stats);
+            return;
+        }
+
+        let namespace = if self.stats.namespaces.len() >= max {
+            OTHER_NAMESPACE
+        } else {
+            namespace
+        };
+        f(&self
+            .stats
+            .namespaces
+            .entry(namespace.to_string())
+            .or_default());
+    }
+
+    /// Whether storing `memory_delta` more bytes under `key` stays within the configured limits
+    fn check_limits(&self, key: &str, memory_delta: usize) -> Result<(), CacheError> {
+        self.check_limits_for(key, &[], memory_delta)
+    }
+
+    /// `check_limits` for a key about to be written under `parents`. Under an evicting
+    /// policy, room is made by evicting other keys; the key and its parents are kept.
+    fn check_limits_for(
+        &self,
+        key: &str,
+        parents: &[String],
+        memory_delta: usize,
+    ) -> Result<(), CacheError> {
+        let protected: Vec<&str> = std::iter::once(key)
+            .chain(parents.iter().map(String::as_str))
+            .collect();
+        if let Some(max_memory) = self.config.max_memory {
+            let current_memory = self.memory_usage();
+            if current_memory + memory_delta > max_memory {
+                let needed = current_memory + memory_delta - max_memory;
+                if !self.evict(needed, &protected) {
+                    return Err(CacheError::MemoryLimitExceeded);
+                }
+            }
+        }
+
+        if let Some(max_keys) = self.config.max_keys
+            && self.data.len() >= max_keys
+            && !self.data.contains_key(key)
+        {
+            let excess = self.data.len() + 1 - max_keys;
+            if !self.evict_keys(excess, &protected) {
+                return Err(CacheError::KeyLimitExceeded);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct ChildrenLevels<'a> {
+    cache: &'a Cache,
+    parents: HashSet<EntryId>,
+    seen: HashSet<EntryId>,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl ChildrenLevels<'_> {
+    fn is_done(&self) -> bool {
+        self.parents.is_empty() || self.depth >= self.max_depth
+    }
+}
+
+impl Iterator for ChildrenLevels<'_> {
+    type Item = Vec<(String, u64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_done() {
+            return None;
+        }
+        self.depth += 1;
+
+        let mut level: Vec<(String, EntryId)> = self
+            .parents
+            .iter()
+            .flat_map(|&parent| self.cache.children_of(parent))
+            .collect();
+        level.retain(|(_, id)| self.seen.insert(*id));
+        level.sort();
+
+        let depth = self.depth as u64;
+        self.parents = level.iter().map(|(_, id)| *id).collect();
+        Some(level.into_iter().map(|(child, _)| (child, depth)).collect())
+    }
+}
+
+fn drop_expired_fields(
+    hash: &mut HashMap<String, Value>,
+    expiry: &mut HashMap<String, Instant>,
+    now: Instant,
+) {
+    expiry.retain(|field, expires_at| {
+        let live = *expires_at > now;
+        if !live {
+            hash.remove(field);
+        }
+        live
+    });
+}
+
+pub(crate) fn matches_pattern(key: &str, pattern: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+
+    if let Some(prefix) = pattern.strip_suffix('*') {
+        key.starts_with(prefix)
+    } else {
+        key == pattern
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache_errors::CacheError;
+
+    use super::*;
+    use std::time::Duration;
+
+    /// Options linking a key to each of `parents`
+    fn linked(parents: &[&str]) -> SetOptions {
+        SetOptions {
+            parents: parents.iter().map(|parent| parent.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets `key` to "v" under `parents`
+    fn set_linked(cache: &Cache, key: &str, parents: &[&str]) {
+        cache
+            .set(key.into(), Value::String("v".into()), linked(parents))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_basic_operations() {
+        let cache = Cache::new(Config::default());
+
+        // Test set and get
+        cache
+            .set(
+                "key1".to_string(),
+                Value::String("value1".to_string()),
+                SetOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(cache.get("key1"), Some(Value::String("value1".to_string())));
+
+        // Test get().is_some() for existence
+        assert!(cache.get("key1").is_some());
+        assert!(cache.get("nonexistent").is_none());
+
+        // Test delete
+        cache.delete("key1");
+        assert!(cache.get("key1").is_none());
+    }
+
+    #[test]
+    fn test_ttl_expiration() {
+        let cache = Cache::new(Config::default());
+
+        // Set with a very short TTL
+        cache
+            .set(
+                "temp".to_string(),
+                Value::String("temp_value".to_string()),
+                SetOptions {
+                    ttl: Some(Duration::from_millis(1)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        // Should exist immediately
+        assert!(cache.get("temp").is_some());
+
+        // Wait for expiration
+        std::thread::sleep(Duration::from_millis(10));
+
+        // Should be expired now
+        assert!(cache.get("temp").is_none());
+    }
+
+    #[test]
+    fn test_children_counts() {
+        let cache = Cache::new(Config::default());
+        set_linked(&cache, "user", &[]);
+        set_linked(&cache, "profile", &["user"]);
+        set_linked(&cache, "settings", &["user"]);
+        set_linked(&cache, "avatar", &["profile"]);
+
+        let parents = ["user", "profile", "missing", "user"].map(String::from);
+        assert_eq!(cache.children_counts(&parents, 16), [3, 1, 0, 3]);
+        assert_eq!(cache.children_counts(&parents, 1), [2, 1, 0, 2]);
+        assert_eq!(
+            cache.children_counts(&["user".into()], 16)[0],
+            cache.children_recursive("user", 16).len()
+        );
+    }
+
+    #[test]
+    fn test_dependencies() {
+        // Dependencies must be enabled in the config
+        let config = Config {
+            enable_dependencies: true,
+            ..Default::default()
+        };
+        let cache = Cache::new(config);
+
+        // Set parent
+        cache
+            .set(
+                "parent".to_string(),
+                Value::String("parent_value".to_string()),
+                SetOptions::default(),
+            )
+            .unwrap();
+
+        // Set child with dependency
+        cache
+            .set(
+                "child".to_string(),
+                Value::String("child_value".to_string()),
+                SetOptions {
+                    parents: vec!["parent".to_string()],
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        // Both should exist
+        assert!(cache.get("parent").is_some());
+        assert!(cache.get("child").is_some());
+
+        // Delete parent
+        cache.delete("parent");
+
+        // Child should now be invalid due to missing parent
+        assert!(cache.get("child").is_none());
+    }
+
+    #[test]
+    fn test_cycle_detection() {
+        let config = Config {
+            enable_dependencies: true,
+            ..Default::default()
+        };
+        let cache = Cache::new(config);
+
+        cache
+            .set(
+                "a".to_string(),
+                Value::String("a".to_string()),
+                SetOptions::default(),
+            )
+            .unwrap();
+        cache
+            .set(
+                "b".to_string(),
+                Value::String("b".to_string()),
+                SetOptions::default(),
+            )
+            .unwrap();
+
+        // a -> b
+        cache
+            .set(
+                "a".to_string(),
+                Value::String("a2".to_string()),
+                SetOptions {
+                    parents: vec!["b".to_string()],
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        // b -> a should fail (would create cycle)
+        let result = cache.set(
+            "b".to_string(),
+            Value::String("b2".to_string()),
+            SetOptions {
+                parents: vec!["a".to_string()],
+                ..Default::default()
+            },
+        );
+
+        // Assert that we get the specific cycle error
+        assert!(matches!(result, Err(CacheError::DependencyCycle(..))));
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let config = Config {
+            max_memory: Some(512),
+            ..Default::default()
+        };
+        let cache = Cache::new(config.clone());
+
+        let key = "small".to_string();
+        let value = Value::String("ok".to_string());
+
+        let entry = Entry::new(value.clone());
+        let expected_size = key.len() + entry.memory_usage();
+
+        let result = cache.set(key, value, SetOptions::default());
+
+        if let Err(e) = result {
+            panic!(
+                "Setting a small key failed unexpectedly! Error: {:?}. Expected size: {}, Max memory: {}",
+                e,
+                expected_size,
+                config.max_memory.unwrap()
+            );
+        }
+
+        let large_value = Value::String("x".repeat(200));
+        let result = cache.set("large".to_string(), large_value, SetOptions::default());
+
+        assert!(matches!(result, Err(CacheError::MemoryLimitExceeded)));
+    }
+
+    #[test]
+    fn test_cleanup_delay() {
+        let period = Duration::from_secs(60);
+        assert_eq!(cleanup_delay(period, 0, 0), period);
+        assert_eq!(cleanup_delay(period, 20, 2), period);
+        assert_eq!(cleanup_delay(period, 20, 5), Duration::from_secs(15));
+    }
+
+    #[test]
+    fn test_sampling_repeats_while_keys_are_stale() {
+        let cache = Cache::new(Config {
+            shard_amount: Some(2),
+            ..Default::default()
+        });
+        let expiring = SetOptions {
+            ttl: Some(Duration::from_millis(1)),
+            ..Default::default()
+        };
+        for i in 0..200 {
+            cache
+                .set(
+                    format!("k{}", i),
+                    Value::String("v".to_string()),
+                    expiring.clone(),
+                )
+                .unwrap();
+        }
+        // Leave the sampling pass to find them
+        cache.expiry_index.clear();
+        std::thread::sleep(Duration::from_millis(10));
+
+        // One pass samples at most 20 keys
+        assert!(cache.sample_expired() > 20);
+    }
+
+    #[test]
+    fn test_orphan_scavenging() {
+        let cache = Cache::new(Config {
+            shard_amount: Some(2),
+            ..Default::default()
+        });
+        set_linked(&cache, "parent", &[]);
+        set_linked(&cache, "other", &[]);
+        for i in 0..50 {
+            set_linked(&cache, &format!("child{}", i), &["parent"]);
+        }
+        set_linked(&cache, "kept", &["other"]);
+        cache.del(&["parent"]);
+        assert_eq!(cache.len(), 52);
+        let mut events = cache.subscribe();
+
+        // Children of the deleted parent go without being read
+        for _ in 0..100 {
+            if cache.len() == 2 {
+                break;
+            }
+            cache.sample_expired();
+        }
+        assert_eq!(cache.len(), 2);
+        assert!(cache.exists("kept"));
+        assert_eq!(cache.stats().orphaned_keys.load(Ordering::Relaxed), 50);
+        let mut invalidated = 0;
+        while let Ok(event) = events.try_recv() {
+            assert!(
+                matches!(event, CacheEvent::Invalidated { key, .. } if key.starts_with("child"))
+            );
+            invalidated += 1;
+        }
+        assert_eq!(invalidated, 50);
+
+        // A read finding a broken chain removes the key the same way, naming the
+        // parent that broke it
+        let short = SetOptions {
+            ttl: Some(Duration::from_millis(1)),
+            ..Default::default()
+        };
+        cache
+            .set("root".into(), Value::String("v".into()), short)
+            .unwrap();
+        set_linked(&cache, "mid", &["root"]);
+        set_linked(&cache, "leaf", &["mid"]);
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(cache.get("leaf").is_none());
+        assert!(matches!(
+            events.try_recv(),
+            Ok(CacheEvent::Invalidated { key, cause }) if key == "leaf" && cause == "mid"
+        ));
+        assert!(cache.data.contains_key("mid"));
+        assert_eq!(cache.stats().orphaned_keys.load(Ordering::Relaxed), 51);
+    }
+
+    #[test]
+    fn test_cleanup_expired() {
+        let cache = Cache::new(Config::default());
+
+        // Create enough entries to populate multiple shards across CPU cores
+        let num_cores = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(4); // fallback to 4 if detection fails
+
+        let entries_per_core = 5;
+        let total_expired = num_cores * entries_per_core;
+
+        // Add expired entries distributed across shards
+        for i in 0..total_expired {
+            cache
+                .set(
+                    format!("exp_{}", i),
+                    Value::String(i.to_string()),
+                    SetOptions {
+                        ttl: Some(Duration::from_millis(1)),
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+        }
+
+        // Add some persistent entries
+        for i in 0..5 {
+            cache
+                .set(
+                    format!("keep_{}", i),
+                    Value::String(format!("keep_{}", i)),
+                    SetOptions::default(),
+                )
+                .unwrap();
+        }
+
+        let initial_count = cache.len();
+        assert_eq!(initial_count, total_expired + 5);
+
+        // Wait for expiration
+        std::thread::sleep(Duration::from_millis(10));
+
+        // Run cleanup enough times to hit all shards multiple times
+        let shard_count = cache.shard_count();
+        let cleanup_rounds = shard_count * 5;
+
+        let mut cleaned_count = 0;
+        for _ in 0..cleanup_rounds {
+            cleaned_count += cache.cleanup_expired();
+        }
+
+        // Verify cleanup worked
+        assert!(
+            cleaned_count > 0,
+            "Should have cleaned some expired entries"
+        );
+        assert_eq!(cache.len(), 5, "Should only have persistent entries left");
+
+        // Verify specific entries
+        for i in 0..5 {
+            assert!(
+                cache.get(&format!("keep_{}", i)).is_some(),
+                "Persistent entry should exist"
+            );
+        }
+
+        // Verify expired entries are gone (test a few samples)
+        for i in 0..std::cmp::min(10, total_expired) {
+            assert!(
+                cache.get(&format!("exp_{}", i)).is_none(),
+                "Expired entry should be gone"
+            );
+        }
+    }
+
+    #[test]
+    fn test_complex_cycle_detection() {
+        let config = Config {
+            enable_dependencies: true,
+            ..Default::default()
+        };
+        let cache = Cache::new(config);
+
+        // Set up a complex chain: a -> b -> c -> d
+        cache
+            .set(
+                "d".to_string(),
+                Value::String("d".to_string()),
+                SetOptions::default(),
+            )
+            .unwrap();
+        cache
+            .set(
+                "c".to_string(),
+                Value::String("c".to_string()),
+                SetOptions {
+                    parents: vec!["d".to_string()],
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        cache
+            .set(
+                "b".to_string(),
+                Value::String("b".to_string()),
+                SetOptions {
+                    parents: vec!["c".to_string()],
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        cache
+            .set(
+                "a".to_string(),
+                Value::String("a".to_string()),
+                SetOptions {
+                    parents: vec!["b".to_string()],
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        // Now try to create a cycle: d -> a (should fail)
+        let result = cache.set(
+            "d".to_string(),
+            Value::String("d2".to_string()),
+            SetOptions {
+                parents: vec!["a".to_string()],
+                ..Default::default()
+            },
+        );
+        assert!(matches!(result, Err(CacheError::DependencyCycle(..))));
+
+        // Try intermediate cycle: c -> a (should also fail)
+        let result = cache.set(
+            "c".to_string(),
+            Value::String("c2".to_string()),
+            SetOptions {
+                parents: vec!["a".to_string()],
+                ..Default::default()
+            },
+        );
+        assert!(matches!(result, Err(CacheError::DependencyCycle(..))));
+    }
+
+    #[test]
+    fn test_expiry_index() {
+        let cache = Cache::new(Config::default());
+
+        for (key, ttl) in [("soon", 1), ("later", 3600)] {
+            cache
+                .set(
+                    key.to_string(),
+                    Value::String(key.to_string()),
+                    SetOptions {
+                        ttl: Some(Duration::from_secs(ttl)),
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+        }
+        cache
+            .set(
+                "forever".to_string(),
+                Value::String("forever".to_string()),
+                SetOptions::default(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            cache.expiring_within(Duration::from_secs(60), usize::MAX),
+            vec!["soon".to_string()]
+        );
+
+        // Moving the TTL out re-buckets the key
+        cache.expire("soon", 7200);
+        assert!(
+            cache
+                .expiring_within(Duration::from_secs(60), usize::MAX)
+                .is_empty()
+        );
+
+        cache.expire("soon", 0);
+        assert_eq!(cache.expire_due(), 1);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_expire_at() {
+        let cache = Cache::new(Config::default());
+        for key in ["future", "past"] {
+            cache
+                .set(
+                    key.to_string(),
+                    Value::String(key.to_string()),
+                    SetOptions::default(),
+                )
+                .unwrap();
+        }
+
+        let in_an_hour = SystemTime::now() + Duration::from_secs(3600);
+        assert_eq!(cache.expire_at("future", in_an_hour), 1);
+        assert!((3598..=3600).contains(&cache.ttl("future")));
+
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        assert_eq!(cache.expire_at("past", an_hour_ago), 1);
+        assert!(cache.get("past").is_none());
+
+        assert_eq!(cache.expire_at("missing", in_an_hour), 0);
+    }
+
+    #[test]
+    fn test_millisecond_ttls() {
+        let cache = Cache::new(Config::default());
+        cache
+            .set(
+                "k".to_string(),
+                Value::String("v".to_string()),
+                SetOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(cache.pttl("k"), -1);
+        assert_eq!(cache.expire_time("k"), -1);
+        assert_eq!(cache.pttl("missing"), -2);
+        assert_eq!(cache.expire_time("missing"), -2);
+
+        assert_eq!(cache.pexpire("k", 1500), 1);
+        assert!((1400..=1500).contains(&cache.pttl("k")));
+        assert_eq!(cache.ttl("k"), 1);
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let at = cache.expire_time("k") - now.as_millis() as i64;
+        assert!((1400..=1600).contains(&at));
+
+        assert_eq!(cache.pexpire("k", 0), 1);
+        assert!(cache.get("k").is_none());
+    }
+
+    #[test]
+    fn test_namespace_ttl_policies() {
+        let config = Config {
+            ttl_policy: TtlPolicy {
+                max: Some(Duration::from_secs(3600)),
+                ..Default::default()
+            },
+            namespace_ttl_policies: HashMap::from([(
+                "session".to_string(),
+                TtlPolicy {
+                    default: Some(Duration::from_secs(60)),
+                    min: Some(Duration::from_secs(30)),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let cache = Cache::new(config);
+
+        let set = |key: &str, ttl: Option<u64>| {
+            cache
+                .set(
+                    key.to_string(),
+                    Value::String("v".to_string()),
+                    SetOptions {
+                        ttl: ttl.map(Duration::from_secs),
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+            cache.ttl(key)
+        };
+
+        // Namespace default, min, and the inherited global max
+        assert!((59..=60).contains(&set("session:a", None)));
+        assert!((29..=30).contains(&set("session:b", Some(1))));
+        assert!((3599..=3600).contains(&set("session:c", Some(86400))));
+
+        // Global policy outside the namespace
+        assert!((3599..=3600).contains(&set("other", None)));
+        assert!((9..=10).contains(&set("other:x", Some(10))));
+    }
+
+    #[test]
+    fn test_max_ttl_clamps_expire() {
+        let cache = Cache::new(Config {
+            ttl_policy: TtlPolicy {
+                max: Some(Duration::from_secs(3600)),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        cache
+            .set(
+                "k".to_string(),
+                Value::String("v".to_string()),
+                SetOptions::default(),
+            )
+            .unwrap();
+        assert!((3599..=3600).contains(&cache.ttl("k")));
+
+        cache.expire("k", 86400);
+        assert!((3599..=3600).contains(&cache.ttl("k")));
+        cache.expire("k", 60);
+        assert!((59..=60).contains(&cache.ttl("k")));
+
+        // PERSIST can't make the key outlive the max, and leaves its TTL alone
+        assert_eq!(cache.persist("k"), 0);
+        assert!((59..=60).contains(&cache.ttl("k")));
+    }
+
+    #[test]
+    fn test_min_ttl_bounds_expire() {
+        let cache = Cache::new(Config {
+            namespace_ttl_policies: HashMap::from([(
+                "session".to_string(),
+                TtlPolicy {
+                    min: Some(Duration::from_secs(30)),
+                    jitter: Some(50),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        });
+        for key in ["session:a", "other"] {
+            cache
+                .set(key.into(), Value::String("v".into()), SetOptions::default())
+                .unwrap();
+            cache.expire(key, 5);
+        }
+
+        // EXPIRE is raised to the namespace min, without jitter
+        assert!((29..=30).contains(&cache.ttl("session:a")));
+        assert!((4..=5).contains(&cache.ttl("other")));
+        cache.expire("session:a", 100);
+        assert!((99..=100).contains(&cache.ttl("session:a")));
+        assert_eq!(cache.persist("session:a"), 1);
+        assert_eq!(cache.ttl("session:a"), -1);
+    }
+
+    #[test]
+    fn test_ttl_jitter() {
+        let cache = Cache::new(Config {
+            ttl_policy: TtlPolicy {
+                jitter: Some(10),
+                max: Some(Duration::from_secs(1050)),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let set = |key: String, jitter: Option<u32>| {
+            let options = SetOptions {
+                ttl: Some(Duration::from_secs(1000)),
+                jitter,
+                ..Default::default()
+            };
+            cache
+                .set(key.clone(), Value::String("v".to_string()), options)
+                .unwrap();
+            cache.ttl(&key)
+        };
+
+        // Spread within 10% either way, still bounded by the max
+        let ttls: HashSet<i64> = (0..50).map(|i| set(format!("k{i}"), None)).collect();
+        assert!(ttls.iter().all(|ttl| (899..=1050).contains(ttl)));
+        assert!(ttls.len() > 1);
+
+        // A write can turn it off
+        assert!((999..=1000).contains(&set("exact".to_string(), Some(0))));
+    }
+
+    #[test]
+    fn test_shard_amount() {
+        let cache = Cache::new(Config {
+            shard_amount: Some(100),
+            ..Default::default()
+        });
+        assert_eq!(cache.shard_count(), 128);
+    }
+
+    #[test]
+    fn test_grow_shards() {
+        let cache = Cache::new(Config {
+            shard_amount: Some(4),
+            shard_growth_keys: Some(8),
+            ..Default::default()
+        });
+        let set = |key: &str| {
+            cache
+                .set(
+                    key.to_string(),
+                    Value::String("v".to_string()),
+                    SetOptions::default(),
+                )
+                .unwrap();
+        };
+        for i in 0..100 {
+            set(&format!("k{}", i));
+        }
+
+        // Cleanup grows past 8 keys a shard and moves every key within its budget
+        cache.cleanup_expired();
+        assert!(!cache.is_migrating());
+        assert_eq!((cache.shard_count(), cache.retired_shards()), (20, 4));
+        assert_eq!(cache.len(), 100);
+        assert!((0..100).all(|i| cache.get(&format!("k{}", i)).is_some()));
+
+        assert!(!cache.grow_shards(8), "must add shards");
+        assert!(cache.grow_shards(64));
+        assert!(cache.is_migrating());
+        assert!(!cache.grow_shards(256), "refused while migrating");
+        set("new");
+        cache.del(&["k0"]);
+        assert_eq!(cache.len(), 100);
+        assert!(cache.exists("k1") && cache.exists("new"));
+
+        // A scan spanning the migration still sees every key
+        let (mut cursor, mut seen) = (0, HashSet::new());
+        loop {
+            let (next, keys) = cache.scan(cursor, None, 10);
+            seen.extend(keys);
+            cache.migrate_shards();
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        assert!(!cache.is_migrating());
+        assert_eq!(seen.len(), 100);
+    }
+
+    #[test]
+    fn test_budgeted_scans_resume() {
+        let cache = Cache::new(Config {
+            shard_amount: Some(4),
+            ..Default::default()
+        });
+        for i in 0..50 {
+            set_linked(&cache, &format!("k{}", i), &[]);
+        }
+        let spent = Deadline::after(Some(Duration::ZERO));
+
+        // Each page covers one shard, and the pages together cover every key
+        let (mut keys, mut cursor) = (Vec::new(), Some(0));
+        let mut pages = 0;
+        while let Some(shard) = cursor {
+            let (page, next) = cache.keys_page("k*", usize::MAX, shard, spent);
+            keys.extend(page);
+            cursor = next;
+            pages += 1;
+        }
+        assert_eq!(pages, cache.shard_count());
+        assert_eq!(keys.len(), 50);
+        let (all, cursor) = cache.keys_page("k*", usize::MAX, 0, Deadline::default());
+        assert_eq!((all.len(), cursor), (50, None));
+
+        // root <- a <- b, one level per page
+        set_linked(&cache, "a", &["k0"]);
+        set_linked(&cache, "b", &["a"]);
+        let page = |offset| cache.children_page_until("k0", 8, offset, 100, spent);
+        assert_eq!(page(0), (vec![("a".to_string(), 1)], Some(1)));
+        assert_eq!(page(1), (vec![("b".to_string(), 2)], Some(2)));
+        assert_eq!(page(2), (vec![], None));
+    }
+
+    #[test]
+    fn test_dry_run_reports_without_mutating() {
+        let cache = Cache::new(Config::default());
+        set_linked(&cache, "root", &[]);
+        set_linked(&cache, "a", &["root"]);
+        set_linked(&cache, "b", &["a"]);
+        set_linked(&cache, "other", &[]);
+        let before = cache.memory_usage();
+
+        let report = cache.dry_run(cache.invalidation_keys("root"));
+        assert_eq!(report.keys, ["a", "b", "root"]);
+        assert_eq!(report.count, 3);
+        assert_eq!(cache.len(), 4);
+
+        let report = cache.dry_run(["other".to_string(), "missing".to_string()]);
+        assert_eq!(report.keys, ["other"]);
+        assert_eq!(cache.memory_usage(), before);
+        cache.del(&["other"]);
+        assert_eq!(before - cache.memory_usage(), report.bytes);
+    }
+
+    #[test]
+    fn test_parent_link_ttl() {
+        let cache = Cache::new(Config {
+            expire_children: true,
+            ..Default::default()
+        });
+        let set = |key: &str, options: SetOptions| {
+            cache
+                .set(key.to_string(), Value::String(key.to_string()), options)
+                .unwrap()
+        };
+        set(
+            "source",
+            SetOptions {
+                ttl: Some(Duration::from_millis(100)),
+                ..Default::default()
+            },
+        );
+        set(
+            "derived",
+            SetOptions {
+                parents: vec!["source".to_string()],
+                parent_ttl: Some(Duration::from_millis(50)),
+                ..Default::default()
+            },
+        );
+        assert_eq!(cache.parent("derived").as_deref(), Some("source"));
+        assert!(cache.parent_ttl("derived").unwrap() <= Duration::from_millis(50));
+        assert_eq!(cache.children_recursive("source", 1).len(), 1);
+
+        // The lapsed link leaves the key independent, so the source expiring later
+        // doesn't take it along
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(cache.parent("derived"), None);
+        assert_eq!(cache.parent_ttl("derived"), None);
+        assert!(cache.children_recursive("source", 1).is_empty());
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(cache.get("source").is_none());
+        assert!(cache.get("derived").is_some());
+
+        // Relinking without a TTL makes the link permanent again
+        set("source", SetOptions::default());
+        cache.set_parent("derived", "source".to_string()).unwrap();
+        assert_eq!(cache.parent_ttl("derived"), None);
+        assert_eq!(cache.parent("derived").as_deref(), Some("source"));
+    }
+
+    #[test]
+    fn test_expiry_cascades_to_children() {
+        let cache = Cache::new(Config {
+            expire_children: true,
+            ..Default::default()
+        });
+        let mut events = cache.subscribe();
+
+        cache
+            .set(
+                "parent".to_string(),
+                Value::String("p".to_string()),
+                SetOptions::default(),
+            )
+            .unwrap();
+        for (key, parent) in [("child", "parent"), ("grandchild", "child")] {
+            cache
+                .set(
+                    key.to_string(),
+                    Value::String(key.to_string()),
+                    SetOptions {
+                        parents: vec![parent.to_string()],
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+        }
+
+        cache.expire("parent", 0);
+        assert_eq!(cache.expire_due(), 3);
+        assert!(cache.is_empty());
+
+        assert_eq!(
+            events.try_recv().unwrap(),
+            CacheEvent::Expired {
+                key: "parent".to_string()
+            }
+        );
+        let mut invalidated: Vec<_> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+        invalidated.sort_by_key(|event| format!("{:?}", event));
+        assert_eq!(
+            invalidated,
+            vec![
+                CacheEvent::Invalidated {
+                    key: "child".to_string(),
+                    cause: "parent".to_string()
+                },
+                CacheEvent::Invalidated {
+                    key: "grandchild".to_string(),
+                    cause: "parent".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unset_parent_and_reparent() {
+        let cache = Cache::new(Config::default());
+        let set = |key: &str, parent: Option<&str>| {
+            cache
+                .set(
+                    key.to_string(),
+                    Value::String(key.to_string()),
+                    linked(parent.as_slice()),
+                )
+                .unwrap();
+        };
+
+        set("a", None);
+        set("b", None);
+        set("a1", Some("a"));
+        set("a2", Some("a"));
+        set("a1x", Some("a1"));
+
+        // Moving a's children under its own grandchild would create a cycle
+        assert!(matches!(
+            cache.reparent_children("a", "a1x"),
+            Err(CacheError::DependencyCycle(..))
+        ));
+        assert_eq!(cache.parent("a2"), Some("a".to_string()));
+
+        assert_eq!(cache.reparent_children("a", "b").unwrap(), 2);
+        assert_eq!(cache.parent("a1"), Some("b".to_string()));
+        assert_eq!(cache.parent("a2"), Some("b".to_string()));
+
+        assert_eq!(cache.unset_parent("a1"), 1);
+        assert_eq!(cache.unset_parent("a1"), 0);
+        cache.delete("b");
+        assert!(cache.get("a1").is_some());
+        assert!(cache.get("a1x").is_some());
+        assert!(cache.get("a2").is_none());
+    }
+
+    #[test]
+    fn test_streaming_walks() {
+        let cache = Cache::new(Config::default());
+        for i in 0..50 {
+            let key = format!("key{i}");
+            cache
+                .set(key, Value::String("v".into()), SetOptions::default())
+                .unwrap();
+        }
+        cache
+            .set(
+                "other".into(),
+                Value::String("v".into()),
+                SetOptions::default(),
+            )
+            .unwrap();
+
+        let mut keys: Vec<String> = (0..cache.shard_count())
+            .flat_map(|shard| cache.shard_keys(shard, "key*"))
+            .collect();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), 50);
+
+        let child = |parent: &str| SetOptions {
+            parents: vec![parent.to_string()],
+            ..Default::default()
+        };
+        cache
+            .set("a".into(), Value::String("v".into()), child("other"))
+            .unwrap();
+        cache
+            .set("b".into(), Value::String("v".into()), child("a"))
+            .unwrap();
+
+        let levels: Vec<_> = cache.children_levels("other", 5).collect();
+        assert_eq!(levels[0], vec![("a".to_string(), 1)]);
+        assert_eq!(levels[1], vec![("b".to_string(), 2)]);
+        assert!(levels.iter().skip(2).all(Vec::is_empty));
+    }
+
+    #[test]
+    fn test_rename_keeps_dependents() {
+        let cache = Cache::new(Config::default());
+        let empty = cache.memory_usage();
+        set_linked(&cache, "p", &[]);
+        set_linked(&cache, "c", &["p"]);
+        set_linked(&cache, "g", &["c"]);
+        set_linked(&cache, "x", &[]);
+        set_linked(&cache, "y", &["x"]);
+
+        cache.rename("p", "q").unwrap();
+        assert!(cache.get("p").is_none());
+        assert_eq!(cache.parent("c").as_deref(), Some("q"));
+        assert_eq!(cache.children_recursive("q", 5).len(), 2);
+        assert!(cache.get("g").is_some());
+        assert!(matches!(cache.rename("p", "q"), Err(CacheError::NoSuchKey)));
+
+        // Overwriting keeps the entry; replacing it by rename or delete doesn't
+        set_linked(&cache, "q", &[]);
+        assert_eq!(cache.parent("c").as_deref(), Some("q"));
+        cache.rename("q", "x").unwrap();
+        assert_eq!(cache.parent("c").as_deref(), Some("x"));
+        assert!(cache.get("y").is_none());
+        cache.delete("x");
+        set_linked(&cache, "x", &[]);
+        assert!(cache.get("c").is_none());
+        assert_eq!(cache.dependency_graph().len(), 0);
+
+        cache.del(&["c", "g", "x", "y"]);
+        assert!(cache.is_empty());
+        assert_eq!(cache.memory_usage(), empty);
+    }
+
+    #[test]
+    fn test_flush_pattern() {
+        let cache = Cache::new(Config::default());
+        for key in ["session:1", "session:2", "sessions", "user:1"] {
+            cache
+                .set(key.into(), Value::String("v".into()), SetOptions::default())
+                .unwrap();
+        }
+
+        assert_eq!(cache.flush_pattern("session:*"), 2);
+        assert_eq!(cache.flush_pattern("session:*"), 0);
+        assert!(cache.get("sessions").is_some());
+        assert!(cache.get("user:1").is_some());
+        assert_eq!(cache.flush_pattern("*"), 2);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_incr_by_float() {
+        let cache = Cache::new(Config::default());
+        assert_eq!(cache.incr_by_float("f", 1.5).unwrap(), 1.5);
+        assert_eq!(cache.incr_by_float("f", -0.25).unwrap(), 1.25);
+        assert_eq!(cache.get("f").unwrap().to_string(), "1.25");
+        // Integers can be bumped by a fraction, but then aren't integers any more
+        cache.incr_by("n", 10, None).unwrap();
+        assert_eq!(cache.incr_by_float("n", 0.5).unwrap(), 10.5);
+        assert!(matches!(
+            cache.incr_by("n", 1, None),
+            Err(CacheError::NotAnInteger)
+        ));
+
+        for (key, value) in [("text", "abc"), ("inf", "inf")] {
+            cache
+                .set(
+                    key.into(),
+                    Value::String(value.into()),
+                    SetOptions::default(),
+                )
+                .unwrap();
+            assert!(matches!(
+                cache.incr_by_float(key, 1.0),
+                Err(CacheError::NotAFloat)
+            ));
+        }
+        assert!(matches!(
+            cache
+                .incr_by_float("f", f64::MAX)
+                .and_then(|_| cache.incr_by_float("f", f64::MAX)),
+            Err(CacheError::NotFinite)
+        ));
+    }
+
+    #[test]
+    fn test_key_memory_counts_length() {
+        let cache = Cache::new(Config::default());
+        let memory = || cache.stats().memory_usage.load(Ordering::Relaxed);
+        let before = memory();
+        let mut key = String::with_capacity(64);
+        key.push('k');
+
+        cache
+            .set(key, Value::String("v".into()), SetOptions::default())
+            .unwrap();
+        cache.incr_by("n", 1, None).unwrap();
+        cache.rename("k", "renamed").unwrap();
+        cache.del(&["renamed", "n"]);
+        assert_eq!(memory(), before);
+    }
+
+    #[test]
+    fn test_incr_by_limit() {
+        let cache = Cache::new(Config::default());
+
+        assert_eq!(
+            cache.incr_by("quota", 5, Some(10)).unwrap(),
+            IncrOutcome::Applied(5)
+        );
+        assert_eq!(
+            cache.incr_by("quota", 5, Some(10)).unwrap(),
+            IncrOutcome::Applied(10)
+        );
+        assert_eq!(
+            cache.incr_by("quota", 1, Some(10)).unwrap(),
+            IncrOutcome::Rejected(10)
+        );
+        assert_eq!(
+            cache.incr_by("quota", -3, None).unwrap(),
+            IncrOutcome::Applied(7)
+        );
+        assert_eq!(cache.get("quota").unwrap().to_string(), "7");
+        assert_eq!(
+            cache.incr_by("fresh", 11, Some(10)).unwrap(),
+            IncrOutcome::Rejected(0)
+        );
+        assert!(!cache.exists("fresh"));
+
+        cache
+            .set(
+                "text".into(),
+                Value::String("abc".into()),
+                SetOptions::default(),
+            )
+            .unwrap();
+        assert!(matches!(
+            cache.incr_by("text", 1, None),
+            Err(CacheError::NotAnInteger)
+        ));
+        cache
+            .set(
+                "num".into(),
+                Value::String("41".into()),
+                SetOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            cache.incr_by("num", 1, None).unwrap(),
+            IncrOutcome::Applied(42)
+        );
+        assert!(matches!(
+            cache.incr_by("num", i64::MAX, None),
+            Err(CacheError::Overflow)
+        ));
+    }
+
+    #[test]
+    fn test_hash_field_expiry() {
+        let cache = Cache::new(Config::default());
+        let fields = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let hash: HashMap<String, Value> = [("a", "1"), ("b", "2"), ("c", "3")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+            .collect();
+        cache
+            .set("session".into(), Value::Hash(hash), SetOptions::default())
+            .unwrap();
+
+        let short = Duration::from_millis(50);
+        assert_eq!(
+            cache
+                .hexpire("session", short, &fields(&["a", "x"]))
+                .unwrap(),
+            vec![1, -2]
+        );
+        assert_eq!(
+            cache
+                .hexpire("session", Duration::from_secs(100), &fields(&["b"]))
+                .unwrap(),
+            vec![1]
+        );
+        assert_eq!(
+            cache.httl("session", &fields(&["b", "c"])).unwrap(),
+            vec![99, -1]
+        );
+        assert_eq!(
+            cache.hpersist("session", &fields(&["b", "c"])).unwrap(),
+            vec![1, -1]
+        );
+        assert_eq!(
+            cache
+                .hexpire("session", Duration::ZERO, &fields(&["b"]))
+                .unwrap(),
+            vec![2]
+        );
+
+        // The janitor drops the expired field through the expiry index
+        let later = || Instant::now() + Duration::from_secs(2);
+        cache.expire_due_at(later());
+        assert_eq!(
+            cache.httl("session", &fields(&["a", "c"])).unwrap(),
+            vec![-2, -1]
+        );
+        assert!(cache.exists("session"));
+
+        // Expiring the last field removes the key
+        cache.hexpire("session", short, &fields(&["c"])).unwrap();
+        cache.expire_due_at(later());
+        assert!(!cache.exists("session"));
+
+        cache
+            .set(
+                "plain".into(),
+                Value::String("v".into()),
+                SetOptions::default(),
+            )
+            .unwrap();
+        assert!(matches!(
+            cache.httl("plain", &fields(&["a"])),
+            Err(CacheError::WrongType)
+        ));
+        assert_eq!(cache.httl("missing", &fields(&["a"])).unwrap(), vec![-2]);
+    }
+
+    #[test]
+    fn test_keyspace_report() {
+        use crate::keyspace::KeyspaceReport;
+
+        let cache = Cache::new(Config::default());
+        let set = |key: &str, ttl: Option<u64>, parent: Option<&str>| {
+            let options = SetOptions {
+                ttl: ttl.map(Duration::from_secs),
+                ..linked(parent.as_slice())
+            };
+            cache
+                .set(key.into(), Value::String("v".into()), options)
+                .unwrap();
+        };
+        set("user:1", None, None);
+        set("user:2", Some(30), Some("user:1"));
+        set("user:3", Some(7200), Some("user:1"));
+        set("plain", None, None);
+        cache.incr_by("count:a", 1, None).unwrap();
+
+        let report = KeyspaceReport::collect(&cache, 1000, Duration::from_secs(1));
+        assert!(report.complete);
+        assert_eq!(report.sampled, 5);
+        assert_eq!(report.by_type["string"].keys, 4);
+        assert_eq!(report.by_type["integer"].keys, 1);
+        assert_eq!(report.ttl["none"], 3);
+        assert_eq!(report.ttl["1m"], 1);
+        assert_eq!(report.ttl["1d"], 1);
+        assert_eq!(report.fanout["10"], 1);
+        assert_eq!(report.namespaces["user"].keys, 3);
+        assert_eq!(report.namespaces["(none)"].keys, 1);
+        assert_eq!(report.big_keys.len(), 5);
+        assert!(
+            report
+                .big_keys
+                .windows(2)
+                .all(|w| w[0].memory >= w[1].memory)
+        );
+
+        let partial = KeyspaceReport::collect(&cache, 2, Duration::from_secs(1));
+        assert_eq!(partial.sampled, 2);
+        assert!(!partial.complete);
+    }
+
+    #[test]
+    fn test_key_sample() {
+        use crate::keyspace::KeySample;
+
+        let cache = Cache::new(Config::default());
+        for i in 0..100 {
+            let options = SetOptions {
+                ttl: (i % 2 == 0).then(|| Duration::from_secs(60)),
+                ..Default::default()
+            };
+            cache
+                .set(format!("k{}", i), Value::String("v".into()), options)
+                .unwrap();
+        }
+
+        let sample = KeySample::collect(&cache, 10, Duration::from_secs(1));
+        assert!(sample.complete);
+        assert_eq!(sample.scanned, 100);
+        assert_eq!(sample.keys.len(), 10);
+        let mut keys: Vec<_> = sample.keys.iter().map(|k| k.key.clone()).collect();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), 10);
+        let with_ttl = sample.keys.iter().find(|k| k.ttl_ms.is_some()).unwrap();
+        assert!(with_ttl.ttl_ms.unwrap() <= 60_000);
+        assert_eq!(with_ttl.type_name, "string");
+
+        // Every key is equally likely to be picked
+        let mut picks = std::collections::HashMap::new();
+        for _ in 0..200 {
+            for key in KeySample::collect(&cache, 10, Duration::from_secs(1)).keys {
+                *picks.entry(key.key).or_insert(0) += 1;
+            }
+        }
+        assert_eq!(picks.len(), 100);
+        assert!(picks.values().all(|&count| count <= 60));
+
+        assert_eq!(
+            KeySample::collect(&cache, 1000, Duration::from_secs(1))
+                .keys
+                .len(),
+            100
+        );
+    }
+
+    #[test]
+    fn test_cold_keys() {
+        use crate::keyspace::{ColdKeyFilter, ColdKeys};
+        let cache = Cache::new(Config::default());
+        for key in ["a", "b", "c"] {
+            cache
+                .set(key.into(), Value::String("v".into()), SetOptions::default())
+                .unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(30));
+        cache.get("b");
+
+        let filter = ColdKeyFilter {
+            idle_gt: Duration::from_millis(20),
+            freq_lt: None,
+            limit: 1,
+        };
+        let report = ColdKeys::collect(&cache, filter, Duration::from_secs(1));
+        assert!(report.complete);
+        assert_eq!((report.warm.keys, report.cold.keys), (1, 2));
+        assert_eq!(report.keys.len(), 1);
+        assert_ne!(report.keys[0].key, "b");
+        assert!(report.keys[0].idle_ms >= 20);
+
+        // Frequently read keys stay warm however long they've been idle
+        let hot = ColdKeyFilter {
+            freq_lt: Some(0),
+            ..filter
+        };
+        let report = ColdKeys::collect(&cache, hot, Duration::from_secs(1));
+        assert_eq!(report.cold.keys, 0);
+    }
+
+    #[test]
+    fn test_metric_labels_and_namespaces() {
+        let cache = Cache::new(Config {
+            metric_labels: vec![
+                ("instance".into(), "a\"1".into()),
+                ("data-center".into(), "eu".into()),
+            ],
+            max_metric_namespaces: 2,
+            ..Default::default()
+        });
+        for key in ["user:1", "plain", "order:1", "team:1"] {
+            cache
+                .set(key.into(), Value::String("v".into()), SetOptions::default())
+                .unwrap();
+        }
+        cache.get("user:1");
+        cache.get("user:2");
+        cache.delete("team:1");
+
+        let metrics = cache.stats().render();
+        assert!(metrics.contains("cache_hits_total{instance=\"a\\\"1\"} 1"));
+        assert!(
+            metrics
+                .contains("cache_namespace_hits_total{instance=\"a\\\"1\",namespace=\"user\"} 1")
+        );
+        assert!(
+            metrics
+                .contains("cache_namespace_misses_total{instance=\"a\\\"1\",namespace=\"user\"} 1")
+        );
+        assert!(!metrics.contains("namespace=\"order\""));
+        assert!(!metrics.contains("data-center"));
+
+        let other = &cache.stats().namespaces;
+        let order_memory = other
+            .get(OTHER_NAMESPACE)
+            .unwrap()
+            .memory_usage
+            .load(Ordering::Relaxed);
+        assert!(order_memory > 0);
+    }
+
+    #[test]
+    fn test_invalidate_cascade() {
+        let cache = Cache::new(Config::default());
+        set_linked(&cache, "root", &[]);
+        set_linked(&cache, "child", &["root"]);
+        set_linked(&cache, "grandchild", &["child"]);
+        set_linked(&cache, "unrelated", &[]);
+        let mut events = cache.subscribe();
+
+        assert_eq!(cache.invalidate("root", InvalidationMode::Stale), 3);
+        assert!(cache.is_stale("grandchild"));
+        assert!(!cache.is_stale("unrelated"));
+        assert_eq!(cache.get("grandchild").unwrap().to_string(), "v");
+        assert_eq!(
+            events.try_recv().unwrap(),
+            CacheEvent::Invalidated {
+                key: "root".into(),
+                cause: "root".into()
+            }
+        );
+
+        set_linked(&cache, "child", &["root"]);
+        assert!(!cache.is_stale("child"));
+
+        assert_eq!(cache.invalidate("root", InvalidationMode::Delete), 3);
+        assert!(!cache.exists("root") && !cache.exists("grandchild"));
+        assert!(cache.exists("unrelated"));
+        while events.try_recv().is_ok() {}
+        assert_eq!(cache.invalidate("root", InvalidationMode::Delete), 0);
+        assert!(events.try_recv().is_err()); // Nothing was there to invalidate
+    }
+
+    #[test]
+    fn test_overwrite_takes_dependency_lock() {
+        let cache = Cache::new(Config::default());
+        let value = || Value::String("v".into());
+        set_linked(&cache, "parent", &[]);
+        set_linked(&cache, "child", &["parent"]);
+
+        // Plain writes don't wait on the graph
+        let guard = cache.dependency_write();
+        cache.set("plain".into(), value(), linked(&[])).unwrap();
+
+        // Unlinking the child by overwriting it waits for the graph to be free
+        std::thread::scope(|scope| {
+            let writer = scope.spawn(|| cache.set("child".into(), value(), linked(&[])));
+            std::thread::sleep(Duration::from_millis(20));
+            assert_eq!(cache.parents("child"), vec!["parent".to_string()]);
+            assert_eq!(cache.children_recursive("parent", 1).len(), 1);
+            drop(guard);
+            writer.join().unwrap().unwrap();
+        });
+        assert!(cache.parents("child").is_empty());
+        assert_eq!(cache.children_recursive("parent", 1).len(), 0);
+    }
+
+    #[test]
+    fn test_children_index() {
+        let cache = Cache::new(Config::default());
+        let children = |key: &str| -> Vec<String> {
+            cache
+                .children_recursive(key, usize::MAX)
+                .into_iter()
+                .map(|(child, _)| child)
+                .collect()
+        };
+        set_linked(&cache, "a", &[]);
+        set_linked(&cache, "b", &[]);
+        set_linked(&cache, "a1", &["a"]);
+        set_linked(&cache, "a2", &["a"]);
+        set_linked(&cache, "a1x", &["a1"]);
+        assert_eq!(children("a"), ["a1", "a2", "a1x"]);
+        assert_eq!(cache.children_counts(&["a".into(), "b".into()], 1), [2, 0]);
+
+        // Overwrites, relinks and unlinks move entries between parents
+        set_linked(&cache, "a2", &["b"]);
+        cache.set_parent("a1x", "b".into()).unwrap();
+        assert_eq!(children("a"), ["a1"]);
+        assert_eq!(children("b"), ["a1x", "a2"]);
+        cache.unset_parent("a2");
+        set_linked(&cache, "a1", &[]);
+        assert!(children("a").is_empty());
+        assert_eq!(children("b"), ["a1x"]);
+
+        // Renames keep the links, and removing entries drops them from the index
+        cache.rename("b", "c").unwrap();
+        assert_eq!(children("c"), ["a1x"]);
+        cache.del(&["a", "a1", "a2", "a1x", "c"]);
+        assert!(cache.indexes.children_by_id.is_empty());
+    }
+
+    #[test]
+    fn test_multiple_parents() {
+        let cache = Cache::new(Config::default());
+        set_linked(&cache, "user", &[]);
+        set_linked(&cache, "team", &[]);
+        set_linked(&cache, "profile", &["user", "team"]);
+        set_linked(&cache, "badge", &["profile", "user"]);
+        assert_eq!(cache.parents("profile"), ["user", "team"]);
+        assert_eq!(cache.parent("profile").as_deref(), Some("user"));
+
+        // The diamond through profile still counts badge once
+        assert_eq!(cache.children_counts(&["user".into()], 2), [2]);
+
+        // Cycles are caught through any parent
+        assert!(matches!(
+            cache.add_parent("team", "badge".into(), None),
+            Err(CacheError::DependencyCycle(..))
+        ));
+        assert!(
+            cache
+                .set("team".into(), Value::String("v".into()), linked(&["badge"]))
+                .is_err()
+        );
+
+        assert_eq!(cache.remove_parent("profile", "user"), 1);
+        assert_eq!(cache.parents("profile"), ["team"]);
+        assert_eq!(cache.add_parent("profile", "user".into(), None).unwrap(), 1);
+
+        // Losing either parent invalidates the child
+        cache.del(&["team"]);
+        assert!(cache.get("profile").is_none());
+        assert!(cache.get("badge").is_none());
+        assert!(cache.get("user").is_some());
+    }
+
+    #[test]
+    fn test_tag_invalidation() {
+        let cache = Cache::new(Config::default());
+        let set = |key: &str, tags: &[&str], parent: Option<&str>| {
+            let options = SetOptions {
+                tags: tags.iter().map(|tag| tag.to_string()).collect(),
+                ..linked(parent.as_slice())
+            };
+            cache
+                .set(key.into(), Value::String("v".into()), options)
+                .unwrap();
+        };
+        set("page:1", &["product:7", "home"], None);
+        set("page:2", &["product:7", "product:7"], None);
+        set("page:1:fragment", &[], Some("page:1"));
+        set("page:3", &["home"], None);
+        assert_eq!(cache.tags("page:2"), ["product:7"]);
+
+        let mut tagged = cache.tagged("product:7");
+        tagged.sort();
+        assert_eq!(tagged, ["page:1", "page:2"]);
+
+        // Dependents of tagged keys go with them
+        assert_eq!(
+            cache.invalidate_tag("product:7", InvalidationMode::Stale),
+            3
+        );
+        assert!(cache.is_stale("page:1:fragment"));
+        assert!(!cache.is_stale("page:3"));
+
+        // Overwriting replaces the tags, and renames keep them
+        set("page:3", &["footer"], None);
+        cache.rename("page:3", "page:4").unwrap();
+        assert_eq!(cache.tagged("home"), ["page:1"]);
+        assert_eq!(cache.tagged("footer"), ["page:4"]);
+
+        assert_eq!(
+            cache.invalidate_tag("product:7", InvalidationMode::Delete),
+            3
+        );
+        assert!(!cache.exists("page:1") && !cache.exists("page:1:fragment"));
+        assert!(cache.tagged("home").is_empty());
+        assert_eq!(
+            cache.invalidate_tag("product:7", InvalidationMode::Delete),
+            0
+        );
+        cache.flush_all();
+        assert!(cache.indexes.ids_by_tag.is_empty());
+    }
+
+    #[test]
+    fn test_max_dependency_depth() {
+        let cache = Cache::new(Config {
+            max_dependency_depth: 3,
+            ..Default::default()
+        });
+        set_linked(&cache, "a", &[]);
+        set_linked(&cache, "b", &["a"]);
+        set_linked(&cache, "c", &["b"]);
+        set_linked(&cache, "d", &["c"]);
+        assert!(matches!(
+            cache.set("e".into(), Value::String("v".into()), linked(&["d"])),
+            Err(CacheError::DependencyTooDeep(_, 3))
+        ));
+
+        // Dependents count too: a can't gain a parent with d three levels below it
+        set_linked(&cache, "root", &[]);
+        assert!(cache.set_parent("a", "root".into()).is_err());
+        assert!(cache.reparent_children("b", "d").is_err());
+        assert_eq!(cache.parent("c").as_deref(), Some("b"));
+
+        set_linked(&cache, "x", &[]);
+        set_linked(&cache, "e", &["x"]);
+        assert_eq!(cache.reparent_children("x", "c").unwrap(), 1);
+        assert!(cache.add_parent("e", "d".into(), None).is_err());
+    }
+
+    #[test]
+    fn test_chain_checks() {
+        let cache = Cache::new(Config::default());
+        set_linked(&cache, "a", &[]);
+        set_linked(&cache, "b", &["a"]);
+        set_linked(&cache, "c", &["b"]);
+        let live = |key: &str| {
+            let id = cache.data.get(key).unwrap().id;
+            cache
+                .indexes
+                .chain_checks
+                .get(&id)
+                .is_some_and(|check| check.live)
+        };
+        assert!(cache.get("c").is_some());
+        assert!(live("c") && !live("b"));
+
+        // Links elsewhere in the graph leave the cached check alone
+        set_linked(&cache, "x", &["a"]);
+        set_linked(&cache, "y", &["x"]);
+        assert!(live("c"));
+        cache.set_parent("x", "b".into()).unwrap();
+        assert!(live("c"));
+        cache.expire("b", 100);
+        assert!(!live("c"));
+
+        // Cached checks don't outlive changes to the ancestors
+        cache.pexpire("a", 1);
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(cache.get("c").is_none());
+
+        set_linked(&cache, "a", &[]);
+        set_linked(&cache, "b", &["a"]);
+        set_linked(&cache, "c", &["b"]);
+        assert!(cache.get("c").is_some());
+        cache.del(&["a"]);
+        assert!(cache.get("c").is_none());
+    }
+
+    #[test]
+    fn test_cascading_delete() {
+        for cascade_deletes in [false, true] {
+            let cache = Cache::new(Config {
+                cascade_deletes,
+                ..Default::default()
+            });
+            set_linked(&cache, "root", &[]);
+            set_linked(&cache, "child", &["root"]);
+            set_linked(&cache, "grandchild", &["child"]);
+            set_linked(&cache, "other", &[]);
+
+            match cascade_deletes {
+                true => assert_eq!(cache.del(&["root", "other"]), 4),
+                false => {
+                    assert_eq!(cache.del(&["root"]), 1);
+                    assert_eq!(cache.len(), 3);
+                    set_linked(&cache, "root", &[]);
+                    set_linked(&cache, "child", &["root"]);
+                    assert_eq!(cache.del_cascade(&["root", "other"]), 4);
+                }
+            }
+            assert_eq!(cache.len(), 0);
+        }
+    }
+
+    #[test]
+    fn test_numeric_encoding() {
+        use crate::executor::{Command, CommandExecutor, CommandResponse};
+
+        assert_eq!(Value::encode_number("42".into()), Value::Integer(42));
+        assert_eq!(Value::encode_number("-1.5".into()), Value::Float(-1.5));
+        for raw in ["042", "1.0", "1e3", "+7", "NaN", "inf", "abc"] {
+            assert_eq!(Value::encode_number(raw.into()), Value::String(raw.into()));
+        }
+
+        assert_eq!(Value::Integer(1).redis_type(), "string");
+        assert_eq!(Value::Integer(1).encoding(), "int");
+        assert_eq!(Value::String("short".into()).encoding(), "embstr");
+        assert_eq!(Value::String("x".repeat(45)).encoding(), "raw");
+
+        let cache = Cache::new(Config {
+            encode_numbers: true,
+            ..Config::default()
+        });
+        let executor = CommandExecutor::new(Arc::new(cache));
+        let run = |line: &str| {
+            let args: Vec<&[u8]> = line.split(' ').map(str::as_bytes).collect();
+            executor.execute(Command::parse(&args).unwrap())
+        };
+        run("SET n 10");
+        run("SET s 010");
+        assert!(matches!(run("OBJECT ENCODING n"), CommandResponse::Value(e) if e == "int"));
+        assert!(matches!(run("OBJECT ENCODING s"), CommandResponse::Value(e) if e == "embstr"));
+        assert!(matches!(run("TYPE n"), CommandResponse::Value(t) if t == "string"));
+        assert!(matches!(run("TYPE missing"), CommandResponse::Value(t) if t == "none"));
+        assert!(matches!(run("GET s"), CommandResponse::Bytes(v) if v == b"010"));
+    }
+
+    #[test]
+    fn test_scan() {
+        let cache = Cache::new(Config::default());
+        for i in 0..100 {
+            let key = format!("{}:{}", if i % 2 == 0 { "even" } else { "odd" }, i);
+            cache
+                .set(key, Value::Integer(i), SetOptions::default())
+                .unwrap();
+        }
+
+        let mut seen = Vec::new();
+        let (mut cursor, mut steps) = (0, 0);
+        loop {
+            let (next, keys) = cache.scan(cursor, Some("even:*"), 5);
+            seen.extend(keys);
+            steps += 1;
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 50);
+        assert!(seen.iter().all(|key| key.starts_with("even:")));
+        assert!(steps > 1);
+        // A cursor past the last shard ends the walk
+        assert_eq!(cache.scan(u64::MAX, None, 10), (0, Vec::new()));
+    }
+
+    #[test]
+    fn test_wrongtype_errors() {
+        use crate::executor::{Command, CommandExecutor, CommandResponse};
+
+        let executor = CommandExecutor::new(Arc::new(Cache::new(Config::default())));
+        let run = |line: &str| {
+            let args: Vec<&[u8]> = line.split(' ').map(str::as_bytes).collect();
+            executor.execute(Command::parse(&args).unwrap())
+        };
+        run("HSET h f v");
+        run("SADD s m");
+        for line in ["GET h", "GET s", "STRLEN h", "APPEND s x", "INCRBY h 1"] {
+            assert!(
+                matches!(run(line), CommandResponse::Error(e) if e.to_string().starts_with("WRONGTYPE")),
+                "{} didn't fail with WRONGTYPE",
+                line
+            );
+        }
+        assert!(matches!(run("TYPE h"), CommandResponse::Value(t) if t == "hash"));
+        assert!(matches!(run("TYPE s"), CommandResponse::Value(t) if t == "set"));
+        // SET replaces a value of any type
+        assert!(matches!(run("SET h v"), CommandResponse::Ok));
+        assert!(matches!(run("GET h"), CommandResponse::Bytes(v) if v == b"v"));
+    }
+
+    #[test]
+    fn test_byte_ranges() {
+        let cache = Cache::new(Config::default());
+
+        assert_eq!(cache.set_range("blob", 2, b"cd").unwrap(), 4);
+        assert_eq!(
+            cache.get_range("blob", 0, -1).unwrap().unwrap().0,
+            b"\0\0cd"
+        );
+        assert_eq!(cache.set_range("blob", 0, b"ab").unwrap(), 4);
+        assert_eq!(cache.get("blob").unwrap(), Value::String("abcd".into()));
+
+        // Invalid UTF-8 switches the value to bytes
+        assert_eq!(cache.set_range("blob", 4, &[0xff]).unwrap(), 5);
+        assert!(matches!(cache.get("blob"), Some(Value::Bytes(_))));
+
+        assert_eq!(
+            cache.get_range("blob", 1, 2).unwrap(),
+            Some((b"bc".to_vec(), 5))
+        );
+        assert_eq!(
+            cache.get_range("blob", -2, -1).unwrap().unwrap().0,
+            b"d\xff"
+        );
+        assert_eq!(
+            cache.get_range("blob", 3, 100).unwrap().unwrap().0,
+            b"d\xff"
+        );
+        assert!(cache.get_range("blob", 5, 9).unwrap().unwrap().0.is_empty());
+        assert_eq!(cache.get_range("missing", 0, -1).unwrap(), None);
+
+        cache
+            .set("n".into(), Value::Integer(1234), SetOptions::default())
             .unwrap();
-        assert_eq!(cache.get("key1"), Some(Value::String("value1".to_string())));
+        assert_eq!(cache.get_range("n", 1, 2).unwrap().unwrap().0, b"23");
+        assert!(matches!(
+            cache.set_range("blob", MAX_STRING_LEN, b"x"),
+            Err(CacheError::ValueTooLarge)
+        ));
+    }
 
-        // Test get().is_some() for existence
-        assert!(cache.get("key1").is_some());
-        assert!(cache.get("nonexistent").is_none());
+    #[test]
+    fn test_set_members() {
+        let cache = Cache::new(Config::default());
+        let empty = cache.memory_usage();
+        let members = |names: &[&str]| names.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(cache.sadd("a", members(&["x", "y", "z"])).unwrap(), 3);
+        assert_eq!(cache.sadd("a", members(&["x", "w", "w"])).unwrap(), 1);
+        assert_eq!(cache.sadd("b", members(&["y", "w", "v"])).unwrap(), 3);
+        assert_eq!(cache.smembers("a").unwrap(), ["w", "x", "y", "z"]);
+        assert!(cache.sismember("a", "x").unwrap());
+        assert!(!cache.sismember("a", "v").unwrap());
+        assert!(!cache.sismember("missing", "x").unwrap());
+
+        let keys = members(&["a", "b"]);
+        assert_eq!(cache.sinter(&keys).unwrap(), ["w", "y"]);
+        assert_eq!(cache.sunion(&keys).unwrap(), ["v", "w", "x", "y", "z"]);
+        assert!(
+            cache
+                .sinter(&members(&["a", "missing"]))
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            cache.sunion(&members(&["b", "missing"])).unwrap(),
+            ["v", "w", "y"]
+        );
 
-        // Test delete
-        cache.delete("key1");
-        assert!(cache.get("key1").is_none());
+        assert_eq!(cache.srem("a", &members(&["x", "nope"])).unwrap(), 1);
+        assert_eq!(cache.srem("missing", &members(&["x"])).unwrap(), 0);
+        assert!(cache.get("missing").is_none());
+        cache.srem("a", &members(&["w", "y", "z"])).unwrap();
+        cache.srem("b", &members(&["y", "w", "v"])).unwrap();
+        assert!(cache.get("a").is_none());
+        assert_eq!(cache.memory_usage(), empty);
+
+        cache
+            .set("s".into(), Value::String("v".into()), SetOptions::default())
+            .unwrap();
+        assert!(matches!(
+            cache.sadd("s", members(&["x"])),
+            Err(CacheError::WrongType)
+        ));
+        assert!(matches!(
+            cache.sunion(&members(&["b", "s"])),
+            Err(CacheError::WrongType)
+        ));
     }
 
     #[test]
-    fn test_ttl_expiration() {
+    fn test_hash_fields() {
         let cache = Cache::new(Config::default());
+        let empty = cache.memory_usage();
+        let fields = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(f, v)| (f.to_string(), Value::String(v.to_string())))
+                .collect::<Vec<_>>()
+        };
 
-        // Set with a very short TTL
+        assert_eq!(
+            cache.hset("h", fields(&[("a", "1"), ("b", "x")])).unwrap(),
+            2
+        );
+        assert_eq!(
+            cache.hset("h", fields(&[("a", "2"), ("c", "y")])).unwrap(),
+            1
+        );
+        assert_eq!(
+            cache.hget("h", "a").unwrap(),
+            Some(Value::String("2".into()))
+        );
+        assert_eq!(cache.hget("h", "zz").unwrap(), None);
+        assert_eq!(cache.hget("missing", "a").unwrap(), None);
+
+        assert_eq!(cache.hincr_by("h", "a", 5).unwrap(), 7);
+        assert_eq!(cache.hincr_by("h", "n", -1).unwrap(), -1);
+        assert!(matches!(
+            cache.hincr_by("h", "b", 1),
+            Err(CacheError::NotAnInteger)
+        ));
+        let all: Vec<String> = cache
+            .hgetall("h")
+            .unwrap()
+            .into_iter()
+            .map(|(f, _)| f)
+            .collect();
+        assert_eq!(all, ["a", "b", "c", "n"]);
+
+        // Overwriting a field clears its TTL
         cache
-            .set(
-                "temp".to_string(),
-                Value::String("temp_value".to_string()),
-                SetOptions {
-                    ttl: Some(Duration::from_millis(1)),
-                    ..Default::default()
-                },
-            )
+            .hexpire("h", Duration::from_secs(60), &["c".into()])
             .unwrap();
+        cache.hset("h", fields(&[("c", "z")])).unwrap();
+        assert_eq!(cache.httl("h", &["c".into()]).unwrap(), [-1]);
 
-        // Should exist immediately
-        assert!(cache.get("temp").is_some());
+        assert_eq!(cache.hdel("h", &["a".into(), "nope".into()]).unwrap(), 1);
+        assert_eq!(
+            cache
+                .hdel("h", &["b".into(), "c".into(), "n".into()])
+                .unwrap(),
+            3
+        );
+        assert!(cache.get("h").is_none());
+        assert_eq!(cache.memory_usage(), empty);
 
-        // Wait for expiration
-        std::thread::sleep(Duration::from_millis(10));
+        cache
+            .set("s".into(), Value::String("v".into()), SetOptions::default())
+            .unwrap();
+        assert!(matches!(cache.hget("s", "a"), Err(CacheError::WrongType)));
+        assert!(matches!(
+            cache.hset("s", fields(&[("a", "1")])),
+            Err(CacheError::WrongType)
+        ));
+    }
 
-        // Should be expired now
-        assert!(cache.get("temp").is_none());
+    #[test]
+    fn test_append_and_strlen() {
+        let cache = Cache::new(Config::default());
+
+        assert_eq!(cache.strlen("log").unwrap(), 0);
+        assert_eq!(cache.append("log", b"a,").unwrap(), 2);
+        assert_eq!(cache.append("log", b"b,").unwrap(), 4);
+        assert_eq!(cache.get("log").unwrap(), Value::String("a,b,".into()));
+        assert_eq!(cache.strlen("log").unwrap(), 4);
+
+        cache
+            .set("n".into(), Value::Integer(12), SetOptions::default())
+            .unwrap();
+        assert_eq!(cache.append("n", b"3").unwrap(), 3);
+        assert_eq!(cache.strlen("n").unwrap(), 3);
+        assert_eq!(cache.get_range("n", 0, -1).unwrap().unwrap().0, b"123");
     }
 
     #[test]
-    fn test_dependencies() {
-        // Dependencies must be enabled in the config
-        let config = Config {
-            enable_dependencies: true,
-            ..Default::default()
+    fn test_dependency_graph_round_trip() {
+        let source = Cache::new(Config::default());
+        for key in ["root", "a", "b", "c"] {
+            source
+                .set(key.into(), Value::String("v".into()), SetOptions::default())
+                .unwrap();
+        }
+        source.set_parent("b", "root".into()).unwrap();
+        source.set_parent("a", "root".into()).unwrap();
+        source.set_parent("c", "a".into()).unwrap();
+
+        let edges = source.dependency_graph();
+        let keys: Vec<_> = edges.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(keys, ["a", "b", "c"]);
+
+        // A freshly warmed cache holding only some of the keys, plus a conflicting link
+        let target = Cache::new(Config::default());
+        for key in ["root", "a", "c"] {
+            target
+                .set(key.into(), Value::String("v".into()), SetOptions::default())
+                .unwrap();
+        }
+        target.set_parent("root", "c".into()).unwrap();
+
+        let result = target.import_dependency_graph(&edges).unwrap();
+        assert_eq!(
+            result,
+            GraphImport {
+                applied: 1,
+                missing: 1,
+                cycles: 1,
+                too_deep: 0,
+            }
+        );
+        assert_eq!(target.parent("a").as_deref(), Some("root"));
+        assert_eq!(target.parent("c"), None);
+    }
+
+    #[tokio::test]
+    async fn test_write_offset_tokens() {
+        use crate::executor::{Command, CommandExecutor};
+
+        let executor = CommandExecutor::new(Arc::new(Cache::new(Config::default())));
+        let get = Command::Get { key: "k".into() };
+        let set = Command::Set {
+            key: "k".into(),
+            value: "v".into(),
+            options: SetOptions {
+                parents: vec!["missing".into()],
+                ..SetOptions::default()
+            },
+            concern: None,
         };
-        let cache = Cache::new(config);
 
-        // Set parent
+        // Reads and failed writes don't advance the offset
+        executor.execute(get);
+        executor.execute(set);
+        assert_eq!(executor.write_offset(), 0);
+
+        executor.execute(Command::Set {
+            key: "k".into(),
+            value: "v".into(),
+            options: SetOptions::default(),
+            concern: None,
+        });
+        assert_eq!(executor.write_offset(), 1);
+        assert!(executor.wait_for_offset(1, Duration::ZERO).await);
+        assert!(!executor.wait_for_offset(2, Duration::from_millis(20)).await);
+    }
+
+    #[test]
+    fn test_soft_delete_tombstones() {
+        let cache = Cache::new(Config {
+            tombstone_retention: Some(Duration::from_millis(100)),
+            ..Config::default()
+        });
+        let set = |key: &str, options| {
+            cache
+                .set(key.into(), Value::String("v".into()), options)
+                .unwrap()
+        };
+        set("a", SetOptions::default());
+        set("b", SetOptions::default());
+        let expiring = SetOptions {
+            ttl: Some(Duration::from_millis(20)),
+            ..SetOptions::default()
+        };
+        set("c", expiring);
+
+        cache.del(&["a", "never"]);
+        assert!(cache.tombstone("a").is_some());
+        assert!(cache.tombstone("never").is_none());
+        assert!(cache.tombstone("b").is_none());
+
+        // Expiry isn't a delete; writing the key again clears its tombstone
+        std::thread::sleep(Duration::from_millis(30));
+        cache.cleanup_expired();
+        assert!(cache.tombstone("c").is_none());
+        set("a", SetOptions::default());
+        assert!(cache.tombstone("a").is_none());
+
+        cache.invalidate("b", InvalidationMode::Delete);
+        assert!(cache.tombstone("b").is_some());
+        assert_eq!(cache.tombstone_count(), 1);
+        std::thread::sleep(Duration::from_millis(110));
+        assert!(cache.tombstone("b").is_none());
+        cache.cleanup_expired();
+        assert_eq!(cache.tombstone_count(), 0);
+
+        let hard = Cache::new(Config::default());
+        hard.set("a".into(), Value::String("v".into()), SetOptions::default())
+            .unwrap();
+        hard.del(&["a"]);
+        assert!(hard.tombstone("a").is_none());
+    }
+
+    #[test]
+    fn test_stale_grace() {
+        let cache = Cache::new(Config {
+            stale_grace: Some(Duration::from_secs(60)),
+            ..Config::default()
+        });
+        let options = SetOptions {
+            ttl: Some(Duration::from_millis(50)),
+            ..SetOptions::default()
+        };
+        cache
+            .set("k".into(), Value::String("v".into()), options)
+            .unwrap();
+        assert!(!cache.is_stale("k"));
+
+        std::thread::sleep(Duration::from_millis(60));
+        cache.cleanup_expired();
+        assert_eq!(cache.get("k"), Some(Value::String("v".into())));
+        assert!(cache.is_stale("k"));
+        assert!(!cache.exists("k"));
+
+        let strict = Cache::new(Config::default());
+        let options = SetOptions {
+            ttl: Some(Duration::from_millis(50)),
+            ..SetOptions::default()
+        };
+        strict
+            .set("k".into(), Value::String("v".into()), options)
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(strict.get("k"), None);
+    }
+
+    #[test]
+    fn test_pinned_memory_cap() {
+        let cache = Cache::new(Config {
+            max_pinned_memory: Some(400),
+            ..Config::default()
+        });
+        let pinned = SetOptions {
+            pinned: true,
+            ..SetOptions::default()
+        };
+
+        cache
+            .set("flags".into(), Value::String("on".into()), pinned.clone())
+            .unwrap();
+        assert!(cache.is_pinned("flags"));
+        let used = cache.stats().pinned_memory.load(Ordering::Relaxed);
+        assert!(used > 0);
+
+        let big = Value::String("x".repeat(400));
+        assert!(matches!(
+            cache.set("blob".into(), big.clone(), pinned.clone()),
+            Err(CacheError::PinnedLimitExceeded)
+        ));
+        // Unpinned keys don't count against the cap
+        cache
+            .set("blob".into(), big, SetOptions::default())
+            .unwrap();
+        assert!(!cache.is_pinned("blob"));
+
+        // Overwriting unpinned releases the pinned bytes, as does deleting
+        cache
+            .set(
+                "flags".into(),
+                Value::String("off".into()),
+                SetOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(cache.stats().pinned_memory.load(Ordering::Relaxed), 0);
+        cache
+            .set("flags".into(), Value::String("on".into()), pinned)
+            .unwrap();
+        cache.del(&["flags"]);
+        assert_eq!(cache.stats().pinned_memory.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_lfu_eviction() {
+        let cache = Cache::new(Config {
+            max_memory: Some(std::mem::size_of::<Cache>() + 4096),
+            eviction_policy: EvictionPolicy::AllKeysLfu,
+            ..Config::default()
+        });
+        let pinned = SetOptions {
+            pinned: true,
+            ..SetOptions::default()
+        };
+        cache
+            .set("config".into(), Value::String("x".repeat(100)), pinned)
+            .unwrap();
+        for key in ["hot:1", "hot:2"] {
+            cache
+                .set(
+                    key.into(),
+                    Value::String("x".repeat(100)),
+                    SetOptions::default(),
+                )
+                .unwrap();
+            for _ in 0..50 {
+                cache.get(key);
+            }
+        }
+
+        for i in 0..100 {
+            cache
+                .set(
+                    format!("cold:{}", i),
+                    Value::String("x".repeat(100)),
+                    SetOptions::default(),
+                )
+                .unwrap();
+        }
+
+        assert!(cache.stats().evicted_keys.load(Ordering::Relaxed) > 0);
+        assert!(cache.memory_usage() <= cache.config.max_memory.unwrap());
+        assert!(cache.exists("config"));
+        assert!(cache.exists("hot:1"));
+        assert!(cache.exists("hot:2"));
+        assert!(cache.exists("cold:99"));
+
+        // Without an evicting policy the write is rejected instead
+        let strict = Cache::new(Config {
+            max_memory: Some(std::mem::size_of::<Cache>() + 1024),
+            ..Config::default()
+        });
+        let result = (0..100).try_for_each(|i| {
+            strict
+                .set(
+                    format!("k{}", i),
+                    Value::String("x".repeat(100)),
+                    SetOptions::default(),
+                )
+                .map(|_| ())
+        });
+        assert!(matches!(result, Err(CacheError::MemoryLimitExceeded)));
+    }
+
+    #[test]
+    fn test_volatile_ttl_eviction() {
+        let cache = Cache::new(Config {
+            max_memory: Some(std::mem::size_of::<Cache>() + 4096),
+            eviction_policy: "volatile-ttl".parse().unwrap(),
+            ..Config::default()
+        });
+        let value = || Value::String("x".repeat(100));
         cache
-            .set(
-                "parent".to_string(),
-                Value::String("parent_value".to_string()),
-                SetOptions::default(),
-            )
+            .set("persistent".into(), value(), SetOptions::default())
             .unwrap();
-
-        // Set child with dependency
         cache
             .set(
-                "child".to_string(),
-                Value::String("child_value".to_string()),
+                "long".into(),
+                value(),
                 SetOptions {
-                    parent: Some("parent".to_string()),
-                    ..Default::default()
+                    ttl: Some(Duration::from_secs(3600)),
+                    ..SetOptions::default()
                 },
             )
             .unwrap();
+        for i in 0..100 {
+            let result = cache.set(
+                format!("short:{}", i),
+                value(),
+                SetOptions {
+                    ttl: Some(Duration::from_secs(60 + i)),
+                    ..SetOptions::default()
+                },
+            );
+            assert!(result.is_ok(), "short:{} was rejected", i);
+        }
 
-        // Both should exist
-        assert!(cache.get("parent").is_some());
-        assert!(cache.get("child").is_some());
+        assert!(cache.stats().evicted_keys.load(Ordering::Relaxed) > 0);
+        assert!(cache.exists("persistent"));
+        assert!(cache.exists("long"));
+        assert!(!cache.exists("short:0"));
+
+        // Once only keys without a TTL are left, writes are rejected
+        let only_persistent = Cache::new(Config {
+            max_memory: Some(std::mem::size_of::<Cache>() + 1024),
+            eviction_policy: EvictionPolicy::VolatileTtl,
+            ..Config::default()
+        });
+        let result = (0..100).try_for_each(|i| {
+            only_persistent
+                .set(format!("k{}", i), value(), SetOptions::default())
+                .map(|_| ())
+        });
+        assert!(matches!(result, Err(CacheError::MemoryLimitExceeded)));
+        assert_eq!(EvictionPolicy::AllKeysRandom.to_string(), "allkeys-random");
+        assert!("allkeys-lru".parse::<EvictionPolicy>().is_err());
+    }
 
-        // Delete parent
-        cache.delete("parent");
+    #[test]
+    fn test_key_limit_policies() {
+        let limited = |policy| {
+            Cache::new(Config {
+                max_keys: Some(3),
+                key_limit_policy: policy,
+                ..Config::default()
+            })
+        };
+        let ttl = |secs| SetOptions {
+            ttl: Some(Duration::from_secs(secs)),
+            ..SetOptions::default()
+        };
+        let value = || Value::String("v".into());
 
-        // Child should now be invalid due to missing parent
-        assert!(cache.get("child").is_none());
+        let lru = limited(KeyLimitPolicy::EvictLru);
+        for key in ["a", "b", "c"] {
+            lru.set(key.into(), value(), SetOptions::default()).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(5));
+        lru.get("a");
+        lru.get("c");
+        lru.set("d".into(), value(), SetOptions::default()).unwrap();
+        assert_eq!(lru.len(), 3);
+        assert!(!lru.exists("b"));
+        assert_eq!(lru.stats().evicted_keys.load(Ordering::Relaxed), 1);
+
+        let oldest_ttl = limited("evict-oldest-ttl".parse().unwrap());
+        oldest_ttl.set("long".into(), value(), ttl(3600)).unwrap();
+        oldest_ttl.set("short".into(), value(), ttl(60)).unwrap();
+        oldest_ttl
+            .set("forever".into(), value(), SetOptions::default())
+            .unwrap();
+        oldest_ttl.set("new".into(), value(), ttl(600)).unwrap();
+        assert!(!oldest_ttl.exists("short"));
+        assert!(oldest_ttl.exists("forever") && oldest_ttl.exists("long"));
+
+        let reject = limited(KeyLimitPolicy::Reject);
+        for key in ["a", "b", "c"] {
+            reject
+                .set(key.into(), value(), SetOptions::default())
+                .unwrap();
+        }
+        assert!(matches!(
+            reject.set("d".into(), value(), SetOptions::default()),
+            Err(CacheError::KeyLimitExceeded)
+        ));
+        assert!(
+            reject
+                .set("a".into(), value(), SetOptions::default())
+                .is_ok()
+        );
     }
 
     #[test]
-    fn test_cycle_detection() {
-        let config = Config {
-            enable_dependencies: true,
-            ..Default::default()
+    fn test_priority_evicted_low_first() {
+        let cache = Cache::new(Config {
+            max_keys: Some(3),
+            key_limit_policy: KeyLimitPolicy::EvictLru,
+            ..Config::default()
+        });
+        let with = |priority| SetOptions {
+            priority,
+            ..SetOptions::default()
         };
-        let cache = Cache::new(config);
+        let value = || Value::String("v".into());
 
         cache
-            .set(
-                "a".to_string(),
-                Value::String("a".to_string()),
-                SetOptions::default(),
-            )
+            .set("high".into(), value(), with(Priority::High))
             .unwrap();
         cache
-            .set(
-                "b".to_string(),
-                Value::String("b".to_string()),
-                SetOptions::default(),
-            )
+            .set("low".into(), value(), with(Priority::Low))
+            .unwrap();
+        cache
+            .set("normal".into(), value(), with(Priority::Normal))
             .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        // The most recently used key still goes first when it is the lowest class
+        cache.get("low");
+        cache
+            .set("a".into(), value(), with(Priority::Normal))
+            .unwrap();
+        assert!(!cache.exists("low"));
+        assert_eq!(cache.priority("high"), Priority::High);
 
-        // a -> b
         cache
-            .set(
-                "a".to_string(),
-                Value::String("a2".to_string()),
-                SetOptions {
-                    parent: Some("b".to_string()),
-                    ..Default::default()
-                },
-            )
+            .set("b".into(), value(), with(Priority::Normal))
             .unwrap();
+        assert!(cache.exists("high"));
+        assert!(!cache.exists("normal"));
+    }
 
-        // b -> a should fail (would create cycle)
-        let result = cache.set(
-            "b".to_string(),
-            Value::String("b2".to_string()),
-            SetOptions {
-                parent: Some("a".to_string()),
-                ..Default::default()
-            },
-        );
+    #[test]
+    fn test_purge_memory() {
+        let cache = Cache::new(Config::default());
+        let mut oversized = String::with_capacity(4096);
+        oversized.push_str("value");
+        cache
+            .set("k".into(), Value::String(oversized), SetOptions::default())
+            .unwrap();
+        let mut members = HashSet::with_capacity(1024);
+        members.insert("m".to_string());
+        cache
+            .set("s".into(), Value::Set(members), SetOptions::default())
+            .unwrap();
 
-        // Assert that we get the specific cycle error
-        assert!(matches!(result, Err(CacheError::DependencyCycle(..))));
+        let before = cache.stats().memory_usage.load(Ordering::Relaxed);
+        let purged = cache.purge_memory();
+        assert!(purged >= 4096 - "value".len());
+        assert_eq!(
+            cache.stats().memory_usage.load(Ordering::Relaxed),
+            before - purged
+        );
+        assert_eq!(cache.get("k").unwrap().to_string(), "value");
+        assert_eq!(cache.purge_memory(), 0);
     }
 
     #[test]
-    fn test_memory_limit() {
-        let config = Config {
-            max_memory: Some(512),
-            ..Default::default()
-        };
-        let cache = Cache::new(config.clone());
+    fn test_hyperloglog_commands() {
+        let cache = Cache::new(Config::default());
+        let members = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        assert!(cache.pfadd("day1", &members(&["a", "b", "c"])).unwrap());
+        assert!(!cache.pfadd("day1", &members(&["a"])).unwrap());
+        assert!(cache.pfadd("empty", &[]).unwrap());
+        assert!(cache.pfadd("day2", &members(&["c", "d"])).unwrap());
+        assert_eq!(cache.pfcount(&members(&["day1"])).unwrap(), 3);
+        assert_eq!(
+            cache
+                .pfcount(&members(&["day1", "day2", "missing"]))
+                .unwrap(),
+            4
+        );
+        assert_eq!(cache.pfcount(&members(&["missing"])).unwrap(), 0);
 
-        let key = "small".to_string();
-        let value = Value::String("ok".to_string());
+        cache.pfmerge("week", &members(&["day1", "day2"])).unwrap();
+        assert_eq!(cache.pfcount(&members(&["week"])).unwrap(), 4);
+        assert_eq!(cache.get("week").unwrap().redis_type(), "string");
 
-        let entry = Entry::new(value.clone());
-        let expected_size = key.len() + entry.memory_usage();
+        cache
+            .set("s".into(), Value::String("v".into()), SetOptions::default())
+            .unwrap();
+        assert!(matches!(
+            cache.pfadd("s", &members(&["a"])),
+            Err(CacheError::WrongType)
+        ));
+        assert!(matches!(
+            cache.pfmerge("week", &members(&["s"])),
+            Err(CacheError::WrongType)
+        ));
+    }
 
-        let result = cache.set(key, value, SetOptions::default());
+    #[test]
+    fn test_json_documents() {
+        use serde_json::json;
+        let cache = Cache::new(Config::default());
+        let doc = json!({"user": {"name": "ada", "visits": 1}});
+        let empty = cache.stats().memory_usage.load(Ordering::Relaxed);
+
+        assert!(matches!(
+            cache.json_set("d", "$.user", json!({}), false, false),
+            Err(CacheError::JsonPath(_))
+        ));
+        assert!(cache.json_set("d", "$", doc, false, false).unwrap());
+        assert!(
+            !cache
+                .json_set("d", "$.user.name", json!("bob"), true, false)
+                .unwrap()
+        );
+        assert!(
+            cache
+                .json_set("d", "$.user.visits", json!(2), false, true)
+                .unwrap()
+        );
+        assert!(
+            cache
+                .json_set("d", "$.user.tags", json!(["a"]), false, false)
+                .unwrap()
+        );
+        assert_eq!(cache.json_get("d", "user.visits").unwrap(), Some(json!(2)));
+        assert_eq!(cache.json_get("d", "$.nope").unwrap(), None);
+        assert_eq!(
+            cache.get("d").unwrap().to_string(),
+            r#"{"user":{"name":"ada","tags":["a"],"visits":2}}"#
+        );
 
-        if let Err(e) = result {
-            panic!(
-                "Setting a small key failed unexpectedly! Error: {:?}. Expected size: {}, Max memory: {}",
-                e,
-                expected_size,
-                config.max_memory.unwrap()
-            );
-        }
+        let before = cache.stats().memory_usage.load(Ordering::Relaxed);
+        assert_eq!(cache.json_del("d", "$.user.tags").unwrap(), 1);
+        assert!(cache.stats().memory_usage.load(Ordering::Relaxed) < before);
+        assert_eq!(cache.json_del("d", "$.user.tags").unwrap(), 0);
+        assert_eq!(cache.json_del("d", "$").unwrap(), 1);
+        assert!(!cache.exists("d"));
+        assert_eq!(cache.stats().memory_usage.load(Ordering::Relaxed), empty);
 
-        let large_value = Value::String("x".repeat(200));
-        let result = cache.set("large".to_string(), large_value, SetOptions::default());
+        cache
+            .set("s".into(), Value::String("v".into()), SetOptions::default())
+            .unwrap();
+        assert!(matches!(
+            cache.json_get("s", "$"),
+            Err(CacheError::WrongType)
+        ));
+    }
 
-        assert!(matches!(result, Err(CacheError::MemoryLimitExceeded)));
+    #[test]
+    fn test_access_count_decays() {
+        let lfu = LfuConfig::default();
+        let mut entry = Entry::new(Value::String("v".into()));
+        entry.access_count = 40;
+        let now = entry.last_accessed;
+        let period = lfu.decay_period;
+        assert_eq!(entry.frequency(now, &lfu), 40);
+        assert_eq!(entry.frequency(now + period, &lfu), 20);
+        assert_eq!(entry.frequency(now + period * 3, &lfu), 5);
+        assert_eq!(entry.frequency(now + period * 100, &lfu), 0);
+
+        // A daily schedule barely decays within the hour; zero never decays
+        let daily = LfuConfig {
+            decay_period: Duration::from_secs(86_400),
+            ..lfu
+        };
+        assert_eq!(entry.frequency(now + Duration::from_secs(3600), &daily), 40);
+        let never = LfuConfig {
+            decay_period: Duration::ZERO,
+            ..lfu
+        };
+        assert_eq!(entry.frequency(now + period * 100, &never), 40);
+
+        // Logarithmic counting: a thousand hits count for far fewer
+        let log = LfuConfig {
+            log_factor: 10,
+            ..lfu
+        };
+        let count = (0..1000).fold(0, |count, _| log.increment(count));
+        assert!(count > 5 && count < 100, "count was {}", count);
     }
 
     #[test]
-    fn test_cleanup_expired() {
-        let cache = Cache::new(Config::default());
+    fn test_object_freq() {
+        use crate::executor::{Command, CommandExecutor, CommandResponse};
 
-        // Create enough entries to populate multiple shards across CPU cores
-        let num_cores = std::thread::available_parallelism()
-            .map(|n| n.get())
-            .unwrap_or(4); // fallback to 4 if detection fails
+        let executor = CommandExecutor::new(Arc::new(Cache::new(Config::default())));
+        let run = |line: &str| {
+            let args: Vec<&[u8]> = line.split(' ').map(str::as_bytes).collect();
+            executor.execute(Command::parse(&args).unwrap())
+        };
+        run("SET k v");
+        run("GET k");
+        run("GET k");
+        assert!(matches!(run("OBJECT FREQ k"), CommandResponse::Integer(2)));
+        // OBJECT itself doesn't count as an access
+        assert!(matches!(run("OBJECT FREQ k"), CommandResponse::Integer(2)));
+        assert!(matches!(run("OBJECT FREQ missing"), CommandResponse::Null));
+    }
 
-        let entries_per_core = 5;
-        let total_expired = num_cores * entries_per_core;
+    #[test]
+    fn test_peek_does_not_count_access() {
+        let cache = Cache::new(Config::default());
+        cache
+            .set(
+                "key".to_string(),
+                Value::String("value".to_string()),
+                SetOptions::default(),
+            )
+            .unwrap();
 
-        // Add expired entries distributed across shards
-        for i in 0..total_expired {
-            cache
-                .set(
-                    format!("exp_{}", i),
-                    Value::String(i.to_string()),
-                    SetOptions {
-                        ttl: Some(Duration::from_millis(1)),
-                        ..Default::default()
-                    },
-                )
-                .unwrap();
-        }
+        let (value, size) = cache.peek("key").unwrap();
+        assert_eq!(value, Value::String("value".to_string()));
+        assert!(size > "value".len());
+        assert!(cache.peek("missing").is_none());
+        assert_eq!(cache.stats().hits.load(Ordering::Relaxed), 0);
+        assert_eq!(cache.stats().misses.load(Ordering::Relaxed), 0);
+    }
 
-        // Add some persistent entries
-        for i in 0..5 {
+    #[test]
+    fn test_children_pagination() {
+        let cache = Cache::new(Config::default());
+        let set = |key: &str, parent: Option<&str>| {
             cache
                 .set(
-                    format!("keep_{}", i),
-                    Value::String(format!("keep_{}", i)),
-                    SetOptions::default(),
+                    key.to_string(),
+                    Value::String(key.to_string()),
+                    linked(parent.as_slice()),
                 )
                 .unwrap();
-        }
-
-        let initial_count = cache.len();
-        assert_eq!(initial_count, total_expired + 5);
-
-        // Wait for expiration
-        std::thread::sleep(Duration::from_millis(10));
-
-        // Run cleanup enough times to hit all shards multiple times
-        let shard_count = cache.data.shards().len();
-        let cleanup_rounds = shard_count * 5;
-
-        let mut cleaned_count = 0;
-        for _ in 0..cleanup_rounds {
-            cleaned_count += cache.cleanup_expired();
-        }
-
-        // Verify cleanup worked
-        assert!(
-            cleaned_count > 0,
-            "Should have cleaned some expired entries"
-        );
-        assert_eq!(cache.len(), 5, "Should only have persistent entries left");
+        };
 
-        // Verify specific entries
-        for i in 0..5 {
-            assert!(
-                cache.get(&format!("keep_{}", i)).is_some(),
-                "Persistent entry should exist"
-            );
+        set("root", None);
+        for child in ["c", "a", "b"] {
+            set(child, Some("root"));
         }
+        set("a1", Some("a"));
 
-        // Verify expired entries are gone (test a few samples)
-        for i in 0..std::cmp::min(10, total_expired) {
-            assert!(
-                cache.get(&format!("exp_{}", i)).is_none(),
-                "Expired entry should be gone"
-            );
-        }
+        let keys = |page: Vec<(String, u64)>| page.into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+        assert_eq!(keys(cache.children_page("root", 1, 0, 10)), ["a", "b", "c"]);
+        assert_eq!(keys(cache.children_page("root", 2, 0, 2)), ["a", "b"]);
+        assert_eq!(keys(cache.children_page("root", 2, 2, 2)), ["c", "a1"]);
+        assert!(cache.children_page("root", 2, 4, 2).is_empty());
     }
 
     #[test]
-    fn test_complex_cycle_detection() {
+    fn test_miss_notifications() {
         let config = Config {
-            enable_dependencies: true,
+            notify_misses: true,
             ..Default::default()
         };
         let cache = Cache::new(config);
+        let mut events = cache.subscribe();
 
-        // Set up a complex chain: a -> b -> c -> d
         cache
             .set(
-                "d".to_string(),
-                Value::String("d".to_string()),
+                "present".to_string(),
+                Value::String("v".to_string()),
                 SetOptions::default(),
             )
             .unwrap();
-        cache
-            .set(
-                "c".to_string(),
-                Value::String("c".to_string()),
-                SetOptions {
-                    parent: Some("d".to_string()),
-                    ..Default::default()
-                },
-            )
-            .unwrap();
-        cache
-            .set(
-                "b".to_string(),
-                Value::String("b".to_string()),
-                SetOptions {
-                    parent: Some("c".to_string()),
-                    ..Default::default()
-                },
-            )
-            .unwrap();
-        cache
-            .set(
-                "a".to_string(),
-                Value::String("a".to_string()),
-                SetOptions {
-                    parent: Some("b".to_string()),
-                    ..Default::default()
-                },
-            )
-            .unwrap();
-
-        // Now try to create a cycle: d -> a (should fail)
-        let result = cache.set(
-            "d".to_string(),
-            Value::String("d2".to_string()),
-            SetOptions {
-                parent: Some("a".to_string()),
-                ..Default::default()
-            },
-        );
-        assert!(matches!(result, Err(CacheError::DependencyCycle(..))));
+        assert!(cache.get("present").is_some());
+        assert!(cache.get("absent").is_none());
 
-        // Try intermediate cycle: c -> a (should also fail)
-        let result = cache.set(
-            "c".to_string(),
-            Value::String("c2".to_string()),
-            SetOptions {
-                parent: Some("a".to_string()),
-                ..Default::default()
-            },
+        assert_eq!(
+            events.try_recv().unwrap(),
+            CacheEvent::Miss {
+                key: "absent".to_string()
+            }
         );
-        assert!(matches!(result, Err(CacheError::DependencyCycle(..))));
+        assert!(events.try_recv().is_err());
     }
 
     // This test is for a helper function and does not need changes
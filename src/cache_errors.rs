@@ -9,9 +9,212 @@ pub enum CacheError {
     #[error("Setting parent '{1}' for key '{0}' would create a dependency cycle.")]
     DependencyCycle(String, String),
 
+    #[error("Dependency chain through key '{0}' would exceed the maximum depth of {1}.")]
+    DependencyTooDeep(String, usize),
+
     #[error("Memory limit exceeded.")]
     MemoryLimitExceeded,
 
     #[error("Key count limit exceeded.")]
     KeyLimitExceeded,
+
+    #[error("Value is not an integer or out of range.")]
+    NotAnInteger,
+
+    #[error("Increment or decrement would overflow.")]
+    Overflow,
+
+    #[error("Value is not a valid float.")]
+    NotAFloat,
+
+    #[error("Increment would produce NaN or Infinity.")]
+    NotFinite,
+
+    #[error("Operation against a key holding the wrong kind of value")]
+    WrongType,
+
+    #[error("String exceeds maximum allowed size.")]
+    ValueTooLarge,
+
+    #[error("Pinned memory limit exceeded.")]
+    PinnedLimitExceeded,
+
+    #[error("No such key.")]
+    NoSuchKey,
+
+    #[error("Invalid JSON: {0}")]
+    InvalidJson(String),
+
+    #[error("JSON path error: {0}")]
+    JsonPath(String),
+}
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum ValidationError {
+    #[error("unknown command '{0}'")]
+    UnknownCommand(String),
+
+    #[error("wrong number of arguments for '{0}' command")]
+    WrongArity(String),
+
+    #[error("{0} and {1} options at the same time are not compatible")]
+    IncompatibleOptions(&'static str, &'static str),
+
+    #[error("invalid {0} in '{1}' command")]
+    OutOfRange(&'static str, String),
+
+    #[error("'{0}' can't be dry-run")]
+    NotDryRunnable(String),
+}
+
+/// Error classes, sent as the leading word of RESP errors so client libraries
+/// raise the matching exception type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    Err,
+    WrongType,
+    Oom,
+    NoPerm,
+    Busy,
+    NotBusy,
+    BusyGroup,
+    ReadOnly,
+    Invalid, // A value rejected by its namespace's schema
+    Locked,  // A key held by an HTTP client's advisory lock
+}
+
+impl ErrorClass {
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            ErrorClass::Err => "ERR",
+            ErrorClass::WrongType => "WRONGTYPE",
+            ErrorClass::Oom => "OOM",
+            ErrorClass::NoPerm => "NOPERM",
+            ErrorClass::Busy => "BUSY",
+            ErrorClass::NotBusy => "NOTBUSY",
+            ErrorClass::BusyGroup => "BUSYGROUP",
+            ErrorClass::ReadOnly => "READONLY",
+            ErrorClass::Invalid => "INVALID",
+            ErrorClass::Locked => "LOCKED",
+        }
+    }
+}
+
+/// Error returned by the executor, tagged with its protocol error class
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandError {
+    pub class: ErrorClass,
+    pub message: String,
+}
+
+impl CommandError {
+    pub fn new(class: ErrorClass, message: impl Into<String>) -> Self {
+        Self {
+            class,
+            message: message.into(),
+        }
+    }
+
+    pub fn err(message: impl Into<String>) -> Self {
+        Self::new(ErrorClass::Err, message)
+    }
+}
+
+impl std::fmt::Display for CommandError {
+    /// RESP wire form, e.g. `OOM Memory limit exceeded.`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.class.prefix(), self.message)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl CacheError {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            CacheError::MemoryLimitExceeded
+            | CacheError::KeyLimitExceeded
+            | CacheError::PinnedLimitExceeded => ErrorClass::Oom,
+            CacheError::WrongType => ErrorClass::WrongType,
+            CacheError::DependenciesDisabled
+            | CacheError::ParentNotFound(_)
+            | CacheError::DependencyCycle(..)
+            | CacheError::DependencyTooDeep(..)
+            | CacheError::NotAnInteger
+            | CacheError::Overflow
+            | CacheError::NotAFloat
+            | CacheError::NotFinite
+            | CacheError::ValueTooLarge
+            | CacheError::NoSuchKey
+            | CacheError::InvalidJson(_)
+            | CacheError::JsonPath(_) => ErrorClass::Err,
+        }
+    }
+}
+
+impl From<CacheError> for CommandError {
+    fn from(e: CacheError) -> Self {
+        Self::new(e.class(), e.to_string())
+    }
+}
+
+impl From<ValidationError> for CommandError {
+    fn from(e: ValidationError) -> Self {
+        Self::err(e.to_string())
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum ParseError {
+    #[error("empty command")]
+    Empty,
+
+    #[error(transparent)]
+    Invalid(#[from] ValidationError),
+
+    #[error("syntax error")]
+    Syntax,
+
+    #[error("value is not an integer or out of range")]
+    NotAnInteger,
+
+    #[error("value is not a valid float")]
+    NotAFloat,
+
+    #[error("invalid UTF-8 in argument")]
+    InvalidUtf8,
+}
+
+impl From<ParseError> for CommandError {
+    fn from(e: ParseError) -> Self {
+        Self::err(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_classes() {
+        let oom: CommandError = CacheError::MemoryLimitExceeded.into();
+        assert_eq!(oom.class, ErrorClass::Oom);
+        assert_eq!(oom.to_string(), "OOM Memory limit exceeded.");
+
+        let wrong: CommandError = CacheError::WrongType.into();
+        assert_eq!(wrong.class, ErrorClass::WrongType);
+        assert!(wrong.to_string().starts_with("WRONGTYPE "));
+
+        assert_eq!(CacheError::KeyLimitExceeded.class(), ErrorClass::Oom);
+        assert_eq!(CacheError::PinnedLimitExceeded.class(), ErrorClass::Oom);
+        assert_eq!(CacheError::NoSuchKey.class(), ErrorClass::Err);
+
+        let invalid: CommandError = ValidationError::WrongArity("get".into()).into();
+        assert_eq!(
+            invalid.to_string(),
+            "ERR wrong number of arguments for 'get' command"
+        );
+        let syntax: CommandError = ParseError::Syntax.into();
+        assert_eq!(syntax.to_string(), "ERR syntax error");
+    }
 }
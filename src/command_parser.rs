@@ -0,0 +1,1195 @@
+use crate::cache::{DependencyEdge, InvalidationMode, SetOptions};
+use crate::cache_errors::ParseError;
+use crate::command_table;
+use crate::eviction::Priority;
+use crate::executor::{Command, HydrateOptions, ObjectSubcommand};
+use crate::mirror::MirrorFilter;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+impl Command {
+    /// Parses a command from its name and arguments, e.g. `["SET", "k", "v", "EX", "10"]`.
+    /// Shared by every protocol layer so the syntax is defined once.
+    pub fn parse(args: &[&[u8]]) -> Result<Command, ParseError> {
+        let (name, rest) = args.split_first().ok_or(ParseError::Empty)?;
+        let name = utf8(name)?.to_ascii_uppercase();
+        let spec = command_table::lookup(&name)
+            .ok_or_else(|| crate::cache_errors::ValidationError::UnknownCommand(name.clone()))?;
+        spec.check_arity(args.len())?;
+
+        let mut args = Args { rest, pos: 0 };
+        let command = match spec.name {
+            "GET" => Command::Get {
+                key: args.string()?,
+            },
+            "SET" => parse_set(&mut args)?,
+            "DEL" | "DELCASCADE" => Command::Del {
+                keys: args.remaining()?,
+                cascade: name == "DELCASCADE",
+            },
+            "EXPIRE" | "PEXPIRE" => Command::Expire {
+                key: args.string()?,
+                ttl: args.integer()?,
+                millis: name == "PEXPIRE",
+            },
+            "EXPIREAT" | "PEXPIREAT" => Command::ExpireAt {
+                key: args.string()?,
+                timestamp: args.integer()?,
+                millis: name == "PEXPIREAT",
+            },
+            "TTL" | "PTTL" => Command::Ttl {
+                key: args.string()?,
+                millis: name == "PTTL",
+            },
+            "EXPIRETIME" | "PEXPIRETIME" => Command::ExpireTime {
+                key: args.string()?,
+                millis: name == "PEXPIRETIME",
+            },
+            "PERSIST" => Command::Persist {
+                key: args.string()?,
+            },
+            "RENAME" => Command::Rename {
+                key: args.string()?,
+                new_key: args.string()?,
+            },
+            "EXISTS" => Command::Exists {
+                keys: args.remaining()?,
+            },
+            "EVAL" => parse_eval(&mut args)?,
+            "SCRIPT" => match args.option()?.as_deref() {
+                Some("KILL") => Command::ScriptKill {},
+                _ => return Err(ParseError::Syntax),
+            },
+            "PING" => Command::Ping {
+                message: args.optional_string()?,
+            },
+            "INFO" => Command::Info {
+                sections: args.remaining()?,
+            },
+            "KEYS" => {
+                let pattern = args.string()?;
+                let (mut limit, mut cursor) = (None, None);
+                while let Some(option) = args.option()? {
+                    match option.as_str() {
+                        "LIMIT" => limit = Some(args.integer()?),
+                        "CURSOR" => cursor = Some(args.integer()?),
+                        _ => return Err(ParseError::Syntax),
+                    }
+                }
+                Command::ListKeys {
+                    pattern,
+                    limit,
+                    cursor,
+                }
+            }
+            "SCAN" => {
+                let cursor = args.integer()?;
+                let (mut pattern, mut count) = (None, None);
+                while let Some(option) = args.option()? {
+                    match option.as_str() {
+                        "MATCH" => pattern = Some(args.string()?),
+                        "COUNT" => count = Some(args.integer()?),
+                        _ => return Err(ParseError::Syntax),
+                    }
+                }
+                Command::Scan {
+                    cursor,
+                    pattern,
+                    count,
+                }
+            }
+            "FLUSHALL" => {
+                // ASYNC/SYNC are accepted for compatibility; flushing is always immediate
+                while let Some(option) = args.option()? {
+                    if option != "ASYNC" && option != "SYNC" {
+                        return Err(ParseError::Syntax);
+                    }
+                }
+                Command::FlushAll {}
+            }
+            "FLUSHPATTERN" => Command::FlushPattern {
+                pattern: args.string()?,
+            },
+            "MEMORY" => match args.option()?.as_deref() {
+                Some("PURGE") => Command::MemoryPurge {},
+                _ => return Err(ParseError::Syntax),
+            },
+            "BGSAVE" => {
+                // SCHEDULE is accepted for compatibility; a save already running is an error
+                while let Some(option) = args.option()? {
+                    if option != "SCHEDULE" {
+                        return Err(ParseError::Syntax);
+                    }
+                }
+                Command::BgSave {}
+            }
+            "REPLICAOF" => {
+                let host = args.string()?;
+                let port = args.string()?;
+                let primary = if host.eq_ignore_ascii_case("NO") && port.eq_ignore_ascii_case("ONE")
+                {
+                    None
+                } else {
+                    Some((host, port.parse().map_err(|_| ParseError::NotAnInteger)?))
+                };
+                Command::ReplicaOf { primary }
+            }
+            // MIRROR host port [MATCH pattern]... [NAMESPACE namespace]..., or MIRROR NO ONE
+            "MIRROR" => {
+                let host = args.string()?;
+                let port = args.string()?;
+                if host.eq_ignore_ascii_case("NO") && port.eq_ignore_ascii_case("ONE") {
+                    Command::Mirror {
+                        target: None,
+                        filter: MirrorFilter::default(),
+                    }
+                } else {
+                    let port = port.parse().map_err(|_| ParseError::NotAnInteger)?;
+                    let mut filter = MirrorFilter::default();
+                    while let Some(option) = args.option()? {
+                        match option.as_str() {
+                            "MATCH" => filter.patterns.push(args.string()?),
+                            "NAMESPACE" => filter.namespaces.push(args.string()?),
+                            _ => return Err(ParseError::Syntax),
+                        }
+                    }
+                    Command::Mirror {
+                        target: Some((host, port)),
+                        filter,
+                    }
+                }
+            }
+            "PUBLISH" => Command::Publish {
+                channel: args.string()?,
+                message: args.string()?,
+            },
+            "INCRBY" => {
+                let key = args.string()?;
+                let delta = args.integer()?;
+                let mut limit = None;
+                while let Some(option) = args.option()? {
+                    match option.as_str() {
+                        "LIMIT" => limit = Some(args.integer()?),
+                        _ => return Err(ParseError::Syntax),
+                    }
+                }
+                Command::IncrBy { key, delta, limit }
+            }
+            "INCR" | "DECR" => Command::IncrBy {
+                key: args.string()?,
+                delta: if spec.name == "INCR" { 1 } else { -1 },
+                limit: None,
+            },
+            "DECRBY" => Command::IncrBy {
+                key: args.string()?,
+                delta: args
+                    .integer::<i64>()?
+                    .checked_neg()
+                    .ok_or(ParseError::NotAnInteger)?,
+                limit: None,
+            },
+            "INCRBYFLOAT" => Command::IncrByFloat {
+                key: args.string()?,
+                delta: args.float()?,
+            },
+            "APPEND" => Command::Append {
+                key: args.string()?,
+                value: args.bytes()?,
+            },
+            "STRLEN" => Command::StrLen {
+                key: args.string()?,
+            },
+            "GETRANGE" => Command::GetRange {
+                key: args.string()?,
+                start: args.integer()?,
+                end: args.integer()?,
+            },
+            "SETRANGE" => Command::SetRange {
+                key: args.string()?,
+                offset: args.integer()?,
+                value: args.bytes()?,
+            },
+            "HSET" => {
+                let key = args.string()?;
+                let rest = args.remaining()?;
+                if rest.len() % 2 != 0 {
+                    return Err(ParseError::Syntax);
+                }
+                let fields = rest
+                    .chunks_exact(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect();
+                Command::HSet { key, fields }
+            }
+            "HGET" => Command::HGet {
+                key: args.string()?,
+                field: args.string()?,
+            },
+            "HDEL" => Command::HDel {
+                key: args.string()?,
+                fields: args.remaining()?,
+            },
+            "HGETALL" => Command::HGetAll {
+                key: args.string()?,
+            },
+            "HINCRBY" => Command::HIncrBy {
+                key: args.string()?,
+                field: args.string()?,
+                delta: args.integer()?,
+            },
+            "JSON.SET" => parse_json_set(&mut args)?,
+            // JSON.GET and JSON.DEL key [path], the path defaulting to the root
+            "JSON.GET" => Command::JsonGet {
+                key: args.string()?,
+                path: args.optional_string()?.unwrap_or_else(|| "$".to_string()),
+            },
+            "JSON.DEL" => Command::JsonDel {
+                key: args.string()?,
+                path: args.optional_string()?.unwrap_or_else(|| "$".to_string()),
+            },
+            "PFADD" => Command::PfAdd {
+                key: args.string()?,
+                members: args.remaining()?,
+            },
+            "PFCOUNT" => Command::PfCount {
+                keys: args.remaining()?,
+            },
+            "PFMERGE" => Command::PfMerge {
+                dest: args.string()?,
+                sources: args.remaining()?,
+            },
+            "SADD" => Command::SAdd {
+                key: args.string()?,
+                members: args.remaining()?,
+            },
+            "SREM" => Command::SRem {
+                key: args.string()?,
+                members: args.remaining()?,
+            },
+            "SMEMBERS" => Command::SMembers {
+                key: args.string()?,
+            },
+            "SISMEMBER" => Command::SIsMember {
+                key: args.string()?,
+                member: args.string()?,
+            },
+            "SINTER" => Command::SInter {
+                keys: args.remaining()?,
+            },
+            "SUNION" => Command::SUnion {
+                keys: args.remaining()?,
+            },
+            "HEXPIRE" => Command::HExpire {
+                key: args.string()?,
+                seconds: args.integer()?,
+                fields: parse_fields(&mut args)?,
+            },
+            "HTTL" => Command::HTtl {
+                key: args.string()?,
+                fields: parse_fields(&mut args)?,
+            },
+            "HPERSIST" => Command::HPersist {
+                key: args.string()?,
+                fields: parse_fields(&mut args)?,
+            },
+            "EXPIRING" => {
+                let within = args.integer()?;
+                let mut limit = None;
+                while let Some(option) = args.option()? {
+                    match option.as_str() {
+                        "LIMIT" => limit = Some(args.integer()?),
+                        _ => return Err(ParseError::Syntax),
+                    }
+                }
+                Command::ExpiringKeys { within, limit }
+            }
+            "SETPARENT" => Command::SetParent {
+                key: args.string()?,
+                parent: args.string()?,
+            },
+            "ADDPARENT" => Command::AddParent {
+                key: args.string()?,
+                parent: args.string()?,
+            },
+            "GETPARENT" => Command::GetParent {
+                key: args.string()?,
+            },
+            "GETPARENTS" => Command::GetParents {
+                key: args.string()?,
+            },
+            // UNSETPARENT key [parent]
+            "UNSETPARENT" => Command::UnsetParent {
+                key: args.string()?,
+                parent: args.optional_string()?,
+            },
+            "REPARENT" => Command::ReparentChildren {
+                from: args.string()?,
+                to: args.string()?,
+            },
+            // IMPORTGRAPH key parent [key parent ...]
+            "IMPORTGRAPH" => {
+                let pairs = args.remaining()?;
+                if pairs.len() % 2 != 0 {
+                    return Err(ParseError::Syntax);
+                }
+                let edges = pairs
+                    .chunks(2)
+                    .map(|pair| DependencyEdge {
+                        key: pair[0].clone(),
+                        parent: pair[1].clone(),
+                    })
+                    .collect();
+                Command::ImportGraph { edges }
+            }
+            "GETCHILDREN" => parse_get_children(&mut args)?,
+            "KEYINFO" => Command::GetInfo {
+                key: args.string()?,
+            },
+            "XREAD" => parse_xread(&mut args)?,
+            "TYPE" => Command::Type {
+                key: args.string()?,
+            },
+            "OBJECT" => {
+                let subcommand = match args.option()?.as_deref() {
+                    Some("ENCODING") => ObjectSubcommand::Encoding,
+                    Some("FREQ") => ObjectSubcommand::Freq,
+                    _ => return Err(ParseError::Syntax),
+                };
+                Command::Object {
+                    subcommand,
+                    key: args.string()?,
+                }
+            }
+            // INVALIDATE DELETE|STALE root [root ...]
+            // INVALIDATE_TAG DELETE|STALE tag [tag ...]
+            "INVALIDATE" | "INVALIDATE_TAG" => {
+                let mode = match args.option()?.as_deref() {
+                    Some("DELETE") => InvalidationMode::Delete,
+                    Some("STALE") => InvalidationMode::Stale,
+                    _ => return Err(ParseError::Syntax),
+                };
+                let targets = args.remaining()?;
+                match spec.name {
+                    "INVALIDATE" => Command::Invalidate {
+                        roots: targets,
+                        mode,
+                    },
+                    _ => Command::InvalidateTag {
+                        tags: targets,
+                        mode,
+                    },
+                }
+            }
+            "DRYRUN" => {
+                args.pos = rest.len();
+                Command::DryRun {
+                    command: Box::new(Command::parse(rest)?),
+                }
+            }
+            _ => return Err(ParseError::Syntax),
+        };
+
+        if args.pos < args.rest.len() {
+            return Err(ParseError::Syntax);
+        }
+        Ok(command)
+    }
+
+    /// The inverse of `parse`: the command line that reproduces this command
+    pub fn to_args(&self) -> Vec<Vec<u8>> {
+        let mut args = vec![self.name().as_bytes().to_vec()];
+        let mut push = |arg: &dyn ToArg| args.push(arg.to_arg());
+
+        match self {
+            Command::DryRun { command } => command.to_args().iter().for_each(|arg| push(&Raw(arg))),
+            Command::Get { key }
+            | Command::Ttl { key, .. }
+            | Command::ExpireTime { key, .. }
+            | Command::Persist { key }
+            | Command::GetParent { key }
+            | Command::GetParents { key }
+            | Command::UnsetParent { key, parent: None }
+            | Command::GetInfo { key }
+            | Command::StrLen { key }
+            | Command::HGetAll { key }
+            | Command::SMembers { key }
+            | Command::Type { key } => push(key),
+            Command::Object { subcommand, key } => {
+                push(&match subcommand {
+                    ObjectSubcommand::Encoding => "ENCODING",
+                    ObjectSubcommand::Freq => "FREQ",
+                });
+                push(key);
+            }
+            // The write concern isn't part of the logged command; replay is never acknowledged
+            Command::Set {
+                key,
+                value,
+                options,
+                ..
+            } => {
+                push(key);
+                push(&Raw(value));
+                if let Some(ttl) = options.ttl {
+                    push(&"PX");
+                    push(&ttl.as_millis());
+                }
+                if options.nx {
+                    push(&"NX");
+                }
+                if options.xx {
+                    push(&"XX");
+                }
+                for parent in &options.parents {
+                    push(&"PARENT");
+                    push(parent);
+                }
+                if let Some(ttl) = options.parent_ttl {
+                    push(&"PARENTPX");
+                    push(&ttl.as_millis());
+                }
+                if options.pinned {
+                    push(&"PIN");
+                }
+                if options.priority != Priority::Normal {
+                    push(&"PRIORITY");
+                    push(&options.priority);
+                }
+                if let Some(percent) = options.jitter {
+                    push(&"JITTER");
+                    push(&percent);
+                }
+                // One per tag, so the list never depends on how tags are joined
+                for tag in &options.tags {
+                    push(&"TAGS");
+                    push(tag);
+                }
+            }
+            Command::Del { keys, .. }
+            | Command::Exists { keys }
+            | Command::SInter { keys }
+            | Command::SUnion { keys }
+            | Command::PfCount { keys } => keys.iter().for_each(|k| push(k)),
+            Command::Info { sections } => sections.iter().for_each(|s| push(s)),
+            Command::JsonSet {
+                key,
+                path,
+                value,
+                nx,
+                xx,
+            } => {
+                push(key);
+                push(path);
+                push(value);
+                if *nx {
+                    push(&"NX");
+                }
+                if *xx {
+                    push(&"XX");
+                }
+            }
+            Command::JsonGet { key, path } | Command::JsonDel { key, path } => {
+                push(key);
+                push(path);
+            }
+            Command::Eval { script, keys, args } => {
+                push(script);
+                push(&keys.len());
+                keys.iter().chain(args).for_each(|arg| push(arg));
+            }
+            Command::ScriptKill {} => push(&"KILL"),
+            Command::Expire { key, ttl, .. } => {
+                push(key);
+                push(ttl);
+            }
+            Command::ExpireAt { key, timestamp, .. } => {
+                push(key);
+                push(timestamp);
+            }
+            Command::Ping { message } => {
+                if let Some(message) = message {
+                    push(message);
+                }
+            }
+            Command::Scan {
+                cursor,
+                pattern,
+                count,
+            } => {
+                push(cursor);
+                if let Some(pattern) = pattern {
+                    push(&"MATCH");
+                    push(pattern);
+                }
+                if let Some(count) = count {
+                    push(&"COUNT");
+                    push(count);
+                }
+            }
+            Command::ListKeys {
+                pattern,
+                limit,
+                cursor,
+            } => {
+                push(pattern);
+                if let Some(limit) = limit {
+                    push(&"LIMIT");
+                    push(limit);
+                }
+                if let Some(cursor) = cursor {
+                    push(&"CURSOR");
+                    push(cursor);
+                }
+            }
+            Command::FlushAll {} | Command::BgSave {} => {}
+            Command::MemoryPurge {} => push(&"PURGE"),
+            Command::FlushPattern { pattern } => push(pattern),
+            Command::Publish { channel, message } => {
+                push(channel);
+                push(message);
+            }
+            Command::Mirror { target, filter } => match target {
+                Some((host, port)) => {
+                    push(host);
+                    push(port);
+                    for pattern in &filter.patterns {
+                        push(&"MATCH");
+                        push(pattern);
+                    }
+                    for namespace in &filter.namespaces {
+                        push(&"NAMESPACE");
+                        push(namespace);
+                    }
+                }
+                None => {
+                    push(&"NO");
+                    push(&"ONE");
+                }
+            },
+            Command::ReplicaOf { primary } => match primary {
+                Some((host, port)) => {
+                    push(host);
+                    push(port);
+                }
+                None => {
+                    push(&"NO");
+                    push(&"ONE");
+                }
+            },
+            Command::IncrByFloat { key, delta } => {
+                push(key);
+                push(delta);
+            }
+            Command::Append { key, value } => {
+                push(key);
+                push(&Raw(value));
+            }
+            Command::GetRange { key, start, end } => {
+                push(key);
+                push(start);
+                push(end);
+            }
+            Command::SetRange { key, offset, value } => {
+                push(key);
+                push(offset);
+                push(&Raw(value));
+            }
+            Command::IncrBy { key, delta, limit } => {
+                push(key);
+                push(delta);
+                if let Some(limit) = limit {
+                    push(&"LIMIT");
+                    push(limit);
+                }
+            }
+            Command::HExpire {
+                key,
+                seconds,
+                fields,
+            } => {
+                push(key);
+                push(seconds);
+                push(&"FIELDS");
+                push(&fields.len());
+                fields.iter().for_each(|f| push(f));
+            }
+            Command::HSet { key, fields } => {
+                push(key);
+                for (field, value) in fields {
+                    push(field);
+                    push(value);
+                }
+            }
+            Command::HGet { key, field } => {
+                push(key);
+                push(field);
+            }
+            Command::HDel { key, fields }
+            | Command::SAdd {
+                key,
+                members: fields,
+            }
+            | Command::SRem {
+                key,
+                members: fields,
+            }
+            | Command::PfAdd {
+                key,
+                members: fields,
+            }
+            | Command::PfMerge {
+                dest: key,
+                sources: fields,
+            } => {
+                push(key);
+                fields.iter().for_each(|f| push(f));
+            }
+            Command::SIsMember { key, member } => {
+                push(key);
+                push(member);
+            }
+            Command::HIncrBy { key, field, delta } => {
+                push(key);
+                push(field);
+                push(delta);
+            }
+            Command::HTtl { key, fields } | Command::HPersist { key, fields } => {
+                push(key);
+                push(&"FIELDS");
+                push(&fields.len());
+                fields.iter().for_each(|f| push(f));
+            }
+            Command::XRead { count, streams } => {
+                if let Some(count) = count {
+                    push(&"COUNT");
+                    push(count);
+                }
+                push(&"STREAMS");
+                streams.iter().for_each(|(key, _)| push(key));
+                streams.iter().for_each(|(_, id)| push(id));
+            }
+            Command::ExpiringKeys { within, limit } => {
+                push(within);
+                if let Some(limit) = limit {
+                    push(&"LIMIT");
+                    push(limit);
+                }
+            }
+            Command::SetParent { key, parent }
+            | Command::AddParent { key, parent }
+            | Command::UnsetParent {
+                key,
+                parent: Some(parent),
+            } => {
+                push(key);
+                push(parent);
+            }
+            Command::ReparentChildren { from, to }
+            | Command::Rename {
+                key: from,
+                new_key: to,
+            } => {
+                push(from);
+                push(to);
+            }
+            Command::ImportGraph { edges } => edges.iter().for_each(|edge| {
+                push(&edge.key);
+                push(&edge.parent);
+            }),
+            Command::GetChildren {
+                parent,
+                depth,
+                offset,
+                limit,
+                hydrate,
+            } => {
+                push(parent);
+                for (name, value) in [("DEPTH", depth), ("OFFSET", offset), ("LIMIT", limit)] {
+                    if let Some(value) = value {
+                        push(&name);
+                        push(value);
+                    }
+                }
+                if let Some(hydrate) = hydrate {
+                    push(&"HYDRATE");
+                    push(&"MAXBYTES");
+                    push(&hydrate.max_bytes);
+                }
+            }
+            Command::Invalidate {
+                roots: targets,
+                mode,
+            }
+            | Command::InvalidateTag {
+                tags: targets,
+                mode,
+            } => {
+                push(&match mode {
+                    InvalidationMode::Delete => "DELETE",
+                    InvalidationMode::Stale => "STALE",
+                });
+                targets.iter().for_each(|t| push(t));
+            }
+        }
+        args
+    }
+}
+
+/// JSON.SET key path value [NX | XX]
+fn parse_json_set(args: &mut Args) -> Result<Command, ParseError> {
+    let key = args.string()?;
+    let path = args.string()?;
+    let value = args.string()?;
+    let (mut nx, mut xx) = (false, false);
+    while let Some(option) = args.option()? {
+        match option.as_str() {
+            "NX" => nx = true,
+            "XX" => xx = true,
+            _ => return Err(ParseError::Syntax),
+        }
+    }
+    Ok(Command::JsonSet {
+        key,
+        path,
+        value,
+        nx,
+        xx,
+    })
+}
+
+/// SET key value [EX seconds | PX milliseconds | PXAT unix-milliseconds] [NX | XX]
+///     [PARENT key [PARENT key ...] [PARENTEX seconds | PARENTPX milliseconds]] [PIN]
+///     [PRIORITY low|normal|high] [JITTER percent] [TAGS tag[,tag ...] ...]
+fn parse_set(args: &mut Args) -> Result<Command, ParseError> {
+    let key = args.string()?;
+    let value = args.bytes()?;
+    let mut options = SetOptions::default();
+    let mut concern = None;
+
+    while let Some(option) = args.option()? {
+        match option.as_str() {
+            "EX" if options.ttl.is_none() => {
+                options.ttl = Some(Duration::from_secs(args.integer()?));
+            }
+            "PX" if options.ttl.is_none() => {
+                options.ttl = Some(Duration::from_millis(args.integer()?));
+            }
+            // An absolute deadline, as logged for jittered writes, is kept exactly
+            "PXAT" if options.ttl.is_none() => {
+                let at = UNIX_EPOCH + Duration::from_millis(args.integer()?);
+                // One already past still has to expire the key, so it waits the shortest TTL
+                let left = at.duration_since(SystemTime::now()).unwrap_or_default();
+                options.ttl = Some(left.max(Duration::from_millis(1)));
+                options.jitter = Some(0);
+            }
+            "NX" => options.nx = true,
+            "XX" => options.xx = true,
+            "PARENT" => options.parents.push(args.string()?),
+            "PARENTEX" if options.parent_ttl.is_none() => {
+                options.parent_ttl = Some(Duration::from_secs(args.integer()?));
+            }
+            "PARENTPX" if options.parent_ttl.is_none() => {
+                options.parent_ttl = Some(Duration::from_millis(args.integer()?));
+            }
+            "PIN" => options.pinned = true,
+            "PRIORITY" => {
+                options.priority = args.string()?.parse().map_err(|_| ParseError::Syntax)?
+            }
+            "JITTER" if options.jitter.is_none() => options.jitter = Some(args.integer()?),
+            "TAGS" => {
+                let tags = args.string()?;
+                if tags.split(',').any(str::is_empty) {
+                    return Err(ParseError::Syntax);
+                }
+                options.tags.extend(tags.split(',').map(str::to_string));
+            }
+            "WRITECONCERN" if concern.is_none() => {
+                concern = Some(args.string()?.parse().map_err(|_| ParseError::Syntax)?);
+            }
+            _ => return Err(ParseError::Syntax),
+        }
+    }
+    if options.parent_ttl.is_some() && options.parents.is_empty() {
+        return Err(ParseError::Syntax);
+    }
+
+    Ok(Command::Set {
+        key,
+        value,
+        options,
+        concern,
+    })
+}
+
+/// EVAL script numkeys [key ...] [arg ...]
+fn parse_eval(args: &mut Args) -> Result<Command, ParseError> {
+    let script = args.string()?;
+    let numkeys: usize = args.integer()?;
+    if numkeys > args.rest.len() - args.pos {
+        return Err(ParseError::Syntax);
+    }
+    let keys = (0..numkeys)
+        .map(|_| args.string())
+        .collect::<Result<_, _>>()?;
+
+    Ok(Command::Eval {
+        script,
+        keys,
+        args: args.remaining()?,
+    })
+}
+
+/// GETCHILDREN key [DEPTH n] [OFFSET n] [LIMIT n] [HYDRATE [MAXBYTES n]]
+fn parse_get_children(args: &mut Args) -> Result<Command, ParseError> {
+    let parent = args.string()?;
+    let (mut depth, mut offset, mut limit, mut hydrate) = (None, None, None, None);
+
+    while let Some(option) = args.option()? {
+        match option.as_str() {
+            "DEPTH" => depth = Some(args.integer()?),
+            "OFFSET" => offset = Some(args.integer()?),
+            "LIMIT" => limit = Some(args.integer()?),
+            "HYDRATE" => hydrate = Some(HydrateOptions::default()),
+            "MAXBYTES" => match &mut hydrate {
+                Some(options) => options.max_bytes = args.integer()?,
+                None => return Err(ParseError::Syntax),
+            },
+            _ => return Err(ParseError::Syntax),
+        }
+    }
+
+    Ok(Command::GetChildren {
+        parent,
+        depth,
+        offset,
+        limit,
+        hydrate,
+    })
+}
+
+/// XREAD [COUNT n] STREAMS key [key ...] id [id ...]. `$` (new entries only) is
+/// refused: without BLOCK it can never return anything
+fn parse_xread(args: &mut Args) -> Result<Command, ParseError> {
+    let mut count = None;
+    loop {
+        match args.option()?.as_deref() {
+            Some("COUNT") => count = Some(args.integer()?),
+            Some("STREAMS") => break,
+            _ => return Err(ParseError::Syntax),
+        }
+    }
+
+    let rest = args.remaining()?;
+    if rest.is_empty() || rest.len() % 2 != 0 {
+        return Err(ParseError::Syntax);
+    }
+    let (keys, ids) = rest.split_at(rest.len() / 2);
+    let streams = keys
+        .iter()
+        .zip(ids)
+        .map(|(key, id)| {
+            let id = id.parse().map_err(|_| ParseError::Syntax)?;
+            Ok((key.clone(), id))
+        })
+        .collect::<Result<_, ParseError>>()?;
+
+    Ok(Command::XRead { count, streams })
+}
+
+/// FIELDS numfields field [field ...]
+fn parse_fields(args: &mut Args) -> Result<Vec<String>, ParseError> {
+    if args.option()?.as_deref() != Some("FIELDS") {
+        return Err(ParseError::Syntax);
+    }
+    let count: usize = args.integer()?;
+    let fields = args.remaining()?;
+    if count == 0 || fields.len() != count {
+        return Err(ParseError::Syntax);
+    }
+    Ok(fields)
+}
+
+/// An argument of a command line, as `to_args` writes it
+trait ToArg {
+    fn to_arg(&self) -> Vec<u8>;
+}
+
+impl<T: ToString + ?Sized> ToArg for T {
+    fn to_arg(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+/// A value argument written as is, since it needn't be UTF-8
+struct Raw<'a>(&'a [u8]);
+
+impl ToArg for Raw<'_> {
+    fn to_arg(&self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+}
+
+struct Args<'a> {
+    rest: &'a [&'a [u8]],
+    pos: usize,
+}
+
+impl Args<'_> {
+    fn next(&mut self) -> Option<&[u8]> {
+        let arg = self.rest.get(self.pos)?;
+        self.pos += 1;
+        Some(arg)
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        let arg = self.next().ok_or(ParseError::Syntax)?;
+        utf8(arg).map(str::to_string)
+    }
+
+    /// Next argument as is, for values that needn't be UTF-8
+    fn bytes(&mut self) -> Result<Vec<u8>, ParseError> {
+        self.next().map(<[u8]>::to_vec).ok_or(ParseError::Syntax)
+    }
+
+    fn optional_string(&mut self) -> Result<Option<String>, ParseError> {
+        self.next()
+            .map(|arg| utf8(arg).map(str::to_string))
+            .transpose()
+    }
+
+    fn integer<T: std::str::FromStr>(&mut self) -> Result<T, ParseError> {
+        let arg = self.next().ok_or(ParseError::Syntax)?;
+        utf8(arg)?.parse().map_err(|_| ParseError::NotAnInteger)
+    }
+
+    fn float(&mut self) -> Result<f64, ParseError> {
+        let arg = self.next().ok_or(ParseError::Syntax)?;
+        utf8(arg)?
+            .parse()
+            .ok()
+            .filter(|f: &f64| f.is_finite())
+            .ok_or(ParseError::NotAFloat)
+    }
+
+    /// Next argument as an upper-cased option keyword
+    fn option(&mut self) -> Result<Option<String>, ParseError> {
+        self.next()
+            .map(|arg| utf8(arg).map(str::to_ascii_uppercase))
+            .transpose()
+    }
+
+    fn remaining(&mut self) -> Result<Vec<String>, ParseError> {
+        std::iter::from_fn(|| self.next().map(|arg| utf8(arg).map(str::to_string))).collect()
+    }
+}
+
+fn utf8(arg: &[u8]) -> Result<&str, ParseError> {
+    std::str::from_utf8(arg).map_err(|_| ParseError::InvalidUtf8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aof::WriteConcern;
+    use crate::cache_errors::ValidationError;
+
+    fn parse(line: &str) -> Result<Command, ParseError> {
+        let args: Vec<&[u8]> = line.split_whitespace().map(str::as_bytes).collect();
+        Command::parse(&args)
+    }
+
+    #[test]
+    fn test_parse_set() {
+        let Ok(Command::Set {
+            key,
+            value,
+            options,
+            concern,
+        }) = parse("set k v px 1500 NX parent p WRITECONCERN fsynced")
+        else {
+            panic!("expected SET");
+        };
+        assert_eq!((key.as_str(), value.as_slice()), ("k", &b"v"[..]));
+        assert_eq!(options.ttl, Some(Duration::from_millis(1500)));
+        assert_eq!(concern, Some(WriteConcern::Fsynced));
+        assert!(options.nx && !options.xx);
+        assert_eq!(options.parents, ["p"]);
+
+        assert_eq!(
+            parse("SET k v EX ten").unwrap_err(),
+            ParseError::NotAnInteger
+        );
+        assert_eq!(parse("SET k v EX 1 PX 1").unwrap_err(), ParseError::Syntax);
+        assert_eq!(parse("SET k v BOGUS").unwrap_err(), ParseError::Syntax);
+
+        let Ok(Command::Set { options, .. }) = parse("SET k v TAGS a,b TAGS c") else {
+            panic!("expected SET");
+        };
+        assert_eq!(options.tags, ["a", "b", "c"]);
+        assert_eq!(parse("SET k v TAGS a,,b").unwrap_err(), ParseError::Syntax);
+
+        // A deadline is taken as is; one already past expires the key at once
+        let Ok(Command::Set { options, .. }) = parse("SET k v PXAT 1700000000000") else {
+            panic!("expected SET");
+        };
+        assert_eq!(
+            (options.ttl, options.jitter),
+            (Some(Duration::from_millis(1)), Some(0))
+        );
+        assert_eq!(
+            parse("SET k v PXAT 1 JITTER 5").unwrap_err(),
+            ParseError::Syntax
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Command::parse(&[]).unwrap_err(), ParseError::Empty);
+        assert_eq!(
+            parse("NOPE k").unwrap_err(),
+            ParseError::Invalid(ValidationError::UnknownCommand("NOPE".to_string()))
+        );
+        assert_eq!(
+            parse("GET a b").unwrap_err(),
+            ParseError::Invalid(ValidationError::WrongArity("get".to_string()))
+        );
+        assert!(matches!(
+            Command::parse(&[b"GET", &[0xff]]),
+            Err(ParseError::InvalidUtf8)
+        ));
+    }
+
+    #[test]
+    fn test_to_args_round_trips() {
+        for line in [
+            "SET k v PX 1500 NX PARENT p PIN",
+            "SET k v PRIORITY low",
+            "SET k v PARENT p PARENTPX 60000",
+            "SET k v PARENT p PARENT q",
+            "ADDPARENT k p",
+            "GETPARENTS k",
+            "UNSETPARENT k",
+            "UNSETPARENT k p",
+            "SET k v PX 60000 JITTER 10",
+            "SET k v TAGS a TAGS b",
+            "DEL a b",
+            "DELCASCADE a b",
+            "EVAL s 2 a b c",
+            "SCRIPT KILL",
+            "PING",
+            "PEXPIRE k 1500",
+            "PEXPIREAT k 1700000000000",
+            "EXPIREAT k 1700000000",
+            "PTTL k",
+            "EXPIRETIME k",
+            "PEXPIRETIME k",
+            "INFO",
+            "INFO memory keyspace",
+            "BGSAVE",
+            "FLUSHPATTERN session:*",
+            "REPLICAOF redis.internal 6379",
+            "REPLICAOF NO ONE",
+            "MIRROR replica.internal 6380 MATCH user:* NAMESPACE session",
+            "MIRROR NO ONE",
+            "PUBLISH news hello",
+            "KEYS user:* LIMIT 10 CURSOR 3",
+            "SCAN 0",
+            "SCAN 7 MATCH user:? COUNT 100",
+            "DRYRUN FLUSHPATTERN session:*",
+            "INCRBY k -3 LIMIT 10",
+            "INCRBYFLOAT k 0.5",
+            "APPEND log line",
+            "GETRANGE k 0 -1",
+            "SETRANGE k 4 ab",
+            "HSET h a 1 b 2",
+            "HGET h a",
+            "RENAME a b",
+            "HDEL h a b",
+            "HGETALL h",
+            "HINCRBY h a -2",
+            "SADD s a b",
+            "PFADD h a b",
+            "PFCOUNT h1 h2",
+            "PFMERGE h h1 h2",
+            "JSON.SET doc $.a 1 NX",
+            "JSON.GET doc $.a",
+            "JSON.DEL doc $",
+            "SREM s a",
+            "SMEMBERS s",
+            "SISMEMBER s a",
+            "SINTER s t",
+            "SUNION s t",
+            "HEXPIRE h 10 FIELDS 2 a b",
+            "XREAD COUNT 5 STREAMS s1 s2 0-0 7-1",
+            "GETCHILDREN p DEPTH 3 HYDRATE MAXBYTES 10",
+            "INVALIDATE STALE a b",
+            "IMPORTGRAPH a p b q",
+            "INVALIDATE_TAG DELETE t",
+            "OBJECT ENCODING k",
+            "OBJECT FREQ k",
+            "MEMORY PURGE",
+        ] {
+            assert_eq!(parse(line).unwrap().to_args().join(&b' '), line.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_parse_counters() {
+        for (line, expected) in [("INCR k", 1), ("DECR k", -1), ("DECRBY k 5", -5)] {
+            assert!(
+                matches!(parse(line), Ok(Command::IncrBy { delta, limit: None, .. }) if delta == expected),
+                "{}",
+                line
+            );
+        }
+        assert_eq!(
+            parse("DECRBY k -9223372036854775808").unwrap_err(),
+            ParseError::NotAnInteger
+        );
+        assert_eq!(
+            parse("INCRBYFLOAT k nan").unwrap_err(),
+            ParseError::NotAFloat
+        );
+    }
+
+    #[test]
+    fn test_parse_variadic_and_options() {
+        assert!(
+            matches!(parse("DEL a b c"), Ok(Command::Del { keys, cascade: false }) if keys.len() == 3)
+        );
+        assert!(
+            matches!(parse("DELCASCADE a"), Ok(Command::Del { keys, cascade: true }) if keys == ["a"])
+        );
+        assert!(
+            matches!(parse("DEL CASCADE a"), Ok(Command::Del { keys, cascade: false }) if keys == ["CASCADE", "a"])
+        );
+        assert!(matches!(parse("PING"), Ok(Command::Ping { message: None })));
+        assert!(matches!(
+            parse("GETCHILDREN p DEPTH 3 HYDRATE MAXBYTES 10"),
+            Ok(Command::GetChildren {
+                depth: Some(3),
+                hydrate: Some(HydrateOptions { max_bytes: 10 }),
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse("HEXPIRE h 10 FIELDS 2 a b"),
+            Ok(Command::HExpire { seconds: 10, fields, .. }) if fields == ["a", "b"]
+        ));
+        assert_eq!(
+            parse("HTTL h FIELDS 3 a b").unwrap_err(),
+            ParseError::Syntax
+        );
+        assert_eq!(
+            parse("GETCHILDREN p MAXBYTES 10").unwrap_err(),
+            ParseError::Syntax
+        );
+        assert!(matches!(
+            parse("EVAL s 2 a b c"),
+            Ok(Command::Eval { keys, args, .. }) if keys == ["a", "b"] && args == ["c"]
+        ));
+        assert_eq!(parse("EVAL s 2 a").unwrap_err(), ParseError::Syntax);
+        assert_eq!(parse("XREAD STREAMS s $").unwrap_err(), ParseError::Syntax);
+        assert!(matches!(parse("script kill"), Ok(Command::ScriptKill {})));
+        assert_eq!(parse("SCRIPT FLUSH").unwrap_err(), ParseError::Syntax);
+    }
+}
@@ -0,0 +1,309 @@
+use crate::cache_errors::ValidationError;
+use crate::executor::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Upper bound for relative expiries, keeping `Instant` arithmetic from overflowing
+pub const MAX_TTL_SECS: u64 = 100 * 365 * 24 * 60 * 60;
+pub const MAX_CHILDREN_DEPTH: u64 = 1024;
+
+#[derive(Debug)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub arity: i32, // Redis convention: counts the command name, negative means "at least"
+    pub write: bool,
+}
+
+impl CommandSpec {
+    pub fn check_arity(&self, argc: usize) -> Result<(), ValidationError> {
+        let required = self.arity.unsigned_abs() as usize;
+        let ok = if self.arity < 0 {
+            argc >= required
+        } else {
+            argc == required
+        };
+
+        if ok {
+            Ok(())
+        } else {
+            Err(ValidationError::WrongArity(self.name.to_lowercase()))
+        }
+    }
+}
+
+const fn read(name: &'static str, arity: i32) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        write: false,
+    }
+}
+
+const fn write(name: &'static str, arity: i32) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        write: true,
+    }
+}
+
+pub const COMMAND_TABLE: &[CommandSpec] = &[
+    // Redis
+    read("GET", 2),
+    write("SET", -3),
+    write("DEL", -2),
+    write("DELCASCADE", -2),
+    write("EXPIRE", 3),
+    write("EXPIREAT", 3),
+    write("PEXPIRE", 3),
+    write("PEXPIREAT", 3),
+    read("TTL", 2),
+    read("PTTL", 2),
+    read("EXPIRETIME", 2),
+    read("PEXPIRETIME", 2),
+    write("PERSIST", 2),
+    write("RENAME", 3),
+    read("EXISTS", -2),
+    read("EVAL", -3), // A script's writes run as commands of their own
+    read("SCRIPT", 2),
+    read("PING", -1),
+    read("INFO", -1),
+    read("KEYS", -2),
+    read("SCAN", -2),
+    write("FLUSHALL", -1),
+    read("BGSAVE", -1),
+    read("REPLICAOF", 3), // Not logged; the link is runtime state
+    read("MIRROR", -3),   // Not logged; the mirror is runtime state
+    write("INCRBY", -3),  // Extended with LIMIT
+    write("INCR", 2),     // Parsed as INCRBY, as are DECR and DECRBY
+    write("DECR", 2),
+    write("DECRBY", 3),
+    write("INCRBYFLOAT", 3),
+    write("APPEND", 3),
+    read("STRLEN", 2),
+    read("GETRANGE", 4),
+    write("SETRANGE", 4),
+    write("HSET", -4),
+    read("HGET", 3),
+    write("HDEL", -3),
+    read("HGETALL", 2),
+    write("HINCRBY", 4),
+    write("SADD", -3),
+    write("SREM", -3),
+    read("SMEMBERS", 2),
+    read("SISMEMBER", 3),
+    read("SINTER", -2),
+    read("SUNION", -2),
+    write("PFADD", -2),
+    read("PFCOUNT", -2),
+    write("PFMERGE", -2),
+    write("JSON.SET", -4),
+    read("JSON.GET", -2),
+    write("JSON.DEL", -2),
+    write("HEXPIRE", -6),
+    read("HTTL", -5),
+    write("HPERSIST", -5),
+    read("XREAD", -4),
+    read("TYPE", 2),
+    read("OBJECT", -3),
+    read("PUBLISH", 3), // Not logged; messages aren't stored
+    read("MEMORY", 2),  // Only PURGE, which leaves values unchanged
+    // custom
+    read("EXPIRING", -2),
+    write("SETPARENT", 3),
+    write("ADDPARENT", 3),
+    read("GETPARENT", 2),
+    read("GETPARENTS", 2),
+    write("UNSETPARENT", -2),
+    write("REPARENT", 3),
+    write("IMPORTGRAPH", -1),
+    read("GETCHILDREN", -2),
+    read("KEYINFO", 2),
+    write("INVALIDATE", -3),
+    write("INVALIDATE_TAG", -3),
+    write("FLUSHPATTERN", 2),
+    read("DRYRUN", -2), // Wraps a DEL, FLUSHALL, FLUSHPATTERN, INVALIDATE or INVALIDATE_TAG
+];
+
+/// Seconds of a time given in seconds, or in milliseconds with `millis`
+fn secs(time: u64, millis: bool) -> u64 {
+    if millis { time / 1000 } else { time }
+}
+
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    COMMAND_TABLE
+        .iter()
+        .find(|spec| spec.name.eq_ignore_ascii_case(name))
+}
+
+/// Checks argument ranges and option combinations before a command reaches the cache
+pub fn validate(command: &Command) -> Result<(), ValidationError> {
+    let name = command.name();
+    if lookup(name).is_none() {
+        return Err(ValidationError::UnknownCommand(name.to_string()));
+    }
+
+    let out_of_range = |what| Err(ValidationError::OutOfRange(what, name.to_lowercase()));
+
+    match command {
+        Command::JsonSet {
+            nx: true, xx: true, ..
+        } => {
+            return Err(ValidationError::IncompatibleOptions("NX", "XX"));
+        }
+        Command::Set { options, .. } => {
+            if options.nx && options.xx {
+                return Err(ValidationError::IncompatibleOptions("NX", "XX"));
+            }
+            if let Some(ttl) = options.ttl
+                && (ttl.is_zero() || ttl.as_secs() > MAX_TTL_SECS)
+            {
+                return out_of_range("expire time");
+            }
+            if options.jitter.is_some_and(|percent| percent > 100) {
+                return out_of_range("jitter");
+            }
+            // SET ... TAGS takes comma-separated lists
+            if options
+                .tags
+                .iter()
+                .any(|tag| tag.is_empty() || tag.contains(','))
+            {
+                return out_of_range("tag");
+            }
+        }
+        Command::Del { keys, .. } | Command::Exists { keys } if keys.is_empty() => {
+            return Err(ValidationError::WrongArity(name.to_lowercase()));
+        }
+        Command::Expire { ttl, millis, .. } if secs(*ttl, *millis) > MAX_TTL_SECS => {
+            return out_of_range("expire time");
+        }
+        Command::ExpireAt {
+            timestamp, millis, ..
+        } => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            if secs(*timestamp, *millis).saturating_sub(now) > MAX_TTL_SECS {
+                return out_of_range("expire time");
+            }
+        }
+        Command::HExpire { seconds, .. } if *seconds > MAX_TTL_SECS => {
+            return out_of_range("expire time");
+        }
+        Command::ExpiringKeys { within, .. } if *within > MAX_TTL_SECS => {
+            return out_of_range("window");
+        }
+        Command::GetChildren {
+            depth: Some(depth), ..
+        } if *depth == 0 || *depth > MAX_CHILDREN_DEPTH => {
+            return out_of_range("depth");
+        }
+        Command::DryRun { command } => {
+            if !matches!(
+                **command,
+                Command::Del { .. }
+                    | Command::FlushAll {}
+                    | Command::FlushPattern { .. }
+                    | Command::Invalidate { .. }
+                    | Command::InvalidateTag { .. }
+            ) {
+                return Err(ValidationError::NotDryRunnable(command.name().to_string()));
+            }
+            return validate(command);
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::SetOptions;
+    use std::time::Duration;
+
+    #[test]
+    fn test_arity() {
+        let get = lookup("get").unwrap();
+        assert!(get.check_arity(2).is_ok());
+        assert!(get.check_arity(3).is_err());
+
+        let del = lookup("DEL").unwrap();
+        assert!(del.check_arity(1).is_err());
+        assert!(del.check_arity(5).is_ok());
+    }
+
+    #[test]
+    fn test_validate() {
+        let set = |options| Command::Set {
+            key: "k".to_string(),
+            value: "v".into(),
+            options,
+            concern: None,
+        };
+
+        assert_eq!(
+            validate(&set(SetOptions {
+                nx: true,
+                xx: true,
+                ..Default::default()
+            })),
+            Err(ValidationError::IncompatibleOptions("NX", "XX"))
+        );
+        assert!(
+            validate(&set(SetOptions {
+                ttl: Some(Duration::ZERO),
+                ..Default::default()
+            }))
+            .is_err()
+        );
+        assert!(validate(&set(SetOptions::default())).is_ok());
+        assert!(
+            validate(&set(SetOptions {
+                jitter: Some(101),
+                ..Default::default()
+            }))
+            .is_err()
+        );
+        assert!(
+            validate(&set(SetOptions {
+                tags: vec!["a,b".to_string()],
+                ..Default::default()
+            }))
+            .is_err()
+        );
+        assert!(
+            validate(&Command::Del {
+                keys: vec![],
+                cascade: false
+            })
+            .is_err()
+        );
+        assert!(
+            validate(&Command::Expire {
+                key: "k".to_string(),
+                ttl: u64::MAX,
+                millis: false,
+            })
+            .is_err()
+        );
+
+        let dry_run = |command| Command::DryRun {
+            command: Box::new(command),
+        };
+        assert!(validate(&dry_run(Command::FlushAll {})).is_ok());
+        assert!(
+            validate(&dry_run(Command::Del {
+                keys: vec![],
+                cascade: false
+            }))
+            .is_err()
+        );
+        assert_eq!(
+            validate(&dry_run(set(SetOptions::default()))),
+            Err(ValidationError::NotDryRunnable("SET".to_string()))
+        );
+    }
+}
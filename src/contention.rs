@@ -0,0 +1,192 @@
+use serde::Serialize;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Acquisition counts and time spent waiting for one lock
+#[derive(Debug, Default)]
+pub struct LockStats {
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+    wait_ns: AtomicU64,
+}
+
+impl LockStats {
+    /// Takes the lock with `try_lock`, falling back to the blocking `lock` and timing
+    /// the wait when it's held elsewhere
+    pub fn acquire<G>(&self, try_lock: impl FnOnce() -> Option<G>, lock: impl FnOnce() -> G) -> G {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if let Some(guard) = try_lock() {
+            return guard;
+        }
+        let start = Instant::now();
+        let guard = lock();
+        self.contended.fetch_add(1, Ordering::Relaxed);
+        self.wait_ns
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        guard
+    }
+
+    pub fn report(&self) -> LockReport {
+        LockReport {
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            contended: self.contended.load(Ordering::Relaxed),
+            wait_us: self.wait_ns.load(Ordering::Relaxed) / 1_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LockReport {
+    pub acquisitions: u64,
+    pub contended: u64, // Acquisitions that had to wait
+    pub wait_us: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShardReport {
+    pub shard: usize,
+    #[serde(flatten)]
+    pub lock: LockReport,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimingReport {
+    pub calls: u64,
+    pub time_us: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ContentionReport {
+    pub shards: Vec<ShardReport>,
+    pub dependency_lock: LockReport,
+    pub memory_accounting: TimingReport,
+}
+
+/// Where cache operations wait: on a key's shard, on the dependency lock, or in memory
+/// accounting. Only kept when `Config::track_contention` is set, since probing locks
+/// and reading the clock cost something on every operation.
+#[derive(Debug)]
+pub struct ContentionTracker {
+    shards: RwLock<Box<[LockStats]>>, // Replaced, with the counts kept, when shards grow
+    pub dependency_lock: LockStats,
+    accounting_calls: AtomicU64,
+    accounting_ns: AtomicU64,
+}
+
+impl ContentionTracker {
+    pub fn new(shards: usize) -> Self {
+        Self {
+            shards: RwLock::new((0..shards).map(|_| LockStats::default()).collect()),
+            dependency_lock: LockStats::default(),
+            accounting_calls: AtomicU64::new(0),
+            accounting_ns: AtomicU64::new(0),
+        }
+    }
+
+    /// `LockStats::acquire` for a shard's lock, counted against that shard
+    pub fn acquire_shard<G>(
+        &self,
+        shard: usize,
+        try_lock: impl FnOnce() -> Option<G>,
+        lock: impl FnOnce() -> G,
+    ) -> G {
+        match self.shards.read().unwrap().get(shard) {
+            Some(stats) => stats.acquire(try_lock, lock),
+            None => lock(),
+        }
+    }
+
+    /// Adds stats for shards added by growing the cache
+    pub fn resize(&self, shards: usize) {
+        let mut stats = self.shards.write().unwrap();
+        if shards > stats.len() {
+            let mut grown = std::mem::take(&mut *stats).into_vec();
+            grown.resize_with(shards, LockStats::default);
+            *stats = grown.into_boxed_slice();
+        }
+    }
+
+    pub fn record_accounting(&self, elapsed: Duration) {
+        self.accounting_calls.fetch_add(1, Ordering::Relaxed);
+        self.accounting_ns
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Shards sorted by time spent waiting, busiest first
+    pub fn report(&self) -> ContentionReport {
+        let mut shards: Vec<ShardReport> = self
+            .shards
+            .read()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .map(|(shard, stats)| ShardReport {
+                shard,
+                lock: stats.report(),
+            })
+            .collect();
+        shards.sort_by(|a, b| {
+            (b.lock.wait_us, b.lock.acquisitions).cmp(&(a.lock.wait_us, a.lock.acquisitions))
+        });
+        ContentionReport {
+            shards,
+            dependency_lock: self.dependency_lock.report(),
+            memory_accounting: TimingReport {
+                calls: self.accounting_calls.load(Ordering::Relaxed),
+                time_us: self.accounting_ns.load(Ordering::Relaxed) / 1_000,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_lock_wait_is_timed() {
+        let stats = LockStats::default();
+        let lock = Arc::new(Mutex::new(()));
+
+        drop(stats.acquire(|| lock.try_lock().ok(), || lock.lock().unwrap()));
+        let held = lock.clone();
+        let holder = std::thread::spawn(move || {
+            let _guard = held.lock().unwrap();
+            std::thread::sleep(Duration::from_millis(30));
+        });
+        while lock.try_lock().is_ok() {
+            std::thread::yield_now();
+        }
+        drop(stats.acquire(|| lock.try_lock().ok(), || lock.lock().unwrap()));
+        holder.join().unwrap();
+
+        let report = stats.report();
+        assert_eq!(report.acquisitions, 2);
+        assert_eq!(report.contended, 1);
+        assert!(report.wait_us > 0);
+    }
+
+    #[test]
+    fn test_cache_reports_shard_ops() {
+        use crate::cache::{Cache, Config, SetOptions, Value};
+
+        assert!(Cache::new(Config::default()).contention_report().is_none());
+        let cache = Cache::new(Config {
+            track_contention: true,
+            ..Config::default()
+        });
+        cache
+            .set("k".into(), Value::Integer(1), SetOptions::default())
+            .unwrap();
+        cache.get("k");
+        cache.exists("k");
+
+        let report = cache.contention_report().unwrap();
+        let ops: u64 = report.shards.iter().map(|s| s.lock.acquisitions).sum();
+        assert_eq!(ops, 3);
+        assert_eq!(report.shards[0].lock.acquisitions, 3); // Busiest shard first
+        assert!(report.memory_accounting.calls >= 1);
+    }
+}
@@ -0,0 +1,139 @@
+use crate::cache::{Cache, matches_pattern};
+use crate::cache_errors::CacheError;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::warn;
+
+#[derive(Debug, Clone)]
+pub struct CounterConfig {
+    pub patterns: Vec<String>, // Keys (`prefix*` globs) whose increments are buffered
+    pub flush_interval: Duration,
+}
+
+impl Default for CounterConfig {
+    fn default() -> Self {
+        Self {
+            patterns: Vec::new(),
+            flush_interval: Duration::from_millis(100),
+        }
+    }
+}
+
+static NEXT_STRIPE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // Each thread sticks to one stripe, so worker threads rarely share a lock
+    static STRIPE: usize = NEXT_STRIPE.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Buffers increments to hot counters in per-thread stripes and folds them into the
+/// cache on a timer. Increments stop contending on the key's shard, at the cost of
+/// reads lagging by up to one flush interval.
+pub struct CounterBuffer {
+    config: CounterConfig,
+    stripes: Box<[Mutex<HashMap<String, i64>>]>,
+}
+
+impl CounterBuffer {
+    pub fn new(config: CounterConfig) -> Self {
+        let stripes = std::thread::available_parallelism().map_or(4, |n| n.get());
+        Self {
+            config,
+            stripes: (0..stripes).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    /// Whether increments to `key` are buffered
+    pub fn buffers(&self, key: &str) -> bool {
+        self.config
+            .patterns
+            .iter()
+            .any(|pattern| matches_pattern(key, pattern))
+    }
+
+    /// Adds `delta` to the calling thread's stripe and returns the delta pending there
+    pub fn add(&self, key: &str, delta: i64) -> Result<i64, CacheError> {
+        let mut stripe = self.stripes[self.stripe_index()].lock().unwrap();
+        let pending = match stripe.get_mut(key) {
+            Some(pending) => pending,
+            None => stripe.entry(key.to_string()).or_insert(0),
+        };
+        *pending = pending.checked_add(delta).ok_or(CacheError::Overflow)?;
+        Ok(*pending)
+    }
+
+    /// Applies every pending delta to the cache, returning how many keys were written.
+    /// Deltas the cache rejects (wrong type, overflow) are dropped with a warning.
+    pub fn flush(&self, cache: &Cache) -> usize {
+        let mut totals: HashMap<String, i64> = HashMap::new();
+        for stripe in &self.stripes {
+            let pending = std::mem::take(&mut *stripe.lock().unwrap());
+            for (key, delta) in pending {
+                let total = totals.entry(key).or_insert(0);
+                *total = total.saturating_add(delta);
+            }
+        }
+
+        let mut written = 0;
+        for (key, delta) in totals {
+            match cache.incr_by(&key, delta, None) {
+                Ok(_) => written += 1,
+                Err(e) => warn!(
+                    "Dropping buffered increment of {} for {}: {}",
+                    delta, key, e
+                ),
+            }
+        }
+        written
+    }
+
+    /// Flushes on every interval tick
+    pub async fn run(self: Arc<Self>, cache: Arc<Cache>) {
+        let mut interval = tokio::time::interval(self.config.flush_interval);
+        loop {
+            interval.tick().await;
+            self.flush(&cache);
+        }
+    }
+
+    fn stripe_index(&self) -> usize {
+        STRIPE.with(|stripe| *stripe) % self.stripes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Config, Value};
+
+    #[test]
+    fn test_buffered_increments_flush() {
+        let cache = Cache::new(Config::default());
+        let counters = Arc::new(CounterBuffer::new(CounterConfig {
+            patterns: vec!["hits:*".to_string()],
+            ..CounterConfig::default()
+        }));
+        assert!(counters.buffers("hits:home"));
+        assert!(!counters.buffers("misses"));
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let counters = counters.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        counters.add("hits:home", 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        threads.into_iter().for_each(|t| t.join().unwrap());
+
+        // Nothing reaches the cache until a flush
+        assert_eq!(cache.get("hits:home"), None);
+        assert_eq!(counters.flush(&cache), 1);
+        assert_eq!(cache.get("hits:home"), Some(Value::Integer(4000)));
+        assert_eq!(counters.flush(&cache), 0);
+    }
+}
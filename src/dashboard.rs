@@ -0,0 +1,253 @@
+use crate::aggregator::{AggregatorConfig, StatsAggregator};
+use crate::bus::{BusConfig, InvalidationBus};
+use crate::cache::{Cache, Config, SetOptions, Value};
+use crate::executor::{Command, CommandExecutor};
+use crate::guardrails::{GuardrailConfig, Guardrails};
+use crate::middleware::CommandMetrics;
+use crate::mirror::Mirroring;
+use crate::refresh::RefreshAhead;
+use crate::response_cache::{ResponseCache, ResponseCacheConfig};
+use crate::schema::Schemas;
+use crate::shadow::{ShadowConfig, Shadowing};
+use serde_json::{Value as Json, json};
+use std::sync::Arc;
+
+/// A metric family as exported: HELP and TYPE, plus the labels its series carry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Family {
+    pub name: String,
+    pub help: String,
+    pub kind: String,
+    pub labels: Vec<String>,
+}
+
+/// Families in a Prometheus text exposition, in order. Static labels are expected
+/// to be absent, so every label found is one that tells series apart.
+pub fn families(exposition: &str) -> Vec<Family> {
+    let mut families: Vec<Family> = Vec::new();
+    for line in exposition.lines() {
+        if let Some(rest) = line.strip_prefix("# HELP ") {
+            let (name, help) = rest.split_once(' ').unwrap_or((rest, ""));
+            families.push(Family {
+                name: name.to_string(),
+                help: help.to_string(),
+                kind: "untyped".to_string(),
+                labels: Vec::new(),
+            });
+        } else if let Some(rest) = line.strip_prefix("# TYPE ") {
+            if let (Some(family), Some((_, kind))) = (families.last_mut(), rest.split_once(' ')) {
+                family.kind = kind.to_string();
+            }
+        } else if let (Some(family), Some((_, labels))) =
+            (families.last_mut(), line.split_once('{'))
+        {
+            for label in label_names(labels) {
+                if label != "le" && !family.labels.contains(&label) {
+                    family.labels.push(label);
+                }
+            }
+        }
+    }
+    families
+}
+
+/// Names in a rendered label set, e.g. `a="x",b="y\"z"} 1`
+fn label_names(labels: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut rest = labels;
+    while let Some((name, value)) = rest.split_once("=\"") {
+        names.push(name.trim_start_matches(',').to_string());
+        // Skip the quoted value, honouring escapes
+        let mut escaped = false;
+        let end = value.char_indices().find(|&(_, c)| {
+            let closes = c == '"' && !escaped;
+            escaped = c == '\\' && !escaped;
+            closes
+        });
+        let Some((end, _)) = end else {
+            break;
+        };
+        rest = &value[end + 1..];
+        if rest.starts_with('}') {
+            break;
+        }
+    }
+    names
+}
+
+/// Metrics from an executor with every optional source attached and exercised, so
+/// families only rendered once they have data are included too
+pub fn sample_exposition() -> String {
+    let cache = Arc::new(Cache::new(Config::default()));
+    let aggregator = Arc::new(StatsAggregator::new(
+        cache.clone(),
+        AggregatorConfig::default(),
+    ));
+    let executor = CommandExecutor::new(cache.clone())
+        .with_middleware(Arc::new(CommandMetrics::default()))
+        .with_middleware(Arc::new(Guardrails::new(
+            cache.clone(),
+            GuardrailConfig::default(),
+        )))
+        .with_middleware(Arc::new(Shadowing::new(ShadowConfig::new(
+            "127.0.0.1:6379".to_string(),
+        ))))
+        .with_stats_aggregator(aggregator.clone())
+        .with_invalidation_bus(Arc::new(InvalidationBus::new(BusConfig::default())))
+        .with_refresh_ahead(Arc::new(RefreshAhead::new(cache.clone())))
+        .with_mirroring(Arc::new(Mirroring::new(cache.clone())))
+        .with_schemas(Arc::new(Schemas::new(cache.config().namespace_delimiter)))
+        .with_response_cache(ResponseCache::new(ResponseCacheConfig::default()));
+    cache
+        .set(
+            "namespace:key".to_string(),
+            Value::String("value".to_string()),
+            SetOptions::default(),
+        )
+        .unwrap();
+    executor.execute(Command::Get {
+        key: "namespace:key".to_string(),
+    });
+    aggregator.refresh();
+    executor.render_metrics()
+}
+
+/// Grafana dashboard with a panel per family: counters as rates, gauges as is and
+/// histograms as p99, each split by the labels its series carry
+pub fn grafana_dashboard(exposition: &str) -> Json {
+    let panels: Vec<Json> = families(exposition)
+        .iter()
+        .enumerate()
+        .map(|(i, family)| {
+            json!({
+                "id": i + 1,
+                "type": "timeseries",
+                "title": family.name,
+                "description": family.help,
+                "datasource": {"type": "prometheus", "uid": "${datasource}"},
+                "gridPos": {"h": 8, "w": 12, "x": (i % 2) * 12, "y": (i / 2) * 8},
+                "fieldConfig": {"defaults": {"unit": unit(&family.name)}, "overrides": []},
+                "targets": [{
+                    "refId": "A",
+                    "expr": expression(family),
+                    "legendFormat": legend(family),
+                }],
+            })
+        })
+        .collect();
+
+    json!({
+        "title": "dashdotcache",
+        "uid": "dashdotcache",
+        "tags": ["dashdotcache"],
+        "schemaVersion": 39,
+        "time": {"from": "now-1h", "to": "now"},
+        "refresh": "30s",
+        "templating": {"list": [{
+            "name": "datasource",
+            "label": "Data source",
+            "type": "datasource",
+            "query": "prometheus",
+        }]},
+        "panels": panels,
+    })
+}
+
+fn expression(family: &Family) -> String {
+    let name = &family.name;
+    let by = family.labels.join(", ");
+    match family.kind.as_str() {
+        "histogram" => format!(
+            "histogram_quantile(0.99, sum by (le{}) (rate({}_bucket[$__rate_interval])))",
+            family
+                .labels
+                .iter()
+                .map(|l| format!(", {}", l))
+                .collect::<String>(),
+            name
+        ),
+        "counter" if by.is_empty() => format!("sum(rate({}[$__rate_interval]))", name),
+        "counter" => format!("sum by ({}) (rate({}[$__rate_interval]))", by, name),
+        _ if by.is_empty() => format!("sum({})", name),
+        _ => format!("sum by ({}) ({})", by, name),
+    }
+}
+
+fn legend(family: &Family) -> String {
+    match family.labels.is_empty() {
+        true => family.name.clone(),
+        false => family
+            .labels
+            .iter()
+            .map(|label| format!("{{{{{}}}}}", label))
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+fn unit(name: &str) -> &'static str {
+    let name = name.trim_end_matches("_total");
+    if name.ends_with("_bytes") {
+        "bytes"
+    } else if name.ends_with("_microseconds") {
+        "µs"
+    } else if name.ends_with("_seconds") {
+        "s"
+    } else if name.ends_with("_ratio") {
+        "percentunit"
+    } else {
+        "short"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dashboard_covers_every_family() {
+        let exposition = sample_exposition();
+        let families = families(&exposition);
+        let dashboard = grafana_dashboard(&exposition);
+        let panels = dashboard["panels"].as_array().unwrap();
+        assert_eq!(panels.len(), families.len());
+
+        let panel = |name: &str| {
+            panels
+                .iter()
+                .find(|panel| panel["title"] == name)
+                .unwrap_or_else(|| panic!("no panel for {}", name))
+        };
+        assert_eq!(
+            panel("cache_hits_total")["targets"][0]["expr"],
+            "sum(rate(cache_hits_total[$__rate_interval]))"
+        );
+        assert_eq!(
+            panel("cache_namespace_memory_usage_bytes")["targets"][0]["expr"],
+            "sum by (namespace) (cache_namespace_memory_usage_bytes)"
+        );
+        assert_eq!(
+            panel("cache_namespace_memory_usage_bytes")["fieldConfig"]["defaults"]["unit"],
+            "bytes"
+        );
+        assert_eq!(
+            panel("cache_command_calls_total")["targets"][0]["legendFormat"],
+            "{{command}}"
+        );
+        panel("cache_type_keys");
+    }
+
+    #[test]
+    fn test_histogram_families() {
+        let exposition = "# HELP latency_seconds Request latency\n\
+            # TYPE latency_seconds histogram\n\
+            latency_seconds_bucket{op=\"get\",le=\"0.1\"} 3\n\
+            latency_seconds_sum{op=\"get\"} 0.2\n";
+        let families = families(exposition);
+        assert_eq!(families[0].labels, vec!["op".to_string()]);
+        assert_eq!(
+            expression(&families[0]),
+            "histogram_quantile(0.99, sum by (le, op) (rate(latency_seconds_bucket[$__rate_interval])))"
+        );
+    }
+}
@@ -0,0 +1,419 @@
+use crate::aof::FsyncPolicy;
+use crate::cache::Config;
+use crate::eviction::{EvictionPolicy, KeyLimitPolicy};
+use crate::guardrails::RssAction;
+use crate::metrics::is_label_name;
+use crate::notifications::KeyspaceEvents;
+use crate::snapshot::DEFAULT_SNAPSHOT_PATH;
+use serde::Serialize;
+use std::fmt;
+use std::path::Path;
+
+/// Clients the default listeners are expected to hold open at once
+pub const EXPECTED_CLIENTS: u64 = 10_000;
+/// Descriptors kept back for listeners, log files and persistence
+const RESERVED_FDS: u64 = 32;
+/// Share of system RAM above which max_memory leaves too little for the OS
+const MAX_MEMORY_RAM_SHARE: f64 = 0.9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// One problem found by a self-check, phrased as what to change
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    pub check: &'static str,
+    pub message: String,
+}
+
+impl Finding {
+    fn warning(check: &'static str, message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            check,
+            message,
+        }
+    }
+
+    fn error(check: &'static str, message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            check,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{} [{}]: {}", level, self.check, self.message)
+    }
+}
+
+/// Runs every self-check against `config` and the host it's running on
+pub fn run(config: &Config) -> Vec<Finding> {
+    let mut findings = check_env();
+    findings.extend(check_config(config));
+    findings.extend(check_memory(config, system_memory()));
+    findings.extend(check_fd_limit(open_files_limit()));
+    if let Ok(dir) = std::env::var("DASHDOT_AOF_DIR") {
+        findings.extend(check_dir("aof", Path::new(&dir)));
+    }
+    if let Ok(dir) = std::env::var("DASHDOT_SCHEMA_DIR")
+        && !Path::new(&dir).is_dir()
+    {
+        findings.push(Finding::error(
+            "schema",
+            format!("DASHDOT_SCHEMA_DIR {} is not a directory", dir),
+        ));
+    }
+    let snapshot =
+        std::env::var("DASHDOT_SNAPSHOT_PATH").unwrap_or_else(|_| DEFAULT_SNAPSHOT_PATH.into());
+    let snapshot_dir = Path::new(&snapshot)
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    findings.extend(check_dir("snapshot", snapshot_dir));
+    findings
+}
+
+/// Environment variables that are set but won't parse, and so are silently ignored
+pub fn check_env() -> Vec<Finding> {
+    const NUMERIC: [&str; 20] = [
+        "DASHDOT_MAX_MEMORY",
+        "DASHDOT_MAX_KEYS",
+        "DASHDOT_MAX_PINNED_MEMORY",
+        "DASHDOT_LFU_DECAY_SECS",
+        "DASHDOT_LFU_LOG_FACTOR",
+        "DASHDOT_COMMAND_BUDGET_MS",
+        "DASHDOT_TOMBSTONE_RETENTION_SECS",
+        "DASHDOT_COMPACTION_INTERVAL_SECS",
+        "DASHDOT_DEFAULT_TTL_SECS",
+        "DASHDOT_MAX_TTL_SECS",
+        "DASHDOT_TTL_JITTER_PERCENT",
+        "DASHDOT_TTL_CLEANUP_MS",
+        "DASHDOT_MAX_DEPENDENCY_DEPTH",
+        "DASHDOT_STALE_GRACE_SECS",
+        "DASHDOT_OPLOG_MAX_LEN",
+        "DASHDOT_WORKER_THREADS",
+        "DASHDOT_BACKGROUND_THREADS",
+        "DASHDOT_REPL_BACKLOG",
+        "DASHDOT_RESPONSE_CACHE_MS",
+        "DASHDOT_MAX_RSS",
+    ];
+
+    let mut findings = Vec::new();
+    for name in NUMERIC {
+        if let Ok(value) = std::env::var(name)
+            && value.parse::<usize>().is_err()
+        {
+            findings.push(Finding::error(
+                "env",
+                format!("{}={:?} is not a number and will be ignored", name, value),
+            ));
+        }
+    }
+    if let Ok(value) = std::env::var("DASHDOT_EVICTION_POLICY")
+        && let Err(e) = value.parse::<EvictionPolicy>()
+    {
+        findings.push(Finding::error(
+            "env",
+            format!("DASHDOT_EVICTION_POLICY: {}; falling back to noeviction", e),
+        ));
+    }
+    if let Ok(value) = std::env::var("DASHDOT_RSS_ACTION")
+        && let Err(e) = value.parse::<RssAction>()
+    {
+        findings.push(Finding::error(
+            "env",
+            format!("DASHDOT_RSS_ACTION: {}; falling back to evict", e),
+        ));
+    }
+    if let Ok(value) = std::env::var("DASHDOT_KEY_LIMIT_POLICY")
+        && let Err(e) = value.parse::<KeyLimitPolicy>()
+    {
+        findings.push(Finding::error(
+            "env",
+            format!("DASHDOT_KEY_LIMIT_POLICY: {}; falling back to reject", e),
+        ));
+    }
+    if let Ok(value) = std::env::var("DASHDOT_NOTIFY_KEYSPACE_EVENTS")
+        && let Err(e) = value.parse::<KeyspaceEvents>()
+    {
+        findings.push(Finding::error(
+            "env",
+            format!(
+                "DASHDOT_NOTIFY_KEYSPACE_EVENTS: {}; keyspace notifications are off",
+                e
+            ),
+        ));
+    }
+    if let Ok(value) = std::env::var("DASHDOT_AOF_FSYNC")
+        && let Err(e) = value.parse::<FsyncPolicy>()
+    {
+        findings.push(Finding::error(
+            "env",
+            format!("DASHDOT_AOF_FSYNC: {}; falling back to everysec", e),
+        ));
+    }
+    findings
+}
+
+/// A persistence directory the server must be able to create files in
+pub fn check_dir(check: &'static str, dir: &Path) -> Vec<Finding> {
+    let display = dir.display();
+    if !dir.exists() {
+        // Created on startup, which needs the nearest existing ancestor to be writable
+        let parent = dir.ancestors().skip(1).find(|a| a.exists());
+        return match parent {
+            Some(parent) if is_writable(parent) => Vec::new(),
+            _ => vec![Finding::error(
+                check,
+                format!("{} doesn't exist and can't be created", display),
+            )],
+        };
+    }
+    if !dir.is_dir() {
+        return vec![Finding::error(
+            check,
+            format!("{} exists but isn't a directory", display),
+        )];
+    }
+    if !is_writable(dir) {
+        return vec![Finding::error(
+            check,
+            format!(
+                "{} isn't writable; check its owner and permissions",
+                display
+            ),
+        )];
+    }
+    Vec::new()
+}
+
+/// Whether a file can be created in `dir`, tested by creating and removing one
+fn is_writable(dir: &Path) -> bool {
+    let probe = dir.join(format!(".dashdot-probe-{}", std::process::id()));
+    let writable = std::fs::write(&probe, b"").is_ok();
+    let _ = std::fs::remove_file(&probe);
+    writable
+}
+
+/// Settings that contradict each other or can't work as configured
+pub fn check_config(config: &Config) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    if config.ttl_cleanup_interval.is_zero() {
+        findings.push(Finding::error(
+            "config",
+            "ttl_cleanup_interval is zero; set it to at least a few milliseconds".into(),
+        ));
+    }
+    if let (Some(default), Some(max)) = (config.ttl_policy.default, config.ttl_policy.max)
+        && default > max
+    {
+        findings.push(Finding::warning(
+            "config",
+            format!(
+                "default TTL ({}s) exceeds the max TTL ({}s), so keys get the max",
+                default.as_secs(),
+                max.as_secs()
+            ),
+        ));
+    }
+    if config.max_memory == Some(0) {
+        findings.push(Finding::error(
+            "config",
+            "max_memory is 0, so every write will be rejected".into(),
+        ));
+    }
+    if config.max_keys == Some(0) {
+        findings.push(Finding::error(
+            "config",
+            "max_keys is 0, so every write will be rejected".into(),
+        ));
+    }
+    if let (Some(pinned), Some(max)) = (config.max_pinned_memory, config.max_memory)
+        && pinned > max
+    {
+        findings.push(Finding::warning(
+            "config",
+            format!(
+                "max_pinned_memory ({} bytes) exceeds max_memory ({} bytes) and has no effect",
+                pinned, max
+            ),
+        ));
+    }
+    if config.eviction_policy.evicts() && config.max_memory.is_none() {
+        findings.push(Finding::warning(
+            "config",
+            format!(
+                "eviction policy {} is set but max_memory isn't, so nothing will be evicted",
+                config.eviction_policy
+            ),
+        ));
+    }
+    if config.key_limit_policy.evicts() && config.max_keys.is_none() {
+        findings.push(Finding::warning(
+            "config",
+            format!(
+                "key limit policy {} is set but max_keys isn't, so nothing will be evicted",
+                config.key_limit_policy
+            ),
+        ));
+    }
+    if config.namespace_delimiter.is_whitespace() {
+        findings.push(Finding::warning(
+            "config",
+            "namespace_delimiter is whitespace; keys with spaces will split into namespaces".into(),
+        ));
+    }
+    for (name, _) in &config.metric_labels {
+        if !is_label_name(name) {
+            findings.push(Finding::error(
+                "config",
+                format!(
+                    "metric label {:?} isn't a valid label name ([a-zA-Z_][a-zA-Z0-9_]*) and will be dropped",
+                    name
+                ),
+            ));
+        }
+    }
+    findings
+}
+
+/// max_memory against `total` bytes of system RAM, when known
+pub fn check_memory(config: &Config, total: Option<usize>) -> Vec<Finding> {
+    let Some(max_memory) = config.max_memory else {
+        return vec![Finding::warning(
+            "memory",
+            "max_memory is unset; the cache will grow until the OS kills it".into(),
+        )];
+    };
+    let Some(total) = total else {
+        return vec![Finding::warning(
+            "memory",
+            "couldn't read system memory to compare against max_memory".into(),
+        )];
+    };
+    if max_memory as f64 > total as f64 * MAX_MEMORY_RAM_SHARE {
+        return vec![Finding::warning(
+            "memory",
+            format!(
+                "max_memory ({} bytes) is over {:.0}% of system RAM ({} bytes); \
+                 lower it to leave room for the OS and connection buffers",
+                max_memory,
+                MAX_MEMORY_RAM_SHARE * 100.0,
+                total
+            ),
+        )];
+    }
+    Vec::new()
+}
+
+/// The soft open files limit against the descriptors the expected clients need
+pub fn check_fd_limit(limit: Option<u64>) -> Vec<Finding> {
+    let needed = EXPECTED_CLIENTS + RESERVED_FDS;
+    match limit {
+        Some(limit) if limit < needed => vec![Finding::warning(
+            "fds",
+            format!(
+                "open files limit is {}, below the {} needed for {} clients; \
+                 raise it with `ulimit -n {}` or LimitNOFILE",
+                limit, needed, EXPECTED_CLIENTS, needed
+            ),
+        )],
+        Some(_) => Vec::new(),
+        None => vec![Finding::warning(
+            "fds",
+            "couldn't read the open files limit".into(),
+        )],
+    }
+}
+
+/// Total system RAM in bytes, from /proc/meminfo
+fn system_memory() -> Option<usize> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|l| l.starts_with("MemTotal:"))?;
+    let kb: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// Soft limit on open files for this process, from /proc/self/limits
+pub(crate) fn open_files_limit() -> Option<u64> {
+    let limits = std::fs::read_to_string("/proc/self/limits").ok()?;
+    let line = limits.lines().find(|l| l.starts_with("Max open files"))?;
+    let soft = line
+        .trim_start_matches("Max open files")
+        .split_whitespace()
+        .next()?;
+    match soft {
+        "unlimited" => Some(u64::MAX),
+        n => n.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checks_flag_bad_settings() {
+        let config = Config {
+            max_memory: Some(1 << 30),
+            max_pinned_memory: Some(2 << 30),
+            ..Config::default()
+        };
+        let findings = check_config(&config);
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].message.contains("max_pinned_memory"));
+
+        let config = Config {
+            eviction_policy: EvictionPolicy::AllKeysLfu,
+            ..Config::default()
+        };
+        assert_eq!(check_config(&config)[0].severity, Severity::Warning);
+        assert!(check_config(&Config::default()).is_empty());
+
+        let config = Config {
+            metric_labels: vec![
+                ("_instance".into(), "a".into()),
+                ("env1".into(), "b".into()),
+                ("1env".into(), "c".into()),
+                ("data-center".into(), "d".into()),
+                ("".into(), "e".into()),
+            ],
+            ..Config::default()
+        };
+        let findings = check_config(&config);
+        assert_eq!(findings.len(), 3);
+        assert!(findings.iter().all(|f| f.severity == Severity::Error));
+
+        let config = Config {
+            max_memory: Some(15 << 30),
+            ..Config::default()
+        };
+        assert_eq!(check_memory(&config, Some(16 << 30)).len(), 1);
+        assert!(check_memory(&config, Some(64 << 30)).is_empty());
+        assert_eq!(check_memory(&Config::default(), Some(64 << 30)).len(), 1);
+
+        let temp = std::env::temp_dir();
+        assert!(check_dir("aof", &temp.join("dashdot-missing/aof")).is_empty());
+        let file = temp.join(format!("dashdot-diag-{}", std::process::id()));
+        std::fs::write(&file, b"").unwrap();
+        let findings = check_dir("aof", &file);
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(findings[0].severity, Severity::Error);
+
+        assert_eq!(check_fd_limit(Some(1024)).len(), 1);
+        assert!(check_fd_limit(Some(65536)).is_empty());
+    }
+}
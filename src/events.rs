@@ -0,0 +1,60 @@
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+const EVENT_BUS_CAPACITY: usize = 1024;
+
+/// Keyspace events published by the cache
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CacheEvent {
+    /// A key was requested but absent (or no longer valid)
+    Miss { key: String },
+    /// A key's TTL ran out and it was removed
+    Expired { key: String },
+    /// A key was invalidated because an ancestor expired, or by an explicit cascade
+    /// invalidation rooted at `cause`
+    Invalidated { key: String, cause: String },
+    /// A key was removed to make room under the memory limit
+    Evicted { key: String },
+    /// A key was written by SET; only published for keyspace notifications
+    Set { key: String },
+    /// A key was deleted by a client; only published for keyspace notifications
+    Deleted { key: String },
+    /// A key was changed by a write command; published by the executor, e.g. for
+    /// mirroring
+    Written { key: String },
+}
+
+/// Fan-out channel for cache events. Publishing is a no-op without subscribers,
+/// and slow subscribers lag rather than blocking writers.
+#[derive(Debug)]
+pub struct EventBus {
+    sender: broadcast::Sender<CacheEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(EVENT_BUS_CAPACITY)
+    }
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    pub fn publish(&self, event: CacheEvent) {
+        if self.has_subscribers() {
+            let _ = self.sender.send(event);
+        }
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<CacheEvent> {
+        self.sender.subscribe()
+    }
+}
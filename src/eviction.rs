@@ -0,0 +1,202 @@
+use crate::cache::Entry;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Default period after which an unread key's access count is halved
+pub const LFU_DECAY_PERIOD: Duration = Duration::from_secs(60);
+/// Keys sampled per eviction round; the worst scoring one is evicted
+pub const EVICTION_SAMPLES: usize = 16;
+
+/// How access counts grow and decay, which is what the LFU policy ranks keys by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LfuConfig {
+    pub decay_period: Duration, // Counts halve each period a key goes unread; zero disables decay
+    pub log_factor: u32, // Above zero, counts grow logarithmically: the Nth hit counts with p = 1/(N*factor+1)
+}
+
+impl Default for LfuConfig {
+    fn default() -> Self {
+        Self {
+            decay_period: LFU_DECAY_PERIOD,
+            log_factor: 0,
+        }
+    }
+}
+
+impl LfuConfig {
+    /// `count` after going unread for `idle`
+    pub fn decay(&self, count: u64, idle: Duration) -> u64 {
+        if self.decay_period.is_zero() {
+            return count;
+        }
+        let periods = idle.as_millis() / self.decay_period.as_millis();
+        u32::try_from(periods)
+            .ok()
+            .and_then(|periods| count.checked_shr(periods))
+            .unwrap_or(0)
+    }
+
+    /// `count` after one more access
+    pub fn increment(&self, count: u64) -> u64 {
+        if self.log_factor == 0 {
+            return count.saturating_add(1);
+        }
+        let p = 1.0 / (count as f64 * self.log_factor as f64 + 1.0);
+        if rand::random::<f64>() < p {
+            count.saturating_add(1)
+        } else {
+            count
+        }
+    }
+}
+
+/// Class of a key for eviction: a sample's lowest class is always evicted before
+/// the policy's score is consulted, so best-effort keys never push out critical ones
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        })
+    }
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "low" => Ok(Priority::Low),
+            "normal" => Ok(Priority::Normal),
+            "high" => Ok(Priority::High),
+            _ => Err(format!("unknown priority '{}'", s)),
+        }
+    }
+}
+
+/// What the cache does when a write would exceed `Config::max_memory`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    /// Reject the write with an OOM error
+    #[default]
+    NoEviction,
+    /// Evict the least frequently used keys, by decayed access count
+    AllKeysLfu,
+    /// Evict keys at random
+    AllKeysRandom,
+    /// Evict the keys closest to expiring; keys without a TTL are never evicted
+    VolatileTtl,
+}
+
+impl EvictionPolicy {
+    pub fn evicts(&self) -> bool {
+        *self != EvictionPolicy::NoEviction
+    }
+
+    /// Eviction score of a candidate; the lowest in a sample is evicted first
+    pub fn score(&self, entry: &Entry, now: Instant, lfu: &LfuConfig) -> Option<u64> {
+        match self {
+            EvictionPolicy::NoEviction => None,
+            EvictionPolicy::AllKeysLfu => Some(entry.frequency(now, lfu)),
+            EvictionPolicy::AllKeysRandom => Some(rand::random()),
+            EvictionPolicy::VolatileTtl => entry
+                .ttl
+                .as_ref()
+                .map(|ttl| ttl.expires_at.saturating_duration_since(now).as_millis() as u64),
+        }
+    }
+}
+
+impl fmt::Display for EvictionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EvictionPolicy::NoEviction => "noeviction",
+            EvictionPolicy::AllKeysLfu => "allkeys-lfu",
+            EvictionPolicy::AllKeysRandom => "allkeys-random",
+            EvictionPolicy::VolatileTtl => "volatile-ttl",
+        })
+    }
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "noeviction" => Ok(EvictionPolicy::NoEviction),
+            "allkeys-lfu" => Ok(EvictionPolicy::AllKeysLfu),
+            "allkeys-random" => Ok(EvictionPolicy::AllKeysRandom),
+            "volatile-ttl" => Ok(EvictionPolicy::VolatileTtl),
+            _ => Err(format!("unknown eviction policy '{}'", s)),
+        }
+    }
+}
+
+/// What the cache does when a new key would exceed `Config::max_keys`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyLimitPolicy {
+    /// Reject the write
+    #[default]
+    Reject,
+    /// Evict the least recently accessed keys
+    EvictLru,
+    /// Evict the keys closest to expiring; keys without a TTL are never evicted
+    EvictOldestTtl,
+}
+
+impl KeyLimitPolicy {
+    pub fn evicts(&self) -> bool {
+        *self != KeyLimitPolicy::Reject
+    }
+
+    /// Eviction score of a candidate; the lowest in a sample is evicted first
+    pub fn score(&self, entry: &Entry, now: Instant) -> Option<u64> {
+        match self {
+            KeyLimitPolicy::Reject => None,
+            KeyLimitPolicy::EvictLru => {
+                let idle = now.saturating_duration_since(entry.last_accessed);
+                Some(u64::MAX - idle.as_millis() as u64)
+            }
+            KeyLimitPolicy::EvictOldestTtl => {
+                EvictionPolicy::VolatileTtl.score(entry, now, &LfuConfig::default())
+            }
+        }
+    }
+}
+
+impl fmt::Display for KeyLimitPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            KeyLimitPolicy::Reject => "reject",
+            KeyLimitPolicy::EvictLru => "evict-lru",
+            KeyLimitPolicy::EvictOldestTtl => "evict-oldest-ttl",
+        })
+    }
+}
+
+impl FromStr for KeyLimitPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "reject" => Ok(KeyLimitPolicy::Reject),
+            "evict-lru" => Ok(KeyLimitPolicy::EvictLru),
+            "evict-oldest-ttl" => Ok(KeyLimitPolicy::EvictOldestTtl),
+            _ => Err(format!("unknown key limit policy '{}'", s)),
+        }
+    }
+}
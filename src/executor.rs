@@ -1,6 +1,31 @@
-use crate::cache::{Cache, SetOptions, Value};
+use crate::aggregator::StatsAggregator;
+use crate::aof::{Aof, WriteConcern};
+use crate::bus::InvalidationBus;
+use crate::cache::{
+    Cache, Deadline, DependencyEdge, DryRunReport, IncrOutcome, InvalidationMode, SetOptions, Value,
+};
+use crate::cache_errors::{CacheError, CommandError, ErrorClass, ValidationError};
+use crate::command_table;
+use crate::counters::CounterBuffer;
+use crate::eviction::Priority;
+use crate::info;
+use crate::middleware::Middleware;
+use crate::mirror::{MirrorFilter, Mirroring};
+use crate::oplog::{OPLOG_KEY, OpLog};
+use crate::plugins::{CommandCheck, Plugins};
+use crate::primary::Primary;
+use crate::pubsub::PubSub;
+use crate::refresh::RefreshAhead;
+use crate::replica::Replication;
+use crate::response_cache::ResponseCache;
+use crate::schema::Schemas;
+use crate::scripting::{ScriptLimits, Scripts};
+use crate::snapshot::SnapshotJobs;
+use crate::stream::{StreamEntry, StreamId};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
 #[derive(Debug, Clone)]
 pub enum Command {
@@ -10,48 +35,256 @@ pub enum Command {
     },
     Set {
         key: String,
-        value: String,
+        value: Vec<u8>, // Raw bytes; stored as a string when they're UTF-8
         options: SetOptions,
+        concern: Option<WriteConcern>, // None follows the AOF's policy
     },
     Del {
         keys: Vec<String>,
+        cascade: bool, // Also delete the keys' dependents, as with `Config::cascade_deletes`
     },
     Expire {
         key: String,
-        seconds: u64,
+        ttl: u64,
+        millis: bool, // PEXPIRE: `ttl` is in milliseconds rather than seconds
+    },
+    ExpireAt {
+        key: String,
+        timestamp: u64, // Unix seconds, or milliseconds with `millis`
+        millis: bool,
     },
     Ttl {
         key: String,
+        millis: bool,
+    },
+    ExpireTime {
+        key: String,
+        millis: bool,
     },
     Persist {
         key: String,
     },
+    Rename {
+        key: String,
+        new_key: String,
+    },
     Exists {
         keys: Vec<String>,
     },
+    Eval {
+        script: String, // WebAssembly module; see `Scripts`
+        keys: Vec<String>,
+        args: Vec<String>,
+    },
+    ScriptKill {},
     Ping {
         message: Option<String>,
     },
+    Info {
+        sections: Vec<String>,
+    },
     ListKeys {
         pattern: String,
         limit: Option<u64>,
+        cursor: Option<u64>, // Shard to resume from, as given by a partial reply
+    },
+    Scan {
+        cursor: u64, // 0 starts a walk; the reply's cursor continues it
+        pattern: Option<String>,
+        count: Option<u64>,
     },
     FlushAll {},
+    FlushPattern {
+        pattern: String,
+    },
+    BgSave {},
+    ReplicaOf {
+        primary: Option<(String, u16)>, // None for REPLICAOF NO ONE
+    },
+    Mirror {
+        target: Option<(String, u16)>, // None for MIRROR NO ONE
+        filter: MirrorFilter,
+    },
+    IncrBy {
+        key: String,
+        delta: i64,
+        limit: Option<i64>, // Only apply if the result stays at or below this
+    },
+    IncrByFloat {
+        key: String,
+        delta: f64,
+    },
+    Append {
+        key: String,
+        value: Vec<u8>,
+    },
+    StrLen {
+        key: String,
+    },
+    GetRange {
+        key: String,
+        start: i64, // Negative offsets count from the end
+        end: i64,
+    },
+    SetRange {
+        key: String,
+        offset: usize,
+        value: Vec<u8>,
+    },
+    HSet {
+        key: String,
+        fields: Vec<(String, String)>,
+    },
+    HGet {
+        key: String,
+        field: String,
+    },
+    HDel {
+        key: String,
+        fields: Vec<String>,
+    },
+    HGetAll {
+        key: String,
+    },
+    HIncrBy {
+        key: String,
+        field: String,
+        delta: i64,
+    },
+    SAdd {
+        key: String,
+        members: Vec<String>,
+    },
+    SRem {
+        key: String,
+        members: Vec<String>,
+    },
+    SMembers {
+        key: String,
+    },
+    SIsMember {
+        key: String,
+        member: String,
+    },
+    SInter {
+        keys: Vec<String>,
+    },
+    SUnion {
+        keys: Vec<String>,
+    },
+    PfAdd {
+        key: String,
+        members: Vec<String>,
+    },
+    PfCount {
+        keys: Vec<String>,
+    },
+    PfMerge {
+        dest: String,
+        sources: Vec<String>,
+    },
+    JsonSet {
+        key: String,
+        path: String,
+        value: String, // JSON text, parsed when the command runs
+        nx: bool,
+        xx: bool,
+    },
+    JsonGet {
+        key: String,
+        path: String,
+    },
+    JsonDel {
+        key: String,
+        path: String,
+    },
+    HExpire {
+        key: String,
+        seconds: u64,
+        fields: Vec<String>,
+    },
+    HTtl {
+        key: String,
+        fields: Vec<String>,
+    },
+    HPersist {
+        key: String,
+        fields: Vec<String>,
+    },
+    XRead {
+        count: Option<u64>,
+        streams: Vec<(String, StreamId)>, // Read entries after each ID
+    },
+    Type {
+        key: String,
+    },
+    Object {
+        subcommand: ObjectSubcommand,
+        key: String,
+    },
+    Publish {
+        channel: String,
+        message: String,
+    },
+    MemoryPurge {},
     // custom
+    ExpiringKeys {
+        within: u64,
+        limit: Option<u64>,
+    },
     SetParent {
+        key: String,
+        parent: String, // Replaces any other parents
+    },
+    AddParent {
         key: String,
         parent: String,
     },
     GetParent {
         key: String,
     },
+    GetParents {
+        key: String,
+    },
+    UnsetParent {
+        key: String,
+        parent: Option<String>, // Just this link; None removes them all
+    },
+    ReparentChildren {
+        from: String,
+        to: String,
+    },
+    ImportGraph {
+        edges: Vec<DependencyEdge>,
+    },
     GetChildren {
         parent: String,
         depth: Option<u64>,
+        offset: Option<u64>,
+        limit: Option<u64>, // Capped by Config::max_children_results
+        hydrate: Option<HydrateOptions>,
     },
     GetInfo {
         key: String,
     },
+    Invalidate {
+        roots: Vec<String>,
+        mode: InvalidationMode,
+    },
+    InvalidateTag {
+        tags: Vec<String>,
+        mode: InvalidationMode,
+    },
+    /// Reports what a DEL, FLUSHALL, FLUSHPATTERN, INVALIDATE or INVALIDATE_TAG would affect
+    DryRun {
+        command: Box<Command>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectSubcommand {
+    Encoding,
+    Freq,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -61,34 +294,656 @@ pub struct KeyInfo {
     pub ttl: i64,
     pub value: Option<String>,
     pub parent: Option<String>,
+    #[serde(default)]
+    pub parents: Vec<String>, // Every live parent, `parent` first
+    pub parent_ttl: Option<u64>, // Seconds before the parent link lapses, if it does
     pub children_count: usize,
+    pub children_truncated: bool, // Count hit `max_children_results`; depth isn't checked
+    pub stale: bool,
+    pub pinned: bool,
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub deleted_at: Option<u64>, // Unix ms of the delete, while soft delete keeps its tombstone
+}
+
+/// Default cap on value bytes included when hydrating children
+pub const DEFAULT_HYDRATE_MAX_BYTES: usize = 1024 * 1024;
+/// Keys a SCAN step aims for without COUNT, as in Redis
+pub const DEFAULT_SCAN_COUNT: u64 = 10;
+
+#[derive(Debug, Clone)]
+pub struct HydrateOptions {
+    pub max_bytes: usize, // Values past this budget are omitted and the reply marked truncated
+}
+
+impl Default for HydrateOptions {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_HYDRATE_MAX_BYTES,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChildInfo {
+    pub key: String,
+    pub depth: u64,
+    pub value: Option<String>,
+    pub ttl: i64,
+    pub size: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HydratedChildren {
+    pub children: Vec<ChildInfo>,
+    pub truncated: bool,
 }
 
 #[derive(Debug, Clone)]
 pub enum CommandResponse {
     Ok,
     Value(String),
+    Bytes(Vec<u8>), // A string value as stored, which needn't be UTF-8
     Integer(i64),
+    Integers(Vec<i64>),
     Array(Vec<String>),
     ArrayWithDepth(Vec<(String, u64)>),
-    KeyInfo(KeyInfo),
+    Children(HydratedChildren),
+    Streams(Vec<(String, Vec<StreamEntry>)>),
+    KeyInfo(Box<KeyInfo>),
+    DryRun(DryRunReport),
+    /// A reply cut short by `Config::command_time_budget`; repeating the command
+    /// with `cursor` carries on
+    Partial {
+        cursor: u64,
+        reply: Box<CommandResponse>,
+    },
     Null,
-    Error(String),
+    Error(CommandError),
+}
+
+impl CommandResponse {
+    /// JSON form of the reply, with errors rendered as `{"error": "ERR ..."}`
+    pub fn to_json(&self) -> serde_json::Value {
+        use serde_json::{Value as Json, json};
+        match self {
+            CommandResponse::Ok => json!("OK"),
+            CommandResponse::Value(value) => json!(value),
+            CommandResponse::Bytes(bytes) => json!(String::from_utf8_lossy(bytes)),
+            CommandResponse::Integer(i) => json!(i),
+            CommandResponse::Integers(items) => json!(items),
+            CommandResponse::Array(items) => json!(items),
+            CommandResponse::ArrayWithDepth(items) => json!(items),
+            CommandResponse::Children(children) => json!(children),
+            CommandResponse::Streams(streams) => json!(streams),
+            CommandResponse::KeyInfo(info) => json!(info),
+            CommandResponse::DryRun(report) => json!(report),
+            CommandResponse::Partial { cursor, reply } => {
+                json!({ "cursor": cursor, "result": reply.to_json() })
+            }
+            CommandResponse::Null => Json::Null,
+            CommandResponse::Error(e) => json!({ "error": e.to_string() }),
+        }
+    }
+}
+
+impl Command {
+    /// Name of the command, as listed in the command table
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Get { .. } => "GET",
+            Command::Set { .. } => "SET",
+            Command::Del { cascade: false, .. } => "DEL",
+            Command::Del { cascade: true, .. } => "DELCASCADE",
+            Command::Expire { millis: false, .. } => "EXPIRE",
+            Command::Expire { millis: true, .. } => "PEXPIRE",
+            Command::ExpireAt { millis: false, .. } => "EXPIREAT",
+            Command::ExpireAt { millis: true, .. } => "PEXPIREAT",
+            Command::Ttl { millis: false, .. } => "TTL",
+            Command::Ttl { millis: true, .. } => "PTTL",
+            Command::ExpireTime { millis: false, .. } => "EXPIRETIME",
+            Command::ExpireTime { millis: true, .. } => "PEXPIRETIME",
+            Command::Persist { .. } => "PERSIST",
+            Command::Rename { .. } => "RENAME",
+            Command::Exists { .. } => "EXISTS",
+            Command::Eval { .. } => "EVAL",
+            Command::ScriptKill {} => "SCRIPT",
+            Command::Ping { .. } => "PING",
+            Command::Info { .. } => "INFO",
+            Command::ListKeys { .. } => "KEYS",
+            Command::Scan { .. } => "SCAN",
+            Command::FlushAll {} => "FLUSHALL",
+            Command::FlushPattern { .. } => "FLUSHPATTERN",
+            Command::BgSave {} => "BGSAVE",
+            Command::ReplicaOf { .. } => "REPLICAOF",
+            Command::Mirror { .. } => "MIRROR",
+            Command::IncrBy { .. } => "INCRBY",
+            Command::IncrByFloat { .. } => "INCRBYFLOAT",
+            Command::Append { .. } => "APPEND",
+            Command::StrLen { .. } => "STRLEN",
+            Command::GetRange { .. } => "GETRANGE",
+            Command::SetRange { .. } => "SETRANGE",
+            Command::HSet { .. } => "HSET",
+            Command::HGet { .. } => "HGET",
+            Command::HDel { .. } => "HDEL",
+            Command::HGetAll { .. } => "HGETALL",
+            Command::HIncrBy { .. } => "HINCRBY",
+            Command::SAdd { .. } => "SADD",
+            Command::SRem { .. } => "SREM",
+            Command::SMembers { .. } => "SMEMBERS",
+            Command::SIsMember { .. } => "SISMEMBER",
+            Command::SInter { .. } => "SINTER",
+            Command::PfAdd { .. } => "PFADD",
+            Command::PfCount { .. } => "PFCOUNT",
+            Command::PfMerge { .. } => "PFMERGE",
+            Command::JsonSet { .. } => "JSON.SET",
+            Command::JsonGet { .. } => "JSON.GET",
+            Command::JsonDel { .. } => "JSON.DEL",
+            Command::SUnion { .. } => "SUNION",
+            Command::HExpire { .. } => "HEXPIRE",
+            Command::HTtl { .. } => "HTTL",
+            Command::HPersist { .. } => "HPERSIST",
+            Command::XRead { .. } => "XREAD",
+            Command::Type { .. } => "TYPE",
+            Command::Object { .. } => "OBJECT",
+            Command::Publish { .. } => "PUBLISH",
+            Command::MemoryPurge {} => "MEMORY",
+            Command::ExpiringKeys { .. } => "EXPIRING",
+            Command::SetParent { .. } => "SETPARENT",
+            Command::AddParent { .. } => "ADDPARENT",
+            Command::GetParent { .. } => "GETPARENT",
+            Command::GetParents { .. } => "GETPARENTS",
+            Command::UnsetParent { .. } => "UNSETPARENT",
+            Command::ReparentChildren { .. } => "REPARENT",
+            Command::ImportGraph { .. } => "IMPORTGRAPH",
+            Command::GetChildren { .. } => "GETCHILDREN",
+            Command::GetInfo { .. } => "KEYINFO",
+            Command::Invalidate { .. } => "INVALIDATE",
+            Command::InvalidateTag { .. } => "INVALIDATE_TAG",
+            Command::DryRun { .. } => "DRYRUN",
+        }
+    }
+
+    /// Keys the command reads or writes
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Command::Get { key }
+            | Command::Set { key, .. }
+            | Command::Expire { key, .. }
+            | Command::ExpireAt { key, .. }
+            | Command::Ttl { key, .. }
+            | Command::ExpireTime { key, .. }
+            | Command::Persist { key }
+            | Command::SetParent { key, .. }
+            | Command::AddParent { key, .. }
+            | Command::GetParent { key }
+            | Command::GetParents { key }
+            | Command::UnsetParent { key, .. }
+            | Command::GetInfo { key }
+            | Command::IncrBy { key, .. }
+            | Command::IncrByFloat { key, .. }
+            | Command::Append { key, .. }
+            | Command::StrLen { key }
+            | Command::GetRange { key, .. }
+            | Command::SetRange { key, .. }
+            | Command::HSet { key, .. }
+            | Command::HGet { key, .. }
+            | Command::HDel { key, .. }
+            | Command::HGetAll { key }
+            | Command::HIncrBy { key, .. }
+            | Command::SAdd { key, .. }
+            | Command::PfAdd { key, .. }
+            | Command::JsonSet { key, .. }
+            | Command::JsonGet { key, .. }
+            | Command::JsonDel { key, .. }
+            | Command::SRem { key, .. }
+            | Command::SMembers { key }
+            | Command::SIsMember { key, .. }
+            | Command::HExpire { key, .. }
+            | Command::HTtl { key, .. }
+            | Command::HPersist { key, .. }
+            | Command::Type { key }
+            | Command::Object { key, .. } => vec![key],
+            Command::GetChildren { parent, .. } => vec![parent],
+            Command::XRead { streams, .. } => streams.iter().map(|(key, _)| key.as_str()).collect(),
+            Command::ReparentChildren { from, to } => vec![from, to],
+            Command::ImportGraph { edges } => edges.iter().map(|edge| edge.key.as_str()).collect(),
+            Command::Rename { key, new_key } => vec![key, new_key],
+            Command::PfMerge { dest, sources } => std::iter::once(dest.as_str())
+                .chain(sources.iter().map(String::as_str))
+                .collect(),
+            Command::Del { keys, .. }
+            | Command::Exists { keys }
+            | Command::Eval { keys, .. }
+            | Command::SInter { keys }
+            | Command::SUnion { keys }
+            | Command::PfCount { keys }
+            | Command::Invalidate { roots: keys, .. } => keys.iter().map(String::as_str).collect(),
+            Command::ScriptKill {}
+            | Command::Ping { .. }
+            | Command::Info { .. }
+            | Command::ListKeys { .. }
+            | Command::Scan { .. }
+            | Command::FlushAll {}
+            | Command::FlushPattern { .. }
+            | Command::InvalidateTag { .. } // Tags aren't keys
+            | Command::BgSave {}
+            | Command::ReplicaOf { .. }
+            | Command::Mirror { .. }
+            | Command::Publish { .. }
+            | Command::MemoryPurge {}
+            | Command::ExpiringKeys { .. } => Vec::new(),
+            Command::DryRun { command } => command.keys(),
+        }
+    }
 }
 
 pub struct CommandExecutor {
     pub cache: Arc<Cache>,
+    scripts: Scripts,
+    middleware: Vec<Arc<dyn Middleware>>,
+    stats_aggregator: Option<Arc<StatsAggregator>>,
+    oplog: Option<OpLog>,
+    aof: Option<Arc<Aof>>,
+    snapshots: Option<Arc<SnapshotJobs>>,
+    replication: Option<Arc<Replication>>,
+    mirroring: Option<Arc<Mirroring>>,
+    primary: Option<Arc<Primary>>,
+    bus: Option<Arc<InvalidationBus>>,
+    counters: Option<Arc<CounterBuffer>>,
+    pubsub: Arc<PubSub>,
+    refresh: Option<Arc<RefreshAhead>>,
+    response_cache: Option<ResponseCache>,
+    schemas: Option<Arc<Schemas>>,
+    plugins: Option<Plugins>,
+    this: Weak<CommandExecutor>, // Handed to plugins, whose host API runs commands here
+    write_offset: AtomicU64,     // Successful writes so far; backs read-your-writes tokens
 }
 
+/// How often `wait_for_offset` re-checks the write offset
+const OFFSET_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
 impl CommandExecutor {
     pub fn new(cache: Arc<Cache>) -> Self {
-        Self { cache }
+        Self {
+            cache,
+            scripts: Scripts::new(ScriptLimits::default()),
+            middleware: Vec::new(),
+            stats_aggregator: None,
+            oplog: None,
+            aof: None,
+            snapshots: None,
+            replication: None,
+            mirroring: None,
+            primary: None,
+            bus: None,
+            counters: None,
+            pubsub: Arc::new(PubSub::default()),
+            refresh: None,
+            response_cache: None,
+            schemas: None,
+            plugins: None,
+            this: Weak::new(),
+            write_offset: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_script_limits(mut self, limits: ScriptLimits) -> Self {
+        self.scripts = Scripts::new(limits);
+        self
+    }
+
+    pub fn scripts(&self) -> &Scripts {
+        &self.scripts
+    }
+
+    pub fn with_middleware(mut self, middleware: Arc<dyn Middleware>) -> Self {
+        self.middleware.push(middleware);
+        self
+    }
+
+    pub fn with_stats_aggregator(mut self, aggregator: Arc<StatsAggregator>) -> Self {
+        self.stats_aggregator = Some(aggregator);
+        self
+    }
+
+    pub fn with_oplog(mut self, oplog: OpLog) -> Self {
+        self.oplog = Some(oplog);
+        self
+    }
+
+    /// Appends successful writes to the AOF; replay it with `Aof::load` beforehand
+    pub fn with_aof(mut self, aof: Arc<Aof>) -> Self {
+        self.aof = Some(aof);
+        self
+    }
+
+    /// Enables BGSAVE, saving to the jobs' snapshot file
+    pub fn with_snapshots(mut self, jobs: Arc<SnapshotJobs>) -> Self {
+        self.snapshots = Some(jobs);
+        self
+    }
+
+    pub fn snapshots(&self) -> Option<&Arc<SnapshotJobs>> {
+        self.snapshots.as_ref()
+    }
+
+    /// Lets REPLICAOF follow a Redis primary
+    pub fn with_replication(mut self, replication: Arc<Replication>) -> Self {
+        self.replication = Some(replication);
+        self
+    }
+
+    pub fn replication(&self) -> Option<&Arc<Replication>> {
+        self.replication.as_ref()
+    }
+
+    /// Lets MIRROR copy keys to another instance
+    pub fn with_mirroring(mut self, mirroring: Arc<Mirroring>) -> Self {
+        self.mirroring = Some(mirroring);
+        self
+    }
+
+    pub fn mirroring(&self) -> Option<&Arc<Mirroring>> {
+        self.mirroring.as_ref()
+    }
+
+    /// Streams successful writes to replicas that connect over RESP with PSYNC
+    pub fn with_primary(mut self, primary: Arc<Primary>) -> Self {
+        self.primary = Some(primary);
+        self
+    }
+
+    pub fn primary(&self) -> Option<&Arc<Primary>> {
+        self.primary.as_ref()
+    }
+
+    /// Dispatches commands the command table doesn't know to `plugins`. `this` is the
+    /// executor's own handle, so build it with `Arc::new_cyclic`.
+    pub fn with_plugins(mut self, plugins: Plugins, this: Weak<CommandExecutor>) -> Self {
+        self.plugins = Some(plugins);
+        self.this = this;
+        self
+    }
+
+    pub fn plugins(&self) -> Option<&Plugins> {
+        self.plugins.as_ref()
+    }
+
+    /// Runs a plugin command from raw arguments, or None if no plugin defines it.
+    /// Protocol layers call this for names `Command::parse` doesn't recognize.
+    pub fn execute_plugin(&self, args: &[&[u8]]) -> Option<CommandResponse> {
+        self.plugins.as_ref()?.call(&self.this, args, None)
+    }
+
+    /// `execute_plugin`, with `check` vetting each command the plugin issues
+    pub fn execute_plugin_checked(
+        &self,
+        args: &[&[u8]],
+        check: CommandCheck,
+    ) -> Option<CommandResponse> {
+        self.plugins.as_ref()?.call(&self.this, args, Some(check))
+    }
+
+    /// Broker behind PUBLISH, which protocol layers subscribe connections to
+    pub fn pubsub(&self) -> &Arc<PubSub> {
+        &self.pubsub
+    }
+
+    /// Sends successful invalidations to the bus's peers
+    pub fn with_invalidation_bus(mut self, bus: Arc<InvalidationBus>) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    pub fn invalidation_bus(&self) -> Option<&Arc<InvalidationBus>> {
+        self.bus.as_ref()
+    }
+
+    /// Exposes refresh-ahead policies to the protocol layers; its `run` task must be
+    /// running for them to take effect
+    pub fn with_refresh_ahead(mut self, refresh: Arc<RefreshAhead>) -> Self {
+        self.refresh = Some(refresh);
+        self
+    }
+
+    pub fn refresh_ahead(&self) -> Option<&Arc<RefreshAhead>> {
+        self.refresh.as_ref()
+    }
+
+    /// Serves repeats of expensive reads, such as GETCHILDREN on big graphs, from a
+    /// short-lived cache keyed by the write offset
+    pub fn with_response_cache(mut self, cache: ResponseCache) -> Self {
+        self.response_cache = Some(cache);
+        self
+    }
+
+    /// Validates SETs against namespace schemas, running as middleware from here on
+    pub fn with_schemas(mut self, schemas: Arc<Schemas>) -> Self {
+        self.middleware.push(schemas.clone());
+        self.schemas = Some(schemas);
+        self
+    }
+
+    pub fn schemas(&self) -> Option<&Arc<Schemas>> {
+        self.schemas.as_ref()
+    }
+
+    /// Buffers INCRBY on matching keys; the buffer's `run` task must be flushing it
+    pub fn with_counters(mut self, counters: Arc<CounterBuffer>) -> Self {
+        self.counters = Some(counters);
+        self
+    }
+
+    pub fn stats_aggregator(&self) -> Option<&Arc<StatsAggregator>> {
+        self.stats_aggregator.as_ref()
+    }
+
+    /// Number of writes applied so far. Handed to clients as a consistency token: once
+    /// a node's offset reaches it, that node has seen the client's writes.
+    pub fn write_offset(&self) -> u64 {
+        self.write_offset.load(Ordering::Acquire)
+    }
+
+    /// Waits until the write offset reaches `offset`; false if `timeout` passes first
+    pub async fn wait_for_offset(&self, offset: u64, timeout: Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        while self.write_offset() < offset {
+            if tokio::time::Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(OFFSET_POLL_INTERVAL).await;
+        }
+        true
     }
 
     pub fn execute(&self, cmd: Command) -> CommandResponse {
+        // Nested in the caller's request span when it's traced
+        let _span = tracing::debug_span!("command", name = cmd.name()).entered();
+        let mut cmd = cmd;
+        for middleware in &self.middleware {
+            cmd = match middleware.before(cmd) {
+                Ok(cmd) => cmd,
+                Err(response) => return response,
+            };
+        }
+
+        let name = cmd.name();
+        let concern = match &cmd {
+            Command::Set { concern, .. } => *concern,
+            _ => None,
+        };
+        let is_write = command_table::lookup(name).is_some_and(|spec| spec.write);
+        if is_write && self.oplog.is_some() && cmd.keys().contains(&OPLOG_KEY) {
+            return CommandResponse::Error(CommandError::err(format!(
+                "'{}' is reserved for the oplog",
+                OPLOG_KEY
+            )));
+        }
+        let logged_args = (is_write
+            && (self.oplog.is_some()
+                || self.aof.is_some()
+                || self.primary.is_some()
+                || self.bus.is_some()))
+        .then(|| cmd.to_args());
+        let jittered = logged_args.as_ref().and_then(|_| self.jittered_set(&cmd));
+        // The oplog is read back later, so every SET is logged with its deadline
+        let deadline_set = self
+            .oplog
+            .as_ref()
+            .and_then(|_| stripped_set(&cmd))
+            .filter(|_| is_write);
+        let written = (is_write && self.cache.has_event_subscribers())
+            .then(|| cmd.keys().into_iter().map(String::from).collect::<Vec<_>>());
+        let _aof_guard = self
+            .aof
+            .as_ref()
+            .filter(|_| is_write)
+            .map(|aof| aof.begin_write());
+        let _primary_guard = self
+            .primary
+            .as_ref()
+            .filter(|_| is_write)
+            .map(|primary| primary.begin_write());
+        let _oplog_guard = self
+            .oplog
+            .as_ref()
+            .filter(|_| is_write)
+            .map(OpLog::begin_write);
+
+        let start = Instant::now();
+        let mut response = match concern {
+            Some(concern @ (WriteConcern::Logged | WriteConcern::Fsynced))
+                if self.aof.is_none() =>
+            {
+                CommandResponse::Error(CommandError::err(format!(
+                    "write concern '{}' needs the AOF enabled",
+                    concern
+                )))
+            }
+            _ => match &self.response_cache {
+                Some(cache) if ResponseCache::caches(&cmd) => {
+                    let args = cmd.to_args();
+                    let version = self.write_offset();
+                    cache.get(&args, version).unwrap_or_else(|| {
+                        let response = self.dispatch(cmd);
+                        cache.insert(args, version, &response);
+                        response
+                    })
+                }
+                _ => self.dispatch(cmd),
+            },
+        };
+        let elapsed = start.elapsed();
+
+        if is_write && !matches!(response, CommandResponse::Error(_)) {
+            let oplog_args = deadline_set.and_then(|(key, cmd)| self.resolved_args(&key, cmd));
+            let logged_args = match jittered {
+                Some((key, cmd)) => self.resolved_args(&key, cmd).or(logged_args),
+                None => logged_args,
+            };
+            if let (Some(aof), Some(args)) = (&self.aof, &logged_args)
+                && concern != Some(WriteConcern::CacheOnly)
+            {
+                let fsync = concern == Some(WriteConcern::Fsynced);
+                if let Err(e) = aof.append(args, fsync)
+                    && fsync
+                {
+                    response = CommandResponse::Error(CommandError::err(format!(
+                        "write applied but not fsynced: {}",
+                        e
+                    )));
+                }
+            }
+            if let (Some(primary), Some(args)) = (&self.primary, &logged_args) {
+                primary.propagate(args);
+            }
+            if let (Some(bus), Some(args)) = (&self.bus, &logged_args) {
+                bus.publish(args);
+            }
+            if let (Some(oplog), Some(args)) = (&self.oplog, oplog_args.or(logged_args)) {
+                oplog.record(&self.cache, args);
+            }
+            if let Some(keys) = written {
+                self.cache.notify_written(keys);
+            }
+            self.write_offset.fetch_add(1, Ordering::Release);
+        }
+
+        for middleware in self.middleware.iter().rev() {
+            middleware.after(name, &mut response, elapsed);
+        }
+        response
+    }
+
+    /// A SET whose TTL gets jittered, with its TTL and jitter stripped, so it can be
+    /// logged with the deadline it was given rather than rolling another on replay
+    fn jittered_set(&self, cmd: &Command) -> Option<(String, Command)> {
+        let Command::Set { key, options, .. } = cmd else {
+            return None;
+        };
+        // An explicit JITTER, even 0, is resolved too: PXAT parses back to JITTER 0
+        let policy = self.cache.config().ttl_policy_for(key);
+        if options.jitter.is_none() && policy.jitter.is_none_or(|percent| percent == 0) {
+            return None;
+        }
+        stripped_set(cmd)
+    }
+
+    /// Logged form of a SET once applied: PXAT with the key's deadline, or None when
+    /// it has none and the SET can be logged as given
+    fn resolved_args(&self, key: &str, stripped: Command) -> Option<Vec<Vec<u8>>> {
+        let at = self.cache.expire_time(key);
+        if at < 0 {
+            return None;
+        }
+        let mut args = stripped.to_args();
+        args.extend([b"PXAT".to_vec(), at.to_string().into_bytes()]);
+        Some(args)
+    }
+
+    /// Prometheus metrics: cache stats, aggregated keyspace stats, and anything
+    /// contributed by middleware
+    pub fn render_metrics(&self) -> String {
+        let labels = &self.cache.stats().labels;
+        let mut out = self.cache.stats().render();
+        self.cache.render_cleanup_metrics(&mut out, labels);
+        if let Some(aggregator) = &self.stats_aggregator {
+            aggregator.render_metrics(&mut out, labels);
+        }
+        if let Some(bus) = &self.bus {
+            bus.render_metrics(&mut out, labels);
+        }
+        if let Some(refresh) = &self.refresh {
+            refresh.render_metrics(&mut out, labels);
+        }
+        if let Some(mirroring) = &self.mirroring {
+            mirroring.render_metrics(&mut out, labels);
+        }
+        self.pubsub.render_metrics(&mut out, labels);
+        if let Some(cache) = &self.response_cache {
+            cache.render_metrics(&mut out, labels);
+        }
+        for middleware in &self.middleware {
+            middleware.render_metrics(&mut out, labels);
+        }
+        out
+    }
+
+    fn dispatch(&self, cmd: Command) -> CommandResponse {
+        if let Err(e) = command_table::validate(&cmd) {
+            return CommandResponse::Error(e.into());
+        }
+
         match cmd {
+            // Only string values are read back; other types aren't described as text
             Command::Get { key } => match self.cache.get(&key) {
-                Some(value) => CommandResponse::Value(value.to_string()),
+                Some(value) => match value.as_bytes() {
+                    Some(bytes) => CommandResponse::Bytes(bytes.into_owned()),
+                    None => CommandResponse::Error(CacheError::WrongType.into()),
+                },
                 None => CommandResponse::Null,
             },
 
@@ -96,15 +951,26 @@ impl CommandExecutor {
                 key,
                 value,
                 options,
-            } => match self.cache.set(key, Value::String(value), options) {
-                Ok(true) => CommandResponse::Ok,
-                Ok(false) => CommandResponse::Null,
-                Err(_) => CommandResponse::Error("SET failed".to_string()),
-            },
+                ..
+            } => {
+                // Values that aren't UTF-8 are kept as bytes
+                let value = match String::from_utf8(value) {
+                    Ok(value) => self.encode_value(value),
+                    Err(e) => Value::Bytes(e.into_bytes()),
+                };
+                match self.cache.set(key, value, options) {
+                    Ok(true) => CommandResponse::Ok,
+                    Ok(false) => CommandResponse::Null,
+                    Err(e) => CommandResponse::Error(e.into()),
+                }
+            }
 
-            Command::Del { keys } => {
+            Command::Del { keys, cascade } => {
                 let key_refs: Vec<&str> = keys.iter().map(String::as_str).collect();
-                let deleted = self.cache.del(&key_refs);
+                let deleted = match cascade {
+                    true => self.cache.del_cascade(&key_refs),
+                    false => self.cache.del(&key_refs),
+                };
                 CommandResponse::Integer(deleted as i64)
             }
 
@@ -114,18 +980,56 @@ impl CommandExecutor {
                 CommandResponse::Integer(count as i64)
             }
 
+            Command::Eval { script, keys, args } => self.scripts.eval(self, &script, &keys, &args),
+
+            Command::ScriptKill {} => match self.scripts.kill() {
+                0 => CommandResponse::Error(CommandError::new(
+                    ErrorClass::NotBusy,
+                    "No scripts in execution right now.",
+                )),
+                _ => CommandResponse::Ok,
+            },
+
             Command::Ping { message } => match message {
                 Some(msg) => CommandResponse::Value(msg),
                 None => CommandResponse::Value("PONG".to_string()),
             },
 
-            Command::Ttl { key } => {
-                let ttl = self.cache.ttl(&key);
-                CommandResponse::Integer(ttl)
+            Command::Info { sections } => CommandResponse::Value(info::render(
+                &self.cache,
+                self.stats_aggregator.as_deref(),
+                &sections,
+            )),
+
+            Command::Ttl { key, millis } => CommandResponse::Integer(match millis {
+                true => self.cache.pttl(&key),
+                false => self.cache.ttl(&key),
+            }),
+
+            Command::ExpireTime { key, millis } => {
+                let at = self.cache.expire_time(&key);
+                CommandResponse::Integer(if at < 0 || millis { at } else { at / 1000 })
             }
 
-            Command::Expire { key, seconds } => {
-                let result = self.cache.expire(&key, seconds);
+            Command::Expire { key, ttl, millis } => {
+                let result = match millis {
+                    true => self.cache.pexpire(&key, ttl),
+                    false => self.cache.expire(&key, ttl),
+                };
+                CommandResponse::Integer(result)
+            }
+
+            Command::ExpireAt {
+                key,
+                timestamp,
+                millis,
+            } => {
+                let at = UNIX_EPOCH
+                    + match millis {
+                        true => Duration::from_millis(timestamp),
+                        false => Duration::from_secs(timestamp),
+                    };
+                let result = self.cache.expire_at(&key, at);
                 CommandResponse::Integer(result)
             }
 
@@ -134,9 +1038,19 @@ impl CommandExecutor {
                 CommandResponse::Integer(result)
             }
 
+            Command::Rename { key, new_key } => match self.cache.rename(&key, &new_key) {
+                Ok(()) => CommandResponse::Ok,
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
             Command::SetParent { key, parent } => match self.cache.set_parent(&key, parent) {
                 Ok(i) => CommandResponse::Integer(i),
-                Err(e) => CommandResponse::Error(e.to_string()),
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            Command::AddParent { key, parent } => match self.cache.add_parent(&key, parent, None) {
+                Ok(i) => CommandResponse::Integer(i),
+                Err(e) => CommandResponse::Error(e.into()),
             },
 
             Command::GetParent { key } => match self.cache.parent(&key) {
@@ -144,20 +1058,102 @@ impl CommandExecutor {
                 None => CommandResponse::Null,
             },
 
-            Command::GetChildren { parent, depth } => {
+            Command::GetParents { key } => CommandResponse::Array(self.cache.parents(&key)),
+
+            Command::UnsetParent { key, parent } => CommandResponse::Integer(match parent {
+                Some(parent) => self.cache.remove_parent(&key, &parent),
+                None => self.cache.unset_parent(&key),
+            }),
+
+            Command::ReparentChildren { from, to } => {
+                match self.cache.reparent_children(&from, &to) {
+                    Ok(moved) => CommandResponse::Integer(moved as i64),
+                    Err(e) => CommandResponse::Error(e.into()),
+                }
+            }
+
+            // Replies [applied, missing, cycles, too_deep]
+            Command::ImportGraph { edges } => match self.cache.import_dependency_graph(&edges) {
+                Ok(result) => CommandResponse::Integers(
+                    [
+                        result.applied,
+                        result.missing,
+                        result.cycles,
+                        result.too_deep,
+                    ]
+                    .map(|count| count as i64)
+                    .to_vec(),
+                ),
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            Command::GetChildren {
+                parent,
+                depth,
+                offset,
+                limit,
+                hydrate,
+            } => {
                 let depth_usize = depth.and_then(|l| usize::try_from(l).ok()).unwrap_or(1);
+                let offset_usize = offset.and_then(|o| usize::try_from(o).ok()).unwrap_or(0);
+                let max_results = self.cache.config().max_children_results;
+                let limit_usize = limit
+                    .and_then(|l| usize::try_from(l).ok())
+                    .map_or(max_results, |l| l.min(max_results));
+
+                let (children, cursor) = self.cache.children_page_until(
+                    &parent,
+                    depth_usize,
+                    offset_usize,
+                    limit_usize,
+                    self.deadline(),
+                );
 
-                let children = self.cache.children_recursive(&parent, depth_usize);
+                let reply = match hydrate {
+                    Some(options) => CommandResponse::Children(self.hydrate(children, &options)),
+                    None => CommandResponse::ArrayWithDepth(children),
+                };
+                partial(reply, cursor)
+            }
+
+            // Always [cursor, keys], the cursor 0 once the walk is done
+            Command::Scan {
+                cursor,
+                pattern,
+                count,
+            } => {
+                let count = count.unwrap_or(DEFAULT_SCAN_COUNT) as usize;
+                let (cursor, keys) = self.cache.scan(cursor, pattern.as_deref(), count);
+                CommandResponse::Partial {
+                    cursor,
+                    reply: Box::new(CommandResponse::Array(keys)),
+                }
+            }
+
+            Command::ListKeys {
+                pattern,
+                limit,
+                cursor,
+            } => {
+                let limit_usize = limit
+                    .and_then(|l| usize::try_from(l).ok())
+                    .unwrap_or(usize::MAX);
+                let cursor_usize = cursor.and_then(|c| usize::try_from(c).ok()).unwrap_or(0);
 
-                CommandResponse::ArrayWithDepth(children)
+                let (keys, cursor) =
+                    self.cache
+                        .keys_page(&pattern, limit_usize, cursor_usize, self.deadline());
+                partial(CommandResponse::Array(keys), cursor)
             }
 
-            Command::ListKeys { pattern, limit } => {
+            Command::ExpiringKeys { within, limit } => {
                 let limit_usize = limit
                     .and_then(|l| usize::try_from(l).ok())
                     .unwrap_or(usize::MAX);
 
-                let keys = self.cache.keys(&pattern, limit_usize);
+                let keys = self
+                    .cache
+                    .expiring_within(Duration::from_secs(within), limit_usize);
                 CommandResponse::Array(keys)
             }
 
@@ -165,23 +1161,478 @@ impl CommandExecutor {
                 let exists = self.cache.exists(&key);
                 let ttl = self.cache.ttl(&key);
                 let value = self.cache.get(&key).map(|v| v.to_string());
-                let parent = self.cache.parent(&key);
-                let children_count = self.cache.children_recursive(&key, usize::MAX).len();
+                let parents = self.cache.parents(&key);
+                let parent = parents.first().cloned();
+                let parent_ttl = self
+                    .cache
+                    .parent_ttl(&key)
+                    .map(|ttl| ttl.as_secs_f64().ceil() as u64);
+                let config = self.cache.config();
+                let max_results = config.max_children_results;
+                let children_count = self
+                    .cache
+                    .children_page(
+                        &key,
+                        config.info_children_depth,
+                        0,
+                        max_results.saturating_add(1),
+                    )
+                    .len();
+                let children_truncated = children_count > max_results;
+                let children_count = children_count.min(max_results);
+                let stale = self.cache.is_stale(&key);
+                let pinned = self.cache.is_pinned(&key);
+                let priority = self.cache.priority(&key);
+                let tags = self.cache.tags(&key);
+                let deleted_at = self.cache.tombstone(&key).map(|tombstone| {
+                    let since_epoch = tombstone.deleted_at.duration_since(UNIX_EPOCH);
+                    since_epoch.unwrap_or_default().as_millis() as u64
+                });
 
-                CommandResponse::KeyInfo(KeyInfo {
+                CommandResponse::KeyInfo(Box::new(KeyInfo {
                     key,
                     exists,
                     ttl,
                     value,
                     parent,
+                    parents,
+                    parent_ttl,
                     children_count,
-                })
+                    children_truncated,
+                    stale,
+                    pinned,
+                    priority,
+                    tags,
+                    deleted_at,
+                }))
             }
 
+            // Validation only lets through commands that can be dry-run
+            Command::DryRun { command } => {
+                let matching = |pattern: &str| -> Vec<String> {
+                    (0..self.cache.shard_count())
+                        .flat_map(|shard| self.cache.shard_keys(shard, pattern))
+                        .collect()
+                };
+                let keys = match *command {
+                    Command::Del { keys, cascade } => {
+                        match cascade || self.cache.config().cascade_deletes {
+                            true => keys
+                                .iter()
+                                .flat_map(|key| self.cache.invalidation_keys(key))
+                                .collect(),
+                            false => keys,
+                        }
+                    }
+                    Command::FlushAll {} => matching("*"),
+                    Command::FlushPattern { pattern } => matching(&pattern),
+                    Command::Invalidate { roots, .. } => roots
+                        .iter()
+                        .flat_map(|root| self.cache.invalidation_keys(root))
+                        .collect(),
+                    Command::InvalidateTag { tags, .. } => tags
+                        .iter()
+                        .flat_map(|tag| self.cache.tag_invalidation_keys(tag))
+                        .map(|(key, _)| key)
+                        .collect(),
+                    command => {
+                        let e = ValidationError::NotDryRunnable(command.name().to_string());
+                        return CommandResponse::Error(e.into());
+                    }
+                };
+                CommandResponse::DryRun(self.cache.dry_run(keys))
+            }
+
+            Command::Invalidate { roots, mode } => CommandResponse::Integers(
+                roots
+                    .iter()
+                    .map(|root| self.cache.invalidate(root, mode) as i64)
+                    .collect(),
+            ),
+
+            Command::InvalidateTag { tags, mode } => CommandResponse::Integers(
+                tags.iter()
+                    .map(|tag| self.cache.invalidate_tag(tag, mode) as i64)
+                    .collect(),
+            ),
+
             Command::FlushAll {} => {
                 self.cache.flush_all();
                 CommandResponse::Ok
             }
+
+            Command::FlushPattern { pattern } => {
+                CommandResponse::Integer(self.cache.flush_pattern(&pattern) as i64)
+            }
+
+            Command::MemoryPurge {} => CommandResponse::Integer(self.cache.purge_memory() as i64),
+
+            Command::Publish { channel, message } => {
+                CommandResponse::Integer(self.pubsub.publish(&channel, &message) as i64)
+            }
+
+            Command::ReplicaOf { primary } => match &self.replication {
+                Some(replication) => {
+                    match primary {
+                        Some((host, port)) => {
+                            replication.replicate_from(format!("{}:{}", host, port))
+                        }
+                        None => {
+                            replication.stop();
+                        }
+                    }
+                    CommandResponse::Ok
+                }
+                None => CommandResponse::Error(CommandError::err("Replication is not configured")),
+            },
+
+            Command::Mirror { target, filter } => match &self.mirroring {
+                Some(mirroring) => {
+                    match target {
+                        Some((host, port)) => mirroring.start(format!("{}:{}", host, port), filter),
+                        None => {
+                            mirroring.stop();
+                        }
+                    }
+                    CommandResponse::Ok
+                }
+                None => CommandResponse::Error(CommandError::err("Mirroring is not configured")),
+            },
+
+            Command::BgSave {} => match &self.snapshots {
+                Some(jobs) => match jobs.start(self.cache.clone()) {
+                    Ok(_) => CommandResponse::Value("Background saving started".to_string()),
+                    Err(_) => CommandResponse::Error(CommandError::err(
+                        "Background save already in progress",
+                    )),
+                },
+                None => CommandResponse::Error(CommandError::err("Snapshots are not configured")),
+            },
+
+            // A rejected bounded increment replies nil, like SET NX on an existing key
+            // Buffered counters reply with the stored value plus this thread's pending
+            // delta; LIMIT needs the exact total, so it always goes to the cache
+            Command::IncrBy {
+                key,
+                delta,
+                limit: None,
+            } if self.counters.as_ref().is_some_and(|c| c.buffers(&key)) => {
+                let counters = self.counters.as_ref().unwrap();
+                match counters.add(&key, delta) {
+                    Ok(pending) => {
+                        let stored = self.cache.peek(&key).and_then(|(v, _)| v.as_integer());
+                        CommandResponse::Integer(stored.unwrap_or(0).saturating_add(pending))
+                    }
+                    Err(e) => CommandResponse::Error(e.into()),
+                }
+            }
+
+            Command::IncrBy { key, delta, limit } => match self.cache.incr_by(&key, delta, limit) {
+                Ok(IncrOutcome::Applied(value)) => CommandResponse::Integer(value),
+                Ok(IncrOutcome::Rejected(_)) => CommandResponse::Null,
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            // Replies with the new value as a string, like Redis
+            Command::IncrByFloat { key, delta } => match self.cache.incr_by_float(&key, delta) {
+                Ok(value) => CommandResponse::Value(Value::Float(value).to_string()),
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            Command::Append { key, value } => match self.cache.append(&key, &value) {
+                Ok(len) => CommandResponse::Integer(len as i64),
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            Command::StrLen { key } => match self.cache.strlen(&key) {
+                Ok(len) => CommandResponse::Integer(len as i64),
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            // A missing key reads as an empty string, like Redis
+            Command::GetRange { key, start, end } => match self.cache.get_range(&key, start, end) {
+                Ok(range) => {
+                    CommandResponse::Bytes(range.map(|(bytes, _)| bytes).unwrap_or_default())
+                }
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            Command::SetRange { key, offset, value } => {
+                match self.cache.set_range(&key, offset, &value) {
+                    Ok(len) => CommandResponse::Integer(len as i64),
+                    Err(e) => CommandResponse::Error(e.into()),
+                }
+            }
+
+            Command::HSet { key, fields } => {
+                let fields = fields
+                    .into_iter()
+                    .map(|(field, value)| (field, self.encode_value(value)))
+                    .collect();
+                match self.cache.hset(&key, fields) {
+                    Ok(added) => CommandResponse::Integer(added as i64),
+                    Err(e) => CommandResponse::Error(e.into()),
+                }
+            }
+
+            Command::HGet { key, field } => match self.cache.hget(&key, &field) {
+                Ok(Some(value)) => CommandResponse::Value(value.to_string()),
+                Ok(None) => CommandResponse::Null,
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            Command::HDel { key, fields } => match self.cache.hdel(&key, &fields) {
+                Ok(removed) => CommandResponse::Integer(removed as i64),
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            // Flattened to field, value, field, value, ... like Redis
+            Command::HGetAll { key } => match self.cache.hgetall(&key) {
+                Ok(fields) => CommandResponse::Array(
+                    fields
+                        .into_iter()
+                        .flat_map(|(field, value)| [field, value.to_string()])
+                        .collect(),
+                ),
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            Command::HIncrBy { key, field, delta } => {
+                match self.cache.hincr_by(&key, &field, delta) {
+                    Ok(value) => CommandResponse::Integer(value),
+                    Err(e) => CommandResponse::Error(e.into()),
+                }
+            }
+
+            Command::SAdd { key, members } => match self.cache.sadd(&key, members) {
+                Ok(added) => CommandResponse::Integer(added as i64),
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            Command::SRem { key, members } => match self.cache.srem(&key, &members) {
+                Ok(removed) => CommandResponse::Integer(removed as i64),
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            Command::SMembers { key } => match self.cache.smembers(&key) {
+                Ok(members) => CommandResponse::Array(members),
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            Command::SIsMember { key, member } => match self.cache.sismember(&key, &member) {
+                Ok(found) => CommandResponse::Integer(found as i64),
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            Command::SInter { keys } => match self.cache.sinter(&keys) {
+                Ok(members) => CommandResponse::Array(members),
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            Command::SUnion { keys } => match self.cache.sunion(&keys) {
+                Ok(members) => CommandResponse::Array(members),
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            Command::PfAdd { key, members } => match self.cache.pfadd(&key, &members) {
+                Ok(changed) => CommandResponse::Integer(changed as i64),
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            Command::PfCount { keys } => match self.cache.pfcount(&keys) {
+                Ok(count) => CommandResponse::Integer(count as i64),
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            Command::PfMerge { dest, sources } => match self.cache.pfmerge(&dest, &sources) {
+                Ok(()) => CommandResponse::Ok,
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            Command::JsonSet {
+                key,
+                path,
+                value,
+                nx,
+                xx,
+            } => {
+                let result = serde_json::from_str(&value)
+                    .map_err(|e| CacheError::InvalidJson(e.to_string()))
+                    .and_then(|value| self.cache.json_set(&key, &path, value, nx, xx));
+                match result {
+                    Ok(true) => CommandResponse::Ok,
+                    Ok(false) => CommandResponse::Null,
+                    Err(e) => CommandResponse::Error(e.into()),
+                }
+            }
+
+            Command::JsonGet { key, path } => match self.cache.json_get(&key, &path) {
+                Ok(Some(value)) => CommandResponse::Value(value.to_string()),
+                Ok(None) => CommandResponse::Null,
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            Command::JsonDel { key, path } => match self.cache.json_del(&key, &path) {
+                Ok(removed) => CommandResponse::Integer(removed as i64),
+                Err(e) => CommandResponse::Error(e.into()),
+            },
+
+            Command::HExpire {
+                key,
+                seconds,
+                fields,
+            } => {
+                let result = self
+                    .cache
+                    .hexpire(&key, Duration::from_secs(seconds), &fields);
+                integers_or_error(result)
+            }
+
+            Command::HTtl { key, fields } => integers_or_error(self.cache.httl(&key, &fields)),
+
+            Command::HPersist { key, fields } => {
+                integers_or_error(self.cache.hpersist(&key, &fields))
+            }
+
+            Command::Type { key } => match self.cache.describe(&key) {
+                Some((value_type, _)) => CommandResponse::Value(value_type.to_string()),
+                None => CommandResponse::Value("none".to_string()),
+            },
+
+            Command::Object {
+                subcommand: ObjectSubcommand::Encoding,
+                key,
+            } => match self.cache.describe(&key) {
+                Some((_, encoding)) => CommandResponse::Value(encoding.to_string()),
+                None => CommandResponse::Null,
+            },
+
+            Command::Object {
+                subcommand: ObjectSubcommand::Freq,
+                key,
+            } => match self.cache.frequency(&key) {
+                Some(freq) => CommandResponse::Integer(freq as i64),
+                None => CommandResponse::Null,
+            },
+
+            // Like Redis, nil when no stream has new entries
+            Command::XRead { count, streams } => {
+                let count = count
+                    .and_then(|c| usize::try_from(c).ok())
+                    .unwrap_or(usize::MAX);
+                let mut replies = Vec::new();
+                for (key, after) in streams {
+                    match self.cache.stream_read(&key, after, count) {
+                        Ok(entries) if entries.is_empty() => {}
+                        Ok(entries) => replies.push((key, entries)),
+                        Err(e) => return CommandResponse::Error(e.into()),
+                    }
+                }
+                if replies.is_empty() {
+                    CommandResponse::Null
+                } else {
+                    CommandResponse::Streams(replies)
+                }
+            }
+        }
+    }
+
+    /// When a budgeted command started now must stop, per `Config::command_time_budget`
+    fn deadline(&self) -> Deadline {
+        Deadline::after(self.cache.config().command_time_budget)
+    }
+
+    /// Value for a SET, honouring `Config::encode_numbers`
+    fn encode_value(&self, value: String) -> Value {
+        if self.cache.config().encode_numbers {
+            Value::encode_number(value)
+        } else {
+            Value::String(value)
         }
     }
+
+    /// Attaches value, TTL and size to each child until the value byte budget runs out
+    fn hydrate(&self, children: Vec<(String, u64)>, options: &HydrateOptions) -> HydratedChildren {
+        let mut budget = options.max_bytes;
+        let mut truncated = false;
+        let children = self.hydrate_with_budget(children, &mut budget, &mut truncated);
+
+        HydratedChildren {
+            children,
+            truncated,
+        }
+    }
+
+    /// Hydrates one batch against a budget shared across batches, for streamed replies
+    pub fn hydrate_with_budget(
+        &self,
+        children: Vec<(String, u64)>,
+        budget: &mut usize,
+        truncated: &mut bool,
+    ) -> Vec<ChildInfo> {
+        children
+            .into_iter()
+            .filter_map(|(key, depth)| {
+                let (value, size) = self.cache.peek(&key)?;
+                let value = value.to_string();
+                let value = if value.len() <= *budget {
+                    *budget -= value.len();
+                    Some(value)
+                } else {
+                    *truncated = true;
+                    None
+                };
+
+                Some(ChildInfo {
+                    ttl: self.cache.ttl(&key),
+                    key,
+                    depth,
+                    value,
+                    size,
+                })
+            })
+            .collect()
+    }
+}
+
+fn integers_or_error(result: Result<Vec<i64>, CacheError>) -> CommandResponse {
+    match result {
+        Ok(items) => CommandResponse::Integers(items),
+        Err(e) => CommandResponse::Error(e.into()),
+    }
+}
+
+/// A SET with its TTL and jitter stripped, to be logged with the deadline it was given
+fn stripped_set(cmd: &Command) -> Option<(String, Command)> {
+    let Command::Set {
+        key,
+        value,
+        options,
+        concern,
+    } = cmd
+    else {
+        return None;
+    };
+    let stripped = Command::Set {
+        key: key.clone(),
+        value: value.clone(),
+        options: SetOptions {
+            ttl: None,
+            jitter: None,
+            ..options.clone()
+        },
+        concern: *concern,
+    };
+    Some((key.clone(), stripped))
+}
+
+/// Wraps a reply that stopped early with the cursor to resume from
+fn partial(reply: CommandResponse, cursor: Option<usize>) -> CommandResponse {
+    match cursor {
+        Some(cursor) => CommandResponse::Partial {
+            cursor: cursor as u64,
+            reply: Box::new(reply),
+        },
+        None => reply,
+    }
 }
@@ -0,0 +1,212 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Secondary index of keys bucketed by the second they are due to expire.
+///
+/// The index is lazy: deletes, persists and sliding TTL resets don't remove
+/// stale entries, so callers must re-check the live entry for anything returned.
+#[derive(Debug)]
+pub struct ExpiryIndex {
+    epoch: Instant,
+    buckets: Mutex<BTreeMap<u64, HashSet<String>>>,
+}
+
+impl Default for ExpiryIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExpiryIndex {
+    pub fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            buckets: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn insert(&self, key: &str, expires_at: Instant) {
+        let bucket = self.bucket(expires_at);
+        self.buckets
+            .lock()
+            .unwrap()
+            .entry(bucket)
+            .or_default()
+            .insert(key.to_string());
+    }
+
+    /// Removes and returns every key in buckets up to and including the current second
+    pub fn take_due(&self, now: Instant) -> Vec<String> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let pending = buckets.split_off(&(self.bucket(now) + 1));
+        let due = std::mem::replace(&mut *buckets, pending);
+        drop(buckets);
+
+        let keys: HashSet<String> = due.into_values().flatten().collect();
+        keys.into_iter().collect()
+    }
+
+    /// Candidate keys due to expire within the window, without removing them
+    pub fn due_within(&self, now: Instant, within: Duration) -> Vec<String> {
+        let last = self.bucket(now + within);
+        let buckets = self.buckets.lock().unwrap();
+        let keys: HashSet<&String> = buckets.range(..=last).flat_map(|(_, keys)| keys).collect();
+        keys.into_iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.buckets.lock().unwrap().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets
+            .lock()
+            .unwrap()
+            .values()
+            .map(HashSet::len)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.lock().unwrap().is_empty()
+    }
+
+    fn bucket(&self, at: Instant) -> u64 {
+        at.saturating_duration_since(self.epoch).as_secs()
+    }
+}
+
+/// Densities are kept in parts per million
+const DENSITY_SCALE: u64 = 1_000_000;
+/// Minimum weight of a shard, so shards that looked clean are still revisited
+const DENSITY_FLOOR: u64 = DENSITY_SCALE / 50;
+
+/// Chooses the shard each sampling cleanup pass visits, with probability proportional
+/// to how many expired keys recent passes found there (plus a floor). Dense shards get
+/// most of the samples without starving the rest.
+#[derive(Debug)]
+pub struct SampleScheduler {
+    shards: RwLock<ShardSamples>, // Replaced, with the history kept, when shards grow
+    passes: AtomicU64,
+    samples: AtomicU64,
+    expired: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct ShardSamples {
+    density: Vec<AtomicU32>, // Moving average of the expired share of samples
+    last_pass: Vec<AtomicU64>,
+    retired: usize, // Leading shards that are never picked, as they stay empty
+}
+
+impl SampleScheduler {
+    pub fn new(shards: usize) -> Self {
+        let scheduler = Self {
+            shards: RwLock::default(),
+            passes: AtomicU64::new(0),
+            samples: AtomicU64::new(0),
+            expired: AtomicU64::new(0),
+        };
+        scheduler.resize(shards, 0);
+        scheduler
+    }
+
+    /// Tracks `shards` shards, of which the first `retired` are no longer sampled
+    pub fn resize(&self, shards: usize, retired: usize) {
+        let mut samples = self.shards.write().unwrap();
+        samples.density.resize_with(shards, || AtomicU32::new(0));
+        samples.last_pass.resize_with(shards, || AtomicU64::new(0));
+        samples.retired = retired.min(shards);
+    }
+
+    /// Weighted random pick of the next shard to sample
+    pub fn next_shard(&self) -> usize {
+        let samples = self.shards.read().unwrap();
+        let live = &samples.density[samples.retired..];
+        let weight = |d: &AtomicU32| DENSITY_FLOOR + d.load(Ordering::Relaxed) as u64;
+        let total: u64 = live.iter().map(weight).sum();
+        let mut target = rand::random_range(0..total.max(1));
+        for (shard, density) in live.iter().enumerate() {
+            match target.checked_sub(weight(density)) {
+                Some(rest) => target = rest,
+                None => return samples.retired + shard,
+            }
+        }
+        samples.density.len().saturating_sub(1)
+    }
+
+    /// Folds the outcome of a pass over `shard` into its density
+    pub fn record(&self, shard: usize, sampled: usize, expired: usize) {
+        let samples = self.shards.read().unwrap();
+        let pass = self.passes.fetch_add(1, Ordering::Relaxed) + 1;
+        samples.last_pass[shard].store(pass, Ordering::Relaxed);
+        self.samples.fetch_add(sampled as u64, Ordering::Relaxed);
+        self.expired.fetch_add(expired as u64, Ordering::Relaxed);
+
+        let observed = if sampled == 0 {
+            0
+        } else {
+            expired as u64 * DENSITY_SCALE / sampled as u64
+        };
+        // Races between passes on one shard only lose an update to the average
+        let previous = samples.density[shard].load(Ordering::Relaxed) as u64;
+        let updated = (previous * 3 + observed) / 4;
+        samples.density[shard].store(updated as u32, Ordering::Relaxed);
+    }
+
+    /// Share of shards, retired ones aside, sampled within the last `window` passes
+    pub fn coverage(&self, window: u64) -> f64 {
+        let samples = self.shards.read().unwrap();
+        let live = &samples.last_pass[samples.retired..];
+        if live.is_empty() {
+            return 0.0;
+        }
+        let passes = self.passes.load(Ordering::Relaxed);
+        let covered = live
+            .iter()
+            .map(|pass| pass.load(Ordering::Relaxed))
+            .filter(|&pass| pass > 0 && passes - pass < window)
+            .count();
+        covered as f64 / live.len() as f64
+    }
+
+    pub fn passes(&self) -> u64 {
+        self.passes.load(Ordering::Relaxed)
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples.load(Ordering::Relaxed)
+    }
+
+    pub fn expired(&self) -> u64 {
+        self.expired.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampling_favours_dense_shards_without_starving() {
+        let scheduler = SampleScheduler::new(8);
+        for _ in 0..20 {
+            scheduler.record(3, 20, 20);
+        }
+
+        let mut picks = [0usize; 8];
+        for _ in 0..10_000 {
+            picks[scheduler.next_shard()] += 1;
+        }
+        assert!(picks[3] > 8_000, "{:?}", picks);
+        assert!(picks.iter().all(|&n| n > 0), "{:?}", picks);
+
+        for shard in 0..8 {
+            scheduler.record(shard, 20, 0);
+        }
+        assert_eq!(scheduler.coverage(8), 1.0);
+        assert_eq!(scheduler.coverage(1), 1.0 / 8.0);
+    }
+}
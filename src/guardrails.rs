@@ -0,0 +1,366 @@
+use crate::cache::Cache;
+use crate::cache_errors::{CommandError, ErrorClass};
+use crate::command_table;
+use crate::diagnostics;
+use crate::executor::{Command, CommandResponse};
+use crate::metrics::Labels;
+use crate::middleware::Middleware;
+use std::fmt::Write;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::{error, warn};
+
+/// Panics anywhere in the process, counted by the hook from `install_panic_hook`
+static PANICS: AtomicU64 = AtomicU64::new(0);
+static RESTARTS: AtomicU64 = AtomicU64::new(0);
+
+const RESTART_MIN: Duration = Duration::from_secs(1);
+const RESTART_MAX: Duration = Duration::from_secs(60);
+
+/// What to do while the process is over `max_rss`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RssAction {
+    /// Evict the excess under the eviction policy, refusing writes if it can't
+    #[default]
+    Evict,
+    RefuseWrites,
+}
+
+impl FromStr for RssAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "evict" => Ok(RssAction::Evict),
+            "refuse-writes" => Ok(RssAction::RefuseWrites),
+            _ => Err(format!("unknown RSS action '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GuardrailConfig {
+    pub max_rss: Option<usize>, // Bytes of resident memory, whatever the logical estimate says
+    pub rss_action: RssAction,
+    pub fd_warn_ratio: f64, // Share of the open files limit in use that's logged
+    pub check_interval: Duration,
+    pub restart_tasks: bool, // Restart background tasks that panic
+}
+
+impl Default for GuardrailConfig {
+    fn default() -> Self {
+        Self {
+            max_rss: None,
+            rss_action: RssAction::default(),
+            fd_warn_ratio: 0.8,
+            check_interval: Duration::from_secs(1),
+            restart_tasks: false,
+        }
+    }
+}
+
+impl GuardrailConfig {
+    /// Reads DASHDOT_MAX_RSS, DASHDOT_RSS_ACTION and DASHDOT_RESTART_TASKS
+    pub fn from_env() -> Self {
+        Self {
+            max_rss: std::env::var("DASHDOT_MAX_RSS")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            rss_action: std::env::var("DASHDOT_RSS_ACTION")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default(),
+            restart_tasks: std::env::var("DASHDOT_RESTART_TASKS")
+                .is_ok_and(|v| v == "1" || v == "true"),
+            ..Self::default()
+        }
+    }
+}
+
+/// Self-limits on the process rather than the keyspace: resident memory, which
+/// fragmentation and buffers push past the logical estimate, and open descriptors.
+/// Registered as middleware, it refuses writes while over `max_rss`.
+pub struct Guardrails {
+    cache: Arc<Cache>,
+    config: GuardrailConfig,
+    rss: AtomicU64,
+    open_files: AtomicU64,
+    refusing: AtomicBool,
+    rss_evictions: AtomicU64,
+    fd_warned: AtomicBool,
+}
+
+impl Guardrails {
+    pub fn new(cache: Arc<Cache>, config: GuardrailConfig) -> Self {
+        Self {
+            cache,
+            config,
+            rss: AtomicU64::new(0),
+            open_files: AtomicU64::new(0),
+            refusing: AtomicBool::new(false),
+            rss_evictions: AtomicU64::new(0),
+            fd_warned: AtomicBool::new(false),
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(self.config.check_interval);
+        loop {
+            interval.tick().await;
+            self.check(
+                resident_memory(),
+                open_files(),
+                diagnostics::open_files_limit(),
+            );
+        }
+    }
+
+    /// Applies the limits to one reading of the process
+    pub fn check(&self, rss: Option<u64>, open_files: Option<u64>, fd_limit: Option<u64>) {
+        if let Some(rss) = rss {
+            self.rss.store(rss, Ordering::Relaxed);
+            self.check_rss(rss);
+        }
+        if let Some(open) = open_files {
+            self.open_files.store(open, Ordering::Relaxed);
+            let near_limit = fd_limit
+                .is_some_and(|limit| open as f64 >= limit as f64 * self.config.fd_warn_ratio);
+            // Logged once per crossing rather than every check
+            if near_limit && !self.fd_warned.swap(true, Ordering::Relaxed) {
+                warn!(
+                    "{} files open, near the limit of {}",
+                    open,
+                    fd_limit.unwrap_or_default()
+                );
+            } else if !near_limit {
+                self.fd_warned.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn check_rss(&self, rss: u64) {
+        let Some(max_rss) = self.config.max_rss else {
+            return;
+        };
+        let excess = (rss as usize).saturating_sub(max_rss);
+        let refuse = excess > 0
+            && match self.config.rss_action {
+                RssAction::RefuseWrites => true,
+                // Freed pages may stay resident for a while, so each check evicts at
+                // most the current excess
+                RssAction::Evict => {
+                    self.rss_evictions.fetch_add(1, Ordering::Relaxed);
+                    !self.cache.shed_memory(excess)
+                }
+            };
+        if refuse != self.refusing.swap(refuse, Ordering::Relaxed) {
+            match refuse {
+                true => warn!("RSS {} is over {}; refusing writes", rss, max_rss),
+                false => warn!("RSS {} is back under {}; accepting writes", rss, max_rss),
+            }
+        }
+    }
+
+    pub fn is_refusing_writes(&self) -> bool {
+        self.refusing.load(Ordering::Relaxed)
+    }
+}
+
+impl Middleware for Guardrails {
+    fn before(&self, command: Command) -> Result<Command, CommandResponse> {
+        let write = command_table::lookup(command.name()).is_some_and(|spec| spec.write);
+        if write && self.is_refusing_writes() {
+            return Err(CommandResponse::Error(CommandError::new(
+                ErrorClass::Oom,
+                "command not allowed when the process is over max_rss",
+            )));
+        }
+        Ok(command)
+    }
+
+    fn render_metrics(&self, out: &mut String, labels: &Labels) {
+        let series = labels.series();
+        let gauges = [
+            (
+                "cache_process_resident_memory_bytes",
+                "Resident memory of the process",
+                self.rss.load(Ordering::Relaxed),
+            ),
+            (
+                "cache_process_open_fds",
+                "Open file descriptors of the process",
+                self.open_files.load(Ordering::Relaxed),
+            ),
+            (
+                "cache_rss_writes_refused",
+                "Whether writes are refused for being over max_rss",
+                self.is_refusing_writes() as u64,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} gauge", name).unwrap();
+            writeln!(out, "{}{} {}", name, series, value).unwrap();
+        }
+        let counters = [
+            (
+                "cache_rss_evictions_total",
+                "Checks that evicted keys for being over max_rss",
+                self.rss_evictions.load(Ordering::Relaxed),
+            ),
+            (
+                "cache_task_panics_total",
+                "Panics caught in any task",
+                PANICS.load(Ordering::Relaxed),
+            ),
+            (
+                "cache_task_restarts_total",
+                "Background tasks restarted after panicking",
+                RESTARTS.load(Ordering::Relaxed),
+            ),
+        ];
+        for (name, help, value) in counters {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} counter", name).unwrap();
+            writeln!(out, "{}{} {}", name, series, value).unwrap();
+        }
+    }
+}
+
+/// Logs panics through tracing with the thread and location, and counts them, so
+/// one dying in a connection task isn't lost to stderr
+pub fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        PANICS.fetch_add(1, Ordering::Relaxed);
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("(non-string payload)");
+        let location = info
+            .location()
+            .map(|l| format!("{}:{}", l.file(), l.line()))
+            .unwrap_or_default();
+        let thread = std::thread::current();
+        error!(
+            "Panic in thread {} at {}: {}",
+            thread.name().unwrap_or("unnamed"),
+            location,
+            message
+        );
+    }));
+}
+
+/// Runs a background task, logging if it panics and with `restart` starting it
+/// again after a backoff. Returns when the task finishes without panicking.
+pub async fn supervise<F, Fut>(name: &'static str, restart: bool, task: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let mut backoff = RESTART_MIN;
+    loop {
+        match tokio::spawn(task()).await {
+            Ok(()) => return,
+            Err(e) if e.is_panic() && restart => {
+                error!("Task {} panicked; restarting in {:?}", name, backoff);
+                RESTARTS.fetch_add(1, Ordering::Relaxed);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(RESTART_MAX);
+            }
+            Err(e) => {
+                error!("Task {} stopped: {}", name, e);
+                return;
+            }
+        }
+    }
+}
+
+/// Resident set size, from /proc/self/status
+pub fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// Descriptors open in this process, from /proc/self/fd
+pub fn open_files() -> Option<u64> {
+    Some(std::fs::read_dir("/proc/self/fd").ok()?.count() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Config, SetOptions, Value};
+    use crate::eviction::EvictionPolicy;
+    use crate::executor::CommandExecutor;
+
+    #[test]
+    fn test_rss_limit() {
+        let cache = Arc::new(Cache::new(Config {
+            eviction_policy: EvictionPolicy::AllKeysLfu,
+            ..Config::default()
+        }));
+        for i in 0..100 {
+            cache
+                .set(
+                    format!("k{}", i),
+                    Value::String("x".repeat(100)),
+                    SetOptions::default(),
+                )
+                .unwrap();
+        }
+        let guardrails = Arc::new(Guardrails::new(
+            cache.clone(),
+            GuardrailConfig {
+                max_rss: Some(1 << 20),
+                ..GuardrailConfig::default()
+            },
+        ));
+        let executor = CommandExecutor::new(cache.clone()).with_middleware(guardrails.clone());
+        let set = || {
+            executor.execute(Command::Set {
+                key: "new".to_string(),
+                value: "v".into(),
+                options: SetOptions::default(),
+                concern: None,
+            })
+        };
+
+        // Evicting the excess keeps writes going
+        guardrails.check(Some((1 << 20) + 2_000), None, None);
+        assert!(cache.len() < 100);
+        assert!(!guardrails.is_refusing_writes());
+        assert!(matches!(set(), CommandResponse::Ok));
+
+        // Evicting everything isn't enough, so writes are refused until the process
+        // shrinks
+        guardrails.check(Some(1 << 30), None, None);
+        assert_eq!(cache.len(), 0);
+        assert!(guardrails.is_refusing_writes());
+        assert!(matches!(set(), CommandResponse::Error(_)));
+        guardrails.check(Some(1 << 19), None, None);
+        assert!(matches!(set(), CommandResponse::Ok));
+    }
+
+    #[tokio::test]
+    async fn test_supervise_restarts_panicking_tasks() {
+        let runs = Arc::new(AtomicU64::new(0));
+        let counter = runs.clone();
+        supervise("flaky", true, move || {
+            let runs = counter.clone();
+            async move {
+                if runs.fetch_add(1, Ordering::Relaxed) < 2 {
+                    panic!("flaky task");
+                }
+            }
+        })
+        .await;
+        assert_eq!(runs.load(Ordering::Relaxed), 3);
+    }
+}
@@ -1,22 +1,57 @@
-use crate::cache::SetOptions;
-use crate::executor::{Command, CommandExecutor, CommandResponse, KeyInfo};
-use axum::http::StatusCode;
+use crate::cache::{DependencyEdge, GraphImport, InvalidationMode, SetOptions};
+use crate::cache_errors::{CommandError, ErrorClass};
+use crate::command_table;
+use crate::contention::ContentionReport;
+use crate::diagnostics::{self, Finding};
+use crate::events::CacheEvent;
+use crate::eviction::Priority;
+use crate::executor::{
+    Command, CommandExecutor, CommandResponse, DEFAULT_HYDRATE_MAX_BYTES, HydrateOptions, KeyInfo,
+};
+use crate::keyspace::{ColdKeyFilter, ColdKeys, KeySample, KeyspaceReport};
+use crate::locks::KeyLocks;
+use crate::mirror::MirrorStatus;
+use crate::plugins::CommandCheck;
+use crate::primary::PrimaryStatus;
+use crate::rdb::{self, RdbImport};
+use crate::refresh::{RefreshAhead, RefreshPolicy, RefreshPolicyInfo, RefreshSource};
+use crate::replica::ReplicaStatus;
+use crate::schema::Schemas;
+use crate::snapshot::JobStatus;
+use crate::trace;
+use axum::body::{Body, Bytes};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode, header};
+use axum::middleware::{Next, from_fn_with_state};
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::response::{IntoResponse, Response};
 use axum::{
     Json, Router,
-    extract::{Path, Query, State},
-    routing::{get, post},
+    extract::{DefaultBodyLimit, FromRef, Path, Query, Request, State},
+    routing::{delete, get, post},
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
 use std::io::Error;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{BroadcastStream, ReceiverStream};
+use tokio_stream::{Stream, StreamExt};
+use tracing::Instrument;
 
 #[derive(Debug)]
 pub enum ApiError {
     NotFound(String),
     BadRequest(String),
+    Locked(String),
+    Unavailable(String),
     InternalError(String),
+    Command(CommandError),
 }
 
 impl IntoResponse for ApiError {
@@ -24,17 +59,220 @@ impl IntoResponse for ApiError {
         let (status, message) = match self {
             ApiError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
             ApiError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
+            ApiError::Locked(msg) => (StatusCode::LOCKED, msg),
+            ApiError::Unavailable(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
             ApiError::InternalError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
+            ApiError::Command(e) => {
+                let status = match e.class {
+                    ErrorClass::Oom => StatusCode::INSUFFICIENT_STORAGE,
+                    ErrorClass::NoPerm | ErrorClass::ReadOnly => StatusCode::FORBIDDEN,
+                    ErrorClass::WrongType | ErrorClass::BusyGroup | ErrorClass::NotBusy => {
+                        StatusCode::CONFLICT
+                    }
+                    ErrorClass::Busy => StatusCode::SERVICE_UNAVAILABLE,
+                    ErrorClass::Invalid => StatusCode::UNPROCESSABLE_ENTITY,
+                    ErrorClass::Locked => StatusCode::LOCKED,
+                    ErrorClass::Err => StatusCode::BAD_REQUEST,
+                };
+                (status, e.message)
+            }
         };
         (status, message).into_response()
     }
 }
 
+impl From<CommandError> for ApiError {
+    fn from(e: CommandError) -> Self {
+        ApiError::Command(e)
+    }
+}
+
 type ApiResult<T> = Result<T, ApiError>;
 
+/// Header carrying the token from `POST /keys/{key}/lock` on writes to a locked key
+const LOCK_TOKEN_HEADER: &str = "x-lock-token";
+const DEFAULT_LOCK_TTL_SECS: u64 = 30;
+const DEFAULT_KEYSPACE_SAMPLES: usize = 10_000;
+const DEFAULT_KEYSPACE_BUDGET_MS: u64 = 50;
+const DEFAULT_KEY_SAMPLE_SIZE: usize = 1000;
+const MAX_KEY_SAMPLE_SIZE: usize = 100_000;
+/// A uniform sample has to scan every key, so it gets a larger budget than the report
+const DEFAULT_KEY_SAMPLE_BUDGET_MS: u64 = 500;
+const DEFAULT_COLD_IDLE_SECS: u64 = 3600;
+const DEFAULT_COLD_KEYS_LIMIT: usize = 100;
+const MAX_COLD_KEYS_LIMIT: usize = 10_000;
+
+#[derive(Clone)]
+struct AppState {
+    executor: Arc<CommandExecutor>,
+    locks: Arc<KeyLocks>,
+}
+
+impl FromRef<AppState> for Arc<CommandExecutor> {
+    fn from_ref(state: &AppState) -> Self {
+        state.executor.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<KeyLocks> {
+    fn from_ref(state: &AppState) -> Self {
+        state.locks.clone()
+    }
+}
+
+fn check_lock(locks: &KeyLocks, headers: &HeaderMap, key: &str) -> ApiResult<()> {
+    let token = headers
+        .get(LOCK_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok());
+    if locks.permits(key, token) {
+        Ok(())
+    } else {
+        Err(ApiError::Locked(format!("Key '{}' is locked", key)))
+    }
+}
+
+/// Checks the lock on every key a delete would remove: when it cascades, that's
+/// each key's dependents too
+fn check_delete_locks(
+    executor: &CommandExecutor,
+    locks: &KeyLocks,
+    headers: &HeaderMap,
+    keys: Vec<&str>,
+    cascade: bool,
+) -> ApiResult<()> {
+    let cascade = cascade || executor.cache.config().cascade_deletes;
+    for key in keys {
+        match cascade {
+            true => executor
+                .cache
+                .invalidation_keys(key)
+                .iter()
+                .try_for_each(|key| check_lock(locks, headers, key))?,
+            false => check_lock(locks, headers, key)?,
+        }
+    }
+    Ok(())
+}
+
+/// Marks a value served after expiry (within `Config::stale_grace`) or stale invalidation
+const STALE_HEADER: &str = "x-stale";
+
+/// Per-write durability on key writes: cache, logged or fsynced
+const WRITE_CONCERN_HEADER: &str = "x-write-concern";
+
+/// Header returned on writes and accepted on any request for read-your-writes
+const CONSISTENCY_TOKEN_HEADER: &str = "x-consistency-token";
+/// Key metadata sent with GET /keys/{key} unless `?metadata=false`
+const TTL_REMAINING_HEADER: &str = "x-ttl-remaining"; // Seconds, -1 without a TTL
+const ACCESS_COUNT_HEADER: &str = "x-access-count"; // Decayed count, as OBJECT FREQ
+const PARENT_HEADER: &str = "x-parent"; // Omitted for root keys
+const VALUE_TYPE_HEADER: &str = "x-value-type";
+/// Set on a list cut short by the command time budget: the `cursor` (for keys) or
+/// `offset` (for children) that carries on
+const CONTINUATION_HEADER: &str = "x-continuation-cursor";
+
+/// Version of the value returned by the wait endpoint, a hash of its content that's
+/// the same across restarts and nodes
+const KEY_VERSION_HEADER: &str = "x-key-version";
+const DEFAULT_KEY_WAIT: Duration = Duration::from_secs(30);
+const MAX_KEY_WAIT: Duration = Duration::from_secs(300);
+/// How long a request waits for this node to catch up to its consistency token
+const CONSISTENCY_WAIT: Duration = Duration::from_secs(1);
+
+/// Opt-in read-your-writes: successful writes return the executor's write offset as a
+/// token, and a request carrying one waits until this node has applied that many
+/// writes. A single node has always caught up; replicas may briefly lag.
+async fn read_your_writes(
+    State(executor): State<Arc<CommandExecutor>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(token) = request.headers().get(CONSISTENCY_TOKEN_HEADER) {
+        let Some(offset) = token.to_str().ok().and_then(|t| t.parse::<u64>().ok()) else {
+            return ApiError::BadRequest("Invalid consistency token".to_string()).into_response();
+        };
+        if !executor.wait_for_offset(offset, CONSISTENCY_WAIT).await {
+            return ApiError::Unavailable(
+                "Timed out waiting to reach the consistency token".to_string(),
+            )
+            .into_response();
+        }
+    }
+
+    let is_read = matches!(*request.method(), Method::GET | Method::HEAD);
+    let mut response = next.run(request).await;
+    if !is_read && response.status().is_success() {
+        response.headers_mut().insert(
+            CONSISTENCY_TOKEN_HEADER,
+            HeaderValue::from(executor.write_offset()),
+        );
+    }
+    response
+}
+
+/// W3C trace context; its trace ID is preferred over a request ID
+const TRACEPARENT_HEADER: &str = "traceparent";
+/// Caller-chosen request ID, echoed back with whichever ID the request was traced under
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// POST routes that only read, served by read-only listeners too
+const READ_ONLY_POSTS: &[&str] = &["/keys/exists", "/keys/children-count", "/ping"];
+
+/// Refuses requests that could write, for listeners exposed as read-only
+async fn refuse_writes(request: Request, next: Next) -> Response {
+    let method = request.method();
+    let reads = matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+        || (method == Method::POST && READ_ONLY_POSTS.contains(&request.uri().path()));
+    if !reads {
+        return ApiError::Command(CommandError::new(
+            ErrorClass::ReadOnly,
+            "This listener is read-only",
+        ))
+        .into_response();
+    }
+    next.run(request).await
+}
+
+/// Runs a request carrying a trace or request ID inside a span tagged with it, so the
+/// commands it executes can be found from the caller's trace
+async fn trace_requests(request: Request, next: Next) -> Response {
+    let headers = request.headers();
+    let trace_id = headers
+        .get(TRACEPARENT_HEADER)
+        .and_then(|value| trace::from_traceparent(value.to_str().ok()?))
+        .or_else(|| trace::request_id(headers.get(REQUEST_ID_HEADER)?.to_str().ok()?));
+    let Some(trace_id) = trace_id else {
+        return next.run(request).await;
+    };
+
+    let mut response = next
+        .run(request)
+        .instrument(trace::request_span(&trace_id))
+        .await;
+    if let Ok(value) = HeaderValue::from_str(&trace_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// Chunks buffered between a streaming producer and the client
+const STREAM_BUFFERED_CHUNKS: usize = 4;
+
 #[derive(Deserialize)]
 pub struct ExpireRequest {
-    pub seconds: u64,
+    pub seconds: Option<u64>,
+    pub milliseconds: Option<u64>, // Instead of `seconds`
+}
+
+#[derive(Deserialize)]
+pub struct TtlQuery {
+    #[serde(default)]
+    pub ms: bool, // In milliseconds rather than seconds
+}
+
+#[derive(Deserialize)]
+pub struct ExpireAtRequest {
+    pub at: String, // RFC3339 timestamp
 }
 
 #[derive(Deserialize)]
@@ -42,21 +280,258 @@ pub struct MultiKeyRequest {
     pub keys: Vec<String>,
 }
 
+#[derive(Deserialize)]
+pub struct ChildrenCountRequest {
+    pub parents: Vec<String>,
+    pub depth: Option<usize>, // Defaults to the depth key info counts to
+}
+
+#[derive(Deserialize)]
+pub struct BatchRequest {
+    pub commands: Vec<Vec<String>>, // e.g. [["SET", "k", "v"], ["GET", "k"]]
+}
+
+#[derive(Deserialize)]
+pub struct InvalidateRequest {
+    pub keys: Vec<String>, // Roots; each is invalidated along with its dependents
+    #[serde(default)]
+    pub mode: InvalidationMode,
+}
+
+#[derive(Serialize)]
+pub struct InvalidateCount {
+    pub key: String,
+    pub affected: i64,
+}
+
+/// Parent-child topology without values, as exported by `GET /graph`
+#[derive(Serialize, Deserialize)]
+pub struct DependencyGraph {
+    pub edges: Vec<DependencyEdge>,
+}
+
+#[derive(Deserialize)]
+pub struct FieldsRequest {
+    pub fields: Vec<String>,
+    #[serde(default)]
+    pub seconds: Option<u64>, // Required when setting an expiry
+}
+
+#[derive(Deserialize)]
+pub struct IncrRequest {
+    #[serde(default = "default_increment")]
+    pub by: i64,
+    pub limit: Option<i64>,
+}
+
+fn default_increment() -> i64 {
+    1
+}
+
+#[derive(Deserialize)]
+pub struct DecrRequest {
+    #[serde(default = "default_increment")]
+    pub by: i64,
+}
+
+#[derive(Deserialize)]
+pub struct HashFieldRequest {
+    pub value: String,
+}
+
+#[derive(Deserialize)]
+pub struct HashIncrRequest {
+    #[serde(default = "default_increment")]
+    pub by: i64,
+}
+
+#[derive(Deserialize)]
+pub struct IncrFloatRequest {
+    pub by: f64,
+}
+
+#[derive(Serialize)]
+pub struct IncrResponse {
+    pub applied: bool,
+    pub value: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct SnapshotJobResponse {
+    pub job_id: u64,
+    pub path: String,
+}
+
+#[derive(Deserialize)]
+pub struct LockRequest {
+    pub ttl: Option<u64>, // seconds
+}
+
+#[derive(Serialize)]
+pub struct LockResponse {
+    pub token: String,
+    pub ttl: u64,
+}
+
 #[derive(Deserialize)]
 pub struct SetParentRequest {
     pub parent: String,
 }
 
+#[derive(Deserialize)]
+pub struct RenameRequest {
+    pub to: String,
+}
+
 #[derive(Deserialize)]
 pub struct GetChildrenRequest {
     #[serde(default)]
     pub depth: Option<u64>,
+    #[serde(default)]
+    pub offset: Option<u64>,
+    #[serde(default)]
+    pub limit: Option<u64>,
+    #[serde(default)]
+    pub hydrate: bool, // Include each child's value, TTL and size
+    #[serde(default)]
+    pub max_bytes: Option<usize>,
+    #[serde(default)]
+    pub stream: bool, // Newline-delimited JSON, written a page at a time
 }
 
 #[derive(Deserialize)]
 pub struct ListKeysQuery {
     pub pattern: Option<String>,
     pub limit: Option<u64>,
+    pub cursor: Option<u64>, // From the continuation header of a cut-short reply
+    #[serde(default)]
+    pub stream: bool, // Newline-delimited JSON, written a shard at a time
+}
+
+#[derive(Deserialize)]
+pub struct FieldsQuery {
+    pub fields: String, // Comma-separated
+}
+
+#[derive(Deserialize)]
+pub struct InfoQuery {
+    pub section: Option<String>, // Comma-separated, all sections when absent
+}
+
+#[derive(Deserialize)]
+pub struct ScanQuery {
+    #[serde(default)]
+    pub cursor: u64, // 0, or the cursor of the previous page
+    #[serde(rename = "match")]
+    pub pattern: Option<String>,
+    pub count: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct ScanPage {
+    pub cursor: u64, // 0 once every key has been visited
+    pub keys: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct GetKeyQuery {
+    pub metadata: Option<bool>, // False leaves out the key metadata headers
+}
+
+#[derive(Deserialize)]
+pub struct JsonPathQuery {
+    #[serde(default = "root_path")]
+    pub path: String, // JSONPath within the document, e.g. `$.user.name`
+    #[serde(default)]
+    pub nx: bool,
+    #[serde(default)]
+    pub xx: bool,
+}
+
+fn root_path() -> String {
+    "$".to_string()
+}
+
+#[derive(Deserialize)]
+pub struct RefreshPolicyRequest {
+    pub pattern: String, // Exact key or prefix ending in `*`
+    pub url: String,     // http:// origin; `{key}` is replaced by the key
+    pub margin_secs: u64,
+    pub ttl_secs: Option<u64>, // Defaults to the key's last TTL
+}
+
+#[derive(Deserialize)]
+pub struct RefreshPolicyQuery {
+    pub pattern: String,
+}
+
+#[derive(Deserialize)]
+pub struct WaitQuery {
+    pub timeout: Option<u64>,    // Seconds, capped at MAX_KEY_WAIT
+    pub version: Option<String>, // Wait for a value other than this `x-key-version`
+}
+
+#[derive(Deserialize)]
+pub struct KeyspaceStatsQuery {
+    pub samples: Option<usize>,
+    pub budget_ms: Option<u64>,
+    #[serde(default)]
+    pub refresh: bool, // Scan now instead of serving the background aggregator's result
+}
+
+#[derive(Deserialize)]
+pub struct KeySampleQuery {
+    pub n: Option<usize>,
+    pub budget_ms: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct ColdKeysQuery {
+    pub idle_gt: Option<u64>, // Seconds
+    pub freq_lt: Option<u64>,
+    pub limit: Option<usize>,
+    pub budget_ms: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct ExpiringKeysQuery {
+    pub within: Option<u64>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct FlushRequest {
+    pub pattern: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DryRunQuery {
+    #[serde(default)]
+    pub dry_run: bool, // Report what would be affected instead of doing it
+}
+
+#[derive(Deserialize)]
+pub struct InvalidateTagQuery {
+    #[serde(default)]
+    pub mode: InvalidationMode,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub cascade: bool, // Also delete the keys' dependents
+}
+
+/// One line of a pattern flush's progress stream, sent as each shard finishes
+#[derive(Serialize)]
+pub struct FlushProgress {
+    pub shards_scanned: usize,
+    pub shards_total: usize,
+    pub deleted: usize,
 }
 
 #[derive(Deserialize)]
@@ -64,6 +539,15 @@ pub struct PingRequest {
     pub message: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub struct EvalRequest {
+    pub script: String,
+    #[serde(default)]
+    pub keys: Vec<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
 #[derive(Deserialize)]
 pub struct SetKeyRequest {
     pub value: String,
@@ -72,146 +556,1364 @@ pub struct SetKeyRequest {
     #[serde(default)]
     pub parent: Option<String>,
     #[serde(default)]
+    pub parents: Vec<String>, // Further parents, alongside `parent`
+    #[serde(default)]
+    pub parent_ttl: Option<u64>, // Seconds before the parent links lapse
+    #[serde(default)]
     pub nx: bool,
     #[serde(default)]
     pub xx: bool,
+    #[serde(default)]
+    pub pinned: bool, // Exempt from eviction
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default)]
+    pub jitter: Option<u32>, // Percent to spread the TTL either way
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+async fn get_metrics(State(executor): State<Arc<CommandExecutor>>) -> String {
+    executor.render_metrics()
+}
+
+async fn get_info(
+    Query(params): Query<InfoQuery>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<String> {
+    let sections = params
+        .section
+        .map(|list| list.split(',').map(|s| s.trim().to_string()).collect())
+        .unwrap_or_default();
+    match executor.execute(Command::Info { sections }) {
+        CommandResponse::Value(info) => Ok(info),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn get_diagnostics(State(executor): State<Arc<CommandExecutor>>) -> Json<Vec<Finding>> {
+    Json(diagnostics::run(executor.cache.config()))
+}
+
+#[derive(Serialize)]
+pub struct PurgeReport {
+    pub purged_bytes: usize,
+}
+
+/// Shrinks stored values to their length, like MEMORY PURGE
+async fn purge_memory(State(executor): State<Arc<CommandExecutor>>) -> Json<PurgeReport> {
+    Json(PurgeReport {
+        purged_bytes: executor.cache.purge_memory(),
+    })
+}
+
+/// Starts a background save; 202 with the job to poll, or 409 naming the running one
+async fn start_snapshot(State(executor): State<Arc<CommandExecutor>>) -> ApiResult<Response> {
+    let jobs = executor
+        .snapshots()
+        .ok_or_else(|| ApiError::Unavailable("Snapshots are not configured".to_string()))?;
+    let path = jobs.path().display().to_string();
+    Ok(match jobs.start(executor.cache.clone()) {
+        Ok(job_id) => (
+            StatusCode::ACCEPTED,
+            Json(SnapshotJobResponse { job_id, path }),
+        )
+            .into_response(),
+        Err(job_id) => (
+            StatusCode::CONFLICT,
+            Json(SnapshotJobResponse { job_id, path }),
+        )
+            .into_response(),
+    })
+}
+
+#[derive(Serialize)]
+#[serde(tag = "role", rename_all = "lowercase")]
+pub enum ReplicationRole {
+    Replica(ReplicaStatus),
+    Primary(PrimaryStatus),
+}
+
+/// The link to the primary set by REPLICAOF while one is up, otherwise the replicas
+/// fed by this node; 404 when replication isn't configured at all
+async fn get_replication(
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<Json<ReplicationRole>> {
+    let replica = executor
+        .replication()
+        .and_then(|replication| replication.status())
+        .map(ReplicationRole::Replica);
+    replica
+        .or_else(|| {
+            executor
+                .primary()
+                .map(|primary| ReplicationRole::Primary(primary.status()))
+        })
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound("Replication is not configured".to_string()))
+}
+
+/// The mirror started by MIRROR; 404 when not mirroring
+async fn get_mirror(State(executor): State<Arc<CommandExecutor>>) -> ApiResult<Json<MirrorStatus>> {
+    executor
+        .mirroring()
+        .and_then(|mirroring| mirroring.status())
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound("Not mirroring".to_string()))
+}
+
+/// Loads an uploaded Redis RDB dump; the body is the raw file
+async fn import_rdb(
+    State(executor): State<Arc<CommandExecutor>>,
+    body: Bytes,
+) -> ApiResult<Json<RdbImport>> {
+    let cache = executor.cache.clone();
+    tokio::task::spawn_blocking(move || rdb::import(&cache, &body))
+        .await
+        .map_err(|e| ApiError::InternalError(e.to_string()))?
+        .map(Json)
+        .map_err(|e| ApiError::BadRequest(format!("Invalid RDB file: {}", e)))
+}
+
+async fn get_snapshot_job(
+    Path(job_id): Path<u64>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<Json<JobStatus>> {
+    executor
+        .snapshots()
+        .and_then(|jobs| jobs.status(job_id))
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound(format!("Snapshot job {} not found", job_id)))
+}
+
+fn refresh_ahead(executor: &CommandExecutor) -> ApiResult<&Arc<RefreshAhead>> {
+    executor
+        .refresh_ahead()
+        .ok_or_else(|| ApiError::Unavailable("Refresh-ahead is not configured".to_string()))
+}
+
+async fn list_refresh_policies(
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<Json<Vec<RefreshPolicyInfo>>> {
+    Ok(Json(refresh_ahead(&executor)?.policies()))
+}
+
+async fn add_refresh_policy(
+    State(executor): State<Arc<CommandExecutor>>,
+    Json(req): Json<RefreshPolicyRequest>,
+) -> ApiResult<String> {
+    let policy = RefreshPolicy {
+        pattern: req.pattern,
+        source: RefreshSource::Url(req.url),
+        margin: Duration::from_secs(req.margin_secs),
+        ttl: req.ttl_secs.map(Duration::from_secs),
+    };
+    refresh_ahead(&executor)?
+        .register(policy)
+        .map_err(ApiError::BadRequest)?;
+    Ok("Refresh policy registered".to_string())
+}
+
+async fn remove_refresh_policy(
+    State(executor): State<Arc<CommandExecutor>>,
+    Query(params): Query<RefreshPolicyQuery>,
+) -> ApiResult<String> {
+    if !refresh_ahead(&executor)?.remove(&params.pattern) {
+        return Err(ApiError::NotFound("No policy for that pattern".to_string()));
+    }
+    Ok("Refresh policy removed".to_string())
+}
+
+fn schemas(executor: &CommandExecutor) -> ApiResult<&Arc<Schemas>> {
+    executor
+        .schemas()
+        .ok_or_else(|| ApiError::Unavailable("Schema validation is not configured".to_string()))
+}
+
+async fn list_schemas(
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<Json<Vec<String>>> {
+    Ok(Json(schemas(&executor)?.namespaces()))
+}
+
+async fn get_schema(
+    Path(namespace): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<Json<serde_json::Value>> {
+    schemas(&executor)?
+        .get(&namespace)
+        .map(Json)
+        .ok_or_else(|| ApiError::NotFound(format!("No schema for namespace '{}'", namespace)))
+}
+
+async fn put_schema(
+    Path(namespace): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    Json(schema): Json<serde_json::Value>,
+) -> ApiResult<String> {
+    schemas(&executor)?
+        .set(&namespace, schema)
+        .map_err(ApiError::BadRequest)?;
+    Ok("Schema attached".to_string())
+}
+
+async fn delete_schema(
+    Path(namespace): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<String> {
+    if !schemas(&executor)?.remove(&namespace) {
+        return Err(ApiError::NotFound(format!(
+            "No schema for namespace '{}'",
+            namespace
+        )));
+    }
+    Ok("Schema removed".to_string())
+}
+
+async fn get_contention(
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<Json<ContentionReport>> {
+    executor.cache.contention_report().map(Json).ok_or_else(|| {
+        ApiError::NotFound(
+            "Contention tracking is off; start with DASHDOT_TRACK_CONTENTION=1".to_string(),
+        )
+    })
+}
+
+async fn get_keyspace_stats(
+    Query(params): Query<KeyspaceStatsQuery>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> Json<KeyspaceReport> {
+    let cached = executor
+        .stats_aggregator()
+        .and_then(|aggregator| aggregator.latest());
+    if let Some(stats) = cached
+        && !params.refresh
+    {
+        return Json(stats.keyspace.clone());
+    }
+
+    let samples = params.samples.unwrap_or(DEFAULT_KEYSPACE_SAMPLES);
+    let budget = Duration::from_millis(params.budget_ms.unwrap_or(DEFAULT_KEYSPACE_BUDGET_MS));
+    Json(KeyspaceReport::collect(&executor.cache, samples, budget))
+}
+
+async fn get_key_sample(
+    Query(params): Query<KeySampleQuery>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> Json<KeySample> {
+    let n = params
+        .n
+        .unwrap_or(DEFAULT_KEY_SAMPLE_SIZE)
+        .min(MAX_KEY_SAMPLE_SIZE);
+    let budget = Duration::from_millis(params.budget_ms.unwrap_or(DEFAULT_KEY_SAMPLE_BUDGET_MS));
+    Json(KeySample::collect(&executor.cache, n, budget))
+}
+
+/// Warm and cold key counts, listing the idlest cold keys
+async fn get_cold_keys(
+    Query(params): Query<ColdKeysQuery>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> Json<ColdKeys> {
+    let filter = ColdKeyFilter {
+        idle_gt: Duration::from_secs(params.idle_gt.unwrap_or(DEFAULT_COLD_IDLE_SECS)),
+        freq_lt: params.freq_lt,
+        limit: params
+            .limit
+            .unwrap_or(DEFAULT_COLD_KEYS_LIMIT)
+            .min(MAX_COLD_KEYS_LIMIT),
+    };
+    let budget = Duration::from_millis(params.budget_ms.unwrap_or(DEFAULT_KEY_SAMPLE_BUDGET_MS));
+    Json(ColdKeys::collect(&executor.cache, filter, budget))
+}
+
+async fn get_dashboard(State(_executor): State<Arc<CommandExecutor>>) -> &'static str {
+    "TODO: React dashboard"
+}
+
+/// Server-sent stream of cache events (e.g. misses, for cache warmers)
+async fn stream_events(
+    State(executor): State<Arc<CommandExecutor>>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let events = BroadcastStream::new(executor.cache.subscribe())
+        .filter_map(|event| event.ok())
+        .map(|event| Event::default().json_data(event));
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+async fn get_key(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    Query(params): Query<GetKeyQuery>,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    if let Some(range) = headers.get(header::RANGE) {
+        let range = range
+            .to_str()
+            .ok()
+            .and_then(parse_byte_range)
+            .ok_or_else(|| ApiError::BadRequest("Invalid Range header".to_string()))?;
+        return get_key_range(&executor, &key, range);
+    }
+
+    let command = Command::Get { key: key.clone() };
+    let response = executor.execute(command);
+    // Values that aren't UTF-8 can't be JSON strings, so they're sent as is
+    let body = |bytes: Vec<u8>| match String::from_utf8(bytes) {
+        Ok(v) => Json(v).into_response(),
+        Err(e) => (
+            [(header::CONTENT_TYPE, "application/octet-stream")],
+            e.into_bytes(),
+        )
+            .into_response(),
+    };
+    let mut response = match response {
+        CommandResponse::Bytes(v) if executor.cache.is_stale(&key) => {
+            ([(STALE_HEADER, "true")], body(v)).into_response()
+        }
+        CommandResponse::Bytes(v) => body(v),
+        CommandResponse::Null => return Err(ApiError::NotFound("Key not found".to_string())),
+        CommandResponse::Error(e) => return Err(e.into()),
+        _ => return Err(ApiError::InternalError("Unexpected response".to_string())),
+    };
+    if params.metadata != Some(false) {
+        add_key_metadata(&executor, &key, response.headers_mut());
+    }
+    Ok(response)
+}
+
+/// Adds the key's TTL, access count, parent and type, read after the value was. A key
+/// removed in between gets only what's still known.
+fn add_key_metadata(executor: &CommandExecutor, key: &str, headers: &mut HeaderMap) {
+    let cache = &executor.cache;
+    headers.insert(TTL_REMAINING_HEADER, HeaderValue::from(cache.ttl(key)));
+    if let Some(count) = cache.frequency(key) {
+        headers.insert(ACCESS_COUNT_HEADER, HeaderValue::from(count));
+    }
+    // Keys that aren't valid header text are left out rather than mangled
+    if let Some(parent) = cache.parent(key)
+        && let Ok(parent) = HeaderValue::from_str(&parent)
+    {
+        headers.insert(PARENT_HEADER, parent);
+    }
+    if let Some((type_name, _)) = cache.describe(key) {
+        headers.insert(VALUE_TYPE_HEADER, HeaderValue::from_static(type_name));
+    }
+}
+
+/// Long-polls until the key exists, or with `version` until its value differs from
+/// that version. Answers 204 if the timeout passes first.
+async fn wait_for_key(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    Query(params): Query<WaitQuery>,
+) -> Response {
+    let timeout = params
+        .timeout
+        .map_or(DEFAULT_KEY_WAIT, Duration::from_secs)
+        .min(MAX_KEY_WAIT);
+    // Subscribed before the first look, so a write landing in between still wakes us
+    let mut events = executor.cache.subscribe();
+    let wait = async {
+        loop {
+            // Peeking keeps the checks out of hit/miss stats and access tracking
+            if let Some((value, _)) = executor.cache.peek(&key) {
+                let value = value.to_string();
+                let version = key_version(&value);
+                if params.version.as_deref() != Some(version.as_str()) {
+                    return ([(KEY_VERSION_HEADER, version)], Json(value)).into_response();
+                }
+            }
+            loop {
+                match events.recv().await {
+                    Ok(CacheEvent::Written { key: written } | CacheEvent::Set { key: written })
+                        if written == key =>
+                    {
+                        break;
+                    }
+                    Ok(_) => {}
+                    // Missed events may have included ours
+                    Err(RecvError::Lagged(_)) => break,
+                    Err(RecvError::Closed) => std::future::pending().await,
+                }
+            }
+        }
+    };
+    tokio::time::timeout(timeout, wait)
+        .await
+        .unwrap_or_else(|_| StatusCode::NO_CONTENT.into_response())
+}
+
+/// 64-bit FNV-1a of the value
+fn key_version(value: &str) -> String {
+    let hash = value.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+async fn set_key(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    Json(req): Json<SetKeyRequest>,
+) -> ApiResult<String> {
+    check_lock(&locks, &headers, &key)?;
+    let parents: Vec<String> = req.parent.into_iter().chain(req.parents).collect();
+    if req.parent_ttl.is_some() && parents.is_empty() {
+        return Err(ApiError::BadRequest(
+            "parent_ttl needs a parent".to_string(),
+        ));
+    }
+    let options = SetOptions {
+        ttl: req.ttl.map(Duration::from_secs),
+        parents,
+        parent_ttl: req.parent_ttl.map(Duration::from_secs),
+        nx: req.nx,
+        xx: req.xx,
+        pinned: req.pinned,
+        priority: req.priority,
+        jitter: req.jitter,
+        tags: req.tags,
+    };
+    let concern = headers
+        .get(WRITE_CONCERN_HEADER)
+        .map(|v| v.to_str().unwrap_or_default().parse())
+        .transpose()
+        .map_err(ApiError::BadRequest)?;
+    let command = Command::Set {
+        key,
+        value: req.value.into_bytes(),
+        options,
+        concern,
+    };
+    let response = executor.execute(command);
+    match response {
+        CommandResponse::Ok => Ok("OK".to_string()),
+        CommandResponse::Null => Ok("Key unchanged".to_string()),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+/// A single byte range from a `Range` or `Content-Range` header: `(Some(first), last)`
+/// for `first-last` or `first-`, `(None, Some(n))` for the last `n` bytes
+type ByteRange = (Option<u64>, Option<u64>);
+
+/// Parses `bytes=first-last`, `bytes=first-` or `bytes=-suffix`. Multiple ranges aren't supported.
+fn parse_byte_range(value: &str) -> Option<ByteRange> {
+    let (first, last) = value.strip_prefix("bytes=")?.trim().split_once('-')?;
+    let parse = |n: &str| match n.trim() {
+        "" => Ok(None),
+        n => n.parse::<u64>().map(Some),
+    };
+    match (parse(first).ok()?, parse(last).ok()?) {
+        (Some(first), Some(last)) if first > last => None,
+        (None, None) => None,
+        range => Some(range),
+    }
+}
+
+/// Parses `bytes first-last/total` (total may be `*`) into the written offset and length
+fn parse_content_range(value: &str) -> Option<(u64, u64)> {
+    let (range, _total) = value.strip_prefix("bytes ")?.trim().split_once('/')?;
+    let (first, last) = range.split_once('-')?;
+    let (first, last) = (first.parse::<u64>().ok()?, last.parse::<u64>().ok()?);
+    (first <= last).then(|| (first, last - first + 1))
+}
+
+/// Serves part of a string or byte value as `206 Partial Content`. The length and
+/// the bytes are read with STRLEN and GETRANGE, so they go through the executor
+/// like any other read.
+fn get_key_range(executor: &CommandExecutor, key: &str, range: ByteRange) -> ApiResult<Response> {
+    let total = match executor.execute(Command::StrLen {
+        key: key.to_string(),
+    }) {
+        CommandResponse::Integer(len) => len as u64,
+        CommandResponse::Error(e) => return Err(e.into()),
+        _ => return Err(ApiError::InternalError("Unexpected response".to_string())),
+    };
+    // STRLEN reads a missing key as empty
+    let exists = Command::Exists {
+        keys: vec![key.to_string()],
+    };
+    if total == 0 && !matches!(executor.execute(exists), CommandResponse::Integer(1)) {
+        return Err(ApiError::NotFound("Key not found".to_string()));
+    }
+
+    let unsatisfiable = || {
+        Ok((
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [(header::CONTENT_RANGE, format!("bytes */{}", total))],
+        )
+            .into_response())
+    };
+    // A range starting past the end, or a zero-length suffix, selects nothing
+    let (first, last) = match range {
+        (Some(first), last) if first < total => (first, last.unwrap_or(u64::MAX).min(total - 1)),
+        (None, Some(suffix)) if suffix > 0 && total > 0 => {
+            (total.saturating_sub(suffix), total - 1)
+        }
+        _ => return unsatisfiable(),
+    };
+    let offset = |n: u64| i64::try_from(n).unwrap_or(i64::MAX);
+    let command = Command::GetRange {
+        key: key.to_string(),
+        start: offset(first),
+        end: offset(last),
+    };
+    let bytes = match executor.execute(command) {
+        CommandResponse::Bytes(bytes) => bytes,
+        CommandResponse::Error(e) => return Err(e.into()),
+        _ => return Err(ApiError::InternalError("Unexpected response".to_string())),
+    };
+    // The value may have shrunk since its length was read
+    if bytes.is_empty() {
+        return unsatisfiable();
+    }
+    let content_range = format!(
+        "bytes {}-{}/{}",
+        first,
+        first + bytes.len() as u64 - 1,
+        total
+    );
+    Ok((
+        StatusCode::PARTIAL_CONTENT,
+        [
+            (header::CONTENT_RANGE, content_range),
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+        ],
+        bytes,
+    )
+        .into_response())
+}
+
+/// Stores the raw body as the key's value. With `Content-Range` the body is written
+/// at that offset instead, zero-padding past the end like SETRANGE, and the reply is
+/// the new length, so large blobs can be uploaded in chunks.
+async fn put_key(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> ApiResult<String> {
+    check_lock(&locks, &headers, &key)?;
+    let Some(content_range) = headers.get(header::CONTENT_RANGE) else {
+        let command = Command::Set {
+            key,
+            value: body.to_vec(),
+            options: SetOptions::default(),
+            concern: None,
+        };
+        return match executor.execute(command) {
+            CommandResponse::Ok => Ok("OK".to_string()),
+            CommandResponse::Error(e) => Err(e.into()),
+            _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+        };
+    };
+
+    let (offset, len) = content_range
+        .to_str()
+        .ok()
+        .and_then(parse_content_range)
+        .ok_or_else(|| ApiError::BadRequest("Invalid Content-Range header".to_string()))?;
+    if len != body.len() as u64 {
+        return Err(ApiError::BadRequest(
+            "Content-Range length does not match the body".to_string(),
+        ));
+    }
+    let command = Command::SetRange {
+        key,
+        offset: usize::try_from(offset).unwrap_or(usize::MAX),
+        value: body.to_vec(),
+    };
+    match executor.execute(command) {
+        CommandResponse::Integer(len) => Ok(len.to_string()),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn delete_key(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    Query(params): Query<DeleteQuery>,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    let command = Command::Del {
+        keys: vec![key.clone()],
+        cascade: params.cascade,
+    };
+    if params.dry_run {
+        return dry_run(&executor, command);
+    }
+    check_delete_locks(&executor, &locks, &headers, command.keys(), params.cascade)?;
+    let response = executor.execute(command);
+    match response {
+        CommandResponse::Integer(0) => Err(ApiError::NotFound("Key not found".to_string())),
+        CommandResponse::Integer(count) => Ok(format!("Deleted {} key(s)", count).into_response()),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn get_ttl(
+    Path(key): Path<String>,
+    Query(params): Query<TtlQuery>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<Json<i64>> {
+    let command = Command::Ttl {
+        key,
+        millis: params.ms,
+    };
+    let response = executor.execute(command);
+    match response {
+        CommandResponse::Integer(-2) => Err(ApiError::NotFound("Key not found".to_string())),
+        CommandResponse::Integer(ttl) => Ok(Json(ttl)),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn get_key_info(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<Json<KeyInfo>> {
+    let command = Command::GetInfo { key };
+    let response = executor.execute(command);
+    match response {
+        CommandResponse::KeyInfo(info) => Ok(Json(*info)),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn set_expire(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    Json(req): Json<ExpireRequest>,
+) -> ApiResult<String> {
+    check_lock(&locks, &headers, &key)?;
+    let command = match (req.seconds, req.milliseconds) {
+        (Some(ttl), None) => Command::Expire {
+            key,
+            ttl,
+            millis: false,
+        },
+        (None, Some(ttl)) => Command::Expire {
+            key,
+            ttl,
+            millis: true,
+        },
+        _ => {
+            return Err(ApiError::BadRequest(
+                "Give one of seconds or milliseconds".to_string(),
+            ));
+        }
+    };
+    let response = executor.execute(command);
+    match response {
+        CommandResponse::Integer(1) => Ok("Expiry set".to_string()),
+        CommandResponse::Integer(0) => Err(ApiError::NotFound("Key not found".to_string())),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn set_expire_at(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    Json(req): Json<ExpireAtRequest>,
+) -> ApiResult<String> {
+    check_lock(&locks, &headers, &key)?;
+    let at = OffsetDateTime::parse(&req.at, &Rfc3339)
+        .map_err(|e| ApiError::BadRequest(format!("Invalid RFC3339 timestamp: {}", e)))?;
+    // Kept to the millisecond, so fractional seconds count
+    let timestamp = u64::try_from(at.unix_timestamp_nanos() / 1_000_000)
+        .map_err(|_| ApiError::BadRequest("Timestamp predates the unix epoch".to_string()))?;
+
+    let command = Command::ExpireAt {
+        key,
+        timestamp,
+        millis: true,
+    };
+    let response = executor.execute(command);
+    match response {
+        CommandResponse::Integer(1) => Ok("Expiry set".to_string()),
+        CommandResponse::Integer(0) => Err(ApiError::NotFound("Key not found".to_string())),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn persist_key(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+) -> ApiResult<String> {
+    check_lock(&locks, &headers, &key)?;
+    let command = Command::Persist { key };
+    let response = executor.execute(command);
+    match response {
+        CommandResponse::Integer(1) => Ok("Key persisted".to_string()),
+        CommandResponse::Integer(0) => Err(ApiError::NotFound("Key not found".to_string())),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn incr_key(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    Json(req): Json<IncrRequest>,
+) -> ApiResult<Json<IncrResponse>> {
+    check_lock(&locks, &headers, &key)?;
+    let command = Command::IncrBy {
+        key,
+        delta: req.by,
+        limit: req.limit,
+    };
+    let response = executor.execute(command);
+    match response {
+        CommandResponse::Integer(value) => Ok(Json(IncrResponse {
+            applied: true,
+            value: Some(value),
+        })),
+        CommandResponse::Null => Ok(Json(IncrResponse {
+            applied: false,
+            value: None,
+        })),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn decr_key(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    Json(req): Json<DecrRequest>,
+) -> ApiResult<Json<IncrResponse>> {
+    check_lock(&locks, &headers, &key)?;
+    let delta = req
+        .by
+        .checked_neg()
+        .ok_or_else(|| ApiError::BadRequest("Decrement out of range".to_string()))?;
+    let command = Command::IncrBy {
+        key,
+        delta,
+        limit: None,
+    };
+    let response = executor.execute(command);
+    match response {
+        CommandResponse::Integer(value) => Ok(Json(IncrResponse {
+            applied: true,
+            value: Some(value),
+        })),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn incr_key_by_float(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    Json(req): Json<IncrFloatRequest>,
+) -> ApiResult<Json<f64>> {
+    check_lock(&locks, &headers, &key)?;
+    let command = Command::IncrByFloat { key, delta: req.by };
+    let response = executor.execute(command);
+    match response {
+        CommandResponse::Value(value) => value
+            .parse()
+            .map(Json)
+            .map_err(|_| ApiError::InternalError("Unexpected response".to_string())),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+/// Whole hash as a JSON object; a missing key reads as an empty one
+async fn get_hash(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<Json<BTreeMap<String, String>>> {
+    match executor.execute(Command::HGetAll { key }) {
+        CommandResponse::Array(items) => Ok(Json(
+            items
+                .chunks_exact(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone()))
+                .collect(),
+        )),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+/// Sets every field of a JSON object, replying with how many were new
+async fn set_hash_fields(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    Json(fields): Json<BTreeMap<String, String>>,
+) -> ApiResult<Json<i64>> {
+    check_lock(&locks, &headers, &key)?;
+    if fields.is_empty() {
+        return Err(ApiError::BadRequest("No fields given".to_string()));
+    }
+    let command = Command::HSet {
+        key,
+        fields: fields.into_iter().collect(),
+    };
+    match executor.execute(command) {
+        CommandResponse::Integer(added) => Ok(Json(added)),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn get_hash_field(
+    Path((key, field)): Path<(String, String)>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<Json<String>> {
+    match executor.execute(Command::HGet { key, field }) {
+        CommandResponse::Value(value) => Ok(Json(value)),
+        CommandResponse::Null => Err(ApiError::NotFound("Field not found".to_string())),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn set_hash_field(
+    Path((key, field)): Path<(String, String)>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    Json(req): Json<HashFieldRequest>,
+) -> ApiResult<String> {
+    check_lock(&locks, &headers, &key)?;
+    let command = Command::HSet {
+        key,
+        fields: vec![(field, req.value)],
+    };
+    match executor.execute(command) {
+        CommandResponse::Integer(_) => Ok("OK".to_string()),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn delete_hash_field(
+    Path((key, field)): Path<(String, String)>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+) -> ApiResult<String> {
+    check_lock(&locks, &headers, &key)?;
+    let command = Command::HDel {
+        key,
+        fields: vec![field],
+    };
+    match executor.execute(command) {
+        CommandResponse::Integer(1) => Ok("Field deleted".to_string()),
+        CommandResponse::Integer(_) => Err(ApiError::NotFound("Field not found".to_string())),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn incr_hash_field(
+    Path((key, field)): Path<(String, String)>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    Json(req): Json<HashIncrRequest>,
+) -> ApiResult<Json<i64>> {
+    check_lock(&locks, &headers, &key)?;
+    let command = Command::HIncrBy {
+        key,
+        field,
+        delta: req.by,
+    };
+    match executor.execute(command) {
+        CommandResponse::Integer(value) => Ok(Json(value)),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+/// Members of a set, sorted; a missing key reads as an empty set
+async fn get_members(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<Json<Vec<String>>> {
+    match executor.execute(Command::SMembers { key }) {
+        CommandResponse::Array(members) => Ok(Json(members)),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+/// Adds every member of a JSON array, replying with how many were new
+async fn add_members(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    Json(members): Json<Vec<String>>,
+) -> ApiResult<Json<i64>> {
+    check_lock(&locks, &headers, &key)?;
+    if members.is_empty() {
+        return Err(ApiError::BadRequest("No members given".to_string()));
+    }
+    match executor.execute(Command::SAdd { key, members }) {
+        CommandResponse::Integer(added) => Ok(Json(added)),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+/// The value at `path` in a JSON document, as JSON
+async fn get_json(
+    Path(key): Path<String>,
+    Query(params): Query<JsonPathQuery>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<Response> {
+    let command = Command::JsonGet {
+        key,
+        path: params.path,
+    };
+    match executor.execute(command) {
+        CommandResponse::Value(json) => {
+            Ok(([(header::CONTENT_TYPE, "application/json")], json).into_response())
+        }
+        CommandResponse::Null => Err(ApiError::NotFound("Key or path not found".to_string())),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+/// Writes the JSON body at `path`, creating the document when `path` is the root
+async fn put_json(
+    Path(key): Path<String>,
+    Query(params): Query<JsonPathQuery>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    Json(value): Json<serde_json::Value>,
+) -> ApiResult<String> {
+    check_lock(&locks, &headers, &key)?;
+    let command = Command::JsonSet {
+        key,
+        path: params.path,
+        value: value.to_string(),
+        nx: params.nx,
+        xx: params.xx,
+    };
+    match executor.execute(command) {
+        CommandResponse::Ok => Ok("OK".to_string()),
+        CommandResponse::Null => Ok("Key unchanged".to_string()),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn delete_json(
+    Path(key): Path<String>,
+    Query(params): Query<JsonPathQuery>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+) -> ApiResult<Json<i64>> {
+    check_lock(&locks, &headers, &key)?;
+    let command = Command::JsonDel {
+        key,
+        path: params.path,
+    };
+    match executor.execute(command) {
+        CommandResponse::Integer(removed) => Ok(Json(removed)),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+/// Estimated distinct members added to a HyperLogLog; 0 for a missing key
+async fn get_cardinality(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<Json<i64>> {
+    match executor.execute(Command::PfCount { keys: vec![key] }) {
+        CommandResponse::Integer(count) => Ok(Json(count)),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+/// Counts every member of a JSON array, replying whether the estimate may have changed
+async fn add_to_cardinality(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    Json(members): Json<Vec<String>>,
+) -> ApiResult<Json<bool>> {
+    check_lock(&locks, &headers, &key)?;
+    match executor.execute(Command::PfAdd { key, members }) {
+        CommandResponse::Integer(changed) => Ok(Json(changed == 1)),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn is_member(
+    Path((key, member)): Path<(String, String)>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<Json<bool>> {
+    match executor.execute(Command::SIsMember { key, member }) {
+        CommandResponse::Integer(found) => Ok(Json(found == 1)),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn add_member(
+    Path((key, member)): Path<(String, String)>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+) -> ApiResult<String> {
+    check_lock(&locks, &headers, &key)?;
+    let command = Command::SAdd {
+        key,
+        members: vec![member],
+    };
+    match executor.execute(command) {
+        CommandResponse::Integer(_) => Ok("OK".to_string()),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn remove_member(
+    Path((key, member)): Path<(String, String)>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+) -> ApiResult<String> {
+    check_lock(&locks, &headers, &key)?;
+    let command = Command::SRem {
+        key,
+        members: vec![member],
+    };
+    match executor.execute(command) {
+        CommandResponse::Integer(1) => Ok("Member removed".to_string()),
+        CommandResponse::Integer(_) => Err(ApiError::NotFound("Member not found".to_string())),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+/// Appends the raw body to the key's value and replies with the new length
+async fn append_key(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    value: String,
+) -> ApiResult<Json<i64>> {
+    check_lock(&locks, &headers, &key)?;
+    match executor.execute(Command::Append {
+        key,
+        value: value.into_bytes(),
+    }) {
+        CommandResponse::Integer(len) => Ok(Json(len)),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn get_strlen(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<Json<i64>> {
+    match executor.execute(Command::StrLen { key }) {
+        CommandResponse::Integer(len) => Ok(Json(len)),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn expire_fields(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    Json(req): Json<FieldsRequest>,
+) -> ApiResult<Json<Vec<i64>>> {
+    check_lock(&locks, &headers, &key)?;
+    let seconds = req
+        .seconds
+        .ok_or_else(|| ApiError::BadRequest("Missing seconds".to_string()))?;
+    let command = Command::HExpire {
+        key,
+        seconds,
+        fields: req.fields,
+    };
+    field_replies(executor.execute(command))
+}
+
+async fn get_field_ttls(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    Query(params): Query<FieldsQuery>,
+) -> ApiResult<Json<Vec<i64>>> {
+    let fields = params.fields.split(',').map(|s| s.trim().to_string());
+    let command = Command::HTtl {
+        key,
+        fields: fields.collect(),
+    };
+    field_replies(executor.execute(command))
 }
 
-async fn get_metrics(State(executor): State<Arc<CommandExecutor>>) -> String {
-    let stats = executor.cache.stats();
-    stats.render()
+async fn persist_fields(
+    Path(key): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    Json(req): Json<FieldsRequest>,
+) -> ApiResult<Json<Vec<i64>>> {
+    check_lock(&locks, &headers, &key)?;
+    let command = Command::HPersist {
+        key,
+        fields: req.fields,
+    };
+    field_replies(executor.execute(command))
 }
 
-async fn get_dashboard(State(_executor): State<Arc<CommandExecutor>>) -> &'static str {
-    "TODO: React dashboard"
+fn field_replies(response: CommandResponse) -> ApiResult<Json<Vec<i64>>> {
+    match response {
+        CommandResponse::Integers(replies) => Ok(Json(replies)),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
 }
 
-async fn get_key(
+async fn lock_key(
     Path(key): Path<String>,
-    State(executor): State<Arc<CommandExecutor>>,
-) -> ApiResult<Json<String>> {
-    let command = Command::Get { key };
-    let response = executor.execute(command);
-    match response {
-        CommandResponse::Value(v) => Ok(Json(v)),
-        CommandResponse::Null => Err(ApiError::NotFound("Key not found".to_string())),
-        CommandResponse::Error(e) => Err(ApiError::BadRequest(e)),
-        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    State(locks): State<Arc<KeyLocks>>,
+    Json(req): Json<LockRequest>,
+) -> ApiResult<Json<LockResponse>> {
+    let ttl = req.ttl.unwrap_or(DEFAULT_LOCK_TTL_SECS);
+    if ttl == 0 || ttl > command_table::MAX_TTL_SECS {
+        return Err(ApiError::BadRequest("Invalid lock TTL".to_string()));
+    }
+    match locks.acquire(&key, Duration::from_secs(ttl)) {
+        Some(token) => Ok(Json(LockResponse { token, ttl })),
+        None => Err(ApiError::Locked(format!("Key '{}' is locked", key))),
     }
 }
 
-async fn set_key(
+async fn unlock_key(
+    Path(key): Path<String>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+) -> ApiResult<String> {
+    let token = headers
+        .get(LOCK_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| ApiError::BadRequest("Missing lock token".to_string()))?;
+    if locks.release(&key, token) {
+        Ok("Lock released".to_string())
+    } else {
+        Err(ApiError::NotFound(
+            "No lock held with that token".to_string(),
+        ))
+    }
+}
+
+/// Moves a key, children and all, replacing any key already at the new name
+async fn rename_key(
     Path(key): Path<String>,
     State(executor): State<Arc<CommandExecutor>>,
-    Json(req): Json<SetKeyRequest>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    Json(req): Json<RenameRequest>,
 ) -> ApiResult<String> {
-    let options = SetOptions {
-        ttl: req.ttl.map(Duration::from_secs),
-        parent: req.parent,
-        nx: req.nx,
-        xx: req.xx,
-    };
-    let command = Command::Set {
+    check_lock(&locks, &headers, &key)?;
+    check_lock(&locks, &headers, &req.to)?;
+    let command = Command::Rename {
         key,
-        value: req.value,
-        options,
+        new_key: req.to,
     };
-    let response = executor.execute(command);
-    match response {
-        CommandResponse::Ok => Ok("OK".to_string()),
-        CommandResponse::Null => Ok("Key unchanged".to_string()),
-        CommandResponse::Error(e) => Err(ApiError::BadRequest(e)),
+    match executor.execute(command) {
+        CommandResponse::Ok => Ok("Key renamed".to_string()),
+        CommandResponse::Error(e) => Err(e.into()),
         _ => Err(ApiError::InternalError("Unexpected response".to_string())),
     }
 }
 
-async fn delete_key(
+async fn set_parent(
     Path(key): Path<String>,
     State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    Json(req): Json<SetParentRequest>,
 ) -> ApiResult<String> {
-    let command = Command::Del { keys: vec![key] };
+    check_lock(&locks, &headers, &key)?;
+    let command = Command::SetParent {
+        key,
+        parent: req.parent,
+    };
     let response = executor.execute(command);
     match response {
+        CommandResponse::Integer(1) => Ok("Parent set".to_string()),
         CommandResponse::Integer(0) => Err(ApiError::NotFound("Key not found".to_string())),
-        CommandResponse::Integer(count) => Ok(format!("Deleted {} key(s)", count)),
-        CommandResponse::Error(e) => Err(ApiError::BadRequest(e)),
+        CommandResponse::Error(e) => Err(e.into()),
         _ => Err(ApiError::InternalError("Unexpected response".to_string())),
     }
 }
 
-async fn get_ttl(
+async fn unset_parent(
     Path(key): Path<String>,
     State(executor): State<Arc<CommandExecutor>>,
-) -> ApiResult<Json<i64>> {
-    let command = Command::Ttl { key };
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+) -> ApiResult<String> {
+    check_lock(&locks, &headers, &key)?;
+    let command = Command::UnsetParent { key, parent: None };
     let response = executor.execute(command);
     match response {
-        CommandResponse::Integer(-2) => Err(ApiError::NotFound("Key not found".to_string())),
-        CommandResponse::Integer(ttl) => Ok(Json(ttl)),
-        CommandResponse::Error(e) => Err(ApiError::BadRequest(e)),
+        CommandResponse::Integer(1) => Ok("Parent removed".to_string()),
+        CommandResponse::Integer(0) => Err(ApiError::NotFound("Key has no parent".to_string())),
+        CommandResponse::Error(e) => Err(e.into()),
         _ => Err(ApiError::InternalError("Unexpected response".to_string())),
     }
 }
 
-async fn get_key_info(
+async fn get_parents(
     Path(key): Path<String>,
     State(executor): State<Arc<CommandExecutor>>,
-) -> ApiResult<Json<KeyInfo>> {
-    let command = Command::GetInfo { key };
-    let response = executor.execute(command);
-    match response {
-        CommandResponse::KeyInfo(info) => Ok(Json(info)),
-        CommandResponse::Error(e) => Err(ApiError::BadRequest(e)),
-        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+) -> ApiResult<Json<Vec<String>>> {
+    if !executor.cache.exists(&key) {
+        return Err(ApiError::NotFound("Key not found".to_string()));
     }
+    Ok(Json(executor.cache.parents(&key)))
 }
 
-async fn set_expire(
+/// Links one more parent, keeping the ones the key already has
+async fn add_parent(
     Path(key): Path<String>,
     State(executor): State<Arc<CommandExecutor>>,
-    Json(req): Json<ExpireRequest>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    Json(req): Json<SetParentRequest>,
 ) -> ApiResult<String> {
-    let command = Command::Expire {
+    check_lock(&locks, &headers, &key)?;
+    let command = Command::AddParent {
         key,
-        seconds: req.seconds,
+        parent: req.parent,
     };
-    let response = executor.execute(command);
-    match response {
-        CommandResponse::Integer(1) => Ok("Expiry set".to_string()),
+    match executor.execute(command) {
+        CommandResponse::Integer(1) => Ok("Parent added".to_string()),
         CommandResponse::Integer(0) => Err(ApiError::NotFound("Key not found".to_string())),
-        CommandResponse::Error(e) => Err(ApiError::BadRequest(e)),
+        CommandResponse::Error(e) => Err(e.into()),
         _ => Err(ApiError::InternalError("Unexpected response".to_string())),
     }
 }
 
-async fn persist_key(
-    Path(key): Path<String>,
+async fn remove_parent(
+    Path((key, parent)): Path<(String, String)>,
     State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
 ) -> ApiResult<String> {
-    let command = Command::Persist { key };
-    let response = executor.execute(command);
-    match response {
-        CommandResponse::Integer(1) => Ok("Key persisted".to_string()),
-        CommandResponse::Integer(0) => Err(ApiError::NotFound("Key not found".to_string())),
-        CommandResponse::Error(e) => Err(ApiError::BadRequest(e)),
+    check_lock(&locks, &headers, &key)?;
+    let command = Command::UnsetParent {
+        key,
+        parent: Some(parent),
+    };
+    match executor.execute(command) {
+        CommandResponse::Integer(1) => Ok("Parent removed".to_string()),
+        CommandResponse::Integer(0) => {
+            Err(ApiError::NotFound("Key has no such parent".to_string()))
+        }
+        CommandResponse::Error(e) => Err(e.into()),
         _ => Err(ApiError::InternalError("Unexpected response".to_string())),
     }
 }
 
-async fn set_parent(
+async fn reparent_children(
     Path(key): Path<String>,
     State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
     Json(req): Json<SetParentRequest>,
 ) -> ApiResult<String> {
-    let command = Command::SetParent {
-        key,
-        parent: req.parent,
+    check_lock(&locks, &headers, &key)?;
+    let command = Command::ReparentChildren {
+        from: key,
+        to: req.parent,
     };
     let response = executor.execute(command);
     match response {
-        CommandResponse::Integer(1) => Ok("Parent set".to_string()),
-        CommandResponse::Integer(0) => Err(ApiError::NotFound("Key not found".to_string())),
-        CommandResponse::Error(e) => Err(ApiError::BadRequest(e)),
+        CommandResponse::Integer(count) => Ok(format!("Moved {} child(ren)", count)),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+/// Descendant counts for many parents at once, without a key info call per parent
+async fn count_children(
+    State(executor): State<Arc<CommandExecutor>>,
+    Json(req): Json<ChildrenCountRequest>,
+) -> Json<BTreeMap<String, usize>> {
+    let depth = req
+        .depth
+        .unwrap_or(executor.cache.config().info_children_depth);
+    let counts = executor.cache.children_counts(&req.parents, depth);
+    Json(req.parents.into_iter().zip(counts).collect())
+}
+
+async fn export_graph(State(executor): State<Arc<CommandExecutor>>) -> Json<DependencyGraph> {
+    Json(DependencyGraph {
+        edges: executor.cache.dependency_graph(),
+    })
+}
+
+async fn import_graph(
+    State(executor): State<Arc<CommandExecutor>>,
+    Json(graph): Json<DependencyGraph>,
+) -> ApiResult<Json<GraphImport>> {
+    let command = Command::ImportGraph { edges: graph.edges };
+    match executor.execute(command) {
+        CommandResponse::Integers(counts) => {
+            let [applied, missing, cycles, too_deep] = counts[..] else {
+                return Err(ApiError::InternalError("Unexpected response".to_string()));
+            };
+            Ok(Json(GraphImport {
+                applied: applied as usize,
+                missing: missing as usize,
+                cycles: cycles as usize,
+                too_deep: too_deep as usize,
+            }))
+        }
+        CommandResponse::Error(e) => Err(e.into()),
         _ => Err(ApiError::InternalError("Unexpected response".to_string())),
     }
 }
@@ -220,19 +1922,64 @@ async fn get_children(
     Path(key): Path<String>,
     State(executor): State<Arc<CommandExecutor>>,
     Json(req): Json<GetChildrenRequest>,
-) -> ApiResult<Json<Vec<String>>> {
+) -> ApiResult<Response> {
+    let hydrate = req.hydrate.then(|| HydrateOptions {
+        max_bytes: req.max_bytes.unwrap_or(DEFAULT_HYDRATE_MAX_BYTES),
+    });
+    if req.stream {
+        return stream_children(key, req, hydrate, executor);
+    }
     let command = Command::GetChildren {
         parent: key,
         depth: req.depth,
+        offset: req.offset,
+        limit: req.limit,
+        hydrate,
     };
-    let response = executor.execute(command);
-    match response {
+    let (response, cursor) = split_partial(executor.execute(command));
+    let response = match response {
         CommandResponse::ArrayWithDepth(children) => {
             let child_keys: Vec<String> = children.into_iter().map(|(key, _)| key).collect();
-            Ok(Json(child_keys))
+            Json(child_keys).into_response()
         }
+        CommandResponse::Children(children) => Json(children).into_response(),
+
+        CommandResponse::Error(e) => return Err(e.into()),
+        _ => return Err(ApiError::InternalError("Unexpected response".to_string())),
+    };
+    Ok(with_continuation(response, cursor))
+}
+
+/// A reply and, if the time budget cut it short, the cursor that carries on
+fn split_partial(response: CommandResponse) -> (CommandResponse, Option<u64>) {
+    match response {
+        CommandResponse::Partial { cursor, reply } => (*reply, Some(cursor)),
+        response => (response, None),
+    }
+}
+
+fn with_continuation(mut response: Response, cursor: Option<u64>) -> Response {
+    if let Some(cursor) = cursor {
+        response
+            .headers_mut()
+            .insert(CONTINUATION_HEADER, HeaderValue::from(cursor));
+    }
+    response
+}
 
-        CommandResponse::Error(e) => Err(ApiError::BadRequest(e)),
+/// A page of an incremental walk over the keyspace, like SCAN
+async fn scan_keys(
+    Query(params): Query<ScanQuery>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<Json<ScanPage>> {
+    let command = Command::Scan {
+        cursor: params.cursor,
+        pattern: params.pattern,
+        count: params.count,
+    };
+    match split_partial(executor.execute(command)) {
+        (CommandResponse::Array(keys), Some(cursor)) => Ok(Json(ScanPage { cursor, keys })),
+        (CommandResponse::Error(e), _) => Err(e.into()),
         _ => Err(ApiError::InternalError("Unexpected response".to_string())),
     }
 }
@@ -240,39 +1987,171 @@ async fn get_children(
 async fn list_keys(
     Query(params): Query<ListKeysQuery>,
     State(executor): State<Arc<CommandExecutor>>,
-) -> ApiResult<Json<Vec<String>>> {
+) -> ApiResult<Response> {
     let pattern = params.pattern.unwrap_or_else(|| "*".to_string());
+    if params.stream {
+        return Ok(stream_keys(pattern, params.limit, executor));
+    }
+
     let command = Command::ListKeys {
         pattern,
         limit: params.limit,
+        cursor: params.cursor,
+    };
+    match split_partial(executor.execute(command)) {
+        (CommandResponse::Array(keys), cursor) => {
+            Ok(with_continuation(Json(keys).into_response(), cursor))
+        }
+        (CommandResponse::Error(e), _) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn list_expiring_keys(
+    Query(params): Query<ExpiringKeysQuery>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> ApiResult<Json<Vec<String>>> {
+    let command = Command::ExpiringKeys {
+        within: params.within.unwrap_or(60),
+        limit: params.limit,
     };
     let response = executor.execute(command);
     match response {
         CommandResponse::Array(keys) => Ok(Json(keys)),
-        CommandResponse::Error(e) => Err(ApiError::BadRequest(e)),
+        CommandResponse::Error(e) => Err(e.into()),
         _ => Err(ApiError::InternalError("Unexpected response".to_string())),
     }
 }
 
 async fn delete_multiple(
     State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    Query(params): Query<DeleteQuery>,
+    headers: HeaderMap,
     Json(req): Json<MultiKeyRequest>,
-) -> ApiResult<String> {
-    let command = Command::Del { keys: req.keys };
+) -> ApiResult<Response> {
+    let command = Command::Del {
+        keys: req.keys,
+        cascade: params.cascade,
+    };
+    if params.dry_run {
+        return dry_run(&executor, command);
+    }
+    check_delete_locks(&executor, &locks, &headers, command.keys(), params.cascade)?;
+    let response = executor.execute(command);
+    match response {
+        CommandResponse::Integer(count) => Ok(format!("Deleted {} key(s)", count).into_response()),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+/// Cascade invalidation, returning the number of keys affected per root
+async fn invalidate(
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    Query(params): Query<DryRunQuery>,
+    headers: HeaderMap,
+    Json(req): Json<InvalidateRequest>,
+) -> ApiResult<Response> {
+    let command = Command::Invalidate {
+        roots: req.keys.clone(),
+        mode: req.mode,
+    };
+    if params.dry_run {
+        return dry_run(&executor, command);
+    }
+    for key in &req.keys {
+        check_lock(&locks, &headers, key)?;
+    }
     let response = executor.execute(command);
     match response {
-        CommandResponse::Integer(count) => Ok(format!("Deleted {} key(s)", count)),
-        CommandResponse::Error(e) => Err(ApiError::BadRequest(e)),
+        CommandResponse::Integers(counts) => {
+            // In request order, one per root even when a root repeats
+            let counts: Vec<InvalidateCount> = req
+                .keys
+                .into_iter()
+                .zip(counts)
+                .map(|(key, affected)| InvalidateCount { key, affected })
+                .collect();
+            Ok(Json(counts).into_response())
+        }
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+/// Deletes, or with `mode=stale` marks stale, every key carrying the tag along with
+/// its dependents, returning the number of keys affected
+async fn invalidate_tag(
+    Path(tag): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    Query(params): Query<InvalidateTagQuery>,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    let tagged = executor.cache.tagged(&tag);
+    let command = Command::InvalidateTag {
+        tags: vec![tag],
+        mode: params.mode,
+    };
+    if params.dry_run {
+        return dry_run(&executor, command);
+    }
+    for key in &tagged {
+        check_lock(&locks, &headers, key)?;
+    }
+    match executor.execute(command) {
+        CommandResponse::Integers(counts) => Ok(Json(counts[0]).into_response()),
+        CommandResponse::Error(e) => Err(e.into()),
         _ => Err(ApiError::InternalError("Unexpected response".to_string())),
     }
 }
 
-async fn flush_all(State(executor): State<Arc<CommandExecutor>>) -> ApiResult<String> {
+async fn get_tagged_keys(
+    Path(tag): Path<String>,
+    State(executor): State<Arc<CommandExecutor>>,
+) -> Json<Vec<String>> {
+    let mut keys = executor.cache.tagged(&tag);
+    keys.sort();
+    Json(keys)
+}
+
+/// Without a body flushes everything; with a pattern, deletes matching keys shard by
+/// shard and streams progress as NDJSON
+async fn flush_all(
+    State(executor): State<Arc<CommandExecutor>>,
+    Query(params): Query<DryRunQuery>,
+    req: Option<Json<FlushRequest>>,
+) -> ApiResult<Response> {
+    let pattern = req.and_then(|Json(req)| req.pattern);
+    if params.dry_run {
+        let command = match pattern {
+            Some(pattern) => Command::FlushPattern { pattern },
+            None => Command::FlushAll {},
+        };
+        return dry_run(&executor, command);
+    }
+    if let Some(pattern) = pattern {
+        return Ok(stream_flush(pattern, executor));
+    }
     let command = Command::FlushAll {};
     let response = executor.execute(command);
     match response {
-        CommandResponse::Ok => Ok("All keys flushed".to_string()),
-        CommandResponse::Error(e) => Err(ApiError::BadRequest(e)),
+        CommandResponse::Ok => Ok("All keys flushed".into_response()),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+/// Runs `command` as a dry run, replying with the keys and bytes it would affect
+fn dry_run(executor: &CommandExecutor, command: Command) -> ApiResult<Response> {
+    let command = Command::DryRun {
+        command: Box::new(command),
+    };
+    match executor.execute(command) {
+        CommandResponse::DryRun(report) => Ok(Json(report).into_response()),
+        CommandResponse::Error(e) => Err(e.into()),
         _ => Err(ApiError::InternalError("Unexpected response".to_string())),
     }
 }
@@ -286,7 +2165,7 @@ async fn ping(
     let response = executor.execute(command);
     match response {
         CommandResponse::Value(msg) => Ok(msg),
-        CommandResponse::Error(e) => Err(ApiError::BadRequest(e)),
+        CommandResponse::Error(e) => Err(e.into()),
         _ => Err(ApiError::InternalError("Unexpected response".to_string())),
     }
 }
@@ -299,42 +2178,771 @@ async fn check_exists(
     let response = executor.execute(command);
     match response {
         CommandResponse::Integer(count) => Ok(Json(count)),
-        CommandResponse::Error(e) => Err(ApiError::BadRequest(e)),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+async fn eval(
+    State(executor): State<Arc<CommandExecutor>>,
+    Json(req): Json<EvalRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let command = Command::Eval {
+        script: req.script,
+        keys: req.keys,
+        args: req.args,
+    };
+    let response = executor.execute(command);
+    match response {
+        CommandResponse::Ok => Ok(Json("OK".into())),
+        CommandResponse::Value(value) => Ok(Json(value.into())),
+        CommandResponse::Integer(n) => Ok(Json(n.into())),
+        CommandResponse::Null => Ok(Json(serde_json::Value::Null)),
+        CommandResponse::Error(e) => Err(e.into()),
         _ => Err(ApiError::InternalError("Unexpected response".to_string())),
     }
 }
 
+async fn kill_scripts(State(executor): State<Arc<CommandExecutor>>) -> ApiResult<String> {
+    let response = executor.execute(Command::ScriptKill {});
+    match response {
+        CommandResponse::Ok => Ok("Scripts killed".to_string()),
+        CommandResponse::Error(e) => Err(e.into()),
+        _ => Err(ApiError::InternalError("Unexpected response".to_string())),
+    }
+}
+
+/// Streams newline-delimited JSON produced on a blocking thread. The bounded
+/// channel applies backpressure, so only a few chunks are in memory at once and
+/// the producer stops when the client goes away.
+fn ndjson_response<F>(produce: F) -> Response
+where
+    F: FnOnce(&mpsc::Sender<Result<Bytes, Infallible>>) + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(STREAM_BUFFERED_CHUNKS);
+    tokio::task::spawn_blocking(move || produce(&tx));
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(ReceiverStream::new(rx)),
+    )
+        .into_response()
+}
+
+fn ndjson_chunk<T: Serialize>(items: impl IntoIterator<Item = T>) -> Bytes {
+    let mut chunk = Vec::new();
+    for item in items {
+        // Serializing plain structs and strings can't fail
+        let _ = serde_json::to_writer(&mut chunk, &item);
+        chunk.push(b'\n');
+    }
+    chunk.into()
+}
+
+fn stream_keys(pattern: String, limit: Option<u64>, executor: Arc<CommandExecutor>) -> Response {
+    let mut remaining = limit
+        .and_then(|l| usize::try_from(l).ok())
+        .unwrap_or(usize::MAX);
+
+    // SCAN steps a shard at a time until it finds a key, so chunks stay shard-sized
+    ndjson_response(move |tx| {
+        let mut cursor = 0;
+        while remaining > 0 {
+            let command = Command::Scan {
+                cursor,
+                pattern: Some(pattern.clone()),
+                count: Some(1),
+            };
+            let CommandResponse::Partial {
+                cursor: next,
+                reply,
+            } = executor.execute(command)
+            else {
+                break;
+            };
+            let CommandResponse::Array(mut keys) = *reply else {
+                break;
+            };
+            keys.truncate(remaining);
+            remaining -= keys.len();
+
+            if !keys.is_empty() && tx.blocking_send(Ok(ndjson_chunk(keys))).is_err() {
+                break;
+            }
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+    })
+}
+
+/// Deletes through the executor, a DEL per shard, so the flush reaches the AOF
+fn stream_flush(pattern: String, executor: Arc<CommandExecutor>) -> Response {
+    ndjson_response(move |tx| {
+        let shards_total = executor.cache.shard_count();
+        let mut deleted = 0;
+        for shard in 0..shards_total {
+            let keys = executor.cache.shard_keys(shard, &pattern);
+            if !keys.is_empty()
+                && let CommandResponse::Integer(n) = executor.execute(Command::Del {
+                    keys,
+                    cascade: false,
+                })
+            {
+                deleted += n as usize;
+            }
+            let progress = FlushProgress {
+                shards_scanned: shard + 1,
+                shards_total,
+                deleted,
+            };
+            if tx.blocking_send(Ok(ndjson_chunk([progress]))).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Pages through GETCHILDREN, so the walk has the buffered reply's caps; a page cut
+/// short by the command time limit is resumed from its cursor. Hydration shares one
+/// byte budget across the whole stream.
+fn stream_children(
+    parent: String,
+    req: GetChildrenRequest,
+    hydrate: Option<HydrateOptions>,
+    executor: Arc<CommandExecutor>,
+) -> ApiResult<Response> {
+    command_table::validate(&Command::GetChildren {
+        parent: parent.clone(),
+        depth: req.depth,
+        offset: req.offset,
+        limit: req.limit,
+        hydrate: hydrate.clone(),
+    })
+    .map_err(CommandError::from)?;
+
+    let mut offset = req.offset.unwrap_or(0);
+    let max_results = executor.cache.config().max_children_results;
+    let mut remaining = req
+        .limit
+        .and_then(|l| usize::try_from(l).ok())
+        .map_or(max_results, |l| l.min(max_results));
+    let mut budget = hydrate.map_or(0, |options| options.max_bytes);
+    let mut truncated = false;
+
+    Ok(ndjson_response(move |tx| {
+        while remaining > 0 {
+            let command = Command::GetChildren {
+                parent: parent.clone(),
+                depth: req.depth,
+                offset: Some(offset),
+                limit: Some(remaining as u64),
+                hydrate: None,
+            };
+            let (page, cursor) = match executor.execute(command) {
+                CommandResponse::ArrayWithDepth(page) => (page, None),
+                CommandResponse::Partial { cursor, reply } => match *reply {
+                    CommandResponse::ArrayWithDepth(page) => (page, Some(cursor)),
+                    _ => break,
+                },
+                _ => break,
+            };
+            remaining -= page.len().min(remaining);
+            if page.is_empty() {
+                break;
+            }
+
+            let chunk = if req.hydrate {
+                ndjson_chunk(executor.hydrate_with_budget(page, &mut budget, &mut truncated))
+            } else {
+                ndjson_chunk(
+                    page.into_iter()
+                        .map(|(key, depth)| ChildEntry { key, depth }),
+                )
+            };
+            if tx.blocking_send(Ok(chunk)).is_err() {
+                break;
+            }
+            match cursor {
+                Some(cursor) => offset = cursor,
+                None => break,
+            }
+        }
+    }))
+}
+
+#[derive(Serialize)]
+struct ChildEntry {
+    key: String,
+    depth: u64,
+}
+
+/// Refuses writes to keys locked against the request's token, as the single-key
+/// routes do
+fn lock_check(locks: Arc<KeyLocks>, headers: &HeaderMap) -> CommandCheck {
+    let token = headers
+        .get(LOCK_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+    Arc::new(move |command| {
+        let write = command_table::lookup(command.name()).is_some_and(|spec| spec.write);
+        let locked = command
+            .keys()
+            .into_iter()
+            .find(|key| write && !locks.permits(key, token.as_deref()));
+        match locked {
+            Some(key) => Err(CommandError::new(
+                ErrorClass::Locked,
+                format!("Key '{}' is locked", key),
+            )),
+            None => Ok(()),
+        }
+    })
+}
+
+/// Runs each command in order; a failing command doesn't stop the rest. A write to
+/// a locked key refuses the whole batch with 423 before anything runs; writes a
+/// plugin command makes are checked as it makes them.
+async fn batch(
+    State(executor): State<Arc<CommandExecutor>>,
+    State(locks): State<Arc<KeyLocks>>,
+    headers: HeaderMap,
+    Json(request): Json<BatchRequest>,
+) -> ApiResult<Json<Vec<serde_json::Value>>> {
+    let check = lock_check(locks, &headers);
+    let commands: Vec<(Vec<&[u8]>, _)> = request
+        .commands
+        .iter()
+        .map(|args| {
+            let args: Vec<&[u8]> = args.iter().map(String::as_bytes).collect();
+            let command = Command::parse(&args);
+            (args, command)
+        })
+        .collect();
+    for (_, command) in &commands {
+        if let Ok(command) = command {
+            check(command)?;
+        }
+    }
+
+    let replies = commands
+        .into_iter()
+        .map(|(args, command)| {
+            let response = match command {
+                Ok(command) => executor.execute(command),
+                Err(e) => executor
+                    .execute_plugin_checked(&args, check.clone())
+                    .unwrap_or_else(|| CommandResponse::Error(e.into())),
+            };
+            response.to_json()
+        })
+        .collect();
+    Ok(Json(replies))
+}
+
+/// An HTTP listener, parsed from `addr` or `addr=readonly`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenerConfig {
+    pub addr: String,
+    pub read_only: bool, // Only reads are routed; writes are refused with 403
+}
+
+impl FromStr for ListenerConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, read_only) = match s.split_once('=') {
+            None => (s, false),
+            Some((addr, mode)) if mode.eq_ignore_ascii_case("readonly") => (addr, true),
+            Some((_, mode)) => return Err(format!("unknown listener mode '{}'", mode)),
+        };
+        if addr.is_empty() {
+            return Err(format!("listener '{}' has no address", s));
+        }
+        Ok(Self {
+            addr: addr.to_string(),
+            read_only,
+        })
+    }
+}
+
 pub struct HttpApiServer {}
 
 impl HttpApiServer {
-    pub fn create_router(executor: Arc<CommandExecutor>) -> Router {
+    /// The full API. Listeners share `locks`, so a lock taken through one holds on all.
+    pub fn create_router(executor: Arc<CommandExecutor>, locks: Arc<KeyLocks>) -> Router {
         Router::new()
             // Raw endpoints
             .route("/metrics", get(get_metrics))
+            .route("/info", get(get_info))
             .route("/dash", get(get_dashboard))
+            .route("/stats/keyspace", get(get_keyspace_stats))
+            .route("/stats/sample", get(get_key_sample))
+            .route("/stats/cold-keys", get(get_cold_keys))
+            .route("/admin/diagnostics", get(get_diagnostics))
+            .route("/admin/memory/purge", post(purge_memory))
+            .route("/debug/contention", get(get_contention))
+            .route("/admin/snapshot", post(start_snapshot))
+            .route("/admin/snapshot/{job_id}", get(get_snapshot_job))
+            .route("/admin/replication", get(get_replication))
+            .route("/admin/mirror", get(get_mirror))
+            .route(
+                "/admin/refresh-policies",
+                get(list_refresh_policies)
+                    .post(add_refresh_policy)
+                    .delete(remove_refresh_policy),
+            )
+            .route("/admin/schemas", get(list_schemas))
+            .route(
+                "/admin/schemas/{namespace}",
+                get(get_schema).put(put_schema).delete(delete_schema),
+            )
+            .route(
+                "/admin/import/rdb",
+                post(import_rdb).layer(DefaultBodyLimit::disable()),
+            )
+            .route("/events", get(stream_events))
             // Core operations
-            .route("/keys/{key}", get(get_key).post(set_key).delete(delete_key))
+            .route(
+                "/keys/{key}",
+                get(get_key).post(set_key).put(put_key).delete(delete_key),
+            )
             // Key operations
             .route("/keys/{key}/ttl", get(get_ttl))
             .route("/keys/{key}/info", get(get_key_info))
+            .route("/keys/{key}/wait", get(wait_for_key))
             .route("/keys/{key}/expire", post(set_expire))
+            .route("/keys/{key}/expireat", post(set_expire_at))
             .route("/keys/{key}/persist", post(persist_key))
+            .route("/keys/{key}/incr", post(incr_key))
+            .route("/keys/{key}/append", post(append_key))
+            .route("/keys/{key}/strlen", get(get_strlen))
+            .route("/keys/{key}/hash", get(get_hash).post(set_hash_fields))
+            .route(
+                "/keys/{key}/hash/{field}",
+                get(get_hash_field)
+                    .put(set_hash_field)
+                    .delete(delete_hash_field),
+            )
+            .route("/keys/{key}/hash/{field}/incr", post(incr_hash_field))
+            .route("/keys/{key}/members", get(get_members).post(add_members))
+            .route(
+                "/keys/{key}/members/{member}",
+                get(is_member).put(add_member).delete(remove_member),
+            )
+            .route(
+                "/keys/{key}/json",
+                get(get_json).put(put_json).delete(delete_json),
+            )
+            .route(
+                "/keys/{key}/cardinality",
+                get(get_cardinality).post(add_to_cardinality),
+            )
+            .route("/keys/{key}/decr", post(decr_key))
+            .route("/keys/{key}/incrbyfloat", post(incr_key_by_float))
+            .route("/keys/{key}/fields/expire", post(expire_fields))
+            .route("/keys/{key}/fields/ttl", get(get_field_ttls))
+            .route("/keys/{key}/fields/persist", post(persist_fields))
+            .route("/keys/{key}/lock", post(lock_key).delete(unlock_key))
             // Relationship operations
-            .route("/keys/{key}/parent", post(set_parent))
+            .route("/keys/{key}/parent", post(set_parent).delete(unset_parent))
+            .route("/keys/{key}/parents", get(get_parents).post(add_parent))
+            .route("/keys/{key}/parents/{parent}", delete(remove_parent))
             .route("/keys/{key}/children", get(get_children))
+            .route("/keys/{key}/children/reparent", post(reparent_children))
+            .route("/keys/{key}/rename", post(rename_key))
+            .route("/graph", get(export_graph).post(import_graph))
             // Bulk operations
             .route("/keys", get(list_keys).delete(delete_multiple))
+            .route("/keys/scan", get(scan_keys))
             .route("/keys/exists", post(check_exists))
+            .route("/keys/children-count", post(count_children))
+            .route("/keys/expiring", get(list_expiring_keys))
             // Admin operations
             .route("/ping", post(ping))
             .route("/flush", post(flush_all))
-            .with_state(executor)
+            .route("/eval", post(eval))
+            .route("/scripts/kill", post(kill_scripts))
+            .route("/invalidate", post(invalidate))
+            .route("/tags/{tag}", get(get_tagged_keys).delete(invalidate_tag))
+            .route("/batch", post(batch))
+            .layer(from_fn_with_state(executor.clone(), read_your_writes))
+            .layer(axum::middleware::from_fn(trace_requests))
+            .with_state(AppState { executor, locks })
     }
 
-    pub async fn run(executor: Arc<CommandExecutor>, addr: &str) -> Result<(), Error> {
-        let app = Self::create_router(executor);
-        let listener = tokio::net::TcpListener::bind(addr).await?;
+    /// The router for one listener: read-only listeners refuse anything that writes
+    pub fn create_listener_router(
+        executor: Arc<CommandExecutor>,
+        locks: Arc<KeyLocks>,
+        read_only: bool,
+    ) -> Router {
+        let router = Self::create_router(executor, locks);
+        match read_only {
+            true => router.layer(axum::middleware::from_fn(refuse_writes)),
+            false => router,
+        }
+    }
+
+    pub async fn run(
+        executor: Arc<CommandExecutor>,
+        locks: Arc<KeyLocks>,
+        listener: ListenerConfig,
+    ) -> Result<(), Error> {
+        let app = Self::create_listener_router(executor, locks, listener.read_only);
+        let listener = tokio::net::TcpListener::bind(&listener.addr).await?;
         axum::serve(listener, app).await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Cache, Config};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Sends one request and returns the status code and body
+    async fn send(
+        addr: std::net::SocketAddr,
+        method: &str,
+        path: &str,
+        body: &str,
+    ) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: test\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status, body)
+    }
+
+    #[test]
+    fn test_listener_config() {
+        let listener: ListenerConfig = "127.0.0.1:3000".parse().unwrap();
+        assert_eq!(listener.addr, "127.0.0.1:3000");
+        assert!(!listener.read_only);
+        let listener: ListenerConfig = "0.0.0.0:3001=ReadOnly".parse().unwrap();
+        assert_eq!(listener.addr, "0.0.0.0:3001");
+        assert!(listener.read_only);
+
+        assert!(
+            "127.0.0.1:3000=writeonly"
+                .parse::<ListenerConfig>()
+                .is_err()
+        );
+        assert!("=readonly".parse::<ListenerConfig>().is_err());
+        assert!("".parse::<ListenerConfig>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_only_listener() {
+        let executor = Arc::new(CommandExecutor::new(Arc::new(
+            Cache::new(Config::default()),
+        )));
+        executor.execute(Command::Set {
+            key: "a".into(),
+            value: "1".into(),
+            options: SetOptions::default(),
+            concern: None,
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let locks = Arc::new(KeyLocks::new());
+        let app = HttpApiServer::create_listener_router(executor.clone(), locks, true);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        assert_eq!(send(addr, "GET", "/keys/a", "").await.0, 200);
+        assert_eq!(send(addr, "PUT", "/keys/a", "2").await.0, 403);
+        assert_eq!(
+            send(addr, "POST", "/keys/a", r#"{"value":"2"}"#).await.0,
+            403
+        );
+        assert_eq!(send(addr, "DELETE", "/keys/a", "").await.0, 403);
+        assert_eq!(send(addr, "POST", "/flush", "").await.0, 403);
+
+        // POSTs that only read are still served
+        let (status, body) = send(addr, "POST", "/keys/exists", r#"{"keys":["a","b"]}"#).await;
+        assert_eq!((status, body.as_str()), (200, "1"));
+        assert_eq!(send(addr, "POST", "/ping", "null").await.0, 200);
+
+        assert!(matches!(
+            executor.execute(Command::Get { key: "a".into() }),
+            CommandResponse::Bytes(value) if value == b"1"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cascade_delete_checks_dependent_locks() {
+        let executor = Arc::new(CommandExecutor::new(Arc::new(
+            Cache::new(Config::default()),
+        )));
+        for (key, parents) in [("parent", vec![]), ("child", vec!["parent".to_string()])] {
+            executor.execute(Command::Set {
+                key: key.into(),
+                value: "v".into(),
+                options: SetOptions {
+                    parents,
+                    ..Default::default()
+                },
+                concern: None,
+            });
+        }
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let locks = Arc::new(KeyLocks::new());
+        let app = HttpApiServer::create_router(executor.clone(), locks.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        assert_eq!(send(addr, "POST", "/keys/child/lock", "{}").await.0, 200);
+
+        // Every listener sees the lock
+        let other = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let other_addr = other.local_addr().unwrap();
+        let app = HttpApiServer::create_listener_router(executor.clone(), locks, false);
+        tokio::spawn(async move { axum::serve(other, app).await });
+        assert_eq!(send(other_addr, "DELETE", "/keys/child", "").await.0, 423);
+
+        // A locked dependent blocks the cascade, but not a delete of the parent alone
+        let cascade = r#"{"keys":["parent"]}"#;
+        assert_eq!(
+            send(addr, "DELETE", "/keys?cascade=true", cascade).await.0,
+            423
+        );
+        assert_eq!(
+            send(addr, "DELETE", "/keys/parent?cascade=true", "")
+                .await
+                .0,
+            423
+        );
+        assert!(executor.cache.exists("child"));
+        assert_eq!(send(addr, "DELETE", "/keys/parent", "").await.0, 200);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_listings_are_capped() {
+        let config = Config {
+            max_children_results: 2,
+            ..Default::default()
+        };
+        let executor = Arc::new(CommandExecutor::new(Arc::new(Cache::new(config))));
+        for (key, parents) in [
+            ("parent", vec![]),
+            ("a", vec!["parent".to_string()]),
+            ("b", vec!["parent".to_string()]),
+            ("c", vec!["parent".to_string()]),
+        ] {
+            executor.execute(Command::Set {
+                key: key.into(),
+                value: "v".into(),
+                options: SetOptions {
+                    parents,
+                    ..Default::default()
+                },
+                concern: None,
+            });
+        }
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = HttpApiServer::create_router(executor, Arc::new(KeyLocks::new()));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let (status, body) = send(addr, "GET", "/keys/parent/children", r#"{"stream":true}"#).await;
+        assert_eq!(status, 200);
+        assert_eq!(body.matches(r#""key":"#).count(), 2);
+
+        let (status, body) = send(addr, "GET", "/keys?stream=true&limit=3", "").await;
+        assert_eq!(status, 200);
+        assert_eq!(body.lines().filter(|line| line.starts_with('"')).count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_batch_checks_locks() {
+        let executor = Arc::new(CommandExecutor::new(Arc::new(
+            Cache::new(Config::default()),
+        )));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let locks = Arc::new(KeyLocks::new());
+        locks.acquire("a", Duration::from_secs(60)).unwrap();
+        let app = HttpApiServer::create_router(executor.clone(), locks);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        // Same status as a single-key write, and nothing in the batch runs
+        let batch = r#"{"commands":[["SET","b","1"],["SET","a","1"]]}"#;
+        assert_eq!(send(addr, "POST", "/batch", batch).await.0, 423);
+        assert_eq!(send(addr, "PUT", "/keys/a", "1").await.0, 423);
+        assert!(!executor.cache.exists("b"));
+
+        let batch = r#"{"commands":[["SET","b","1"],["GET","a"]]}"#;
+        assert_eq!(send(addr, "POST", "/batch", batch).await.0, 200);
+        assert!(executor.cache.exists("b"));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_key() {
+        let executor = Arc::new(CommandExecutor::new(Arc::new(
+            Cache::new(Config::default()),
+        )));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = HttpApiServer::create_router(executor.clone(), Arc::new(KeyLocks::new()));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let set = |value: &str| {
+            executor.execute(Command::Set {
+                key: "job".into(),
+                value: value.into(),
+                options: SetOptions::default(),
+                concern: None,
+            })
+        };
+
+        // A waiter wakes on the write rather than at its timeout
+        let started = tokio::time::Instant::now();
+        let waiter = tokio::spawn(send(addr, "GET", "/keys/job/wait?timeout=30", ""));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        set("done");
+        let (status, body) = waiter.await.unwrap();
+        assert_eq!((status, body.as_str()), (200, r#""done""#));
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // Waiting past the version already seen times out, until the value changes
+        let path = format!("/keys/job/wait?timeout=1&version={}", key_version("done"));
+        assert_eq!(send(addr, "GET", &path, "").await.0, 204);
+        let waiter = tokio::spawn(async move { send(addr, "GET", &path, "").await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        set("again");
+        assert_eq!(waiter.await.unwrap(), (200, r#""again""#.to_string()));
+
+        // Versions are stable, whichever process computes them
+        assert_eq!(key_version("done"), "dc51fb6761fd6e91");
+    }
+
+    #[tokio::test]
+    async fn test_get_key_metadata() {
+        let executor = Arc::new(CommandExecutor::new(Arc::new(
+            Cache::new(Config::default()),
+        )));
+        for (key, parents, ttl) in [
+            ("parent", vec![], None),
+            (
+                "child",
+                vec!["parent".to_string()],
+                Some(Duration::from_secs(60)),
+            ),
+        ] {
+            executor.execute(Command::Set {
+                key: key.into(),
+                value: "v".into(),
+                options: SetOptions {
+                    ttl,
+                    parents,
+                    ..Default::default()
+                },
+                concern: None,
+            });
+        }
+        let get = |key: &str, metadata| {
+            get_key(
+                Path(key.to_string()),
+                State(executor.clone()),
+                Query(GetKeyQuery { metadata }),
+                HeaderMap::new(),
+            )
+        };
+
+        let response = get("child", None).await.unwrap();
+        let headers = response.headers();
+        let ttl: i64 = headers[TTL_REMAINING_HEADER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((50..=60).contains(&ttl));
+        assert_eq!(headers[PARENT_HEADER], "parent");
+        assert_eq!(headers[VALUE_TYPE_HEADER], "string");
+        assert!(headers.contains_key(ACCESS_COUNT_HEADER));
+
+        // Root keys have no parent header, and TTL -1 without an expiry
+        let response = get("parent", Some(true)).await.unwrap();
+        assert_eq!(response.headers()[TTL_REMAINING_HEADER], "-1");
+        assert!(!response.headers().contains_key(PARENT_HEADER));
+
+        let response = get("child", Some(false)).await.unwrap();
+        for name in [
+            TTL_REMAINING_HEADER,
+            ACCESS_COUNT_HEADER,
+            PARENT_HEADER,
+            VALUE_TYPE_HEADER,
+        ] {
+            assert!(!response.headers().contains_key(name));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_key_ranges() {
+        let executor = Arc::new(CommandExecutor::new(Arc::new(
+            Cache::new(Config::default()),
+        )));
+        let locks = Arc::new(KeyLocks::new());
+        let put = |content_range: Option<&str>, body: &'static [u8]| {
+            let mut headers = HeaderMap::new();
+            if let Some(range) = content_range {
+                headers.insert(header::CONTENT_RANGE, range.parse().unwrap());
+            }
+            put_key(
+                Path("blob".to_string()),
+                State(executor.clone()),
+                State(locks.clone()),
+                headers,
+                Bytes::from_static(body),
+            )
+        };
+        let get = |key: &str, range: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::RANGE, range.parse().unwrap());
+            get_key(
+                Path(key.to_string()),
+                State(executor.clone()),
+                Query(GetKeyQuery { metadata: None }),
+                headers,
+            )
+        };
+
+        assert_eq!(put(None, b"hello\xff").await.unwrap(), "OK");
+        assert_eq!(put(Some("bytes 6-8/*"), b"abc").await.unwrap(), "9");
+        let read = Command::GetRange {
+            key: "blob".into(),
+            start: 0,
+            end: -1,
+        };
+        assert!(matches!(
+            executor.execute(read),
+            CommandResponse::Bytes(value) if value == b"hello\xffabc"
+        ));
+
+        for (range, status, content_range) in [
+            ("bytes=0-4", 206, "bytes 0-4/9"),
+            ("bytes=-3", 206, "bytes 6-8/9"),
+            ("bytes=5-", 206, "bytes 5-8/9"),
+            ("bytes=-0", 416, "bytes */9"),
+            ("bytes=9-", 416, "bytes */9"),
+        ] {
+            let response = get("blob", range).await.unwrap();
+            assert_eq!(response.status().as_u16(), status, "{}", range);
+            assert_eq!(response.headers()[header::CONTENT_RANGE], content_range);
+        }
+        assert!(matches!(
+            get("missing", "bytes=0-1").await,
+            Err(ApiError::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+
+/// Index bits; 2^14 registers give a standard error of about 0.81%, as in Redis
+const PRECISION: u32 = 14;
+pub const REGISTERS: usize = 1 << PRECISION;
+
+/// Cardinality estimate of the members added, without storing them: each member's
+/// hash picks a register, which keeps the longest run of leading zeros seen in the
+/// rest of the hash.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self {
+            registers: vec![0; REGISTERS],
+        }
+    }
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers as stored in snapshots; None unless there are exactly `REGISTERS`
+    pub fn from_registers(registers: Vec<u8>) -> Option<Self> {
+        (registers.len() == REGISTERS).then_some(Self { registers })
+    }
+
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    /// Adds a member, returning whether the estimate may have changed
+    pub fn add(&mut self, member: &str) -> bool {
+        let hash = hash(member.as_bytes());
+        let index = (hash >> (64 - PRECISION)) as usize;
+        // The guard bit caps the run for hashes whose remaining bits are all zero
+        let rank = ((hash << PRECISION) | (1 << (PRECISION - 1))).leading_zeros() as u8 + 1;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Folds in another estimate, so this one counts the union of both
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (register, &theirs) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(theirs);
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&rank| 2f64.powi(-(rank as i32)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+
+        // Linear counting is more accurate while many registers are still empty
+        let empty = self.registers.iter().filter(|&&rank| rank == 0).count();
+        if estimate <= 2.5 * m && empty > 0 {
+            (m * (m / empty as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of_val(self) + self.registers.capacity()
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.registers.shrink_to_fit();
+    }
+}
+
+/// FNV-1a with a 64-bit finalizer to spread short inputs across all bits. Fixed,
+/// unlike `DefaultHasher`, so estimates merge across nodes and restarts.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_and_merge() {
+        let mut visitors = HyperLogLog::new();
+        assert_eq!(visitors.count(), 0);
+        assert!(visitors.add("user:1"));
+        assert!(!visitors.add("user:1"));
+        assert_eq!(visitors.count(), 1);
+
+        for i in 0..100_000 {
+            visitors.add(&format!("user:{}", i));
+        }
+        let error = (visitors.count() as f64 - 100_000.0).abs() / 100_000.0;
+        assert!(error < 0.03, "estimate off by {:.2}%", error * 100.0);
+
+        let mut other = HyperLogLog::new();
+        for i in 50_000..150_000 {
+            other.add(&format!("user:{}", i));
+        }
+        visitors.merge(&other);
+        let error = (visitors.count() as f64 - 150_000.0).abs() / 150_000.0;
+        assert!(error < 0.03, "merged estimate off by {:.2}%", error * 100.0);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::aggregator::StatsAggregator;
+use crate::cache::Cache;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const SECTIONS: [&str; 5] = ["server", "clients", "memory", "stats", "keyspace"];
+
+/// The INFO report: `# Section` headers followed by `field:value` lines, as Redis
+/// clients expect. No sections, "default", "all" or "everything" selects them all;
+/// unknown ones are left out.
+pub fn render(cache: &Cache, aggregator: Option<&StatsAggregator>, sections: &[String]) -> String {
+    let all = sections.is_empty()
+        || sections.iter().any(|section| {
+            ["default", "all", "everything"]
+                .iter()
+                .any(|s| section.eq_ignore_ascii_case(s))
+        });
+    let mut out = String::new();
+    for section in SECTIONS {
+        if !all && !sections.iter().any(|s| s.eq_ignore_ascii_case(section)) {
+            continue;
+        }
+        if !out.is_empty() {
+            out.push_str("\r\n");
+        }
+        let mut title = section.to_string();
+        title[..1].make_ascii_uppercase();
+        write!(out, "# {}\r\n", title).unwrap();
+        let fields = match section {
+            "server" => server(cache),
+            "clients" => clients(cache),
+            "memory" => memory(cache),
+            "stats" => stats(cache),
+            _ => keyspace(cache, aggregator),
+        };
+        for (field, value) in fields {
+            write!(out, "{}:{}\r\n", field, value).unwrap();
+        }
+    }
+    out
+}
+
+fn server(cache: &Cache) -> Vec<(&'static str, String)> {
+    let uptime = cache.uptime().as_secs();
+    vec![
+        (
+            "dashdotcache_version",
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+        ("os", std::env::consts::OS.to_string()),
+        ("arch", std::env::consts::ARCH.to_string()),
+        ("process_id", std::process::id().to_string()),
+        ("uptime_in_seconds", uptime.to_string()),
+        ("uptime_in_days", (uptime / 86_400).to_string()),
+    ]
+}
+
+fn clients(cache: &Cache) -> Vec<(&'static str, String)> {
+    let stats = cache.stats();
+    vec![(
+        "connected_clients",
+        stats.connected_clients.load(Ordering::Relaxed).to_string(),
+    )]
+}
+
+fn memory(cache: &Cache) -> Vec<(&'static str, String)> {
+    let config = cache.config();
+    let used = cache.memory_usage();
+    let max = config.max_memory.unwrap_or(0);
+    vec![
+        ("used_memory", used.to_string()),
+        ("used_memory_human", human_bytes(used)),
+        (
+            "used_memory_pinned",
+            cache
+                .stats()
+                .pinned_memory
+                .load(Ordering::Relaxed)
+                .to_string(),
+        ),
+        ("maxmemory", max.to_string()),
+        ("maxmemory_human", human_bytes(max)),
+        ("maxmemory_policy", config.eviction_policy.to_string()),
+    ]
+}
+
+fn stats(cache: &Cache) -> Vec<(&'static str, String)> {
+    let stats = cache.stats();
+    let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed).to_string();
+    vec![
+        ("total_connections_received", load(&stats.total_connections)),
+        ("total_commands_processed", load(&stats.commands_processed)),
+        ("total_net_input_bytes", load(&stats.net_input_bytes)),
+        ("total_net_output_bytes", load(&stats.net_output_bytes)),
+        ("keyspace_hits", load(&stats.hits)),
+        ("keyspace_misses", load(&stats.misses)),
+        ("expired_keys", load(&stats.expired_keys)),
+        ("orphaned_keys", load(&stats.orphaned_keys)),
+        ("evicted_keys", load(&stats.evicted_keys)),
+    ]
+}
+
+/// One line for the single keyspace, left out while it's empty. Keys with a TTL
+/// are only known from the aggregator's last pass, scaled up if it sampled.
+fn keyspace(cache: &Cache, aggregator: Option<&StatsAggregator>) -> Vec<(&'static str, String)> {
+    let keys = cache.len();
+    if keys == 0 {
+        return Vec::new();
+    }
+    let mut line = format!("keys={}", keys);
+    if let Some(latest) = aggregator
+        .and_then(StatsAggregator::latest)
+        .filter(|latest| latest.keyspace.sampled > 0)
+    {
+        let report = &latest.keyspace;
+        let untimed = report.ttl.get("none").copied().unwrap_or(0);
+        let expiring = (report.sampled - untimed) as f64 / report.sampled as f64;
+        write!(
+            line,
+            ",expires={}",
+            (expiring * keys as f64).round() as usize
+        )
+        .unwrap();
+    }
+    vec![("db0", line)]
+}
+
+/// Redis style sizes: 1023B, 1.50K, 2.00M
+fn human_bytes(bytes: usize) -> String {
+    let units = [("G", 1 << 30), ("M", 1 << 20), ("K", 1 << 10)];
+    units
+        .iter()
+        .find(|&&(_, size)| bytes >= size)
+        .map(|&(unit, size)| format!("{:.2}{}", bytes as f64 / size as f64, unit))
+        .unwrap_or_else(|| format!("{}B", bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Config, SetOptions, Value};
+
+    #[test]
+    fn test_info_sections() {
+        let cache = Cache::new(Config::default());
+        cache
+            .set(
+                "k".to_string(),
+                Value::String("v".to_string()),
+                SetOptions::default(),
+            )
+            .unwrap();
+        cache.get("k");
+        cache.get("missing");
+
+        let all = render(&cache, None, &[]);
+        for header in ["# Server", "# Clients", "# Memory", "# Stats", "# Keyspace"] {
+            assert!(all.contains(header), "{} missing", header);
+        }
+        assert!(all.contains("keyspace_hits:1\r\n"));
+        assert!(all.contains("keyspace_misses:1\r\n"));
+        assert!(all.contains("db0:keys=1\r\n"));
+
+        let memory = render(&cache, None, &["MEMORY".to_string()]);
+        assert!(memory.starts_with("# Memory\r\nused_memory:"));
+        assert!(!memory.contains("# Stats"));
+        assert_eq!(render(&cache, None, &["nope".to_string()]), "");
+        assert_eq!(human_bytes(1536), "1.50K");
+    }
+}
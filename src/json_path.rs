@@ -0,0 +1,195 @@
+use serde_json::Value as Json;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Key(String),
+    Index(i64), // Negative counts from the end
+}
+
+/// A definite JSONPath: `$`, `.name`, `['name']` and `[index]` steps, also accepted
+/// in the legacy form without the leading `$` (`.a.b`, `a.b`). Wildcards, recursive
+/// descent and filters aren't supported, so a path names at most one value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathError(String);
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<Self, PathError> {
+        let invalid = || PathError(format!("invalid JSON path '{}'", path));
+        let mut segments = Vec::new();
+        if path == "." {
+            return Ok(Self { segments });
+        }
+        let mut rest = path.strip_prefix('$').unwrap_or(path);
+        // Legacy paths may start with a bare name
+        if !rest.is_empty() && !rest.starts_with(['.', '[']) {
+            let end = rest.find(['.', '[']).unwrap_or(rest.len());
+            segments.push(Segment::Key(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                let name = &after[..end];
+                if name.is_empty() || name == "*" {
+                    return Err(invalid());
+                }
+                segments.push(Segment::Key(name.to_string()));
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let end = after.find(']').ok_or_else(invalid)?;
+                let inner = after[..end].trim();
+                let segment = match inner.as_bytes().first() {
+                    Some(b'\'' | b'"') if inner.len() >= 2 && inner.ends_with(&inner[..1]) => {
+                        Segment::Key(inner[1..inner.len() - 1].to_string())
+                    }
+                    _ => Segment::Index(inner.parse().map_err(|_| invalid())?),
+                };
+                segments.push(segment);
+                rest = &after[end + 1..];
+            } else {
+                return Err(invalid());
+            }
+        }
+        Ok(Self { segments })
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub fn get<'a>(&self, root: &'a Json) -> Option<&'a Json> {
+        self.segments
+            .iter()
+            .try_fold(root, |value, segment| step(value, segment))
+    }
+
+    /// Replaces the value at the path, or adds it as a new member of an existing
+    /// object. Arrays aren't grown; an index past the end is an error.
+    pub fn set(&self, root: &mut Json, new: Json) -> Result<(), PathError> {
+        let Some((last, parents)) = self.segments.split_last() else {
+            *root = new;
+            return Ok(());
+        };
+        let missing = || PathError("path does not exist".to_string());
+        let parent = parents
+            .iter()
+            .try_fold(root, |value, segment| step_mut(value, segment))
+            .ok_or_else(missing)?;
+        match (parent, last) {
+            (Json::Object(map), Segment::Key(key)) => {
+                map.insert(key.clone(), new);
+                Ok(())
+            }
+            (Json::Array(items), Segment::Index(index)) => {
+                let slot = resolve(*index, items.len()).ok_or_else(missing)?;
+                items[slot] = new;
+                Ok(())
+            }
+            _ => Err(missing()),
+        }
+    }
+
+    /// Removes the value at the path, returning whether there was one. The root
+    /// can't be removed this way; callers delete the key instead.
+    pub fn delete(&self, root: &mut Json) -> bool {
+        let Some((last, parents)) = self.segments.split_last() else {
+            return false;
+        };
+        let parent = parents
+            .iter()
+            .try_fold(root, |value, segment| step_mut(value, segment));
+        match (parent, last) {
+            (Some(Json::Object(map)), Segment::Key(key)) => map.remove(key).is_some(),
+            (Some(Json::Array(items)), Segment::Index(index)) => {
+                match resolve(*index, items.len()) {
+                    Some(slot) => {
+                        items.remove(slot);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+fn resolve(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    usize::try_from(index).ok().filter(|&i| i < len)
+}
+
+fn step<'a>(value: &'a Json, segment: &Segment) -> Option<&'a Json> {
+    match (value, segment) {
+        (Json::Object(map), Segment::Key(key)) => map.get(key),
+        (Json::Array(items), Segment::Index(index)) => {
+            resolve(*index, items.len()).map(|i| &items[i])
+        }
+        _ => None,
+    }
+}
+
+fn step_mut<'a>(value: &'a mut Json, segment: &Segment) -> Option<&'a mut Json> {
+    match (value, segment) {
+        (Json::Object(map), Segment::Key(key)) => map.get_mut(key),
+        (Json::Array(items), Segment::Index(index)) => {
+            resolve(*index, items.len()).map(|i| &mut items[i])
+        }
+        _ => None,
+    }
+}
+
+/// Estimated heap footprint of a JSON document
+pub fn memory_usage(value: &Json) -> usize {
+    std::mem::size_of::<Json>()
+        + match value {
+            Json::String(s) => s.capacity(),
+            Json::Array(items) => items.iter().map(memory_usage).sum(),
+            Json::Object(map) => map
+                .iter()
+                .map(|(key, value)| key.capacity() + memory_usage(value))
+                .sum(),
+            Json::Null | Json::Bool(_) | Json::Number(_) => 0,
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_paths() {
+        let path = |p| JsonPath::parse(p).unwrap();
+        let mut doc = json!({"user": {"name": "ada", "tags": ["a", "b"]}});
+
+        assert!(path("$").is_root() && path(".").is_root());
+        assert_eq!(path("$.user.name").get(&doc), Some(&json!("ada")));
+        assert_eq!(path("user.tags[-1]").get(&doc), Some(&json!("b")));
+        assert_eq!(path("$['user'].tags[0]").get(&doc), Some(&json!("a")));
+        assert_eq!(path("$.user.age").get(&doc), None);
+        assert!(JsonPath::parse("$..name").is_err());
+        assert!(JsonPath::parse("$.user[").is_err());
+
+        path("$.user.age").set(&mut doc, json!(36)).unwrap();
+        path("$.user.tags[0]").set(&mut doc, json!("z")).unwrap();
+        assert!(path("$.user.tags[5]").set(&mut doc, json!(1)).is_err());
+        assert!(path("$.missing.x").set(&mut doc, json!(1)).is_err());
+        assert!(path(".user.name").delete(&mut doc));
+        assert!(!path(".user.name").delete(&mut doc));
+        assert_eq!(doc, json!({"user": {"age": 36, "tags": ["z", "b"]}}));
+    }
+}
@@ -0,0 +1,279 @@
+use crate::cache::{Cache, EntryId, NO_NAMESPACE, namespace_of};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::time::{Duration, Instant};
+
+/// Upper bounds (seconds) of the TTL histogram buckets; keys past the last go in "inf"
+const TTL_BUCKETS: &[(u64, &str)] = &[(60, "1m"), (3600, "1h"), (86400, "1d"), (604800, "7d")];
+
+/// Number of largest keys kept in a report
+pub const BIG_KEYS: usize = 10;
+
+/// Upper bounds of the dependency fan-out buckets (direct children per parent)
+const FANOUT_BUCKETS: &[(usize, &str)] = &[(1, "1"), (10, "10"), (100, "100"), (1000, "1000")];
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Breakdown {
+    pub keys: usize,
+    pub memory: usize,
+}
+
+/// Distribution of keys over a sampled scan. Counts are of sampled keys; scale by
+/// `total_keys / sampled` for an estimate of the whole keyspace.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct KeyspaceReport {
+    pub total_keys: usize,
+    pub sampled: usize,
+    pub complete: bool, // Every key was visited within the budget
+    pub elapsed_ms: u64,
+    pub by_type: BTreeMap<&'static str, Breakdown>,
+    pub ttl: BTreeMap<&'static str, usize>,
+    pub fanout: BTreeMap<&'static str, usize>,
+    pub namespaces: BTreeMap<String, Breakdown>,
+    pub big_keys: Vec<BigKey>, // Largest sampled keys, biggest first
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct BigKey {
+    pub memory: usize,
+    pub key: String,
+    #[serde(rename = "type")]
+    pub type_name: &'static str,
+}
+
+impl KeyspaceReport {
+    /// Scans up to `samples` keys, stopping early once `budget` is spent
+    pub fn collect(cache: &Cache, samples: usize, budget: Duration) -> Self {
+        let start = Instant::now();
+        let now = Instant::now();
+        let delimiter = cache.config().namespace_delimiter;
+        let mut report = KeyspaceReport {
+            total_keys: cache.len(),
+            ..Default::default()
+        };
+        let mut children_per_parent: HashMap<EntryId, usize> = HashMap::new();
+        let mut big_keys = BinaryHeap::with_capacity(BIG_KEYS + 1);
+
+        report.sampled = cache.sample_entries(samples, budget, |key, entry| {
+            let memory = key.len() + entry.memory_usage();
+
+            let by_type = report.by_type.entry(entry.value.type_name()).or_default();
+            by_type.keys += 1;
+            by_type.memory += memory;
+
+            let ttl_bucket = match &entry.ttl {
+                None => "none",
+                Some(ttl) => {
+                    let secs = ttl.expires_at.saturating_duration_since(now).as_secs();
+                    TTL_BUCKETS
+                        .iter()
+                        .find(|(bound, _)| secs < *bound)
+                        .map_or("inf", |(_, label)| *label)
+                }
+            };
+            *report.ttl.entry(ttl_bucket).or_default() += 1;
+
+            for parent in entry.live_parents() {
+                *children_per_parent.entry(parent).or_default() += 1;
+            }
+
+            let namespace = namespace_of(key, delimiter).unwrap_or(NO_NAMESPACE);
+            let namespace = report.namespaces.entry(namespace.to_string()).or_default();
+            namespace.keys += 1;
+            namespace.memory += memory;
+
+            // Min-heap of the largest keys seen so far
+            let smallest = big_keys
+                .peek()
+                .map(|Reverse(big): &Reverse<BigKey>| big.memory);
+            if big_keys.len() < BIG_KEYS || smallest.is_some_and(|smallest| memory > smallest) {
+                big_keys.push(Reverse(BigKey {
+                    memory,
+                    key: key.to_string(),
+                    type_name: entry.value.type_name(),
+                }));
+                if big_keys.len() > BIG_KEYS {
+                    big_keys.pop();
+                }
+            }
+        });
+        report.big_keys = big_keys
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(big)| big)
+            .collect();
+
+        for children in children_per_parent.into_values() {
+            let bucket = FANOUT_BUCKETS
+                .iter()
+                .find(|(bound, _)| children <= *bound)
+                .map_or("inf", |(_, label)| *label);
+            *report.fanout.entry(bucket).or_default() += 1;
+        }
+
+        report.complete = report.sampled >= report.total_keys;
+        report.elapsed_ms = start.elapsed().as_millis() as u64;
+        report
+    }
+}
+
+/// One key of a `KeySample`
+#[derive(Debug, Clone, Serialize)]
+pub struct SampledKey {
+    pub key: String,
+    #[serde(rename = "type")]
+    pub type_name: &'static str,
+    pub memory: usize,
+    pub ttl_ms: Option<u64>, // None without a TTL
+    pub idle_ms: u64,        // Since last read or write
+}
+
+/// Uniform random sample of keys. Uniform over the whole keyspace when `complete`;
+/// otherwise over the shards scanned before the budget ran out.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct KeySample {
+    pub total_keys: usize,
+    pub scanned: usize,
+    pub complete: bool,
+    pub elapsed_ms: u64,
+    pub keys: Vec<SampledKey>,
+}
+
+impl KeySample {
+    /// Reservoir-samples `n` keys from a scan bounded by `budget`
+    pub fn collect(cache: &Cache, n: usize, budget: Duration) -> Self {
+        let start = Instant::now();
+        let mut sample = KeySample {
+            total_keys: cache.len(),
+            ..Default::default()
+        };
+        let mut seen = 0;
+        sample.scanned = cache.sample_entries(usize::MAX, budget, |key, entry| {
+            let slot = if sample.keys.len() < n {
+                sample.keys.len()
+            } else {
+                rand::random_range(0..=seen)
+            };
+            seen += 1;
+            if slot >= n {
+                return;
+            }
+            let key = SampledKey {
+                key: key.to_string(),
+                type_name: entry.value.type_name(),
+                memory: key.len() + entry.memory_usage(),
+                ttl_ms: entry
+                    .ttl
+                    .as_ref()
+                    .map(|ttl| ttl.remaining().unwrap_or_default().as_millis() as u64),
+                idle_ms: entry.last_accessed.elapsed().as_millis() as u64,
+            };
+            if slot == sample.keys.len() {
+                sample.keys.push(key);
+            } else {
+                sample.keys[slot] = key;
+            }
+        });
+        sample.complete = sample.scanned >= sample.total_keys;
+        sample.elapsed_ms = start.elapsed().as_millis() as u64;
+        sample
+    }
+}
+
+/// What makes a key cold: idle longer than `idle_gt` and, if set, an access count
+/// (decayed as LFU ranks it) below `freq_lt`
+#[derive(Debug, Clone, Copy)]
+pub struct ColdKeyFilter {
+    pub idle_gt: Duration,
+    pub freq_lt: Option<u64>,
+    pub limit: usize, // Cold keys listed; all of them are counted
+}
+
+/// One key of a `ColdKeys` listing
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct ColdKey {
+    pub idle_ms: u64,
+    pub key: String,
+    #[serde(rename = "type")]
+    pub type_name: &'static str,
+    pub memory: usize,
+    pub frequency: u64,
+    pub ttl_ms: Option<u64>, // None without a TTL
+}
+
+/// Keys split into warm and cold by access recency and frequency, with the idlest
+/// cold keys listed as candidates for cleanup or tighter TTLs. Counts cover the
+/// keys scanned, which is every key when `complete`.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ColdKeys {
+    pub total_keys: usize,
+    pub scanned: usize,
+    pub complete: bool,
+    pub elapsed_ms: u64,
+    pub warm: Breakdown,
+    pub cold: Breakdown,
+    pub keys: Vec<ColdKey>, // Idlest first
+}
+
+impl ColdKeys {
+    pub fn collect(cache: &Cache, filter: ColdKeyFilter, budget: Duration) -> Self {
+        let start = Instant::now();
+        let now = Instant::now();
+        let lfu = &cache.config().lfu;
+        let mut report = ColdKeys {
+            total_keys: cache.len(),
+            ..Default::default()
+        };
+        // Min-heap by idle time of the idlest cold keys so far
+        let mut idlest = BinaryHeap::with_capacity(filter.limit + 1);
+
+        report.scanned = cache.sample_entries(usize::MAX, budget, |key, entry| {
+            let memory = key.len() + entry.memory_usage();
+            let idle = now.saturating_duration_since(entry.last_accessed);
+            let frequency = entry.frequency(now, lfu);
+            let cold =
+                idle > filter.idle_gt && filter.freq_lt.is_none_or(|limit| frequency < limit);
+            let bucket = if cold {
+                &mut report.cold
+            } else {
+                &mut report.warm
+            };
+            bucket.keys += 1;
+            bucket.memory += memory;
+            if !cold || filter.limit == 0 {
+                return;
+            }
+
+            let idle_ms = idle.as_millis() as u64;
+            let least_idle = idlest
+                .peek()
+                .map(|Reverse(cold): &Reverse<ColdKey>| cold.idle_ms);
+            if idlest.len() < filter.limit || least_idle.is_some_and(|least| idle_ms > least) {
+                idlest.push(Reverse(ColdKey {
+                    idle_ms,
+                    key: key.to_string(),
+                    type_name: entry.value.type_name(),
+                    memory,
+                    frequency,
+                    ttl_ms: entry
+                        .ttl
+                        .as_ref()
+                        .map(|ttl| ttl.remaining().unwrap_or_default().as_millis() as u64),
+                }));
+                if idlest.len() > filter.limit {
+                    idlest.pop();
+                }
+            }
+        });
+        report.keys = idlest
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(cold)| cold)
+            .collect();
+
+        report.complete = report.scanned >= report.total_keys;
+        report.elapsed_ms = start.elapsed().as_millis() as u64;
+        report
+    }
+}
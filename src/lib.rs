@@ -1,5 +1,46 @@
+pub mod aggregator;
+pub mod aof;
+pub mod bus;
 pub mod cache;
 pub mod cache_errors;
+pub mod command_parser;
+pub mod command_table;
+pub mod contention;
+pub mod counters;
+pub mod dashboard;
+pub mod diagnostics;
+pub mod events;
+pub mod eviction;
 pub mod executor;
+pub mod expiry;
+pub mod guardrails;
 pub mod http_api;
+pub mod hyperloglog;
+pub mod info;
+pub mod json_path;
+pub mod keyspace;
+pub mod locks;
+pub mod memcached;
+pub mod metrics;
+pub mod middleware;
+pub mod mirror;
+pub mod notifications;
+pub mod oplog;
+pub mod plugins;
+pub mod primary;
+pub mod pubsub;
+pub mod rdb;
+pub mod refresh;
+pub mod replay;
+pub mod replica;
 pub mod resp_api;
+pub mod response_cache;
+pub mod runtime;
+pub mod schema;
+pub mod scripting;
+pub mod shadow;
+pub mod shards;
+pub mod snapshot;
+pub mod stream;
+pub mod tombstone;
+pub mod trace;
@@ -0,0 +1,123 @@
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Acquisitions between sweeps for expired locks on keys nobody touched again
+const PRUNE_EVERY: u64 = 1024;
+
+/// Advisory per-key locks for HTTP clients doing read-modify-write.
+///
+/// A held lock only gates writes that go through the HTTP API: those must present
+/// the lock's token until it is released or its TTL runs out. Expired locks are
+/// dropped the next time the key is touched, or by a sweep every `PRUNE_EVERY`
+/// acquisitions.
+#[derive(Debug, Default)]
+pub struct KeyLocks {
+    locks: DashMap<String, Lock>,
+    acquisitions: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Lock {
+    token: String,
+    expires_at: Instant,
+}
+
+impl Lock {
+    fn is_held(&self) -> bool {
+        Instant::now() < self.expires_at
+    }
+}
+
+impl KeyLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes the lock, returning its token, or None if someone else holds it
+    pub fn acquire(&self, key: &str, ttl: Duration) -> Option<String> {
+        if self.acquisitions.fetch_add(1, Ordering::Relaxed) % PRUNE_EVERY == PRUNE_EVERY - 1 {
+            self.prune();
+        }
+        let lock = Lock {
+            token: format!("{:032x}", rand::random::<u128>()),
+            expires_at: Instant::now() + ttl,
+        };
+        let token = lock.token.clone();
+
+        match self.locks.entry(key.to_string()) {
+            Entry::Occupied(mut held) if !held.get().is_held() => {
+                held.insert(lock);
+            }
+            Entry::Occupied(_) => return None,
+            Entry::Vacant(vacant) => {
+                vacant.insert(lock);
+            }
+        }
+        Some(token)
+    }
+
+    /// Releases the lock if the token matches. A lapsed lock is dropped too, but
+    /// reports false: it was no longer held.
+    pub fn release(&self, key: &str, token: &str) -> bool {
+        self.locks
+            .remove_if(key, |_, lock| lock.token == token)
+            .is_some_and(|(_, lock)| lock.is_held())
+    }
+
+    /// Drops every lock whose TTL has run out, returning how many
+    pub fn prune(&self) -> usize {
+        let before = self.locks.len();
+        self.locks.retain(|_, lock| lock.is_held());
+        before.saturating_sub(self.locks.len())
+    }
+
+    /// Whether a write with this token (if any) may proceed
+    pub fn permits(&self, key: &str, token: Option<&str>) -> bool {
+        let Some(lock) = self.locks.get(key) else {
+            return true;
+        };
+        if lock.is_held() {
+            return token == Some(lock.token.as_str());
+        }
+
+        drop(lock);
+        self.locks.remove_if(key, |_, lock| !lock.is_held());
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_locks() {
+        let locks = KeyLocks::new();
+        let token = locks.acquire("k", Duration::from_secs(60)).unwrap();
+
+        assert!(locks.acquire("k", Duration::from_secs(60)).is_none());
+        assert!(!locks.permits("k", None));
+        assert!(!locks.permits("k", Some("wrong")));
+        assert!(locks.permits("k", Some(&token)));
+        assert!(locks.permits("other", None));
+
+        assert!(!locks.release("k", "wrong"));
+        assert!(locks.release("k", &token));
+        assert!(locks.permits("k", None));
+
+        // An expired lock no longer blocks writers or new lockers
+        locks.acquire("k", Duration::ZERO).unwrap();
+        assert!(locks.permits("k", None));
+        assert!(locks.acquire("k", Duration::from_secs(60)).is_some());
+
+        // Lapsed locks on keys nobody touches again are swept
+        let lapsed = locks.acquire("lapsed", Duration::ZERO).unwrap();
+        assert!(!locks.release("lapsed", &lapsed));
+        locks.acquire("idle", Duration::ZERO).unwrap();
+        assert_eq!(locks.locks.len(), 2);
+        assert_eq!(locks.prune(), 1);
+        assert_eq!(locks.locks.len(), 1);
+    }
+}
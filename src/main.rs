@@ -1,15 +1,397 @@
+use dashdotcache::aggregator::{AggregatorConfig, StatsAggregator};
+use dashdotcache::aof::{Aof, AofConfig};
+use dashdotcache::bus::{BusConfig, InvalidationBus};
 use dashdotcache::cache::{Cache, Config};
+use dashdotcache::counters::{CounterBuffer, CounterConfig};
+use dashdotcache::dashboard;
+use dashdotcache::diagnostics::{self, Severity};
 use dashdotcache::executor::CommandExecutor;
-use dashdotcache::http_api::HttpApiServer;
+use dashdotcache::guardrails::{self, GuardrailConfig, Guardrails};
+use dashdotcache::http_api::{HttpApiServer, ListenerConfig};
+use dashdotcache::locks::KeyLocks;
+use dashdotcache::memcached;
+use dashdotcache::middleware::{AuditLog, CommandMetrics};
+use dashdotcache::mirror::{MirrorFilter, Mirroring};
+use dashdotcache::notifications::KeyspaceNotifier;
+use dashdotcache::oplog::OpLog;
+use dashdotcache::plugins::{Plugins, WasmLimits};
+use dashdotcache::primary::{DEFAULT_BACKLOG_SIZE, Primary};
+use dashdotcache::rdb;
+use dashdotcache::refresh::RefreshAhead;
+use dashdotcache::replay::{self as replays, ReplaySource, ReplaySpeed};
+use dashdotcache::replica::Replication;
 use dashdotcache::resp_api::RespServer;
+use dashdotcache::response_cache::{ResponseCache, ResponseCacheConfig};
+use dashdotcache::runtime::{RuntimeConfig, Runtimes};
+use dashdotcache::schema::Schemas;
+use dashdotcache::scripting::ScriptLimits;
+use dashdotcache::shadow::{ShadowConfig, Shadowing};
+use dashdotcache::snapshot::{self, DEFAULT_SNAPSHOT_PATH, LoadReport, SnapshotInfo, SnapshotJobs};
+use std::io;
+use std::path::Path;
 use std::sync::Arc;
+use tokio::runtime::Handle;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("check-config") => return check_config(),
+        Some("diff") => return diff_snapshots(&args[2..]),
+        Some("replay") => return replay(&args[2..]),
+        Some("grafana-dashboard") => return grafana_dashboard(),
+        _ => {}
+    }
+
+    let runtimes = Runtimes::build(&RuntimeConfig::from_env())?;
+    runtimes
+        .requests
+        .block_on(serve(runtimes.background_handle()))
+}
+
+/// `dashdotcache grafana-dashboard`: print a Grafana dashboard for the exported metrics
+fn grafana_dashboard() -> Result<(), Box<dyn std::error::Error>> {
+    let exposition = dashboard::sample_exposition();
+    let dashboard = dashboard::grafana_dashboard(&exposition);
+    println!("{}", serde_json::to_string_pretty(&dashboard)?);
+    Ok(())
+}
+
+/// `dashdotcache check-config`: print every finding, failing if any is an error
+fn check_config() -> Result<(), Box<dyn std::error::Error>> {
+    let findings = diagnostics::run(&Config::from_env());
+    for finding in &findings {
+        println!("{}", finding);
+    }
+    let errors = findings
+        .iter()
+        .filter(|f| f.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        return Err(format!("{} configuration error(s)", errors).into());
+    }
+    println!("Configuration OK ({} warning(s))", findings.len());
+    Ok(())
+}
+
+/// `dashdotcache diff [--json] <before> <after>`: what changed between two snapshots
+fn diff_snapshots(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let json = args.iter().any(|arg| arg == "--json");
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--json").collect();
+    let [before, after] = paths[..] else {
+        return Err("usage: dashdotcache diff [--json] <before> <after>".into());
+    };
+    let diff = snapshot::diff(&std::fs::read(before)?, &std::fs::read(after)?)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+        return Ok(());
+    }
+
+    for (label, deltas) in [
+        ("added", &diff.added),
+        ("removed", &diff.removed),
+        ("changed", &diff.changed),
+    ] {
+        for delta in deltas {
+            println!(
+                "{:<8} {} ({}) {} -> {} bytes",
+                label, delta.key, delta.type_name, delta.memory_before, delta.memory_after
+            );
+        }
+    }
+    println!(
+        "{} added, {} removed, {} changed, {} unchanged; memory {} -> {} bytes ({:+})",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.changed.len(),
+        diff.unchanged,
+        diff.memory_before,
+        diff.memory_after,
+        diff.memory_delta()
+    );
+    Ok(())
+}
+
+/// `dashdotcache replay --from aof|oplog --target <addr> [--speed 2x|max] [--path <p>]`:
+/// sends recorded writes to another instance. The AOF is read from DASHDOT_AOF_DIR
+/// and the oplog from the snapshot file unless `--path` says otherwise.
+fn replay(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    const USAGE: &str =
+        "usage: dashdotcache replay --from aof|oplog --target <addr> [--speed 2x|max] [--path <p>]";
+    let mut options = std::collections::HashMap::new();
+    for pair in args.chunks(2) {
+        match pair {
+            [flag, value] if flag.starts_with("--") => options.insert(&flag[2..], value.clone()),
+            _ => return Err(USAGE.into()),
+        };
+    }
+    let (Some(source), Some(target)) = (options.get("from"), options.get("target")) else {
+        return Err(USAGE.into());
+    };
+    let source: ReplaySource = source.parse()?;
+    let speed: ReplaySpeed = match options.get("speed") {
+        Some(speed) => speed.parse()?,
+        None => ReplaySpeed::default(),
+    };
+    let path = match (options.get("path"), source) {
+        (Some(path), _) => path.clone(),
+        (None, ReplaySource::Aof) => std::env::var("DASHDOT_AOF_DIR")
+            .map_err(|_| "set --path or DASHDOT_AOF_DIR to replay the AOF")?,
+        (None, ReplaySource::OpLog) => {
+            std::env::var("DASHDOT_SNAPSHOT_PATH").unwrap_or_else(|_| DEFAULT_SNAPSHOT_PATH.into())
+        }
+    };
+
+    let mutations = replays::read(source, Path::new(&path))?;
+    println!(
+        "Replaying {} mutations from the {} at {} to {} at {} speed",
+        mutations.len(),
+        source,
+        path,
+        target,
+        speed
+    );
+    let summary = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(replays::replay(&mutations, target, speed))?;
+    println!(
+        "Sent {} mutations in {} ms, {} failed",
+        summary.sent, summary.elapsed_ms, summary.failed
+    );
+    Ok(())
+}
+
+async fn serve(background: Handle) -> Result<(), Box<dyn std::error::Error>> {
     println!("Starting Dashdotcache!");
+    guardrails::install_panic_hook();
 
-    let cache = Arc::new(Cache::new(Config::default()));
-    let executor = Arc::new(CommandExecutor::new(cache));
+    let config = Config::from_env();
+    for finding in diagnostics::run(&config) {
+        eprintln!("{}", finding);
+    }
+    let cache = Arc::new(Cache::new(config));
+    let snapshot_path =
+        std::env::var("DASHDOT_SNAPSHOT_PATH").unwrap_or_else(|_| DEFAULT_SNAPSHOT_PATH.into());
+    let aof_dir = std::env::var("DASHDOT_AOF_DIR").ok();
+    if let Some(report) = load_snapshot(&cache, Path::new(&snapshot_path), aof_dir.is_some())? {
+        println!(
+            "Loaded {} keys from {} ({} expired while down)",
+            report.loaded, snapshot_path, report.expired
+        );
+    }
+    // One-off migration: copy every key from a running memcached before serving
+    if let Ok(addr) = std::env::var("DASHDOT_IMPORT_MEMCACHED") {
+        let report = memcached::import(&addr, &cache).await?;
+        println!(
+            "Imported {} keys from memcached at {} ({} expired, {} gone, {} rejected)",
+            report.imported, addr, report.expired, report.missing, report.rejected
+        );
+    }
+    // Same for a Redis dump file
+    if let Ok(path) = std::env::var("DASHDOT_IMPORT_RDB") {
+        let report = rdb::import(&cache, &std::fs::read(&path)?)?;
+        println!(
+            "Imported {} keys from {} ({} expired, {} unsupported, {} rejected)",
+            report.imported, path, report.expired, report.unsupported, report.rejected
+        );
+    }
+    let aggregator = Arc::new(StatsAggregator::new(
+        cache.clone(),
+        AggregatorConfig::default(),
+    ));
+    // Watch the process itself: resident memory against DASHDOT_MAX_RSS, handled per
+    // DASHDOT_RSS_ACTION, and open descriptors against the limit. With
+    // DASHDOT_RESTART_TASKS, supervised background tasks come back after a panic.
+    let guardrail_config = GuardrailConfig::from_env();
+    let restart = guardrail_config.restart_tasks;
+    let guardrails = Arc::new(Guardrails::new(cache.clone(), guardrail_config));
+    background.spawn(guardrails::supervise("guardrails", restart, {
+        let guardrails = guardrails.clone();
+        move || guardrails.clone().run()
+    }));
+    background.spawn(guardrails::supervise("aggregator", restart, {
+        let aggregator = aggregator.clone();
+        move || aggregator.clone().run()
+    }));
+    // Remove expired keys every DASHDOT_TTL_CLEANUP_MS, rather than only on access
+    let cleanup_interval = cache.config().ttl_cleanup_interval;
+    if !cleanup_interval.is_zero() {
+        background.spawn(guardrails::supervise("expiration", restart, {
+            let cache = cache.clone();
+            move || cache.clone().run_expiration(cleanup_interval)
+        }));
+    }
+    // Release spare value capacity every DASHDOT_COMPACTION_INTERVAL_SECS
+    if let Some(period) = cache.config().compaction_interval {
+        background.spawn(cache.clone().run_compaction(period));
+    }
+
+    // Follow a Redis primary from startup with DASHDOT_REPLICAOF=host:port
+    let replication = Arc::new(Replication::new(cache.clone(), 6379));
+    if let Ok(primary) = std::env::var("DASHDOT_REPLICAOF") {
+        replication.replicate_from(primary);
+    }
+    // Copy keys to another instance from startup with DASHDOT_MIRROR=host:port,
+    // limited to DASHDOT_MIRROR_MATCH patterns and DASHDOT_MIRROR_NAMESPACES
+    let mirroring = Arc::new(Mirroring::new(cache.clone()));
+    if let Ok(target) = std::env::var("DASHDOT_MIRROR") {
+        let list = |name: &str| -> Vec<String> {
+            std::env::var(name)
+                .map(|list| {
+                    list.split(',')
+                        .map(str::trim)
+                        .filter(|item| !item.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let filter = MirrorFilter {
+            patterns: list("DASHDOT_MIRROR_MATCH"),
+            namespaces: list("DASHDOT_MIRROR_NAMESPACES"),
+        };
+        mirroring.start(target, filter);
+    }
+    // Feed any replica that connects over RESP; DASHDOT_REPL_BACKLOG sizes the
+    // window a reconnecting replica can resume from
+    let primary = Arc::new(Primary::new(
+        std::env::var("DASHDOT_REPL_BACKLOG")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_BACKLOG_SIZE),
+    ));
+    background.spawn(primary.clone().run());
+    // Keep independent instances coherent: DASHDOT_BUS_PEERS=host:port,... receive
+    // this node's invalidations, and DASHDOT_BUS_LISTEN accepts theirs
+    let bus_config = BusConfig {
+        listen: std::env::var("DASHDOT_BUS_LISTEN").ok(),
+        peers: std::env::var("DASHDOT_BUS_PEERS")
+            .map(|peers| {
+                peers
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|peer| !peer.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default(),
+    };
+    let bus = (bus_config.listen.is_some() || !bus_config.peers.is_empty()).then(|| {
+        let bus = Arc::new(InvalidationBus::new(bus_config));
+        background.spawn(bus.clone().run(cache.clone()));
+        bus
+    });
+    let mut executor = CommandExecutor::new(cache.clone())
+        .with_script_limits(ScriptLimits::from_env())
+        .with_stats_aggregator(aggregator)
+        .with_snapshots(Arc::new(SnapshotJobs::new(&snapshot_path)))
+        .with_middleware(replication.read_only())
+        .with_middleware(guardrails)
+        .with_replication(replication)
+        .with_mirroring(mirroring)
+        .with_primary(primary)
+        .with_middleware(Arc::new(CommandMetrics::default()))
+        .with_middleware(Arc::new(AuditLog));
+    if let Some(bus) = bus {
+        executor = executor.with_invalidation_bus(bus);
+    }
+    // Mirror writes into the oplog stream when DASHDOT_OPLOG_MAX_LEN is set
+    if let Some(max_len) = std::env::var("DASHDOT_OPLOG_MAX_LEN")
+        .ok()
+        .and_then(|v| v.parse().ok())
+    {
+        executor = executor.with_oplog(OpLog::new(max_len));
+    }
+    // Log writes under DASHDOT_AOF_DIR, replaying what's there first
+    let mut aof = None;
+    if let Some(dir) = aof_dir {
+        let config = AofConfig {
+            fsync: std::env::var("DASHDOT_AOF_FSYNC")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default(),
+            ..AofConfig::new(dir)
+        };
+        let report = Aof::load(&cache, &config)?;
+        println!(
+            "Restored {} keys and replayed {} commands from the AOF",
+            report.base_keys, report.commands
+        );
+        let log = Arc::new(Aof::open(config)?);
+        background.spawn(log.clone().run(cache.clone()));
+        executor = executor.with_aof(log.clone());
+        aof = Some(log);
+    }
+    // Buffer INCRBY on hot counters matching DASHDOT_BUFFERED_COUNTERS, e.g. "hits:*,views:*"
+    let mut counters = None;
+    if let Ok(patterns) = std::env::var("DASHDOT_BUFFERED_COUNTERS") {
+        let buffer = Arc::new(CounterBuffer::new(CounterConfig {
+            patterns: patterns.split(',').map(str::to_string).collect(),
+            ..CounterConfig::default()
+        }));
+        background.spawn(buffer.clone().run(cache.clone()));
+        executor = executor.with_counters(buffer.clone());
+        counters = Some(buffer);
+    }
+    // Reload keys before they expire, per policies added at /admin/refresh-policies
+    let refresh = Arc::new(RefreshAhead::new(cache.clone()));
+    background.spawn(guardrails::supervise("refresh-ahead", restart, {
+        let refresh = refresh.clone();
+        move || refresh.clone().run()
+    }));
+    executor = executor.with_refresh_ahead(refresh);
+    // Answer repeated GETCHILDREN, KEYS and EXPIRING from a cache kept for
+    // DASHDOT_RESPONSE_CACHE_MS, or until the next write
+    let response_cache_ms = std::env::var("DASHDOT_RESPONSE_CACHE_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&ms: &u64| ms > 0);
+    if let Some(ms) = response_cache_ms {
+        executor = executor.with_response_cache(ResponseCache::new(ResponseCacheConfig {
+            ttl: std::time::Duration::from_millis(ms),
+            ..ResponseCacheConfig::default()
+        }));
+    }
+    // Validate SETs per namespace, with schemas from DASHDOT_SCHEMA_DIR or /admin/schemas
+    let schemas = Arc::new(Schemas::new(cache.config().namespace_delimiter));
+    if let Ok(dir) = std::env::var("DASHDOT_SCHEMA_DIR") {
+        schemas
+            .load_dir(Path::new(&dir))
+            .map_err(|e| format!("Failed to load schemas from {}: {}", dir, e))?;
+    }
+    executor = executor.with_schemas(schemas);
+    // Duplicate DASHDOT_SHADOW_PERCENT of commands to a secondary at DASHDOT_SHADOW,
+    // e.g. a new version being validated before cutover
+    if let Ok(target) = std::env::var("DASHDOT_SHADOW") {
+        let config = ShadowConfig {
+            percent: std::env::var("DASHDOT_SHADOW_PERCENT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(100.0),
+            ..ShadowConfig::new(target)
+        };
+        let shadowing = Arc::new(Shadowing::new(config));
+        background.spawn(shadowing.clone().run());
+        executor = executor.with_middleware(shadowing);
+    }
+    // Custom commands from the WASM modules in DASHDOT_PLUGIN_DIR, one per file
+    let mut plugins = Plugins::default();
+    if let Ok(dir) = std::env::var("DASHDOT_PLUGIN_DIR") {
+        let loaded = plugins
+            .load_dir(Path::new(&dir), WasmLimits::default())
+            .map_err(|e| format!("Failed to load plugins from {}: {}", dir, e))?;
+        println!("Loaded {} plugin commands from {}", loaded, dir);
+    }
+    let executor = if plugins.is_empty() {
+        Arc::new(executor)
+    } else {
+        Arc::new_cyclic(|this| executor.with_plugins(plugins, this.clone()))
+    };
+    // Republish keyspace events on pub/sub, per DASHDOT_NOTIFY_KEYSPACE_EVENTS
+    if cache.config().keyspace_events.enabled() {
+        let notifier = KeyspaceNotifier::new(cache.clone(), executor.pubsub().clone());
+        background.spawn(Arc::new(notifier).run());
+    }
 
     println!(
         "Cache initialized. Memory usage: {}",
@@ -19,9 +401,36 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let http_executor = executor.clone();
     let resp_executor = executor.clone();
 
+    // HTTP listeners from DASHDOT_HTTP_LISTEN, e.g. 127.0.0.1:8080,0.0.0.0:8081=readonly
+    let listeners = std::env::var("DASHDOT_HTTP_LISTEN")
+        .unwrap_or_else(|_| "127.0.0.1:8080".to_string())
+        .split(',')
+        .map(str::trim)
+        .filter(|listener| !listener.is_empty())
+        .map(str::parse::<ListenerConfig>)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid DASHDOT_HTTP_LISTEN: {}", e))?;
     let http_server = tokio::spawn(async move {
-        println!("Starting HTTP API server on http://127.0.0.1:8080");
-        HttpApiServer::run(http_executor, "127.0.0.1:8080").await
+        let locks = Arc::new(KeyLocks::new());
+        let mut servers = tokio::task::JoinSet::new();
+        for listener in listeners {
+            println!(
+                "Starting {}HTTP API server on http://{}",
+                if listener.read_only { "read-only " } else { "" },
+                listener.addr
+            );
+            servers.spawn(HttpApiServer::run(
+                http_executor.clone(),
+                locks.clone(),
+                listener,
+            ));
+        }
+        // The first listener to stop stops them all
+        match servers.join_next().await {
+            Some(Ok(result)) => result,
+            Some(Err(e)) => Err(std::io::Error::other(e)),
+            None => Ok(()),
+        }
     });
 
     let resp_server = tokio::spawn(async move {
@@ -45,7 +454,112 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 Err(e) => eprintln!("RESP server task error: {}", e),
             }
         }
+        () = shutdown_signal() => println!("Shutting down"),
+    }
+
+    let saved = tokio::task::spawn_blocking(move || {
+        persist_on_shutdown(
+            &cache,
+            counters.as_deref(),
+            aof.as_deref(),
+            Path::new(&snapshot_path),
+        )
+    })
+    .await??;
+    if let Some(saved) = saved {
+        println!("Saved {} keys to the snapshot", saved.keys);
     }
 
     Ok(())
 }
+
+/// Loads the dump file, if there is one. The AOF carries its own base snapshot,
+/// so the dump file is only read without one.
+fn load_snapshot(cache: &Cache, path: &Path, aof: bool) -> io::Result<Option<LoadReport>> {
+    if aof || !path.exists() {
+        return Ok(None);
+    }
+    snapshot::load(cache, path).map(Some)
+}
+
+/// Pending increments and buffered log writes make it to disk before exiting;
+/// without an AOF a final snapshot is written instead
+fn persist_on_shutdown(
+    cache: &Cache,
+    counters: Option<&CounterBuffer>,
+    aof: Option<&Aof>,
+    path: &Path,
+) -> io::Result<Option<SnapshotInfo>> {
+    if let Some(counters) = counters {
+        counters.flush(cache);
+    }
+    match aof {
+        Some(aof) => aof.sync().map(|()| None),
+        None => snapshot::save(cache, path).map(Some),
+    }
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM where there is one
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            eprintln!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dashdotcache::cache::{SetOptions, Value};
+
+    #[test]
+    fn test_snapshot_across_restart() {
+        let path =
+            std::env::temp_dir().join(format!("dashdot-restart-{}.snap", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let cache = Cache::new(Config::default());
+        assert!(load_snapshot(&cache, &path, false).unwrap().is_none());
+
+        cache
+            .set(
+                "k".to_string(),
+                Value::String("v".to_string()),
+                SetOptions::default(),
+            )
+            .unwrap();
+        let counters = CounterBuffer::new(CounterConfig {
+            patterns: vec!["hits:*".to_string()],
+            ..CounterConfig::default()
+        });
+        counters.add("hits:home", 3).unwrap();
+        let saved = persist_on_shutdown(&cache, Some(&counters), None, &path).unwrap();
+        assert_eq!(saved.unwrap().keys, 2);
+
+        // Buffered increments flushed on shutdown come back with the rest
+        let restarted = Cache::new(Config::default());
+        assert!(load_snapshot(&restarted, &path, true).unwrap().is_none());
+        let report = load_snapshot(&restarted, &path, false).unwrap().unwrap();
+        assert_eq!(report.loaded, 2);
+        assert_eq!(restarted.get("hits:home").unwrap().to_string(), "3");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,255 @@
+use crate::cache::{Cache, SetOptions, Value};
+use std::io::{self, ErrorKind};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tracing::{info, warn};
+
+/// Keys fetched per multi-get
+const GET_BATCH: usize = 100;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub expired: usize,  // Listed by the dump but expired before they were fetched
+    pub missing: usize,  // Listed by the dump but evicted or deleted before they were fetched
+    pub rejected: usize, // Refused by the cache, e.g. over max_memory
+}
+
+/// One key from `lru_crawler metadump`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DumpedKey {
+    key: String,
+    expires_at: Option<u64>, // Unix seconds; None for keys that never expire
+}
+
+/// Copies every key from the memcached server at `addr` into `cache`, keeping their
+/// remaining TTLs. Keys are listed with `lru_crawler metadump all` (memcached 1.4.31+)
+/// and fetched in batches; memcached client flags aren't carried over.
+pub async fn import(addr: &str, cache: &Cache) -> io::Result<ImportReport> {
+    let stream = TcpStream::connect(addr).await?;
+    let mut conn = BufReader::new(stream);
+    let keys = metadump(&mut conn).await?;
+    info!("memcached at {} listed {} keys", addr, keys.len());
+
+    let mut report = ImportReport::default();
+    for batch in keys.chunks(GET_BATCH) {
+        let now = unix_secs();
+        let live: Vec<&DumpedKey> = batch
+            .iter()
+            .filter(|k| k.expires_at.is_none_or(|at| at > now))
+            .collect();
+        report.expired += batch.len() - live.len();
+        if live.is_empty() {
+            continue;
+        }
+
+        let names: Vec<&str> = live.iter().map(|k| k.key.as_str()).collect();
+        let values = get_many(&mut conn, &names).await?;
+        report.missing += live.len() - values.len();
+
+        let now = unix_secs();
+        for (key, data) in values {
+            let Some(dumped) = live.iter().find(|k| k.key == key) else {
+                continue;
+            };
+            let ttl = match dumped.expires_at {
+                Some(at) if at <= now => {
+                    report.expired += 1;
+                    continue;
+                }
+                Some(at) => Some(Duration::from_secs(at - now)),
+                None => None,
+            };
+            let options = SetOptions {
+                ttl,
+                ..SetOptions::default()
+            };
+            match cache.set(key, Value::from_bytes(data), options) {
+                Ok(_) => report.imported += 1,
+                Err(e) => {
+                    warn!("Skipping memcached key: {}", e);
+                    report.rejected += 1;
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+async fn metadump(conn: &mut BufReader<TcpStream>) -> io::Result<Vec<DumpedKey>> {
+    conn.get_mut()
+        .write_all(b"lru_crawler metadump all\r\n")
+        .await?;
+    let mut keys = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if conn.read_line(&mut line).await? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        if line == "END" {
+            return Ok(keys);
+        }
+        if line.starts_with("ERROR") || line.starts_with("CLIENT_ERROR") || line.starts_with("BUSY")
+        {
+            return Err(io::Error::other(format!("metadump failed: {}", line)));
+        }
+        if let Some(key) = parse_metadump_line(line) {
+            keys.push(key);
+        }
+    }
+}
+
+/// Parses `key=<uri-encoded> exp=<unix secs or -1> la=... cas=... fetch=... cls=... size=...`
+fn parse_metadump_line(line: &str) -> Option<DumpedKey> {
+    let mut key = None;
+    let mut expires_at = None;
+    for field in line.split_whitespace() {
+        match field.split_once('=')? {
+            ("key", encoded) => key = Some(uri_decode(encoded)?),
+            ("exp", exp) => expires_at = u64::try_from(exp.parse::<i64>().ok()?).ok(),
+            _ => {}
+        }
+    }
+    Some(DumpedKey {
+        key: key?,
+        expires_at,
+    })
+}
+
+fn uri_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Multi-get; keys that no longer exist are simply absent from the result
+async fn get_many(
+    conn: &mut BufReader<TcpStream>,
+    keys: &[&str],
+) -> io::Result<Vec<(String, Vec<u8>)>> {
+    conn.get_mut()
+        .write_all(format!("get {}\r\n", keys.join(" ")).as_bytes())
+        .await?;
+    let mut values = Vec::with_capacity(keys.len());
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if conn.read_line(&mut line).await? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        let header = line.trim_end();
+        if header == "END" {
+            return Ok(values);
+        }
+        // VALUE <key> <flags> <bytes> [<cas>]
+        let mut parts = header.split(' ');
+        let (Some("VALUE"), Some(key), Some(_flags), Some(len)) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(io::Error::other(format!("unexpected reply: {}", header)));
+        };
+        let len: usize = len
+            .parse()
+            .map_err(|_| io::Error::other(format!("bad value length in: {}", header)))?;
+        let key = key.to_string();
+        let mut data = vec![0; len + 2];
+        conn.read_exact(&mut data).await?;
+        data.truncate(len);
+        values.push((key, data));
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Config;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_parse_metadump_line() {
+        let line = "key=user%3A1%20x exp=-1 la=1700000000 cas=5 fetch=no cls=1 size=70";
+        assert_eq!(
+            parse_metadump_line(line),
+            Some(DumpedKey {
+                key: "user:1 x".into(),
+                expires_at: None
+            })
+        );
+        let line = "key=s exp=1900000000 la=1 cas=2 fetch=yes cls=1 size=60";
+        assert_eq!(
+            parse_metadump_line(line).unwrap().expires_at,
+            Some(1900000000)
+        );
+        assert_eq!(parse_metadump_line("key=%zz exp=-1"), None);
+    }
+
+    #[tokio::test]
+    async fn test_import_from_memcached() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let in_an_hour = unix_secs() + 3600;
+
+        // Just enough of memcached's text protocol to answer the importer
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut conn = BufReader::new(stream);
+            let mut line = String::new();
+            conn.read_line(&mut line).await.unwrap();
+            assert_eq!(line, "lru_crawler metadump all\r\n");
+            let dump = format!(
+                "key=a exp=-1 la=1 cas=1 fetch=no cls=1 size=1\n\
+                 key=b exp={} la=1 cas=2 fetch=no cls=1 size=1\n\
+                 key=old exp=1 la=1 cas=3 fetch=no cls=1 size=1\n\
+                 key=gone exp=-1 la=1 cas=4 fetch=no cls=1 size=1\n\
+                 END\r\n",
+                in_an_hour
+            );
+            conn.get_mut().write_all(dump.as_bytes()).await.unwrap();
+
+            line.clear();
+            conn.read_line(&mut line).await.unwrap();
+            assert_eq!(line, "get a b gone\r\n");
+            conn.get_mut()
+                .write_all(b"VALUE a 0 5\r\nhello\r\nVALUE b 3 2\r\n\xff\x00\r\nEND\r\n")
+                .await
+                .unwrap();
+        });
+
+        let cache = Cache::new(Config::default());
+        let report = import(&addr, &cache).await.unwrap();
+        assert_eq!(
+            report,
+            ImportReport {
+                imported: 2,
+                expired: 1,
+                missing: 1,
+                rejected: 0
+            }
+        );
+        assert_eq!(cache.get("a"), Some(Value::String("hello".into())));
+        assert_eq!(cache.get("b"), Some(Value::Bytes(vec![0xff, 0])));
+        assert_eq!(cache.ttl("a"), -1);
+        assert!(cache.ttl("b") > 3500);
+    }
+}
@@ -0,0 +1,63 @@
+use std::fmt::Write;
+
+/// Static labels attached to every exported series (instance, environment, tenant...),
+/// pre-rendered once so each sample only appends its own labels
+#[derive(Debug, Default, Clone)]
+pub struct Labels {
+    rendered: String,
+}
+
+impl Labels {
+    /// Pairs whose name isn't a valid label name are dropped so the exposition still parses
+    pub fn new(pairs: &[(String, String)]) -> Self {
+        let mut rendered = String::new();
+        for (name, value) in pairs.iter().filter(|(name, _)| is_label_name(name)) {
+            push_label(&mut rendered, name, value);
+        }
+        Self { rendered }
+    }
+
+    /// Label set for a series, e.g. `{instance="a"}`, or empty without labels
+    pub fn series(&self) -> String {
+        self.series_with(&[])
+    }
+
+    /// Label set for a series with extra per-series labels appended
+    pub fn series_with(&self, extra: &[(&str, &str)]) -> String {
+        let mut labels = self.rendered.clone();
+        for (name, value) in extra {
+            push_label(&mut labels, name, value);
+        }
+
+        if labels.is_empty() {
+            labels
+        } else {
+            format!("{{{}}}", labels)
+        }
+    }
+}
+
+/// Whether `name` matches the Prometheus label grammar `[a-zA-Z_][a-zA-Z0-9_]*`
+pub fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn push_label(out: &mut String, name: &str, value: &str) {
+    if !out.is_empty() {
+        out.push(',');
+    }
+    write!(out, "{}=\"", name).unwrap();
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
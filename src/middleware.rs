@@ -0,0 +1,186 @@
+use crate::cache_errors::{CommandError, ErrorClass};
+use crate::command_table;
+use crate::executor::{Command, CommandResponse};
+use crate::metrics::Labels;
+use dashmap::DashMap;
+use dashmap::mapref::multiple::RefMulti;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::info;
+
+/// Hook around `CommandExecutor::execute`. Middleware runs in registration order
+/// before execution and in reverse order after it.
+pub trait Middleware: Send + Sync {
+    /// Rewrites the command, or rejects it by returning the response to send instead
+    fn before(&self, command: Command) -> Result<Command, CommandResponse> {
+        Ok(command)
+    }
+
+    fn after(&self, _name: &'static str, _response: &mut CommandResponse, _elapsed: Duration) {}
+
+    /// Appends any Prometheus metrics owned by the middleware
+    fn render_metrics(&self, _out: &mut String, _labels: &Labels) {}
+}
+
+/// Rejects write commands while enabled; can be toggled at runtime
+#[derive(Debug, Default)]
+pub struct ReadOnly {
+    enabled: AtomicBool,
+}
+
+impl ReadOnly {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled: AtomicBool::new(enabled),
+        }
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+}
+
+impl Middleware for ReadOnly {
+    fn before(&self, command: Command) -> Result<Command, CommandResponse> {
+        let write = command_table::lookup(command.name()).is_some_and(|spec| spec.write);
+        if write && self.enabled.load(Ordering::Relaxed) {
+            return Err(CommandResponse::Error(CommandError::new(
+                ErrorClass::ReadOnly,
+                "You can't write against a read only instance",
+            )));
+        }
+        Ok(command)
+    }
+}
+
+/// Logs every write command under the `audit` tracing target
+#[derive(Debug, Default)]
+pub struct AuditLog;
+
+impl Middleware for AuditLog {
+    fn before(&self, command: Command) -> Result<Command, CommandResponse> {
+        if command_table::lookup(command.name()).is_some_and(|spec| spec.write) {
+            info!(target: "audit", command = command.name(), "{:?}", command);
+        }
+        Ok(command)
+    }
+}
+
+#[derive(Debug, Default)]
+struct CommandCounters {
+    calls: AtomicU64,
+    errors: AtomicU64,
+    duration_micros: AtomicU64,
+}
+
+/// Per-command call, error and latency counters
+#[derive(Debug, Default)]
+pub struct CommandMetrics {
+    commands: DashMap<&'static str, CommandCounters>,
+}
+
+impl Middleware for CommandMetrics {
+    fn after(&self, name: &'static str, response: &mut CommandResponse, elapsed: Duration) {
+        let counters = self.commands.entry(name).or_default();
+        counters.calls.fetch_add(1, Ordering::Relaxed);
+        counters
+            .duration_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        if matches!(response, CommandResponse::Error(_)) {
+            counters.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn render_metrics(&self, out: &mut String, labels: &Labels) {
+        let mut commands: Vec<_> = self.commands.iter().collect();
+        commands.sort_by_key(|entry| *entry.key());
+
+        write_family(
+            out,
+            "cache_command_calls_total",
+            "Total number of calls per command",
+            &commands,
+            labels,
+            |c| &c.calls,
+        );
+        write_family(
+            out,
+            "cache_command_errors_total",
+            "Total number of error replies per command",
+            &commands,
+            labels,
+            |c| &c.errors,
+        );
+        write_family(
+            out,
+            "cache_command_duration_microseconds_total",
+            "Total execution time per command in microseconds",
+            &commands,
+            labels,
+            |c| &c.duration_micros,
+        );
+    }
+}
+
+fn write_family(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    commands: &[RefMulti<'_, &'static str, CommandCounters>],
+    labels: &Labels,
+    counter: impl Fn(&CommandCounters) -> &AtomicU64,
+) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} counter", name).unwrap();
+    for entry in commands {
+        let command = entry.key().to_lowercase();
+        let value = counter(entry.value()).load(Ordering::Relaxed);
+        let labels = labels.series_with(&[("command", &command)]);
+        writeln!(out, "{}{} {}", name, labels, value).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Cache, Config};
+    use crate::executor::CommandExecutor;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_read_only_and_metrics() {
+        let read_only = Arc::new(ReadOnly::new(true));
+        let metrics = Arc::new(CommandMetrics::default());
+        let executor = CommandExecutor::new(Arc::new(Cache::new(Config::default())))
+            .with_middleware(read_only.clone())
+            .with_middleware(metrics.clone());
+        let del = || Command::Del {
+            keys: vec!["k".to_string()],
+            cascade: false,
+        };
+
+        match executor.execute(del()) {
+            CommandResponse::Error(e) => assert_eq!(e.class, ErrorClass::ReadOnly),
+            other => panic!("expected READONLY, got {:?}", other),
+        }
+        assert!(matches!(
+            executor.execute(Command::Get {
+                key: "k".to_string()
+            }),
+            CommandResponse::Null
+        ));
+        read_only.set_enabled(false);
+        assert!(matches!(
+            executor.execute(del()),
+            CommandResponse::Integer(0)
+        ));
+
+        // Rejected commands never reach the middleware registered after ReadOnly
+        let mut out = String::new();
+        metrics.render_metrics(&mut out, &Labels::default());
+        assert!(out.contains("cache_command_calls_total{command=\"del\"} 1\n"));
+        assert!(out.contains("cache_command_calls_total{command=\"get\"} 1\n"));
+        assert!(out.contains("cache_command_errors_total{command=\"del\"} 0\n"));
+    }
+}
@@ -0,0 +1,405 @@
+use crate::cache::{Cache, Value, namespace_of};
+use crate::events::CacheEvent;
+use crate::metrics::Labels;
+use crate::pubsub::glob_match;
+use crate::replay::Conn;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+/// Keys remembered while the target is unreachable; changes past this are dropped
+const MAX_PENDING: usize = 100_000;
+
+/// Keys to mirror: those matching any glob pattern or in any namespace. Empty
+/// mirrors everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct MirrorFilter {
+    pub patterns: Vec<String>,
+    pub namespaces: Vec<String>,
+}
+
+impl MirrorFilter {
+    pub fn matches(&self, key: &str, delimiter: char) -> bool {
+        (self.patterns.is_empty() && self.namespaces.is_empty())
+            || self.patterns.iter().any(|pattern| glob_match(pattern, key))
+            || namespace_of(key, delimiter)
+                .is_some_and(|namespace| self.namespaces.iter().any(|n| n == namespace))
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MirrorStatus {
+    pub target: String,
+    pub filter: MirrorFilter,
+    pub connected: bool,
+    pub pending: usize, // Changed keys waiting to be copied
+    pub copied: u64,
+    pub deleted: u64,
+    pub unsupported: u64, // Keys of types the target has no command to write
+    pub dropped: u64,     // Changes lost to lag or a full pending set
+    pub last_error: Option<String>,
+}
+
+struct Link {
+    task: JoinHandle<()>,
+    status: Arc<Mutex<MirrorStatus>>,
+}
+
+/// Asynchronous copy of matching keys to another dashdotcache over RESP, driven by
+/// the key event stream. Starting copies the matching keys already present, then
+/// each write and cascaded invalidation is followed by copying the key's current
+/// value and TTL, or deleting it remotely if it's gone. Expiry is left to the TTL
+/// sent along and eviction stays local. Strings, hashes, sets and JSON documents
+/// are copied; other types are counted as unsupported.
+pub struct Mirroring {
+    cache: Arc<Cache>,
+    link: Mutex<Option<Link>>,
+}
+
+impl Mirroring {
+    pub fn new(cache: Arc<Cache>) -> Self {
+        Self {
+            cache,
+            link: Mutex::new(None),
+        }
+    }
+
+    /// Starts mirroring to `target` ("host:port"), replacing any current mirror
+    pub fn start(&self, target: String, filter: MirrorFilter) {
+        let status = Arc::new(Mutex::new(MirrorStatus {
+            target: target.clone(),
+            filter: filter.clone(),
+            ..MirrorStatus::default()
+        }));
+        // Subscribed before listing, so no write falls between the two
+        let events = self.cache.subscribe();
+        let delimiter = self.cache.config().namespace_delimiter;
+        let pending: HashSet<String> = self
+            .cache
+            .keys("*", MAX_PENDING)
+            .into_iter()
+            .filter(|key| filter.matches(key, delimiter))
+            .collect();
+        info!("Mirroring {} keys to {}", pending.len(), target);
+        let mirror = Mirror {
+            cache: self.cache.clone(),
+            target,
+            filter,
+            pending,
+            status: status.clone(),
+        };
+        let task = tokio::spawn(mirror.run(events));
+        if let Some(old) = self.link.lock().unwrap().replace(Link { task, status }) {
+            old.task.abort();
+        }
+    }
+
+    /// Stops mirroring. Returns whether a mirror was running.
+    pub fn stop(&self) -> bool {
+        let link = self.link.lock().unwrap().take();
+        link.map(|link| link.task.abort()).is_some()
+    }
+
+    /// None when not mirroring
+    pub fn status(&self) -> Option<MirrorStatus> {
+        let link = self.link.lock().unwrap();
+        link.as_ref()
+            .map(|link| link.status.lock().unwrap().clone())
+    }
+
+    pub fn render_metrics(&self, out: &mut String, labels: &Labels) {
+        let series = labels.series();
+        let status = self.status().unwrap_or_default();
+        let counters = [
+            (
+                "cache_mirror_copied_total",
+                "Keys copied to the mirror target",
+                status.copied,
+            ),
+            (
+                "cache_mirror_deleted_total",
+                "Keys deleted on the mirror target",
+                status.deleted,
+            ),
+            (
+                "cache_mirror_unsupported_total",
+                "Changed keys of a type the mirror can't copy",
+                status.unsupported,
+            ),
+            (
+                "cache_mirror_dropped_total",
+                "Key changes lost before reaching the mirror target",
+                status.dropped,
+            ),
+        ];
+        for (name, help, value) in counters {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} counter", name).unwrap();
+            writeln!(out, "{}{} {}", name, series, value).unwrap();
+        }
+        writeln!(
+            out,
+            "# HELP cache_mirror_pending_keys Changed keys waiting to be copied"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE cache_mirror_pending_keys gauge").unwrap();
+        writeln!(
+            out,
+            "cache_mirror_pending_keys{} {}",
+            series, status.pending
+        )
+        .unwrap();
+    }
+}
+
+struct Mirror {
+    cache: Arc<Cache>,
+    target: String,
+    filter: MirrorFilter,
+    pending: HashSet<String>,
+    status: Arc<Mutex<MirrorStatus>>,
+}
+
+impl Mirror {
+    async fn run(mut self, mut events: Receiver<CacheEvent>) {
+        let mut backoff = RECONNECT_MIN;
+        loop {
+            let result = match Conn::connect(&self.target).await {
+                Ok(mut conn) => {
+                    backoff = RECONNECT_MIN;
+                    self.status.lock().unwrap().connected = true;
+                    self.stream(&mut conn, &mut events).await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                warn!("Mirroring to {} failed: {}", self.target, e);
+                let mut status = self.status.lock().unwrap();
+                status.connected = false;
+                status.last_error = Some(e.to_string());
+            }
+            // Keep collecting changes until it's time to reconnect
+            let wait = tokio::time::sleep(backoff);
+            tokio::pin!(wait);
+            loop {
+                tokio::select! {
+                    _ = &mut wait => break,
+                    event = events.recv() => match event {
+                        Ok(event) => self.collect(event),
+                        Err(RecvError::Lagged(skipped)) => self.count_dropped(skipped),
+                        Err(RecvError::Closed) => return,
+                    },
+                }
+            }
+            backoff = (backoff * 2).min(RECONNECT_MAX);
+        }
+    }
+
+    /// Copies pending keys, then each changed key as events arrive
+    async fn stream(
+        &mut self,
+        conn: &mut Conn,
+        events: &mut Receiver<CacheEvent>,
+    ) -> io::Result<()> {
+        loop {
+            while let Some(key) = self.pending.iter().next().cloned() {
+                self.pending.remove(&key);
+                if let Err(e) = self.copy(conn, &key).await {
+                    self.pending.insert(key);
+                    return Err(e);
+                }
+                self.status.lock().unwrap().pending = self.pending.len();
+            }
+            match events.recv().await {
+                Ok(event) => self.collect(event),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Mirroring fell behind; skipped {} changes", skipped);
+                    self.count_dropped(skipped);
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+
+    fn collect(&mut self, event: CacheEvent) {
+        let key = match event {
+            CacheEvent::Written { key }
+            | CacheEvent::Set { key }
+            | CacheEvent::Deleted { key }
+            | CacheEvent::Invalidated { key, .. } => key,
+            CacheEvent::Miss { .. } | CacheEvent::Expired { .. } | CacheEvent::Evicted { .. } => {
+                return;
+            }
+        };
+        if !self
+            .filter
+            .matches(&key, self.cache.config().namespace_delimiter)
+        {
+            return;
+        }
+        if self.pending.len() >= MAX_PENDING && !self.pending.contains(&key) {
+            self.count_dropped(1);
+            return;
+        }
+        self.pending.insert(key);
+        self.status.lock().unwrap().pending = self.pending.len();
+    }
+
+    fn count_dropped(&self, changes: u64) {
+        self.status.lock().unwrap().dropped += changes;
+    }
+
+    /// Writes the key's current state to the target
+    async fn copy(&self, conn: &mut Conn, key: &str) -> io::Result<()> {
+        let Some((value, _)) = self.cache.peek(key) else {
+            conn.call(&args(&["DEL", key]))
+                .await?
+                .map_err(io::Error::other)?;
+            self.status.lock().unwrap().deleted += 1;
+            return Ok(());
+        };
+        let ttl = self.cache.ttl_remaining(key);
+        let Some(commands) = write_commands(key, &value, ttl) else {
+            self.status.lock().unwrap().unsupported += 1;
+            return Ok(());
+        };
+        for command in commands {
+            // The key may have changed type since; it's copied again on its next event
+            if let Err(reply) = conn.call(&command).await? {
+                warn!(
+                    "Mirror target refused {}: {}",
+                    String::from_utf8_lossy(&command[0]),
+                    reply
+                );
+            }
+        }
+        self.status.lock().unwrap().copied += 1;
+        Ok(())
+    }
+}
+
+fn args(args: &[&str]) -> Vec<Vec<u8>> {
+    args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+}
+
+/// Commands recreating a value on another server; None for types without one
+fn write_commands(key: &str, value: &Value, ttl: Option<Duration>) -> Option<Vec<Vec<Vec<u8>>>> {
+    let with_values = |command: &str, values: Vec<String>| {
+        let mut args = args(&[command, key]);
+        args.extend(values.into_iter().map(String::into_bytes));
+        args
+    };
+    let mut commands = match value {
+        Value::String(_) | Value::Integer(_) | Value::Float(_) | Value::Bytes(_) => {
+            let mut set = args(&["SET", key]);
+            set.extend(value.as_bytes().map(Cow::into_owned));
+            if let Some(ttl) = ttl {
+                set.extend(args(&["PX", &ttl.as_millis().max(1).to_string()]));
+            }
+            return Some(vec![set]);
+        }
+        Value::Hash(fields) if !fields.is_empty() => vec![
+            args(&["DEL", key]),
+            with_values(
+                "HSET",
+                fields
+                    .iter()
+                    .flat_map(|(field, value)| [field.clone(), value.to_string()])
+                    .collect(),
+            ),
+        ],
+        Value::Set(members) if !members.is_empty() => vec![
+            args(&["DEL", key]),
+            with_values("SADD", members.iter().cloned().collect()),
+        ],
+        Value::Json(document) => vec![args(&["JSON.SET", key, "$", &document.to_string()])],
+        _ => return None,
+    };
+    // Whole seconds, rounded up so the copy doesn't expire first
+    if let Some(ttl) = ttl {
+        let secs = ttl.as_millis().div_ceil(1000).max(1);
+        commands.push(args(&["EXPIRE", key, &secs.to_string()]));
+    }
+    Some(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Config, SetOptions};
+    use crate::executor::{Command, CommandExecutor};
+    use crate::resp_api::RespServer;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_mirror_copies_matching_keys() {
+        let target = Arc::new(Cache::new(Config::default()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = RespServer::new(Arc::new(CommandExecutor::new(target.clone())));
+        tokio::spawn(async move { server.serve(listener).await });
+
+        let source = Arc::new(Cache::new(Config::default()));
+        let executor = CommandExecutor::new(source.clone());
+        let set = |key: &str| {
+            executor.execute(Command::Set {
+                key: key.to_string(),
+                value: "v".into(),
+                options: SetOptions {
+                    ttl: Some(Duration::from_secs(60)),
+                    ..Default::default()
+                },
+                concern: None,
+            })
+        };
+        set("user:existing");
+        let mirroring = Mirroring::new(source.clone());
+        mirroring.start(
+            addr,
+            MirrorFilter {
+                patterns: vec!["*:pinned".to_string()],
+                namespaces: vec!["user".to_string()],
+            },
+        );
+        set("user:1");
+        set("order:1");
+        set("order:pinned");
+        executor.execute(Command::SAdd {
+            key: "user:tags".to_string(),
+            members: vec!["a".to_string(), "b".to_string()],
+        });
+        executor.execute(Command::Del {
+            keys: vec!["user:existing".to_string()],
+            cascade: false,
+        });
+
+        for _ in 0..100 {
+            if target.get("user:tags").is_some() && target.get("user:existing").is_none() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(target.get("user:existing").is_none());
+        assert!(target.get("user:1").is_some());
+        assert!(target.get("order:pinned").is_some());
+        assert!(target.get("order:1").is_none());
+        assert_eq!(target.smembers("user:tags").unwrap().len(), 2);
+        assert!(target.ttl_remaining("user:1").unwrap() > Duration::from_secs(50));
+
+        let status = mirroring.status().unwrap();
+        assert!(status.connected);
+        assert!(status.deleted >= 1);
+        assert!(mirroring.stop());
+        assert!(mirroring.status().is_none());
+    }
+}
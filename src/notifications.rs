@@ -0,0 +1,217 @@
+use crate::cache::Cache;
+use crate::events::CacheEvent;
+use crate::pubsub::PubSub;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+/// Which keyspace notifications are published, configured with Redis'
+/// notify-keyspace-events flags: `K` and `E` pick the `__keyspace@0__:<key>` and
+/// `__keyevent@0__:<event>` channels, and the rest pick events. `g` is del, `$` set,
+/// `x` expired, `e` evicted, `m` keymiss, and `i` (ours) invalidated by an ancestor;
+/// `A` is all of them but `m`. Redis' other type flags are accepted and emit nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KeyspaceEvents {
+    pub keyspace: bool,
+    pub keyevent: bool,
+    pub del: bool,
+    pub set: bool,
+    pub expired: bool,
+    pub evicted: bool,
+    pub miss: bool,
+    pub invalidated: bool,
+}
+
+impl KeyspaceEvents {
+    /// Whether any notification is published at all
+    pub fn enabled(&self) -> bool {
+        (self.keyspace || self.keyevent)
+            && (self.del
+                || self.set
+                || self.expired
+                || self.evicted
+                || self.miss
+                || self.invalidated)
+    }
+
+    /// Event name and key of a cache event, if it's selected
+    fn select(&self, event: CacheEvent) -> Option<(&'static str, String)> {
+        match event {
+            CacheEvent::Set { key } if self.set => Some(("set", key)),
+            CacheEvent::Deleted { key } if self.del => Some(("del", key)),
+            CacheEvent::Expired { key } if self.expired => Some(("expired", key)),
+            CacheEvent::Evicted { key } if self.evicted => Some(("evicted", key)),
+            CacheEvent::Miss { key } if self.miss => Some(("keymiss", key)),
+            CacheEvent::Invalidated { key, .. } if self.invalidated => Some(("invalidated", key)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for KeyspaceEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (on, flag) in [
+            (self.keyspace, 'K'),
+            (self.keyevent, 'E'),
+            (self.del, 'g'),
+            (self.set, '$'),
+            (self.expired, 'x'),
+            (self.evicted, 'e'),
+            (self.miss, 'm'),
+            (self.invalidated, 'i'),
+        ] {
+            if on {
+                write!(f, "{}", flag)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for KeyspaceEvents {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut events = KeyspaceEvents::default();
+        for flag in s.chars() {
+            match flag {
+                'K' => events.keyspace = true,
+                'E' => events.keyevent = true,
+                'g' => events.del = true,
+                '$' => events.set = true,
+                'x' => events.expired = true,
+                'e' => events.evicted = true,
+                'm' => events.miss = true,
+                'i' => events.invalidated = true,
+                'A' => {
+                    events.del = true;
+                    events.set = true;
+                    events.expired = true;
+                    events.evicted = true;
+                    events.invalidated = true;
+                }
+                'l' | 's' | 'h' | 'z' | 't' | 'n' | 'd' => {}
+                _ => return Err(format!("unknown keyspace event flag '{}'", flag)),
+            }
+        }
+        Ok(events)
+    }
+}
+
+/// Republishes the cache's events as keyspace notifications over pub/sub. Events
+/// arrive through the cache's event bus, so a notifier that falls too far behind
+/// skips some rather than slowing writers.
+pub struct KeyspaceNotifier {
+    cache: Arc<Cache>,
+    pubsub: Arc<PubSub>,
+    events: KeyspaceEvents,
+}
+
+impl KeyspaceNotifier {
+    pub fn new(cache: Arc<Cache>, pubsub: Arc<PubSub>) -> Self {
+        let events = cache.config().keyspace_events;
+        Self {
+            cache,
+            pubsub,
+            events,
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut rx = self.cache.subscribe();
+        loop {
+            match rx.recv().await {
+                Ok(event) => self.notify(event),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Keyspace notifications fell behind; skipped {}", skipped)
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+
+    fn notify(&self, event: CacheEvent) {
+        let Some((name, key)) = self.events.select(event) else {
+            return;
+        };
+        if self.events.keyspace {
+            self.pubsub
+                .publish(&format!("__keyspace@0__:{}", key), name);
+        }
+        if self.events.keyevent {
+            self.pubsub
+                .publish(&format!("__keyevent@0__:{}", name), &key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Config, SetOptions, Value};
+    use crate::pubsub::Message;
+
+    #[test]
+    fn test_parse_keyspace_events() {
+        let events: KeyspaceEvents = "KEA".parse().unwrap();
+        assert_eq!(events.to_string(), "KEg$xei");
+        assert!(events.enabled());
+        assert!(!"Ex".parse::<KeyspaceEvents>().unwrap().keyspace);
+        assert!(!"K".parse::<KeyspaceEvents>().unwrap().enabled());
+        assert!(!"x".parse::<KeyspaceEvents>().unwrap().enabled());
+        assert!("Kq".parse::<KeyspaceEvents>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_keyspace_notifications() {
+        let cache = Arc::new(Cache::new(Config {
+            keyspace_events: "KEg$i".parse().unwrap(),
+            ..Default::default()
+        }));
+        let pubsub = Arc::new(PubSub::default());
+        let mut subscriber = pubsub.subscriber();
+        subscriber.psubscribe("__keyspace@0__:*");
+        subscriber.subscribe("__keyevent@0__:invalidated");
+        let notifier = Arc::new(KeyspaceNotifier::new(cache.clone(), pubsub));
+        tokio::spawn(notifier.run());
+        tokio::task::yield_now().await;
+
+        cache
+            .set(
+                "user".into(),
+                Value::String("v".into()),
+                SetOptions::default(),
+            )
+            .unwrap();
+        let child = SetOptions {
+            parents: vec!["user".into()],
+            ..Default::default()
+        };
+        cache
+            .set("profile".into(), Value::String("v".into()), child)
+            .unwrap();
+        cache.invalidate("user", crate::cache::InvalidationMode::Delete);
+
+        let mut received = Vec::new();
+        while received.len() < 6 {
+            let Message {
+                channel, payload, ..
+            } = subscriber.recv().await.unwrap();
+            received.push(format!("{} {}", channel, payload));
+        }
+        received.sort();
+        assert_eq!(
+            received,
+            [
+                "__keyevent@0__:invalidated profile",
+                "__keyevent@0__:invalidated user",
+                "__keyspace@0__:profile invalidated",
+                "__keyspace@0__:profile set",
+                "__keyspace@0__:user invalidated",
+                "__keyspace@0__:user set",
+            ]
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use crate::cache::{Cache, Value};
+use std::sync::{Mutex, MutexGuard};
+use tracing::warn;
+
+/// System key holding the operation log stream. The executor refuses client writes
+/// to it while an oplog is configured.
+pub const OPLOG_KEY: &str = "__dashdot:oplog";
+pub const DEFAULT_OPLOG_MAX_LEN: usize = 10_000;
+
+/// Mirrors successful write commands into a capped stream under `OPLOG_KEY`, giving
+/// consumers a change feed over XREAD. Each entry holds a `command` field followed
+/// by one `arg` field per argument, so entries can be replayed through `Command::parse`.
+#[derive(Debug)]
+pub struct OpLog {
+    max_len: usize,
+    writes: Mutex<()>,
+}
+
+impl Default for OpLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_OPLOG_MAX_LEN)
+    }
+}
+
+impl OpLog {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            max_len,
+            writes: Mutex::new(()),
+        }
+    }
+
+    /// Held while a write command is applied and recorded, so entries are in the
+    /// order the writes took effect
+    pub fn begin_write(&self) -> MutexGuard<'_, ()> {
+        self.writes.lock().unwrap()
+    }
+
+    pub fn record(&self, cache: &Cache, args: Vec<Vec<u8>>) {
+        // Stream fields are text, so a value that isn't UTF-8 is logged lossily
+        let mut args = args
+            .into_iter()
+            .map(|arg| String::from_utf8_lossy(&arg).into_owned());
+        let Some(command) = args.next() else {
+            return;
+        };
+        let fields = std::iter::once(("command".to_string(), command))
+            .chain(args.map(|arg| ("arg".to_string(), arg)))
+            .collect();
+
+        if let Err(e) = cache.stream_append(OPLOG_KEY, fields, Some(self.max_len)) {
+            warn!("Dropping oplog entry: {}", e);
+        }
+    }
+}
+
+/// Commands in `cache`'s oplog, oldest first, with the Unix milliseconds each was
+/// recorded at (the entry ID's time part)
+pub fn recorded(cache: &Cache) -> Vec<(u64, Vec<Vec<u8>>)> {
+    let Some((Value::Stream(stream), _)) = cache.peek(OPLOG_KEY) else {
+        return Vec::new();
+    };
+    stream
+        .entries()
+        .map(|entry| {
+            let args = entry
+                .fields
+                .iter()
+                .map(|(_, arg)| arg.as_bytes().to_vec())
+                .collect();
+            (entry.id.ms, args)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Config, SetOptions};
+    use crate::executor::{Command, CommandExecutor, CommandResponse};
+    use crate::stream::StreamId;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_oplog_records_successful_writes() {
+        let cache = Arc::new(Cache::new(Config::default()));
+        let executor = CommandExecutor::new(cache).with_oplog(OpLog::new(2));
+        let set = |key: &str| Command::Set {
+            key: key.to_string(),
+            value: "v".into(),
+            options: SetOptions::default(),
+            concern: None,
+        };
+
+        executor.execute(set("a"));
+        executor.execute(Command::Get { key: "a".into() });
+        executor.execute(Command::Expire {
+            key: "a".into(),
+            ttl: u64::MAX, // Rejected by validation, so not logged
+            millis: false,
+        });
+        executor.execute(set("b"));
+        executor.execute(set("c"));
+
+        let read = Command::XRead {
+            count: None,
+            streams: vec![(OPLOG_KEY.to_string(), StreamId::default())],
+        };
+        let CommandResponse::Streams(streams) = executor.execute(read) else {
+            panic!("expected stream entries");
+        };
+        let entries = &streams[0].1;
+        assert_eq!(entries.len(), 2); // Capped
+        let replay: Vec<String> = entries[1].fields.iter().map(|(_, v)| v.clone()).collect();
+        assert_eq!(replay, ["SET", "c", "v"]);
+    }
+
+    #[test]
+    fn test_oplog_logs_deadlines_and_reserves_its_key() {
+        let cache = Arc::new(Cache::new(Config::default()));
+        let executor = CommandExecutor::new(cache.clone()).with_oplog(OpLog::default());
+        let set = |key: &str| Command::Set {
+            key: key.to_string(),
+            value: "v".into(),
+            options: SetOptions {
+                ttl: Some(std::time::Duration::from_secs(60)),
+                ..Default::default()
+            },
+            concern: None,
+        };
+
+        executor.execute(set("a"));
+        assert!(matches!(
+            executor.execute(set(OPLOG_KEY)),
+            CommandResponse::Error(_)
+        ));
+
+        let recorded = recorded(&cache);
+        assert_eq!(recorded.len(), 1);
+        let at = cache.expire_time("a").to_string();
+        let expected: Vec<&[u8]> = vec![b"SET", b"a", b"v", b"PXAT", at.as_bytes()];
+        assert_eq!(recorded[0].1, expected);
+    }
+}
@@ -0,0 +1,569 @@
+use crate::cache::SetOptions;
+use crate::cache_errors::CommandError;
+use crate::command_table;
+use crate::executor::{Command, CommandExecutor, CommandResponse};
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use wasmi::{
+    Caller, Engine, Extern, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+};
+
+/// A custom command implementation. Plugins reach the cache only through `PluginHost`.
+pub trait Plugin: Send + Sync {
+    /// Runs the command `name` (upper case) with `args`
+    fn call(&self, host: &PluginHost, name: &str, args: &[String]) -> CommandResponse;
+}
+
+/// Vets each command a plugin issues before it runs, for protocol layers with access
+/// rules of their own, such as HTTP key locks
+pub type CommandCheck = Arc<dyn Fn(&Command) -> Result<(), CommandError> + Send + Sync>;
+
+/// The restricted API plugins get: get, set, del and ttl. They go through the
+/// executor like any client command, so plugin writes are validated, logged and
+/// replicated.
+#[derive(Clone)]
+pub struct PluginHost {
+    executor: Arc<CommandExecutor>,
+    check: Option<CommandCheck>,
+}
+
+impl PluginHost {
+    fn execute(&self, command: Command) -> CommandResponse {
+        if let Some(check) = &self.check
+            && let Err(e) = check(&command)
+        {
+            return CommandResponse::Error(e);
+        }
+        self.executor.execute(command)
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        match self.execute(Command::Get { key: key.into() }) {
+            CommandResponse::Bytes(value) => Some(String::from_utf8_lossy(&value).into_owned()),
+            _ => None,
+        }
+    }
+
+    pub fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<(), CommandError> {
+        let command = Command::Set {
+            key: key.into(),
+            value: value.into(),
+            options: SetOptions {
+                ttl,
+                ..Default::default()
+            },
+            concern: None,
+        };
+        match self.execute(command) {
+            CommandResponse::Error(e) => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn del(&self, key: &str) -> bool {
+        let command = Command::Del {
+            keys: vec![key.into()],
+            cascade: false,
+        };
+        matches!(self.execute(command), CommandResponse::Integer(1))
+    }
+
+    /// Seconds to expiry, -1 without a TTL or -2 for a missing key, as TTL replies
+    pub fn ttl(&self, key: &str) -> i64 {
+        match self.execute(Command::Ttl {
+            key: key.into(),
+            millis: false,
+        }) {
+            CommandResponse::Integer(ttl) => ttl,
+            _ => -2,
+        }
+    }
+}
+
+/// Custom commands by name. The executor dispatches names the command table doesn't
+/// know to these, so plugins can add commands but never replace built-in ones.
+#[derive(Default)]
+pub struct Plugins {
+    commands: HashMap<String, Arc<dyn Plugin>>,
+}
+
+impl Plugins {
+    pub fn register(&mut self, name: &str, plugin: Arc<dyn Plugin>) -> Result<(), String> {
+        let name = name.to_ascii_uppercase();
+        if command_table::lookup(&name).is_some() {
+            return Err(format!("'{}' is a built-in command", name));
+        }
+        if self.commands.contains_key(&name) {
+            return Err(format!("'{}' is registered twice", name));
+        }
+        self.commands.insert(name, plugin);
+        Ok(())
+    }
+
+    /// Registers every `*.wasm` module in `dir` as the command named by its file stem
+    pub fn load_dir(&mut self, dir: &Path, limits: WasmLimits) -> io::Result<usize> {
+        let mut loaded = 0;
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "wasm") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let invalid =
+                |e: String| io::Error::new(ErrorKind::InvalidData, format!("{:?}: {}", path, e));
+            let plugin = WasmPlugin::new(&std::fs::read(&path)?, limits)
+                .map_err(|e| invalid(e.to_string()))?;
+            self.register(name, Arc::new(plugin)).map_err(invalid)?;
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.commands.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Runs a plugin command, or None if no plugin defines `args[0]`
+    pub fn call(
+        &self,
+        executor: &Weak<CommandExecutor>,
+        args: &[&[u8]],
+        check: Option<CommandCheck>,
+    ) -> Option<CommandResponse> {
+        let (name, rest) = args.split_first()?;
+        let name = String::from_utf8_lossy(name).to_ascii_uppercase();
+        let plugin = self.commands.get(&name)?;
+        // The executor owns the plugins, so it's alive for as long as they run
+        let executor = executor.upgrade()?;
+        let Ok(rest) = rest
+            .iter()
+            .map(|arg| String::from_utf8(arg.to_vec()))
+            .collect::<Result<Vec<_>, _>>()
+        else {
+            return Some(CommandResponse::Error(CommandError::err(
+                "invalid UTF-8 in arguments",
+            )));
+        };
+        Some(plugin.call(&PluginHost { executor, check }, &name, &rest))
+    }
+}
+
+/// Host module WASM plugins import from; nothing else is linked, so a plugin has no
+/// I/O beyond these
+const HOST_MODULE: &str = "dashdot";
+const HOST_FUNCTIONS: [&str; 4] = ["get", "set", "del", "ttl"];
+
+/// Per-call resource limits for WASM plugins
+#[derive(Debug, Clone, Copy)]
+pub struct WasmLimits {
+    pub fuel: u64,         // Roughly one unit per instruction
+    pub max_memory: usize, // Bytes of linear memory
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self {
+            fuel: 10_000_000,
+            max_memory: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// A custom command compiled from a WebAssembly module. Each call runs in a fresh
+/// instance, so plugins keep no state outside the cache.
+///
+/// The module exports `memory`, `alloc(len) -> ptr` and `run(ptr, len) -> i64`.
+/// `run` gets the command name and arguments, each as a little-endian u32 length
+/// then its bytes, and returns `ptr << 32 | len` of its reply: `+` for OK, `$value`,
+/// `:integer`, `-error` or `_` for nil. It may import from `dashdot`:
+///
+/// - `get(key, key_len, out, out_cap) -> i64`: value length, -1 if missing; the
+///   value is copied to `out` only when it fits
+/// - `set(key, key_len, value, value_len, ttl_ms) -> i32`: 0 on success; no TTL
+///   unless `ttl_ms` is positive
+/// - `del(key, key_len) -> i32`: 1 if the key was removed
+/// - `ttl(key, key_len) -> i64`: as the TTL command
+pub struct WasmPlugin {
+    engine: Engine,
+    module: Module,
+    linker: Linker<Sandbox>,
+    limits: WasmLimits,
+}
+
+/// Store data for one plugin call
+struct Sandbox {
+    host: PluginHost,
+    limits: StoreLimits,
+}
+
+impl WasmPlugin {
+    /// Compiles `wasm`, refusing modules that import anything but the host API or
+    /// don't fit within `limits`
+    pub fn new(wasm: &[u8], limits: WasmLimits) -> Result<Self, wasmi::Error> {
+        let mut config = wasmi::Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm)?;
+        let plugin = Self {
+            linker: host_linker(&engine)?,
+            engine,
+            module,
+            limits,
+        };
+        for import in plugin.module.imports() {
+            if import.module() != HOST_MODULE || !HOST_FUNCTIONS.contains(&import.name()) {
+                return Err(wasmi::Error::new(format!(
+                    "unknown import '{}.{}'",
+                    import.module(),
+                    import.name()
+                )));
+            }
+        }
+        for name in ["memory", "alloc", "run"] {
+            if plugin.module.get_export(name).is_none() {
+                return Err(wasmi::Error::new(format!("missing export '{}'", name)));
+            }
+        }
+        Ok(plugin)
+    }
+
+    fn run(
+        &self,
+        host: &PluginHost,
+        name: &str,
+        args: &[String],
+    ) -> Result<CommandResponse, wasmi::Error> {
+        let mut store = Store::new(
+            &self.engine,
+            Sandbox {
+                host: host.clone(),
+                limits: StoreLimitsBuilder::new()
+                    .memory_size(self.limits.max_memory)
+                    .instances(1)
+                    .build(),
+            },
+        );
+        store.limiter(|sandbox| &mut sandbox.limits);
+        store.set_fuel(self.limits.fuel)?;
+        let instance = self
+            .linker
+            .instantiate(&mut store, &self.module)?
+            .start(&mut store)?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| wasmi::Error::new("missing export 'memory'"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")?;
+        let run = instance.get_typed_func::<(i32, i32), i64>(&store, "run")?;
+
+        let mut input = Vec::new();
+        for arg in std::iter::once(name).chain(args.iter().map(String::as_str)) {
+            input.extend_from_slice(&(arg.len() as u32).to_le_bytes());
+            input.extend_from_slice(arg.as_bytes());
+        }
+        let len =
+            i32::try_from(input.len()).map_err(|_| wasmi::Error::new("arguments too large"))?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as u32 as usize, &input)?;
+
+        let reply = run.call(&mut store, (ptr, len))?;
+        let reply = read(&memory, &store, (reply >> 32) as i32, reply as i32)?;
+        Ok(match reply.split_first() {
+            Some((b'+', _)) => CommandResponse::Ok,
+            Some((b'_', _)) => CommandResponse::Null,
+            Some((b'$', value)) => {
+                CommandResponse::Value(String::from_utf8_lossy(value).into_owned())
+            }
+            Some((b':', n)) => match std::str::from_utf8(n).ok().and_then(|n| n.parse().ok()) {
+                Some(n) => CommandResponse::Integer(n),
+                None => return Err(wasmi::Error::new("invalid integer reply")),
+            },
+            Some((b'-', message)) => {
+                CommandResponse::Error(CommandError::err(String::from_utf8_lossy(message)))
+            }
+            _ => return Err(wasmi::Error::new("invalid reply")),
+        })
+    }
+}
+
+impl Plugin for WasmPlugin {
+    fn call(&self, host: &PluginHost, name: &str, args: &[String]) -> CommandResponse {
+        self.run(host, name, args).unwrap_or_else(|e| {
+            let message = match e.as_trap_code() {
+                Some(wasmi::core::TrapCode::OutOfFuel) => "ran out of fuel".to_string(),
+                _ => e.to_string(),
+            };
+            CommandResponse::Error(CommandError::err(format!(
+                "plugin '{}' failed: {}",
+                name, message
+            )))
+        })
+    }
+}
+
+/// Copies `len` bytes at `ptr` out of the guest's memory
+fn read(
+    memory: &Memory,
+    store: impl wasmi::AsContext,
+    ptr: i32,
+    len: i32,
+) -> Result<Vec<u8>, wasmi::Error> {
+    let mut buf = vec![0; len as u32 as usize];
+    memory.read(store, ptr as u32 as usize, &mut buf)?;
+    Ok(buf)
+}
+
+/// The caller's memory and the UTF-8 string at `ptr`
+fn read_str(
+    caller: &Caller<'_, Sandbox>,
+    ptr: i32,
+    len: i32,
+) -> Result<(Memory, String), wasmi::Error> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmi::Error::new("missing export 'memory'"))?;
+    let bytes = read(&memory, caller, ptr, len)?;
+    let text =
+        String::from_utf8(bytes).map_err(|_| wasmi::Error::new("invalid UTF-8 from plugin"))?;
+    Ok((memory, text))
+}
+
+fn host_linker(engine: &Engine) -> Result<Linker<Sandbox>, wasmi::Error> {
+    let mut linker = Linker::new(engine);
+    linker.func_wrap(
+        HOST_MODULE,
+        "get",
+        |mut caller: Caller<'_, Sandbox>, key: i32, key_len: i32, out: i32, out_cap: i32| {
+            let (memory, key) = read_str(&caller, key, key_len)?;
+            let Some(value) = caller.data().host.get(&key) else {
+                return Ok(-1);
+            };
+            if value.len() <= out_cap as u32 as usize {
+                memory.write(&mut caller, out as u32 as usize, value.as_bytes())?;
+            }
+            Ok::<i64, wasmi::Error>(value.len() as i64)
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "set",
+        |caller: Caller<'_, Sandbox>,
+         key: i32,
+         key_len: i32,
+         value: i32,
+         value_len: i32,
+         ttl_ms: i64| {
+            let (_, key) = read_str(&caller, key, key_len)?;
+            let (_, value) = read_str(&caller, value, value_len)?;
+            let ttl = (ttl_ms > 0).then(|| Duration::from_millis(ttl_ms as u64));
+            Ok::<i32, wasmi::Error>(match caller.data().host.set(&key, &value, ttl) {
+                Ok(()) => 0,
+                Err(_) => -1,
+            })
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "del",
+        |caller: Caller<'_, Sandbox>, key: i32, key_len: i32| {
+            let (_, key) = read_str(&caller, key, key_len)?;
+            Ok::<i32, wasmi::Error>(caller.data().host.del(&key) as i32)
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "ttl",
+        |caller: Caller<'_, Sandbox>, key: i32, key_len: i32| {
+            let (_, key) = read_str(&caller, key, key_len)?;
+            Ok::<i64, wasmi::Error>(caller.data().host.ttl(&key))
+        },
+    )?;
+    Ok(linker)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Cache, Config};
+
+    /// RENAMENX-like plugin built on the host API
+    struct Move;
+
+    impl Plugin for Move {
+        fn call(&self, host: &PluginHost, _name: &str, args: &[String]) -> CommandResponse {
+            let [from, to] = args else {
+                return CommandResponse::Error(CommandError::err("usage: MOVE from to"));
+            };
+            let Some(value) = host.get(from) else {
+                return CommandResponse::Integer(0);
+            };
+            if let Err(e) = host.set(to, &value, None) {
+                return CommandResponse::Error(e);
+            }
+            host.del(from);
+            CommandResponse::Integer(1)
+        }
+    }
+
+    #[test]
+    fn test_plugin_commands() {
+        let mut plugins = Plugins::default();
+        plugins.register("move", Arc::new(Move)).unwrap();
+        assert!(plugins.register("GET", Arc::new(Move)).is_err());
+        assert!(plugins.register("MOVE", Arc::new(Move)).is_err());
+        assert_eq!(plugins.names(), ["MOVE"]);
+
+        let cache = Arc::new(Cache::new(Config::default()));
+        let executor = Arc::new_cyclic(|this| {
+            CommandExecutor::new(cache.clone()).with_plugins(plugins, this.clone())
+        });
+        executor.execute(Command::Set {
+            key: "a".into(),
+            value: "v".into(),
+            options: SetOptions::default(),
+            concern: None,
+        });
+
+        let reply = executor.execute_plugin(&[b"move", b"a", b"b"]);
+        assert!(matches!(reply, Some(CommandResponse::Integer(1))));
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_some());
+        assert!(executor.execute_plugin(&[b"NOPE"]).is_none());
+
+        // A check sees every command the plugin issues
+        let read_only: CommandCheck = Arc::new(|command| match command {
+            Command::Get { .. } => Ok(()),
+            _ => Err(CommandError::err("read only")),
+        });
+        let reply = executor.execute_plugin_checked(&[b"move", b"b", b"c"], read_only);
+        assert!(matches!(reply, Some(CommandResponse::Error(_))));
+        assert!(cache.get("b").is_some() && cache.get("c").is_none());
+    }
+
+    /// COPY from to: copies a value through the host API, replying :1, or nil when
+    /// `from` is missing
+    const COPY: &str = r#"
+        (module
+          (import "dashdot" "get" (func $get (param i32 i32 i32 i32) (result i64)))
+          (import "dashdot" "set" (func $set (param i32 i32 i32 i32 i64) (result i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) ":1_")
+          (global $next (mut i32) (i32.const 4096))
+          (func (export "alloc") (param $len i32) (result i32)
+            (global.get $next)
+            (global.set $next (i32.add (global.get $next) (local.get $len))))
+          (func $reply (param $ptr i32) (param $len i32) (result i64)
+            (i64.or
+              (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+              (i64.extend_i32_u (local.get $len))))
+          (func (export "run") (param $ptr i32) (param $len i32) (result i64)
+            (local $from i32) (local $to i32) (local $n i64)
+            (local.set $from
+              (i32.add (local.get $ptr) (i32.add (i32.const 4) (i32.load (local.get $ptr)))))
+            (local.set $to
+              (i32.add (local.get $from) (i32.add (i32.const 4) (i32.load (local.get $from)))))
+            (local.set $n
+              (call $get
+                (i32.add (local.get $from) (i32.const 4)) (i32.load (local.get $from))
+                (i32.const 1024) (i32.const 1024)))
+            (if (i64.lt_s (local.get $n) (i64.const 0))
+              (then (return (call $reply (i32.const 2) (i32.const 1)))))
+            (drop
+              (call $set
+                (i32.add (local.get $to) (i32.const 4)) (i32.load (local.get $to))
+                (i32.const 1024) (i32.wrap_i64 (local.get $n)) (i64.const 0)))
+            (call $reply (i32.const 0) (i32.const 2))))
+    "#;
+
+    /// Module with the required exports whose `run` body is `body`
+    fn module(memory_pages: u32, body: &str) -> Vec<u8> {
+        wat::parse_str(format!(
+            r#"(module
+                 (memory (export "memory") {})
+                 (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                 (func (export "run") (param i32 i32) (result i64) {}))"#,
+            memory_pages, body
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_wasm_plugins() {
+        let dir = std::env::temp_dir().join(format!("dashdot-plugins-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("copy.wasm"), wat::parse_str(COPY).unwrap()).unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a plugin").unwrap();
+        let limits = WasmLimits {
+            fuel: 100_000,
+            ..WasmLimits::default()
+        };
+        let mut plugins = Plugins::default();
+        assert_eq!(plugins.load_dir(&dir, limits).unwrap(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let spin = "(loop $spin (br $spin)) (unreachable)";
+        plugins
+            .register(
+                "spin",
+                Arc::new(WasmPlugin::new(&module(1, spin), limits).unwrap()),
+            )
+            .unwrap();
+        // 512 pages is 32 MiB, over the default 16 MiB memory limit
+        let big = WasmPlugin::new(&module(512, "(i64.const 0)"), limits).unwrap();
+        plugins.register("big", Arc::new(big)).unwrap();
+        assert_eq!(plugins.names(), ["BIG", "COPY", "SPIN"]);
+
+        let cache = Arc::new(Cache::new(Config::default()));
+        let executor = Arc::new_cyclic(|this| {
+            CommandExecutor::new(cache.clone()).with_plugins(plugins, this.clone())
+        });
+        cache
+            .set(
+                "a".into(),
+                crate::cache::Value::String("v".into()),
+                SetOptions::default(),
+            )
+            .unwrap();
+
+        let reply = executor.execute_plugin(&[b"COPY", b"a", b"b"]);
+        assert!(matches!(reply, Some(CommandResponse::Integer(1))));
+        assert_eq!(cache.get("b").unwrap().to_string(), "v");
+        let reply = executor.execute_plugin(&[b"copy", b"missing", b"c"]);
+        assert!(matches!(reply, Some(CommandResponse::Null)));
+
+        // Runaway loops and oversized memories fail the command, not the server
+        let Some(CommandResponse::Error(e)) = executor.execute_plugin(&[b"SPIN"]) else {
+            panic!("expected an error");
+        };
+        assert_eq!(e.message, "plugin 'SPIN' failed: ran out of fuel");
+        assert!(matches!(
+            executor.execute_plugin(&[b"BIG"]),
+            Some(CommandResponse::Error(_))
+        ));
+
+        // Modules only get the host API
+        let wasi = wat::parse_str(
+            r#"(module
+                 (import "wasi_snapshot_preview1" "fd_write"
+                   (func (param i32 i32 i32 i32) (result i32)))
+                 (memory (export "memory") 1)
+                 (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                 (func (export "run") (param i32 i32) (result i64) (i64.const 0)))"#,
+        )
+        .unwrap();
+        assert!(WasmPlugin::new(&wasi, limits).is_err());
+        let no_run = wat::parse_str(r#"(module (memory (export "memory") 1))"#).unwrap();
+        assert!(WasmPlugin::new(&no_run, limits).is_err());
+    }
+}
@@ -0,0 +1,345 @@
+use crate::cache::Cache;
+use crate::snapshot;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+/// Bytes of recent writes kept for replicas resuming with PSYNC
+pub const DEFAULT_BACKLOG_SIZE: usize = 1024 * 1024;
+/// Writes buffered per replica before a slow one is dropped and has to resync
+const REPLICA_BUFFER: usize = 16 * 1024;
+/// Keepalive sent down idle links, well inside a replica's timeout
+const PING_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplicaInfo {
+    pub addr: SocketAddr,
+    pub ack_offset: u64, // Last offset the replica acknowledged
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PrimaryStatus {
+    pub replid: String,
+    pub offset: u64,
+    pub replicas: Vec<ReplicaInfo>,
+}
+
+/// Recent writes as sent to replicas, and where they sit in the stream
+struct Backlog {
+    buf: VecDeque<u8>,
+    offset: u64,  // Bytes propagated since this node started acting as a primary
+    active: bool, // Set by the first sync; until then writes aren't encoded at all
+    tx: broadcast::Sender<Arc<Vec<u8>>>,
+}
+
+/// How a PSYNC is answered: the reply line, then what follows it
+pub struct SyncPlan {
+    reply: String,
+    payload: Vec<u8>,
+    rx: broadcast::Receiver<Arc<Vec<u8>>>,
+}
+
+/// The primary side of replication. Every successful write goes out to connected
+/// replicas in the same RESP form the AOF logs; a replica joining or falling too
+/// far behind gets a snapshot first.
+pub struct Primary {
+    replid: String,
+    backlog_size: usize,
+    writes: RwLock<()>, // Held for reading per write, and for writing while a full sync dumps
+    backlog: Mutex<Backlog>,
+    replicas: Mutex<HashMap<SocketAddr, Arc<AtomicU64>>>,
+}
+
+impl Primary {
+    pub fn new(backlog_size: usize) -> Self {
+        Self {
+            replid: (0..40)
+                .map(|_| char::from_digit(rand::random_range(0..16), 16).unwrap())
+                .collect(),
+            backlog_size,
+            writes: RwLock::new(()),
+            backlog: Mutex::new(Backlog {
+                buf: VecDeque::new(),
+                offset: 0,
+                active: false,
+                tx: broadcast::channel(REPLICA_BUFFER).0,
+            }),
+            replicas: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Held while a write command is applied and propagated
+    pub fn begin_write(&self) -> RwLockReadGuard<'_, ()> {
+        self.writes.read().unwrap()
+    }
+
+    /// Sends a successful write to the replicas and keeps it in the backlog
+    pub fn propagate(&self, args: &[Vec<u8>]) {
+        let mut backlog = self.backlog.lock().unwrap();
+        if !backlog.active {
+            return;
+        }
+        let mut record = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            record.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            record.extend_from_slice(arg);
+            record.extend_from_slice(b"\r\n");
+        }
+
+        backlog.offset += record.len() as u64;
+        backlog.buf.extend(&record);
+        let excess = backlog.buf.len().saturating_sub(self.backlog_size);
+        backlog.buf.drain(..excess);
+        // No receivers just means no replica is connected right now
+        let _ = backlog.tx.send(Arc::new(record));
+    }
+
+    /// Plans the answer to `PSYNC replid offset`. A replica that was following this
+    /// primary and is still within the backlog continues from there; any other gets
+    /// a snapshot, taken while writes wait so it lines up exactly with the offset.
+    pub fn plan_sync(&self, cache: &Cache, replid: &str, offset: i64) -> SyncPlan {
+        {
+            let mut backlog = self.backlog.lock().unwrap();
+            backlog.active = true;
+            let start = backlog.offset - backlog.buf.len() as u64;
+            // The replica asks for the first byte it's missing
+            if replid == self.replid
+                && let Ok(next) = u64::try_from(offset)
+                && (start + 1..=backlog.offset + 1).contains(&next)
+            {
+                let skip = (next - 1 - start) as usize;
+                return SyncPlan {
+                    reply: format!("+CONTINUE {}\r\n", self.replid),
+                    payload: backlog.buf.iter().skip(skip).copied().collect(),
+                    rx: backlog.tx.subscribe(),
+                };
+            }
+        }
+
+        let _writes = self.writes.write().unwrap();
+        let dump = snapshot::dump(cache);
+        let backlog = self.backlog.lock().unwrap();
+        let mut payload = format!("${}\r\n", dump.len()).into_bytes();
+        payload.extend(dump);
+        SyncPlan {
+            reply: format!("+FULLRESYNC {} {}\r\n", self.replid, backlog.offset),
+            payload,
+            rx: backlog.tx.subscribe(),
+        }
+    }
+
+    /// Feeds a replica connection that sent PSYNC until it goes away or falls behind.
+    /// `input` is whatever the connection had buffered after the PSYNC.
+    pub async fn serve(
+        &self,
+        mut stream: TcpStream,
+        peer: SocketAddr,
+        mut input: Vec<u8>,
+        mut plan: SyncPlan,
+    ) -> io::Result<()> {
+        info!("Replica {} syncing ({})", peer, plan.reply.trim_end());
+        stream.write_all(plan.reply.as_bytes()).await?;
+        stream.write_all(&plan.payload).await?;
+
+        let ack = Arc::new(AtomicU64::new(0));
+        self.replicas.lock().unwrap().insert(peer, ack.clone());
+        let result = async {
+            loop {
+                tokio::select! {
+                    record = plan.rx.recv() => match record {
+                        Ok(record) => stream.write_all(&record).await?,
+                        Err(broadcast::error::RecvError::Lagged(_)) => {
+                            warn!("Replica {} fell behind; dropping it to resync", peer);
+                            return Ok(());
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                    },
+                    read = stream.read_buf(&mut input) => {
+                        if read? == 0 {
+                            return Ok(());
+                        }
+                        record_acks(&mut input, &ack);
+                    }
+                }
+            }
+        }
+        .await;
+        self.replicas.lock().unwrap().remove(&peer);
+        info!("Replica {} disconnected", peer);
+        result
+    }
+
+    pub fn status(&self) -> PrimaryStatus {
+        let offset = self.backlog.lock().unwrap().offset;
+        let replicas = self.replicas.lock().unwrap();
+        PrimaryStatus {
+            replid: self.replid.clone(),
+            offset,
+            replicas: replicas
+                .iter()
+                .map(|(addr, ack)| ReplicaInfo {
+                    addr: *addr,
+                    ack_offset: ack.load(Ordering::Relaxed),
+                })
+                .collect(),
+        }
+    }
+
+    /// Pings replicas while any are connected, so idle links aren't timed out
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(PING_INTERVAL);
+        loop {
+            interval.tick().await;
+            if !self.replicas.lock().unwrap().is_empty() {
+                self.propagate(&[b"PING".to_vec()]);
+            }
+        }
+    }
+}
+
+/// Consumes the `REPLCONF ACK <offset>` commands replicas send back, keeping the
+/// latest offset. Anything else from a replica is ignored.
+fn record_acks(input: &mut Vec<u8>, ack: &AtomicU64) {
+    use redis_protocol::resp2::decode::decode;
+    use redis_protocol::resp2::types::OwnedFrame;
+
+    while let Ok(Some((frame, used))) = decode(input) {
+        input.drain(..used);
+        let OwnedFrame::Array(args) = frame else {
+            continue;
+        };
+        if let [
+            _,
+            OwnedFrame::BulkString(sub),
+            OwnedFrame::BulkString(offset),
+        ] = args.as_slice()
+            && sub.eq_ignore_ascii_case(b"ACK")
+            && let Some(offset) = std::str::from_utf8(offset)
+                .ok()
+                .and_then(|o| o.parse().ok())
+        {
+            ack.store(offset, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Config, SetOptions, Value};
+    use crate::executor::{Command, CommandExecutor};
+    use crate::replica::{LinkState, Replication};
+    use crate::resp_api::RespServer;
+
+    async fn wait_until(check: impl Fn() -> bool) {
+        for _ in 0..500 {
+            if check() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replica_follows_primary() {
+        let primary_cache = Arc::new(Cache::new(Config::default()));
+        primary_cache
+            .set(
+                "before".into(),
+                Value::String("1".into()),
+                SetOptions::default(),
+            )
+            .unwrap();
+        let primary = Arc::new(Primary::new(DEFAULT_BACKLOG_SIZE));
+        let executor =
+            Arc::new(CommandExecutor::new(primary_cache.clone()).with_primary(primary.clone()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = RespServer::new(executor.clone());
+        tokio::spawn(async move { server.serve(listener).await });
+
+        let replica_cache = Arc::new(Cache::new(Config::default()));
+        let replication = Replication::new(replica_cache.clone(), 0);
+        replication.replicate_from(addr.to_string());
+
+        wait_until(|| {
+            replication
+                .status()
+                .is_some_and(|s| s.state == LinkState::Streaming)
+        })
+        .await;
+        assert!(replica_cache.get("before").is_some());
+
+        executor.execute(Command::Set {
+            key: "after".into(),
+            value: "2".into(),
+            options: SetOptions::default(),
+            concern: None,
+        });
+        executor.execute(Command::Del {
+            keys: vec!["before".into()],
+            cascade: false,
+        });
+        wait_until(|| replica_cache.get("before").is_none()).await;
+        assert_eq!(replica_cache.get("after"), Some(Value::String("2".into())));
+        assert_eq!(primary.status().replicas.len(), 1);
+
+        // Client writes are refused while the link is up
+        let read_only =
+            CommandExecutor::new(replica_cache.clone()).with_middleware(replication.read_only());
+        let write = read_only.execute(Command::Del {
+            keys: vec!["after".into()],
+            cascade: false,
+        });
+        assert!(matches!(write, crate::executor::CommandResponse::Error(_)));
+        replication.stop();
+        let write = read_only.execute(Command::Del {
+            keys: vec!["after".into()],
+            cascade: false,
+        });
+        assert!(matches!(
+            write,
+            crate::executor::CommandResponse::Integer(1)
+        ));
+    }
+
+    #[test]
+    fn test_partial_resync_from_backlog() {
+        let cache = Cache::new(Config::default());
+        let primary = Primary::new(64);
+        let set = |key: &str| vec![b"SET".to_vec(), key.as_bytes().to_vec(), b"v".to_vec()];
+
+        // Nothing is kept until a replica first syncs
+        primary.propagate(&set("a"));
+        let full = primary.plan_sync(&cache, "?", -1);
+        assert!(full.reply.starts_with("+FULLRESYNC"));
+        assert!(full.reply.ends_with(" 0\r\n"));
+
+        primary.propagate(&set("b"));
+        primary.propagate(&set("c"));
+        let record_len = 27;
+        let resumed = primary.plan_sync(&cache, &primary.replid, record_len + 1);
+        assert!(resumed.reply.starts_with("+CONTINUE"));
+        assert_eq!(
+            resumed.payload,
+            b"*3\r\n$3\r\nSET\r\n$1\r\nc\r\n$1\r\nv\r\n"
+        );
+
+        // Past the end of the backlog, or from another primary, it starts over
+        for _ in 0..4 {
+            primary.propagate(&set("d"));
+        }
+        let stale = primary.plan_sync(&cache, &primary.replid, 1);
+        assert!(stale.reply.starts_with("+FULLRESYNC"));
+        let other = primary.plan_sync(&cache, "0000", record_len + 1);
+        assert!(other.reply.starts_with("+FULLRESYNC"));
+    }
+}
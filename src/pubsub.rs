@@ -0,0 +1,429 @@
+use crate::metrics::Labels;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::{self, Write as _};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+/// Messages buffered per subscriber; one that falls this far behind is disconnected,
+/// as Redis does past its pub/sub output buffer limit
+const SUBSCRIBER_QUEUE: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub channel: String,
+    pub payload: String,
+    pub pattern: Option<String>, // The subscription that matched, for PSUBSCRIBE
+}
+
+type Subscriptions = HashMap<String, HashSet<u64>>;
+
+#[derive(Default)]
+struct Registry {
+    channels: Subscriptions,
+    patterns: Subscriptions,
+    senders: HashMap<u64, mpsc::Sender<Message>>,
+}
+
+fn join(subscriptions: &mut Subscriptions, name: &str, id: u64) {
+    subscriptions
+        .entry(name.to_string())
+        .or_default()
+        .insert(id);
+}
+
+fn leave(subscriptions: &mut Subscriptions, name: &str, id: u64) {
+    if let Some(ids) = subscriptions.get_mut(name) {
+        ids.remove(&id);
+        if ids.is_empty() {
+            subscriptions.remove(name);
+        }
+    }
+}
+
+/// Channel-based publish/subscribe, to channels by name or by glob pattern. Nothing is
+/// stored: a message reaches whoever is subscribed when it's published.
+#[derive(Default)]
+pub struct PubSub {
+    registry: Mutex<Registry>,
+    next_id: AtomicU64,
+    published: AtomicU64,
+    disconnected: AtomicU64, // Subscribers dropped for falling behind
+}
+
+impl PubSub {
+    /// A new subscriber with no subscriptions; dropping it unsubscribes everything
+    pub fn subscriber(self: &Arc<Self>) -> Subscriber {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_QUEUE);
+        self.registry.lock().unwrap().senders.insert(id, tx);
+        Subscriber {
+            id,
+            pubsub: self.clone(),
+            channels: BTreeSet::new(),
+            patterns: BTreeSet::new(),
+            rx,
+        }
+    }
+
+    /// Sends a message to the channel's subscribers and to those of every matching
+    /// pattern, returning how many deliveries were made. A connection subscribed both
+    /// ways gets the message once per subscription, as with Redis.
+    pub fn publish(&self, channel: &str, payload: &str) -> usize {
+        self.published.fetch_add(1, Ordering::Relaxed);
+        let mut registry = self.registry.lock().unwrap();
+
+        let direct = registry.channels.get(channel).map(|ids| (None, ids));
+        let matched = registry
+            .patterns
+            .iter()
+            .filter(|(pattern, _)| glob_match(pattern, channel))
+            .map(|(pattern, ids)| (Some(pattern), ids));
+        let mut received = 0;
+        let mut slow = Vec::new();
+        for (pattern, ids) in direct.into_iter().chain(matched) {
+            let message = Message {
+                channel: channel.to_string(),
+                payload: payload.to_string(),
+                pattern: pattern.cloned(),
+            };
+            for id in ids {
+                let Some(tx) = registry.senders.get(id) else {
+                    continue;
+                };
+                match tx.try_send(message.clone()) {
+                    Ok(()) => received += 1,
+                    Err(_) => slow.push(*id),
+                }
+            }
+        }
+        // Without its sender the subscriber drains what it has, then sees the end
+        for id in slow {
+            if registry.senders.remove(&id).is_some() {
+                self.disconnected.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        received
+    }
+
+    /// Channels with at least one subscriber
+    pub fn channels(&self) -> Vec<String> {
+        let mut channels: Vec<_> = self
+            .registry
+            .lock()
+            .unwrap()
+            .channels
+            .keys()
+            .cloned()
+            .collect();
+        channels.sort();
+        channels
+    }
+
+    pub fn render_metrics(&self, out: &mut String, labels: &Labels) {
+        let series = labels.series();
+        let channels = self.registry.lock().unwrap().channels.len();
+        writeln!(
+            out,
+            "# HELP cache_pubsub_channels Channels with subscribers"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE cache_pubsub_channels gauge").unwrap();
+        writeln!(out, "cache_pubsub_channels{} {}", series, channels).unwrap();
+        writeln!(
+            out,
+            "# HELP cache_pubsub_published_total Messages published"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE cache_pubsub_published_total counter").unwrap();
+        writeln!(
+            out,
+            "cache_pubsub_published_total{} {}",
+            series,
+            self.published.load(Ordering::Relaxed)
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "# HELP cache_pubsub_disconnected_total Subscribers disconnected for falling behind"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE cache_pubsub_disconnected_total counter").unwrap();
+        writeln!(
+            out,
+            "cache_pubsub_disconnected_total{} {}",
+            series,
+            self.disconnected.load(Ordering::Relaxed)
+        )
+        .unwrap();
+    }
+}
+
+/// One connection's subscriptions and the messages waiting for it
+pub struct Subscriber {
+    id: u64,
+    pubsub: Arc<PubSub>,
+    channels: BTreeSet<String>,
+    patterns: BTreeSet<String>,
+    rx: mpsc::Receiver<Message>,
+}
+
+impl Subscriber {
+    /// Subscribes to a channel, returning the number of subscriptions afterwards
+    pub fn subscribe(&mut self, channel: &str) -> usize {
+        if self.channels.insert(channel.to_string()) {
+            join(
+                &mut self.pubsub.registry.lock().unwrap().channels,
+                channel,
+                self.id,
+            );
+        }
+        self.count()
+    }
+
+    /// Unsubscribes from a channel, returning the number of subscriptions afterwards
+    pub fn unsubscribe(&mut self, channel: &str) -> usize {
+        if self.channels.remove(channel) {
+            leave(
+                &mut self.pubsub.registry.lock().unwrap().channels,
+                channel,
+                self.id,
+            );
+        }
+        self.count()
+    }
+
+    /// Subscribes to every channel matching a glob pattern
+    pub fn psubscribe(&mut self, pattern: &str) -> usize {
+        if self.patterns.insert(pattern.to_string()) {
+            join(
+                &mut self.pubsub.registry.lock().unwrap().patterns,
+                pattern,
+                self.id,
+            );
+        }
+        self.count()
+    }
+
+    pub fn punsubscribe(&mut self, pattern: &str) -> usize {
+        if self.patterns.remove(pattern) {
+            leave(
+                &mut self.pubsub.registry.lock().unwrap().patterns,
+                pattern,
+                self.id,
+            );
+        }
+        self.count()
+    }
+
+    /// Subscribed channels, in order
+    pub fn channels(&self) -> Vec<String> {
+        self.channels.iter().cloned().collect()
+    }
+
+    /// Subscribed patterns, in order
+    pub fn patterns(&self) -> Vec<String> {
+        self.patterns.iter().cloned().collect()
+    }
+
+    /// Channel and pattern subscriptions together, as Redis reports them
+    pub fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    /// Next message; None once the subscriber was disconnected for falling behind
+    pub async fn recv(&mut self) -> Option<Message> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        for channel in self.channels() {
+            self.unsubscribe(&channel);
+        }
+        for pattern in self.patterns() {
+            self.punsubscribe(&pattern);
+        }
+        self.pubsub
+            .registry
+            .lock()
+            .unwrap()
+            .senders
+            .remove(&self.id);
+    }
+}
+
+impl fmt::Debug for Subscriber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscriber")
+            .field("id", &self.id)
+            .field("channels", &self.channels)
+            .field("patterns", &self.patterns)
+            .finish()
+    }
+}
+
+/// Redis-style glob matching: `*` and `?` wildcards, `[abc]`, `[a-z]` and `[^abc]`
+/// classes, and `\` escaping the next character
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let (p, s) = (pattern.as_bytes(), name.as_bytes());
+    let (mut pi, mut si) = (0, 0);
+    // After a mismatch, retry from the last `*` with it absorbing one more byte
+    let mut star: Option<(usize, usize)> = None;
+    while si < s.len() {
+        if p.get(pi) == Some(&b'*') {
+            pi += 1;
+            star = Some((pi, si));
+            continue;
+        }
+        if pi < p.len()
+            && let Some(next) = match_one(p, pi, s[si])
+        {
+            pi = next;
+            si += 1;
+            continue;
+        }
+        let Some((after, from)) = star else {
+            return false;
+        };
+        star = Some((after, from + 1));
+        pi = after;
+        si = from + 1;
+    }
+    p[pi..].iter().all(|&b| b == b'*')
+}
+
+/// Matches one byte against the pattern element at `pi`, returning where the next
+/// element starts
+fn match_one(p: &[u8], pi: usize, c: u8) -> Option<usize> {
+    match p[pi] {
+        b'?' => Some(pi + 1),
+        b'[' => {
+            let (matched, next) = match_class(p, pi, c);
+            matched.then_some(next)
+        }
+        b'\\' if pi + 1 < p.len() => (p[pi + 1] == c).then_some(pi + 2),
+        literal => (literal == c).then_some(pi + 1),
+    }
+}
+
+/// Matches a byte against the class opening at `start`. An unterminated class runs to
+/// the end of the pattern, as in Redis.
+fn match_class(p: &[u8], start: usize, c: u8) -> (bool, usize) {
+    let mut i = start + 1;
+    let negate = p.get(i) == Some(&b'^');
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    while i < p.len() && p[i] != b']' {
+        if p[i] == b'\\' && i + 1 < p.len() {
+            matched |= p[i + 1] == c;
+            i += 2;
+        } else if i + 2 < p.len() && p[i + 1] == b'-' && p[i + 2] != b']' {
+            let (low, high) = (p[i].min(p[i + 2]), p[i].max(p[i + 2]));
+            matched |= (low..=high).contains(&c);
+            i += 3;
+        } else {
+            matched |= p[i] == c;
+            i += 1;
+        }
+    }
+    (matched != negate, (i + 1).min(p.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_publish_fan_out() {
+        let pubsub = Arc::new(PubSub::default());
+        let mut a = pubsub.subscriber();
+        let mut b = pubsub.subscriber();
+        assert_eq!(a.subscribe("news"), 1);
+        assert_eq!(a.subscribe("news"), 1);
+        assert_eq!(a.subscribe("sports"), 2);
+        assert_eq!(b.subscribe("news"), 1);
+
+        assert_eq!(pubsub.publish("news", "hello"), 2);
+        assert_eq!(pubsub.publish("weather", "rain"), 0);
+        let expected = Message {
+            channel: "news".into(),
+            payload: "hello".into(),
+            pattern: None,
+        };
+        assert_eq!(a.recv().await, Some(expected.clone()));
+        assert_eq!(b.recv().await, Some(expected));
+
+        assert_eq!(b.unsubscribe("news"), 0);
+        assert_eq!(pubsub.publish("news", "again"), 1);
+        assert_eq!(pubsub.channels(), vec!["news", "sports"]);
+        drop(a);
+        assert!(pubsub.channels().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pattern_subscriptions() {
+        let pubsub = Arc::new(PubSub::default());
+        let mut orders = pubsub.subscriber();
+        assert_eq!(orders.subscribe("orders.eu"), 1);
+        assert_eq!(orders.psubscribe("orders.*"), 2);
+        assert_eq!(orders.psubscribe("orders.??"), 3);
+
+        // Once directly and once per matching pattern
+        assert_eq!(pubsub.publish("orders.eu", "1"), 3);
+        assert_eq!(pubsub.publish("orders.apac", "2"), 1);
+        assert_eq!(pubsub.publish("users.eu", "3"), 0);
+        let mut received: Vec<_> = std::iter::from_fn(|| orders.rx.try_recv().ok())
+            .map(|message| (message.payload, message.pattern))
+            .collect();
+        received.sort();
+        let pattern = |p: &str| Some(p.to_string());
+        assert_eq!(
+            received,
+            [
+                ("1".to_string(), None),
+                ("1".to_string(), pattern("orders.*")),
+                ("1".to_string(), pattern("orders.??")),
+                ("2".to_string(), pattern("orders.*")),
+            ]
+        );
+        assert_eq!(orders.punsubscribe("orders.??"), 2);
+        drop(orders);
+        assert_eq!(pubsub.publish("orders.eu", "4"), 0);
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("orders.*", "orders.eu.paid"));
+        assert!(glob_match("*.paid", "orders.eu.paid"));
+        assert!(glob_match("o*s.*.p?id", "orders.eu.paid"));
+        assert!(!glob_match("orders.?", "orders.eu"));
+        assert!(glob_match("h[ae]llo", "hallo"));
+        assert!(!glob_match("h[^e]llo", "hello"));
+        assert!(glob_match("h[a-c]llo", "hbllo"));
+        assert!(glob_match("h[c-a]llo", "hbllo"));
+        assert!(!glob_match("h[a-c]llo", "hdllo"));
+        assert!(glob_match("a\\*b", "a*b"));
+        assert!(!glob_match("a\\*b", "axb"));
+        assert!(glob_match("a[\\]]b", "a]b"));
+        assert!(glob_match("*a*a*a*b", "aaaaaaaaaaaaaaaaaaaab"));
+        assert!(!glob_match("*a*a*a*b", "aaaaaaaaaaaaaaaaaaaaa"));
+    }
+
+    #[test]
+    fn test_slow_subscriber_is_disconnected() {
+        let pubsub = Arc::new(PubSub::default());
+        let mut slow = pubsub.subscriber();
+        slow.subscribe("c");
+        for _ in 0..SUBSCRIBER_QUEUE {
+            assert_eq!(pubsub.publish("c", "m"), 1);
+        }
+        assert_eq!(pubsub.publish("c", "m"), 0);
+        assert_eq!(pubsub.disconnected.load(Ordering::Relaxed), 1);
+        assert_eq!(pubsub.publish("c", "m"), 0);
+    }
+}
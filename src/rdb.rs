@@ -0,0 +1,614 @@
+use crate::cache::{Cache, MAX_STRING_LEN, SetOptions, Value};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, ErrorKind};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+// Opcodes
+const OP_FUNCTION: u8 = 0xF5;
+const OP_MODULE_AUX: u8 = 0xF7;
+const OP_IDLE: u8 = 0xF8;
+const OP_FREQ: u8 = 0xF9;
+const OP_AUX: u8 = 0xFA;
+const OP_RESIZEDB: u8 = 0xFB;
+const OP_EXPIRETIME_MS: u8 = 0xFC;
+const OP_EXPIRETIME: u8 = 0xFD;
+const OP_SELECTDB: u8 = 0xFE;
+const OP_EOF: u8 = 0xFF;
+
+// Value types
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_SET_LISTPACK: u8 = 20;
+
+/// Oldest and newest RDB versions understood (Redis 2.6 through 7.4)
+const MIN_VERSION: u32 = 6;
+const MAX_VERSION: u32 = 12;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RdbImport {
+    pub imported: usize,
+    pub expired: usize,     // TTL already passed
+    pub unsupported: usize, // Sorted sets, which the cache has no type for
+    pub rejected: usize,    // Refused by the cache, e.g. over max_memory
+}
+
+/// Loads the string, list, set and hash keys of a Redis RDB dump into `cache`, with
+/// their TTLs. Keys from every database land in the one keyspace. Sorted sets are
+/// skipped; streams and module types can't be skipped over and fail the import.
+pub fn import(cache: &Cache, rdb: &[u8]) -> io::Result<RdbImport> {
+    let mut reader = Reader { buf: rdb, pos: 0 };
+    if reader.take(5)? != b"REDIS" {
+        return Err(invalid("not an RDB file"));
+    }
+    let version: u32 = std::str::from_utf8(reader.take(4)?)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| invalid("bad RDB version"))?;
+    if !(MIN_VERSION..=MAX_VERSION).contains(&version) {
+        return Err(invalid(&format!("unsupported RDB version {}", version)));
+    }
+
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let mut report = RdbImport::default();
+    let mut expires_at = None;
+    loop {
+        match reader.u8()? {
+            OP_EOF => return Ok(report), // The trailing CRC64 isn't verified
+            OP_AUX => {
+                reader.string()?;
+                reader.string()?;
+            }
+            OP_SELECTDB => {
+                reader.length()?;
+            }
+            OP_RESIZEDB => {
+                reader.length()?;
+                reader.length()?;
+            }
+            OP_EXPIRETIME_MS => expires_at = Some(reader.u64_le()?),
+            OP_EXPIRETIME => expires_at = Some(reader.u32_le()? as u64 * 1000),
+            OP_IDLE => {
+                reader.length()?;
+            }
+            OP_FREQ => {
+                reader.u8()?;
+            }
+            OP_MODULE_AUX | OP_FUNCTION => {
+                return Err(invalid("modules and functions aren't supported"));
+            }
+            value_type => {
+                let key = reader.string()?;
+                let value = reader.value(value_type)?;
+                let ttl = expires_at.take().map(|at| at.saturating_sub(now_ms));
+                let Some(value) = value else {
+                    report.unsupported += 1;
+                    continue;
+                };
+                if ttl == Some(0) {
+                    report.expired += 1;
+                    continue;
+                }
+                let options = SetOptions {
+                    ttl: ttl.map(Duration::from_millis),
+                    ..SetOptions::default()
+                };
+                match cache.set(lossy(key), value, options) {
+                    Ok(_) => report.imported += 1,
+                    Err(e) => {
+                        warn!("Skipping RDB key: {}", e);
+                        report.rejected += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn lossy(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+}
+
+fn list(items: Vec<Vec<u8>>) -> Value {
+    Value::List(items.into_iter().map(Value::from_bytes).collect())
+}
+
+fn set(members: Vec<Vec<u8>>) -> Value {
+    Value::Set(members.into_iter().map(lossy).collect::<HashSet<_>>())
+}
+
+/// Field/value pairs, as hashes are flattened in ziplists and listpacks
+fn hash(flat: Vec<Vec<u8>>) -> io::Result<Value> {
+    if !flat.len().is_multiple_of(2) {
+        return Err(invalid("hash with an odd number of elements"));
+    }
+    let mut fields = HashMap::with_capacity(flat.len() / 2);
+    let mut items = flat.into_iter();
+    while let (Some(field), Some(value)) = (items.next(), items.next()) {
+        fields.insert(lossy(field), Value::from_bytes(value));
+    }
+    Ok(Value::Hash(fields))
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.buf.len())
+            .ok_or_else(|| io::Error::from(ErrorKind::UnexpectedEof))?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32_le(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64_le(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// A length, or the special string encoding number when the top bits are 11
+    fn length_or_encoding(&mut self) -> io::Result<(u64, bool)> {
+        let first = self.u8()?;
+        Ok(match first >> 6 {
+            0 => ((first & 0x3f) as u64, false),
+            1 => (((first as u64 & 0x3f) << 8) | self.u8()? as u64, false),
+            2 => match first {
+                0x80 => (
+                    u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64,
+                    false,
+                ),
+                0x81 => (u64::from_be_bytes(self.take(8)?.try_into().unwrap()), false),
+                _ => return Err(invalid("bad length encoding")),
+            },
+            _ => ((first & 0x3f) as u64, true),
+        })
+    }
+
+    fn length(&mut self) -> io::Result<usize> {
+        match self.length_or_encoding()? {
+            (len, false) => usize::try_from(len).map_err(|_| invalid("length out of range")),
+            (_, true) => Err(invalid("expected a length")),
+        }
+    }
+
+    /// Element count, bounded by the bytes left since every element takes at least one
+    fn count(&mut self) -> io::Result<usize> {
+        let count = self.length()?;
+        if count > self.buf.len() - self.pos {
+            return Err(io::Error::from(ErrorKind::UnexpectedEof));
+        }
+        Ok(count)
+    }
+
+    fn string(&mut self) -> io::Result<Vec<u8>> {
+        match self.length_or_encoding()? {
+            (len, false) => Ok(self.take(len as usize)?.to_vec()),
+            (0, true) => Ok((self.u8()? as i8).to_string().into_bytes()),
+            (1, true) => {
+                let n = i16::from_le_bytes(self.take(2)?.try_into().unwrap());
+                Ok(n.to_string().into_bytes())
+            }
+            (2, true) => {
+                let n = i32::from_le_bytes(self.take(4)?.try_into().unwrap());
+                Ok(n.to_string().into_bytes())
+            }
+            (3, true) => {
+                let compressed_len = self.length()?;
+                let len = self.length()?;
+                lzf_decompress(self.take(compressed_len)?, len)
+            }
+            _ => Err(invalid("unknown string encoding")),
+        }
+    }
+
+    fn strings(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let count = self.count()?;
+        (0..count).map(|_| self.string()).collect()
+    }
+
+    /// None for types read past but not imported
+    fn value(&mut self, value_type: u8) -> io::Result<Option<Value>> {
+        Ok(Some(match value_type {
+            TYPE_STRING => Value::from_bytes(self.string()?),
+            TYPE_LIST => list(self.strings()?),
+            TYPE_SET => set(self.strings()?),
+            TYPE_HASH => {
+                let count = self.count()?;
+                let mut flat = Vec::with_capacity(count * 2);
+                for _ in 0..count {
+                    flat.push(self.string()?);
+                    flat.push(self.string()?);
+                }
+                hash(flat)?
+            }
+            TYPE_ZSET | TYPE_ZSET_2 => {
+                let count = self.count()?;
+                for _ in 0..count {
+                    self.string()?;
+                    if value_type == TYPE_ZSET_2 {
+                        self.take(8)?;
+                    } else {
+                        let len = self.u8()?;
+                        if len < 253 {
+                            self.take(len as usize)?;
+                        }
+                    }
+                }
+                return Ok(None);
+            }
+            TYPE_ZSET_ZIPLIST | TYPE_ZSET_LISTPACK => {
+                self.string()?;
+                return Ok(None);
+            }
+            TYPE_LIST_ZIPLIST => list(ziplist(&self.string()?)?),
+            TYPE_HASH_ZIPLIST => hash(ziplist(&self.string()?)?)?,
+            TYPE_SET_INTSET => set(intset(&self.string()?)?),
+            TYPE_HASH_LISTPACK => hash(listpack(&self.string()?)?)?,
+            TYPE_SET_LISTPACK => set(listpack(&self.string()?)?),
+            TYPE_LIST_QUICKLIST => {
+                let nodes = self.count()?;
+                let mut items = Vec::new();
+                for _ in 0..nodes {
+                    items.extend(ziplist(&self.string()?)?);
+                }
+                list(items)
+            }
+            TYPE_LIST_QUICKLIST_2 => {
+                const PLAIN: usize = 1;
+                let nodes = self.count()?;
+                let mut items = Vec::new();
+                for _ in 0..nodes {
+                    let container = self.length()?;
+                    let node = self.string()?;
+                    if container == PLAIN {
+                        items.push(node);
+                    } else {
+                        items.extend(listpack(&node)?);
+                    }
+                }
+                list(items)
+            }
+            other => return Err(invalid(&format!("unsupported value type {}", other))),
+        }))
+    }
+}
+
+/// Entries of a ziplist: a 10 byte header, entries, then 0xFF
+fn ziplist(buf: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let mut r = Reader { buf, pos: 10 };
+    let mut items = Vec::new();
+    loop {
+        let prev_len = r.u8()?;
+        if prev_len == 0xFF {
+            return Ok(items);
+        }
+        if prev_len == 0xFE {
+            r.take(4)?;
+        }
+        let encoding = r.u8()?;
+        let item = match encoding >> 6 {
+            0 => r.take((encoding & 0x3f) as usize)?.to_vec(),
+            1 => {
+                let len = ((encoding as usize & 0x3f) << 8) | r.u8()? as usize;
+                r.take(len)?.to_vec()
+            }
+            2 => {
+                let len = u32::from_be_bytes(r.take(4)?.try_into().unwrap());
+                r.take(len as usize)?.to_vec()
+            }
+            _ => {
+                let n: i64 = match encoding {
+                    0xC0 => i16::from_le_bytes(r.take(2)?.try_into().unwrap()) as i64,
+                    0xD0 => i32::from_le_bytes(r.take(4)?.try_into().unwrap()) as i64,
+                    0xE0 => i64::from_le_bytes(r.take(8)?.try_into().unwrap()),
+                    0xF0 => {
+                        let b = r.take(3)?;
+                        (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as i64
+                    }
+                    0xFE => r.u8()? as i8 as i64,
+                    0xF1..=0xFD => (encoding & 0x0f) as i64 - 1,
+                    _ => return Err(invalid("bad ziplist entry encoding")),
+                };
+                n.to_string().into_bytes()
+            }
+        };
+        items.push(item);
+    }
+}
+
+/// Entries of a listpack: a 6 byte header, entries each followed by a back-length,
+/// then 0xFF
+fn listpack(buf: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let mut r = Reader { buf, pos: 6 };
+    let mut items = Vec::new();
+    loop {
+        let start = r.pos;
+        let encoding = r.u8()?;
+        let item = if encoding == 0xFF {
+            return Ok(items);
+        } else if encoding & 0x80 == 0 {
+            (encoding as i64).to_string().into_bytes()
+        } else if encoding & 0xC0 == 0x80 {
+            r.take((encoding & 0x3f) as usize)?.to_vec()
+        } else if encoding & 0xE0 == 0xC0 {
+            let raw = ((encoding as u16 & 0x1f) << 8) | r.u8()? as u16;
+            // 13-bit two's complement
+            (((raw << 3) as i16) >> 3).to_string().into_bytes()
+        } else if encoding & 0xF0 == 0xE0 {
+            let len = ((encoding as usize & 0x0f) << 8) | r.u8()? as usize;
+            r.take(len)?.to_vec()
+        } else {
+            match encoding {
+                0xF0 => {
+                    let len = r.u32_le()?;
+                    r.take(len as usize)?.to_vec()
+                }
+                0xF1 => i16::from_le_bytes(r.take(2)?.try_into().unwrap())
+                    .to_string()
+                    .into_bytes(),
+                0xF2 => {
+                    let b = r.take(3)?;
+                    (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8)
+                        .to_string()
+                        .into_bytes()
+                }
+                0xF3 => i32::from_le_bytes(r.take(4)?.try_into().unwrap())
+                    .to_string()
+                    .into_bytes(),
+                0xF4 => i64::from_le_bytes(r.take(8)?.try_into().unwrap())
+                    .to_string()
+                    .into_bytes(),
+                _ => return Err(invalid("bad listpack entry encoding")),
+            }
+        };
+        r.take(backlen_size(r.pos - start))?;
+        items.push(item);
+    }
+}
+
+/// Bytes a listpack back-length takes for an entry of `len` bytes
+fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+/// Members of an intset: element width, count, then little-endian integers
+fn intset(buf: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let mut r = Reader { buf, pos: 0 };
+    let width = r.u32_le()? as usize;
+    let count = r.u32_le()? as usize;
+    if !matches!(width, 2 | 4 | 8) {
+        return Err(invalid("bad intset encoding"));
+    }
+    (0..count)
+        .map(|_| {
+            let bytes = r.take(width)?;
+            let n = match width {
+                2 => i16::from_le_bytes(bytes.try_into().unwrap()) as i64,
+                4 => i32::from_le_bytes(bytes.try_into().unwrap()) as i64,
+                _ => i64::from_le_bytes(bytes.try_into().unwrap()),
+            };
+            Ok(n.to_string().into_bytes())
+        })
+        .collect()
+}
+
+/// Expands LZF `input` to exactly `len` bytes. The claimed length only bounds the
+/// output, it isn't allocated up front, so a corrupt header can't reserve memory.
+fn lzf_decompress(input: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let corrupt = || invalid("corrupt LZF data");
+    if len > MAX_STRING_LEN {
+        return Err(invalid("LZF string too long"));
+    }
+    let mut out = Vec::with_capacity(len.min(input.len()));
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 32 {
+            // Literal run of ctrl + 1 bytes
+            let run = input.get(i..i + ctrl + 1).ok_or_else(corrupt)?;
+            out.extend_from_slice(run);
+            i += ctrl + 1;
+        } else {
+            // Back reference
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += *input.get(i).ok_or_else(corrupt)? as usize;
+                i += 1;
+            }
+            let low = *input.get(i).ok_or_else(corrupt)? as usize;
+            i += 1;
+            let back = ((ctrl & 0x1f) << 8) + low + 1;
+            let from = out.len().checked_sub(back).ok_or_else(corrupt)?;
+            for k in 0..run + 2 {
+                out.push(out[from + k]);
+            }
+        }
+        if out.len() > len {
+            return Err(corrupt());
+        }
+    }
+    if out.len() != len {
+        return Err(corrupt());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Config;
+
+    fn string(buf: &mut Vec<u8>, s: &[u8]) {
+        buf.push(s.len() as u8);
+        buf.extend_from_slice(s);
+    }
+
+    /// A listpack holding short strings and small integers
+    fn listpack_of(items: &[&[u8]]) -> Vec<u8> {
+        let mut entries = Vec::new();
+        for item in items {
+            match std::str::from_utf8(item)
+                .ok()
+                .and_then(|s| s.parse::<u8>().ok())
+            {
+                Some(n) if n < 128 => entries.extend([n, 1]),
+                _ => {
+                    entries.push(0x80 | item.len() as u8);
+                    entries.extend_from_slice(item);
+                    entries.push(item.len() as u8 + 1);
+                }
+            }
+        }
+        let mut lp = Vec::new();
+        lp.extend(((entries.len() + 7) as u32).to_le_bytes());
+        lp.extend((items.len() as u16).to_le_bytes());
+        lp.extend(entries);
+        lp.push(0xFF);
+        lp
+    }
+
+    #[test]
+    fn test_import_rdb() {
+        let far = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+            + 3_600_000;
+
+        let mut rdb = b"REDIS0011".to_vec();
+        rdb.push(OP_AUX);
+        string(&mut rdb, b"redis-ver");
+        string(&mut rdb, b"7.2.0");
+        rdb.extend([OP_SELECTDB, 0, OP_RESIZEDB, 5, 1]);
+
+        rdb.push(TYPE_STRING);
+        string(&mut rdb, b"greeting");
+        string(&mut rdb, b"hello");
+
+        rdb.push(OP_EXPIRETIME_MS);
+        rdb.extend(far.to_le_bytes());
+        rdb.push(TYPE_STRING);
+        string(&mut rdb, b"counter");
+        rdb.extend([0xC0, 42]); // 8-bit integer encoding
+
+        rdb.push(OP_EXPIRETIME_MS);
+        rdb.extend(1000u64.to_le_bytes());
+        rdb.push(TYPE_STRING);
+        string(&mut rdb, b"old");
+        string(&mut rdb, b"x");
+
+        rdb.push(TYPE_LIST_QUICKLIST_2);
+        string(&mut rdb, b"queue");
+        rdb.extend([1, 2]); // One packed node
+        string(&mut rdb, &listpack_of(&[b"a", b"7", b"b"]));
+
+        rdb.push(TYPE_HASH_LISTPACK);
+        string(&mut rdb, b"user");
+        string(&mut rdb, &listpack_of(&[b"name", b"ann", b"age", b"30"]));
+
+        rdb.push(TYPE_SET_INTSET);
+        string(&mut rdb, b"ids");
+        let mut intset = Vec::new();
+        intset.extend(2u32.to_le_bytes());
+        intset.extend(2u32.to_le_bytes());
+        intset.extend(5i16.to_le_bytes());
+        intset.extend((-3i16).to_le_bytes());
+        string(&mut rdb, &intset);
+
+        rdb.push(TYPE_ZSET_2);
+        string(&mut rdb, b"board");
+        rdb.push(1);
+        string(&mut rdb, b"m");
+        rdb.extend(1.5f64.to_le_bytes());
+
+        rdb.push(OP_EOF);
+        rdb.extend([0; 8]);
+
+        let cache = Cache::new(Config::default());
+        let report = import(&cache, &rdb).unwrap();
+        assert_eq!(
+            report,
+            RdbImport {
+                imported: 5,
+                expired: 1,
+                unsupported: 1,
+                rejected: 0
+            }
+        );
+        assert_eq!(cache.get("greeting"), Some(Value::String("hello".into())));
+        assert_eq!(cache.get("counter"), Some(Value::String("42".into())));
+        assert!(cache.ttl("counter") > 3500);
+        assert_eq!(
+            cache.get("queue"),
+            Some(Value::List(vec![
+                Value::String("a".into()),
+                Value::String("7".into()),
+                Value::String("b".into()),
+            ]))
+        );
+        let Some(Value::Hash(user)) = cache.get("user") else {
+            panic!("expected a hash");
+        };
+        assert_eq!(user.get("age"), Some(&Value::String("30".into())));
+        let Some(Value::Set(ids)) = cache.get("ids") else {
+            panic!("expected a set");
+        };
+        assert!(ids.contains("5") && ids.contains("-3"));
+
+        assert!(import(&cache, b"REDIS0011\x13").is_err());
+        assert!(import(&cache, b"NOTRDB").is_err());
+    }
+
+    #[test]
+    fn test_lzf_decompress() {
+        // "abcabcabc": a literal "abc" then a 6 byte back reference to offset 3
+        let compressed = [0x02, b'a', b'b', b'c', 0x80, 0x02];
+        assert_eq!(lzf_decompress(&compressed, 9).unwrap(), b"abcabcabc");
+        assert!(lzf_decompress(&[0x80, 0x05], 3).is_err());
+
+        // Oversized claimed lengths fail before anything is reserved
+        assert!(lzf_decompress(&compressed, MAX_STRING_LEN + 1).is_err());
+        assert!(lzf_decompress(&compressed, usize::MAX).is_err());
+        assert!(lzf_decompress(&compressed, 4).is_err());
+    }
+}
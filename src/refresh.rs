@@ -0,0 +1,361 @@
+use crate::cache::{Cache, SetOptions, Value, matches_pattern};
+use crate::metrics::Labels;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::future::Future;
+use std::io::{self, ErrorKind};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tracing::debug;
+
+/// How often keys are checked for being due a refresh
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+/// Due keys considered per check; the rest wait for the next one
+const MAX_DUE_PER_CHECK: usize = 10_000;
+/// Origin fetches in flight at once
+const REFRESH_CONCURRENCY: usize = 16;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest origin response accepted
+const MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+pub type RefreshFuture = Pin<Box<dyn Future<Output = Option<String>> + Send>>;
+/// Loads a key's fresh value; None leaves the key to expire
+pub type RefreshCallback = Arc<dyn Fn(String) -> RefreshFuture + Send + Sync>;
+
+/// Where fresh values come from
+#[derive(Clone)]
+pub enum RefreshSource {
+    /// `http://` URL fetched with GET; `{key}` is replaced by the key
+    Url(String),
+    Callback(RefreshCallback),
+}
+
+#[derive(Clone)]
+pub struct RefreshPolicy {
+    pub pattern: String, // Exact key or prefix ending in `*`
+    pub source: RefreshSource,
+    pub margin: Duration,      // Refresh this long before expiry
+    pub ttl: Option<Duration>, // TTL of the refreshed value; defaults to the key's last TTL
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RefreshPolicyInfo {
+    pub pattern: String,
+    pub url: Option<String>, // None for callbacks
+    pub margin_secs: u64,
+    pub ttl_secs: Option<u64>,
+}
+
+/// Refresh-ahead: keys matching a policy are reloaded from their origin shortly
+/// before they expire, so readers keep hitting warm entries. Only keys with a TTL
+/// are refreshed, and a failed load just lets the key expire as it would have.
+/// Refreshed values are written to the cache directly, like flushed counters.
+pub struct RefreshAhead {
+    cache: Arc<Cache>,
+    policies: RwLock<Vec<RefreshPolicy>>,
+    in_flight: Mutex<HashSet<String>>,
+    permits: Arc<Semaphore>,
+    refreshed: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl RefreshAhead {
+    pub fn new(cache: Arc<Cache>) -> Self {
+        Self {
+            cache,
+            policies: RwLock::new(Vec::new()),
+            in_flight: Mutex::new(HashSet::new()),
+            permits: Arc::new(Semaphore::new(REFRESH_CONCURRENCY)),
+            refreshed: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        }
+    }
+
+    /// Adds a policy, replacing any with the same pattern. Keys matching several
+    /// patterns follow the first one registered.
+    pub fn register(&self, policy: RefreshPolicy) -> Result<(), String> {
+        if let RefreshSource::Url(url) = &policy.source {
+            parse_url(url)?;
+        }
+        let mut policies = self.policies.write().unwrap();
+        match policies.iter_mut().find(|p| p.pattern == policy.pattern) {
+            Some(existing) => *existing = policy,
+            None => policies.push(policy),
+        }
+        Ok(())
+    }
+
+    pub fn remove(&self, pattern: &str) -> bool {
+        let mut policies = self.policies.write().unwrap();
+        let before = policies.len();
+        policies.retain(|p| p.pattern != pattern);
+        policies.len() < before
+    }
+
+    pub fn policies(&self) -> Vec<RefreshPolicyInfo> {
+        self.policies
+            .read()
+            .unwrap()
+            .iter()
+            .map(|policy| RefreshPolicyInfo {
+                pattern: policy.pattern.clone(),
+                url: match &policy.source {
+                    RefreshSource::Url(url) => Some(url.clone()),
+                    RefreshSource::Callback(_) => None,
+                },
+                margin_secs: policy.margin.as_secs(),
+                ttl_secs: policy.ttl.map(|ttl| ttl.as_secs()),
+            })
+            .collect()
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.refresh_due();
+        }
+    }
+
+    /// Starts refreshing every key within its policy's margin of expiring, returning
+    /// how many were started
+    pub fn refresh_due(self: &Arc<Self>) -> usize {
+        let policies = self.policies.read().unwrap().clone();
+        let Some(widest) = policies.iter().map(|p| p.margin).max() else {
+            return 0;
+        };
+        let mut started = 0;
+        for key in self.cache.expiring_within(widest, MAX_DUE_PER_CHECK) {
+            let Some(policy) = policies.iter().find(|p| matches_pattern(&key, &p.pattern)) else {
+                continue;
+            };
+            let remaining = self.cache.ttl_remaining(&key).unwrap_or_default();
+            if remaining > policy.margin || !self.in_flight.lock().unwrap().insert(key.clone()) {
+                continue;
+            }
+            let refresher = self.clone();
+            let policy = policy.clone();
+            tokio::spawn(async move {
+                let Ok(_permit) = refresher.permits.clone().acquire_owned().await else {
+                    return;
+                };
+                refresher.refresh(&key, &policy).await;
+                refresher.in_flight.lock().unwrap().remove(&key);
+            });
+            started += 1;
+        }
+        started
+    }
+
+    async fn refresh(&self, key: &str, policy: &RefreshPolicy) {
+        let ttl = policy.ttl.or_else(|| self.cache.ttl_duration(key));
+        let value = match &policy.source {
+            RefreshSource::Url(url) => {
+                let url = url.replace("{key}", key);
+                match tokio::time::timeout(FETCH_TIMEOUT, fetch(&url)).await {
+                    Ok(Ok(value)) => Some(value),
+                    Ok(Err(e)) => {
+                        debug!("Refreshing {} from {} failed: {}", key, url, e);
+                        None
+                    }
+                    Err(_) => None,
+                }
+            }
+            RefreshSource::Callback(load) => load(key.to_string()).await,
+        };
+        let options = SetOptions {
+            ttl,
+            parents: self.cache.parents(key),
+            ..Default::default()
+        };
+        let stored = value.is_some_and(|value| {
+            self.cache
+                .set(key.to_string(), Value::String(value), options)
+                .is_ok()
+        });
+        let counter = if stored {
+            &self.refreshed
+        } else {
+            &self.failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn render_metrics(&self, out: &mut String, labels: &Labels) {
+        let series = labels.series();
+        for (name, help, value) in [
+            (
+                "cache_refresh_ahead_total",
+                "Keys reloaded from their origin before expiring",
+                &self.refreshed,
+            ),
+            (
+                "cache_refresh_ahead_failures_total",
+                "Refreshes whose origin gave no value, left to expire",
+                &self.failed,
+            ),
+        ] {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} counter", name).unwrap();
+            writeln!(out, "{}{} {}", name, series, value.load(Ordering::Relaxed)).unwrap();
+        }
+    }
+}
+
+/// Host, port and path of an `http://` URL
+fn parse_url(url: &str) -> Result<(String, u16, String), String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("only http:// origins are supported: {}", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| format!("invalid port in {}", url))?,
+        ),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(format!("missing host in {}", url));
+    }
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+/// Body of a successful GET, over HTTP/1.1 with the connection closed after it
+async fn fetch(url: &str) -> io::Result<String> {
+    let invalid = |message: String| io::Error::new(ErrorKind::InvalidData, message);
+    let (host, port, path) = parse_url(url).map_err(invalid)?;
+    let mut stream = TcpStream::connect((host.as_str(), port)).await?;
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    stream
+        .take(MAX_RESPONSE_BYTES as u64 + 1)
+        .read_to_end(&mut response)
+        .await?;
+    if response.len() > MAX_RESPONSE_BYTES {
+        return Err(invalid("response too large".into()));
+    }
+
+    let head_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| invalid("incomplete response".into()))?;
+    let head = String::from_utf8_lossy(&response[..head_end]);
+    let status = head.split_whitespace().nth(1).unwrap_or_default();
+    if status != "200" {
+        return Err(invalid(format!("origin answered {}", status)));
+    }
+    if head
+        .lines()
+        .any(|line| line.to_ascii_lowercase().starts_with("transfer-encoding:"))
+    {
+        return Err(invalid("chunked responses aren't supported".into()));
+    }
+    String::from_utf8(response[head_end + 4..].to_vec())
+        .map_err(|_| invalid("response body isn't UTF-8".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Config;
+    use tokio::net::TcpListener;
+
+    async fn wait_for(cache: &Cache, key: &str, value: &str) {
+        for _ in 0..500 {
+            if cache.get(key).is_some_and(|v| v.to_string() == value) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("{} never became {}", key, value);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_ahead() {
+        let cache = Arc::new(Cache::new(Config::default()));
+        let set = |key: &str, ttl: u64| {
+            let options = SetOptions {
+                ttl: Some(Duration::from_secs(ttl)),
+                ..Default::default()
+            };
+            cache
+                .set(key.into(), Value::String("old".into()), options)
+                .unwrap();
+        };
+        set("user:1", 5);
+        set("user:2", 600); // Not due yet
+        set("page:1", 5);
+
+        // A one-shot origin for page:*
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let n = stream.read(&mut request).await.unwrap();
+            assert!(String::from_utf8_lossy(&request[..n]).starts_with("GET /pages/page:1 "));
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nfresh")
+                .await
+                .unwrap();
+        });
+
+        let refresher = Arc::new(RefreshAhead::new(cache.clone()));
+        let load: RefreshCallback =
+            Arc::new(|key| Box::pin(async move { Some(format!("loaded {}", key)) }));
+        let margin = Duration::from_secs(10);
+        refresher
+            .register(RefreshPolicy {
+                pattern: "user:*".into(),
+                source: RefreshSource::Callback(load),
+                margin,
+                ttl: None,
+            })
+            .unwrap();
+        refresher
+            .register(RefreshPolicy {
+                pattern: "page:*".into(),
+                source: RefreshSource::Url(format!("http://{}/pages/{{key}}", addr)),
+                margin,
+                ttl: Some(Duration::from_secs(60)),
+            })
+            .unwrap();
+        assert!(
+            refresher
+                .register(RefreshPolicy {
+                    pattern: "x".into(),
+                    source: RefreshSource::Url("https://origin".into()),
+                    margin,
+                    ttl: None,
+                })
+                .is_err()
+        );
+
+        assert_eq!(refresher.refresh_due(), 2);
+        wait_for(&cache, "user:1", "loaded user:1").await;
+        wait_for(&cache, "page:1", "fresh").await;
+        assert_eq!(cache.get("user:2").unwrap().to_string(), "old");
+        // Refreshed keys keep their TTL, or take the policy's
+        assert_eq!(cache.ttl_duration("user:1"), Some(Duration::from_secs(5)));
+        assert!(cache.ttl("page:1") > 10);
+        assert_eq!(refresher.refreshed.load(Ordering::Relaxed), 2);
+
+        assert!(refresher.remove("page:*"));
+        assert_eq!(refresher.policies().len(), 1);
+    }
+}
@@ -0,0 +1,231 @@
+use crate::aof::{Aof, AofConfig};
+use crate::cache::{Cache, Config};
+use crate::oplog;
+use crate::snapshot;
+use redis_protocol::resp2::decode::decode;
+use redis_protocol::resp2::types::OwnedFrame;
+use serde::Serialize;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+use tracing::warn;
+
+/// Where recorded mutations are read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplaySource {
+    Aof,   // The log in an AOF directory
+    OpLog, // The oplog stream in a snapshot file
+}
+
+impl fmt::Display for ReplaySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ReplaySource::Aof => "aof",
+            ReplaySource::OpLog => "oplog",
+        })
+    }
+}
+
+impl FromStr for ReplaySource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "aof" => Ok(ReplaySource::Aof),
+            "oplog" => Ok(ReplaySource::OpLog),
+            _ => Err(format!("unknown replay source '{}'", s)),
+        }
+    }
+}
+
+/// How fast recorded time passes during a replay: `2x` halves the gaps between
+/// mutations, `max` drops them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    Factor(f64),
+    Max,
+}
+
+impl Default for ReplaySpeed {
+    fn default() -> Self {
+        ReplaySpeed::Factor(1.0)
+    }
+}
+
+impl fmt::Display for ReplaySpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplaySpeed::Factor(factor) => write!(f, "{}x", factor),
+            ReplaySpeed::Max => f.write_str("max"),
+        }
+    }
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("max") {
+            return Ok(ReplaySpeed::Max);
+        }
+        match s.strip_suffix(['x', 'X']).unwrap_or(s).parse::<f64>() {
+            Ok(factor) if factor.is_finite() && factor > 0.0 => Ok(ReplaySpeed::Factor(factor)),
+            _ => Err(format!("invalid replay speed '{}'", s)),
+        }
+    }
+}
+
+/// One recorded write command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mutation {
+    pub at_ms: u64, // Unix milliseconds it was recorded at
+    pub args: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ReplaySummary {
+    pub sent: usize,
+    pub failed: usize, // Commands the target answered with an error
+    pub elapsed_ms: u64,
+}
+
+/// Recorded mutations at `path`: an AOF directory or a snapshot file holding an oplog
+pub fn read(source: ReplaySource, path: &Path) -> io::Result<Vec<Mutation>> {
+    let recorded = match source {
+        ReplaySource::Aof => Aof::logged_commands(&AofConfig::new(path))?,
+        ReplaySource::OpLog => {
+            let cache = Cache::new(Config::default());
+            snapshot::load(&cache, path)?;
+            oplog::recorded(&cache)
+        }
+    };
+    Ok(recorded
+        .into_iter()
+        .map(|(at_ms, args)| Mutation { at_ms, args })
+        .collect())
+}
+
+/// Sends `mutations` to the instance at `target` in order over one connection,
+/// spaced as they were recorded (scaled by `speed`). Each waits for the previous
+/// reply, so the target applies them exactly in recorded order.
+pub async fn replay(
+    mutations: &[Mutation],
+    target: &str,
+    speed: ReplaySpeed,
+) -> io::Result<ReplaySummary> {
+    let mut conn = Conn::connect(target).await?;
+    let start = Instant::now();
+    let first_at = mutations.first().map_or(0, |m| m.at_ms);
+    let mut summary = ReplaySummary::default();
+    for mutation in mutations {
+        if let ReplaySpeed::Factor(factor) = speed {
+            let offset = mutation.at_ms.saturating_sub(first_at) as f64 / factor;
+            tokio::time::sleep_until(start + Duration::from_secs_f64(offset / 1000.0)).await;
+        }
+        if let Err(e) = conn.call(&mutation.args).await? {
+            if summary.failed == 0 {
+                let command = String::from_utf8_lossy(&mutation.args[0]);
+                warn!("Replayed {} failed: {}", command, e);
+            }
+            summary.failed += 1;
+        }
+        summary.sent += 1;
+    }
+    summary.elapsed_ms = start.elapsed().as_millis() as u64;
+    Ok(summary)
+}
+
+/// A RESP client connection to another server
+pub(crate) struct Conn {
+    stream: TcpStream,
+    buf: Vec<u8>,
+}
+
+impl Conn {
+    pub(crate) async fn connect(addr: &str) -> io::Result<Self> {
+        Ok(Self {
+            stream: TcpStream::connect(addr).await?,
+            buf: Vec::new(),
+        })
+    }
+
+    /// Sends a command and waits for its reply, returning an error reply as `Err`
+    pub(crate) async fn call(
+        &mut self,
+        args: &[impl AsRef<[u8]>],
+    ) -> io::Result<Result<(), String>> {
+        let mut out = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args.iter().map(AsRef::as_ref) {
+            out.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            out.extend_from_slice(arg);
+            out.extend_from_slice(b"\r\n");
+        }
+        self.stream.write_all(&out).await?;
+        loop {
+            match decode(&self.buf) {
+                Ok(Some((frame, used))) => {
+                    self.buf.drain(..used);
+                    return Ok(match frame {
+                        OwnedFrame::Error(e) => Err(e),
+                        _ => Ok(()),
+                    });
+                }
+                Ok(None) => {
+                    if self.stream.read_buf(&mut self.buf).await? == 0 {
+                        return Err(io::Error::from(ErrorKind::ConnectionAborted));
+                    }
+                }
+                Err(e) => return Err(io::Error::new(ErrorKind::InvalidData, e.details())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::CommandExecutor;
+    use crate::resp_api::RespServer;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_replay_against_target() {
+        assert_eq!("2x".parse(), Ok(ReplaySpeed::Factor(2.0)));
+        assert_eq!("0.5".parse(), Ok(ReplaySpeed::Factor(0.5)));
+        assert_eq!("MAX".parse(), Ok(ReplaySpeed::Max));
+        assert!("0x".parse::<ReplaySpeed>().is_err());
+
+        let target = Arc::new(Cache::new(Config::default()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = RespServer::new(Arc::new(CommandExecutor::new(target.clone())));
+        tokio::spawn(async move { server.serve(listener).await });
+
+        let mutation = |at_ms: u64, args: &[&str]| Mutation {
+            at_ms,
+            args: args.iter().map(|arg| arg.as_bytes().to_vec()).collect(),
+        };
+        let mutations = [
+            mutation(1_000, &["SET", "a", "1"]),
+            mutation(1_040, &["SET", "a", "2"]),
+            mutation(1_080, &["EXPIRE", "a", "nope"]),
+            mutation(1_100, &["SET", "b", "3"]),
+        ];
+        let summary = replay(&mutations, &addr, ReplaySpeed::Factor(2.0))
+            .await
+            .unwrap();
+        assert_eq!((summary.sent, summary.failed), (4, 1));
+        assert!(summary.elapsed_ms >= 50); // 100ms recorded at double speed
+        assert_eq!(
+            target.get("a").unwrap(),
+            crate::cache::Value::String("2".into())
+        );
+        assert!(target.get("b").is_some());
+    }
+}
@@ -0,0 +1,524 @@
+use crate::cache::Cache;
+use crate::executor::{Command, CommandExecutor, CommandResponse};
+use crate::middleware::ReadOnly;
+use crate::rdb;
+use crate::snapshot;
+use redis_protocol::resp2::decode::decode;
+use redis_protocol::resp2::types::OwnedFrame;
+use serde::Serialize;
+use std::io::{self, ErrorKind};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Silence from the primary after which the link is dropped, as Redis' repl-timeout
+const REPL_TIMEOUT: Duration = Duration::from_secs(60);
+const ACK_INTERVAL: Duration = Duration::from_secs(1);
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+/// Length of the delimiter that ends a diskless RDB transfer
+const EOF_MARK_LEN: usize = 40;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkState {
+    #[default]
+    Connecting,
+    Syncing, // Receiving or loading the RDB
+    Streaming,
+    Down, // Waiting to reconnect
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReplicaStatus {
+    pub primary: String,
+    pub state: LinkState,
+    pub replid: Option<String>,
+    pub offset: u64,  // Replication offset applied up to
+    pub applied: u64, // Streamed commands applied
+    pub skipped: u64, // Streamed commands the cache doesn't support
+    pub last_error: Option<String>,
+}
+
+struct Link {
+    task: JoinHandle<()>,
+    status: Arc<Mutex<ReplicaStatus>>,
+}
+
+/// One-way replication from a Redis or dashdotcache primary: a full sync of its
+/// dataset, then every write it propagates. Reconnects resume with PSYNC where the
+/// primary's backlog allows. Client writes are refused while a link is up.
+pub struct Replication {
+    cache: Arc<Cache>,
+    listening_port: u16, // Reported to the primary for its INFO replication
+    link: Mutex<Option<Link>>,
+    read_only: Arc<ReadOnly>,
+}
+
+impl Replication {
+    pub fn new(cache: Arc<Cache>, listening_port: u16) -> Self {
+        Self {
+            cache,
+            listening_port,
+            link: Mutex::new(None),
+            read_only: Arc::new(ReadOnly::new(false)),
+        }
+    }
+
+    /// Middleware refusing writes while replicating, to register on client-facing
+    /// executors. Replicated writes are applied past it.
+    pub fn read_only(&self) -> Arc<ReadOnly> {
+        self.read_only.clone()
+    }
+
+    /// Starts replicating from `primary` ("host:port"), dropping any current link.
+    /// The full sync replaces the whole keyspace.
+    pub fn replicate_from(&self, primary: String) {
+        let status = Arc::new(Mutex::new(ReplicaStatus {
+            primary: primary.clone(),
+            ..ReplicaStatus::default()
+        }));
+        let task = tokio::spawn(run(
+            self.cache.clone(),
+            primary,
+            self.listening_port,
+            status.clone(),
+        ));
+        if let Some(old) = self.link.lock().unwrap().replace(Link { task, status }) {
+            old.task.abort();
+        }
+        self.read_only.set_enabled(true);
+    }
+
+    /// Stops replicating, keeping the data synced so far. Returns whether a link was up.
+    pub fn stop(&self) -> bool {
+        let link = self.link.lock().unwrap().take();
+        self.read_only.set_enabled(false);
+        link.map(|link| link.task.abort()).is_some()
+    }
+
+    /// None when not replicating
+    pub fn status(&self) -> Option<ReplicaStatus> {
+        let link = self.link.lock().unwrap();
+        link.as_ref()
+            .map(|link| link.status.lock().unwrap().clone())
+    }
+}
+
+async fn run(cache: Arc<Cache>, primary: String, port: u16, status: Arc<Mutex<ReplicaStatus>>) {
+    let mut backoff = RECONNECT_MIN;
+    loop {
+        if let Err(e) = sync(&cache, &primary, port, &status, &mut backoff).await {
+            warn!("Replication from {} failed: {}", primary, e);
+            let mut status = status.lock().unwrap();
+            status.state = LinkState::Down;
+            status.last_error = Some(e.to_string());
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(RECONNECT_MAX);
+    }
+}
+
+/// One connection's worth of replication; only returns on error
+async fn sync(
+    cache: &Arc<Cache>,
+    primary: &str,
+    port: u16,
+    status: &Mutex<ReplicaStatus>,
+    backoff: &mut Duration,
+) -> io::Result<()> {
+    status.lock().unwrap().state = LinkState::Connecting;
+    let stream = tokio::time::timeout(REPL_TIMEOUT, TcpStream::connect(primary))
+        .await
+        .map_err(|_| io::Error::from(ErrorKind::TimedOut))??;
+    let mut conn = Conn {
+        stream,
+        buf: Vec::new(),
+    };
+
+    conn.expect(&["PING"]).await?;
+    conn.expect(&["REPLCONF", "listening-port", &port.to_string()])
+        .await?;
+    conn.expect(&["REPLCONF", "capa", "eof", "capa", "psync2"])
+        .await?;
+
+    let resume = {
+        let status = status.lock().unwrap();
+        status.replid.clone().map(|id| (id, status.offset))
+    };
+    let (psync_id, psync_offset) = match &resume {
+        Some((id, offset)) => (id.clone(), (offset + 1).to_string()),
+        None => ("?".to_string(), "-1".to_string()),
+    };
+    conn.send(&["PSYNC", &psync_id, &psync_offset]).await?;
+    let reply = conn.line().await?;
+    let mut words = reply.split(' ');
+    match (words.next(), words.next(), words.next()) {
+        (Some("+FULLRESYNC"), Some(replid), Some(offset)) => {
+            let offset = offset.parse().map_err(|_| invalid(&reply))?;
+            let replid = replid.to_string();
+            status.lock().unwrap().state = LinkState::Syncing;
+            let dump = conn.rdb().await?;
+            let cache = cache.clone();
+            // Redis sends an RDB, a dashdotcache primary its own snapshot format
+            let loaded = tokio::task::spawn_blocking(move || {
+                cache.flush_all();
+                if dump.starts_with(snapshot::MAGIC) {
+                    snapshot::restore(&cache, &dump).map(|report| report.loaded)
+                } else {
+                    rdb::import(&cache, &dump).map(|report| report.imported)
+                }
+            })
+            .await
+            .map_err(io::Error::other)??;
+            info!("Full sync from {}: {} keys", primary, loaded);
+            let mut status = status.lock().unwrap();
+            status.replid = Some(replid);
+            status.offset = offset;
+        }
+        (Some("+CONTINUE"), replid, _) => {
+            info!("Resumed replication from {}", primary);
+            if let Some(replid) = replid {
+                status.lock().unwrap().replid = Some(replid.to_string());
+            }
+        }
+        _ => return Err(invalid(&reply)),
+    }
+
+    {
+        let mut status = status.lock().unwrap();
+        status.state = LinkState::Streaming;
+        status.last_error = None;
+    }
+    *backoff = RECONNECT_MIN;
+
+    // Replicated writes aren't re-logged or mirrored, so a plain executor applies them
+    let executor = CommandExecutor::new(cache.clone());
+    let mut ack = tokio::time::interval(ACK_INTERVAL);
+    loop {
+        tokio::select! {
+            read = tokio::time::timeout(REPL_TIMEOUT, conn.fill()) => {
+                read.map_err(|_| io::Error::from(ErrorKind::TimedOut))??;
+            }
+            _ = ack.tick() => {
+                let offset = status.lock().unwrap().offset;
+                conn.send(&["REPLCONF", "ACK", &offset.to_string()]).await?;
+                continue;
+            }
+        }
+
+        while let Some((args, used)) = conn.frame()? {
+            let offset = {
+                let mut status = status.lock().unwrap();
+                status.offset += used as u64;
+                status.offset
+            };
+            if args
+                .first()
+                .is_some_and(|a| a.eq_ignore_ascii_case(b"REPLCONF"))
+            {
+                if args
+                    .get(1)
+                    .is_some_and(|a| a.eq_ignore_ascii_case(b"GETACK"))
+                {
+                    conn.send(&["REPLCONF", "ACK", &offset.to_string()]).await?;
+                }
+                continue;
+            }
+            let Some(args) = translate(args) else {
+                continue;
+            };
+            let args: Vec<&[u8]> = args.iter().map(Vec::as_slice).collect();
+            let mut status = status.lock().unwrap();
+            match Command::parse(&args).map(|command| executor.execute(command)) {
+                Ok(CommandResponse::Error(e)) => {
+                    debug!("Replicated command failed: {}", e);
+                    status.skipped += 1;
+                }
+                Ok(_) => status.applied += 1,
+                Err(e) => {
+                    debug!("Skipping replicated command: {}", e);
+                    status.skipped += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Rewrites what Redis propagates into commands the cache parses. None for commands
+/// with nothing to apply: database selection, keepalives and transaction markers.
+fn translate(mut args: Vec<Vec<u8>>) -> Option<Vec<Vec<u8>>> {
+    let name = args.first()?.to_ascii_uppercase();
+    match name.as_slice() {
+        b"SELECT" | b"PING" | b"MULTI" | b"EXEC" => None,
+        b"UNLINK" => {
+            args[0] = b"DEL".to_vec();
+            Some(args)
+        }
+        // Redis 7 propagates expiries as absolute times
+        b"SET" => {
+            let at = args
+                .iter()
+                .skip(3)
+                .position(|a| a.eq_ignore_ascii_case(b"PXAT") || a.eq_ignore_ascii_case(b"EXAT"));
+            if let Some(at) = at.map(|i| i + 3)
+                && let Some(deadline) = args.get(at + 1).and_then(|a| parse_u64(a))
+            {
+                let scale = if args[at].eq_ignore_ascii_case(b"EXAT") {
+                    1000
+                } else {
+                    1
+                };
+                let remaining = deadline.saturating_mul(scale).saturating_sub(unix_ms());
+                args[at] = b"PX".to_vec();
+                // Already expired on the primary; 1ms lets it lapse here too
+                args[at + 1] = remaining.max(1).to_string().into_bytes();
+            }
+            Some(args)
+        }
+        b"PEXPIREAT" if args.len() == 3 => {
+            let secs = parse_u64(&args[2])?.div_ceil(1000);
+            Some(vec![
+                b"EXPIREAT".to_vec(),
+                args.swap_remove(1),
+                secs.to_string().into_bytes(),
+            ])
+        }
+        _ => Some(args),
+    }
+}
+
+fn parse_u64(bytes: &[u8]) -> Option<u64> {
+    std::str::from_utf8(bytes).ok()?.parse().ok()
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn invalid(reply: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("unexpected reply from primary: {}", reply),
+    )
+}
+
+struct Conn {
+    stream: TcpStream,
+    buf: Vec<u8>,
+}
+
+impl Conn {
+    async fn send(&mut self, args: &[&str]) -> io::Result<()> {
+        let mut out = format!("*{}\r\n", args.len());
+        for arg in args {
+            out.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+        }
+        self.stream.write_all(out.as_bytes()).await
+    }
+
+    /// Sends a handshake command, failing if the reply is an error. Primaries differ
+    /// in how they answer PING, so any other reply will do.
+    async fn expect(&mut self, args: &[&str]) -> io::Result<()> {
+        self.send(args).await?;
+        loop {
+            match decode(&self.buf) {
+                Ok(Some((frame, used))) => {
+                    self.buf.drain(..used);
+                    return match frame {
+                        OwnedFrame::Error(e) => Err(invalid(&e)),
+                        _ => Ok(()),
+                    };
+                }
+                Ok(None) => self.fill().await?,
+                Err(e) => return Err(invalid(e.details())),
+            }
+        }
+    }
+
+    async fn fill(&mut self) -> io::Result<()> {
+        if self.stream.read_buf(&mut self.buf).await? == 0 {
+            return Err(io::Error::from(ErrorKind::ConnectionAborted));
+        }
+        Ok(())
+    }
+
+    /// Next CRLF-terminated line, skipping the bare newlines primaries send as
+    /// keepalives while preparing an RDB
+    async fn line(&mut self) -> io::Result<String> {
+        loop {
+            let skip = self.buf.iter().take_while(|&&b| b == b'\n').count();
+            self.buf.drain(..skip);
+            if let Some(end) = self.buf.windows(2).position(|w| w == b"\r\n") {
+                let line = String::from_utf8_lossy(&self.buf[..end]).into_owned();
+                self.buf.drain(..end + 2);
+                return Ok(line);
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// The RDB of a full sync: `$<len>\r\n` then the bytes, or for a diskless sync
+    /// `$EOF:<mark>\r\n` then the bytes followed by the mark
+    async fn rdb(&mut self) -> io::Result<Vec<u8>> {
+        let header = self.line().await?;
+        let spec = header.strip_prefix('$').ok_or_else(|| invalid(&header))?;
+        if let Some(mark) = spec.strip_prefix("EOF:") {
+            let mark = mark.as_bytes().to_vec();
+            if mark.len() != EOF_MARK_LEN {
+                return Err(invalid(&header));
+            }
+            while !self.buf.ends_with(&mark) {
+                self.fill().await?;
+            }
+            let mut dump = std::mem::take(&mut self.buf);
+            dump.truncate(dump.len() - EOF_MARK_LEN);
+            return Ok(dump);
+        }
+        let len: usize = spec.parse().map_err(|_| invalid(&header))?;
+        while self.buf.len() < len {
+            self.fill().await?;
+        }
+        let rest = self.buf.split_off(len);
+        Ok(std::mem::replace(&mut self.buf, rest))
+    }
+
+    /// Next complete command in the stream and the bytes it took
+    fn frame(&mut self) -> io::Result<Option<(Vec<Vec<u8>>, usize)>> {
+        let (frame, used) = match decode(&self.buf) {
+            Ok(Some(decoded)) => decoded,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(invalid(e.details())),
+        };
+        self.buf.drain(..used);
+        let OwnedFrame::Array(items) = frame else {
+            return Err(invalid("expected a command array"));
+        };
+        let args = items
+            .into_iter()
+            .map(|item| match item {
+                OwnedFrame::BulkString(bytes) => Ok(bytes),
+                _ => Err(invalid("expected bulk string arguments")),
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Some((args, used)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Config, Value};
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::net::TcpListener;
+
+    fn resp(args: &[&str]) -> String {
+        let mut out = format!("*{}\r\n", args.len());
+        for arg in args {
+            out.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+        }
+        out
+    }
+
+    /// Reads one handshake command off a fake primary's socket, returning its name
+    async fn read_command(conn: &mut BufReader<TcpStream>) -> String {
+        let mut line = String::new();
+        conn.read_line(&mut line).await.unwrap();
+        let count: usize = line.trim()[1..].parse().unwrap();
+        let mut args = Vec::new();
+        for _ in 0..count * 2 {
+            line.clear();
+            conn.read_line(&mut line).await.unwrap();
+            args.push(line.trim().to_string());
+        }
+        args[1..]
+            .iter()
+            .step_by(2)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[tokio::test]
+    async fn test_full_sync_then_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let primary = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut conn = BufReader::new(stream);
+            assert_eq!(read_command(&mut conn).await, "PING");
+            conn.write_all(b"+PONG\r\n").await.unwrap();
+            assert!(
+                read_command(&mut conn)
+                    .await
+                    .starts_with("REPLCONF listening-port")
+            );
+            conn.write_all(b"+OK\r\n").await.unwrap();
+            read_command(&mut conn).await;
+            conn.write_all(b"+OK\r\n").await.unwrap();
+            assert_eq!(read_command(&mut conn).await, "PSYNC ? -1");
+            conn.write_all(b"+FULLRESYNC 8de1787ba490483314a4d30f1c628bc5025eb761 100\r\n\n")
+                .await
+                .unwrap();
+
+            let mut dump = b"REDIS0011\x00\x01a\x03old".to_vec();
+            dump.extend([0xFF, 0, 0, 0, 0, 0, 0, 0, 0]);
+            conn.write_all(format!("${}\r\n", dump.len()).as_bytes())
+                .await
+                .unwrap();
+            conn.write_all(&dump).await.unwrap();
+
+            let expires_at = (unix_ms() + 3_600_000).to_string();
+            let stream = [
+                resp(&["SELECT", "0"]),
+                resp(&["SET", "b", "new", "PXAT", &expires_at]),
+                resp(&["UNLINK", "a"]),
+                resp(&["ZADD", "z", "1", "m"]),
+                resp(&["REPLCONF", "GETACK", "*"]),
+            ]
+            .concat();
+            conn.write_all(stream.as_bytes()).await.unwrap();
+            // Acks carry the offset after everything above
+            let expected = format!("REPLCONF ACK {}", 100 + stream.len());
+            while read_command(&mut conn).await != expected {}
+            conn
+        });
+
+        let cache = Arc::new(Cache::new(Config::default()));
+        let replication = Replication::new(cache.clone(), 6379);
+        replication.replicate_from(addr.to_string());
+        let _conn = primary.await.unwrap();
+
+        let status = replication.status().unwrap();
+        assert_eq!(status.state, LinkState::Streaming);
+        assert_eq!((status.applied, status.skipped), (2, 1));
+        assert!(cache.get("a").is_none());
+        assert_eq!(cache.get("b"), Some(Value::String("new".into())));
+        assert!(cache.ttl("b") > 0);
+
+        assert!(replication.stop());
+        assert!(replication.status().is_none());
+    }
+
+    #[test]
+    fn test_translate() {
+        let args = |line: &str| line.split(' ').map(|a| a.as_bytes().to_vec()).collect();
+        assert_eq!(translate(args("SELECT 1")), None);
+        assert_eq!(translate(args("UNLINK a b")), Some(args("DEL a b")));
+        assert_eq!(
+            translate(args("PEXPIREAT k 1500")),
+            Some(args("EXPIREAT k 2"))
+        );
+        assert_eq!(
+            translate(args("SET k v PXAT 1")),
+            Some(args("SET k v PX 1"))
+        );
+        assert_eq!(translate(args("SET k v")), Some(args("SET k v")));
+    }
+}
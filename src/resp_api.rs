@@ -1,45 +1,930 @@
-/// Placeholder - TODO after http & cache optimization
-use crate::executor::{Command, CommandExecutor};
+use crate::cache_errors::CommandError;
+use crate::executor::{Command, CommandExecutor, CommandResponse};
+use crate::pubsub::{Message, Subscriber};
+use crate::trace;
+use redis_protocol::resp2::decode::decode;
+use redis_protocol::resp2::encode::encode;
+use redis_protocol::resp2::types::{OwnedFrame, Resp2Frame};
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
+use tracing::debug;
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// State owned by a single RESP connection; totals are mirrored into `Stats`
+#[derive(Debug)]
+pub struct ClientState {
+    pub id: u64,
+    pub addr: SocketAddr,
+    pub name: Option<String>,
+    pub db: u64,
+    pub authenticated: bool,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub commands_processed: u64,
+    pub connected_at: Instant,
+    pub subscriber: Option<Subscriber>, // Created by the first SUBSCRIBE
+    pub trace_id: Option<String>,       // Set by CLIENT TRACEID; tags the commands that follow
+}
+
+impl ClientState {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            addr,
+            name: None,
+            db: 0,
+            authenticated: false,
+            bytes_in: 0,
+            bytes_out: 0,
+            commands_processed: 0,
+            connected_at: Instant::now(),
+            subscriber: None,
+            trace_id: None,
+        }
+    }
+
+    /// Formats the connection as a CLIENT INFO line
+    pub fn info(&self) -> String {
+        format!(
+            "id={} addr={} name={} age={} db={} sub={} auth={} cmds={} tot-net-in={} tot-net-out={} traceid={}",
+            self.id,
+            self.addr,
+            self.name.as_deref().unwrap_or(""),
+            self.connected_at.elapsed().as_secs(),
+            self.db,
+            self.subscriptions(),
+            self.authenticated as u8,
+            self.commands_processed,
+            self.bytes_in,
+            self.bytes_out,
+            self.trace_id.as_deref().unwrap_or(""),
+        )
+    }
+
+    pub fn subscriptions(&self) -> usize {
+        self.subscriber.as_ref().map_or(0, Subscriber::count)
+    }
+}
+
+/// Caps on what a client may ask the server to buffer for one request. Headers are
+/// checked as soon as they arrive, before any of the announced data is read.
+#[derive(Debug, Clone, Copy)]
+pub struct RespLimits {
+    pub max_multibulk_len: usize, // Arguments per request
+    pub max_bulk_len: usize,      // Bytes per argument
+    pub max_request_size: usize,  // Bytes per request, headers included
+    pub max_inline_len: usize,    // Bytes per inline command line
+}
+
+impl Default for RespLimits {
+    fn default() -> Self {
+        Self {
+            max_multibulk_len: 1024 * 1024,
+            max_bulk_len: 512 * 1024 * 1024,
+            max_request_size: 1024 * 1024 * 1024,
+            max_inline_len: 64 * 1024,
+        }
+    }
+}
+
 pub struct RespServer {
     executor: Arc<CommandExecutor>,
+    limits: RespLimits,
 }
 
 impl RespServer {
     pub fn new(executor: Arc<CommandExecutor>) -> Self {
-        Self { executor }
+        Self {
+            executor,
+            limits: RespLimits::default(),
+        }
+    }
+
+    pub fn with_limits(mut self, limits: RespLimits) -> Self {
+        self.limits = limits;
+        self
     }
 
     pub async fn run(&self, addr: &str) -> Result<(), std::io::Error> {
-        // TODO: Implement full RESP protocol parsing
-        let listener = TcpListener::bind(addr).await?;
+        self.serve(TcpListener::bind(addr).await?).await
+    }
 
+    pub async fn serve(&self, listener: TcpListener) -> Result<(), std::io::Error> {
         loop {
-            let (stream, _) = listener.accept().await?;
+            let (stream, peer) = listener.accept().await?;
             let executor = self.executor.clone();
+            let limits = self.limits;
 
             tokio::spawn(async move {
-                handle_connection(stream, executor).await;
+                handle_connection(stream, ClientState::new(peer), executor, limits).await;
             });
         }
     }
 }
 
-async fn handle_connection(stream: TcpStream, executor: Arc<CommandExecutor>) {
-    let (reader, mut writer) = stream.into_split();
-    let mut reader = BufReader::new(reader);
-    let mut line = String::new();
+/// Bytes requested from the socket per read
+const READ_CHUNK: usize = 16 * 1024;
+
+/// What the connection does after replying to a request
+enum Flow {
+    Continue,
+    Close,
+    /// Hand the connection to the primary as a replica link; PSYNC's arguments
+    Sync {
+        replid: String,
+        offset: i64,
+    },
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    mut client: ClientState,
+    executor: Arc<CommandExecutor>,
+    limits: RespLimits,
+) {
+    let stats = executor.cache.stats();
+    stats.connected_clients.fetch_add(1, Ordering::Relaxed);
+    stats.total_connections.fetch_add(1, Ordering::Relaxed);
+
+    let mut input = Vec::with_capacity(READ_CHUNK);
+    let mut output = Vec::new();
+    let mut flow = Flow::Continue;
+
+    while let Flow::Continue = flow {
+        let read = tokio::select! {
+            read = stream.read_buf(&mut input) => match read {
+                Ok(0) | Err(_) => break,
+                Ok(read) => read,
+            },
+            message = next_message(&mut client.subscriber) => {
+                match message {
+                    Some(message) => write_frame(&mut output, &message_frame(message)),
+                    None => break, // Disconnected for falling behind
+                }
+                0 // Nothing read; a partial request stays buffered below
+            }
+        };
+        client.bytes_in += read as u64;
+        stats
+            .net_input_bytes
+            .fetch_add(read as u64, Ordering::Relaxed);
+
+        // Answer every complete request in the buffer, so pipelined commands share a write
+        let mut consumed = 0;
+        while let Flow::Continue = flow {
+            let args = match next_request(&input[consumed..], &limits) {
+                Ok(Some((args, used))) => {
+                    consumed += used;
+                    args
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    debug!("Closing client {}: {}", client.id, e);
+                    let error = OwnedFrame::Error(format!("ERR Protocol error: {}", e));
+                    write_frame(&mut output, &error);
+                    flow = Flow::Close;
+                    break;
+                }
+            };
+            // Like Redis, blank inline lines and empty arrays get no reply
+            if args.is_empty() {
+                continue;
+            }
+
+            let replies;
+            (replies, flow) = handle_request(&args, &mut client, &executor);
+            // The primary answers PSYNC itself, once pending replies are flushed
+            if !matches!(flow, Flow::Sync { .. }) {
+                for reply in &replies {
+                    write_frame(&mut output, reply);
+                }
+            }
+            client.commands_processed += 1;
+            stats.commands_processed.fetch_add(1, Ordering::Relaxed);
+        }
+        input.drain(..consumed);
+
+        if output.is_empty() {
+            continue;
+        }
+        if stream.write_all(&output).await.is_err() {
+            break;
+        }
+        client.bytes_out += output.len() as u64;
+        stats
+            .net_output_bytes
+            .fetch_add(output.len() as u64, Ordering::Relaxed);
+        output.clear();
+    }
+
+    if let Flow::Sync { replid, offset } = flow
+        && let Some(primary) = executor.primary().cloned()
+    {
+        let planner = primary.clone();
+        let cache = executor.cache.clone();
+        let plan =
+            tokio::task::spawn_blocking(move || planner.plan_sync(&cache, &replid, offset)).await;
+        if let Ok(plan) = plan
+            && let Err(e) = primary.serve(stream, client.addr, input, plan).await
+        {
+            debug!("Replica link {} closed: {}", client.addr, e);
+        }
+    }
+
+    stats.connected_clients.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Waits for the next pub/sub message; never resolves for a connection that hasn't subscribed
+async fn next_message(subscriber: &mut Option<Subscriber>) -> Option<Message> {
+    match subscriber {
+        Some(subscriber) => subscriber.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Runs one request, answering connection-level commands here and the rest through the
+/// executor. Most requests get one reply; (P)(UN)SUBSCRIBE gets one per channel or pattern.
+fn handle_request(
+    args: &[Vec<u8>],
+    client: &mut ClientState,
+    executor: &CommandExecutor,
+) -> (Vec<OwnedFrame>, Flow) {
+    let args: Vec<&[u8]> = args.iter().map(Vec::as_slice).collect();
+    let name = args
+        .first()
+        .map(|name| String::from_utf8_lossy(name).to_ascii_uppercase());
+    let subscribed = client.subscriptions() > 0;
+
+    let reply = match name.as_deref() {
+        None => return (vec![OwnedFrame::Array(Vec::new())], Flow::Continue),
+        Some("QUIT") => return (vec![ok()], Flow::Close),
+        Some("SUBSCRIBE") => {
+            return (
+                subscribe(&args[1..], false, client, executor),
+                Flow::Continue,
+            );
+        }
+        Some("PSUBSCRIBE") => {
+            return (
+                subscribe(&args[1..], true, client, executor),
+                Flow::Continue,
+            );
+        }
+        Some("UNSUBSCRIBE") => return (unsubscribe(&args[1..], false, client), Flow::Continue),
+        Some("PUNSUBSCRIBE") => return (unsubscribe(&args[1..], true, client), Flow::Continue),
+        // A subscribed connection only carries pub/sub traffic
+        Some("PING") if subscribed => OwnedFrame::Array(vec![
+            bulk("pong".to_string()),
+            bulk(args.get(1).map_or_else(String::new, |message| {
+                String::from_utf8_lossy(message).into_owned()
+            })),
+        ]),
+        Some(_) if subscribed => error_frame(&CommandError::err(format!(
+            "Can't execute '{}': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT are allowed in this context",
+            String::from_utf8_lossy(args[0]).to_lowercase()
+        ))),
+        Some("CLIENT") => client_command(&args[1..], client),
+        // Replica handshake; the listening port and capabilities aren't needed
+        Some("REPLCONF") => ok(),
+        Some("PSYNC") | Some("SYNC") => {
+            if executor.primary().is_none() {
+                return (
+                    vec![error_frame(&CommandError::err(
+                        "replication is not enabled",
+                    ))],
+                    Flow::Continue,
+                );
+            }
+            let replid = args
+                .get(1)
+                .map(|id| String::from_utf8_lossy(id).into_owned());
+            let offset = args
+                .get(2)
+                .and_then(|o| std::str::from_utf8(o).ok()?.parse().ok());
+            let flow = Flow::Sync {
+                replid: replid.unwrap_or_else(|| "?".to_string()),
+                offset: offset.unwrap_or(-1),
+            };
+            return (Vec::new(), flow);
+        }
+        // Clients probe COMMAND DOCS on connect; an empty reply means no docs
+        Some("COMMAND") => OwnedFrame::Array(Vec::new()),
+        Some(_) => match Command::parse(&args) {
+            Ok(command) => {
+                let _span = client
+                    .trace_id
+                    .as_deref()
+                    .map(|id| trace::request_span(id).entered());
+                response_frame(executor.execute(command))
+            }
+            Err(e) => match executor.execute_plugin(&args) {
+                Some(response) => response_frame(response),
+                None => error_frame(&e.into()),
+            },
+        },
+    };
+    (vec![reply], Flow::Continue)
+}
+
+/// SUBSCRIBE channel [channel ...] or PSUBSCRIBE pattern [pattern ...], confirming
+/// each with the connection's subscription count
+fn subscribe(
+    names: &[&[u8]],
+    pattern: bool,
+    client: &mut ClientState,
+    executor: &CommandExecutor,
+) -> Vec<OwnedFrame> {
+    let kind = if pattern { "psubscribe" } else { "subscribe" };
+    if names.is_empty() {
+        return vec![error_frame(&CommandError::err(format!(
+            "wrong number of arguments for '{}' command",
+            kind
+        )))];
+    }
+    let subscriber = client
+        .subscriber
+        .get_or_insert_with(|| executor.pubsub().subscriber());
+    names
+        .iter()
+        .map(|name| {
+            let name = String::from_utf8_lossy(name).into_owned();
+            let count = if pattern {
+                subscriber.psubscribe(&name)
+            } else {
+                subscriber.subscribe(&name)
+            };
+            subscription_frame(kind, Some(name), count)
+        })
+        .collect()
+}
+
+/// UNSUBSCRIBE [channel ...] or PUNSUBSCRIBE [pattern ...], from all of them when none
+/// are named
+fn unsubscribe(names: &[&[u8]], pattern: bool, client: &mut ClientState) -> Vec<OwnedFrame> {
+    let kind = if pattern {
+        "punsubscribe"
+    } else {
+        "unsubscribe"
+    };
+    let Some(subscriber) = &mut client.subscriber else {
+        return vec![subscription_frame(kind, None, 0)];
+    };
+    let names: Vec<String> = match (names.is_empty(), pattern) {
+        (true, false) => subscriber.channels(),
+        (true, true) => subscriber.patterns(),
+        (false, _) => names
+            .iter()
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect(),
+    };
+    if names.is_empty() {
+        return vec![subscription_frame(kind, None, subscriber.count())];
+    }
+    names
+        .into_iter()
+        .map(|name| {
+            let count = if pattern {
+                subscriber.punsubscribe(&name)
+            } else {
+                subscriber.unsubscribe(&name)
+            };
+            subscription_frame(kind, Some(name), count)
+        })
+        .collect()
+}
+
+fn subscription_frame(kind: &str, channel: Option<String>, count: usize) -> OwnedFrame {
+    OwnedFrame::Array(vec![
+        bulk(kind.to_string()),
+        channel.map_or(OwnedFrame::Null, bulk),
+        OwnedFrame::Integer(count as i64),
+    ])
+}
+
+/// A published message as pushed to subscribers: `["message", channel, payload]`, or
+/// `["pmessage", pattern, channel, payload]` through a pattern subscription
+fn message_frame(message: Message) -> OwnedFrame {
+    let mut frames = match message.pattern {
+        Some(pattern) => vec![bulk("pmessage".to_string()), bulk(pattern)],
+        None => vec![bulk("message".to_string())],
+    };
+    frames.push(bulk(message.channel));
+    frames.push(bulk(message.payload));
+    OwnedFrame::Array(frames)
+}
+
+/// CLIENT ID | INFO | GETNAME | SETNAME name | TRACEID id
+fn client_command(args: &[&[u8]], client: &mut ClientState) -> OwnedFrame {
+    let subcommand = args
+        .first()
+        .map(|arg| String::from_utf8_lossy(arg).to_ascii_uppercase());
+    match (subcommand.as_deref(), &args[args.len().min(1)..]) {
+        (Some("ID"), []) => OwnedFrame::Integer(client.id as i64),
+        (Some("INFO"), []) => OwnedFrame::BulkString(format!("{}\n", client.info()).into_bytes()),
+        (Some("GETNAME"), []) => match &client.name {
+            Some(name) => OwnedFrame::BulkString(name.clone().into_bytes()),
+            None => OwnedFrame::Null,
+        },
+        (Some("SETNAME"), [name]) => {
+            let name = String::from_utf8_lossy(name);
+            if name.contains(char::is_whitespace) {
+                return error_frame(&CommandError::err(
+                    "Client names cannot contain spaces, newlines or special characters.",
+                ));
+            }
+            client.name = (!name.is_empty()).then(|| name.into_owned());
+            ok()
+        }
+        // A traceparent value or a plain request ID; empty clears it
+        (Some("TRACEID"), [id]) => {
+            let id = String::from_utf8_lossy(id);
+            if id.is_empty() {
+                client.trace_id = None;
+                return ok();
+            }
+            match trace::from_traceparent(&id).or_else(|| trace::request_id(&id)) {
+                Some(trace_id) => {
+                    client.trace_id = Some(trace_id);
+                    ok()
+                }
+                None => error_frame(&CommandError::err("Invalid trace ID")),
+            }
+        }
+        _ => error_frame(&CommandError::err(
+            "unknown subcommand or wrong number of arguments for 'CLIENT' command",
+        )),
+    }
+}
+
+/// Arguments of one request, command name first
+type Args = Vec<Vec<u8>>;
+
+/// Next complete request in `buf` and the bytes it used. Clients send arrays of bulk
+/// strings; anything else is read as an inline command, a line of space-separated
+/// words as typed into telnet or sent by simple health checkers.
+fn next_request(buf: &[u8], limits: &RespLimits) -> Result<Option<(Args, usize)>, String> {
+    match buf.first() {
+        None => Ok(None),
+        Some(b'*') => {
+            check_limits(buf, limits)?;
+            match decode(buf) {
+                Ok(Some((frame, used))) => request_args(frame)
+                    .map(|args| Some((args, used)))
+                    .ok_or_else(|| "expected an array of bulk strings".to_string()),
+                Ok(None) => Ok(None),
+                Err(e) => Err(e.details().to_string()),
+            }
+        }
+        Some(_) => {
+            let Some(end) = buf.iter().position(|&b| b == b'\n') else {
+                if buf.len() > limits.max_inline_len {
+                    return Err("too big inline request".into());
+                }
+                return Ok(None);
+            };
+            if end > limits.max_inline_len {
+                return Err("too big inline request".into());
+            }
+            let line = buf[..end].strip_suffix(b"\r").unwrap_or(&buf[..end]);
+            let args = split_inline(line).ok_or("unbalanced quotes in request")?;
+            Ok(Some((args, end + 1)))
+        }
+    }
+}
+
+/// Checks the array and bulk headers received so far against `limits`, so oversized
+/// requests are refused before their data is buffered. Malformed frames are left for
+/// the decoder to report.
+fn check_limits(buf: &[u8], limits: &RespLimits) -> Result<(), String> {
+    let mut pos = 0;
+    let Some(count) = header(buf, &mut pos, "multibulk")? else {
+        return Ok(());
+    };
+    if count > limits.max_multibulk_len as i64 {
+        return Err("invalid multibulk length".into());
+    }
+
+    for _ in 0..count {
+        if buf.get(pos) != Some(&b'$') {
+            return Ok(());
+        }
+        let Some(len) = header(buf, &mut pos, "bulk")? else {
+            return Ok(());
+        };
+        if len > limits.max_bulk_len as i64 {
+            return Err("invalid bulk length".into());
+        }
+        pos += len.max(0) as usize + 2;
+        if pos > limits.max_request_size {
+            return Err("request exceeds the maximum size".into());
+        }
+    }
+    Ok(())
+}
+
+/// Length in the `*N\r\n` or `$N\r\n` header at `pos`, advancing past it. None while
+/// the header is incomplete.
+fn header(buf: &[u8], pos: &mut usize, kind: &str) -> Result<Option<i64>, String> {
+    // A sign and 20 digits, plus the type byte and CRLF
+    const MAX_HEADER_LEN: usize = 24;
+
+    let rest = &buf[*pos..];
+    let Some(end) = rest.iter().take(MAX_HEADER_LEN).position(|&b| b == b'\r') else {
+        if rest.len() >= MAX_HEADER_LEN {
+            return Err(format!("invalid {} length", kind));
+        }
+        return Ok(None);
+    };
+    let len = std::str::from_utf8(&rest[1..end])
+        .ok()
+        .and_then(|digits| digits.parse().ok())
+        .ok_or_else(|| format!("invalid {} length", kind))?;
+    *pos += end + 2;
+    Ok(Some(len))
+}
+
+/// Splits an inline command into words. Like redis-cli, words may be quoted: double
+/// quotes understand backslash escapes (`\n`, `\"`, `\x41`, ...), single quotes only `\'`.
+fn split_inline(line: &[u8]) -> Option<Args> {
+    let mut args = Vec::new();
+    let mut bytes = line.iter().copied().peekable();
+
+    loop {
+        while bytes.next_if(u8::is_ascii_whitespace).is_some() {}
+        let Some(first) = bytes.next() else {
+            return Some(args);
+        };
+
+        let mut arg = Vec::new();
+        match first {
+            b'"' => loop {
+                match bytes.next()? {
+                    b'"' => break,
+                    b'\\' => match bytes.next()? {
+                        b'n' => arg.push(b'\n'),
+                        b'r' => arg.push(b'\r'),
+                        b't' => arg.push(b'\t'),
+                        b'x' => {
+                            let hex = [bytes.next()?, bytes.next()?];
+                            let hex = std::str::from_utf8(&hex).ok()?;
+                            arg.push(u8::from_str_radix(hex, 16).ok()?);
+                        }
+                        other => arg.push(other),
+                    },
+                    b => arg.push(b),
+                }
+            },
+            b'\'' => loop {
+                match bytes.next()? {
+                    b'\'' => break,
+                    b'\\' if bytes.peek() == Some(&b'\'') => arg.push(bytes.next()?),
+                    b => arg.push(b),
+                }
+            },
+            b => {
+                arg.push(b);
+                while let Some(b) = bytes.next_if(|b| !b.is_ascii_whitespace()) {
+                    arg.push(b);
+                }
+            }
+        }
+        // A closing quote must end the word
+        if matches!(first, b'"' | b'\'') && bytes.peek().is_some_and(|b| !b.is_ascii_whitespace()) {
+            return None;
+        }
+        args.push(arg);
+    }
+}
+
+/// Arguments of a request, which clients send as an array of bulk strings
+fn request_args(frame: OwnedFrame) -> Option<Args> {
+    let OwnedFrame::Array(frames) = frame else {
+        return None;
+    };
+    frames
+        .into_iter()
+        .map(|frame| match frame {
+            OwnedFrame::BulkString(arg) | OwnedFrame::SimpleString(arg) => Some(arg),
+            _ => None,
+        })
+        .collect()
+}
+
+/// RESP2 form of an executor reply. Structured replies without a Redis equivalent are
+/// flattened the way HGETALL replies are: objects become field/value arrays.
+pub fn response_frame(response: CommandResponse) -> OwnedFrame {
+    match response {
+        CommandResponse::Ok => ok(),
+        CommandResponse::Value(value) => OwnedFrame::BulkString(value.into_bytes()),
+        CommandResponse::Bytes(bytes) => OwnedFrame::BulkString(bytes),
+        CommandResponse::Integer(i) => OwnedFrame::Integer(i),
+        CommandResponse::Integers(items) => {
+            OwnedFrame::Array(items.into_iter().map(OwnedFrame::Integer).collect())
+        }
+        CommandResponse::Array(items) => OwnedFrame::Array(items.into_iter().map(bulk).collect()),
+        CommandResponse::ArrayWithDepth(items) => OwnedFrame::Array(
+            items
+                .into_iter()
+                .map(|(key, depth)| {
+                    OwnedFrame::Array(vec![bulk(key), OwnedFrame::Integer(depth as i64)])
+                })
+                .collect(),
+        ),
+        // Same shape as Redis XREAD: [[key, [[id, [field, value, ...]], ...]], ...]
+        CommandResponse::Streams(streams) => OwnedFrame::Array(
+            streams
+                .into_iter()
+                .map(|(key, entries)| {
+                    let entries = entries
+                        .into_iter()
+                        .map(|entry| {
+                            let fields = entry
+                                .fields
+                                .into_iter()
+                                .flat_map(|(field, value)| [bulk(field), bulk(value)])
+                                .collect();
+                            OwnedFrame::Array(vec![
+                                bulk(entry.id.to_string()),
+                                OwnedFrame::Array(fields),
+                            ])
+                        })
+                        .collect();
+                    OwnedFrame::Array(vec![bulk(key), OwnedFrame::Array(entries)])
+                })
+                .collect(),
+        ),
+        response @ (CommandResponse::Children(_)
+        | CommandResponse::KeyInfo(_)
+        | CommandResponse::DryRun(_)) => json_frame(response.to_json()),
+        // Same shape as a Redis SCAN reply: [cursor, reply]
+        CommandResponse::Partial { cursor, reply } => {
+            OwnedFrame::Array(vec![bulk(cursor.to_string()), response_frame(*reply)])
+        }
+        CommandResponse::Null => OwnedFrame::Null,
+        CommandResponse::Error(e) => error_frame(&e),
+    }
+}
+
+fn json_frame(value: serde_json::Value) -> OwnedFrame {
+    use serde_json::Value as Json;
+    match value {
+        Json::Null => OwnedFrame::Null,
+        Json::Bool(b) => OwnedFrame::Integer(b as i64),
+        Json::Number(n) => match n.as_i64() {
+            Some(i) => OwnedFrame::Integer(i),
+            None => bulk(n.to_string()),
+        },
+        Json::String(s) => bulk(s),
+        Json::Array(items) => OwnedFrame::Array(items.into_iter().map(json_frame).collect()),
+        Json::Object(fields) => OwnedFrame::Array(
+            fields
+                .into_iter()
+                .flat_map(|(field, value)| [bulk(field), json_frame(value)])
+                .collect(),
+        ),
+    }
+}
+
+fn ok() -> OwnedFrame {
+    OwnedFrame::SimpleString(b"OK".to_vec())
+}
+
+fn bulk(s: String) -> OwnedFrame {
+    OwnedFrame::BulkString(s.into_bytes())
+}
+
+/// Error replies are single lines, so any newlines in the message are flattened
+fn error_frame(e: &CommandError) -> OwnedFrame {
+    OwnedFrame::Error(e.to_string().replace(['\r', '\n'], " "))
+}
+
+fn write_frame(out: &mut Vec<u8>, frame: &OwnedFrame) {
+    let offset = out.len();
+    out.resize(offset + frame.encode_len(false), 0);
+    // The buffer is sized from encode_len, so encoding can't run out of room
+    let written = encode(&mut out[offset..], frame, false).expect("frame fits its encoded length");
+    out.truncate(offset + written);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Cache, Config};
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn request(executor: &CommandExecutor, client: &mut ClientState, raw: &[u8]) -> Vec<u8> {
+        let (args, _) = next_request(raw, &RespLimits::default()).unwrap().unwrap();
+        let (replies, _) = handle_request(&args, client, executor);
+        let mut out = Vec::new();
+        for reply in &replies {
+            write_frame(&mut out, reply);
+        }
+        out
+    }
+
+    #[test]
+    fn test_resp_round_trip() {
+        let executor = CommandExecutor::new(Arc::new(Cache::new(Config::default())));
+        let mut client = ClientState::new(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0));
+
+        let set = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$5\r\nhello\r\n";
+        assert_eq!(request(&executor, &mut client, set), b"+OK\r\n");
+        let get = b"*2\r\n$3\r\nget\r\n$1\r\nk\r\n";
+        assert_eq!(request(&executor, &mut client, get), b"$5\r\nhello\r\n");
+        let missing = b"*2\r\n$3\r\nGET\r\n$1\r\nx\r\n";
+        assert_eq!(request(&executor, &mut client, missing), b"$-1\r\n");
+        let del = b"*3\r\n$3\r\nDEL\r\n$1\r\nk\r\n$1\r\nx\r\n";
+        assert_eq!(request(&executor, &mut client, del), b":1\r\n");
+
+        // Values needn't be UTF-8
+        let set = b"*3\r\n$3\r\nSET\r\n$1\r\nb\r\n$3\r\n\xff\x00\xfe\r\n";
+        assert_eq!(request(&executor, &mut client, set), b"+OK\r\n");
+        let append = b"*3\r\n$6\r\nAPPEND\r\n$1\r\nb\r\n$1\r\n\x80\r\n";
+        assert_eq!(request(&executor, &mut client, append), b":4\r\n");
+        let get = b"*2\r\n$3\r\nGET\r\n$1\r\nb\r\n";
+        assert_eq!(
+            request(&executor, &mut client, get),
+            b"$4\r\n\xff\x00\xfe\x80\r\n"
+        );
+        let getrange = b"*4\r\n$8\r\nGETRANGE\r\n$1\r\nb\r\n$1\r\n0\r\n$1\r\n0\r\n";
+        assert_eq!(request(&executor, &mut client, getrange), b"$1\r\n\xff\r\n");
+
+        let unknown = b"*1\r\n$4\r\nNOPE\r\n";
+        assert!(request(&executor, &mut client, unknown).starts_with(b"-ERR unknown command"));
+        let setname = b"*3\r\n$6\r\nCLIENT\r\n$7\r\nSETNAME\r\n$3\r\ncli\r\n";
+        assert_eq!(request(&executor, &mut client, setname), b"+OK\r\n");
+        assert_eq!(client.name.as_deref(), Some("cli"));
+        let traceid = b"CLIENT TRACEID 00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01\r\n";
+        assert_eq!(request(&executor, &mut client, traceid), b"+OK\r\n");
+        assert!(
+            client
+                .info()
+                .ends_with("traceid=4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+        let invalid = b"CLIENT TRACEID \"a b\"\r\n";
+        assert!(request(&executor, &mut client, invalid).starts_with(b"-ERR Invalid trace ID"));
+
+        let inline = b"SET greeting \"hello world\"\r\n";
+        assert_eq!(request(&executor, &mut client, inline), b"+OK\r\n");
+        let inline = b"GET 'greeting'\n";
+        assert_eq!(
+            request(&executor, &mut client, inline),
+            b"$11\r\nhello world\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pubsub() {
+        let executor = CommandExecutor::new(Arc::new(Cache::new(Config::default())));
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+        let mut subscriber = ClientState::new(addr);
+        let mut publisher = ClientState::new(addr);
+
+        assert_eq!(
+            request(&executor, &mut subscriber, b"SUBSCRIBE news sports\r\n"),
+            b"*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n\
+              *3\r\n$9\r\nsubscribe\r\n$6\r\nsports\r\n:2\r\n"
+        );
+        assert_eq!(
+            request(&executor, &mut publisher, b"PUBLISH news hi\r\n"),
+            b":1\r\n"
+        );
+        let message = next_message(&mut subscriber.subscriber).await.unwrap();
+        let mut out = Vec::new();
+        write_frame(&mut out, &message_frame(message));
+        assert_eq!(out, b"*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n");
+
+        // Only pub/sub commands are accepted until every channel is unsubscribed
+        assert!(
+            request(&executor, &mut subscriber, b"GET k\r\n")
+                .starts_with(b"-ERR Can't execute 'get'")
+        );
+        assert_eq!(
+            request(&executor, &mut subscriber, b"PING\r\n"),
+            b"*2\r\n$4\r\npong\r\n$0\r\n\r\n"
+        );
+        assert_eq!(
+            request(&executor, &mut subscriber, b"PSUBSCRIBE news.*\r\n"),
+            b"*3\r\n$10\r\npsubscribe\r\n$6\r\nnews.*\r\n:3\r\n"
+        );
+        request(&executor, &mut publisher, b"PUBLISH news.eu hey\r\n");
+        let message = next_message(&mut subscriber.subscriber).await.unwrap();
+        let mut out = Vec::new();
+        write_frame(&mut out, &message_frame(message));
+        assert_eq!(
+            out,
+            b"*4\r\n$8\r\npmessage\r\n$6\r\nnews.*\r\n$7\r\nnews.eu\r\n$3\r\nhey\r\n"
+        );
+
+        request(&executor, &mut subscriber, b"UNSUBSCRIBE\r\n");
+        assert_eq!(subscriber.subscriptions(), 1);
+        request(&executor, &mut subscriber, b"PUNSUBSCRIBE\r\n");
+        assert_eq!(subscriber.subscriptions(), 0);
+        assert_eq!(
+            request(&executor, &mut subscriber, b"GET k\r\n"),
+            b"$-1\r\n"
+        );
+        assert_eq!(
+            request(&executor, &mut subscriber, b"UNSUBSCRIBE\r\n"),
+            b"*3\r\n$11\r\nunsubscribe\r\n$-1\r\n:0\r\n"
+        );
+    }
+
+    #[test]
+    fn test_split_inline() {
+        let split = |line: &str| split_inline(line.as_bytes());
+        assert_eq!(split("  ping  "), Some(vec![b"ping".to_vec()]));
+        assert_eq!(split(""), Some(vec![]));
+        assert_eq!(
+            split(r#"set k "a\"b\x41\n" 'it\'s'"#),
+            Some(vec![
+                b"set".to_vec(),
+                b"k".to_vec(),
+                b"a\"bA\n".to_vec(),
+                b"it's".to_vec()
+            ])
+        );
+        assert_eq!(split("get \"unterminated"), None);
+        assert_eq!(split("get \"a\"b"), None);
+        assert_eq!(next_request(b"PING", &RespLimits::default()), Ok(None));
+    }
+
+    #[test]
+    fn test_request_limits() {
+        let limits = RespLimits {
+            max_multibulk_len: 4,
+            max_bulk_len: 8,
+            max_request_size: 24,
+            max_inline_len: 16,
+        };
+        let next = |raw: &[u8]| next_request(raw, &limits);
+
+        // Refused from the header alone, before the data arrives
+        assert_eq!(next(b"*5\r\n"), Err("invalid multibulk length".into()));
+        assert_eq!(
+            next(b"*2\r\n$3\r\nSET\r\n$9\r\n"),
+            Err("invalid bulk length".into())
+        );
+        assert_eq!(
+            next(b"*4\r\n$8\r\n12345678\r\n$8\r\n"),
+            Err("request exceeds the maximum size".into())
+        );
+        assert_eq!(
+            next(b"*99999999999999999999999"),
+            Err("invalid multibulk length".into())
+        );
+        assert_eq!(next(b"*2\r\n$x\r\n"), Err("invalid bulk length".into()));
+        assert_eq!(next(&[b'a'; 17]), Err("too big inline request".into()));
+
+        // Within limits, partial requests wait for more data
+        assert_eq!(next(b"*2\r\n$3\r\nGET\r\n$2\r\n"), Ok(None));
+        assert_eq!(
+            next(b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n"),
+            Ok(Some((vec![b"GET".to_vec(), b"k".to_vec()], 20)))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connection_stats() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let executor = Arc::new(CommandExecutor::new(Arc::new(
+            Cache::new(Config::default()),
+        )));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = RespServer::new(executor.clone());
+        tokio::spawn(async move { server.serve(listener).await });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut reply = [0; 512];
+        stream.write_all(b"CLIENT SETNAME app\r\n").await.unwrap();
+        assert_eq!(stream.read(&mut reply).await.unwrap(), 5);
+        stream.write_all(b"CLIENT INFO\r\n").await.unwrap();
+        let n = stream.read(&mut reply).await.unwrap();
+        let info = String::from_utf8_lossy(&reply[..n]);
+        assert!(info.contains(" name=app "), "{info}");
+        assert!(
+            info.contains(" cmds=1 tot-net-in=33 tot-net-out=5"),
+            "{info}"
+        );
+
+        let stats = executor.cache.stats();
+        assert_eq!(stats.connected_clients.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.total_connections.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.net_input_bytes.load(Ordering::Relaxed), 33);
+        assert!(stats.render().contains("cache_connected_clients 1"));
 
-    while reader.read_line(&mut line).await.unwrap_or(0) > 0 {
-        // TODO: Implement RESP protocol parsing
-        // parse -> Command
-        // executor.execute(command);
-        executor.execute(Command::Ping {
-            message: (Some("TODO".to_string())),
-        });
-        writer.write_all(b"TODO: RESP parsing\r\n").await.ok();
-        line.clear();
+        stream.write_all(b"QUIT\r\n").await.unwrap();
+        assert_eq!(stream.read(&mut reply).await.unwrap(), 5);
+        assert_eq!(stream.read(&mut reply).await.unwrap(), 0);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(stats.connected_clients.load(Ordering::Relaxed), 0);
+        assert_eq!(stats.commands_processed.load(Ordering::Relaxed), 3);
     }
 }
@@ -0,0 +1,158 @@
+use crate::executor::{Command, CommandResponse};
+use crate::metrics::Labels;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct ResponseCacheConfig {
+    pub ttl: Duration,
+    pub max_entries: usize, // Replies beyond this aren't cached until expired ones are dropped
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(1),
+            max_entries: 1024,
+        }
+    }
+}
+
+struct Cached {
+    response: CommandResponse,
+    version: u64,
+    stored_at: Instant,
+}
+
+/// Replies to expensive idempotent reads, so dashboards polling the same traversal
+/// share one computation. Replies are keyed by the command's arguments and tagged
+/// with the keyspace version they were computed at: any write since makes them
+/// stale, and `ttl` bounds how long expiry and eviction, which aren't writes, go
+/// unnoticed.
+pub struct ResponseCache {
+    config: ResponseCacheConfig,
+    entries: Mutex<HashMap<Vec<Vec<u8>>, Cached>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ResponseCache {
+    pub fn new(config: ResponseCacheConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether replies to the command are worth caching
+    pub fn caches(command: &Command) -> bool {
+        matches!(
+            command,
+            Command::GetChildren { .. } | Command::ListKeys { .. } | Command::ExpiringKeys { .. }
+        )
+    }
+
+    /// The reply for `args`, if computed at `version` within the TTL
+    pub fn get(&self, args: &[Vec<u8>], version: u64) -> Option<CommandResponse> {
+        let entries = self.entries.lock().unwrap();
+        let response = entries
+            .get(args)
+            .filter(|cached| {
+                cached.version == version && cached.stored_at.elapsed() < self.config.ttl
+            })
+            .map(|cached| cached.response.clone());
+        let counter = match response {
+            Some(_) => &self.hits,
+            None => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        response
+    }
+
+    /// Stores a reply computed at `version`; errors aren't cached
+    pub fn insert(&self, args: Vec<Vec<u8>>, version: u64, response: &CommandResponse) {
+        if matches!(response, CommandResponse::Error(_)) {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.config.max_entries && !entries.contains_key(&args) {
+            let ttl = self.config.ttl;
+            entries.retain(|_, cached| cached.stored_at.elapsed() < ttl);
+            if entries.len() >= self.config.max_entries {
+                return;
+            }
+        }
+        entries.insert(
+            args,
+            Cached {
+                response: response.clone(),
+                version,
+                stored_at: Instant::now(),
+            },
+        );
+    }
+
+    pub fn render_metrics(&self, out: &mut String, labels: &Labels) {
+        let series = labels.series();
+        let families = [
+            (
+                "cache_response_cache_hits_total",
+                "Expensive reads answered from the response cache",
+                &self.hits,
+            ),
+            (
+                "cache_response_cache_misses_total",
+                "Expensive reads computed and stored in the response cache",
+                &self.misses,
+            ),
+        ];
+        for (name, help, counter) in families {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} counter", name).unwrap();
+            writeln!(
+                out,
+                "{}{} {}",
+                name,
+                series,
+                counter.load(Ordering::Relaxed)
+            )
+            .unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replies_expire_on_write_and_ttl() {
+        let cache = ResponseCache::new(ResponseCacheConfig {
+            ttl: Duration::from_millis(50),
+            max_entries: 1,
+        });
+        let args = vec![b"KEYS".to_vec(), b"*".to_vec()];
+        let reply = CommandResponse::Integer(1);
+
+        cache.insert(args.clone(), 7, &reply);
+        assert!(matches!(
+            cache.get(&args, 7),
+            Some(CommandResponse::Integer(1))
+        ));
+        assert!(cache.get(&args, 8).is_none());
+
+        // Full until the first reply expires
+        let other = vec![b"KEYS".to_vec(), b"a*".to_vec()];
+        cache.insert(other.clone(), 7, &reply);
+        assert!(cache.get(&other, 7).is_none());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(cache.get(&args, 7).is_none());
+        cache.insert(other.clone(), 7, &reply);
+        assert!(cache.get(&other, 7).is_some());
+    }
+}
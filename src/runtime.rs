@@ -0,0 +1,109 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::runtime::{Builder, Handle, Runtime};
+
+/// Tokio topology: request workers, optional core pinning, and an optional
+/// dedicated runtime so background jobs can't preempt request handling
+#[derive(Debug, Clone, Default)]
+pub struct RuntimeConfig {
+    pub worker_threads: Option<usize>, // Defaults to one per core
+    pub pin_cores: bool,
+    pub background_threads: Option<usize>, // Background jobs share the request runtime when unset
+}
+
+impl RuntimeConfig {
+    /// Reads DASHDOT_WORKER_THREADS, DASHDOT_PIN_CORES and DASHDOT_BACKGROUND_THREADS
+    pub fn from_env() -> Self {
+        let threads = |name| std::env::var(name).ok().and_then(|v| v.parse().ok());
+        Self {
+            worker_threads: threads("DASHDOT_WORKER_THREADS"),
+            pin_cores: std::env::var("DASHDOT_PIN_CORES").is_ok_and(|v| v == "1" || v == "true"),
+            background_threads: threads("DASHDOT_BACKGROUND_THREADS"),
+        }
+    }
+}
+
+pub struct Runtimes {
+    pub requests: Runtime,
+    pub background: Option<Runtime>,
+}
+
+impl Runtimes {
+    pub fn build(config: &RuntimeConfig) -> io::Result<Self> {
+        let mut requests = Builder::new_multi_thread();
+        requests.enable_all().thread_name("dashdot-worker");
+        if let Some(threads) = config.worker_threads {
+            requests.worker_threads(threads);
+        }
+        if config.pin_cores {
+            let cores = Arc::new(core_affinity::get_core_ids().unwrap_or_default());
+            let next = AtomicUsize::new(0);
+            requests.on_thread_start(move || {
+                if !cores.is_empty() {
+                    let core = cores[next.fetch_add(1, Ordering::Relaxed) % cores.len()];
+                    core_affinity::set_for_current(core);
+                }
+            });
+        }
+
+        let background = config
+            .background_threads
+            .map(|threads| {
+                Builder::new_multi_thread()
+                    .enable_all()
+                    .thread_name("dashdot-background")
+                    .worker_threads(threads.max(1))
+                    .build()
+            })
+            .transpose()?;
+
+        Ok(Self {
+            requests: requests.build()?,
+            background,
+        })
+    }
+
+    /// Handle for spawning background jobs (cleanup, persistence, aggregation)
+    pub fn background_handle(&self) -> Handle {
+        self.background
+            .as_ref()
+            .unwrap_or(&self.requests)
+            .handle()
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_background_runtime() {
+        let shared = Runtimes::build(&RuntimeConfig {
+            worker_threads: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(shared.background.is_none());
+        let worker = shared.requests.block_on(
+            shared
+                .background_handle()
+                .spawn(async { std::thread::current().name().map(String::from) }),
+        );
+        assert_eq!(worker.unwrap().as_deref(), Some("dashdot-worker"));
+
+        let split = Runtimes::build(&RuntimeConfig {
+            worker_threads: Some(1),
+            pin_cores: true,
+            background_threads: Some(0),
+        })
+        .unwrap();
+        let worker = split.requests.block_on(
+            split
+                .background_handle()
+                .spawn(async { std::thread::current().name().map(String::from) }),
+        );
+        assert_eq!(worker.unwrap().as_deref(), Some("dashdot-background"));
+    }
+}
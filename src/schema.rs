@@ -0,0 +1,220 @@
+use crate::cache::namespace_of;
+use crate::cache_errors::{CommandError, ErrorClass};
+use crate::executor::{Command, CommandResponse};
+use crate::metrics::Labels;
+use crate::middleware::Middleware;
+use jsonschema::Validator;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Violations listed in a rejection; the rest are summarised as a count
+const MAX_REPORTED_ERRORS: usize = 5;
+
+struct NamespaceSchema {
+    schema: serde_json::Value,
+    validator: Validator,
+}
+
+/// JSON Schemas attached to key namespaces. A SET into a namespace with a schema
+/// must carry a JSON value that satisfies it, or is rejected with an INVALID error
+/// (422 over HTTP) listing what's wrong. Namespaces without a schema are unchecked.
+pub struct Schemas {
+    delimiter: char,
+    schemas: RwLock<HashMap<String, NamespaceSchema>>,
+    rejected: AtomicU64,
+}
+
+impl Schemas {
+    pub fn new(delimiter: char) -> Self {
+        Self {
+            delimiter,
+            schemas: RwLock::new(HashMap::new()),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Attaches `schema` to `namespace`, replacing any schema it had
+    pub fn set(&self, namespace: &str, schema: serde_json::Value) -> Result<(), String> {
+        if namespace.is_empty() || namespace.contains(self.delimiter) {
+            return Err(format!("invalid namespace '{}'", namespace));
+        }
+        let validator = jsonschema::validator_for(&schema)
+            .map_err(|e| format!("invalid schema for '{}': {}", namespace, e))?;
+        self.schemas
+            .write()
+            .unwrap()
+            .insert(namespace.to_string(), NamespaceSchema { schema, validator });
+        Ok(())
+    }
+
+    pub fn remove(&self, namespace: &str) -> bool {
+        self.schemas.write().unwrap().remove(namespace).is_some()
+    }
+
+    pub fn get(&self, namespace: &str) -> Option<serde_json::Value> {
+        let schemas = self.schemas.read().unwrap();
+        schemas.get(namespace).map(|s| s.schema.clone())
+    }
+
+    pub fn namespaces(&self) -> Vec<String> {
+        let mut namespaces: Vec<String> = self.schemas.read().unwrap().keys().cloned().collect();
+        namespaces.sort();
+        namespaces
+    }
+
+    /// Loads `<namespace>.json` files from `dir`, returning how many were attached
+    pub fn load_dir(&self, dir: &Path) -> io::Result<usize> {
+        let mut loaded = 0;
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let Some(namespace) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let schema = serde_json::from_slice(&std::fs::read(&path)?).map_err(|e| {
+                io::Error::new(ErrorKind::InvalidData, format!("{:?}: {}", path, e))
+            })?;
+            self.set(namespace, schema)
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+
+    /// Checks a value written to `key` against its namespace's schema
+    pub fn validate(&self, key: &str, value: &[u8]) -> Result<(), CommandError> {
+        let Some(namespace) = namespace_of(key, self.delimiter) else {
+            return Ok(());
+        };
+        let schemas = self.schemas.read().unwrap();
+        let Some(schema) = schemas.get(namespace) else {
+            return Ok(());
+        };
+        let invalid = |detail: String| {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            CommandError::new(
+                ErrorClass::Invalid,
+                format!(
+                    "value for '{}' doesn't match the schema for namespace '{}': {}",
+                    key, namespace, detail
+                ),
+            )
+        };
+        let instance: serde_json::Value = serde_json::from_slice(value)
+            .map_err(|e| invalid(format!("not valid JSON ({})", e)))?;
+        let errors: Vec<String> = schema
+            .validator
+            .iter_errors(&instance)
+            .map(|e| match e.instance_path.as_str() {
+                "" => e.to_string(),
+                path => format!("{}: {}", path, e),
+            })
+            .collect();
+        if errors.is_empty() {
+            return Ok(());
+        }
+        let mut detail = errors[..errors.len().min(MAX_REPORTED_ERRORS)].join("; ");
+        if errors.len() > MAX_REPORTED_ERRORS {
+            write!(detail, " (and {} more)", errors.len() - MAX_REPORTED_ERRORS).unwrap();
+        }
+        Err(invalid(detail))
+    }
+}
+
+impl Middleware for Schemas {
+    fn before(&self, command: Command) -> Result<Command, CommandResponse> {
+        let result = match &command {
+            Command::Set { key, value, .. } => self.validate(key, value),
+            // A partial write can't be checked without the rest of the value
+            Command::Append { key, .. } | Command::SetRange { key, .. }
+                if namespace_of(key, self.delimiter)
+                    .and_then(|ns| self.get(ns))
+                    .is_some() =>
+            {
+                Err(CommandError::new(
+                    ErrorClass::Invalid,
+                    format!(
+                        "'{}' is in a namespace with a schema; write it whole with SET",
+                        key
+                    ),
+                ))
+            }
+            _ => Ok(()),
+        };
+        match result {
+            Ok(()) => Ok(command),
+            Err(e) => Err(CommandResponse::Error(e)),
+        }
+    }
+
+    fn render_metrics(&self, out: &mut String, labels: &Labels) {
+        let name = "cache_schema_rejections_total";
+        writeln!(out, "# HELP {} Writes rejected by a namespace schema", name).unwrap();
+        writeln!(out, "# TYPE {} counter", name).unwrap();
+        let rejected = self.rejected.load(Ordering::Relaxed);
+        writeln!(out, "{}{} {}", name, labels.series(), rejected).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_schema_validation() {
+        let schemas = Schemas::new(':');
+        let schema = json!({
+            "type": "object",
+            "properties": {"name": {"type": "string"}, "age": {"type": "integer"}},
+            "required": ["name"]
+        });
+        schemas.set("user", schema).unwrap();
+        assert!(schemas.set("user", json!({"type": 5})).is_err());
+        assert!(schemas.set("a:b", json!({})).is_err());
+        assert_eq!(schemas.namespaces(), ["user"]);
+
+        assert!(
+            schemas
+                .validate("user:1", br#"{"name": "ada", "age": 36}"#)
+                .is_ok()
+        );
+        assert!(schemas.validate("session:1", b"not json").is_ok());
+        assert!(schemas.validate("user", b"not json").is_ok());
+
+        let e = schemas
+            .validate("user:1", br#"{"age": "old"}"#)
+            .unwrap_err();
+        assert_eq!(e.class, ErrorClass::Invalid);
+        assert!(e.message.contains("/age"), "{}", e.message);
+        assert!(
+            e.message.contains("\"name\" is a required property"),
+            "{}",
+            e.message
+        );
+        let e = schemas.validate("user:2", b"{oops").unwrap_err();
+        assert!(e.message.contains("not valid JSON"), "{}", e.message);
+
+        let set = |value: &str| Command::Set {
+            key: "user:3".into(),
+            value: value.into(),
+            options: Default::default(),
+            concern: None,
+        };
+        assert!(schemas.before(set(r#"{"name": "x"}"#)).is_ok());
+        assert!(schemas.before(set("[]")).is_err());
+        let append = Command::Append {
+            key: "user:3".into(),
+            value: "}".into(),
+        };
+        assert!(schemas.before(append).is_err());
+        assert!(schemas.remove("user"));
+        assert!(schemas.before(set("[]")).is_ok());
+    }
+}
@@ -0,0 +1,580 @@
+use crate::cache::SetOptions;
+use crate::cache_errors::{CommandError, ErrorClass};
+use crate::executor::{Command, CommandExecutor, CommandResponse};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use wasmi::core::{HostError, TrapCode};
+use wasmi::{
+    Caller, Engine, Extern, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+    TypedResumableCall, Val,
+};
+
+/// Host module scripts import from; nothing else is linked, so a script has no I/O
+/// beyond these
+const HOST_MODULE: &str = "dashdot";
+
+/// Per-script resource limits for EVAL
+#[derive(Debug, Clone, Copy)]
+pub struct ScriptLimits {
+    pub fuel: u64,            // Roughly one unit per instruction
+    pub max_memory: usize,    // Bytes of linear memory
+    pub time_limit: Duration, // Enforced at each host call
+    pub busy_after: Duration, // Other EVALs get BUSY while a script runs longer than this
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self {
+            fuel: 100_000_000,
+            max_memory: 16 * 1024 * 1024,
+            time_limit: Duration::from_secs(30),
+            busy_after: Duration::from_secs(5),
+        }
+    }
+}
+
+impl ScriptLimits {
+    /// Defaults overridden by DASHDOT_SCRIPT_FUEL, DASHDOT_SCRIPT_MAX_MEMORY,
+    /// DASHDOT_SCRIPT_TIME_LIMIT_MS and DASHDOT_SCRIPT_BUSY_MS
+    pub fn from_env() -> Self {
+        fn parsed<T: std::str::FromStr>(name: &str) -> Option<T> {
+            std::env::var(name).ok()?.parse().ok()
+        }
+        let defaults = Self::default();
+        Self {
+            fuel: parsed("DASHDOT_SCRIPT_FUEL").unwrap_or(defaults.fuel),
+            max_memory: parsed("DASHDOT_SCRIPT_MAX_MEMORY").unwrap_or(defaults.max_memory),
+            time_limit: parsed("DASHDOT_SCRIPT_TIME_LIMIT_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.time_limit),
+            busy_after: parsed("DASHDOT_SCRIPT_BUSY_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.busy_after),
+        }
+    }
+}
+
+/// A script in progress
+struct Running {
+    started: Instant,
+    killed: AtomicBool,
+}
+
+/// Runs EVAL scripts: WebAssembly modules, in text or binary form, each call in a
+/// fresh sandbox.
+///
+/// A script exports `memory`, `alloc(len) -> ptr` and `run(ptr, len) -> i64`. `run`
+/// gets the number of keys as a little-endian u32, then the keys and arguments, each
+/// as a u32 length then its bytes. It returns `ptr << 32 | len` of its reply: `+` for
+/// OK, `$value`, `:integer`, `-error` or `_` for nil. It may import from `dashdot`:
+///
+/// - `get(key, key_len, out, out_cap) -> i64`: value length, -1 if missing; the
+///   value is copied to `out` only when it fits
+/// - `set(key, key_len, value, value_len, ttl_ms) -> i32`: 0 on success; no TTL
+///   unless `ttl_ms` is positive
+/// - `del(key, key_len) -> i32`: 1 if the key was removed
+///
+/// Host calls suspend the script while the executor runs them as ordinary commands.
+/// A script that uses up its fuel or memory fails; one that was killed or is over
+/// its time limit fails at its next host call. Writes made before that stay.
+pub struct Scripts {
+    engine: Engine,
+    linker: Linker<StoreLimits>,
+    limits: ScriptLimits,
+    running: Mutex<Vec<Arc<Running>>>,
+}
+
+impl Scripts {
+    pub fn new(limits: ScriptLimits) -> Self {
+        let mut config = wasmi::Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        Self {
+            linker: host_linker(&engine),
+            engine,
+            limits,
+            running: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn limits(&self) -> ScriptLimits {
+        self.limits
+    }
+
+    /// Scripts running right now
+    pub fn running(&self) -> usize {
+        self.running.lock().unwrap().len()
+    }
+
+    /// Stops every running script at its next host call, returning how many there were
+    pub fn kill(&self) -> usize {
+        let running = self.running.lock().unwrap();
+        for script in running.iter() {
+            script.killed.store(true, Ordering::Relaxed);
+        }
+        running.len()
+    }
+
+    pub fn eval(
+        &self,
+        executor: &CommandExecutor,
+        script: &str,
+        keys: &[String],
+        args: &[String],
+    ) -> CommandResponse {
+        let module = match wat::parse_str(script)
+            .map_err(|e| e.to_string())
+            .and_then(|wasm| Module::new(&self.engine, &wasm[..]).map_err(|e| e.to_string()))
+        {
+            Ok(module) => module,
+            Err(e) => {
+                return CommandResponse::Error(CommandError::err(format!(
+                    "Error compiling script: {}",
+                    e
+                )));
+            }
+        };
+
+        let script = Arc::new(Running {
+            started: Instant::now(),
+            killed: AtomicBool::new(false),
+        });
+        {
+            let mut running = self.running.lock().unwrap();
+            if running
+                .iter()
+                .any(|other| other.started.elapsed() >= self.limits.busy_after)
+            {
+                return CommandResponse::Error(CommandError::new(
+                    ErrorClass::Busy,
+                    "A script is running long. You can only call SCRIPT KILL.",
+                ));
+            }
+            running.push(script.clone());
+        }
+        let _guard = RunningGuard {
+            running: &self.running,
+            script: script.clone(),
+        };
+
+        self.run(executor, &module, &script, keys, args)
+            .unwrap_or_else(|e| {
+                let message = match e.as_trap_code() {
+                    Some(TrapCode::OutOfFuel) => format!(
+                        "Script exceeded the limit of {} instructions",
+                        self.limits.fuel
+                    ),
+                    _ => format!("Error running script: {}", e),
+                };
+                CommandResponse::Error(CommandError::err(message))
+            })
+    }
+
+    fn run(
+        &self,
+        executor: &CommandExecutor,
+        module: &Module,
+        script: &Running,
+        keys: &[String],
+        args: &[String],
+    ) -> Result<CommandResponse, wasmi::Error> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.limits.max_memory)
+            .instances(1)
+            .build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(self.limits.fuel)?;
+        let instance = self
+            .linker
+            .instantiate(&mut store, module)?
+            .start(&mut store)?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| wasmi::Error::new("missing export 'memory'"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")?;
+        let run = instance.get_typed_func::<(i32, i32), i64>(&store, "run")?;
+
+        let mut input = (keys.len() as u32).to_le_bytes().to_vec();
+        for arg in keys.iter().chain(args) {
+            input.extend_from_slice(&(arg.len() as u32).to_le_bytes());
+            input.extend_from_slice(arg.as_bytes());
+        }
+        let len =
+            i32::try_from(input.len()).map_err(|_| wasmi::Error::new("arguments too large"))?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as u32 as usize, &input)?;
+
+        let mut call = run.call_resumable(&mut store, (ptr, len))?;
+        let reply = loop {
+            let invocation = match call {
+                TypedResumableCall::Finished(reply) => break reply,
+                TypedResumableCall::Resumable(invocation) => invocation,
+            };
+            if script.killed.load(Ordering::Relaxed) {
+                return Ok(CommandResponse::Error(CommandError::err(
+                    "Script killed by user with SCRIPT KILL...",
+                )));
+            }
+            if script.started.elapsed() >= self.limits.time_limit {
+                return Ok(CommandResponse::Error(CommandError::err(format!(
+                    "Script exceeded the time limit of {} ms",
+                    self.limits.time_limit.as_millis()
+                ))));
+            }
+            let host_call = invocation
+                .host_error()
+                .downcast_ref::<HostCall>()
+                .ok_or_else(|| wasmi::Error::new("unexpected host error"))?;
+            let result = host_call.run(executor, &memory, &mut store)?;
+            call = invocation.resume(&mut store, &[result])?;
+        };
+        let reply = read(&memory, &store, (reply >> 32) as i32, reply as i32)?;
+        Ok(match reply.split_first() {
+            Some((b'+', _)) => CommandResponse::Ok,
+            Some((b'_', _)) => CommandResponse::Null,
+            Some((b'$', value)) => CommandResponse::Bytes(value.to_vec()),
+            Some((b':', n)) => match std::str::from_utf8(n).ok().and_then(|n| n.parse().ok()) {
+                Some(n) => CommandResponse::Integer(n),
+                None => return Err(wasmi::Error::new("invalid integer reply")),
+            },
+            Some((b'-', message)) => {
+                CommandResponse::Error(CommandError::err(String::from_utf8_lossy(message)))
+            }
+            _ => return Err(wasmi::Error::new("invalid reply")),
+        })
+    }
+}
+
+/// Unregisters a script when it returns or panics
+struct RunningGuard<'a> {
+    running: &'a Mutex<Vec<Arc<Running>>>,
+    script: Arc<Running>,
+}
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        let mut running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        running.retain(|script| !Arc::ptr_eq(script, &self.script));
+    }
+}
+
+/// A host API call. It suspends the script, so the executor runs it outside the
+/// sandbox and resumes the script with its result.
+#[derive(Debug)]
+enum HostCall {
+    Get {
+        key: String,
+        out: u32,
+        out_cap: u32,
+    },
+    Set {
+        key: String,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    },
+    Del {
+        key: String,
+    },
+}
+
+impl fmt::Display for HostCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "host call {:?}", self)
+    }
+}
+
+impl HostError for HostCall {}
+
+impl HostCall {
+    fn run(
+        &self,
+        executor: &CommandExecutor,
+        memory: &Memory,
+        store: &mut Store<StoreLimits>,
+    ) -> Result<Val, wasmi::Error> {
+        Ok(match self {
+            HostCall::Get { key, out, out_cap } => {
+                let command = Command::Get { key: key.clone() };
+                let CommandResponse::Bytes(value) = executor.execute(command) else {
+                    return Ok(Val::I64(-1));
+                };
+                if value.len() <= *out_cap as usize {
+                    memory.write(&mut *store, *out as usize, &value)?;
+                }
+                Val::I64(value.len() as i64)
+            }
+            HostCall::Set { key, value, ttl } => {
+                let command = Command::Set {
+                    key: key.clone(),
+                    value: value.clone(),
+                    options: SetOptions {
+                        ttl: *ttl,
+                        ..Default::default()
+                    },
+                    concern: None,
+                };
+                match executor.execute(command) {
+                    CommandResponse::Error(_) => Val::I32(-1),
+                    _ => Val::I32(0),
+                }
+            }
+            HostCall::Del { key } => {
+                let command = Command::Del {
+                    keys: vec![key.clone()],
+                    cascade: false,
+                };
+                let removed = matches!(executor.execute(command), CommandResponse::Integer(1));
+                Val::I32(removed as i32)
+            }
+        })
+    }
+}
+
+/// Copies `len` bytes at `ptr` out of the script's memory
+fn read(
+    memory: &Memory,
+    store: impl wasmi::AsContext,
+    ptr: i32,
+    len: i32,
+) -> Result<Vec<u8>, wasmi::Error> {
+    let mut buf = vec![0; len as u32 as usize];
+    memory.read(store, ptr as u32 as usize, &mut buf)?;
+    Ok(buf)
+}
+
+/// The bytes at `ptr` in the caller's memory
+fn read_bytes(
+    caller: &Caller<'_, StoreLimits>,
+    ptr: i32,
+    len: i32,
+) -> Result<Vec<u8>, wasmi::Error> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmi::Error::new("missing export 'memory'"))?;
+    read(&memory, caller, ptr, len)
+}
+
+/// The UTF-8 string at `ptr` in the caller's memory
+fn read_str(caller: &Caller<'_, StoreLimits>, ptr: i32, len: i32) -> Result<String, wasmi::Error> {
+    String::from_utf8(read_bytes(caller, ptr, len)?)
+        .map_err(|_| wasmi::Error::new("invalid UTF-8 from script"))
+}
+
+/// The host API. Each function only reads its arguments and suspends the script
+/// with the call; see `HostCall`.
+fn host_linker(engine: &Engine) -> Linker<StoreLimits> {
+    let mut linker = Linker::new(engine);
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "get",
+            |caller: Caller<'_, StoreLimits>, key: i32, key_len: i32, out: i32, out_cap: i32| {
+                let key = read_str(&caller, key, key_len)?;
+                let (out, out_cap) = (out as u32, out_cap as u32);
+                Err::<i64, _>(wasmi::Error::host(HostCall::Get { key, out, out_cap }))
+            },
+        )
+        .and_then(|linker| {
+            linker.func_wrap(
+                HOST_MODULE,
+                "set",
+                |caller: Caller<'_, StoreLimits>,
+                 key: i32,
+                 key_len: i32,
+                 value: i32,
+                 value_len: i32,
+                 ttl_ms: i64| {
+                    let key = read_str(&caller, key, key_len)?;
+                    let value = read_bytes(&caller, value, value_len)?;
+                    let ttl = (ttl_ms > 0).then(|| Duration::from_millis(ttl_ms as u64));
+                    Err::<i32, _>(wasmi::Error::host(HostCall::Set { key, value, ttl }))
+                },
+            )
+        })
+        .and_then(|linker| {
+            linker.func_wrap(
+                HOST_MODULE,
+                "del",
+                |caller: Caller<'_, StoreLimits>, key: i32, key_len: i32| {
+                    let key = read_str(&caller, key, key_len)?;
+                    Err::<i32, _>(wasmi::Error::host(HostCall::Del { key }))
+                },
+            )
+        })
+        .expect("host functions have distinct names");
+    linker
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Cache, Config, Value};
+
+    /// COPY: copies KEYS[1] to KEYS[2] through the host API, replying :1, or nil
+    /// when KEYS[1] is missing
+    const COPY: &str = r#"
+        (module
+          (import "dashdot" "get" (func $get (param i32 i32 i32 i32) (result i64)))
+          (import "dashdot" "set" (func $set (param i32 i32 i32 i32 i64) (result i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) ":1_")
+          (global $next (mut i32) (i32.const 4096))
+          (func (export "alloc") (param $len i32) (result i32)
+            (global.get $next)
+            (global.set $next (i32.add (global.get $next) (local.get $len))))
+          (func $reply (param $ptr i32) (param $len i32) (result i64)
+            (i64.or
+              (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+              (i64.extend_i32_u (local.get $len))))
+          (func (export "run") (param $ptr i32) (param $len i32) (result i64)
+            (local $from i32) (local $to i32) (local $n i64)
+            (local.set $from (i32.add (local.get $ptr) (i32.const 4)))
+            (local.set $to
+              (i32.add (local.get $from) (i32.add (i32.const 4) (i32.load (local.get $from)))))
+            (local.set $n
+              (call $get
+                (i32.add (local.get $from) (i32.const 4)) (i32.load (local.get $from))
+                (i32.const 1024) (i32.const 1024)))
+            (if (i64.lt_s (local.get $n) (i64.const 0))
+              (then (return (call $reply (i32.const 2) (i32.const 1)))))
+            (drop
+              (call $set
+                (i32.add (local.get $to) (i32.const 4)) (i32.load (local.get $to))
+                (i32.const 1024) (i32.wrap_i64 (local.get $n)) (i64.const 0)))
+            (call $reply (i32.const 0) (i32.const 2))))
+    "#;
+
+    /// Script with the required exports whose `run` body is `body`
+    fn script(memory_pages: u32, body: &str) -> String {
+        format!(
+            r#"(module
+                 (import "dashdot" "get" (func $get (param i32 i32 i32 i32) (result i64)))
+                 (memory (export "memory") {})
+                 (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                 (func (export "run") (param i32 i32) (result i64) {}))"#,
+            memory_pages, body
+        )
+    }
+
+    /// Calls `get` until stopped
+    const POLL: &str = "(loop $poll
+        (drop (call $get (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0)))
+        (br $poll))
+        (unreachable)";
+
+    fn executor(limits: ScriptLimits) -> CommandExecutor {
+        CommandExecutor::new(Arc::new(Cache::new(Config::default()))).with_script_limits(limits)
+    }
+
+    fn eval(executor: &CommandExecutor, script: &str, keys: &[&str]) -> CommandResponse {
+        executor.execute(Command::Eval {
+            script: script.to_string(),
+            keys: keys.iter().map(|key| key.to_string()).collect(),
+            args: Vec::new(),
+        })
+    }
+
+    fn error(response: CommandResponse) -> CommandError {
+        match response {
+            CommandResponse::Error(e) => e,
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_eval() {
+        let executor = executor(ScriptLimits::default());
+        executor
+            .cache
+            .set("a".into(), Value::String("v".into()), SetOptions::default())
+            .unwrap();
+
+        assert!(matches!(
+            eval(&executor, COPY, &["a", "b"]),
+            CommandResponse::Integer(1)
+        ));
+        assert_eq!(executor.cache.get("b").unwrap().to_string(), "v");
+        assert!(matches!(
+            eval(&executor, COPY, &["missing", "c"]),
+            CommandResponse::Null
+        ));
+        assert!(!executor.cache.exists("c"));
+
+        let e = error(eval(&executor, "(module", &[]));
+        assert!(e.message.starts_with("Error compiling script"));
+        // Scripts only get the host API
+        let wasi = r#"(module
+            (import "wasi_snapshot_preview1" "fd_write" (func (param i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "run") (param i32 i32) (result i64) (i64.const 0)))"#;
+        assert!(
+            error(eval(&executor, wasi, &[]))
+                .message
+                .starts_with("Error running script")
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        let executor = executor(ScriptLimits {
+            fuel: 100_000,
+            ..ScriptLimits::default()
+        });
+        let spin = script(1, "(loop $spin (br $spin)) (unreachable)");
+        let e = error(eval(&executor, &spin, &[]));
+        assert_eq!(
+            e.message,
+            "Script exceeded the limit of 100000 instructions"
+        );
+        // 512 pages is 32 MiB, over the default 16 MiB
+        let big = script(512, "(i64.const 0)");
+        assert!(
+            error(eval(&executor, &big, &[]))
+                .message
+                .starts_with("Error running script")
+        );
+
+        let executor = self::executor(ScriptLimits {
+            time_limit: Duration::ZERO,
+            ..ScriptLimits::default()
+        });
+        let e = error(eval(&executor, &script(1, POLL), &[]));
+        assert_eq!(e.message, "Script exceeded the time limit of 0 ms");
+        assert_eq!(executor.scripts().running(), 0);
+    }
+
+    #[test]
+    fn test_script_kill() {
+        let executor = Arc::new(executor(ScriptLimits {
+            fuel: u64::MAX,
+            busy_after: Duration::ZERO,
+            ..ScriptLimits::default()
+        }));
+        let e = error(executor.execute(Command::ScriptKill {}));
+        assert_eq!(e.class, ErrorClass::NotBusy);
+
+        let polling = std::thread::spawn({
+            let executor = executor.clone();
+            move || eval(&executor, &script(1, POLL), &[])
+        });
+        while executor.scripts().running() == 0 {
+            std::thread::yield_now();
+        }
+        // Other scripts get BUSY while one runs past `busy_after`
+        let e = error(eval(&executor, COPY, &["a", "b"]));
+        assert_eq!(e.class, ErrorClass::Busy);
+
+        assert!(matches!(
+            executor.execute(Command::ScriptKill {}),
+            CommandResponse::Ok
+        ));
+        let e = error(polling.join().unwrap());
+        assert_eq!(e.message, "Script killed by user with SCRIPT KILL...");
+        assert_eq!(executor.scripts().running(), 0);
+    }
+}
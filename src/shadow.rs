@@ -0,0 +1,211 @@
+use crate::executor::{Command, CommandResponse};
+use crate::metrics::Labels;
+use crate::middleware::Middleware;
+use crate::replay::Conn;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tracing::{info, warn};
+
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+
+/// Commands about this instance's own runtime state, or that block, which the
+/// shadow doesn't get
+const NOT_SHADOWED: [&str; 4] = ["BGSAVE", "REPLICAOF", "MIRROR", "XREAD"];
+
+#[derive(Debug, Clone)]
+pub struct ShadowConfig {
+    pub target: String, // "host:port" of the secondary's RESP listener
+    pub percent: f64,   // Share of commands duplicated, 0 to 100
+    pub queue: usize,   // Commands waiting to be sent; more are dropped
+}
+
+impl ShadowConfig {
+    pub fn new(target: String) -> Self {
+        Self {
+            target,
+            percent: 100.0,
+            queue: 10_000,
+        }
+    }
+}
+
+/// Duplicates a sample of the commands executed here to a secondary dashdotcache,
+/// to try a new version or configuration under real traffic. Registered as
+/// middleware, so HTTP and RESP commands are both seen. Commands are sent in order
+/// from a background task and the secondary's replies are ignored; nothing waits
+/// on it, so commands are dropped while it's slow or unreachable.
+pub struct Shadowing {
+    config: ShadowConfig,
+    sender: Sender<Vec<Vec<u8>>>,
+    receiver: Mutex<Option<Receiver<Vec<Vec<u8>>>>>,
+    connected: AtomicBool,
+    sent: AtomicU64,
+    rejected: AtomicU64, // Error replies from the secondary
+    dropped: AtomicU64,
+}
+
+impl Shadowing {
+    pub fn new(config: ShadowConfig) -> Self {
+        let (sender, receiver) = mpsc::channel(config.queue.max(1));
+        Self {
+            config,
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+            connected: AtomicBool::new(false),
+            sent: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Sends queued commands to the target, reconnecting with backoff. Only the
+    /// first call does anything.
+    pub async fn run(self: Arc<Self>) {
+        let Some(mut receiver) = self.receiver.lock().unwrap().take() else {
+            return;
+        };
+        let mut backoff = RECONNECT_MIN;
+        loop {
+            let mut conn = match Conn::connect(&self.config.target).await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    warn!("Shadow target {} unreachable: {}", self.config.target, e);
+                    // Whatever queued up meanwhile is stale
+                    while receiver.try_recv().is_ok() {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(RECONNECT_MAX);
+                    continue;
+                }
+            };
+            info!("Shadowing commands to {}", self.config.target);
+            self.connected.store(true, Ordering::Relaxed);
+            backoff = RECONNECT_MIN;
+            while let Some(args) = receiver.recv().await {
+                match conn.call(&args).await {
+                    Ok(Ok(())) => self.sent.fetch_add(1, Ordering::Relaxed),
+                    Ok(Err(_)) => self.rejected.fetch_add(1, Ordering::Relaxed),
+                    Err(e) => {
+                        warn!("Lost shadow target {}: {}", self.config.target, e);
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                        break;
+                    }
+                };
+            }
+            self.connected.store(false, Ordering::Relaxed);
+        }
+    }
+
+    fn sampled(&self) -> bool {
+        self.config.percent >= 100.0 || rand::random::<f64>() * 100.0 < self.config.percent
+    }
+}
+
+impl Middleware for Shadowing {
+    fn before(&self, command: Command) -> Result<Command, CommandResponse> {
+        if !NOT_SHADOWED.contains(&command.name())
+            && self.sampled()
+            && self.sender.try_send(command.to_args()).is_err()
+        {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(command)
+    }
+
+    fn render_metrics(&self, out: &mut String, labels: &Labels) {
+        let series = labels.series();
+        let counters = [
+            (
+                "cache_shadow_sent_total",
+                "Commands duplicated to the shadow target",
+                self.sent.load(Ordering::Relaxed),
+            ),
+            (
+                "cache_shadow_rejected_total",
+                "Shadowed commands the shadow target replied to with an error",
+                self.rejected.load(Ordering::Relaxed),
+            ),
+            (
+                "cache_shadow_dropped_total",
+                "Sampled commands never sent to the shadow target",
+                self.dropped.load(Ordering::Relaxed),
+            ),
+        ];
+        for (name, help, value) in counters {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} counter", name).unwrap();
+            writeln!(out, "{}{} {}", name, series, value).unwrap();
+        }
+        writeln!(
+            out,
+            "# HELP cache_shadow_connected Whether the shadow target is connected"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE cache_shadow_connected gauge").unwrap();
+        writeln!(
+            out,
+            "cache_shadow_connected{} {}",
+            series,
+            self.connected.load(Ordering::Relaxed) as u8
+        )
+        .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Cache, Config, SetOptions};
+    use crate::executor::CommandExecutor;
+    use crate::resp_api::RespServer;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_shadow_duplicates_commands() {
+        let target = Arc::new(Cache::new(Config::default()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = RespServer::new(Arc::new(CommandExecutor::new(target.clone())));
+        tokio::spawn(async move { server.serve(listener).await });
+
+        let shadowing = Arc::new(Shadowing::new(ShadowConfig::new(addr)));
+        tokio::spawn(shadowing.clone().run());
+        let source = Arc::new(Cache::new(Config::default()));
+        let executor = CommandExecutor::new(source.clone()).with_middleware(shadowing.clone());
+        executor.execute(Command::Set {
+            key: "a".to_string(),
+            value: "1".into(),
+            options: SetOptions::default(),
+            concern: None,
+        });
+        executor.execute(Command::IncrBy {
+            key: "a".to_string(),
+            delta: 2,
+            limit: None,
+        });
+        executor.execute(Command::Get {
+            key: "a".to_string(),
+        });
+
+        for _ in 0..100 {
+            if shadowing.sent.load(Ordering::Relaxed) == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(shadowing.sent.load(Ordering::Relaxed), 3);
+        assert_eq!(target.get("a"), source.get("a"));
+
+        // Nothing is sampled at 0%
+        let none = Shadowing::new(ShadowConfig {
+            percent: 0.0,
+            ..ShadowConfig::new("127.0.0.1:1".to_string())
+        });
+        assert!(!none.sampled());
+    }
+}
@@ -0,0 +1,514 @@
+use dashmap::DashMap;
+use dashmap::mapref::entry::{self, Entry};
+use dashmap::mapref::multiple::{RefMulti, RefMutMulti};
+use dashmap::mapref::one::{Ref, RefMut};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Most generations a map goes through, so it can grow this many times less one
+pub const MAX_GENERATIONS: usize = 4;
+
+/// A string-keyed DashMap whose shard count can grow while it's in use.
+///
+/// A DashMap fixes its shards when it's built, so growing starts a larger generation
+/// that takes every write from then on. Keys move to it when they're written, and in
+/// batches from `migrate`; until an older generation is drained, lookups go through
+/// it first. Keys only ever move from older generations to newer ones, which is what
+/// lets lookups walking oldest-first find a key that's moving under them.
+///
+/// Shards are numbered across generations, oldest first. Drained generations keep
+/// their (empty) shards, so a shard's number never changes and keys only move to
+/// higher-numbered shards: a walk over shard numbers sees every key at least once.
+///
+/// The map counts its keys as they come and go, so `len` doesn't lock every shard.
+pub struct ShardedMap<V> {
+    generations: Box<[OnceLock<DashMap<String, V>>; MAX_GENERATIONS]>,
+    len: AtomicUsize,
+    current: AtomicUsize,      // Newest generation, which takes the writes
+    oldest: AtomicUsize,       // Oldest generation that may still hold keys
+    drain_cursor: AtomicUsize, // Next shard of `oldest` for `migrate` to empty
+}
+
+impl<V> ShardedMap<V> {
+    /// `shard_amount` is rounded up to a power of two; DashMap's default without one
+    pub fn new(shard_amount: Option<usize>) -> Self {
+        let generations: Box<[OnceLock<_>; MAX_GENERATIONS]> =
+            Box::new(std::array::from_fn(|_| OnceLock::new()));
+        let first = match shard_amount {
+            Some(amount) => DashMap::with_shard_amount(amount.max(2).next_power_of_two()),
+            None => DashMap::new(),
+        };
+        let _ = generations[0].set(first);
+        Self {
+            generations,
+            len: AtomicUsize::new(0),
+            current: AtomicUsize::new(0),
+            oldest: AtomicUsize::new(0),
+            drain_cursor: AtomicUsize::new(0),
+        }
+    }
+
+    fn generation(&self, index: usize) -> &DashMap<String, V> {
+        self.generations[index]
+            .get()
+            .expect("generation is initialized")
+    }
+
+    /// Generations that may hold keys, oldest first. Reads `current` as it goes, so a
+    /// generation started partway through a walk is included.
+    fn live(&self) -> impl Iterator<Item = &DashMap<String, V>> {
+        (self.oldest.load(Ordering::SeqCst)..).map_while(|index| {
+            (index <= self.current.load(Ordering::SeqCst)).then(|| self.generation(index))
+        })
+    }
+
+    /// Starts a generation of `shard_amount` shards (rounded up to a power of two).
+    /// Refused while an older generation is still draining, when it wouldn't add
+    /// shards, or past `MAX_GENERATIONS`.
+    pub fn grow(&self, shard_amount: usize) -> bool {
+        let current = self.current.load(Ordering::SeqCst);
+        let amount = shard_amount.max(2).next_power_of_two();
+        if self.oldest.load(Ordering::SeqCst) != current
+            || amount <= self.generation(current).shards().len()
+            || current + 1 >= MAX_GENERATIONS
+            || self.generations[current + 1]
+                .set(DashMap::with_shard_amount(amount))
+                .is_err()
+        {
+            return false;
+        }
+        self.drain_cursor.store(0, Ordering::SeqCst);
+        self.current.store(current + 1, Ordering::SeqCst);
+        true
+    }
+
+    /// Whether keys are still moving out of an older generation
+    pub fn is_migrating(&self) -> bool {
+        self.oldest.load(Ordering::SeqCst) != self.current.load(Ordering::SeqCst)
+    }
+
+    /// Moves up to `max_keys` keys out of the oldest generation, retiring it once it's
+    /// empty. Shards busy elsewhere are skipped and come around again. Returns how many
+    /// keys moved.
+    pub fn migrate(&self, max_keys: usize) -> usize {
+        let current = self.current.load(Ordering::SeqCst);
+        let oldest = self.oldest.load(Ordering::SeqCst);
+        if oldest == current {
+            return 0;
+        }
+        let (from, to) = (self.generation(oldest), self.generation(current));
+        let mut moved = 0;
+        while moved < max_keys {
+            let shard = self.drain_cursor.load(Ordering::SeqCst);
+            if shard >= from.shards().len() {
+                // A write that locked its shard before the generation changed may have
+                // landed behind the cursor; `is_empty` waits it out, then it's swept up
+                if from.is_empty() {
+                    let _ = self.oldest.compare_exchange(
+                        oldest,
+                        oldest + 1,
+                        Ordering::SeqCst,
+                        Ordering::SeqCst,
+                    );
+                }
+                self.drain_cursor.store(0, Ordering::SeqCst);
+                break;
+            }
+            let keys = read_keys(from, shard, max_keys - moved);
+            if keys.is_empty() {
+                let _ = self.drain_cursor.compare_exchange(
+                    shard,
+                    shard + 1,
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                );
+                continue;
+            }
+            let batch = keys.len();
+            let batch_moved = keys.iter().filter(|key| move_key(from, to, key)).count();
+            moved += batch_moved;
+            if batch_moved < batch {
+                break; // Target shards are locked; leave the rest for next time
+            }
+        }
+        moved
+    }
+
+    /// Moves `key` from every older generation into the newest, waiting out writers
+    /// holding the newest generation's shard
+    fn take_ownership(&self, key: &str, current: usize) {
+        let to = self.generation(current);
+        for index in self.oldest.load(Ordering::SeqCst)..current {
+            let from = self.generation(index);
+            while from.contains_key(key) && !move_key(from, to, key) {
+                std::thread::yield_now();
+            }
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Ref<'_, String, V>> {
+        self.live().find_map(|generation| generation.get(key))
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.live().any(|generation| generation.contains_key(key))
+    }
+
+    pub fn get_mut(&self, key: &str) -> Option<RefMut<'_, String, V>> {
+        loop {
+            let current = self.current.load(Ordering::SeqCst);
+            self.take_ownership(key, current);
+            let entry = self.generation(current).get_mut(key);
+            // Rechecked once the shard is locked: a write to a generation that stopped
+            // being the newest in between goes around again
+            if self.current.load(Ordering::SeqCst) == current {
+                return entry;
+            }
+        }
+    }
+
+    pub fn entry(&self, key: String) -> MapEntry<'_, V> {
+        if self.is_migrating() {
+            self.migrate(MIGRATE_PER_WRITE);
+        }
+        let mut key = key;
+        loop {
+            let current = self.current.load(Ordering::SeqCst);
+            self.take_ownership(&key, current);
+            let entry = self.generation(current).entry(key);
+            if self.current.load(Ordering::SeqCst) == current {
+                let len = &self.len;
+                return match entry {
+                    Entry::Occupied(inner) => MapEntry::Occupied(OccupiedEntry { inner, len }),
+                    Entry::Vacant(inner) => MapEntry::Vacant(VacantEntry { inner, len }),
+                };
+            }
+            key = entry.into_key();
+        }
+    }
+
+    pub fn insert(&self, key: String, value: V) -> Option<V> {
+        match self.entry(key) {
+            MapEntry::Occupied(mut occupied) => Some(occupied.insert(value)),
+            MapEntry::Vacant(vacant) => {
+                vacant.insert(value);
+                None
+            }
+        }
+    }
+
+    pub fn remove(&self, key: &str) -> Option<(String, V)> {
+        self.remove_if(key, |_, _| true)
+    }
+
+    /// Removes the key if `f` passes with its shard locked. Walks oldest first like
+    /// lookups, stopping at the generation holding the key.
+    pub fn remove_if(&self, key: &str, f: impl FnOnce(&String, &V) -> bool) -> Option<(String, V)> {
+        let mut f = Some(f);
+        for generation in self.live() {
+            if !generation.contains_key(key) {
+                continue;
+            }
+            let mut held = false;
+            let removed = generation.remove_if(key, |key, value| {
+                held = true;
+                f.take().is_some_and(|f| f(key, value))
+            });
+            if held {
+                if removed.is_some() {
+                    self.len.fetch_sub(1, Ordering::Relaxed);
+                }
+                return removed;
+            }
+        }
+        None
+    }
+
+    /// Every entry, oldest generation first. A key moving generations during the walk
+    /// may come up twice.
+    pub fn iter(&self) -> impl Iterator<Item = RefMulti<'_, String, V>> {
+        self.live().flat_map(DashMap::iter)
+    }
+
+    pub fn iter_mut(&self) -> impl Iterator<Item = RefMutMulti<'_, String, V>> {
+        self.live().flat_map(DashMap::iter_mut)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        for generation in self.live() {
+            generation.retain(|_, _| {
+                self.len.fetch_sub(1, Ordering::Relaxed);
+                false
+            });
+        }
+    }
+
+    /// Shards across every generation started so far, drained ones included
+    pub fn shard_count(&self) -> usize {
+        (0..=self.current.load(Ordering::SeqCst))
+            .map(|index| self.generation(index).shards().len())
+            .sum()
+    }
+
+    /// Shards in generations that have been drained, numbered before all the others
+    pub fn retired_shards(&self) -> usize {
+        (0..self.oldest.load(Ordering::SeqCst))
+            .map(|index| self.generation(index).shards().len())
+            .sum()
+    }
+
+    /// The generation and its own shard index for shard number `shard`
+    fn locate(&self, shard: usize) -> Option<(&DashMap<String, V>, usize)> {
+        let mut offset = 0;
+        for index in 0..=self.current.load(Ordering::SeqCst) {
+            let generation = self.generation(index);
+            let shards = generation.shards().len();
+            if shard < offset + shards {
+                return Some((generation, shard - offset));
+            }
+            offset += shards;
+        }
+        None
+    }
+
+    /// Number of the shard that takes writes to `key`
+    pub fn shard_of(&self, key: &str) -> usize {
+        let current = self.current.load(Ordering::SeqCst);
+        let offset: usize = (0..current)
+            .map(|index| self.generation(index).shards().len())
+            .sum();
+        offset + self.generation(current).determine_map(key)
+    }
+
+    /// Runs `f` over a shard's entries with its read lock held; None past the last shard
+    pub fn read_shard<R>(
+        &self,
+        shard: usize,
+        f: impl FnOnce(&mut dyn ExactSizeIterator<Item = (&String, &V)>) -> R,
+    ) -> Option<R> {
+        let (generation, index) = self.locate(shard)?;
+        let guard = generation.shards()[index].read();
+        // SAFETY: buckets are only read, and only while the shard's read lock is held
+        let mut entries = unsafe { guard.iter() }.map(|bucket| {
+            let (key, value) = unsafe { bucket.as_ref() };
+            (key, value.get())
+        });
+        Some(f(&mut entries))
+    }
+
+    /// Takes and drops a shard's lock without waiting, or None if it's held elsewhere
+    pub fn try_lock_shard(&self, shard: usize, write: bool) -> Option<()> {
+        let (generation, index) = self.locate(shard)?;
+        let lock = &generation.shards()[index];
+        match write {
+            true => lock.try_write().map(drop),
+            false => lock.try_read().map(drop),
+        }
+    }
+
+    /// Waits for a shard's lock, then drops it
+    pub fn lock_shard(&self, shard: usize, write: bool) {
+        if let Some((generation, index)) = self.locate(shard) {
+            let lock = &generation.shards()[index];
+            match write {
+                true => drop(lock.write()),
+                false => drop(lock.read()),
+            }
+        }
+    }
+}
+
+/// An entry of a `ShardedMap`, holding its shard's write lock
+pub enum MapEntry<'a, V> {
+    Occupied(OccupiedEntry<'a, V>),
+    Vacant(VacantEntry<'a, V>),
+}
+
+impl<'a, V> MapEntry<'a, V> {
+    pub fn key(&self) -> &String {
+        match self {
+            MapEntry::Occupied(occupied) => occupied.key(),
+            MapEntry::Vacant(vacant) => vacant.key(),
+        }
+    }
+
+    pub fn or_insert(self, value: V) -> RefMut<'a, String, V> {
+        match self {
+            MapEntry::Occupied(occupied) => occupied.into_ref(),
+            MapEntry::Vacant(vacant) => vacant.insert(value),
+        }
+    }
+}
+
+pub struct OccupiedEntry<'a, V> {
+    inner: entry::OccupiedEntry<'a, String, V>,
+    len: &'a AtomicUsize,
+}
+
+impl<'a, V> OccupiedEntry<'a, V> {
+    pub fn key(&self) -> &String {
+        self.inner.key()
+    }
+
+    pub fn get(&self) -> &V {
+        self.inner.get()
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        self.inner.get_mut()
+    }
+
+    pub fn into_ref(self) -> RefMut<'a, String, V> {
+        self.inner.into_ref()
+    }
+
+    pub fn insert(&mut self, value: V) -> V {
+        self.inner.insert(value)
+    }
+
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    pub fn remove_entry(self) -> (String, V) {
+        self.len.fetch_sub(1, Ordering::Relaxed);
+        self.inner.remove_entry()
+    }
+}
+
+pub struct VacantEntry<'a, V> {
+    inner: entry::VacantEntry<'a, String, V>,
+    len: &'a AtomicUsize,
+}
+
+impl<'a, V> VacantEntry<'a, V> {
+    pub fn key(&self) -> &String {
+        self.inner.key()
+    }
+
+    pub fn into_key(self) -> String {
+        self.inner.into_key()
+    }
+
+    pub fn insert(self, value: V) -> RefMut<'a, String, V> {
+        self.len.fetch_add(1, Ordering::Relaxed);
+        self.inner.insert(value)
+    }
+}
+
+/// Keys moved out of an older generation by each write while migrating
+const MIGRATE_PER_WRITE: usize = 8;
+
+/// Up to `limit` keys from one shard of `map`
+fn read_keys<V>(map: &DashMap<String, V>, shard: usize, limit: usize) -> Vec<String> {
+    let guard = map.shards()[shard].read();
+    // SAFETY: buckets are only read, and only while the shard's read lock is held
+    unsafe { guard.iter() }
+        .take(limit)
+        .map(|bucket| unsafe { bucket.as_ref() }.0.clone())
+        .collect()
+}
+
+/// Moves `key` from `from` to `to`, false if its shard in `to` is locked elsewhere.
+/// The key stays locked in `from` until `to`'s shard is held, and `to`'s shard stays
+/// locked until the key is in it, so a lookup going from `from` to `to` finds it in one.
+fn move_key<V>(from: &DashMap<String, V>, to: &DashMap<String, V>, key: &str) -> bool {
+    let Entry::Occupied(occupied) = from.entry(key.to_string()) else {
+        return true;
+    };
+    match to.try_entry(key.to_string()) {
+        Some(Entry::Vacant(vacant)) => {
+            let (_, value) = occupied.remove_entry();
+            vacant.insert(value);
+            true
+        }
+        // Written there already, so the older value is out of date
+        Some(Entry::Occupied(_)) => {
+            occupied.remove();
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_grow_and_migrate() {
+        let map = ShardedMap::new(Some(2));
+        for i in 0..100 {
+            map.insert(format!("k{}", i), i);
+        }
+        assert!(!map.grow(2));
+        assert!(map.grow(8));
+        assert!(!map.grow(32)); // Still draining
+        assert_eq!(map.shard_count(), 10);
+        assert!((2..10).contains(&map.shard_of("k1")));
+
+        // Old keys are still found, and writes move them
+        assert_eq!(map.get("k1").as_deref(), Some(&1));
+        *map.get_mut("k1").unwrap() += 100;
+        assert!(map.generation(1).contains_key("k1"));
+        assert_eq!(map.get("k1").as_deref(), Some(&101));
+        assert_eq!(map.remove("k2"), Some(("k2".to_string(), 2)));
+        assert_eq!(map.remove_if("k3", |_, _| false), None);
+        assert_eq!(map.len(), 99);
+
+        while map.is_migrating() {
+            map.migrate(10);
+        }
+        assert!(map.generation(0).is_empty());
+        assert_eq!(map.retired_shards(), 2);
+        assert_eq!(map.len(), 99);
+        assert_eq!(map.iter().count(), 99);
+        let per_shard: usize = (0..map.shard_count())
+            .map(|shard| map.read_shard(shard, |entries| entries.len()).unwrap())
+            .sum();
+        assert_eq!(per_shard, 99);
+        assert!(map.read_shard(10, |entries| entries.len()).is_none());
+        assert!(map.grow(32));
+    }
+
+    #[test]
+    fn test_concurrent_writes_while_migrating() {
+        let map = Arc::new(ShardedMap::new(Some(2)));
+        for i in 0..1000 {
+            map.insert(format!("k{}", i), 0u64);
+        }
+        map.grow(16);
+        let writers: Vec<_> = (0..4)
+            .map(|_| {
+                let map = map.clone();
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        let key = format!("k{}", i);
+                        *map.entry(key.clone()).or_insert(0) += 1;
+                        assert!(map.get(&key).is_some());
+                    }
+                })
+            })
+            .collect();
+        while map.is_migrating() {
+            map.migrate(16);
+        }
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        while map.is_migrating() {
+            map.migrate(16);
+        }
+        assert_eq!(map.len(), 1000);
+        assert!(map.iter().all(|entry| *entry.value() == 4));
+    }
+}
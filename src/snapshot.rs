@@ -0,0 +1,924 @@
+use crate::cache::{Cache, Entry, Ttl, Value};
+use crate::eviction::Priority;
+use crate::hyperloglog::HyperLogLog;
+use crate::stream::{Stream, StreamEntry, StreamId};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+/// Where snapshots are saved and loaded from unless configured otherwise
+pub const DEFAULT_SNAPSHOT_PATH: &str = "dump.snap";
+/// Finished jobs remembered for polling; older ones are forgotten
+const JOB_HISTORY: usize = 16;
+
+pub const MAGIC: &[u8; 4] = b"DDSN";
+/// Version 2 added parent link deadlines, version 3 several parents per key and
+/// version 4 tags; older files still load
+const VERSION: u8 = 4;
+
+// Record flags
+const HAS_TTL: u8 = 1;
+const SLIDING: u8 = 1 << 1;
+const PINNED: u8 = 1 << 2;
+const HAS_PARENT: u8 = 1 << 3;
+const HAS_FIELD_EXPIRY: u8 = 1 << 4;
+const STALE: u8 = 1 << 5;
+const PRIORITY_LOW: u8 = 1 << 6;
+const PRIORITY_HIGH: u8 = 1 << 7;
+
+// Value tags
+const STRING: u8 = 0;
+const INTEGER: u8 = 1;
+const FLOAT: u8 = 2;
+const BYTES: u8 = 3;
+const HASH: u8 = 4;
+const LIST: u8 = 5;
+const SET: u8 = 6;
+const STREAM: u8 = 7;
+const HYPERLOGLOG: u8 = 8;
+const JSON: u8 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotInfo {
+    pub keys: usize,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadReport {
+    pub loaded: usize,
+    pub expired: usize,  // Expired while the snapshot sat on disk
+    pub rejected: usize, // Refused by the cache, e.g. over max_memory
+    pub orphaned: usize, // Parent missing, or the link past max_dependency_depth; loaded without it
+}
+
+/// Writes every live key to `path`. The file is written beside it and renamed into
+/// place, so a crash mid-save leaves the previous snapshot intact.
+pub fn save(cache: &Cache, path: &Path) -> io::Result<SnapshotInfo> {
+    let (buf, keys) = encode(cache);
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, &buf)?;
+    fs::rename(&tmp, path)?;
+    debug!(
+        "Saved {} keys ({} bytes) to {}",
+        keys,
+        buf.len(),
+        path.display()
+    );
+    Ok(SnapshotInfo {
+        keys,
+        bytes: buf.len(),
+    })
+}
+
+/// A snapshot of every live key, as `save` would write it
+pub fn dump(cache: &Cache) -> Vec<u8> {
+    encode(cache).0
+}
+
+/// Snapshot bytes and the number of keys in them
+fn encode(cache: &Cache) -> (Vec<u8>, usize) {
+    let clock = Clock::now();
+    let mut records = Vec::new();
+    let mut keys = 0;
+    cache.for_each_entry(|key, entry| {
+        let parents: Vec<_> = entry
+            .parents
+            .iter()
+            .filter(|link| link.is_live())
+            .filter_map(|link| Some((cache.key_of(link.id)?, link.expires_at)))
+            .collect();
+        write_entry(&mut records, key, entry, &parents, &clock);
+        keys += 1;
+    });
+
+    let mut buf = Vec::with_capacity(records.len() + 32);
+    buf.extend_from_slice(MAGIC);
+    buf.push(VERSION);
+    write_varint(&mut buf, clock.unix_ms(clock.now));
+    write_varint(&mut buf, keys as u64);
+    buf.extend_from_slice(&records);
+    (buf, keys)
+}
+
+/// Loads a snapshot written by `save` into `cache`, on top of whatever it holds.
+/// TTLs keep counting down across the restart; keys that expired meanwhile are dropped.
+pub fn load(cache: &Cache, path: &Path) -> io::Result<LoadReport> {
+    restore(cache, &fs::read(path)?)
+}
+
+/// Loads snapshot bytes, from a file or `dump`, into `cache`
+pub fn restore(cache: &Cache, buf: &[u8]) -> io::Result<LoadReport> {
+    let mut report = LoadReport::default();
+    let entries;
+    (entries, report.expired) = decode(buf, false)?;
+
+    let keys: HashSet<&str> = entries.iter().map(|record| record.key.as_str()).collect();
+    let missing_parent: Vec<bool> = entries
+        .iter()
+        .map(|record| {
+            record
+                .parents
+                .iter()
+                .any(|(parent, _)| !keys.contains(parent.as_str()))
+        })
+        .collect();
+    let mut links = Vec::new();
+    for (record, orphan) in entries.into_iter().zip(missing_parent) {
+        if orphan {
+            report.orphaned += 1;
+        }
+        match cache.restore(record.key.clone(), record.entry) {
+            Ok(()) => {
+                report.loaded += 1;
+                if !orphan {
+                    for (parent, expires_at) in record.parents {
+                        links.push((record.key.clone(), parent, expires_at));
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Skipping snapshot key: {}", e);
+                report.rejected += 1;
+            }
+        }
+    }
+
+    // Parents may come after their children, so links wait until every key is in
+    for (key, parent, expires_at) in links {
+        if cache.add_parent(&key, parent, expires_at).is_err() {
+            report.orphaned += 1;
+        }
+    }
+    Ok(report)
+}
+
+/// A decoded entry. Its parents are kept as keys, since entry IDs don't outlive the
+/// cache that assigned them.
+struct Record {
+    key: String,
+    entry: Entry,
+    parents: Vec<(String, Option<Instant>)>,
+}
+
+/// Live entries in snapshot bytes and how many had expired. Expiry is judged as of
+/// now, or with `as_saved` as of when the snapshot was taken.
+fn decode(buf: &[u8], as_saved: bool) -> io::Result<(Vec<Record>, usize)> {
+    let mut reader = Reader { buf, pos: 0 };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(invalid("not a snapshot file"));
+    }
+    let version = reader.u8()?;
+    if version == 0 || version > VERSION {
+        return Err(invalid(&format!(
+            "unsupported snapshot version {}",
+            version
+        )));
+    }
+    let saved_at = reader.varint()?;
+    let count = reader.varint()? as usize;
+
+    let mut clock = Clock::now();
+    if as_saved {
+        clock.wall = UNIX_EPOCH + Duration::from_millis(saved_at);
+    }
+    let mut entries = Vec::with_capacity(count.min(1 << 20));
+    let mut expired = 0;
+    for _ in 0..count {
+        match read_entry(&mut reader, &clock, version)? {
+            Some(record) => entries.push(record),
+            None => expired += 1,
+        }
+    }
+    if reader.pos != buf.len() {
+        return Err(invalid("trailing data after the last record"));
+    }
+    Ok((entries, expired))
+}
+
+/// A key present in either snapshot of a diff, with its memory in each (0 if absent)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeyDelta {
+    pub key: String,
+    #[serde(rename = "type")]
+    pub type_name: &'static str,
+    pub memory_before: usize,
+    pub memory_after: usize,
+}
+
+/// What changed between two snapshots, each as it stood when it was taken
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SnapshotDiff {
+    pub added: Vec<KeyDelta>,
+    pub removed: Vec<KeyDelta>,
+    pub changed: Vec<KeyDelta>, // Value, type, tags or parents differ
+    pub unchanged: usize,
+    pub memory_before: usize,
+    pub memory_after: usize,
+}
+
+impl SnapshotDiff {
+    pub fn memory_delta(&self) -> i64 {
+        self.memory_after as i64 - self.memory_before as i64
+    }
+}
+
+/// Compares two snapshots key by key. Keys are listed in order within each group.
+pub fn diff(before: &[u8], after: &[u8]) -> io::Result<SnapshotDiff> {
+    let memory = |key: &str, entry: &Entry| key.len() + entry.memory_usage();
+    let by_key = |records: Vec<Record>| -> BTreeMap<String, (Entry, Vec<String>)> {
+        records
+            .into_iter()
+            .map(|record| {
+                let parents = record.parents.into_iter().map(|(parent, _)| parent);
+                (record.key, (record.entry, parents.collect()))
+            })
+            .collect()
+    };
+    let before = by_key(decode(before, true)?.0);
+    let mut after = by_key(decode(after, true)?.0);
+
+    let mut diff = SnapshotDiff::default();
+    for (key, (old, old_parents)) in before {
+        let memory_before = memory(&key, &old);
+        diff.memory_before += memory_before;
+        let Some((new, new_parents)) = after.remove(&key) else {
+            diff.removed.push(KeyDelta {
+                type_name: old.value.type_name(),
+                key,
+                memory_before,
+                memory_after: 0,
+            });
+            continue;
+        };
+        let memory_after = memory(&key, &new);
+        diff.memory_after += memory_after;
+        if old.value == new.value && old.tags() == new.tags() && old_parents == new_parents {
+            diff.unchanged += 1;
+            continue;
+        }
+        diff.changed.push(KeyDelta {
+            type_name: new.value.type_name(),
+            key,
+            memory_before,
+            memory_after,
+        });
+    }
+    for (key, (new, _)) in after {
+        let memory_after = memory(&key, &new);
+        diff.memory_after += memory_after;
+        diff.added.push(KeyDelta {
+            type_name: new.value.type_name(),
+            key,
+            memory_before: 0,
+            memory_after,
+        });
+    }
+    Ok(diff)
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Done {
+        keys: usize,
+        bytes: usize,
+        duration_ms: u64,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+/// Background saves to one snapshot file, at most one at a time. Each save gets an
+/// id that can be polled until it finishes.
+pub struct SnapshotJobs {
+    path: PathBuf,
+    next_id: AtomicU64,
+    jobs: Mutex<BTreeMap<u64, JobStatus>>,
+}
+
+impl SnapshotJobs {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            next_id: AtomicU64::new(1),
+            jobs: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Starts saving `cache` on a blocking task and returns the job id, or the id of
+    /// the save already running as the error. Must be called within a Tokio runtime.
+    pub fn start(self: &Arc<Self>, cache: Arc<Cache>) -> Result<u64, u64> {
+        let id = {
+            let mut jobs = self.jobs.lock().unwrap();
+            if let Some((&running, _)) = jobs
+                .iter()
+                .find(|(_, status)| matches!(status, JobStatus::Running))
+            {
+                return Err(running);
+            }
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            jobs.insert(id, JobStatus::Running);
+            while jobs.len() > JOB_HISTORY {
+                jobs.pop_first();
+            }
+            id
+        };
+
+        let jobs = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let start = Instant::now();
+            let status = match save(&cache, &jobs.path) {
+                Ok(saved) => {
+                    info!("Background save {} wrote {} keys", id, saved.keys);
+                    JobStatus::Done {
+                        keys: saved.keys,
+                        bytes: saved.bytes,
+                        duration_ms: start.elapsed().as_millis() as u64,
+                    }
+                }
+                Err(e) => {
+                    warn!("Background save {} failed: {}", id, e);
+                    JobStatus::Failed {
+                        error: e.to_string(),
+                    }
+                }
+            };
+            jobs.jobs.lock().unwrap().insert(id, status);
+        });
+        Ok(id)
+    }
+
+    pub fn status(&self, id: u64) -> Option<JobStatus> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+}
+
+/// One reading of both clocks, for converting between Instant and wall time
+struct Clock {
+    now: Instant,
+    wall: SystemTime,
+}
+
+impl Clock {
+    fn now() -> Self {
+        Self {
+            now: Instant::now(),
+            wall: SystemTime::now(),
+        }
+    }
+
+    fn unix_ms(&self, at: Instant) -> u64 {
+        let wall = if at >= self.now {
+            self.wall + (at - self.now)
+        } else {
+            self.wall - (self.now - at)
+        };
+        wall.duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+
+    /// None if `ms` is already in the past
+    fn instant(&self, ms: u64) -> Option<Instant> {
+        let wall = UNIX_EPOCH + Duration::from_millis(ms);
+        let ahead = wall.duration_since(self.wall).ok()?;
+        (!ahead.is_zero()).then(|| self.now + ahead)
+    }
+}
+
+fn write_entry(
+    buf: &mut Vec<u8>,
+    key: &str,
+    entry: &Entry,
+    parents: &[(String, Option<Instant>)],
+    clock: &Clock,
+) {
+    let mut flags = 0;
+    if let Some(ttl) = &entry.ttl {
+        flags |= HAS_TTL;
+        if ttl.sliding {
+            flags |= SLIDING;
+        }
+    }
+    if entry.pinned {
+        flags |= PINNED;
+    }
+    if !parents.is_empty() {
+        flags |= HAS_PARENT;
+    }
+    if entry.field_expiry.is_some() {
+        flags |= HAS_FIELD_EXPIRY;
+    }
+    if entry.stale {
+        flags |= STALE;
+    }
+    flags |= match entry.priority {
+        Priority::Low => PRIORITY_LOW,
+        Priority::Normal => 0,
+        Priority::High => PRIORITY_HIGH,
+    };
+
+    write_str(buf, key);
+    buf.push(flags);
+    if let Some(ttl) = &entry.ttl {
+        write_varint(buf, clock.unix_ms(ttl.expires_at));
+        write_varint(buf, ttl.duration.as_millis() as u64);
+    }
+    if !parents.is_empty() {
+        write_varint(buf, parents.len() as u64);
+        for (parent, expires_at) in parents {
+            write_str(buf, parent);
+            write_varint(buf, expires_at.map_or(0, |at| clock.unix_ms(at)));
+        }
+    }
+    write_varint(buf, entry.access_count);
+    write_varint(buf, entry.tags().len() as u64);
+    for tag in entry.tags() {
+        write_str(buf, tag);
+    }
+    if let Some(fields) = &entry.field_expiry {
+        write_varint(buf, fields.len() as u64);
+        for (field, at) in fields.iter() {
+            write_str(buf, field);
+            write_varint(buf, clock.unix_ms(*at));
+        }
+    }
+    write_value(buf, &entry.value);
+}
+
+/// None if the key expired since the snapshot was taken
+fn read_entry(reader: &mut Reader, clock: &Clock, version: u8) -> io::Result<Option<Record>> {
+    let key = reader.string()?;
+    let flags = reader.u8()?;
+    let mut expired = false;
+    let ttl = if flags & HAS_TTL != 0 {
+        let expires_at = reader.varint()?;
+        let duration = Duration::from_millis(reader.varint()?);
+        match clock.instant(expires_at) {
+            Some(expires_at) => Some(Ttl {
+                expires_at,
+                sliding: flags & SLIDING != 0,
+                duration,
+            }),
+            None => {
+                expired = true;
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let mut parents = Vec::new();
+    if flags & HAS_PARENT != 0 {
+        let count = if version >= 3 { reader.varint()? } else { 1 };
+        for _ in 0..count {
+            let parent = reader.string()?;
+            // 0 for a link without a deadline; a lapsed link is dropped
+            let deadline = if version >= 2 { reader.varint()? } else { 0 };
+            if deadline == 0 {
+                parents.push((parent, None));
+            } else if let Some(at) = clock.instant(deadline) {
+                parents.push((parent, Some(at)));
+            }
+        }
+    }
+    let access_count = reader.varint()?;
+    let tag_count = if version >= 4 { reader.varint()? } else { 0 };
+    let tags = (0..tag_count)
+        .map(|_| reader.string())
+        .collect::<io::Result<Vec<_>>>()?;
+    let field_expiry = if flags & HAS_FIELD_EXPIRY != 0 {
+        let count = reader.varint()?;
+        let mut fields = HashMap::new();
+        for _ in 0..count {
+            let field = reader.string()?;
+            if let Some(at) = clock.instant(reader.varint()?) {
+                fields.insert(field, at);
+            }
+        }
+        Some(Box::new(fields))
+    } else {
+        None
+    };
+    let mut value = reader.value()?;
+    if expired {
+        return Ok(None);
+    }
+
+    // Fields that expired on disk are dropped along with their deadlines
+    if let (Some(fields), Value::Hash(hash)) = (&field_expiry, &mut value) {
+        hash.retain(|field, _| fields.contains_key(field));
+    }
+    let mut entry = Entry::new(value);
+    entry.ttl = ttl;
+    entry.access_count = access_count;
+    entry.set_tags(tags);
+    entry.field_expiry = field_expiry.filter(|fields| !fields.is_empty());
+    entry.stale = flags & STALE != 0;
+    entry.pinned = flags & PINNED != 0;
+    entry.priority = if flags & PRIORITY_LOW != 0 {
+        Priority::Low
+    } else if flags & PRIORITY_HIGH != 0 {
+        Priority::High
+    } else {
+        Priority::Normal
+    };
+    Ok(Some(Record {
+        key,
+        entry,
+        parents,
+    }))
+}
+
+fn write_value(buf: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::String(s) => {
+            buf.push(STRING);
+            write_str(buf, s);
+        }
+        Value::Integer(i) => {
+            buf.push(INTEGER);
+            buf.extend_from_slice(&i.to_le_bytes());
+        }
+        Value::Float(f) => {
+            buf.push(FLOAT);
+            buf.extend_from_slice(&f.to_bits().to_le_bytes());
+        }
+        Value::Bytes(bytes) => {
+            buf.push(BYTES);
+            write_bytes(buf, bytes);
+        }
+        Value::Hash(hash) => {
+            buf.push(HASH);
+            write_varint(buf, hash.len() as u64);
+            for (field, value) in hash {
+                write_str(buf, field);
+                write_value(buf, value);
+            }
+        }
+        Value::List(items) => {
+            buf.push(LIST);
+            write_varint(buf, items.len() as u64);
+            for item in items {
+                write_value(buf, item);
+            }
+        }
+        Value::Set(members) => {
+            buf.push(SET);
+            write_varint(buf, members.len() as u64);
+            for member in members {
+                write_str(buf, member);
+            }
+        }
+        Value::Stream(stream) => {
+            buf.push(STREAM);
+            let last_id = stream.last_id();
+            write_varint(buf, last_id.ms);
+            write_varint(buf, last_id.seq);
+            write_varint(buf, stream.len() as u64);
+            for entry in stream.entries() {
+                write_varint(buf, entry.id.ms);
+                write_varint(buf, entry.id.seq);
+                write_varint(buf, entry.fields.len() as u64);
+                for (name, value) in &entry.fields {
+                    write_str(buf, name);
+                    write_str(buf, value);
+                }
+            }
+        }
+        Value::HyperLogLog(hll) => {
+            buf.push(HYPERLOGLOG);
+            write_bytes(buf, hll.registers());
+        }
+        Value::Json(doc) => {
+            buf.push(JSON);
+            write_str(buf, &doc.to_string());
+        }
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn write_str(buf: &mut Vec<u8>, s: &str) {
+    write_bytes(buf, s.as_bytes());
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.buf.len())
+            .ok_or_else(|| io::Error::from(ErrorKind::UnexpectedEof))?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(invalid("varint too long"))
+    }
+
+    fn len(&mut self) -> io::Result<usize> {
+        let len = self.varint()? as usize;
+        // Every element takes at least a byte, so a bigger count means corruption
+        if len > self.buf.len() - self.pos {
+            return Err(io::Error::from(ErrorKind::UnexpectedEof));
+        }
+        Ok(len)
+    }
+
+    fn bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.len()?;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        String::from_utf8(self.bytes()?).map_err(|_| invalid("invalid UTF-8 in string"))
+    }
+
+    fn value(&mut self) -> io::Result<Value> {
+        Ok(match self.u8()? {
+            STRING => Value::String(self.string()?),
+            INTEGER => Value::Integer(self.u64()? as i64),
+            FLOAT => Value::Float(f64::from_bits(self.u64()?)),
+            BYTES => Value::Bytes(self.bytes()?),
+            HASH => {
+                let len = self.len()?;
+                let mut hash = HashMap::with_capacity(len);
+                for _ in 0..len {
+                    hash.insert(self.string()?, self.value()?);
+                }
+                Value::Hash(hash)
+            }
+            LIST => {
+                let len = self.len()?;
+                let mut items = Vec::with_capacity(len);
+                for _ in 0..len {
+                    items.push(self.value()?);
+                }
+                Value::List(items)
+            }
+            SET => {
+                let len = self.len()?;
+                let mut members = HashSet::with_capacity(len);
+                for _ in 0..len {
+                    members.insert(self.string()?);
+                }
+                Value::Set(members)
+            }
+            STREAM => {
+                let last_id = self.stream_id()?;
+                let len = self.len()?;
+                let mut entries = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let id = self.stream_id()?;
+                    let count = self.len()?;
+                    let mut fields = Vec::with_capacity(count);
+                    for _ in 0..count {
+                        fields.push((self.string()?, self.string()?));
+                    }
+                    entries.push(StreamEntry { id, fields });
+                }
+                Value::Stream(Stream::from_entries(entries, last_id))
+            }
+            HYPERLOGLOG => Value::HyperLogLog(
+                HyperLogLog::from_registers(self.bytes()?)
+                    .ok_or_else(|| invalid("bad hyperloglog registers"))?,
+            ),
+            JSON => Value::Json(
+                serde_json::from_str(&self.string()?).map_err(|e| invalid(&e.to_string()))?,
+            ),
+            tag => return Err(invalid(&format!("unknown value tag {}", tag))),
+        })
+    }
+
+    fn stream_id(&mut self) -> io::Result<StreamId> {
+        Ok(StreamId {
+            ms: self.varint()?,
+            seq: self.varint()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Config, SetOptions};
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let path = std::env::temp_dir().join(format!("dashdot-{}.snap", std::process::id()));
+        let cache = Cache::new(Config::default());
+        let ttl = |secs| SetOptions {
+            ttl: Some(Duration::from_secs(secs)),
+            ..SetOptions::default()
+        };
+        cache
+            .set("user:1".into(), Value::String("alice".into()), ttl(3600))
+            .unwrap();
+        cache
+            .set(
+                "user:1:avatar".into(),
+                Value::Bytes(vec![0, 159, 146, 150]),
+                SetOptions {
+                    parents: vec!["user:1".into()],
+                    parent_ttl: Some(Duration::from_secs(600)),
+                    pinned: true,
+                    tags: vec!["profile".into()],
+                    ..SetOptions::default()
+                },
+            )
+            .unwrap();
+        let mut hash = HashMap::new();
+        hash.insert("n".to_string(), Value::Integer(-7));
+        hash.insert("f".to_string(), Value::Float(1.5));
+        cache
+            .set("h".into(), Value::Hash(hash.clone()), SetOptions::default())
+            .unwrap();
+        cache.add_parent("user:1:avatar", "h".into(), None).unwrap();
+        let mut stream = Stream::default();
+        stream.append(vec![("a".into(), "1".into())], None);
+        cache
+            .set(
+                "s".into(),
+                Value::Stream(stream.clone()),
+                SetOptions::default(),
+            )
+            .unwrap();
+        cache
+            .pfadd("visitors", &["a".to_string(), "b".to_string()])
+            .unwrap();
+
+        let info = save(&cache, &path).unwrap();
+        assert_eq!(info.keys, 5);
+
+        let restored = Cache::new(Config::default());
+        let report = load(&restored, &path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(report.loaded, 5);
+        assert_eq!(report.orphaned, 0);
+
+        assert_eq!(
+            restored.get("user:1").unwrap(),
+            Value::String("alice".into())
+        );
+        let remaining = restored.ttl("user:1");
+        assert!(remaining > 3500 && remaining <= 3600);
+        assert_eq!(restored.parents("user:1:avatar"), ["user:1", "h"]);
+        let link = restored.parent_ttl("user:1:avatar").unwrap();
+        assert!(link > Duration::from_secs(590) && link <= Duration::from_secs(600));
+        assert!(restored.is_pinned("user:1:avatar"));
+        assert_eq!(restored.tagged("profile"), ["user:1:avatar"]);
+        assert_eq!(
+            restored.get("user:1:avatar").unwrap(),
+            Value::Bytes(vec![0, 159, 146, 150])
+        );
+        assert_eq!(restored.get("h").unwrap(), Value::Hash(hash));
+        assert_eq!(restored.get("s").unwrap(), Value::Stream(stream));
+        assert_eq!(restored.pfcount(&["visitors".to_string()]).unwrap(), 2);
+        assert_eq!(restored.len(), cache.len());
+
+        fs::write(&path, b"DDSN\x01\x00\x05").unwrap();
+        assert!(load(&restored, &path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_restore_limits_depth() {
+        let cache = Cache::new(Config::default());
+        for (key, parent) in [("a", None), ("b", Some("a")), ("c", Some("b"))] {
+            let options = SetOptions {
+                parents: parent.map(str::to_string).into_iter().collect(),
+                ..SetOptions::default()
+            };
+            cache
+                .set(key.into(), Value::String("v".into()), options)
+                .unwrap();
+        }
+
+        // A cache configured shallower keeps every key, but not the link too deep for it
+        let restored = Cache::new(Config {
+            max_dependency_depth: 1,
+            ..Config::default()
+        });
+        let report = restore(&restored, &dump(&cache)).unwrap();
+        assert_eq!((report.loaded, report.orphaned), (3, 1));
+        let linked = ["b", "c"].map(|key| restored.parent(key).is_some());
+        assert_eq!(linked.iter().filter(|&&linked| linked).count(), 1);
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let cache = Cache::new(Config::default());
+        let set = |key: &str, value: &str| {
+            cache
+                .set(
+                    key.into(),
+                    Value::String(value.into()),
+                    SetOptions::default(),
+                )
+                .unwrap();
+        };
+        set("same", "v");
+        set("changed", "short");
+        set("removed", "v");
+        let before = dump(&cache);
+        set("changed", "a much longer value");
+        cache.delete("removed");
+        set("added", "v");
+        let after = dump(&cache);
+
+        let diff = diff(&before, &after).unwrap();
+        let keys = |deltas: &[KeyDelta]| deltas.iter().map(|d| d.key.clone()).collect::<Vec<_>>();
+        assert_eq!(keys(&diff.added), ["added"]);
+        assert_eq!(keys(&diff.removed), ["removed"]);
+        assert_eq!(keys(&diff.changed), ["changed"]);
+        assert_eq!(diff.unchanged, 1);
+        assert_eq!(diff.removed[0].memory_after, 0);
+        let changed = &diff.changed[0];
+        assert!(changed.memory_after > changed.memory_before);
+        assert_eq!(
+            diff.memory_delta(),
+            diff.added[0].memory_after as i64 - diff.removed[0].memory_before as i64
+                + changed.memory_after as i64
+                - changed.memory_before as i64
+        );
+        assert!(super::diff(&before, b"nope").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_background_save_jobs() {
+        let path = std::env::temp_dir().join(format!("dashdot-bg-{}.snap", std::process::id()));
+        let cache = Arc::new(Cache::new(Config::default()));
+        cache
+            .set("k".into(), Value::Integer(1), SetOptions::default())
+            .unwrap();
+        let jobs = Arc::new(SnapshotJobs::new(&path));
+
+        let id = jobs.start(cache.clone()).unwrap();
+        // Only one save runs at a time
+        if let Err(running) = jobs.start(cache.clone()) {
+            assert_eq!(running, id);
+        }
+        while let Some(JobStatus::Running) = jobs.status(id) {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert!(matches!(
+            jobs.status(id),
+            Some(JobStatus::Done { keys: 1, .. })
+        ));
+        assert!(jobs.status(id + 100).is_none());
+        fs::remove_file(&path).unwrap();
+    }
+}
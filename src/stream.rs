@@ -0,0 +1,189 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Stream entry ID: milliseconds since the epoch plus a sequence within that millisecond
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    /// Sorts after every entry; reading after it only sees entries added later
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl FromStr for StreamId {
+    type Err = std::num::ParseIntError;
+
+    /// Accepts `ms-seq` or a bare `ms`, which means `ms-0`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('-') {
+            Some((ms, seq)) => Ok(StreamId {
+                ms: ms.parse()?,
+                seq: seq.parse()?,
+            }),
+            None => Ok(StreamId {
+                ms: s.parse()?,
+                seq: 0,
+            }),
+        }
+    }
+}
+
+impl Serialize for StreamId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for StreamId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamEntry {
+    pub id: StreamId,
+    pub fields: Vec<(String, String)>,
+}
+
+/// Append-only log of entries with increasing IDs, optionally capped in length
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stream {
+    entries: VecDeque<StreamEntry>,
+    last_id: StreamId,
+}
+
+impl Stream {
+    /// Appends with an auto-generated ID, evicting the oldest entries past `max_len`
+    pub fn append(&mut self, fields: Vec<(String, String)>, max_len: Option<usize>) -> StreamId {
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        // IDs never go backwards, even if the clock does
+        let id = if now_ms > self.last_id.ms {
+            StreamId { ms: now_ms, seq: 0 }
+        } else {
+            StreamId {
+                ms: self.last_id.ms,
+                seq: self.last_id.seq + 1,
+            }
+        };
+
+        self.entries.push_back(StreamEntry { id, fields });
+        self.last_id = id;
+        if let Some(max_len) = max_len {
+            while self.entries.len() > max_len {
+                self.entries.pop_front();
+            }
+        }
+        id
+    }
+
+    /// Up to `count` entries with IDs strictly greater than `after`
+    pub fn read_after(&self, after: StreamId, count: usize) -> Vec<StreamEntry> {
+        let start = self.entries.partition_point(|entry| entry.id <= after);
+        self.entries.range(start..).take(count).cloned().collect()
+    }
+
+    /// Rebuilds a stream from entries in ID order, as read back from disk
+    pub fn from_entries(entries: Vec<StreamEntry>, last_id: StreamId) -> Self {
+        Self {
+            entries: entries.into(),
+            last_id,
+        }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &StreamEntry> {
+        self.entries.iter()
+    }
+
+    /// ID of the newest entry ever appended, even if since trimmed away
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Drops spare capacity in entries and their fields
+    pub fn shrink_to_fit(&mut self) {
+        self.entries.shrink_to_fit();
+        for entry in &mut self.entries {
+            entry.fields.shrink_to_fit();
+            for (name, value) in &mut entry.fields {
+                name.shrink_to_fit();
+                value.shrink_to_fit();
+            }
+        }
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        let mut size = std::mem::size_of_val(self);
+        size += self
+            .entries
+            .iter()
+            .map(|entry| {
+                std::mem::size_of::<StreamEntry>()
+                    + entry
+                        .fields
+                        .iter()
+                        .map(|(name, value)| name.capacity() + value.capacity())
+                        .sum::<usize>()
+            })
+            .sum::<usize>();
+        size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_append_and_read() {
+        let mut stream = Stream::default();
+        let field = |v: &str| vec![("f".to_string(), v.to_string())];
+        let first = stream.append(field("a"), Some(2));
+        let second = stream.append(field("b"), Some(2));
+        let third = stream.append(field("c"), Some(2));
+        assert!(first < second && second < third);
+        assert_eq!(stream.len(), 2);
+
+        let read = stream.read_after(StreamId::default(), 10);
+        assert_eq!(
+            read.iter().map(|e| e.id).collect::<Vec<_>>(),
+            [second, third]
+        );
+        assert_eq!(stream.read_after(second, 10)[0].id, third);
+        assert!(stream.read_after(StreamId::MAX, 10).is_empty());
+
+        assert_eq!(
+            "5-3".parse::<StreamId>().unwrap(),
+            StreamId { ms: 5, seq: 3 }
+        );
+        assert_eq!("7".parse::<StreamId>().unwrap().to_string(), "7-0");
+        assert!("x-1".parse::<StreamId>().is_err());
+    }
+}
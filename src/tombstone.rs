@@ -0,0 +1,67 @@
+use dashmap::DashMap;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Marker left by a soft delete
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tombstone {
+    pub deleted_at: SystemTime,
+    expires_at: Instant,
+}
+
+/// Tombstones kept for deleted keys in soft-delete mode, so "deleted" and "never
+/// existed" can be told apart for `retention` after a delete. Writing the key again
+/// clears its tombstone.
+///
+/// Like the expiry index, this is lazy: expired tombstones are ignored on read and
+/// only dropped by `purge`.
+#[derive(Debug)]
+pub struct Tombstones {
+    retention: Duration,
+    entries: DashMap<String, Tombstone>,
+}
+
+impl Tombstones {
+    pub fn new(retention: Duration) -> Self {
+        Self {
+            retention,
+            entries: DashMap::new(),
+        }
+    }
+
+    pub fn record(&self, key: &str) {
+        let tombstone = Tombstone {
+            deleted_at: SystemTime::now(),
+            expires_at: Instant::now() + self.retention,
+        };
+        self.entries.insert(key.to_string(), tombstone);
+    }
+
+    pub fn clear(&self, key: &str) {
+        self.entries.remove(key);
+    }
+
+    pub fn get(&self, key: &str) -> Option<Tombstone> {
+        let now = Instant::now();
+        self.entries
+            .get(key)
+            .map(|tombstone| *tombstone)
+            .filter(|tombstone| tombstone.expires_at > now)
+    }
+
+    /// Drops tombstones past their retention, returning how many went
+    pub fn purge(&self) -> usize {
+        let now = Instant::now();
+        let before = self.entries.len();
+        self.entries
+            .retain(|_, tombstone| tombstone.expires_at > now);
+        before.saturating_sub(self.entries.len())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
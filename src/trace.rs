@@ -0,0 +1,76 @@
+use tracing::{Span, info_span};
+
+/// Longest caller-supplied request ID that's accepted
+const MAX_ID_LEN: usize = 128;
+
+/// Trace ID of a W3C `traceparent` value: `00-<32 hex trace id>-<16 hex parent id>-<flags>`.
+/// The all-zero trace ID is invalid per the spec.
+pub fn from_traceparent(value: &str) -> Option<String> {
+    let mut parts = value.trim().split('-');
+    let (version, trace_id, parent_id, flags) =
+        (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    let is_hex = |s: &str, len: usize| {
+        s.len() == len
+            && s.bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    };
+    // Later versions may append fields, so only version 00 has to end here
+    let valid = is_hex(version, 2)
+        && version != "ff"
+        && (version != "00" || parts.next().is_none())
+        && is_hex(trace_id, 32)
+        && is_hex(parent_id, 16)
+        && is_hex(flags, 2)
+        && trace_id.bytes().any(|b| b != b'0');
+    valid.then(|| trace_id.to_string())
+}
+
+/// A caller's own request ID (`X-Request-Id`, CLIENT TRACEID), if it's safe to log
+/// as-is: up to 128 letters, digits and `-_.:`
+pub fn request_id(value: &str) -> Option<String> {
+    let valid = !value.is_empty()
+        && value.len() <= MAX_ID_LEN
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b));
+    valid.then(|| value.to_string())
+}
+
+/// Span for the work done on behalf of one traced request. Commands run inside it, so
+/// their events (the audit log among them) carry the caller's trace ID.
+pub fn request_span(trace_id: &str) -> Span {
+    info_span!("request", trace_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_trace_ids() {
+        let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+        assert_eq!(
+            from_traceparent(&format!("00-{}-00f067aa0ba902b7-01", trace_id)).as_deref(),
+            Some(trace_id)
+        );
+        assert_eq!(
+            from_traceparent(&format!("01-{}-00f067aa0ba902b7-01-extra", trace_id)).as_deref(),
+            Some(trace_id)
+        );
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        ] {
+            assert_eq!(from_traceparent(invalid), None, "{}", invalid);
+        }
+
+        assert_eq!(request_id("req-42:a_b.c").as_deref(), Some("req-42:a_b.c"));
+        assert_eq!(request_id(""), None);
+        assert_eq!(request_id("has space"), None);
+        assert_eq!(request_id(&"a".repeat(129)), None);
+    }
+}